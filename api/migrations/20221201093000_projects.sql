@@ -0,0 +1,24 @@
+-- Projects group a user's evals, and carry per-project settings.
+
+-- A project is identified by its name, which is unique per user. Clients name the project an eval
+-- belongs to when inserting it, and the project is created on first use.
+
+CREATE TABLE IF NOT EXISTS projects (
+    id              UUID            DEFAULT uuid_generate_v4() PRIMARY KEY,
+    user_id         UUID            NOT NULL REFERENCES users(id),
+    name            VARCHAR(100)    NOT NULL,
+    -- opt-in: index `result_json` of this project's evals for content search
+    index_results   BOOL            NOT NULL DEFAULT false,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    UNIQUE (user_id, name)
+);
+
+ALTER TABLE evals
+    ADD COLUMN IF NOT EXISTS project_id UUID REFERENCES projects(id),
+    ADD COLUMN IF NOT EXISTS result_indexed BOOL NOT NULL DEFAULT false;
+
+-- Only evals belonging to projects which have opted in get indexed. The `jsonb_path_ops` operator
+-- class supports containment (`@>`) and jsonpath predicate (`@@`) queries.
+CREATE INDEX IF NOT EXISTS evals_result_json_gin
+    ON evals USING GIN (result_json jsonb_path_ops)
+    WHERE result_indexed;
@@ -0,0 +1,25 @@
+-- Named numeric metrics recorded against evals, and the per-project rules used to derive them from
+-- `result_json`.
+
+-- Each rule maps a jsonpath expression over an eval's `result_json` to a metric name. Rules are
+-- applied when the eval is inserted; results which don't produce a number at the path are skipped.
+
+CREATE TABLE IF NOT EXISTS metric_rules (
+    id              BIGSERIAL       PRIMARY KEY,
+    project_id      UUID            NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    name            VARCHAR(100)    NOT NULL,
+    path            TEXT            NOT NULL,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    UNIQUE (project_id, name)
+);
+
+CREATE TABLE IF NOT EXISTS metrics (
+    id              BIGSERIAL           PRIMARY KEY,
+    eval_id         UUID                NOT NULL REFERENCES evals(id) ON DELETE CASCADE,
+    name            VARCHAR(100)        NOT NULL,
+    value           DOUBLE PRECISION    NOT NULL,
+    create_dt       TIMESTAMPTZ         NOT NULL DEFAULT current_timestamp,
+    UNIQUE (eval_id, name)
+);
+
+CREATE INDEX metrics_name ON metrics (name);
@@ -0,0 +1,23 @@
+-- User-defined alert rules over recorded metrics.
+
+-- A rule fires when any value of `metric` recorded within the last `window_secs` seconds compares
+-- against `threshold` using `comparison`. Once fired, a rule stays quiet for one window, so that a
+-- single breach doesn't send a notification on every evaluation.
+
+CREATE TABLE IF NOT EXISTS alert_rules (
+    id              BIGSERIAL           PRIMARY KEY,
+    user_id         UUID                NOT NULL REFERENCES users(id),
+    -- restrict the rule to the metrics of one project; all projects when null
+    project_id      UUID                REFERENCES projects(id) ON DELETE CASCADE,
+    metric          VARCHAR(100)        NOT NULL,
+    comparison      VARCHAR(2)          NOT NULL CHECK (comparison IN ('>', '>=', '<', '<=')),
+    threshold       DOUBLE PRECISION    NOT NULL,
+    window_secs     INT                 NOT NULL CHECK (window_secs > 0),
+    channel         VARCHAR(10)         NOT NULL CHECK (channel IN ('email', 'webhook')),
+    -- email address or webhook URL, depending on `channel`
+    target          TEXT                NOT NULL,
+    last_fired_dt   TIMESTAMPTZ,
+    create_dt       TIMESTAMPTZ         NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX alert_rules_user_id ON alert_rules (user_id);
@@ -0,0 +1,24 @@
+-- Experiment runs and their lifecycle.
+
+-- A run is registered by the client when an experiment starts, before there is any eval to record.
+-- It then moves through the states below, ending in one of the terminal states (succeeded, failed
+-- or cancelled). Cancellation is cooperative: the dashboard sets `cancel_requested`, and the
+-- client, which polls the run, moves it to `cancelled` once it has stopped.
+
+CREATE TABLE IF NOT EXISTS runs (
+    id                  UUID            DEFAULT uuid_generate_v4() PRIMARY KEY,
+    user_id             UUID            NOT NULL REFERENCES users(id),
+    project_id          UUID            REFERENCES projects(id),
+    fn_key              TEXT            NOT NULL,
+    fn_hash             VARCHAR(64)     NOT NULL,
+    args_hash           VARCHAR(64)     NOT NULL,
+    state               VARCHAR(10)     NOT NULL DEFAULT 'queued'
+        CHECK (state IN ('queued', 'running', 'succeeded', 'failed', 'cancelled')),
+    cancel_requested    BOOL            NOT NULL DEFAULT false,
+    -- the eval recorded by the run, once it has succeeded
+    eval_id             UUID            REFERENCES evals(id),
+    create_dt           TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    update_dt           TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX runs_user_id ON runs (user_id);
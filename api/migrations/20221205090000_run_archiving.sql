@@ -0,0 +1,15 @@
+-- Run-level archiving, separate from cache retention.
+
+-- Projects may opt in to archiving the artifacts of finished runs `archive_after_days` days after
+-- the run finished. Run metadata (the `runs` and `evals` rows) is kept forever; only the BLOB is
+-- moved to cold storage, which is tracked on the blob by `storage_class`.
+
+ALTER TABLE projects
+    ADD COLUMN IF NOT EXISTS archive_after_days INT CHECK (archive_after_days > 0);
+
+ALTER TABLE runs
+    ADD COLUMN IF NOT EXISTS archived_dt TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS unarchive_requested_dt TIMESTAMPTZ;
+
+ALTER TABLE blobs
+    ADD COLUMN IF NOT EXISTS storage_class VARCHAR(20) NOT NULL DEFAULT 'STANDARD';
@@ -0,0 +1,31 @@
+-- Storage for runs logged through the MLflow compatibility API.
+
+-- MLflow logs metrics against runs (possibly many values per metric, one per step), rather than
+-- deriving them from an eval's result. A metric now belongs to exactly one of an eval or a run.
+
+ALTER TABLE runs
+    ADD COLUMN IF NOT EXISTS name TEXT;
+
+ALTER TABLE metrics
+    ALTER COLUMN eval_id DROP NOT NULL,
+    ADD COLUMN IF NOT EXISTS run_id UUID REFERENCES runs(id) ON DELETE CASCADE,
+    ADD COLUMN IF NOT EXISTS step BIGINT NOT NULL DEFAULT 0,
+    ADD CONSTRAINT metrics_eval_or_run CHECK ((eval_id IS NULL) <> (run_id IS NULL));
+
+CREATE INDEX metrics_run_id ON metrics (run_id);
+
+CREATE TABLE IF NOT EXISTS run_params (
+    run_id          UUID            NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+    key             VARCHAR(250)    NOT NULL,
+    value           TEXT            NOT NULL,
+    PRIMARY KEY (run_id, key)
+);
+
+-- Files logged as artifacts of a run, stored as BLOBs.
+CREATE TABLE IF NOT EXISTS run_artifacts (
+    run_id          UUID            NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+    path            TEXT            NOT NULL,
+    blob_id         BIGINT          NOT NULL REFERENCES blobs(id),
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    PRIMARY KEY (run_id, path)
+);
@@ -0,0 +1,14 @@
+-- Mapping from the MD5 addresses used by DVC remotes to BLOBs.
+
+-- DVC addresses cache objects by the MD5 of their contents, whereas BLOBs are addressed by their
+-- BLAKE3 hash. The MD5 is claimed by the client in the upload path and isn't verified, so objects
+-- are scoped to the user who uploaded them: a wrong claim can only affect that user's own cache.
+
+CREATE TABLE IF NOT EXISTS dvc_objects (
+    user_id         UUID            NOT NULL REFERENCES users(id),
+    -- 32 hex digits, with a `.dir` suffix for directory listings
+    md5             VARCHAR(36)     NOT NULL,
+    blob_id         BIGINT          NOT NULL REFERENCES blobs(id),
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    PRIMARY KEY (user_id, md5)
+);
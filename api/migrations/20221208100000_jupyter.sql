@@ -0,0 +1,11 @@
+-- Support for the Jupyter notebook extension.
+
+-- `session_id` identifies the kernel session an eval was computed in, so that the sidebar can list
+-- the session's recent evals. Pinned evals are ones the user chose to keep from a notebook; they
+-- are exempt from any clean-up of the cache.
+
+ALTER TABLE evals
+    ADD COLUMN IF NOT EXISTS session_id VARCHAR(100),
+    ADD COLUMN IF NOT EXISTS pinned BOOL NOT NULL DEFAULT false;
+
+CREATE INDEX evals_session_id ON evals (session_id, start_time) WHERE session_id IS NOT NULL;
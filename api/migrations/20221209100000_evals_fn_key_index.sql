@@ -0,0 +1,3 @@
+-- Index evals by function key, for per-function cache status lookups.
+
+CREATE INDEX evals_user_id_fn_key ON evals (user_id, fn_key);
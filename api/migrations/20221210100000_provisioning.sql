@@ -0,0 +1,44 @@
+-- Declarative provisioning of organisations, projects, service accounts and their API keys.
+
+-- Each provisioned resource is identified by an external id chosen by the client (e.g. by a
+-- Terraform configuration), unique within its parent, and carries a version which is bumped
+-- whenever the resource changes. The version is exposed as the resource's ETag, so that clients
+-- can make conditional requests.
+
+CREATE TABLE IF NOT EXISTS orgs (
+    id              UUID            DEFAULT uuid_generate_v4() PRIMARY KEY,
+    owner_id        UUID            NOT NULL REFERENCES users(id),
+    external_id     VARCHAR(100)    NOT NULL,
+    name            VARCHAR(100)    NOT NULL,
+    version         INT             NOT NULL DEFAULT 1,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    update_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    UNIQUE (owner_id, external_id)
+);
+
+-- Projects belonging to an org are owned by the org's owner, so evals inserted with keys of the
+-- org's service accounts land in them.
+ALTER TABLE projects
+    ADD COLUMN IF NOT EXISTS org_id UUID REFERENCES orgs(id),
+    ADD COLUMN IF NOT EXISTS external_id VARCHAR(100),
+    ADD COLUMN IF NOT EXISTS version INT NOT NULL DEFAULT 1,
+    ADD CONSTRAINT projects_org_id_external_id UNIQUE (org_id, external_id);
+
+-- A service account is a non-human principal of an org, which holds API keys. Its keys act on
+-- behalf of the org's owner.
+CREATE TABLE IF NOT EXISTS service_accounts (
+    id              UUID            DEFAULT uuid_generate_v4() PRIMARY KEY,
+    org_id          UUID            NOT NULL REFERENCES orgs(id),
+    external_id     VARCHAR(100)    NOT NULL,
+    name            VARCHAR(100)    NOT NULL,
+    version         INT             NOT NULL DEFAULT 1,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    update_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    UNIQUE (org_id, external_id)
+);
+
+ALTER TABLE api_keys
+    ADD COLUMN IF NOT EXISTS service_account_id UUID REFERENCES service_accounts(id) ON DELETE CASCADE,
+    ADD COLUMN IF NOT EXISTS external_id VARCHAR(100),
+    ADD COLUMN IF NOT EXISTS version INT NOT NULL DEFAULT 1,
+    ADD CONSTRAINT api_keys_service_account_id_external_id UNIQUE (service_account_id, external_id);
@@ -0,0 +1,39 @@
+-- Org memberships and groups, provisioned by enterprise identity providers over SCIM.
+
+-- Users are global, identified by their email address (`gh_email`), and become members of an org
+-- when the org's identity provider provisions them. The identity provider's own attributes for the
+-- user (its external id and the user's name) are kept per membership. Deprovisioning a user
+-- deactivates or removes their membership, never the user itself.
+
+CREATE TABLE IF NOT EXISTS org_members (
+    org_id          UUID            NOT NULL REFERENCES orgs(id) ON DELETE CASCADE,
+    user_id         UUID            NOT NULL REFERENCES users(id),
+    external_id     VARCHAR(100),
+    given_name      VARCHAR(100),
+    family_name     VARCHAR(100),
+    active          BOOL            NOT NULL DEFAULT true,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    update_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    PRIMARY KEY (org_id, user_id)
+);
+
+CREATE INDEX org_members_user_id ON org_members (user_id);
+
+CREATE TABLE IF NOT EXISTS org_groups (
+    id              UUID            DEFAULT uuid_generate_v4() PRIMARY KEY,
+    org_id          UUID            NOT NULL REFERENCES orgs(id) ON DELETE CASCADE,
+    display_name    VARCHAR(100)    NOT NULL,
+    external_id     VARCHAR(100),
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    update_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    UNIQUE (org_id, display_name)
+);
+
+-- Group members must be members of the group's org, and leave its groups when they leave it.
+CREATE TABLE IF NOT EXISTS org_group_members (
+    group_id        UUID            NOT NULL REFERENCES org_groups(id) ON DELETE CASCADE,
+    org_id          UUID            NOT NULL,
+    user_id         UUID            NOT NULL,
+    PRIMARY KEY (group_id, user_id),
+    FOREIGN KEY (org_id, user_id) REFERENCES org_members(org_id, user_id) ON DELETE CASCADE
+);
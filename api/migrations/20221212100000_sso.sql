@@ -0,0 +1,36 @@
+-- Single sign-on through an org's own identity provider, as an alternative to signing in with
+-- GitHub.
+
+-- Each org can configure one OpenID Connect provider. Users who sign in through it are created on
+-- their first sign-in and made members of the org, with a role derived from the groups the
+-- provider says they belong to.
+
+ALTER TABLE org_members
+    ADD COLUMN IF NOT EXISTS role VARCHAR(10) NOT NULL DEFAULT 'member'
+        CHECK (role IN ('admin', 'member', 'viewer'));
+
+CREATE TABLE IF NOT EXISTS org_sso_configs (
+    org_id          UUID            NOT NULL PRIMARY KEY REFERENCES orgs(id) ON DELETE CASCADE,
+    protocol        VARCHAR(10)     NOT NULL DEFAULT 'oidc' CHECK (protocol IN ('oidc')),
+    issuer          TEXT            NOT NULL,
+    client_id       TEXT            NOT NULL,
+    client_secret   TEXT            NOT NULL,
+    -- the ID token claim listing the user's groups
+    groups_claim    VARCHAR(100)    NOT NULL DEFAULT 'groups',
+    -- maps group names to roles, e.g. `{"ml-platform": "admin"}`
+    role_mappings   JSONB           NOT NULL DEFAULT '{}',
+    default_role    VARCHAR(10)     NOT NULL DEFAULT 'member'
+        CHECK (default_role IN ('admin', 'member', 'viewer')),
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    update_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp
+);
+
+-- Sign-ins which have been started but not yet completed. The `state` is handed to the identity
+-- provider and back, and the `nonce` is checked against the ID token.
+CREATE TABLE IF NOT EXISTS sso_logins (
+    state           VARCHAR(64)     NOT NULL PRIMARY KEY,
+    org_id          UUID            NOT NULL REFERENCES orgs(id) ON DELETE CASCADE,
+    nonce           VARCHAR(64)     NOT NULL,
+    redirect_uri    TEXT            NOT NULL,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp
+);
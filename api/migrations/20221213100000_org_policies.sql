@@ -0,0 +1,11 @@
+-- Authorization policies configured per org.
+
+-- `rules` is a JSON list of rules, which are added to the built-in rules when authorizing requests
+-- made by the org's members and service accounts. See `policy::Rule` for their format.
+
+CREATE TABLE IF NOT EXISTS org_policies (
+    org_id          UUID            NOT NULL PRIMARY KEY REFERENCES orgs(id) ON DELETE CASCADE,
+    rules           JSONB           NOT NULL DEFAULT '[]',
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    update_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp
+);
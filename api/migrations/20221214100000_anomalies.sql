@@ -0,0 +1,36 @@
+-- Anomaly detection on cache behaviour.
+
+-- `cache_activity` is a short-lived log of cache lookups, BLOB downloads and uploads rejected for
+-- an invalid hash. The anomaly job compares the most recent activity against the activity of the
+-- preceding day, flags what looks suspicious in `anomalies`, and prunes older activity.
+
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS cache_activity (
+    id              BIGSERIAL       PRIMARY KEY,
+    user_id         UUID            NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- the key the request was made with; null for signed in users
+    api_key         VARCHAR(64)     REFERENCES api_keys(key) ON DELETE CASCADE,
+    kind            VARCHAR(20)     NOT NULL CHECK (kind IN ('hit', 'miss', 'download',
+                                                             'invalid_hash')),
+    fn_key          TEXT,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX cache_activity_kind_create_dt ON cache_activity (kind, create_dt);
+
+CREATE TABLE IF NOT EXISTS anomalies (
+    id              BIGSERIAL           PRIMARY KEY,
+    kind            VARCHAR(30)         NOT NULL CHECK (kind IN ('miss_storm', 'download_spike',
+                                                                 'invalid_hash_uploads')),
+    user_id         UUID                NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    api_key         VARCHAR(64)         REFERENCES api_keys(key) ON DELETE CASCADE,
+    fn_key          TEXT,
+    -- activity within the detection window, and the average per window over the preceding day
+    observed        BIGINT              NOT NULL,
+    baseline        DOUBLE PRECISION    NOT NULL,
+    create_dt       TIMESTAMPTZ         NOT NULL DEFAULT current_timestamp,
+    acknowledged_dt TIMESTAMPTZ
+);
+
+CREATE INDEX anomalies_create_dt ON anomalies (create_dt);
@@ -0,0 +1,17 @@
+-- Cache outcomes reported by clients, metered per function per hour.
+
+-- Clients report every lookup, including hits on their local cache which never reach the server,
+-- so that hit rates and time saved reflect how the cache is actually used. `compute_time` is the
+-- process time spent computing results on misses, and `saved_time` the process time hits saved.
+
+CREATE TABLE IF NOT EXISTS eval_usage (
+    user_id         UUID            NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    fn_key          TEXT            NOT NULL,
+    period_start    TIMESTAMPTZ     NOT NULL,
+    hits            BIGINT          NOT NULL DEFAULT 0,
+    local_hits      BIGINT          NOT NULL DEFAULT 0,
+    misses          BIGINT          NOT NULL DEFAULT 0,
+    compute_time    BIGINT          NOT NULL DEFAULT 0 CHECK (compute_time >= 0),
+    saved_time      BIGINT          NOT NULL DEFAULT 0 CHECK (saved_time >= 0),
+    PRIMARY KEY (user_id, fn_key, period_start)
+);
@@ -0,0 +1,15 @@
+-- Registry of the functions whose evals are cached, as uploaded by clients.
+
+-- Each version of a function (identified by its hash) is stored once per user, so that the code
+-- which produced a cached value can be shown alongside it.
+
+CREATE TABLE IF NOT EXISTS functions (
+    user_id         UUID            NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    fn_key          TEXT            NOT NULL,
+    fn_hash         TEXT            NOT NULL,
+    source          TEXT            NOT NULL,
+    docstring       TEXT,
+    signature       TEXT,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    PRIMARY KEY (user_id, fn_key, fn_hash)
+);
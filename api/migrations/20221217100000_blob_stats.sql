@@ -0,0 +1,15 @@
+-- Statistics of tabular artifacts (Parquet, Arrow and CSV), computed by a background job.
+
+-- Stats are keyed by content hash, since they only depend on the BLOB's contents. A BLOB has no
+-- row until the job has looked at it; `status` records whether stats could be computed.
+
+CREATE TABLE IF NOT EXISTS blob_stats (
+    content_hash    CHAR(64)        NOT NULL PRIMARY KEY,
+    status          VARCHAR(20)     NOT NULL CHECK (status IN ('done', 'unsupported', 'failed')),
+    format          VARCHAR(10)     CHECK (format IN ('parquet', 'arrow', 'csv')),
+    row_count       BIGINT,
+    columns         JSONB,
+    -- why stats couldn't be computed, for `failed` and `unsupported`
+    error           TEXT,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp
+);
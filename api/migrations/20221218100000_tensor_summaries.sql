@@ -0,0 +1,16 @@
+-- Summaries of tensor artifacts (numpy arrays, safetensors and PyTorch archives), computed by a
+-- background job.
+
+-- As with `blob_stats`, summaries are keyed by content hash, and a BLOB has no row until the job
+-- has looked at it. The NaN and infinity counts are also recorded as metrics of the evals whose
+-- result is the BLOB, so that alert rules can catch them.
+
+CREATE TABLE IF NOT EXISTS tensor_summaries (
+    content_hash    CHAR(64)        NOT NULL PRIMARY KEY,
+    status          VARCHAR(20)     NOT NULL CHECK (status IN ('done', 'unsupported', 'failed')),
+    format          VARCHAR(20)     CHECK (format IN ('npy', 'safetensors', 'torch')),
+    tensors         JSONB,
+    -- why the BLOB couldn't be summarised, for `failed` and `unsupported`
+    error           TEXT,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp
+);
@@ -0,0 +1,16 @@
+-- Embeddings of eval arguments, for finding cached evals whose arguments are close to those of a
+-- lookup which missed. Requires the pgvector extension.
+
+-- Embeddings are computed by a background job when an embedding provider is configured. The
+-- model is recorded since embeddings from different models aren't comparable, and dimensions
+-- differ between models, so the column isn't sized. Searches are always within one user's evals
+-- of one function, so they scan few rows and there's no approximate nearest-neighbour index.
+
+CREATE EXTENSION IF NOT EXISTS vector;
+
+CREATE TABLE IF NOT EXISTS eval_embeddings (
+    eval_id         UUID            NOT NULL PRIMARY KEY REFERENCES evals(id) ON DELETE CASCADE,
+    model           VARCHAR(100)    NOT NULL,
+    embedding       vector          NOT NULL,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp
+);
@@ -0,0 +1,25 @@
+-- Verification of BLOB metadata against the objects stored in S3.
+
+-- Early uploads trusted the hash and length claimed by the client. An admin-triggered backfill
+-- streams each stored object, recomputing its blake3 hash and length. Missing lengths are filled
+-- in, but anything which disagrees with what's recorded is reported in `blob_discrepancies` for
+-- an admin to look into, rather than overwritten.
+
+ALTER TABLE blobs
+    ADD COLUMN IF NOT EXISTS content_length BIGINT,
+    ADD COLUMN IF NOT EXISTS verified_dt TIMESTAMPTZ;
+
+CREATE TABLE IF NOT EXISTS blob_discrepancies (
+    id              BIGSERIAL       PRIMARY KEY,
+    content_hash    CHAR(64)        NOT NULL,
+    kind            VARCHAR(20)     NOT NULL CHECK (kind IN ('hash_mismatch', 'length_mismatch',
+                                                             'missing_object')),
+    -- the value recorded in `blobs`, and the value found in S3
+    recorded        TEXT,
+    actual          TEXT,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    resolved_dt     TIMESTAMPTZ
+);
+
+CREATE INDEX blob_discrepancies_unresolved ON blob_discrepancies (create_dt)
+    WHERE resolved_dt IS NULL;
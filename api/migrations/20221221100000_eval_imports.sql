@@ -0,0 +1,38 @@
+-- Bulk imports of evals.
+
+-- An import is created first, then its evals are uploaded as newline-delimited JSON. They're
+-- inserted in chunks, each in its own transaction, and `eval_imports` records progress after
+-- each one, so that the importer can be polled while the upload is in progress.
+
+-- Each chunk is copied into the unlogged `eval_import_rows` staging table with binary COPY, then
+-- moved into `evals` by a single statement, which skips evals the user already has.
+
+CREATE TABLE IF NOT EXISTS eval_imports (
+    id              UUID            DEFAULT uuid_generate_v4() PRIMARY KEY,
+    user_id         UUID            NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    project_id      UUID            REFERENCES projects(id) ON DELETE CASCADE,
+    status          VARCHAR(10)     NOT NULL DEFAULT 'pending'
+                                    CHECK (status IN ('pending', 'running', 'done', 'failed')),
+    rows_received   BIGINT          NOT NULL DEFAULT 0,
+    rows_inserted   BIGINT          NOT NULL DEFAULT 0,
+    chunks          INT             NOT NULL DEFAULT 0,
+    error           TEXT,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    update_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp
+);
+
+CREATE UNLOGGED TABLE IF NOT EXISTS eval_import_rows (
+    import_id               UUID            NOT NULL,
+    fn_key                  TEXT            NOT NULL,
+    fn_hash                 TEXT            NOT NULL,
+    args                    JSONB,
+    args_hash               TEXT            NOT NULL,
+    result_json             JSONB           NOT NULL,
+    content_hash            CHAR(64)        NOT NULL,
+    is_experiment           BOOLEAN         NOT NULL,
+    start_time              TIMESTAMPTZ     NOT NULL,
+    elapsed_process_time    BIGINT          NOT NULL,
+    session_id              TEXT
+);
+
+CREATE INDEX eval_import_rows_import_id ON eval_import_rows (import_id);
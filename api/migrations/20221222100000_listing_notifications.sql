@@ -0,0 +1,63 @@
+-- Notifications of changes to listings.
+
+-- Servers cache the results of expensive listings, such as a project's metrics. When evals or
+-- metrics are inserted, the users and projects they belong to are sent on the `listings_changed`
+-- channel, as `{"user_id": ..., "project": ...}`, so that every server can invalidate the results
+-- which may have included them. Notifications are only delivered once the inserting transaction
+-- commits, and duplicates within a transaction are only delivered once.
+
+CREATE OR REPLACE FUNCTION notify_evals_inserted()
+RETURNS trigger
+AS
+$BODY$
+BEGIN
+    PERFORM pg_notify(
+        'listings_changed',
+        json_build_object('user_id', n.user_id, 'project', p.name)::text
+    )
+    FROM (SELECT DISTINCT user_id, project_id FROM inserted) n
+    LEFT JOIN projects p
+        ON p.id = n.project_id;
+
+    RETURN NULL;
+END
+$BODY$
+LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION notify_metrics_inserted()
+RETURNS trigger
+AS
+$BODY$
+BEGIN
+    PERFORM pg_notify(
+        'listings_changed',
+        json_build_object('user_id', n.user_id, 'project', p.name)::text
+    )
+    FROM (
+        SELECT DISTINCT coalesce(e.user_id, r.user_id) AS user_id,
+            coalesce(e.project_id, r.project_id) AS project_id
+        FROM inserted m
+        LEFT JOIN evals e
+            ON e.id = m.eval_id
+        LEFT JOIN runs r
+            ON r.id = m.run_id
+    ) n
+    LEFT JOIN projects p
+        ON p.id = n.project_id;
+
+    RETURN NULL;
+END
+$BODY$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER evals_notify_inserted
+    AFTER INSERT ON evals
+    REFERENCING NEW TABLE AS inserted
+    FOR EACH STATEMENT
+    EXECUTE FUNCTION notify_evals_inserted();
+
+CREATE TRIGGER metrics_notify_inserted
+    AFTER INSERT ON metrics
+    REFERENCING NEW TABLE AS inserted
+    FOR EACH STATEMENT
+    EXECUTE FUNCTION notify_metrics_inserted();
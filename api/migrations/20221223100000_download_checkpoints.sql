@@ -0,0 +1,18 @@
+-- Checkpoints of verified downloads, so that a dropped download can be resumed without verifying
+-- the BLOB from the start again.
+
+-- A verified download is given a resume token, and the state of its BLAKE3 hash is recorded
+-- against the token at regular offsets. `cv_stack` is the concatenated chaining values of the
+-- complete subtrees to the left of `byte_offset`. A download starts with a checkpoint at offset 0.
+
+CREATE TABLE IF NOT EXISTS download_checkpoints (
+    token           UUID            NOT NULL,
+    user_id         UUID            NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    content_hash    CHAR(64)        NOT NULL,
+    byte_offset     BIGINT          NOT NULL,
+    cv_stack        BYTEA           NOT NULL,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    PRIMARY KEY (token, byte_offset)
+);
+
+CREATE INDEX download_checkpoints_create_dt ON download_checkpoints (create_dt);
@@ -0,0 +1,8 @@
+-- Per-key caps on the bandwidth of BLOB transfers, in bytes per second.
+
+-- A key without a cap is limited by the server's default cap for that direction, if one is
+-- configured. Caps are enforced per server, not across all servers.
+
+ALTER TABLE api_keys
+    ADD COLUMN IF NOT EXISTS upload_bytes_per_sec BIGINT CHECK (upload_bytes_per_sec > 0),
+    ADD COLUMN IF NOT EXISTS download_bytes_per_sec BIGINT CHECK (download_bytes_per_sec > 0);
@@ -0,0 +1,30 @@
+-- Opt-in capture of failing BLOB uploads and MessagePack requests, so that malformed frames
+-- reported by client users can be reproduced.
+
+-- While a key's `capture_until` is in the future, the raw bytes of its failing requests are kept
+-- in `request_captures`, up to the server's size cap. The bytes are sealed with the server's
+-- capture key before they're stored, and captures are pruned once they expire.
+
+ALTER TABLE api_keys ADD COLUMN IF NOT EXISTS capture_until TIMESTAMPTZ;
+
+CREATE TABLE IF NOT EXISTS request_captures (
+    id              BIGSERIAL       PRIMARY KEY,
+    user_id         UUID            NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    api_key         VARCHAR(64)     NOT NULL REFERENCES api_keys(key) ON DELETE CASCADE,
+    method          VARCHAR(10)     NOT NULL,
+    path            TEXT            NOT NULL,
+    content_type    TEXT,
+    user_agent      TEXT,
+    status          SMALLINT        NOT NULL,
+    -- the request's content length, if the client sent one; only the first `captured_len` bytes
+    -- of the body are kept
+    body_len        BIGINT,
+    captured_len    BIGINT          NOT NULL,
+    -- a nonce followed by the ciphertext of the captured bytes
+    sealed_body     BYTEA           NOT NULL,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    expire_dt       TIMESTAMPTZ     NOT NULL
+);
+
+CREATE INDEX request_captures_user_id_create_dt ON request_captures (user_id, create_dt);
+CREATE INDEX request_captures_expire_dt ON request_captures (expire_dt);
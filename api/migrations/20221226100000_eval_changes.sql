@@ -0,0 +1,32 @@
+-- A log of changes to each user's evals, so that clients can keep their local caches coherent
+-- with the server without listing every eval again.
+
+-- A change is recorded whenever an eval key gets its first result (`inserted`), gets a different
+-- result (`invalidated`), or has its evals deleted (`deleted`). Clients read the log from the last
+-- change they saw, by id. Changes are pruned after a while; `pruned_to` is the highest id pruned,
+-- so that a client asking for changes from before it can be told to list again.
+
+CREATE TABLE IF NOT EXISTS eval_changes (
+    id              BIGSERIAL       PRIMARY KEY,
+    user_id         UUID            NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind            VARCHAR(20)     NOT NULL CHECK (kind IN ('inserted', 'invalidated', 'deleted')),
+    fn_key          TEXT            NOT NULL,
+    fn_hash         VARCHAR(64)     NOT NULL,
+    args_hash       VARCHAR(64)     NOT NULL,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX eval_changes_user_id_id ON eval_changes (user_id, id);
+CREATE INDEX eval_changes_create_dt ON eval_changes (create_dt);
+
+CREATE TABLE IF NOT EXISTS eval_changes_pruned (
+    singleton       BOOL            PRIMARY KEY DEFAULT true CHECK (singleton),
+    pruned_to       BIGINT          NOT NULL
+);
+
+INSERT INTO eval_changes_pruned (pruned_to) VALUES (0) ON CONFLICT DO NOTHING;
+
+-- Evals can now be deleted. A run keeps its record when the eval it recorded is deleted.
+ALTER TABLE runs
+    DROP CONSTRAINT IF EXISTS runs_eval_id_fkey,
+    ADD CONSTRAINT runs_eval_id_fkey FOREIGN KEY (eval_id) REFERENCES evals(id) ON DELETE SET NULL;
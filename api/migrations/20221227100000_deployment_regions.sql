@@ -0,0 +1,21 @@
+-- The deployment topology: the regions the service is deployed in, and the client regions each
+-- one serves.
+
+-- Clients ask `GET /route` where to send their BLOB requests, so that replicas can be added in
+-- other regions without redeploying clients. A client in a region which isn't served anywhere is
+-- routed to the default region. An entry of `serves` ending in `*` matches every client region
+-- starting with what comes before it.
+
+CREATE TABLE IF NOT EXISTS deployment_regions (
+    name            VARCHAR(64)     PRIMARY KEY,
+    blob_endpoint   TEXT            NOT NULL,
+    bucket          TEXT            NOT NULL,
+    serves          TEXT[]          NOT NULL DEFAULT '{}',
+    is_default      BOOL            NOT NULL DEFAULT false,
+    enabled         BOOL            NOT NULL DEFAULT true,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    update_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp
+);
+
+-- At most one region is the default.
+CREATE UNIQUE INDEX deployment_regions_default ON deployment_regions (is_default) WHERE is_default;
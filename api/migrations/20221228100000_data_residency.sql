@@ -0,0 +1,12 @@
+-- Data residency: an org can pin its BLOBs to one deployment region, so that they're only ever
+-- stored in that region's bucket (e.g. EU-only residency).
+
+-- BLOBs owned by an org's owner (and so uploaded by its service accounts) are stored in the
+-- region's bucket. Each BLOB records where it was stored, so that it can still be read after its
+-- org's residency changes; NULL means the server's own bucket.
+
+ALTER TABLE orgs ADD COLUMN IF NOT EXISTS residency_region VARCHAR(64)
+    REFERENCES deployment_regions(name);
+
+ALTER TABLE blobs ADD COLUMN IF NOT EXISTS storage_region VARCHAR(64);
+ALTER TABLE blobs ADD COLUMN IF NOT EXISTS storage_bucket TEXT;
@@ -0,0 +1,84 @@
+-- Legal holds: an org can put selected runs and evals on hold, making them immutable until the
+-- hold is released. Held evals (including the evals recorded by held runs) can't be deleted, and
+-- held runs aren't archived by their project's retention policy.
+
+-- Holds are never deleted, only released, and every change to a hold is recorded in
+-- `legal_hold_events` along with who made it and what was held at the time.
+
+CREATE TABLE IF NOT EXISTS legal_holds (
+    id              BIGSERIAL       PRIMARY KEY,
+    org_id          UUID            NOT NULL REFERENCES orgs(id),
+    reason          TEXT            NOT NULL CHECK (reason <> ''),
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    release_dt      TIMESTAMPTZ
+);
+
+CREATE INDEX legal_holds_org_id ON legal_holds (org_id);
+
+CREATE TABLE IF NOT EXISTS legal_hold_runs (
+    hold_id         BIGINT          NOT NULL REFERENCES legal_holds(id),
+    run_id          UUID            NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+    PRIMARY KEY (hold_id, run_id)
+);
+
+CREATE INDEX legal_hold_runs_run_id ON legal_hold_runs (run_id);
+
+CREATE TABLE IF NOT EXISTS legal_hold_evals (
+    hold_id         BIGINT          NOT NULL REFERENCES legal_holds(id),
+    eval_id         UUID            NOT NULL REFERENCES evals(id) ON DELETE CASCADE,
+    PRIMARY KEY (hold_id, eval_id)
+);
+
+CREATE INDEX legal_hold_evals_eval_id ON legal_hold_evals (eval_id);
+
+CREATE TABLE IF NOT EXISTS legal_hold_events (
+    id              BIGSERIAL       PRIMARY KEY,
+    hold_id         BIGINT          NOT NULL REFERENCES legal_holds(id),
+    action          VARCHAR(10)     NOT NULL CHECK (action IN ('placed', 'released')),
+    user_id         UUID            NOT NULL REFERENCES users(id),
+    -- the label of the API key the change was made with, if it was made with one
+    api_key_label   TEXT,
+    runs            UUID[]          NOT NULL,
+    evals           UUID[]          NOT NULL,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX legal_hold_events_hold_id ON legal_hold_events (hold_id);
+
+-- Whether an eval is under an unreleased hold, either itself or through a run which recorded it.
+CREATE OR REPLACE FUNCTION eval_held(eval UUID) RETURNS BOOL AS
+$BODY$
+    SELECT EXISTS (
+        SELECT 1
+        FROM legal_hold_evals he
+        JOIN legal_holds h ON h.id = he.hold_id
+        WHERE he.eval_id = eval
+            AND h.release_dt IS NULL
+    ) OR EXISTS (
+        SELECT 1
+        FROM legal_hold_runs hr
+        JOIN legal_holds h ON h.id = hr.hold_id
+        JOIN runs r ON r.id = hr.run_id
+        WHERE r.eval_id = eval
+            AND h.release_dt IS NULL
+    )
+$BODY$
+LANGUAGE sql STABLE;
+
+-- Whether a run is under an unreleased hold, either itself or through the eval it recorded.
+CREATE OR REPLACE FUNCTION run_held(run UUID) RETURNS BOOL AS
+$BODY$
+    SELECT EXISTS (
+        SELECT 1
+        FROM legal_hold_runs hr
+        JOIN legal_holds h ON h.id = hr.hold_id
+        WHERE hr.run_id = run
+            AND h.release_dt IS NULL
+    ) OR EXISTS (
+        SELECT 1
+        FROM runs r
+        WHERE r.id = run
+            AND eval_held(r.eval_id)
+    )
+$BODY$
+LANGUAGE sql STABLE;
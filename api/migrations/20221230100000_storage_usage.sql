@@ -0,0 +1,14 @@
+-- Hourly snapshots of the bytes each user stores, by storage tier (hot, infrequent access,
+-- archive, deduplicated), so that billing and quotas can weight the tiers differently.
+
+CREATE TABLE IF NOT EXISTS storage_usage (
+    user_id         UUID            NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    period_start    TIMESTAMPTZ     NOT NULL,
+    tier            VARCHAR(20)     NOT NULL
+        CHECK (tier IN ('hot', 'infrequent_access', 'archive', 'deduplicated')),
+    bytes           BIGINT          NOT NULL,
+    PRIMARY KEY (user_id, period_start, tier)
+);
+
+-- Finding whether a BLOB was stored first by someone else.
+CREATE INDEX IF NOT EXISTS blobs_content_hash_id ON blobs (content_hash, id);
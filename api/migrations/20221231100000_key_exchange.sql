@@ -0,0 +1,31 @@
+-- Short-lived API keys exchanged for a signed in session, so that clients don't need to keep the
+-- session's JWT on disk.
+
+-- An exchanged key is recorded with the name of the device it was issued to, and may be scoped to
+-- one project. Keys stop authenticating once `expire_dt` has passed; keys without one don't
+-- expire.
+
+ALTER TABLE api_keys
+    ADD COLUMN IF NOT EXISTS expire_dt TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS device_name TEXT,
+    ADD COLUMN IF NOT EXISTS project_id UUID REFERENCES projects(id) ON DELETE CASCADE;
+
+CREATE OR REPLACE FUNCTION user_from_key(IN key VARCHAR(64), OUT _result UUID)
+AS
+$BODY$
+BEGIN
+    SELECT u.id INTO _result
+        FROM users u
+        JOIN api_keys ak
+        ON u.id = ak.user_id
+        WHERE ak.key = $1
+            AND (ak.expire_dt IS NULL OR ak.expire_dt > now());
+
+    IF NOT FOUND THEN
+        RAISE EXCEPTION 'Invalid key %', $1 USING ERRCODE = 'invalid_password';
+    END IF;
+
+    RETURN;
+END
+$BODY$
+LANGUAGE plpgsql;
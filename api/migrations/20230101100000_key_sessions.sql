@@ -0,0 +1,6 @@
+-- When, and from where, each API key was last used, so that users can see which of their devices
+-- hold keys and revoke them, e.g. after a laptop is stolen.
+
+ALTER TABLE api_keys
+    ADD COLUMN IF NOT EXISTS last_seen_dt TIMESTAMPTZ,
+    ADD COLUMN IF NOT EXISTS last_seen_ip TEXT;
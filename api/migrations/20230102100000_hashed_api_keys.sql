@@ -0,0 +1,29 @@
+-- API keys are hashed at rest. See `src/keys.rs`.
+
+-- A hashed key is stored under its id, its first 20 characters, in `key`, along with an Argon2
+-- hash of the whole key in `key_hash`. Keys are checked against their hashes by the server, not
+-- here, and queries are then given the id: `user_from_key` and friends resolve ids, and never see
+-- keys. If the server has an S3 gateway key, `sealed_key` holds the key sealed with it, so that
+-- the gateway can check request signatures.
+
+-- Keys issued before this have no hash yet, and are still stored whole in `key`, until the server
+-- hashes them when it starts. Their ids change when they're hashed, so references to them follow.
+
+ALTER TABLE api_keys
+    ADD COLUMN IF NOT EXISTS key_hash TEXT,
+    ADD COLUMN IF NOT EXISTS sealed_key BYTEA;
+
+ALTER TABLE request_captures
+    DROP CONSTRAINT IF EXISTS request_captures_api_key_fkey,
+    ADD CONSTRAINT request_captures_api_key_fkey FOREIGN KEY (api_key) REFERENCES api_keys(key)
+        ON DELETE CASCADE ON UPDATE CASCADE;
+
+ALTER TABLE cache_activity
+    DROP CONSTRAINT IF EXISTS cache_activity_api_key_fkey,
+    ADD CONSTRAINT cache_activity_api_key_fkey FOREIGN KEY (api_key) REFERENCES api_keys(key)
+        ON DELETE CASCADE ON UPDATE CASCADE;
+
+ALTER TABLE anomalies
+    DROP CONSTRAINT IF EXISTS anomalies_api_key_fkey,
+    ADD CONSTRAINT anomalies_api_key_fkey FOREIGN KEY (api_key) REFERENCES api_keys(key)
+        ON DELETE CASCADE ON UPDATE CASCADE;
@@ -0,0 +1,24 @@
+-- Uploads of BLOBs in parts, so that an interrupted upload can be resumed.
+
+-- Each upload is an S3 multipart upload of the BLOB to where it's to be stored. Parts are
+-- received in order: `part_etags` holds the S3 ETag of each part received, and `cv_stack` the
+-- chaining values of the BLOB's BLAKE3 hash after the last of them (as in
+-- `download_checkpoints`). An upload is deleted once it's completed, or aborted once it expires.
+
+CREATE TABLE IF NOT EXISTS blob_uploads (
+    id              UUID            PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id         UUID            NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    content_hash    CHAR(64)        NOT NULL,
+    content_length  BIGINT          NOT NULL,
+    storage_region  VARCHAR(64),
+    storage_bucket  TEXT,
+    s3_upload_id    TEXT            NOT NULL,
+    parts_received  INTEGER         NOT NULL DEFAULT 0,
+    part_etags      TEXT[]          NOT NULL DEFAULT '{}',
+    cv_stack        BYTEA           NOT NULL DEFAULT '',
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    expire_dt       TIMESTAMPTZ     NOT NULL
+);
+
+CREATE INDEX blob_uploads_user_id_content_hash ON blob_uploads (user_id, content_hash);
+CREATE INDEX blob_uploads_expire_dt ON blob_uploads (expire_dt);
@@ -0,0 +1,36 @@
+-- Whether each BLOB's object is actually stored, so that evals whose BLOB isn't can say so.
+
+-- A BLOB is recorded as soon as an eval referencing it is put, which is before its bytes are
+-- uploaded, and its object can go missing later on (a failed upload, a lost race with a delete).
+-- `object_status` is 'pending' until the object is seen in S3, when it's 'available', or
+-- 'missing' if it still isn't there after a grace period, or it has gone. Uploads mark BLOBs
+-- available, and the reconciliation job settles the rest, recording when it last looked in
+-- `checked_dt`. Whether a BLOB is archived is read from its storage class.
+
+ALTER TABLE blobs
+    ADD COLUMN IF NOT EXISTS object_status VARCHAR(20) NOT NULL DEFAULT 'pending'
+        CHECK (object_status IN ('pending', 'available', 'missing')),
+    ADD COLUMN IF NOT EXISTS create_dt TIMESTAMPTZ NOT NULL DEFAULT current_timestamp,
+    ADD COLUMN IF NOT EXISTS checked_dt TIMESTAMPTZ;
+
+-- BLOBs which the backfill found are stored.
+UPDATE blobs b
+SET object_status = 'available', checked_dt = b.verified_dt
+WHERE b.verified_dt IS NOT NULL
+    AND NOT EXISTS (
+        SELECT 1
+        FROM blob_discrepancies d
+        WHERE d.content_hash = b.content_hash
+            AND d.kind = 'missing_object'
+            AND d.resolved_dt IS NULL
+    );
+
+CREATE INDEX blobs_unsettled ON blobs (checked_dt NULLS FIRST)
+    WHERE object_status <> 'available';
+
+-- The status of a BLOB as reported to clients: 'available', 'pending', 'missing' or 'archived'.
+CREATE OR REPLACE FUNCTION blob_status(object_status TEXT, storage_class TEXT) RETURNS TEXT AS
+$BODY$
+    SELECT CASE WHEN storage_class <> 'STANDARD' THEN 'archived' ELSE object_status END
+$BODY$
+LANGUAGE sql IMMUTABLE;
@@ -0,0 +1,7 @@
+-- Index the tables which reference BLOBs by their `blob_id`, so that the garbage collector can
+-- tell whether a BLOB is referenced without scanning them. Deleting a BLOB's row also checks
+-- these, for the foreign keys.
+
+CREATE INDEX IF NOT EXISTS evals_blob_id ON evals (blob_id);
+CREATE INDEX IF NOT EXISTS run_artifacts_blob_id ON run_artifacts (blob_id);
+CREATE INDEX IF NOT EXISTS dvc_objects_blob_id ON dvc_objects (blob_id);
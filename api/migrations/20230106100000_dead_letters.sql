@@ -0,0 +1,28 @@
+-- Dead letters: persists which failed partway, with everything needed to finish them.
+
+-- A BLOB's object is stored in S3 and its row in Postgres, so a failure between the two leaves
+-- one without the other. Rather than rely on the client to notice and retry, the server records
+-- what it was doing here, and an admin can re-drive it once the cause is fixed, or dismiss it.
+-- `detail` holds the operation, tagged with its `kind`.
+
+CREATE TABLE IF NOT EXISTS dead_letters (
+    id              BIGSERIAL       PRIMARY KEY,
+    user_id         UUID            NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind            VARCHAR(20)     NOT NULL
+                                    CHECK (kind IN ('blob', 'dvc_object', 'run_artifact',
+                                        'missing_blob')),
+    content_hash    CHAR(64)        NOT NULL,
+    storage_region  VARCHAR(64),
+    storage_bucket  TEXT,
+    detail          JSONB           NOT NULL,
+    error           TEXT            NOT NULL,
+    status          VARCHAR(10)     NOT NULL DEFAULT 'open'
+                                    CHECK (status IN ('open', 'redriven', 'dismissed')),
+    attempts        INT             NOT NULL DEFAULT 0,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    resolve_dt      TIMESTAMPTZ,
+    -- the admin who re-drove or dismissed it
+    resolved_by     UUID            REFERENCES users(id)
+);
+
+CREATE INDEX dead_letters_open ON dead_letters (create_dt) WHERE status = 'open';
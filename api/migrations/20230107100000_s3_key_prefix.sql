@@ -0,0 +1,8 @@
+-- The prefix of each BLOB's S3 key, so that deployments can share a bucket under different
+-- prefixes (`S3_KEY_PREFIX`). The prefix is recorded as it was when the BLOB was stored, so BLOBs
+-- can still be found after the configured prefix changes, and moved between prefixes. BLOBs
+-- stored before prefixes existed have none.
+
+ALTER TABLE blobs ADD COLUMN IF NOT EXISTS storage_prefix TEXT;
+ALTER TABLE blob_uploads ADD COLUMN IF NOT EXISTS storage_prefix TEXT;
+ALTER TABLE dead_letters ADD COLUMN IF NOT EXISTS storage_prefix TEXT;
@@ -0,0 +1,42 @@
+-- Table of stored BLOB objects, shared by every user who owns the BLOB. `blobs` records who owns
+-- each BLOB (and is what access is checked against); identical BLOBs uploaded by different users
+-- are stored once per bucket, under whatever prefix they were first stored.
+--
+-- `used_dt` is when an upload last found the object already stored. The garbage collector leaves
+-- objects used within its grace period, since their new owner may not have been recorded yet.
+
+CREATE TABLE IF NOT EXISTS blob_objects (
+    id              BIGSERIAL       PRIMARY KEY,
+    content_hash    CHAR(64)        NOT NULL,
+    storage_region  VARCHAR(64),
+    storage_bucket  TEXT,
+    storage_prefix  TEXT,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT now(),
+    used_dt         TIMESTAMPTZ     NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS blob_objects_location ON blob_objects
+    (content_hash, coalesce(storage_region, ''), coalesce(storage_bucket, ''));
+
+-- The object an owner's BLOB is stored as. Its location is kept on `blobs` too.
+ALTER TABLE blobs ADD COLUMN IF NOT EXISTS object_id BIGINT REFERENCES blob_objects(id);
+CREATE INDEX IF NOT EXISTS blobs_object_id ON blobs (object_id);
+
+-- BLOBs stored already. Where the same BLOB was stored under several prefixes of a bucket, the
+-- first copy is the one shared from now on; the others are left to their current owners.
+INSERT INTO blob_objects (content_hash, storage_region, storage_bucket, storage_prefix, create_dt)
+SELECT DISTINCT ON (content_hash, coalesce(storage_region, ''), coalesce(storage_bucket, ''))
+    content_hash, storage_region, storage_bucket, storage_prefix, create_dt
+FROM blobs
+WHERE object_status = 'available'
+ORDER BY content_hash, coalesce(storage_region, ''), coalesce(storage_bucket, ''), id
+ON CONFLICT DO NOTHING;
+
+UPDATE blobs b
+SET object_id = o.id
+FROM blob_objects o
+WHERE o.content_hash = b.content_hash
+    AND o.storage_region IS NOT DISTINCT FROM b.storage_region
+    AND o.storage_bucket IS NOT DISTINCT FROM b.storage_bucket
+    AND o.storage_prefix IS NOT DISTINCT FROM b.storage_prefix
+    AND b.object_status = 'available';
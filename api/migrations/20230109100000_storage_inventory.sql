@@ -0,0 +1,46 @@
+-- Inventories of the objects actually stored in each bucket, listed periodically and compared with
+-- the BLOBs recorded as stored there, so that drift which per-request accounting misses (objects
+-- deleted or overwritten outside the server, objects stored but never recorded) is caught.
+
+CREATE TABLE IF NOT EXISTS storage_inventories (
+    id              BIGSERIAL       PRIMARY KEY,
+    status          VARCHAR(20)     NOT NULL DEFAULT 'running'
+        CHECK (status IN ('running', 'finished', 'failed')),
+    objects         BIGINT          NOT NULL DEFAULT 0,
+    bytes           BIGINT          NOT NULL DEFAULT 0,
+    -- Objects which no BLOB is recorded as stored as, and the bytes they take up.
+    unowned_objects BIGINT          NOT NULL DEFAULT 0,
+    unowned_bytes   BIGINT          NOT NULL DEFAULT 0,
+    error           TEXT,
+    start_dt        TIMESTAMPTZ     NOT NULL DEFAULT now(),
+    finish_dt       TIMESTAMPTZ
+);
+
+-- The objects an inventory listed. They're dropped once a later inventory finishes; the reports
+-- are kept.
+CREATE TABLE IF NOT EXISTS inventory_objects (
+    inventory_id    BIGINT          NOT NULL REFERENCES storage_inventories(id) ON DELETE CASCADE,
+    storage_region  VARCHAR(64),
+    storage_bucket  TEXT,
+    storage_prefix  TEXT,
+    content_hash    CHAR(64)        NOT NULL,
+    size            BIGINT          NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS inventory_objects_content_hash
+    ON inventory_objects (inventory_id, content_hash);
+
+-- What each user's BLOBs should take up, as recorded, against what was found. Orgs are reported
+-- through their owner, whose BLOBs their keys store.
+CREATE TABLE IF NOT EXISTS storage_reports (
+    inventory_id        BIGINT      NOT NULL REFERENCES storage_inventories(id) ON DELETE CASCADE,
+    user_id             UUID        NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    expected_objects    BIGINT      NOT NULL,
+    expected_bytes      BIGINT      NOT NULL,
+    actual_bytes        BIGINT      NOT NULL,
+    -- BLOBs recorded as available whose object wasn't found.
+    missing_objects     BIGINT      NOT NULL,
+    -- BLOBs whose object was found with a different length than recorded.
+    mismatched_objects  BIGINT      NOT NULL,
+    PRIMARY KEY (inventory_id, user_id)
+);
@@ -0,0 +1,13 @@
+-- BLOBs can be compressed at rest with zstd (`BLOB_ZSTD_LEVEL`). A compressed BLOB's object says so
+-- in its `Content-Encoding`, and is decompressed as it's read, so these only record it: how the
+-- object is compressed, if it is, and its length as stored, where `content_length` stays the
+-- length of the BLOB itself. Both are NULL for objects stored as they are before this was
+-- recorded.
+
+ALTER TABLE blob_objects
+    ADD COLUMN IF NOT EXISTS compression VARCHAR(20),
+    ADD COLUMN IF NOT EXISTS stored_length BIGINT;
+
+ALTER TABLE blobs
+    ADD COLUMN IF NOT EXISTS compression VARCHAR(20),
+    ADD COLUMN IF NOT EXISTS stored_length BIGINT;
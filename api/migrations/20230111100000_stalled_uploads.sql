@@ -0,0 +1,7 @@
+-- Uploads aborted because no bytes arrived for `UPLOAD_STALL_SECS` are recorded as cache activity,
+-- alongside uploads rejected for an invalid hash.
+
+ALTER TABLE cache_activity
+    DROP CONSTRAINT IF EXISTS cache_activity_kind_check,
+    ADD CONSTRAINT cache_activity_kind_check CHECK (kind IN ('hit', 'miss', 'download',
+                                                             'invalid_hash', 'stalled_upload'));
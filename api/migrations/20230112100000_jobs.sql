@@ -0,0 +1,29 @@
+-- Long-running productions, such as exports, run as jobs in the background rather than while an
+-- HTTP response is held open. Submitting one returns its id, which is polled at `GET /jobs/{id}`
+-- for progress. What a finished job produced is a BLOB owned by whoever submitted it, which is
+-- downloaded like any other (in ranges, if it's large). Jobs, and so their BLOBs, expire after
+-- `JOB_TTL_HOURS`.
+
+CREATE TABLE IF NOT EXISTS jobs (
+    id              UUID            PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id         UUID            NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    kind            VARCHAR(30)     NOT NULL CHECK (kind IN ('openlineage_export')),
+    -- what to produce, with everything needed to produce it
+    spec            JSONB           NOT NULL,
+    status          VARCHAR(20)     NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'running', 'finished', 'failed')),
+    -- how many times the job has been started; a job interrupted (e.g. by a restart) is run again
+    attempts        INT             NOT NULL DEFAULT 0,
+    progress_done   BIGINT          NOT NULL DEFAULT 0,
+    progress_total  BIGINT,
+    blob_id         BIGINT          REFERENCES blobs(id),
+    error           TEXT,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT now(),
+    -- when the job last made progress
+    update_dt       TIMESTAMPTZ     NOT NULL DEFAULT now(),
+    finish_dt       TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS jobs_user_id ON jobs (user_id, create_dt);
+CREATE INDEX IF NOT EXISTS jobs_unfinished ON jobs (create_dt)
+    WHERE status IN ('pending', 'running');
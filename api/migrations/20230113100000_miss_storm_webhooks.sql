@@ -0,0 +1,17 @@
+-- Per-project webhooks on cache-miss storms.
+
+-- A project with a `miss_storm_webhook_url` is sent an event when more than `miss_storm_threshold`
+-- of the lookups of its functions reported within the last `miss_storm_window_hours` hours missed,
+-- which usually means a refactor invalidated its cache. Lookups are counted from `eval_usage`,
+-- which is hourly, so the window takes in the current, partial hour. A project's functions are
+-- those it has evals of. The event is sent at most once per window.
+
+ALTER TABLE projects
+    ADD COLUMN IF NOT EXISTS miss_storm_webhook_url TEXT,
+    ADD COLUMN IF NOT EXISTS miss_storm_threshold DOUBLE PRECISION NOT NULL DEFAULT 0.5
+        CHECK (miss_storm_threshold > 0 AND miss_storm_threshold < 1),
+    ADD COLUMN IF NOT EXISTS miss_storm_window_hours INT NOT NULL DEFAULT 1
+        CHECK (miss_storm_window_hours > 0),
+    ADD COLUMN IF NOT EXISTS miss_storm_fired_dt TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS evals_project_id_fn_key ON evals (project_id, fn_key);
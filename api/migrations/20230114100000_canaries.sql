@@ -0,0 +1,42 @@
+-- Canary BLOBs, for detecting leaks.
+
+-- An org generates a canary: a BLOB of random bytes owned by the org's owner, which nothing
+-- should have reason to download except what the org expects to. The org plants its hash where
+-- it would be found by whoever has leaked something, e.g. among a project's evals. A download with
+-- a key not in `allowed_keys`, or from an address outside `allowed_cidrs`, trips the canary: the
+-- trip is recorded, and an event sent to its webhook. Either list is ignored when empty, and a
+-- canary with neither is tripped by every download. The download itself goes ahead, so whoever
+-- made it can't tell it was a canary.
+
+-- Set on the BLOBs of canaries, so that downloads of other BLOBs needn't look for any.
+ALTER TABLE blobs
+    ADD COLUMN IF NOT EXISTS canary BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS canaries (
+    id              BIGSERIAL       PRIMARY KEY,
+    org_id          UUID            NOT NULL REFERENCES orgs(id) ON DELETE CASCADE,
+    blob_id         BIGINT          NOT NULL REFERENCES blobs(id) ON DELETE CASCADE,
+    label           TEXT            NOT NULL CHECK (label <> ''),
+    -- ids of the API keys expected to download the canary
+    allowed_keys    TEXT[]          NOT NULL DEFAULT '{}',
+    allowed_cidrs   CIDR[]          NOT NULL DEFAULT '{}',
+    webhook_url     TEXT,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX canaries_org_id ON canaries (org_id);
+CREATE INDEX canaries_blob_id ON canaries (blob_id);
+
+CREATE TABLE IF NOT EXISTS canary_trips (
+    id              BIGSERIAL       PRIMARY KEY,
+    canary_id       BIGINT          NOT NULL REFERENCES canaries(id) ON DELETE CASCADE,
+    user_id         UUID            NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    -- the id and label of the key the download was made with; null for signed in users
+    api_key         VARCHAR(64),
+    api_key_label   TEXT,
+    -- the address the download came from, as given, if there was one
+    ip              TEXT,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX canary_trips_canary_id ON canary_trips (canary_id, create_dt);
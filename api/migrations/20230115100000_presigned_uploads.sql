@@ -0,0 +1,23 @@
+-- Uploads of BLOBs straight to S3, with presigned URLs, so that very large BLOBs needn't pass
+-- through the server.
+
+-- The client is given a URL to PUT the BLOB to a staging key, `<prefix>staged/<id>`, rather than
+-- its own key, so that nothing it uploads can replace a BLOB which is already stored. Once it
+-- confirms the upload, the staged object is checked against the claimed hash and length, and moved
+-- to the BLOB's key. An upload is deleted once it's confirmed, or deleted, along with whatever was
+-- staged, once it expires.
+
+CREATE TABLE IF NOT EXISTS presigned_uploads (
+    id              UUID            PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id         UUID            NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    content_hash    CHAR(64)        NOT NULL,
+    content_length  BIGINT          NOT NULL,
+    storage_region  VARCHAR(64),
+    storage_bucket  TEXT,
+    storage_prefix  TEXT,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    expire_dt       TIMESTAMPTZ     NOT NULL
+);
+
+CREATE INDEX presigned_uploads_user_id ON presigned_uploads (user_id);
+CREATE INDEX presigned_uploads_expire_dt ON presigned_uploads (expire_dt);
@@ -0,0 +1,13 @@
+-- Fuzzy search over the names of a user's things, for the dashboard's search box.
+
+-- Names are matched by trigram (the pg_trgm extension): a name matches if it contains the query,
+-- or a word close to it, so that "resnet" finds `models.resnet50.train` and "resnt" still finds
+-- it. The GIN indexes serve both `ILIKE` and word similarity (`<%`) lookups.
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS evals_fn_key_trgm ON evals USING GIN (fn_key gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS runs_name_trgm ON runs USING GIN (name gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS runs_fn_key_trgm ON runs USING GIN (fn_key gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS run_artifacts_path_trgm ON run_artifacts USING GIN (path gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS projects_name_trgm ON projects USING GIN (name gin_trgm_ops);
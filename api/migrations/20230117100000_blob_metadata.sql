@@ -0,0 +1,8 @@
+-- Metadata clients give with a BLOB when they upload it, so that others can decide whether to
+-- download it from a `HEAD /blob/{hash}`: what kind of content it is, and a label of their own.
+-- `content_length` is now recorded as BLOBs are uploaded too, rather than left to the backfill.
+-- Uploading a BLOB again with metadata replaces what was recorded; without it, leaves it be.
+
+ALTER TABLE blobs
+    ADD COLUMN IF NOT EXISTS mime_type VARCHAR(255),
+    ADD COLUMN IF NOT EXISTS label TEXT;
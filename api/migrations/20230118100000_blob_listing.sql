@@ -0,0 +1,4 @@
+-- The storage browser lists a user's BLOBs newest first, a page at a time, following the id of the
+-- last BLOB of each page.
+
+CREATE INDEX IF NOT EXISTS blobs_user_id_id ON blobs (user_id, id DESC);
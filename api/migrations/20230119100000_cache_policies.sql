@@ -0,0 +1,16 @@
+-- Per-function caching policies, which clients fetch and follow, so that how each function is
+-- cached is decided in one place for everyone using the account, rather than in each client's
+-- configuration. Functions without a policy are cached as usual.
+
+CREATE TABLE IF NOT EXISTS cache_policies (
+    user_id             UUID            NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    fn_key              TEXT            NOT NULL,
+    mode                VARCHAR(20)     NOT NULL DEFAULT 'cache'
+                                        CHECK (mode IN ('cache', 'never', 'local_only')),
+    -- how long an eval is used for before it's computed again
+    ttl_secs            BIGINT          CHECK (ttl_secs > 0),
+    -- results larger than this aren't cached
+    max_result_bytes    BIGINT          CHECK (max_result_bytes >= 0),
+    update_dt           TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    PRIMARY KEY (user_id, fn_key)
+);
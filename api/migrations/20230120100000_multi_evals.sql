@@ -0,0 +1,7 @@
+-- Evals put together with one BLOB holding all of their results, e.g. the named outputs of one
+-- function, each have their result at a range of the BLOB. Evals whose result is the whole BLOB
+-- have neither.
+
+ALTER TABLE evals
+    ADD COLUMN IF NOT EXISTS blob_offset BIGINT CHECK (blob_offset >= 0),
+    ADD COLUMN IF NOT EXISTS blob_length BIGINT CHECK (blob_length >= 0);
@@ -0,0 +1,6 @@
+-- An eval's result is either the whole of its BLOB, or the range of it at `blob_offset` and
+-- `blob_length`, so the two are set together. Payloads are downloaded by eval id.
+
+ALTER TABLE evals
+    ADD CONSTRAINT evals_blob_range_check
+        CHECK ((blob_offset IS NULL) = (blob_length IS NULL));
@@ -0,0 +1,12 @@
+-- Per-project webhooks which validate evals before they're stored.
+
+-- A project with a `validation_webhook_url` has each put of its evals sent there first, and the
+-- put is refused if the webhook rejects it. A webhook which doesn't answer within
+-- `validation_timeout_ms`, or fails, lets the put through if `validation_fail_open`, and refuses
+-- it otherwise.
+
+ALTER TABLE projects
+    ADD COLUMN IF NOT EXISTS validation_webhook_url TEXT,
+    ADD COLUMN IF NOT EXISTS validation_timeout_ms INT NOT NULL DEFAULT 2000
+        CHECK (validation_timeout_ms > 0 AND validation_timeout_ms <= 30000),
+    ADD COLUMN IF NOT EXISTS validation_fail_open BOOLEAN NOT NULL DEFAULT true;
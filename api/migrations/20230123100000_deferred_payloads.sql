@@ -0,0 +1,21 @@
+-- Evals put before their result BLOBs are ready to upload.
+
+-- A BLOB whose eval was put with `deferred` is 'awaiting_payload' rather than 'pending', until
+-- `payload_due_dt` rather than for the usual grace period, so that slow serialization of the
+-- result doesn't get it reported missing. It's reported to clients as 'pending' all the same.
+
+ALTER TABLE blobs
+    DROP CONSTRAINT IF EXISTS blobs_object_status_check,
+    ADD CONSTRAINT blobs_object_status_check
+        CHECK (object_status IN ('pending', 'awaiting_payload', 'available', 'missing')),
+    ADD COLUMN IF NOT EXISTS payload_due_dt TIMESTAMPTZ;
+
+CREATE OR REPLACE FUNCTION blob_status(object_status TEXT, storage_class TEXT) RETURNS TEXT AS
+$BODY$
+    SELECT CASE
+        WHEN storage_class <> 'STANDARD' THEN 'archived'
+        WHEN object_status = 'awaiting_payload' THEN 'pending'
+        ELSE object_status
+    END
+$BODY$
+LANGUAGE sql IMMUTABLE;
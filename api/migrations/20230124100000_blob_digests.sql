@@ -0,0 +1,16 @@
+-- Digests of stored BLOBs under hash algorithms other than the one they're addressed by.
+
+-- While `CONTENT_HASH_SECONDARY` is set, each object's digest under it is recorded here, so that
+-- BLOBs can be looked up by either hash until the content-address space has been moved over.
+-- Digests are of the BLOB's bytes, not of its stored object, which may be compressed.
+
+CREATE TABLE IF NOT EXISTS blob_digests (
+    content_hash TEXT NOT NULL,
+    algorithm TEXT NOT NULL,
+    digest TEXT NOT NULL,
+    create_dt TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (algorithm, digest)
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS blob_digests_content_hash
+    ON blob_digests (content_hash, algorithm);
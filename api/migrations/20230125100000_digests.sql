@@ -0,0 +1,23 @@
+-- Weekly digest emails, and users' preferences for them.
+
+-- Each user is sent a digest of their own week, and the owner of each org one of its members'
+-- week, unless they've opted out in their preferences. A user without preferences has the
+-- defaults. `digest_sends` records each digest as it's claimed for sending, so that a digest is
+-- only sent once even with several servers running; `org_id` is null for a user's own digest.
+
+CREATE TABLE IF NOT EXISTS user_preferences (
+    user_id         UUID            PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    digest_emails   BOOL            NOT NULL DEFAULT true,
+    update_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp
+);
+
+CREATE TABLE IF NOT EXISTS digest_sends (
+    id              BIGSERIAL       PRIMARY KEY,
+    recipient_id    UUID            NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    org_id          UUID            REFERENCES orgs(id) ON DELETE CASCADE,
+    period_start    TIMESTAMPTZ     NOT NULL,
+    sent_dt         TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS digest_sends_period ON digest_sends
+    (recipient_id, coalesce(org_id, '00000000-0000-0000-0000-000000000000'), period_start);
@@ -0,0 +1,32 @@
+-- Chunks of BLOBs stored in chunks, and the manifests saying which chunks each BLOB is made of.
+--
+-- With `BLOB_CHUNKING` set, large BLOBs are cut into content-defined chunks, and each chunk is
+-- stored as an object keyed by its own hash, once per bucket however many BLOBs it's in. A BLOB's
+-- manifest lists its chunks in order, and is keyed by the BLOB's location like `blob_objects`.
+
+CREATE TABLE IF NOT EXISTS blob_chunks (
+    id              BIGSERIAL       PRIMARY KEY,
+    chunk_hash      CHAR(64)        NOT NULL,
+    storage_region  VARCHAR(64),
+    storage_bucket  TEXT,
+    storage_prefix  TEXT,
+    length          BIGINT          NOT NULL,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS blob_chunks_location ON blob_chunks
+    (chunk_hash, coalesce(storage_region, ''), coalesce(storage_bucket, ''));
+
+CREATE TABLE IF NOT EXISTS blob_manifests (
+    content_hash    CHAR(64)        NOT NULL,
+    storage_region  VARCHAR(64),
+    storage_bucket  TEXT,
+    seq             INT             NOT NULL,
+    chunk_id        BIGINT          NOT NULL REFERENCES blob_chunks(id),
+    chunk_offset    BIGINT          NOT NULL,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS blob_manifests_location ON blob_manifests
+    (content_hash, coalesce(storage_region, ''), coalesce(storage_bucket, ''), seq);
+CREATE INDEX IF NOT EXISTS blob_manifests_chunk_id ON blob_manifests (chunk_id);
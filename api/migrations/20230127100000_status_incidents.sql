@@ -0,0 +1,32 @@
+-- Incidents published in the status feed at `/status.json`, posted and updated by admins.
+--
+-- An incident affects some of the components whose health the feed reports (`api`, `database`,
+-- `blob_store` and `job_queue`), and while it's unresolved, they're reported as at least as bad as
+-- its impact. Each note posted on it moves it to the note's status; it's resolved by a note with
+-- the status `resolved`.
+
+CREATE TABLE IF NOT EXISTS status_incidents (
+    id              BIGSERIAL       PRIMARY KEY,
+    title           TEXT            NOT NULL,
+    components      TEXT[]          NOT NULL,
+    impact          VARCHAR(20)     NOT NULL CHECK (impact IN ('degraded', 'outage')),
+    status          VARCHAR(20)     NOT NULL
+        CHECK (status IN ('investigating', 'identified', 'monitoring', 'resolved')),
+    -- the admin who posted it
+    user_id         UUID            REFERENCES users(id) ON DELETE SET NULL,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT now(),
+    update_dt       TIMESTAMPTZ     NOT NULL DEFAULT now(),
+    resolve_dt      TIMESTAMPTZ
+);
+
+CREATE TABLE IF NOT EXISTS status_incident_notes (
+    id              BIGSERIAL       PRIMARY KEY,
+    incident_id     BIGINT          NOT NULL REFERENCES status_incidents(id) ON DELETE CASCADE,
+    status          VARCHAR(20)     NOT NULL,
+    message         TEXT            NOT NULL,
+    user_id         UUID            REFERENCES users(id) ON DELETE SET NULL,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS status_incident_notes_incident_id
+    ON status_incident_notes (incident_id, create_dt);
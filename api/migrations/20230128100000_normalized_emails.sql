@@ -0,0 +1,53 @@
+-- Email addresses are stored normalized: without surrounding whitespace, and lowercase (see
+-- `models::user::normalize_email`), so they can be compared exactly, and an address can't be on
+-- the waitlist, or belong to more than one user, in two cases.
+
+-- Waitlist entries differing only by case are duplicates; the earliest is kept.
+DELETE FROM waitlist w
+USING waitlist earlier
+WHERE lower(btrim(w.email)) = lower(btrim(earlier.email))
+    AND (earlier.create_dt, earlier.id) < (w.create_dt, w.id);
+
+UPDATE waitlist SET email = lower(btrim(email)) WHERE email <> lower(btrim(email));
+
+CREATE UNIQUE INDEX IF NOT EXISTS waitlist_email_lower ON waitlist (lower(email));
+
+-- Users whose addresses differ only by case can't be merged here, since each may own BLOBs,
+-- API keys and memberships. The earliest keeps the address; the others lose it, and are recorded
+-- so they can be merged by hand.
+CREATE TABLE IF NOT EXISTS user_email_conflicts (
+    user_id         UUID            PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    -- the address the user had
+    email           VARCHAR(100)    NOT NULL,
+    -- the user who kept it
+    kept_user_id    UUID            NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    create_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp
+);
+
+INSERT INTO user_email_conflicts (user_id, email, kept_user_id)
+SELECT u.id, u.gh_email, kept.id
+FROM users u
+JOIN LATERAL (
+    SELECT k.id
+    FROM users k
+    WHERE lower(btrim(k.gh_email)) = lower(btrim(u.gh_email))
+    ORDER BY k.create_dt, k.id
+    LIMIT 1
+) kept ON kept.id <> u.id
+ON CONFLICT (user_id) DO NOTHING;
+
+UPDATE users
+SET gh_email = NULL, update_dt = current_timestamp
+WHERE id IN (SELECT user_id FROM user_email_conflicts);
+
+-- Users who have never signed in with GitHub have their address as their GitHub login, too.
+UPDATE users
+SET gh_email = lower(btrim(gh_email)),
+    gh_login = CASE
+        WHEN gh_id IS NULL AND gh_login = gh_email THEN lower(btrim(gh_email))
+        ELSE gh_login
+    END,
+    update_dt = current_timestamp
+WHERE gh_email <> lower(btrim(gh_email));
+
+CREATE UNIQUE INDEX IF NOT EXISTS users_gh_email_lower ON users (lower(gh_email));
@@ -0,0 +1,41 @@
+-- Differential sync of the functions registry, for editor plugins and clients refreshing their view
+-- of the user's functions, their versions, and their caching policies with
+-- `GET /function/changed?since=`.
+
+-- When each version of a function was last changed. Versions are identified by their source's
+-- hash, so they're only changed by backfills of their docstrings or signatures.
+ALTER TABLE functions ADD COLUMN IF NOT EXISTS update_dt TIMESTAMPTZ;
+UPDATE functions SET update_dt = create_dt WHERE update_dt IS NULL;
+ALTER TABLE functions ALTER COLUMN update_dt SET DEFAULT current_timestamp;
+ALTER TABLE functions ALTER COLUMN update_dt SET NOT NULL;
+
+CREATE OR REPLACE FUNCTION functions_set_update_dt()
+RETURNS trigger
+AS
+$BODY$
+BEGIN
+    NEW.update_dt = current_timestamp;
+    RETURN NEW;
+END
+$BODY$
+LANGUAGE plpgsql;
+
+CREATE TRIGGER functions_set_update_dt
+    BEFORE UPDATE ON functions
+    FOR EACH ROW
+    EXECUTE FUNCTION functions_set_update_dt();
+
+CREATE INDEX IF NOT EXISTS functions_user_id_update_dt ON functions (user_id, update_dt);
+CREATE INDEX IF NOT EXISTS cache_policies_user_id_update_dt ON cache_policies (user_id, update_dt);
+
+-- The functions whose caching policies were removed, and when, so that clients which synced before
+-- can drop them. A policy which is set again is no longer removed.
+CREATE TABLE IF NOT EXISTS cache_policy_removals (
+    user_id         UUID            NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    fn_key          TEXT            NOT NULL,
+    remove_dt       TIMESTAMPTZ     NOT NULL DEFAULT current_timestamp,
+    PRIMARY KEY (user_id, fn_key)
+);
+
+CREATE INDEX IF NOT EXISTS cache_policy_removals_user_id_remove_dt
+    ON cache_policy_removals (user_id, remove_dt);
@@ -0,0 +1,13 @@
+-- An org's identity provider asserting an email address isn't enough to bring the account which
+-- has it into the org, where the org's policies apply to it and its usage is the org's. Memberships
+-- SCIM provisions for accounts which already existed wait for their users to accept them.
+ALTER TABLE org_members
+    ADD COLUMN IF NOT EXISTS accepted BOOL NOT NULL DEFAULT true;
+
+-- Provisioning which created its user did so in the same transaction as the membership, so the
+-- two were created at the same time. Memberships created later attached an existing account.
+UPDATE org_members m
+SET accepted = false, update_dt = current_timestamp
+FROM users u
+WHERE u.id = m.user_id
+    AND u.create_dt <> m.create_dt;
@@ -1,9 +1,15 @@
 #[macro_use]
 extern crate lazy_static;
 
-use actix_web::{error, middleware, web, App, HttpServer, Result};
+use actix_web::{dev::Service, error, middleware, web, App, HttpServer, Result};
 use hitsave_api::config::{Config, Opts};
-use hitsave_api::{handlers, msg_pack};
+use hitsave_api::middlewares::access_log::AccessLog;
+use hitsave_api::middlewares::capture::Capture;
+use hitsave_api::middlewares::error_codes::ErrorCodes;
+use hitsave_api::middlewares::key_auth::KeyAuth;
+use hitsave_api::priority::{self, Priority};
+use hitsave_api::{handlers, jobs, msg_pack};
+use std::time::Instant;
 
 lazy_static! {
     pub static ref CONFIG: Config = Config::parse_from_env();
@@ -15,10 +21,37 @@ async fn main() -> std::io::Result<()> {
     let config = &*CONFIG;
     let state = config.clone().into_state().await;
     let state2 = state.clone();
+    let internal_state = state.clone();
+
+    // Before the jobs which wait on its elections.
+    actix_rt::spawn(jobs::leader::run(state.clone()));
+    actix_rt::spawn(jobs::alerts::run(state.clone()));
+    actix_rt::spawn(jobs::digests::run(state.clone()));
+    actix_rt::spawn(jobs::archive::run(state.clone()));
+    actix_rt::spawn(jobs::anomalies::run(state.clone()));
+    actix_rt::spawn(jobs::miss_storms::run(state.clone()));
+    actix_rt::spawn(jobs::blob_stats::run(state.clone()));
+    actix_rt::spawn(jobs::blob_reconcile::run(state.clone()));
+    actix_rt::spawn(jobs::blob_uploads::run(state.clone()));
+    actix_rt::spawn(jobs::blob_gc::run(state.clone()));
+    actix_rt::spawn(jobs::tensor_summaries::run(state.clone()));
+    actix_rt::spawn(jobs::embeddings::run(state.clone()));
+    actix_rt::spawn(jobs::manifest::run(state.clone()));
+    actix_rt::spawn(jobs::fanout::run(state.clone()));
+    actix_rt::spawn(jobs::listing_cache::run(state.clone()));
+    actix_rt::spawn(jobs::slo::run(state.clone()));
+    actix_rt::spawn(jobs::status_checks::run(state.clone()));
+    actix_rt::spawn(jobs::captures::run(state.clone()));
+    actix_rt::spawn(jobs::changes::run(state.clone()));
+    actix_rt::spawn(jobs::storage_usage::run(state.clone()));
+    actix_rt::spawn(jobs::inventory::run(state.clone()));
+    actix_rt::spawn(jobs::key_hashing::run(state.clone()));
+    actix_rt::spawn(jobs::rehashing::run(state.clone()));
+    actix_rt::spawn(jobs::queued::run(state.clone()));
 
     log::info!("starting server..");
 
-    HttpServer::new(move || {
+    let public = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(state.clone()))
             .app_data(state.clone())
@@ -27,22 +60,141 @@ async fn main() -> std::io::Result<()> {
             .app_data(web::JsonConfig::default())
             .app_data(web::QueryConfig::default())
             .app_data(web::FormConfig::default())
+            .wrap(Capture::new(state.clone()))
+            .wrap_fn({
+                let state = state.clone();
+                move |req, srv| {
+                    // Once the server is busy, batch requests are turned away.
+                    let priority = Priority::of(req.request());
+                    let admitted = priority::admits(
+                        priority,
+                        state.load.in_flight(),
+                        state.config.batch_capacity,
+                    );
+                    // Rejections are answered here, as middleware outside this one can't hold on to
+                    // the request to answer errors with.
+                    let res = if admitted {
+                        Ok((state.load.start(), srv.call(req)))
+                    } else {
+                        Err(req.error_response(priority::overloaded(state.poll_after_ms())))
+                    };
+                    let slo = state.slo.clone();
+                    let start = Instant::now();
+                    async move {
+                        let (guard, res) = match res {
+                            Ok(admitted) => admitted,
+                            Err(rejected) => return Ok(rejected),
+                        };
+                        let res = res.await;
+                        drop(guard);
+                        // Requests which match no route aren't tracked, as their paths are
+                        // unbounded.
+                        if let Ok(res) = &res {
+                            if let Some(route) = res.request().match_pattern() {
+                                slo.record(&route, res.status().as_u16(), start.elapsed());
+                            }
+                        }
+                        res
+                    }
+                }
+            })
+            // Inside compression, as error bodies are replaced.
+            .wrap(ErrorCodes)
             .wrap(middleware::Compress::default())
-            .wrap(middleware::Logger::new(
-                "%a %r %s %b %{Referer}i %{User-Agent}i %Dms",
+            .wrap(middleware::Condition::new(
+                !state.config.access_log_json,
+                middleware::Logger::new("%a %r %s %b %{Referer}i %{User-Agent}i %Dms"),
+            ))
+            .wrap(middleware::Condition::new(
+                state.config.access_log_json,
+                AccessLog::new(state.db_conn.clone()),
             ))
+            // API keys are checked first, so that the middleware above sees checked keys.
+            .wrap(KeyAuth::new(state.clone()))
             .default_service(web::route().to(not_found))
+            // Before `/blob`, which would otherwise match uploads' paths.
+            .service(web::scope("/blob/upload").configure(handlers::blob_upload::init))
+            .service(web::scope("/blob/presign-upload").configure(handlers::presigned_upload::init))
             .service(web::scope("/blob").configure(handlers::blob::init))
             .service(web::scope("/eval").configure(handlers::eval::init))
             .service(web::scope("/user").configure(handlers::user::init))
             .service(web::scope("/api_key").configure(handlers::api_key::init))
             .service(web::scope("/waitlist").configure(handlers::waitlist::init))
+            .service(web::scope("/project").configure(handlers::project::init))
+            .service(web::scope("/metric").configure(handlers::metric::init))
+            .service(web::scope("/alert").configure(handlers::alert::init))
+            .service(web::scope("/run").configure(handlers::run::init))
+            .service(web::scope("/export").configure(handlers::export::init))
+            .service(web::scope("/jobs").configure(handlers::job::init))
+            .service(web::scope("/mlflow").configure(handlers::mlflow::init))
+            .service(web::scope("/dvc").configure(handlers::dvc::init))
+            .service(web::scope("/s3").configure(handlers::s3gateway::init))
+            .service(web::scope("/jupyter").configure(handlers::jupyter::init))
+            .service(web::scope("/provision").configure(handlers::provision::init))
+            .service(web::scope("/scim/v2").configure(handlers::scim::init))
+            .service(web::scope("/sso").configure(handlers::sso::init))
+            .service(
+                web::scope("/policy")
+                    .configure(handlers::cache_policy::init)
+                    .configure(handlers::policy::init),
+            )
+            .service(web::scope("/function").configure(handlers::function::init))
+            .service(web::scope("/changes").configure(handlers::change::init))
+            .service(web::scope("/route").configure(handlers::route::init))
+            .service(web::scope("/hold").configure(handlers::hold::init))
+            .service(web::scope("/canary").configure(handlers::canary::init))
+            .service(web::scope("/usage").configure(handlers::usage::init))
+            .service(web::scope("/search").configure(handlers::search::init))
+            .configure(handlers::status::init)
+            .configure(handlers::capabilities::init)
     })
     .workers(1)
     .keep_alive(std::time::Duration::from_secs(300))
     .bind(("0.0.0.0", state2.config.port))?
-    .run()
-    .await
+    .run();
+
+    // Operational endpoints are served on their own listener, bound to a private interface, so
+    // that they're never exposed on the public one.
+    let internal = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(internal_state.clone()))
+            .app_data(internal_state.clone())
+            .app_data(web::PathConfig::default())
+            .app_data(web::JsonConfig::default())
+            .app_data(web::QueryConfig::default())
+            .wrap(ErrorCodes)
+            .wrap(middleware::Condition::new(
+                !internal_state.config.access_log_json,
+                middleware::Logger::new("%a %r %s %b %{Referer}i %{User-Agent}i %Dms"),
+            ))
+            .wrap(middleware::Condition::new(
+                internal_state.config.access_log_json,
+                AccessLog::new(internal_state.db_conn.clone()),
+            ))
+            .wrap(KeyAuth::new(internal_state.clone()))
+            .default_service(web::route().to(not_found))
+            .configure(handlers::ops::init)
+            .service(web::scope("/admin/anomalies").configure(handlers::anomaly::init))
+            .service(web::scope("/admin/blobs").configure(handlers::blob_backfill::init))
+            .service(web::scope("/admin/bandwidth").configure(handlers::bandwidth::init))
+            .service(web::scope("/admin/slo").configure(handlers::slo::init))
+            .service(web::scope("/admin/chaos").configure(handlers::chaos::init))
+            .service(web::scope("/admin/captures").configure(handlers::capture::init))
+            .service(web::scope("/admin/dead_letters").configure(handlers::dead_letter::init))
+            .service(web::scope("/admin/storage").configure(handlers::storage_report::init))
+            .service(web::scope("/admin/topology").configure(handlers::topology::init))
+            .service(web::scope("/admin/incidents").configure(handlers::incident::init))
+            .service(web::scope("/admin/jobs").configure(handlers::leader::init))
+    })
+    .workers(1)
+    .bind((
+        state2.config.internal_host.as_str(),
+        state2.config.internal_port,
+    ))?
+    .run();
+
+    futures::future::try_join(public, internal).await?;
+    Ok(())
 }
 
 async fn not_found() -> Result<&'static str> {
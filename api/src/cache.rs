@@ -0,0 +1,177 @@
+//! A cache of the results of listings whose queries are too expensive to re-run on every refresh
+//! of the dashboard.
+//!
+//! Results are cached per user, project and listing parameters. When evals or metrics are inserted,
+//! Postgres notifies every server on [`CHANNEL`] of the users and projects which changed, and the
+//! `listing_cache` job invalidates the results which may have included them. Results also expire
+//! after a while, in case a notification is missed.
+use serde::Deserialize;
+use sqlx::types::Uuid;
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The channel Postgres sends [`Change`]s on.
+pub const CHANNEL: &str = "listings_changed";
+
+/// Evals or metrics were inserted for a user, in a project or in no project.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub user_id: Uuid,
+    pub project: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub user_id: Uuid,
+    /// The project the listing is restricted to, if it is.
+    pub project: Option<String>,
+    pub listing: &'static str,
+    /// The listing's other parameters.
+    pub params: String,
+}
+
+impl CacheKey {
+    /// Whether the listing may include what changed.
+    fn affected_by(&self, change: &Change) -> bool {
+        self.user_id == change.user_id && (self.project.is_none() || self.project == change.project)
+    }
+}
+
+struct Entry {
+    value: Arc<dyn Any + Send + Sync>,
+    inserted: Instant,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<CacheKey, Entry>,
+    /// Counts invalidations, so that results fetched before one aren't cached after it.
+    generation: u64,
+}
+
+#[derive(Clone)]
+pub struct ListingCache {
+    inner: Arc<Mutex<Inner>>,
+    ttl: Duration,
+    max_entries: usize,
+}
+
+impl ListingCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Inner::default())),
+            ttl,
+            max_entries,
+        }
+    }
+
+    pub fn get<T: Clone + Send + Sync + 'static>(&self, key: &CacheKey) -> Option<Vec<T>> {
+        let inner = self.inner.lock().unwrap();
+        let entry = inner.entries.get(key)?;
+        if entry.inserted.elapsed() > self.ttl {
+            return None;
+        }
+        entry.value.downcast_ref::<Vec<T>>().cloned()
+    }
+
+    fn insert<T: Send + Sync + 'static>(&self, key: CacheKey, value: Vec<T>, generation: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.generation != generation || self.max_entries == 0 {
+            return;
+        }
+        if inner.entries.len() >= self.max_entries {
+            let ttl = self.ttl;
+            inner.entries.retain(|_, e| e.inserted.elapsed() <= ttl);
+        }
+        // Rather than track which entries are least recently used, a full cache starts over.
+        if inner.entries.len() >= self.max_entries {
+            inner.entries.clear();
+        }
+        inner.entries.insert(
+            key,
+            Entry {
+                value: Arc::new(value),
+                inserted: Instant::now(),
+            },
+        );
+    }
+
+    /// Returns the cached result of a listing, or fetches and caches it.
+    pub async fn get_or_fetch<T, E, F>(&self, key: CacheKey, fetch: F) -> Result<Vec<T>, E>
+    where
+        T: Clone + Send + Sync + 'static,
+        F: Future<Output = Result<Vec<T>, E>>,
+    {
+        if let Some(value) = self.get(&key) {
+            return Ok(value);
+        }
+        let generation = self.inner.lock().unwrap().generation;
+        let value = fetch.await?;
+        self.insert(key, value.clone(), generation);
+        Ok(value)
+    }
+
+    /// Drops the cached listings which may include `change`.
+    pub fn invalidate(&self, change: &Change) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        inner.entries.retain(|k, _| !k.affected_by(change));
+    }
+
+    /// Drops every cached listing, e.g. when notifications may have been missed.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        inner.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(user_id: Uuid, project: Option<&str>) -> CacheKey {
+        CacheKey {
+            user_id,
+            project: project.map(String::from),
+            listing: "metrics",
+            params: String::new(),
+        }
+    }
+
+    #[test]
+    fn invalidates_affected_listings() {
+        let cache = ListingCache::new(Duration::from_secs(60), 10);
+        let (alice, bob) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        for k in [
+            key(alice, None),
+            key(alice, Some("a")),
+            key(alice, Some("b")),
+            key(bob, None),
+        ] {
+            cache.insert(k, vec![1], 0);
+        }
+
+        cache.invalidate(&Change {
+            user_id: alice,
+            project: Some("a".to_string()),
+        });
+
+        assert_eq!(cache.get::<i32>(&key(alice, None)), None);
+        assert_eq!(cache.get::<i32>(&key(alice, Some("a"))), None);
+        assert_eq!(cache.get::<i32>(&key(alice, Some("b"))), Some(vec![1]));
+        assert_eq!(cache.get::<i32>(&key(bob, None)), Some(vec![1]));
+    }
+
+    #[test]
+    fn skips_results_fetched_before_invalidation() {
+        let cache = ListingCache::new(Duration::from_secs(60), 10);
+        let user_id = Uuid::from_u128(1);
+        cache.clear();
+        cache.insert(key(user_id, None), vec![1], 0);
+        assert_eq!(cache.get::<i32>(&key(user_id, None)), None);
+    }
+}
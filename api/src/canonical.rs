@@ -0,0 +1,176 @@
+//! Canonical forms of eval arguments, so that arguments which are equal but serialized differently,
+//! e.g. by different versions of a client, hash the same and so find the same evals.
+//!
+//! The canonical text of arguments is compact JSON in which:
+//!
+//! - the keys of objects are sorted, by code point,
+//! - numbers which are whole, and small enough to be exact as floats (within ±2^53), are written
+//!   as integers, so `1.0` and `1` are the same, as are `-0.0` and `0`,
+//! - other numbers are written as the shortest decimal which reads back as the same float,
+//! - the non-finite floats, which JSON can't represent but which clients write as `NaN`,
+//!   `Infinity` and `-Infinity`, are written as `{"$float":"nan"}`, `{"$float":"inf"}` and
+//!   `{"$float":"-inf"}`.
+//!
+//! The arguments' hash is the blake3 hash of their canonical text, in hex.
+use serde_json::{Map, Number, Value};
+
+/// The key of the object standing in for a non-finite float.
+pub const NON_FINITE_KEY: &str = "$float";
+
+/// The largest whole number which floats represent exactly, along with all those below it.
+const MAX_EXACT_FLOAT: f64 = 9_007_199_254_740_992.0;
+
+/// Arguments in their canonical form.
+#[derive(Serialize, Debug)]
+pub struct CanonicalArgs {
+    pub args: Value,
+    /// The canonical text of the arguments, which is what's hashed.
+    pub canonical: String,
+    pub args_hash: String,
+}
+
+/// Replaces the `NaN`, `Infinity` and `-Infinity` tokens which clients write for non-finite floats
+/// with the objects standing in for them, so that the text can be read as JSON. Tokens inside
+/// strings are left be.
+fn replace_non_finite(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else {
+            let token = [("NaN", "nan"), ("-Infinity", "-inf"), ("Infinity", "inf")]
+                .into_iter()
+                .find(|(token, _)| rest.starts_with(token));
+            if let Some((token, name)) = token {
+                out.push_str(&format!("{{\"{}\":\"{}\"}}", NON_FINITE_KEY, name));
+                rest = &rest[token.len()..];
+                continue;
+            }
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+/// The canonical form of a number.
+fn canonical_number(n: &Number) -> Value {
+    if n.is_i64() || n.is_u64() {
+        return Value::Number(n.clone());
+    }
+    match n.as_f64() {
+        Some(f) if f.fract() == 0.0 && f.abs() <= MAX_EXACT_FLOAT => Value::from(f as i64),
+        Some(f) => Number::from_f64(f).map_or(Value::Null, Value::Number),
+        None => Value::Number(n.clone()),
+    }
+}
+
+/// The canonical form of arguments.
+pub fn canonicalize(value: &Value) -> Value {
+    match value {
+        Value::Number(n) => canonical_number(n),
+        Value::Array(items) => Value::Array(items.iter().map(canonicalize).collect()),
+        Value::Object(fields) => {
+            let mut keys = fields.keys().collect::<Vec<_>>();
+            keys.sort();
+            let mut map = Map::new();
+            for key in keys {
+                map.insert(key.clone(), canonicalize(&fields[key]));
+            }
+            Value::Object(map)
+        }
+        v => v.clone(),
+    }
+}
+
+/// Writes the canonical text of arguments which are already in their canonical form. Keys are
+/// sorted as they're written, whatever order the map keeps them in.
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(item, out);
+            }
+            out.push(']');
+        }
+        Value::Object(fields) => {
+            let mut keys = fields.keys().collect::<Vec<_>>();
+            keys.sort();
+            out.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push(':');
+                write_canonical(&fields[key], out);
+            }
+            out.push('}');
+        }
+        v => out.push_str(&v.to_string()),
+    }
+}
+
+/// Reads arguments as a client wrote them, returning their canonical form and hash.
+pub fn canonical_args(text: &str) -> Result<CanonicalArgs, serde_json::Error> {
+    let value = serde_json::from_str::<Value>(&replace_non_finite(text))?;
+    let args = canonicalize(&value);
+    let mut canonical = String::new();
+    write_canonical(&args, &mut canonical);
+    let args_hash = blake3::hash(canonical.as_bytes()).to_hex().to_string();
+
+    Ok(CanonicalArgs {
+        args,
+        canonical,
+        args_hash,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_arguments_hash_the_same() {
+        let a = canonical_args(r#"{"b": [1.0, -0.0, 2.5], "a": {"y": 1, "x": "z"}}"#).unwrap();
+        let b = canonical_args(r#"{"a":{"x":"z","y":1.0},"b":[1,0,2.50]}"#).unwrap();
+        assert_eq!(a.canonical, r#"{"a":{"x":"z","y":1},"b":[1,0,2.5]}"#);
+        assert_eq!(a.canonical, b.canonical);
+        assert_eq!(a.args_hash, b.args_hash);
+    }
+
+    #[test]
+    fn reads_non_finite_floats() {
+        let args = canonical_args(r#"[NaN, Infinity, -Infinity, "NaN"]"#).unwrap();
+        assert_eq!(
+            args.canonical,
+            r#"[{"$float":"nan"},{"$float":"inf"},{"$float":"-inf"},"NaN"]"#
+        );
+    }
+
+    #[test]
+    fn keeps_large_and_fractional_numbers() {
+        let args = canonical_args("[1e300, 0.1, 18446744073709551615]").unwrap();
+        assert_eq!(args.canonical, "[1e300,0.1,18446744073709551615]");
+    }
+
+    #[test]
+    fn leaves_escaped_quotes_in_strings() {
+        let args = canonical_args(r#"{"k": "say \"NaN\""}"#).unwrap();
+        assert_eq!(args.canonical, r#"{"k":"say \"NaN\""}"#);
+    }
+}
@@ -0,0 +1,115 @@
+//! Capture of the raw bytes of failing requests, so that malformed frames reported by client users
+//! can be reproduced.
+//!
+//! A user opts an API key into capture for a while. Until then, BLOB uploads (which are sent as
+//! `WithBlob` frames) and MessagePack requests made with the key which fail are kept: the start of
+//! the body, up to `CAPTURE_MAX_BYTES`, along with what's needed to replay the request. The bytes
+//! are sealed with the server's capture key (AES-256-GCM) before they're stored, captures expire
+//! after `CAPTURE_RETENTION_HOURS`, and only admins can read them.
+use actix_web::http::Method;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::sync::Arc;
+
+/// The longest a key can be opted into capture for at once.
+pub const MAX_CAPTURE_HOURS: i64 = 7 * 24;
+
+/// The content type of MessagePack requests.
+pub const MSGPACK_CONTENT_TYPE: &str = "application/x-msgpack";
+
+/// Whether a request is of a kind which is captured: a BLOB upload or a MessagePack request.
+pub fn capturable(method: &Method, path: &str, content_type: &str) -> bool {
+    let blob_upload = method == Method::PUT && (path == "/blob" || path == "/blob/patch");
+    blob_upload || content_type == MSGPACK_CONTENT_TYPE
+}
+
+/// The key captured bytes are sealed with.
+#[derive(Clone)]
+pub struct CaptureKey(Arc<LessSafeKey>);
+
+impl std::fmt::Debug for CaptureKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CaptureKey(..)")
+    }
+}
+
+impl CaptureKey {
+    /// Parses a hex-encoded 256 bit key.
+    pub fn from_hex(s: &str) -> Option<Self> {
+        let bytes = hex::decode(s.trim()).ok()?;
+        let key = UnboundKey::new(&AES_256_GCM, &bytes).ok()?;
+        Some(Self(Arc::new(LessSafeKey::new(key))))
+    }
+
+    /// Seals `bytes`, returning a random nonce followed by the ciphertext.
+    pub fn seal(&self, bytes: &[u8]) -> Option<Vec<u8>> {
+        let mut nonce = [0; NONCE_LEN];
+        SystemRandom::new().fill(&mut nonce).ok()?;
+
+        let mut sealed = Vec::with_capacity(NONCE_LEN + bytes.len() + AES_256_GCM.tag_len());
+        sealed.extend_from_slice(&nonce);
+        let mut ciphertext = bytes.to_vec();
+        self.0
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut ciphertext,
+            )
+            .ok()?;
+        sealed.extend_from_slice(&ciphertext);
+        Some(sealed)
+    }
+
+    /// Opens bytes sealed by `seal`. Returns `None` if they were sealed with another key or have
+    /// been tampered with.
+    pub fn open(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+        let mut bytes = ciphertext.to_vec();
+        let len = self
+            .0
+            .open_in_place(nonce, Aad::empty(), &mut bytes)
+            .ok()?
+            .len();
+        bytes.truncate(len);
+        Some(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    #[test]
+    fn seals_and_opens() {
+        let key = CaptureKey::from_hex(KEY).unwrap();
+        let sealed = key.seal(b"\x00\x00\x00\x02{}blob").unwrap();
+        assert_eq!(key.open(&sealed).unwrap(), b"\x00\x00\x00\x02{}blob");
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(key.open(&tampered).is_none());
+
+        let other = CaptureKey::from_hex(&KEY.replace("00", "ff")).unwrap();
+        assert!(other.open(&sealed).is_none());
+        assert!(CaptureKey::from_hex("0011").is_none());
+    }
+
+    #[test]
+    fn captures_uploads_and_msgpack() {
+        assert!(capturable(
+            &Method::PUT,
+            "/blob",
+            "application/octet-stream"
+        ));
+        assert!(capturable(&Method::PUT, "/blob/patch", ""));
+        assert!(capturable(&Method::POST, "/eval", MSGPACK_CONTENT_TYPE));
+        assert!(!capturable(&Method::GET, "/blob/abc", ""));
+        assert!(!capturable(&Method::PUT, "/eval", "application/json"));
+    }
+}
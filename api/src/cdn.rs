@@ -0,0 +1,127 @@
+//! Signed CloudFront URLs, for serving BLOB downloads from a CDN in front of the server's bucket
+//! rather than through the server.
+//!
+//! When a CDN is configured, `GET /blob/{hash}` still authorizes the download against Postgres,
+//! then redirects the client to a URL for the BLOB's object on the CDN, signed with a canned policy
+//! which expires shortly after. Only BLOBs stored as single, uncompressed objects in the server's
+//! own bucket can be served this way; any others are still sent through the server.
+//!
+//! A canned policy's signature is the RSA-SHA1 signature of the policy, base64 encoded, with the
+//! characters CloudFront doesn't accept in query strings replaced:
+//!
+//! ```text
+//! {"Statement":[{"Resource":"<url>","Condition":{"DateLessThan":{"AWS:EpochTime":<expires>}}}]}
+//! ```
+use openssl::hash::MessageDigest;
+use openssl::pkey::{PKey, Private};
+use openssl::sign::Signer;
+use std::sync::Arc;
+
+/// Signs URLs for a CloudFront distribution.
+#[derive(Clone)]
+pub struct Cdn {
+    /// The distribution's URL, without a trailing `/`.
+    base_url: String,
+    /// The id of the CloudFront public key the private key pairs with.
+    key_pair_id: String,
+    key: Arc<PKey<Private>>,
+}
+
+impl Cdn {
+    /// Returns `None` if `pem` isn't an RSA private key in PEM format.
+    pub fn from_pem(base_url: &str, key_pair_id: &str, pem: &[u8]) -> Option<Self> {
+        let key = PKey::private_key_from_pem(pem).ok()?;
+        key.rsa().ok()?;
+        Some(Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            key_pair_id: key_pair_id.to_string(),
+            key: Arc::new(key),
+        })
+    }
+
+    /// A URL for the object at `key`, which can be fetched until `expires`, in seconds since the
+    /// epoch.
+    pub fn signed_url(&self, key: &str, expires: i64) -> String {
+        let url = format!("{}/{}", self.base_url, key);
+        let signature = self.sign(canned_policy(&url, expires).as_bytes());
+        format!(
+            "{}?Expires={}&Signature={}&Key-Pair-Id={}",
+            url, expires, signature, self.key_pair_id
+        )
+    }
+
+    fn sign(&self, policy: &[u8]) -> String {
+        let mut signer =
+            Signer::new(MessageDigest::sha1(), &self.key).expect("RSA keys can sign with SHA-1");
+        signer.update(policy).expect("signing can't fail");
+        let signature = signer.sign_to_vec().expect("signing can't fail");
+        url_safe(&base64::encode(&signature))
+    }
+}
+
+fn canned_policy(url: &str, expires: i64) -> String {
+    format!(
+        r#"{{"Statement":[{{"Resource":"{}","Condition":{{"DateLessThan":{{"AWS:EpochTime":{}}}}}}}]}}"#,
+        url, expires
+    )
+}
+
+/// Base64 with `+`, `=` and `/` replaced by `-`, `_` and `~`, as CloudFront expects.
+fn url_safe(base64: &str) -> String {
+    base64
+        .chars()
+        .map(|c| match c {
+            '+' => '-',
+            '=' => '_',
+            '/' => '~',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::rsa::Rsa;
+    use openssl::sign::Verifier;
+
+    #[test]
+    fn signs_canned_policies() {
+        let rsa = Rsa::generate(2048).unwrap();
+        let pem = rsa.private_key_to_pem().unwrap();
+        let cdn = Cdn::from_pem("https://d111.cloudfront.net/", "K2JCJMDEHXQW5F", &pem).unwrap();
+
+        let url = cdn.signed_url("blobs/abc", 1_700_000_000);
+        let (resource, query) = url.split_once('?').unwrap();
+        assert_eq!(resource, "https://d111.cloudfront.net/blobs/abc");
+        let params: Vec<(&str, &str)> = query
+            .split('&')
+            .map(|p| p.split_once('=').unwrap())
+            .collect();
+        assert_eq!(params[0], ("Expires", "1700000000"));
+        assert_eq!(params[2], ("Key-Pair-Id", "K2JCJMDEHXQW5F"));
+
+        let signature: String = params[1]
+            .1
+            .chars()
+            .map(|c| match c {
+                '-' => '+',
+                '_' => '=',
+                '~' => '/',
+                c => c,
+            })
+            .collect();
+        let signature = base64::decode(&signature).unwrap();
+        let public = PKey::public_key_from_pem(&rsa.public_key_to_pem().unwrap()).unwrap();
+        let mut verifier = Verifier::new(MessageDigest::sha1(), &public).unwrap();
+        let policy = canned_policy(resource, 1_700_000_000);
+        verifier.update(policy.as_bytes()).unwrap();
+        assert!(verifier.verify(&signature).unwrap());
+    }
+
+    #[test]
+    fn rejects_keys_which_arent_rsa() {
+        assert!(Cdn::from_pem("https://d111.cloudfront.net", "K", b"not a key").is_none());
+        assert_eq!(url_safe("a+b/c=="), "a-b~c__");
+    }
+}
@@ -0,0 +1,275 @@
+//! Fault injection, so that clients' retry logic and the server's own fallbacks (such as serving
+//! BLOBs from the manifest while the database is unavailable) can be exercised in staging.
+//!
+//! Faults can only be set when the server is built with the `chaos` feature. An admin sets faults
+//! on a layer, the database or BLOB storage, for a while; each operation on the layer is then
+//! delayed and/or failed with the given probabilities until the faults expire or are cleared.
+//!
+//! Database faults are injected as connections are taken from the pool or opened. A failure
+//! drops the connection, as a dead one would be, and the pool tries another; a request only fails,
+//! with [`sqlx::Error::PoolTimedOut`], once it's failed to get a connection for the pool's acquire
+//! timeout. So a failure probability of 1 looks like the database being down, while a lower one
+//! looks like a flaky network. BLOB storage faults fail the S3 operation outright.
+use crate::middlewares::auth::Auth;
+use crate::models::SqlDateTime;
+use crate::persisters::user::is_admin;
+use crate::state::State;
+use chrono::Utc;
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// The longest faults can be set for, so that a forgotten experiment doesn't outlive the day.
+pub const MAX_DURATION_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layer {
+    Db,
+    Blob,
+}
+
+/// Faults to inject into a layer, as set by an admin.
+#[derive(Deserialize, Debug)]
+pub struct FaultUpdate {
+    pub layer: Layer,
+    /// How long operations are delayed by, when they are.
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// The probability that an operation is delayed.
+    #[serde(default)]
+    pub latency_probability: f64,
+    /// The probability that an operation fails.
+    #[serde(default)]
+    pub failure_probability: f64,
+    /// How long the faults are injected for.
+    pub duration_secs: u64,
+}
+
+/// Faults being injected into a layer.
+#[derive(Serialize, Debug, Clone)]
+pub struct Fault {
+    pub layer: Layer,
+    pub latency_ms: u64,
+    pub latency_probability: f64,
+    pub failure_probability: f64,
+    /// When the faults stop being injected.
+    pub until: SqlDateTime,
+}
+
+/// An operation failed on purpose.
+#[derive(Debug)]
+pub struct Injected(pub Layer);
+
+impl std::fmt::Display for Injected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "fault injected into {:?} layer", self.0)
+    }
+}
+
+impl std::error::Error for Injected {}
+
+/// A connection a fault was injected into is treated as broken.
+impl From<Injected> for sqlx::Error {
+    fn from(e: Injected) -> Self {
+        sqlx::Error::Io(std::io::Error::new(std::io::ErrorKind::Other, e))
+    }
+}
+
+/// The faults being injected into each layer.
+#[derive(Clone, Default)]
+pub struct Chaos {
+    faults: Arc<Mutex<HashMap<Layer, Fault>>>,
+}
+
+impl Chaos {
+    /// The faults being injected, which haven't expired.
+    pub fn faults(&self) -> Vec<Fault> {
+        let now = Utc::now();
+        let mut faults = self.faults.lock().unwrap();
+        faults.retain(|_, f| f.until > now);
+        let mut res = faults.values().cloned().collect::<Vec<_>>();
+        res.sort_by_key(|f| f.layer as u8);
+        res
+    }
+
+    /// Whether to delay and whether to fail an operation on `layer` at `now`.
+    fn roll<R: Rng>(
+        &self,
+        layer: Layer,
+        now: SqlDateTime,
+        rng: &mut R,
+    ) -> (Option<Duration>, bool) {
+        let faults = self.faults.lock().unwrap();
+        match faults.get(&layer) {
+            Some(f) if f.until > now => {
+                let delay = (f.latency_ms > 0 && rng.gen_bool(f.latency_probability))
+                    .then(|| Duration::from_millis(f.latency_ms));
+                (delay, rng.gen_bool(f.failure_probability))
+            }
+            _ => (None, false),
+        }
+    }
+
+    /// Injects the faults set on `layer`, if any, into an operation on it: this may delay, and
+    /// then fail. Does nothing unless the server was built with the `chaos` feature.
+    pub async fn inject(&self, layer: Layer) -> Result<(), Injected> {
+        if !cfg!(feature = "chaos") {
+            return Ok(());
+        }
+
+        let (delay, fail) = self.roll(layer, Utc::now(), &mut rand::thread_rng());
+        if let Some(delay) = delay {
+            tokio::time::sleep(delay).await;
+        }
+        if fail {
+            return Err(Injected(layer));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ChaosError {
+    Unauthorized,
+    /// Only admins can inject faults.
+    Forbidden,
+    /// The server wasn't built with the `chaos` feature.
+    Disabled,
+    /// A probability outside `0..=1`, or a duration of zero or longer than
+    /// [`MAX_DURATION_SECS`].
+    InvalidFault,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for ChaosError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+async fn require_admin(auth: Option<&Auth>, state: &State) -> Result<(), ChaosError> {
+    if !cfg!(feature = "chaos") {
+        return Err(ChaosError::Disabled);
+    }
+
+    let auth = auth.ok_or(ChaosError::Unauthorized)?;
+
+    if !is_admin(auth, state).await? {
+        return Err(ChaosError::Forbidden);
+    }
+
+    Ok(())
+}
+
+/// The faults being injected, for an admin.
+pub async fn faults(auth: Option<&Auth>, state: &State) -> Result<Vec<Fault>, ChaosError> {
+    require_admin(auth, state).await?;
+
+    Ok(state.chaos.faults())
+}
+
+/// Starts injecting faults into a layer, replacing any already set on it.
+pub async fn set(
+    auth: Option<&Auth>,
+    state: &State,
+    update: FaultUpdate,
+) -> Result<Fault, ChaosError> {
+    require_admin(auth, state).await?;
+
+    let probability = 0.0..=1.0;
+    if !probability.contains(&update.latency_probability)
+        || !probability.contains(&update.failure_probability)
+        || update.duration_secs == 0
+        || update.duration_secs > MAX_DURATION_SECS
+    {
+        return Err(ChaosError::InvalidFault);
+    }
+
+    let fault = Fault {
+        layer: update.layer,
+        latency_ms: update.latency_ms,
+        latency_probability: update.latency_probability,
+        failure_probability: update.failure_probability,
+        until: Utc::now() + chrono::Duration::seconds(update.duration_secs as i64),
+    };
+    log::warn!("injecting faults: {:?}", fault);
+    state
+        .chaos
+        .faults
+        .lock()
+        .unwrap()
+        .insert(fault.layer, fault.clone());
+
+    Ok(fault)
+}
+
+/// Stops injecting faults into `layer`, or into every layer.
+pub async fn clear(
+    auth: Option<&Auth>,
+    state: &State,
+    layer: Option<Layer>,
+) -> Result<(), ChaosError> {
+    require_admin(auth, state).await?;
+
+    let mut faults = state.chaos.faults.lock().unwrap();
+    match layer {
+        Some(layer) => {
+            faults.remove(&layer);
+        }
+        None => faults.clear(),
+    }
+    log::warn!("cleared injected faults: {:?}", layer);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    fn chaos(latency_probability: f64, failure_probability: f64, until: SqlDateTime) -> Chaos {
+        let chaos = Chaos::default();
+        chaos.faults.lock().unwrap().insert(
+            Layer::Db,
+            Fault {
+                layer: Layer::Db,
+                latency_ms: 250,
+                latency_probability,
+                failure_probability,
+                until,
+            },
+        );
+        chaos
+    }
+
+    #[test]
+    fn rolls_set_faults() {
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        let now = Utc::now();
+        let later = now + chrono::Duration::minutes(5);
+
+        let always = chaos(1.0, 1.0, later);
+        assert_eq!(
+            always.roll(Layer::Db, now, &mut rng),
+            (Some(Duration::from_millis(250)), true)
+        );
+        // Other layers are left alone.
+        assert_eq!(always.roll(Layer::Blob, now, &mut rng), (None, false));
+
+        let never = chaos(0.0, 0.0, later);
+        assert_eq!(never.roll(Layer::Db, now, &mut rng), (None, false));
+    }
+
+    #[test]
+    fn ignores_expired_faults() {
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        let now = Utc::now();
+        let expired = chaos(1.0, 1.0, now - chrono::Duration::seconds(1));
+        assert_eq!(expired.roll(Layer::Db, now, &mut rng), (None, false));
+        assert!(expired.faults().is_empty());
+    }
+}
@@ -0,0 +1,186 @@
+//! Content-defined chunking of BLOBs, so that BLOBs which differ only slightly share most of their
+//! chunks, and shared chunks are stored once. See [`crate::persisters::chunked_store`].
+//!
+//! Chunks are cut where a gear hash of the bytes just before the cut matches a mask, so cut points
+//! depend only on nearby content: inserting or deleting bytes moves the cut points around the
+//! edit, and the chunks after it are cut as they were. Chunks are kept between a minimum and
+//! maximum length.
+
+/// How BLOBs are cut into chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkParams {
+    /// No chunk but the last is shorter than this.
+    pub min_len: usize,
+    /// Roughly how much longer than `min_len` chunks are on average. Rounded up to a power of two.
+    pub avg_len: usize,
+    /// No chunk is longer than this.
+    pub max_len: usize,
+}
+
+/// How the server chunks BLOBs.
+pub const DEFAULT_PARAMS: ChunkParams = ChunkParams {
+    min_len: 512 * 1024,
+    avg_len: 2 * 1024 * 1024,
+    max_len: 8 * 1024 * 1024,
+};
+
+/// Random values for each byte, which the gear hash is made of. Generated with splitmix64, so
+/// cut points never change between builds.
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+impl ChunkParams {
+    /// The bits of the hash which have to be zero at a cut point. They're its high bits, which
+    /// depend on the most bytes.
+    fn mask(&self) -> u64 {
+        let bits = self.avg_len.next_power_of_two().trailing_zeros();
+        u64::MAX << (64 - bits)
+    }
+
+    /// The length of the first chunk of `bytes`. Only the first `max_len` bytes are looked at, so
+    /// as long as at least that many are given, it doesn't matter what follows them. Otherwise
+    /// `bytes` is taken to be the rest of the BLOB.
+    pub fn next_cut(&self, bytes: &[u8]) -> usize {
+        if bytes.len() <= self.min_len {
+            return bytes.len();
+        }
+        let end = bytes.len().min(self.max_len);
+        let mask = self.mask();
+        let mut hash: u64 = 0;
+        for (i, &b) in bytes.iter().enumerate().take(end).skip(self.min_len) {
+            hash = (hash << 1).wrapping_add(GEAR[b as usize]);
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+
+        end
+    }
+
+    /// Cuts the whole of `bytes` into chunks.
+    pub fn chunks<'a>(&self, mut bytes: &'a [u8]) -> Vec<&'a [u8]> {
+        let mut chunks = vec![];
+        while !bytes.is_empty() {
+            let (chunk, rest) = bytes.split_at(self.next_cut(bytes));
+            chunks.push(chunk);
+            bytes = rest;
+        }
+        chunks
+    }
+}
+
+/// The part of a chunk which a range of its BLOB covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    /// Which of the BLOB's chunks it's in.
+    pub chunk: usize,
+    pub first: u64,
+    pub last: u64,
+}
+
+/// The parts of chunks of the given lengths, in order, which the bytes `first..=last` of their
+/// BLOB are in.
+pub fn spans(lengths: &[u64], first: u64, last: u64) -> Vec<Span> {
+    let mut spans = vec![];
+    let mut offset = 0;
+    for (chunk, &len) in lengths.iter().enumerate() {
+        let end = offset + len;
+        if len > 0 && first < end && last >= offset {
+            spans.push(Span {
+                chunk,
+                first: first.max(offset) - offset,
+                last: last.min(end - 1) - offset,
+            });
+        }
+        offset = end;
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAMS: ChunkParams = ChunkParams {
+        min_len: 64,
+        avg_len: 256,
+        max_len: 1024,
+    };
+
+    /// Bytes which look random, but are the same each time.
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chunks_cover_the_blob_within_bounds() {
+        let bytes = noise(50_000, 1);
+        let chunks = PARAMS.chunks(&bytes);
+        assert_eq!(chunks.concat(), bytes);
+        let (last, rest) = chunks.split_last().unwrap();
+        assert!(last.len() <= PARAMS.max_len);
+        assert!(rest
+            .iter()
+            .all(|c| c.len() >= PARAMS.min_len && c.len() <= PARAMS.max_len));
+        // Content is cut, rather than every chunk being as long as it can be.
+        assert!(rest.iter().any(|c| c.len() < PARAMS.max_len));
+
+        assert!(PARAMS.chunks(&[]).is_empty());
+        assert_eq!(PARAMS.chunks(&bytes[..10]), vec![&bytes[..10]]);
+    }
+
+    #[test]
+    fn edits_only_change_nearby_chunks() {
+        let bytes = noise(50_000, 2);
+        let mut edited = bytes[..20_000].to_vec();
+        edited.extend_from_slice(b"a few more bytes");
+        edited.extend_from_slice(&bytes[20_000..]);
+
+        let before = PARAMS.chunks(&bytes);
+        let after = PARAMS.chunks(&edited);
+        let changed = after.iter().filter(|c| !before.contains(c)).count();
+        assert!(
+            changed <= 3,
+            "{} of {} chunks changed",
+            changed,
+            after.len()
+        );
+    }
+
+    #[test]
+    fn spans_cover_the_range() {
+        let lengths = [10, 0, 5, 20];
+        let covered = |first, last| -> Vec<(usize, u64, u64)> {
+            spans(&lengths, first, last)
+                .into_iter()
+                .map(|s| (s.chunk, s.first, s.last))
+                .collect()
+        };
+        assert_eq!(covered(0, 34), vec![(0, 0, 9), (2, 0, 4), (3, 0, 19)]);
+        assert_eq!(covered(12, 17), vec![(2, 2, 4), (3, 0, 2)]);
+        assert_eq!(covered(0, u64::MAX), covered(0, 34));
+        assert_eq!(covered(40, 50), vec![]);
+    }
+}
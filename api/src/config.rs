@@ -1,19 +1,223 @@
+use crate::cache::ListingCache;
+use crate::capture::CaptureKey;
+use crate::cdn::Cdn;
+use crate::chaos::{Chaos, Layer};
+use crate::downloads::Downloads;
+use crate::embed::Embedder;
+use crate::fanout::Fanout;
+use crate::gc::GcStats;
+use crate::hashing::HashAlgorithm;
+use crate::integrity::IntegrityKey;
+use crate::keys::VerifiedKeys;
+use crate::leader::Leadership;
+use crate::load::Load;
+use crate::notify::Notifier;
+use crate::persisters::blob_store::BlobStore;
+use crate::persisters::cached_store::{BlobCacheStats, CachedStore};
+use crate::persisters::chunked_store::ChunkedStore;
+use crate::persisters::fs_store::FsStore;
 use crate::persisters::s3store::S3Store;
+use crate::slo::SloTracker;
 use crate::state::*;
+use crate::status::StatusBoard;
+use crate::throttle::Throttle;
 
 use std::env;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Config {
     pub database_url: String,
     pub port: u16,
+    /// The interface the operational endpoints (`/healthz`, `/metrics` and `/admin`) are served
+    /// on. This should be a private interface, as these are never served on the public port.
+    pub internal_host: String,
+    /// The port the operational endpoints are served on.
+    pub internal_port: u16,
+    /// Whether requests are logged as JSON lines on stdout, with their principal and byte counts,
+    /// rather than in the default text format.
+    pub access_log_json: bool,
     pub jwt_priv: String,
     pub gh_client_id: String,
     pub gh_client_secret: String,
     pub gh_user_agent: String,
     pub aws_s3_cred_file: String,
     pub aws_s3_blob_bucket: String,
+    /// Region of the BLOB bucket. It's read from the credentials file's profile when this is unset.
+    pub aws_s3_region: Option<String>,
+    /// URL of an S3-compatible service to use instead of AWS, e.g. `http://localhost:9000` for a
+    /// local MinIO. Buckets are addressed in the path, so no DNS is needed for them.
+    pub aws_s3_endpoint_url: Option<String>,
+    /// Prefix of the keys of new objects, e.g. `staging/`, so that several deployments can share
+    /// a bucket. Each BLOB's prefix is recorded when it's stored, so changing this doesn't lose
+    /// track of BLOBs stored before.
+    pub s3_key_prefix: String,
+    /// The zstd level new BLOBs are compressed at rest with, from 1 to 22, or `None` to store
+    /// them as they are. BLOBs which don't get smaller are stored as they are either way.
+    pub blob_zstd_level: Option<i32>,
+    /// The server-side encryption new objects are written with, as S3 names it: `AES256` for
+    /// SSE-S3 or `aws:kms` for SSE-KMS, or `None` to leave it to the bucket. Set from `S3_SSE`,
+    /// which is `s3` or `kms`.
+    pub s3_sse: Option<String>,
+    /// The KMS key SSE-KMS encrypts with, or `None` for the account's AWS managed key. Setting it
+    /// implies SSE-KMS.
+    pub s3_sse_kms_key_id: Option<String>,
+    /// The most attempts made at an S3 operation which fails transiently, including the first.
+    pub s3_retry_attempts: u32,
+    /// How long, in milliseconds, the first retry of an S3 operation waits at most. The most each
+    /// retry after it waits doubles.
+    pub s3_retry_base_ms: u64,
+    /// How long, in milliseconds, after an S3 operation's first attempt no more are started.
+    pub s3_retry_deadline_ms: u64,
+    /// Directory BLOBs are stored in instead of S3, e.g. for self-hosting or integration tests.
+    /// Each bucket is a directory in it, and S3 isn't used at all. BLOBs are stored there as they
+    /// are, whatever `BLOB_ZSTD_LEVEL` says.
+    pub blob_store_dir: Option<String>,
+    /// URL of the HTTP mail relay used to send notification emails. Email notifications are
+    /// dropped (with a warning) when this is unset.
+    pub mailer_url: Option<String>,
+    /// How often, in seconds, the background job evaluates alert rules.
+    pub alert_interval_secs: u64,
+    /// How often, in seconds, the background job archives and unarchives runs.
+    pub archive_interval_secs: u64,
+    /// How often, in seconds, the background job looks for anomalies in cache activity.
+    pub anomaly_interval_secs: u64,
+    /// How often, in seconds, the background job checks projects' cache-miss rates for storms.
+    pub miss_storm_interval_secs: u64,
+    /// How often, in seconds, the background job lists every stored object, to report drift
+    /// between what's stored and what's recorded.
+    pub inventory_interval_secs: u64,
+    /// Webhook which is sent an event for each anomaly found. Anomalies are only logged and
+    /// listed for admins when this is unset.
+    pub anomaly_webhook_url: Option<String>,
+    /// How often, in seconds, the background jobs look for new tabular and tensor artifacts to
+    /// compute stats and summaries of.
+    pub blob_stats_interval_secs: u64,
+    /// URL of the embedding provider used for eval similarity search. Similarity search is
+    /// disabled when this is unset.
+    pub embedding_url: Option<String>,
+    /// The model the embedding provider is asked to use.
+    pub embedding_model: String,
+    /// API key sent to the embedding provider as a bearer token, if it needs one.
+    pub embedding_api_key: Option<String>,
+    /// How often, in seconds, the background job embeds the arguments of new evals.
+    pub embedding_interval_secs: u64,
+    /// How long, in milliseconds, polling clients are asked to wait between polls when the server
+    /// is idle. The wait grows with load.
+    pub poll_base_ms: u64,
+    /// How many requests the server can serve at once before it counts as fully loaded.
+    pub poll_capacity: usize,
+    /// How many connections the database pool can open.
+    pub db_max_connections: u32,
+    /// How many requests can be in flight before batch requests are turned away, leaving the rest
+    /// of the server's capacity to interactive requests.
+    pub batch_capacity: usize,
+    /// How many BLOBs can be downloaded through the server at once, before further downloads are
+    /// turned away until one finishes. Downloads are unlimited when this is 0.
+    pub download_concurrency: usize,
+    /// How often, in seconds, the background job exports the manifests used to serve BLOB
+    /// downloads while the database is down.
+    pub manifest_interval_secs: u64,
+    /// How long, in seconds, cached listings are kept, in case a notification that they've
+    /// changed is missed.
+    pub listing_cache_ttl_secs: u64,
+    /// The most listings cached at once. Caching is disabled when this is 0.
+    pub listing_cache_max_entries: usize,
+    /// How many Postgres notifications a subscriber can fall behind before it misses some, and
+    /// has to resync, e.g. by clearing the listing cache.
+    pub fanout_buffer: usize,
+    /// How long, in seconds, clients may reuse a cached listing without revalidating it.
+    pub listing_max_age_secs: u64,
+    /// The default cap, in bytes per second, on BLOB uploads by each API key, for keys without a
+    /// cap of their own. Uploads are uncapped when this is unset.
+    pub bandwidth_upload_bytes_per_sec: Option<u64>,
+    /// The default cap, in bytes per second, on BLOB downloads by each API key, for keys without
+    /// a cap of their own. Downloads are uncapped when this is unset.
+    pub bandwidth_download_bytes_per_sec: Option<u64>,
+    /// How long, in seconds, a BLOB upload may go without any bytes arriving before it's aborted.
+    pub upload_stall_secs: u64,
+    /// The fraction of each route's requests which should succeed within the latency threshold.
+    pub slo_target: f64,
+    /// How long, in milliseconds, a request can take before it counts against the objective.
+    pub slo_latency_ms: u64,
+    /// The rolling window, in minutes, each route's error budget is measured over.
+    pub slo_window_mins: u64,
+    /// How many times faster than the objective allows a route must be spending its error budget,
+    /// over both the last 5 minutes and the last hour, to be alerted on.
+    pub slo_burn_rate_alert: f64,
+    /// Webhook which is sent an event for each route burning its error budget too fast. Such
+    /// routes are only logged when this is unset.
+    pub slo_webhook_url: Option<String>,
+    /// File holding the hex-encoded 256 bit key captured requests are sealed with. Keys can't be
+    /// opted into capture when this is unset.
+    pub capture_key_file: Option<String>,
+    /// The most bytes of each failing request's body which are captured.
+    pub capture_max_bytes: usize,
+    /// How long, in hours, captured requests are kept.
+    pub capture_retention_hours: u64,
+    /// How long, in days, the change feed of eval keys is kept. Clients which haven't followed it
+    /// for longer have to list their evals again.
+    pub change_retention_days: u64,
+    /// File holding the hex-encoded 256 bit Ed25519 seed integrity manifests are signed with.
+    /// Projects' integrity manifests aren't available when this is unset.
+    pub integrity_key_file: Option<String>,
+    /// File holding the hex-encoded 256 bit key API keys are sealed with, so that the S3 gateway
+    /// can check the signatures of requests made with them. Keys issued while this is unset can't
+    /// be used with the S3 gateway. See [`crate::keys`].
+    pub gateway_key_file: Option<String>,
+    /// The URL of a CloudFront distribution in front of the server's bucket. When it's set, along
+    /// with `CDN_KEY_PAIR_ID` and `CDN_PRIVATE_KEY_FILE`, BLOB downloads are redirected to it
+    /// rather than sent through the server. See [`crate::cdn`].
+    pub cdn_url: Option<String>,
+    /// The id of the CloudFront public key CDN URLs are signed for.
+    pub cdn_key_pair_id: Option<String>,
+    /// File holding the PEM-encoded RSA private key CDN URLs are signed with.
+    pub cdn_private_key_file: Option<String>,
+    /// How long, in seconds, a signed CDN URL can be fetched for.
+    pub cdn_url_ttl_secs: i64,
+    /// What a byte of hot storage counts for in billing and quotas. See [`crate::metering`].
+    pub storage_weight_hot: f64,
+    /// What a byte of infrequent access storage counts for.
+    pub storage_weight_infrequent_access: f64,
+    /// What a byte of archived storage counts for.
+    pub storage_weight_archive: f64,
+    /// What a byte of a BLOB deduplicated against another user's counts for.
+    pub storage_weight_deduplicated: f64,
+    /// The most billable bytes each user can store, as counted by [`crate::metering`]. Checked
+    /// before BLOBs are uploaded, and before uploads straight to S3 are allowed. Storage is
+    /// unlimited when this is unset.
+    pub storage_quota_bytes: Option<i64>,
+    /// The longest, in seconds, a key exchanged for a session at `/user/exchange` is valid for.
+    pub exchange_key_ttl_secs: u64,
+    /// Whether the BLOB garbage collector only reports what it would reclaim, rather than
+    /// deleting anything.
+    pub blob_gc_dry_run: bool,
+    /// How old, in days, an unreferenced BLOB has to be before it's garbage collected. This gives
+    /// clients time to put the eval referencing a BLOB they've uploaded.
+    pub blob_gc_grace_days: u64,
+    /// How long, in hours, a finished job, and the BLOB it produced, are kept.
+    pub job_ttl_hours: u64,
+    /// Webhook which is sent each dead letter as it's recorded, for support to follow up on. Dead
+    /// letters are only listed at `/admin/dead_letters` when this is unset.
+    pub dead_letter_webhook_url: Option<String>,
+    /// The hash algorithm BLOBs are being migrated to, whose digests are recorded alongside their
+    /// content hashes while it's set. See [`crate::hashing`].
+    pub content_hash_secondary: Option<HashAlgorithm>,
+    /// Whether large BLOBs are stored in content-defined chunks, so that chunks they have in
+    /// common are stored once. See [`crate::persisters::chunked_store`]. Once BLOBs have been
+    /// stored in chunks, this has to stay set for them to be read; raise `blob_chunk_min_bytes` to
+    /// stop chunking new BLOBs.
+    pub blob_chunking: bool,
+    /// The shortest BLOB which is stored in chunks, in bytes.
+    pub blob_chunk_min_bytes: i64,
+    /// Directory BLOBs retrieved from S3 are cached in, if they're cached on local disk. See
+    /// [`crate::persisters::cached_store`].
+    pub blob_cache_dir: Option<String>,
+    /// How many bytes of BLOBs are cached before the least recently used are evicted.
+    pub blob_cache_max_bytes: u64,
+    /// The longest BLOB which is cached, in bytes.
+    pub blob_cache_max_blob_bytes: u64,
 }
 
 #[derive(Debug, Deserialize, PartialEq, Serialize)]
@@ -77,6 +281,17 @@ impl Config {
             .expect("no port environment variable present")
             .parse::<u16>()
             .expect("invalid port");
+        let internal_host = env_vars
+            .remove("INTERNAL_HOST")
+            .unwrap_or_else(|| "127.0.0.1".to_string());
+        let internal_port = env_vars
+            .remove("INTERNAL_PORT")
+            .map(|s| s.parse::<u16>().expect("invalid INTERNAL_PORT"))
+            .unwrap_or(9090);
+        let access_log_json = env_vars
+            .remove("ACCESS_LOG_JSON")
+            .map(|s| s.parse::<bool>().expect("invalid ACCESS_LOG_JSON"))
+            .unwrap_or(false);
         let jwt_priv_file = env_vars
             .remove("JWT_PRIV_FILE")
             .expect("no JWT_PRIV_FILE environment variable present");
@@ -89,12 +304,245 @@ impl Config {
         let gh_user_agent = env_vars
             .remove("GH_USER_AGENT")
             .expect("no GH_USER_AGENT environment variable present");
+        // Without S3, there are no AWS credentials, and the bucket is just a directory.
+        let blob_store_dir = env_vars.remove("BLOB_STORE_DIR");
         let aws_s3_cred_file = env_vars
             .remove("AWS_S3_CRED_FILE")
+            .or_else(|| blob_store_dir.as_ref().map(|_| String::new()))
             .expect("no AWS_S3_CRED_FILE environment variable present");
         let aws_s3_blob_bucket = env_vars
             .remove("AWS_S3_BLOB_BUCKET")
+            .or_else(|| blob_store_dir.as_ref().map(|_| "blobs".to_string()))
             .expect("no AWS_S3_BLOB_BUCKET environemtn variable present");
+        let aws_s3_region = env_vars.remove("AWS_S3_REGION");
+        let aws_s3_endpoint_url = env_vars.remove("AWS_S3_ENDPOINT_URL");
+        let s3_key_prefix = env_vars.remove("S3_KEY_PREFIX").unwrap_or_default();
+        let blob_zstd_level = env_vars
+            .remove("BLOB_ZSTD_LEVEL")
+            .map(|s| s.parse::<i32>().expect("invalid BLOB_ZSTD_LEVEL"));
+        let s3_sse_kms_key_id = env_vars.remove("S3_SSE_KMS_KEY_ID");
+        let s3_sse = match env_vars.remove("S3_SSE").as_deref() {
+            Some("s3") if s3_sse_kms_key_id.is_some() => {
+                panic!("S3_SSE_KMS_KEY_ID is set, but S3_SSE is not kms")
+            }
+            Some("s3") => Some("AES256".to_string()),
+            Some("kms") => Some("aws:kms".to_string()),
+            Some(_) => panic!("invalid S3_SSE"),
+            None => s3_sse_kms_key_id.as_ref().map(|_| "aws:kms".to_string()),
+        };
+        let s3_retry_attempts = env_vars
+            .remove("S3_RETRY_ATTEMPTS")
+            .map(|s| s.parse::<u32>().expect("invalid S3_RETRY_ATTEMPTS"))
+            .unwrap_or(4);
+        let s3_retry_base_ms = env_vars
+            .remove("S3_RETRY_BASE_MS")
+            .map(|s| s.parse::<u64>().expect("invalid S3_RETRY_BASE_MS"))
+            .unwrap_or(100);
+        let s3_retry_deadline_ms = env_vars
+            .remove("S3_RETRY_DEADLINE_MS")
+            .map(|s| s.parse::<u64>().expect("invalid S3_RETRY_DEADLINE_MS"))
+            .unwrap_or(10_000);
+
+        let mailer_url = env_vars.remove("MAILER_URL");
+        let alert_interval_secs = env_vars
+            .remove("ALERT_INTERVAL_SECS")
+            .map(|s| s.parse::<u64>().expect("invalid ALERT_INTERVAL_SECS"))
+            .unwrap_or(60);
+        let archive_interval_secs = env_vars
+            .remove("ARCHIVE_INTERVAL_SECS")
+            .map(|s| s.parse::<u64>().expect("invalid ARCHIVE_INTERVAL_SECS"))
+            .unwrap_or(3600);
+        let anomaly_interval_secs = env_vars
+            .remove("ANOMALY_INTERVAL_SECS")
+            .map(|s| s.parse::<u64>().expect("invalid ANOMALY_INTERVAL_SECS"))
+            .unwrap_or(300);
+        let miss_storm_interval_secs = env_vars
+            .remove("MISS_STORM_INTERVAL_SECS")
+            .map(|s| s.parse::<u64>().expect("invalid MISS_STORM_INTERVAL_SECS"))
+            .unwrap_or(300);
+        let inventory_interval_secs = env_vars
+            .remove("INVENTORY_INTERVAL_SECS")
+            .map(|s| s.parse::<u64>().expect("invalid INVENTORY_INTERVAL_SECS"))
+            .unwrap_or(86400);
+        let anomaly_webhook_url = env_vars.remove("ANOMALY_WEBHOOK_URL");
+        let blob_stats_interval_secs = env_vars
+            .remove("BLOB_STATS_INTERVAL_SECS")
+            .map(|s| s.parse::<u64>().expect("invalid BLOB_STATS_INTERVAL_SECS"))
+            .unwrap_or(60);
+        let embedding_url = env_vars.remove("EMBEDDING_URL");
+        let embedding_model = env_vars
+            .remove("EMBEDDING_MODEL")
+            .unwrap_or_else(|| "text-embedding-ada-002".to_string());
+        let embedding_api_key = env_vars.remove("EMBEDDING_API_KEY_FILE").map(|f| {
+            let mut key = std::fs::read_to_string(f)
+                .expect("could not read embedding api key file; does it exist?");
+            trim_newline(&mut key);
+            key
+        });
+        let embedding_interval_secs = env_vars
+            .remove("EMBEDDING_INTERVAL_SECS")
+            .map(|s| s.parse::<u64>().expect("invalid EMBEDDING_INTERVAL_SECS"))
+            .unwrap_or(60);
+        let poll_base_ms = env_vars
+            .remove("POLL_BASE_MS")
+            .map(|s| s.parse::<u64>().expect("invalid POLL_BASE_MS"))
+            .unwrap_or(1000);
+        let poll_capacity = env_vars
+            .remove("POLL_CAPACITY")
+            .map(|s| s.parse::<usize>().expect("invalid POLL_CAPACITY"))
+            .unwrap_or(64);
+        let db_max_connections = env_vars
+            .remove("DB_MAX_CONNECTIONS")
+            .map(|s| s.parse::<u32>().expect("invalid DB_MAX_CONNECTIONS"))
+            .unwrap_or(10);
+        let batch_capacity = env_vars
+            .remove("BATCH_CAPACITY")
+            .map(|s| s.parse::<usize>().expect("invalid BATCH_CAPACITY"))
+            .unwrap_or(48);
+        let download_concurrency = env_vars
+            .remove("DOWNLOAD_CONCURRENCY")
+            .map(|s| s.parse::<usize>().expect("invalid DOWNLOAD_CONCURRENCY"))
+            .unwrap_or(256);
+        let manifest_interval_secs = env_vars
+            .remove("MANIFEST_INTERVAL_SECS")
+            .map(|s| s.parse::<u64>().expect("invalid MANIFEST_INTERVAL_SECS"))
+            .unwrap_or(900);
+        let listing_cache_ttl_secs = env_vars
+            .remove("LISTING_CACHE_TTL_SECS")
+            .map(|s| s.parse::<u64>().expect("invalid LISTING_CACHE_TTL_SECS"))
+            .unwrap_or(300);
+        let listing_cache_max_entries = env_vars
+            .remove("LISTING_CACHE_MAX_ENTRIES")
+            .map(|s| {
+                s.parse::<usize>()
+                    .expect("invalid LISTING_CACHE_MAX_ENTRIES")
+            })
+            .unwrap_or(10_000);
+        let fanout_buffer = env_vars
+            .remove("FANOUT_BUFFER")
+            .map(|s| s.parse::<usize>().expect("invalid FANOUT_BUFFER"))
+            .unwrap_or(1024);
+        let listing_max_age_secs = env_vars
+            .remove("LISTING_MAX_AGE_SECS")
+            .map(|s| s.parse::<u64>().expect("invalid LISTING_MAX_AGE_SECS"))
+            .unwrap_or(5);
+        let slo_target = env_vars
+            .remove("SLO_TARGET")
+            .map(|s| s.parse::<f64>().expect("invalid SLO_TARGET"))
+            .unwrap_or(0.999);
+        let slo_latency_ms = env_vars
+            .remove("SLO_LATENCY_MS")
+            .map(|s| s.parse::<u64>().expect("invalid SLO_LATENCY_MS"))
+            .unwrap_or(1000);
+        let slo_window_mins = env_vars
+            .remove("SLO_WINDOW_MINS")
+            .map(|s| s.parse::<u64>().expect("invalid SLO_WINDOW_MINS"))
+            .unwrap_or(24 * 60);
+        let slo_burn_rate_alert = env_vars
+            .remove("SLO_BURN_RATE_ALERT")
+            .map(|s| s.parse::<f64>().expect("invalid SLO_BURN_RATE_ALERT"))
+            .unwrap_or(14.4);
+        let slo_webhook_url = env_vars.remove("SLO_WEBHOOK_URL");
+        let capture_key_file = env_vars.remove("CAPTURE_KEY_FILE");
+        let capture_max_bytes = env_vars
+            .remove("CAPTURE_MAX_BYTES")
+            .map(|s| s.parse::<usize>().expect("invalid CAPTURE_MAX_BYTES"))
+            .unwrap_or(1024 * 1024);
+        let capture_retention_hours = env_vars
+            .remove("CAPTURE_RETENTION_HOURS")
+            .map(|s| s.parse::<u64>().expect("invalid CAPTURE_RETENTION_HOURS"))
+            .unwrap_or(72);
+        let change_retention_days = env_vars
+            .remove("CHANGE_RETENTION_DAYS")
+            .map(|s| s.parse::<u64>().expect("invalid CHANGE_RETENTION_DAYS"))
+            .unwrap_or(30);
+        let integrity_key_file = env_vars.remove("INTEGRITY_KEY_FILE");
+        let gateway_key_file = env_vars.remove("GATEWAY_KEY_FILE");
+        let cdn_url = env_vars.remove("CDN_URL");
+        let cdn_key_pair_id = env_vars.remove("CDN_KEY_PAIR_ID");
+        let cdn_private_key_file = env_vars.remove("CDN_PRIVATE_KEY_FILE");
+        let cdn_url_ttl_secs = env_vars
+            .remove("CDN_URL_TTL_SECS")
+            .map(|s| s.parse::<i64>().expect("invalid CDN_URL_TTL_SECS"))
+            .unwrap_or(300);
+        let storage_weight_hot = env_vars
+            .remove("STORAGE_WEIGHT_HOT")
+            .map(|s| s.parse::<f64>().expect("invalid STORAGE_WEIGHT_HOT"))
+            .unwrap_or(1.0);
+        let storage_weight_infrequent_access = env_vars
+            .remove("STORAGE_WEIGHT_INFREQUENT_ACCESS")
+            .map(|s| {
+                s.parse::<f64>()
+                    .expect("invalid STORAGE_WEIGHT_INFREQUENT_ACCESS")
+            })
+            .unwrap_or(0.5);
+        let storage_weight_archive = env_vars
+            .remove("STORAGE_WEIGHT_ARCHIVE")
+            .map(|s| s.parse::<f64>().expect("invalid STORAGE_WEIGHT_ARCHIVE"))
+            .unwrap_or(0.2);
+        let storage_weight_deduplicated = env_vars
+            .remove("STORAGE_WEIGHT_DEDUPLICATED")
+            .map(|s| {
+                s.parse::<f64>()
+                    .expect("invalid STORAGE_WEIGHT_DEDUPLICATED")
+            })
+            .unwrap_or(0.0);
+        let storage_quota_bytes = env_vars
+            .remove("STORAGE_QUOTA_BYTES")
+            .map(|s| s.parse::<i64>().expect("invalid STORAGE_QUOTA_BYTES"));
+        let exchange_key_ttl_secs = env_vars
+            .remove("EXCHANGE_KEY_TTL_SECS")
+            .map(|s| s.parse::<u64>().expect("invalid EXCHANGE_KEY_TTL_SECS"))
+            .unwrap_or(7 * 24 * 60 * 60);
+        let blob_gc_dry_run = env_vars
+            .remove("BLOB_GC_DRY_RUN")
+            .map(|s| s.parse::<bool>().expect("invalid BLOB_GC_DRY_RUN"))
+            .unwrap_or(true);
+        let blob_gc_grace_days = env_vars
+            .remove("BLOB_GC_GRACE_DAYS")
+            .map(|s| s.parse::<u64>().expect("invalid BLOB_GC_GRACE_DAYS"))
+            .unwrap_or(7);
+        let job_ttl_hours = env_vars
+            .remove("JOB_TTL_HOURS")
+            .map(|s| s.parse::<u64>().expect("invalid JOB_TTL_HOURS"))
+            .unwrap_or(168);
+        let dead_letter_webhook_url = env_vars.remove("DEAD_LETTER_WEBHOOK_URL");
+        let content_hash_secondary = env_vars.remove("CONTENT_HASH_SECONDARY").map(|s| {
+            s.parse::<HashAlgorithm>()
+                .expect("invalid CONTENT_HASH_SECONDARY; expected blake3 or sha256")
+        });
+        let blob_chunking = env_vars
+            .remove("BLOB_CHUNKING")
+            .map(|s| s.parse::<bool>().expect("invalid BLOB_CHUNKING"))
+            .unwrap_or(false);
+        let blob_chunk_min_bytes = env_vars
+            .remove("BLOB_CHUNK_MIN_BYTES")
+            .map(|s| s.parse::<i64>().expect("invalid BLOB_CHUNK_MIN_BYTES"))
+            .unwrap_or(16 * 1024 * 1024);
+        let blob_cache_dir = env_vars.remove("BLOB_CACHE_DIR");
+        let blob_cache_max_bytes = env_vars
+            .remove("BLOB_CACHE_MAX_BYTES")
+            .map(|s| s.parse::<u64>().expect("invalid BLOB_CACHE_MAX_BYTES"))
+            .unwrap_or(10 * 1024 * 1024 * 1024);
+        let blob_cache_max_blob_bytes = env_vars
+            .remove("BLOB_CACHE_MAX_BLOB_BYTES")
+            .map(|s| s.parse::<u64>().expect("invalid BLOB_CACHE_MAX_BLOB_BYTES"))
+            .unwrap_or(256 * 1024 * 1024);
+        let bandwidth_upload_bytes_per_sec =
+            env_vars.remove("BANDWIDTH_UPLOAD_BYTES_PER_SEC").map(|s| {
+                s.parse::<u64>()
+                    .expect("invalid BANDWIDTH_UPLOAD_BYTES_PER_SEC")
+            });
+        let bandwidth_download_bytes_per_sec = env_vars
+            .remove("BANDWIDTH_DOWNLOAD_BYTES_PER_SEC")
+            .map(|s| {
+                s.parse::<u64>()
+                    .expect("invalid BANDWIDTH_DOWNLOAD_BYTES_PER_SEC")
+            });
+        let upload_stall_secs = env_vars
+            .remove("UPLOAD_STALL_SECS")
+            .map(|s| s.parse::<u64>().expect("invalid UPLOAD_STALL_SECS"))
+            .unwrap_or(60);
 
         let mut jwt_priv = std::fs::read_to_string(jwt_priv_file)
             .expect("could not read jwt priv file; does it exist?");
@@ -107,17 +555,87 @@ impl Config {
         Config {
             database_url,
             port,
+            internal_host,
+            internal_port,
+            access_log_json,
             jwt_priv,
             gh_client_id,
             gh_client_secret,
             gh_user_agent,
             aws_s3_cred_file,
             aws_s3_blob_bucket,
+            aws_s3_region,
+            aws_s3_endpoint_url,
+            s3_key_prefix,
+            blob_zstd_level,
+            s3_sse,
+            s3_sse_kms_key_id,
+            s3_retry_attempts,
+            s3_retry_base_ms,
+            s3_retry_deadline_ms,
+            blob_store_dir,
+            mailer_url,
+            alert_interval_secs,
+            archive_interval_secs,
+            anomaly_interval_secs,
+            miss_storm_interval_secs,
+            inventory_interval_secs,
+            anomaly_webhook_url,
+            blob_stats_interval_secs,
+            embedding_url,
+            embedding_model,
+            embedding_api_key,
+            embedding_interval_secs,
+            poll_base_ms,
+            poll_capacity,
+            db_max_connections,
+            batch_capacity,
+            download_concurrency,
+            manifest_interval_secs,
+            listing_cache_ttl_secs,
+            listing_cache_max_entries,
+            fanout_buffer,
+            listing_max_age_secs,
+            bandwidth_upload_bytes_per_sec,
+            bandwidth_download_bytes_per_sec,
+            upload_stall_secs,
+            slo_target,
+            slo_latency_ms,
+            slo_window_mins,
+            slo_burn_rate_alert,
+            slo_webhook_url,
+            capture_key_file,
+            capture_max_bytes,
+            capture_retention_hours,
+            change_retention_days,
+            integrity_key_file,
+            gateway_key_file,
+            cdn_url,
+            cdn_key_pair_id,
+            cdn_private_key_file,
+            cdn_url_ttl_secs,
+            storage_weight_hot,
+            storage_weight_infrequent_access,
+            storage_weight_archive,
+            storage_weight_deduplicated,
+            storage_quota_bytes,
+            exchange_key_ttl_secs,
+            blob_gc_dry_run,
+            blob_gc_grace_days,
+            job_ttl_hours,
+            dead_letter_webhook_url,
+            content_hash_secondary,
+            blob_chunking,
+            blob_chunk_min_bytes,
+            blob_cache_dir,
+            blob_cache_max_bytes,
+            blob_cache_max_blob_bytes,
         }
     }
     pub async fn into_state(self) -> AppStateRaw {
         info!("config: {:?}", self);
-        let mut pool_options = PoolOptions::new();
+        let mut pool_options = PoolOptions::new().max_connections(self.db_max_connections);
+        let mut set_time_zone: Option<&'static str> = None;
 
         if let Some(opstr) = url::Url::parse(&self.database_url)
             .expect("Invalid SqlDB URL")
@@ -144,27 +662,145 @@ impl Config {
                     // cannot move out of `set_str`, a captured variable in an `Fn` closure
                     let set_str = unsafe { std::mem::transmute::<_, &'static str>(set.as_str()) };
                     std::mem::forget(set);
-                    pool_options = pool_options.after_connect(move |conn, _meta| {
-                        Box::pin(async move {
-                            use crate::sqlx::Executor;
-                            conn.execute(set_str).await.map(|_| ())
-                        })
-                    })
+                    set_time_zone = Some(set_str);
                 }
             }
         }
 
+        let chaos = Chaos::default();
+        if set_time_zone.is_some() || cfg!(feature = "chaos") {
+            let chaos = chaos.clone();
+            pool_options = pool_options.after_connect(move |conn, _meta| {
+                let chaos = chaos.clone();
+                Box::pin(async move {
+                    chaos.inject(Layer::Db).await?;
+                    if let Some(set_str) = set_time_zone {
+                        use crate::sqlx::Executor;
+                        conn.execute(set_str).await?;
+                    }
+                    Ok(())
+                })
+            })
+        }
+        if cfg!(feature = "chaos") {
+            let chaos = chaos.clone();
+            pool_options = pool_options.before_acquire(move |_conn, _meta| {
+                let chaos = chaos.clone();
+                Box::pin(async move {
+                    chaos.inject(Layer::Db).await?;
+                    Ok(true)
+                })
+            })
+        }
+
         let db_conn = pool_options
             .connect(&self.database_url)
             .await
             .expect("sql open");
 
-        let s3_store = S3Store::new().await;
+        let blob_cache = BlobCacheStats::default();
+        let blob_store: Arc<dyn BlobStore> = match &self.blob_store_dir {
+            Some(dir) => Arc::new(FsStore::new(dir, chaos.clone())),
+            None => Arc::new(S3Store::new(chaos.clone()).await),
+        };
+        // BLOBs stored on local disk already are, so only those in S3 are cached.
+        let blob_store: Arc<dyn BlobStore> = match (&self.blob_cache_dir, &self.blob_store_dir) {
+            (Some(dir), None) => Arc::new(
+                CachedStore::new(
+                    blob_store,
+                    dir,
+                    self.blob_cache_max_bytes,
+                    self.blob_cache_max_blob_bytes,
+                    blob_cache.clone(),
+                )
+                .expect("could not open BLOB cache directory"),
+            ),
+            _ => blob_store,
+        };
+        let blob_store: Arc<dyn BlobStore> = match self.blob_chunking {
+            true => Arc::new(ChunkedStore::new(
+                blob_store,
+                db_conn.clone(),
+                self.blob_chunk_min_bytes,
+            )),
+            false => blob_store,
+        };
+        let notifier = Notifier::new(self.mailer_url.clone());
+        let embedder = Embedder::new(
+            self.embedding_url.clone(),
+            self.embedding_model.clone(),
+            self.embedding_api_key.clone(),
+        );
+        let listing_cache = ListingCache::new(
+            Duration::from_secs(self.listing_cache_ttl_secs),
+            self.listing_cache_max_entries,
+        );
+
+        let capture_key = self.capture_key_file.as_ref().map(|f| {
+            let key = std::fs::read_to_string(f)
+                .expect("could not read capture key file; does it exist?");
+            CaptureKey::from_hex(&key).expect("invalid capture key; expected 64 hex characters")
+        });
+
+        let integrity_key = self.integrity_key_file.as_ref().map(|f| {
+            let key = std::fs::read_to_string(f)
+                .expect("could not read integrity key file; does it exist?");
+            IntegrityKey::from_hex(&key).expect("invalid integrity key; expected 64 hex characters")
+        });
+
+        let gateway_key = self.gateway_key_file.as_ref().map(|f| {
+            let key = std::fs::read_to_string(f)
+                .expect("could not read gateway key file; does it exist?");
+            CaptureKey::from_hex(&key).expect("invalid gateway key; expected 64 hex characters")
+        });
+
+        let cdn = match (
+            &self.cdn_url,
+            &self.cdn_key_pair_id,
+            &self.cdn_private_key_file,
+        ) {
+            (Some(url), Some(key_pair_id), Some(f)) => {
+                let pem =
+                    std::fs::read(f).expect("could not read CDN private key file; does it exist?");
+                Some(
+                    Cdn::from_pem(url, key_pair_id, &pem)
+                        .expect("invalid CDN private key; expected a PEM-encoded RSA key"),
+                )
+            }
+            (None, _, _) => None,
+            _ => panic!("CDN_URL requires CDN_KEY_PAIR_ID and CDN_PRIVATE_KEY_FILE"),
+        };
+
+        let slo = SloTracker::new(
+            self.slo_window_mins,
+            Duration::from_millis(self.slo_latency_ms),
+        );
+
+        let downloads = Downloads::new(self.download_concurrency);
+        let fanout = Fanout::new(self.fanout_buffer);
 
         Arc::new(State {
             config: self,
             db_conn,
-            s3_store,
+            blob_store,
+            notifier,
+            embedder,
+            load: Load::default(),
+            downloads,
+            listing_cache,
+            fanout,
+            throttle: Throttle::default(),
+            slo,
+            chaos,
+            capture_key,
+            integrity_key,
+            gateway_key,
+            cdn,
+            verified_keys: VerifiedKeys::default(),
+            gc: GcStats::default(),
+            blob_cache,
+            status: StatusBoard::default(),
+            leadership: Leadership::default(),
         })
     }
     // generate and show config string
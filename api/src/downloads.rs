@@ -0,0 +1,110 @@
+//! A limit on how many BLOBs are downloaded through the server at once, so that a burst of large
+//! downloads can't exhaust its memory, or its connections to BLOB storage.
+//!
+//! A download holds a permit from before it's fetched from storage until its body has been sent,
+//! or the client has gone away. Downloads past the limit are turned away with 503, and told when
+//! to retry. Bodies are passed on in chunks of at most [`MAX_CHUNK_LEN`], and a chunk is only read
+//! from storage once the last has been written, so each download buffers little ahead of its
+//! client however large the BLOB.
+use bytes::Bytes;
+use futures::{stream, Stream, StreamExt};
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// The most bytes of a download passed on at once.
+pub const MAX_CHUNK_LEN: usize = 256 * 1024;
+
+#[derive(Clone)]
+pub struct Downloads {
+    /// `None` if downloads aren't limited.
+    permits: Option<Arc<Semaphore>>,
+}
+
+/// Lets a download proceed until dropped.
+pub struct DownloadPermit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Downloads {
+    /// Allows up to `concurrency` downloads at once, or any number if it's 0.
+    pub fn new(concurrency: usize) -> Self {
+        Self {
+            permits: (concurrency > 0).then(|| Arc::new(Semaphore::new(concurrency))),
+        }
+    }
+
+    /// A permit to start a download, or `None` if the server is already serving as many as it can.
+    pub fn start(&self) -> Option<DownloadPermit> {
+        match &self.permits {
+            Some(permits) => permits
+                .clone()
+                .try_acquire_owned()
+                .ok()
+                .map(|p| DownloadPermit { _permit: Some(p) }),
+            None => Some(DownloadPermit { _permit: None }),
+        }
+    }
+}
+
+/// `bytes`, in pieces of at most [`MAX_CHUNK_LEN`]. The pieces share `bytes`' buffer.
+fn split(bytes: Bytes) -> Vec<Bytes> {
+    (0..bytes.len())
+        .step_by(MAX_CHUNK_LEN)
+        .map(|start| bytes.slice(start..(start + MAX_CHUNK_LEN).min(bytes.len())))
+        .collect()
+}
+
+/// Passes on the chunks of `stream`, split to at most [`MAX_CHUNK_LEN`], holding `permit` until
+/// the stream is dropped.
+pub fn limited<S, E>(stream: S, permit: DownloadPermit) -> impl Stream<Item = Result<Bytes, E>>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+{
+    stream.flat_map(move |item| {
+        let _permit = &permit;
+        let items = match item {
+            Ok(bytes) => split(bytes).into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(e)],
+        };
+        stream::iter(items)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turns_downloads_away_past_the_limit() {
+        let downloads = Downloads::new(2);
+        let a = downloads.start();
+        let b = downloads.start();
+        assert!(a.is_some() && b.is_some());
+        assert!(downloads.start().is_none());
+        drop(a);
+        assert!(downloads.start().is_some());
+
+        let unlimited = Downloads::new(0);
+        let permits: Vec<_> = (0..1000).map(|_| unlimited.start()).collect();
+        assert!(permits.iter().all(Option::is_some));
+    }
+
+    #[test]
+    fn splits_large_chunks() {
+        let bytes = Bytes::from(vec![7; 2 * MAX_CHUNK_LEN + 10]);
+        let lengths: Vec<usize> = split(bytes).iter().map(Bytes::len).collect();
+        assert_eq!(lengths, vec![MAX_CHUNK_LEN, MAX_CHUNK_LEN, 10]);
+        assert!(split(Bytes::new()).is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn holds_its_permit_until_dropped() {
+        let downloads = Downloads::new(1);
+        let chunks = vec![Ok::<_, ()>(Bytes::from(vec![0; MAX_CHUNK_LEN + 1]))];
+        let body = limited(stream::iter(chunks), downloads.start().unwrap());
+        assert!(downloads.start().is_none());
+        let lengths: Vec<usize> = body.map(|c| c.unwrap().len()).collect().await;
+        assert_eq!(lengths, vec![MAX_CHUNK_LEN, 1]);
+        assert!(downloads.start().is_some());
+    }
+}
@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+use sqlx::types::JsonValue;
+
+/// The longest input, in characters, sent to the embedding provider. Longer arguments are
+/// truncated, so only their start contributes to similarity.
+pub const MAX_INPUT_LEN: usize = 8000;
+
+/// Embeds eval arguments, for finding evals whose arguments are close to, but not the same as,
+/// those of a lookup which missed.
+///
+/// Embeddings come from an HTTP provider (configured by `EMBEDDING_URL`) speaking the OpenAI
+/// embeddings API, which most providers and self-hosted embedding servers support. Similarity
+/// search is disabled when this is unset.
+#[derive(Clone)]
+pub struct Embedder {
+    client: reqwest::Client,
+    url: Option<String>,
+    model: String,
+    api_key: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum EmbedError {
+    /// No embedding provider has been configured.
+    Disabled,
+    /// The provider's response held no embedding.
+    Empty,
+    Http(reqwest::Error),
+}
+
+impl From<reqwest::Error> for EmbedError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http(e)
+    }
+}
+
+/// The request body accepted by the embedding provider.
+#[derive(Serialize, Debug)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a str,
+}
+
+#[derive(Deserialize, Debug)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+}
+
+/// The text embedded for `args`: its JSON, with object keys in sorted order so that equal
+/// arguments always embed the same way.
+pub fn args_text(args: &JsonValue) -> String {
+    let text = args.to_string();
+    match text.char_indices().nth(MAX_INPUT_LEN) {
+        Some((i, _)) => text[..i].to_string(),
+        None => text,
+    }
+}
+
+/// Formats an embedding as a pgvector literal, e.g. `[0.1,0.2]`, to be cast with `::vector`.
+pub fn to_vector_literal(embedding: &[f32]) -> String {
+    let values = embedding
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("[{}]", values)
+}
+
+impl Embedder {
+    pub fn new(url: Option<String>, model: String, api_key: Option<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url,
+            model,
+            api_key,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.url.is_some()
+    }
+
+    /// The model embeddings are made with. Embeddings from different models aren't comparable.
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Embeds the arguments of an eval.
+    pub async fn embed_args(&self, args: &JsonValue) -> Result<Vec<f32>, EmbedError> {
+        let url = self.url.as_ref().ok_or(EmbedError::Disabled)?;
+
+        let mut req = self.client.post(url).json(&EmbeddingRequest {
+            model: &self.model,
+            input: &args_text(args),
+        });
+        if let Some(key) = &self.api_key {
+            req = req.bearer_auth(key);
+        }
+
+        let res = req
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<EmbeddingResponse>()
+            .await?;
+
+        res.data
+            .into_iter()
+            .next()
+            .map(|d| d.embedding)
+            .filter(|e| !e.is_empty())
+            .ok_or(EmbedError::Empty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args_text_is_canonical() {
+        let a = serde_json::json!({ "lr": 0.1, "epochs": 10 });
+        let b = serde_json::json!({ "epochs": 10, "lr": 0.1 });
+        assert_eq!(args_text(&a), args_text(&b));
+
+        let long = JsonValue::String("é".repeat(MAX_INPUT_LEN));
+        assert_eq!(args_text(&long).chars().count(), MAX_INPUT_LEN);
+    }
+
+    #[test]
+    fn formats_vector_literal() {
+        assert_eq!(to_vector_literal(&[0.5, -1.0, 2.25]), "[0.5,-1,2.25]");
+    }
+}
@@ -0,0 +1,236 @@
+//! The response envelope of listing endpoints.
+//!
+//! Listings are returned as a bare JSON array, as they always have been, unless the client asks
+//! for the envelope by accepting [`ENVELOPE_MEDIA_TYPE`]. The envelope wraps the array as `data`,
+//! along with any `warnings` about soft issues with the request and, for listings with a limit, a
+//! `pagination` block, and for listings which clients poll, a `poll_after_ms` hint. Clients which
+//! don't ask for the envelope still get the warnings, as `Warning` headers. The hint is also always
+//! sent as a [`POLL_AFTER_HEADER`] header.
+//!
+//! Listings which may be cached by clients are sent with `Cache-Control` and a weak `ETag`, so
+//! that clients can revalidate them with `If-None-Match` and get a `304 Not Modified` when they're
+//! unchanged.
+use actix_web::{
+    body::BoxBody,
+    error::JsonPayloadError,
+    http::{
+        header::{
+            ContentType, HeaderName, ACCEPT, CACHE_CONTROL, ETAG, IF_NONE_MATCH, VARY, WARNING,
+        },
+        StatusCode,
+    },
+    HttpRequest, HttpResponse, Responder,
+};
+use serde::Serialize;
+
+/// The media type a client accepts to have listings returned in the envelope.
+pub const ENVELOPE_MEDIA_TYPE: &str = "application/vnd.hitsave.envelope+json";
+
+/// The header carrying the poll hint.
+pub const POLL_AFTER_HEADER: HeaderName = HeaderName::from_static("x-hitsave-poll-after-ms");
+
+/// A soft issue with a request, which didn't stop it from being served.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// A stable identifier for the kind of issue, e.g. `limit_reduced` or `deprecated`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Pagination {
+    /// The most items which were returned.
+    pub limit: i64,
+    /// Whether there may be more items than were returned.
+    pub has_more: bool,
+}
+
+#[derive(Serialize, Debug)]
+struct Envelope<T> {
+    data: Vec<T>,
+    warnings: Vec<Warning>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pagination: Option<Pagination>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    poll_after_ms: Option<u64>,
+}
+
+/// A listing, which responds with or without the envelope depending on the request's `Accept`
+/// header.
+#[derive(Debug)]
+pub struct Listing<T> {
+    data: Vec<T>,
+    warnings: Vec<Warning>,
+    pagination: Option<Pagination>,
+    poll_after_ms: Option<u64>,
+    max_age_secs: Option<u64>,
+}
+
+impl<T> Listing<T> {
+    pub fn new(data: Vec<T>) -> Self {
+        Self {
+            data,
+            warnings: vec![],
+            pagination: None,
+            poll_after_ms: None,
+            max_age_secs: None,
+        }
+    }
+
+    pub fn warn(mut self, code: &'static str, message: impl Into<String>) -> Self {
+        self.warnings.push(Warning {
+            code,
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Adds a warning when `cond` holds.
+    pub fn warn_if(self, cond: bool, code: &'static str, message: impl Into<String>) -> Self {
+        if cond {
+            self.warn(code, message)
+        } else {
+            self
+        }
+    }
+
+    /// Records that the listing was limited to `limit` items.
+    pub fn paginated(mut self, limit: i64) -> Self {
+        self.pagination = Some(Pagination {
+            limit,
+            has_more: self.data.len() as i64 >= limit,
+        });
+        self
+    }
+
+    /// Hints how long a client polling the listing should wait before polling again.
+    pub fn poll_after(mut self, ms: u64) -> Self {
+        self.poll_after_ms = Some(ms);
+        self
+    }
+
+    /// Lets clients reuse the listing for `secs` seconds, then revalidate it by its `ETag`.
+    pub fn cache_for(mut self, secs: u64) -> Self {
+        self.max_age_secs = Some(secs);
+        self
+    }
+}
+
+/// A weak entity tag for a response body.
+pub fn etag(body: &[u8]) -> String {
+    format!("W/\"{}\"", &blake3::hash(body).to_hex()[..32])
+}
+
+/// Whether an `If-None-Match` header value matches `etag`.
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let strip = |t: &str| t.trim().trim_start_matches("W/").to_string();
+    if_none_match
+        .split(',')
+        .any(|t| t.trim() == "*" || strip(t) == strip(etag))
+}
+
+/// Whether an `Accept` header value asks for the envelope.
+pub fn accepts_envelope(accept: &str) -> bool {
+    accept
+        .split(',')
+        .filter_map(|t| t.split(';').next())
+        .any(|t| t.trim().eq_ignore_ascii_case(ENVELOPE_MEDIA_TYPE))
+}
+
+/// Formats a warning as a `Warning` header value, with the miscellaneous persistent warning code.
+fn warning_header(warning: &Warning) -> String {
+    format!("299 hitsave \"{}\"", warning.message.replace('"', "'"))
+}
+
+impl<T: Serialize> Responder for Listing<T> {
+    type Body = BoxBody;
+
+    fn respond_to(self, req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let enveloped = req
+            .headers()
+            .get_all(ACCEPT)
+            .filter_map(|v| v.to_str().ok())
+            .any(accepts_envelope);
+
+        let mut res = HttpResponse::Ok();
+        res.insert_header((VARY, "Accept"));
+        if let Some(ms) = self.poll_after_ms {
+            res.insert_header((POLL_AFTER_HEADER, ms));
+        }
+
+        let body = if enveloped {
+            serde_json::to_vec(&Envelope {
+                data: self.data,
+                warnings: self.warnings,
+                pagination: self.pagination,
+                poll_after_ms: self.poll_after_ms,
+            })
+        } else {
+            for warning in &self.warnings {
+                res.append_header((WARNING, warning_header(warning)));
+            }
+            serde_json::to_vec(&self.data)
+        };
+        let body = match body {
+            Ok(body) => body,
+            Err(e) => return HttpResponse::from_error(JsonPayloadError::Serialize(e)),
+        };
+
+        if let Some(secs) = self.max_age_secs {
+            let etag = etag(&body);
+            res.insert_header((CACHE_CONTROL, format!("private, max-age={}", secs)));
+            res.insert_header((ETAG, etag.clone()));
+            let unchanged = req
+                .headers()
+                .get_all(IF_NONE_MATCH)
+                .filter_map(|v| v.to_str().ok())
+                .any(|v| etag_matches(v, &etag));
+            if unchanged {
+                return res.status(StatusCode::NOT_MODIFIED).finish();
+            }
+        }
+
+        res.content_type(ContentType::json()).body(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_envelope() {
+        assert!(accepts_envelope(ENVELOPE_MEDIA_TYPE));
+        assert!(accepts_envelope(
+            "application/json, application/vnd.hitsave.envelope+json;q=0.9"
+        ));
+        assert!(!accepts_envelope("application/json"));
+        assert!(!accepts_envelope("*/*"));
+    }
+
+    #[test]
+    fn paginates() {
+        let listing = Listing::new(vec![1, 2, 3]).paginated(3);
+        assert_eq!(
+            listing.pagination,
+            Some(Pagination {
+                limit: 3,
+                has_more: true
+            })
+        );
+        let listing = Listing::new(vec![1, 2]).paginated(3);
+        assert!(!listing.pagination.unwrap().has_more);
+    }
+
+    #[test]
+    fn matches_etags() {
+        let tag = etag(b"[]");
+        assert!(etag_matches(&tag, &tag));
+        assert!(etag_matches(
+            &format!("\"x\", {}", tag.trim_start_matches("W/")),
+            &tag
+        ));
+        assert!(etag_matches("*", &tag));
+        assert!(!etag_matches(&etag(b"[1]"), &tag));
+    }
+}
@@ -0,0 +1,908 @@
+//! Error codes, so that clients can tell errors apart without parsing their messages.
+//!
+//! Every error response carries a code, as `HS-<number>`, in the [`ERROR_CODE_HEADER`] header,
+//! and, unless it's in a protocol's own format (e.g. SCIM's, or S3's), or has more to say than a
+//! message (e.g. why an eval missed), as the body:
+//!
+//! ```json
+//! {"code": "HS-1001", "name": "InvalidHash", "message": "invalid hash"}
+//! ```
+//!
+//! Errors with a meaning of their own have their own codes, made with [`coded`], numbered in
+//! blocks of a hundred by what they're about. Errors any request may have, such as not being
+//! authenticated, or failing, are given the general code of their status by the `error_codes`
+//! middleware. The codes are listed,
+//! with what they mean, at `GET /capabilities`. A code's meaning never changes once it's listed.
+use actix_web::{error, http::header, http::StatusCode, HttpResponse, HttpResponseBuilder};
+
+/// The header an error response's code is sent in.
+pub const ERROR_CODE_HEADER: &str = "x-hitsave-error-code";
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    // General codes, one per status, for errors without a code of their own.
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    Conflict,
+    PayloadTooLarge,
+    RangeNotSatisfiable,
+    TooManyRequests,
+    Internal,
+    NotImplemented,
+    Unavailable,
+    // BLOBs.
+    InvalidHash,
+    BlobNotFound,
+    BlobArchived,
+    BlobMissing,
+    BlobTooLarge,
+    InvalidPatch,
+    QuotaExceeded,
+    InvalidResume,
+    InvalidRange,
+    DirectDownloadUnsupported,
+    InvalidBlobMetadata,
+    DownloadsBusy,
+    BlobStoreError,
+    InvalidBlobQuery,
+    MissingPayload,
+    BlobStoreUnavailable,
+    InvalidBlobEncoding,
+    UnexpectedEof,
+    UploadStalled,
+    MissingBlobProto,
+    UnsupportedBlobProto,
+    FrameVersionMismatch,
+    UploadNotFound,
+    UploadTooLarge,
+    DirectUploadUnsupported,
+    NothingUploaded,
+    InvalidPart,
+    PartOutOfOrder,
+    UploadIncomplete,
+    // Load.
+    BatchRejected,
+    // Evals, their changes, and search.
+    EvalsNotFound,
+    UnresolvedPrincipal,
+    EvalMiss,
+    EvalsRejected,
+    ValidationFailed,
+    InvalidEvalQuery,
+    InvalidCacheReport,
+    InvalidImport,
+    InvalidMultiEval,
+    ImportClosed,
+    EvalsHeld,
+    SimilarityDisabled,
+    EmbeddingFailed,
+    CursorExpired,
+    EmptySearchQuery,
+    // Authentication and API keys.
+    NoAuthHeader,
+    InvalidAuthHeader,
+    InvalidJwt,
+    InvalidApiKey,
+    InvalidExchange,
+    // Runs.
+    RunNotFound,
+    RunEvalNotFound,
+    InvalidRunTransition,
+    RunNotArchived,
+    // Users and signing in.
+    EmailExists,
+    EmailInUse,
+    InviteNotFound,
+    GitHubLoginFailed,
+    NoPrimaryEmail,
+    AlreadyOnWaitlist,
+    // Single sign-on.
+    SsoNotConfigured,
+    InvalidSsoConfig,
+    LoginExpired,
+    IdentityProviderFailed,
+    InvalidIdToken,
+    NoVerifiedEmail,
+    AccountExists,
+    Deprovisioned,
+    // SCIM. Their bodies are SCIM's own.
+    ScimNotFound,
+    ScimUniqueness,
+    ScimMutability,
+    ScimInvalidFilter,
+    ScimInvalidPath,
+    ScimInvalidValue,
+    // Orgs: their policies, provisioning, holds and canaries.
+    OrgNotFound,
+    InvalidPolicyRules,
+    ProvisionNotFound,
+    ProvisionConflict,
+    ProvisionPreconditionFailed,
+    InvalidResource,
+    InvalidResidency,
+    HoldNotFound,
+    InvalidHold,
+    HoldReleased,
+    CanaryNotFound,
+    InvalidCanary,
+    // Projects: their settings, metrics, alerts, cache policies and functions.
+    ProjectNotFound,
+    IntegrityDisabled,
+    InvalidProjectSettings,
+    MetricNotFound,
+    InvalidJsonPath,
+    AlertNotFound,
+    InvalidAlertRule,
+    CachePolicyNotFound,
+    InvalidFunctionKeys,
+    InvalidCachePolicy,
+    FunctionNotFound,
+    FunctionTooLarge,
+    // Integrations: DVC, Jupyter, MLflow and the S3 gateway. The bodies of MLflow's and S3's are
+    // their own.
+    DvcObjectNotFound,
+    InvalidDvcPath,
+    NotebookNotFound,
+    MlflowNotFound,
+    MlflowInvalidParameter,
+    S3AccessDenied,
+    S3SignatureDoesNotMatch,
+    S3InvalidObjectState,
+    S3NoSuchKey,
+    // Administration.
+    InventoryNotFound,
+    InventoryRunning,
+    DeadLetterNotFound,
+    DeadLetterResolved,
+    DeadLetterNotStored,
+    DeadLetterRunDeleted,
+    CaptureNotFound,
+    CaptureDisabled,
+    InvalidCaptureDuration,
+    ChaosDisabled,
+    InvalidFault,
+    RegionNotFound,
+    RegionsNotConfigured,
+    InvalidRegion,
+    RegionInUse,
+    DiscrepancyNotFound,
+    BackfillRunning,
+    BandwidthKeysNotFound,
+    InvalidBandwidthCap,
+    AnomalyNotFound,
+    IncidentNotFound,
+    InvalidIncident,
+    // Jobs.
+    JobNotFound,
+}
+
+/// Every code, in order.
+pub const ERROR_CODES: [ErrorCode; 141] = [
+    ErrorCode::BadRequest,
+    ErrorCode::Unauthorized,
+    ErrorCode::Forbidden,
+    ErrorCode::NotFound,
+    ErrorCode::Conflict,
+    ErrorCode::PayloadTooLarge,
+    ErrorCode::RangeNotSatisfiable,
+    ErrorCode::TooManyRequests,
+    ErrorCode::Internal,
+    ErrorCode::NotImplemented,
+    ErrorCode::Unavailable,
+    ErrorCode::InvalidHash,
+    ErrorCode::BlobNotFound,
+    ErrorCode::BlobArchived,
+    ErrorCode::BlobMissing,
+    ErrorCode::BlobTooLarge,
+    ErrorCode::InvalidPatch,
+    ErrorCode::QuotaExceeded,
+    ErrorCode::InvalidResume,
+    ErrorCode::InvalidRange,
+    ErrorCode::DirectDownloadUnsupported,
+    ErrorCode::InvalidBlobMetadata,
+    ErrorCode::DownloadsBusy,
+    ErrorCode::BlobStoreError,
+    ErrorCode::InvalidBlobQuery,
+    ErrorCode::MissingPayload,
+    ErrorCode::BlobStoreUnavailable,
+    ErrorCode::InvalidBlobEncoding,
+    ErrorCode::UnexpectedEof,
+    ErrorCode::UploadStalled,
+    ErrorCode::MissingBlobProto,
+    ErrorCode::UnsupportedBlobProto,
+    ErrorCode::FrameVersionMismatch,
+    ErrorCode::UploadNotFound,
+    ErrorCode::UploadTooLarge,
+    ErrorCode::DirectUploadUnsupported,
+    ErrorCode::NothingUploaded,
+    ErrorCode::InvalidPart,
+    ErrorCode::PartOutOfOrder,
+    ErrorCode::UploadIncomplete,
+    ErrorCode::BatchRejected,
+    ErrorCode::EvalsNotFound,
+    ErrorCode::UnresolvedPrincipal,
+    ErrorCode::EvalMiss,
+    ErrorCode::EvalsRejected,
+    ErrorCode::ValidationFailed,
+    ErrorCode::InvalidEvalQuery,
+    ErrorCode::InvalidCacheReport,
+    ErrorCode::InvalidImport,
+    ErrorCode::InvalidMultiEval,
+    ErrorCode::ImportClosed,
+    ErrorCode::EvalsHeld,
+    ErrorCode::SimilarityDisabled,
+    ErrorCode::EmbeddingFailed,
+    ErrorCode::CursorExpired,
+    ErrorCode::EmptySearchQuery,
+    ErrorCode::NoAuthHeader,
+    ErrorCode::InvalidAuthHeader,
+    ErrorCode::InvalidJwt,
+    ErrorCode::InvalidApiKey,
+    ErrorCode::InvalidExchange,
+    ErrorCode::RunNotFound,
+    ErrorCode::RunEvalNotFound,
+    ErrorCode::InvalidRunTransition,
+    ErrorCode::RunNotArchived,
+    ErrorCode::EmailExists,
+    ErrorCode::EmailInUse,
+    ErrorCode::InviteNotFound,
+    ErrorCode::GitHubLoginFailed,
+    ErrorCode::NoPrimaryEmail,
+    ErrorCode::AlreadyOnWaitlist,
+    ErrorCode::SsoNotConfigured,
+    ErrorCode::InvalidSsoConfig,
+    ErrorCode::LoginExpired,
+    ErrorCode::IdentityProviderFailed,
+    ErrorCode::InvalidIdToken,
+    ErrorCode::NoVerifiedEmail,
+    ErrorCode::AccountExists,
+    ErrorCode::Deprovisioned,
+    ErrorCode::ScimNotFound,
+    ErrorCode::ScimUniqueness,
+    ErrorCode::ScimMutability,
+    ErrorCode::ScimInvalidFilter,
+    ErrorCode::ScimInvalidPath,
+    ErrorCode::ScimInvalidValue,
+    ErrorCode::OrgNotFound,
+    ErrorCode::InvalidPolicyRules,
+    ErrorCode::ProvisionNotFound,
+    ErrorCode::ProvisionConflict,
+    ErrorCode::ProvisionPreconditionFailed,
+    ErrorCode::InvalidResource,
+    ErrorCode::InvalidResidency,
+    ErrorCode::HoldNotFound,
+    ErrorCode::InvalidHold,
+    ErrorCode::HoldReleased,
+    ErrorCode::CanaryNotFound,
+    ErrorCode::InvalidCanary,
+    ErrorCode::ProjectNotFound,
+    ErrorCode::IntegrityDisabled,
+    ErrorCode::InvalidProjectSettings,
+    ErrorCode::MetricNotFound,
+    ErrorCode::InvalidJsonPath,
+    ErrorCode::AlertNotFound,
+    ErrorCode::InvalidAlertRule,
+    ErrorCode::CachePolicyNotFound,
+    ErrorCode::InvalidFunctionKeys,
+    ErrorCode::InvalidCachePolicy,
+    ErrorCode::FunctionNotFound,
+    ErrorCode::FunctionTooLarge,
+    ErrorCode::DvcObjectNotFound,
+    ErrorCode::InvalidDvcPath,
+    ErrorCode::NotebookNotFound,
+    ErrorCode::MlflowNotFound,
+    ErrorCode::MlflowInvalidParameter,
+    ErrorCode::S3AccessDenied,
+    ErrorCode::S3SignatureDoesNotMatch,
+    ErrorCode::S3InvalidObjectState,
+    ErrorCode::S3NoSuchKey,
+    ErrorCode::InventoryNotFound,
+    ErrorCode::InventoryRunning,
+    ErrorCode::DeadLetterNotFound,
+    ErrorCode::DeadLetterResolved,
+    ErrorCode::DeadLetterNotStored,
+    ErrorCode::DeadLetterRunDeleted,
+    ErrorCode::CaptureNotFound,
+    ErrorCode::CaptureDisabled,
+    ErrorCode::InvalidCaptureDuration,
+    ErrorCode::ChaosDisabled,
+    ErrorCode::InvalidFault,
+    ErrorCode::RegionNotFound,
+    ErrorCode::RegionsNotConfigured,
+    ErrorCode::InvalidRegion,
+    ErrorCode::RegionInUse,
+    ErrorCode::DiscrepancyNotFound,
+    ErrorCode::BackfillRunning,
+    ErrorCode::BandwidthKeysNotFound,
+    ErrorCode::InvalidBandwidthCap,
+    ErrorCode::AnomalyNotFound,
+    ErrorCode::IncidentNotFound,
+    ErrorCode::InvalidIncident,
+    ErrorCode::JobNotFound,
+];
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "HS-0400",
+            ErrorCode::Unauthorized => "HS-0401",
+            ErrorCode::Forbidden => "HS-0403",
+            ErrorCode::NotFound => "HS-0404",
+            ErrorCode::Conflict => "HS-0409",
+            ErrorCode::PayloadTooLarge => "HS-0413",
+            ErrorCode::RangeNotSatisfiable => "HS-0416",
+            ErrorCode::TooManyRequests => "HS-0429",
+            ErrorCode::Internal => "HS-0500",
+            ErrorCode::NotImplemented => "HS-0501",
+            ErrorCode::Unavailable => "HS-0503",
+            ErrorCode::InvalidHash => "HS-1001",
+            ErrorCode::BlobNotFound => "HS-1002",
+            ErrorCode::BlobArchived => "HS-1003",
+            ErrorCode::BlobMissing => "HS-1004",
+            ErrorCode::BlobTooLarge => "HS-1005",
+            ErrorCode::InvalidPatch => "HS-1006",
+            ErrorCode::QuotaExceeded => "HS-1007",
+            ErrorCode::InvalidResume => "HS-1008",
+            ErrorCode::InvalidRange => "HS-1009",
+            ErrorCode::DirectDownloadUnsupported => "HS-1010",
+            ErrorCode::InvalidBlobMetadata => "HS-1011",
+            ErrorCode::DownloadsBusy => "HS-1012",
+            ErrorCode::BlobStoreError => "HS-1013",
+            ErrorCode::InvalidBlobQuery => "HS-1014",
+            ErrorCode::MissingPayload => "HS-1015",
+            ErrorCode::BlobStoreUnavailable => "HS-1016",
+            ErrorCode::InvalidBlobEncoding => "HS-1017",
+            ErrorCode::UnexpectedEof => "HS-1018",
+            ErrorCode::UploadStalled => "HS-1019",
+            ErrorCode::MissingBlobProto => "HS-1020",
+            ErrorCode::UnsupportedBlobProto => "HS-1021",
+            ErrorCode::FrameVersionMismatch => "HS-1022",
+            ErrorCode::UploadNotFound => "HS-1023",
+            ErrorCode::UploadTooLarge => "HS-1024",
+            ErrorCode::DirectUploadUnsupported => "HS-1025",
+            ErrorCode::NothingUploaded => "HS-1026",
+            ErrorCode::InvalidPart => "HS-1027",
+            ErrorCode::PartOutOfOrder => "HS-1028",
+            ErrorCode::UploadIncomplete => "HS-1029",
+            ErrorCode::BatchRejected => "HS-1101",
+            ErrorCode::EvalsNotFound => "HS-1201",
+            ErrorCode::UnresolvedPrincipal => "HS-1202",
+            ErrorCode::EvalMiss => "HS-1203",
+            ErrorCode::EvalsRejected => "HS-1204",
+            ErrorCode::ValidationFailed => "HS-1205",
+            ErrorCode::InvalidEvalQuery => "HS-1206",
+            ErrorCode::InvalidCacheReport => "HS-1207",
+            ErrorCode::InvalidImport => "HS-1208",
+            ErrorCode::InvalidMultiEval => "HS-1209",
+            ErrorCode::ImportClosed => "HS-1210",
+            ErrorCode::EvalsHeld => "HS-1211",
+            ErrorCode::SimilarityDisabled => "HS-1212",
+            ErrorCode::EmbeddingFailed => "HS-1213",
+            ErrorCode::CursorExpired => "HS-1214",
+            ErrorCode::EmptySearchQuery => "HS-1215",
+            ErrorCode::NoAuthHeader => "HS-1301",
+            ErrorCode::InvalidAuthHeader => "HS-1302",
+            ErrorCode::InvalidJwt => "HS-1303",
+            ErrorCode::InvalidApiKey => "HS-1304",
+            ErrorCode::InvalidExchange => "HS-1305",
+            ErrorCode::RunNotFound => "HS-1401",
+            ErrorCode::RunEvalNotFound => "HS-1402",
+            ErrorCode::InvalidRunTransition => "HS-1403",
+            ErrorCode::RunNotArchived => "HS-1404",
+            ErrorCode::EmailExists => "HS-1501",
+            ErrorCode::EmailInUse => "HS-1502",
+            ErrorCode::InviteNotFound => "HS-1503",
+            ErrorCode::GitHubLoginFailed => "HS-1504",
+            ErrorCode::NoPrimaryEmail => "HS-1505",
+            ErrorCode::AlreadyOnWaitlist => "HS-1506",
+            ErrorCode::SsoNotConfigured => "HS-1601",
+            ErrorCode::InvalidSsoConfig => "HS-1602",
+            ErrorCode::LoginExpired => "HS-1603",
+            ErrorCode::IdentityProviderFailed => "HS-1604",
+            ErrorCode::InvalidIdToken => "HS-1605",
+            ErrorCode::NoVerifiedEmail => "HS-1606",
+            ErrorCode::AccountExists => "HS-1607",
+            ErrorCode::Deprovisioned => "HS-1608",
+            ErrorCode::ScimNotFound => "HS-1701",
+            ErrorCode::ScimUniqueness => "HS-1702",
+            ErrorCode::ScimMutability => "HS-1703",
+            ErrorCode::ScimInvalidFilter => "HS-1704",
+            ErrorCode::ScimInvalidPath => "HS-1705",
+            ErrorCode::ScimInvalidValue => "HS-1706",
+            ErrorCode::OrgNotFound => "HS-1801",
+            ErrorCode::InvalidPolicyRules => "HS-1802",
+            ErrorCode::ProvisionNotFound => "HS-1803",
+            ErrorCode::ProvisionConflict => "HS-1804",
+            ErrorCode::ProvisionPreconditionFailed => "HS-1805",
+            ErrorCode::InvalidResource => "HS-1806",
+            ErrorCode::InvalidResidency => "HS-1807",
+            ErrorCode::HoldNotFound => "HS-1808",
+            ErrorCode::InvalidHold => "HS-1809",
+            ErrorCode::HoldReleased => "HS-1810",
+            ErrorCode::CanaryNotFound => "HS-1811",
+            ErrorCode::InvalidCanary => "HS-1812",
+            ErrorCode::ProjectNotFound => "HS-1901",
+            ErrorCode::IntegrityDisabled => "HS-1902",
+            ErrorCode::InvalidProjectSettings => "HS-1903",
+            ErrorCode::MetricNotFound => "HS-1904",
+            ErrorCode::InvalidJsonPath => "HS-1905",
+            ErrorCode::AlertNotFound => "HS-1906",
+            ErrorCode::InvalidAlertRule => "HS-1907",
+            ErrorCode::CachePolicyNotFound => "HS-1908",
+            ErrorCode::InvalidFunctionKeys => "HS-1909",
+            ErrorCode::InvalidCachePolicy => "HS-1910",
+            ErrorCode::FunctionNotFound => "HS-1911",
+            ErrorCode::FunctionTooLarge => "HS-1912",
+            ErrorCode::DvcObjectNotFound => "HS-2001",
+            ErrorCode::InvalidDvcPath => "HS-2002",
+            ErrorCode::NotebookNotFound => "HS-2003",
+            ErrorCode::MlflowNotFound => "HS-2004",
+            ErrorCode::MlflowInvalidParameter => "HS-2005",
+            ErrorCode::S3AccessDenied => "HS-2006",
+            ErrorCode::S3SignatureDoesNotMatch => "HS-2007",
+            ErrorCode::S3InvalidObjectState => "HS-2008",
+            ErrorCode::S3NoSuchKey => "HS-2009",
+            ErrorCode::InventoryNotFound => "HS-2101",
+            ErrorCode::InventoryRunning => "HS-2102",
+            ErrorCode::DeadLetterNotFound => "HS-2103",
+            ErrorCode::DeadLetterResolved => "HS-2104",
+            ErrorCode::DeadLetterNotStored => "HS-2105",
+            ErrorCode::DeadLetterRunDeleted => "HS-2106",
+            ErrorCode::CaptureNotFound => "HS-2107",
+            ErrorCode::CaptureDisabled => "HS-2108",
+            ErrorCode::InvalidCaptureDuration => "HS-2109",
+            ErrorCode::ChaosDisabled => "HS-2110",
+            ErrorCode::InvalidFault => "HS-2111",
+            ErrorCode::RegionNotFound => "HS-2112",
+            ErrorCode::RegionsNotConfigured => "HS-2113",
+            ErrorCode::InvalidRegion => "HS-2114",
+            ErrorCode::RegionInUse => "HS-2115",
+            ErrorCode::DiscrepancyNotFound => "HS-2116",
+            ErrorCode::BackfillRunning => "HS-2117",
+            ErrorCode::BandwidthKeysNotFound => "HS-2118",
+            ErrorCode::InvalidBandwidthCap => "HS-2119",
+            ErrorCode::AnomalyNotFound => "HS-2120",
+            ErrorCode::IncidentNotFound => "HS-2121",
+            ErrorCode::InvalidIncident => "HS-2122",
+            ErrorCode::JobNotFound => "HS-2201",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::BadRequest
+            | ErrorCode::InvalidHash
+            | ErrorCode::InvalidPatch
+            | ErrorCode::InvalidBlobMetadata
+            | ErrorCode::InvalidBlobQuery
+            | ErrorCode::MissingPayload
+            | ErrorCode::InvalidBlobEncoding
+            | ErrorCode::UnexpectedEof
+            | ErrorCode::MissingBlobProto
+            | ErrorCode::UnsupportedBlobProto
+            | ErrorCode::FrameVersionMismatch
+            | ErrorCode::UploadTooLarge
+            | ErrorCode::InvalidPart
+            | ErrorCode::InvalidEvalQuery
+            | ErrorCode::InvalidCacheReport
+            | ErrorCode::InvalidImport
+            | ErrorCode::InvalidMultiEval
+            | ErrorCode::EmptySearchQuery
+            | ErrorCode::InvalidExchange
+            | ErrorCode::EmailExists
+            | ErrorCode::InvalidSsoConfig
+            | ErrorCode::LoginExpired
+            | ErrorCode::ScimMutability
+            | ErrorCode::ScimInvalidFilter
+            | ErrorCode::ScimInvalidPath
+            | ErrorCode::ScimInvalidValue
+            | ErrorCode::InvalidPolicyRules
+            | ErrorCode::InvalidResource
+            | ErrorCode::InvalidResidency
+            | ErrorCode::InvalidHold
+            | ErrorCode::InvalidCanary
+            | ErrorCode::InvalidProjectSettings
+            | ErrorCode::InvalidJsonPath
+            | ErrorCode::InvalidAlertRule
+            | ErrorCode::InvalidFunctionKeys
+            | ErrorCode::InvalidCachePolicy
+            | ErrorCode::InvalidDvcPath
+            | ErrorCode::MlflowInvalidParameter
+            | ErrorCode::InvalidCaptureDuration
+            | ErrorCode::InvalidFault
+            | ErrorCode::InvalidRegion
+            | ErrorCode::InvalidBandwidthCap
+            | ErrorCode::InvalidIncident => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized
+            | ErrorCode::NoAuthHeader
+            | ErrorCode::InvalidAuthHeader
+            | ErrorCode::InvalidApiKey
+            | ErrorCode::InvalidIdToken => StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden
+            | ErrorCode::UnresolvedPrincipal
+            | ErrorCode::InvalidJwt
+            | ErrorCode::NoVerifiedEmail
+            | ErrorCode::Deprovisioned
+            | ErrorCode::S3AccessDenied
+            | ErrorCode::S3SignatureDoesNotMatch
+            | ErrorCode::S3InvalidObjectState => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound
+            | ErrorCode::BlobNotFound
+            | ErrorCode::BlobMissing
+            | ErrorCode::UploadNotFound
+            | ErrorCode::EvalsNotFound
+            | ErrorCode::EvalMiss
+            | ErrorCode::RunNotFound
+            | ErrorCode::RunEvalNotFound
+            | ErrorCode::InviteNotFound
+            | ErrorCode::SsoNotConfigured
+            | ErrorCode::ScimNotFound
+            | ErrorCode::OrgNotFound
+            | ErrorCode::ProvisionNotFound
+            | ErrorCode::HoldNotFound
+            | ErrorCode::CanaryNotFound
+            | ErrorCode::ProjectNotFound
+            | ErrorCode::IntegrityDisabled
+            | ErrorCode::MetricNotFound
+            | ErrorCode::AlertNotFound
+            | ErrorCode::CachePolicyNotFound
+            | ErrorCode::FunctionNotFound
+            | ErrorCode::DvcObjectNotFound
+            | ErrorCode::NotebookNotFound
+            | ErrorCode::MlflowNotFound
+            | ErrorCode::S3NoSuchKey
+            | ErrorCode::InventoryNotFound
+            | ErrorCode::DeadLetterNotFound
+            | ErrorCode::CaptureNotFound
+            | ErrorCode::CaptureDisabled
+            | ErrorCode::ChaosDisabled
+            | ErrorCode::RegionNotFound
+            | ErrorCode::RegionsNotConfigured
+            | ErrorCode::DiscrepancyNotFound
+            | ErrorCode::BandwidthKeysNotFound
+            | ErrorCode::AnomalyNotFound
+            | ErrorCode::IncidentNotFound
+            | ErrorCode::JobNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Conflict
+            | ErrorCode::BlobArchived
+            | ErrorCode::NothingUploaded
+            | ErrorCode::PartOutOfOrder
+            | ErrorCode::UploadIncomplete
+            | ErrorCode::ImportClosed
+            | ErrorCode::EvalsHeld
+            | ErrorCode::InvalidRunTransition
+            | ErrorCode::RunNotArchived
+            | ErrorCode::EmailInUse
+            | ErrorCode::AlreadyOnWaitlist
+            | ErrorCode::AccountExists
+            | ErrorCode::ScimUniqueness
+            | ErrorCode::ProvisionConflict
+            | ErrorCode::HoldReleased
+            | ErrorCode::InventoryRunning
+            | ErrorCode::DeadLetterResolved
+            | ErrorCode::DeadLetterNotStored
+            | ErrorCode::DeadLetterRunDeleted
+            | ErrorCode::RegionInUse
+            | ErrorCode::BackfillRunning => StatusCode::CONFLICT,
+            ErrorCode::PayloadTooLarge
+            | ErrorCode::BlobTooLarge
+            | ErrorCode::QuotaExceeded
+            | ErrorCode::FunctionTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::RangeNotSatisfiable | ErrorCode::InvalidResume | ErrorCode::InvalidRange => {
+                StatusCode::RANGE_NOT_SATISFIABLE
+            }
+            ErrorCode::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::Internal
+            | ErrorCode::BlobStoreError
+            | ErrorCode::GitHubLoginFailed
+            | ErrorCode::NoPrimaryEmail => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::NotImplemented
+            | ErrorCode::DirectDownloadUnsupported
+            | ErrorCode::DirectUploadUnsupported => StatusCode::NOT_IMPLEMENTED,
+            ErrorCode::Unavailable
+            | ErrorCode::DownloadsBusy
+            | ErrorCode::BlobStoreUnavailable
+            | ErrorCode::BatchRejected
+            | ErrorCode::ValidationFailed
+            | ErrorCode::SimilarityDisabled => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorCode::UploadStalled => StatusCode::REQUEST_TIMEOUT,
+            ErrorCode::EvalsRejected => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::EmbeddingFailed | ErrorCode::IdentityProviderFailed => {
+                StatusCode::BAD_GATEWAY
+            }
+            ErrorCode::CursorExpired => StatusCode::GONE,
+            ErrorCode::ProvisionPreconditionFailed => StatusCode::PRECONDITION_FAILED,
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "The request is malformed.",
+            ErrorCode::Unauthorized => "The request isn't authenticated, or can't be authorized.",
+            ErrorCode::Forbidden => "The authenticated user isn't allowed to do this.",
+            ErrorCode::NotFound => "There's nothing at the path, or the resource doesn't exist.",
+            ErrorCode::Conflict => "The request conflicts with the resource's current state.",
+            ErrorCode::PayloadTooLarge => "The request's body is too large.",
+            ErrorCode::RangeNotSatisfiable => "The requested range can't be sent.",
+            ErrorCode::TooManyRequests => "Too many requests have been made; retry later.",
+            ErrorCode::Internal => "The server failed to handle the request.",
+            ErrorCode::NotImplemented => "The server doesn't support this.",
+            ErrorCode::Unavailable => "The server can't handle the request right now.",
+            ErrorCode::InvalidHash => "The content hash isn't a valid BLOB hash.",
+            ErrorCode::BlobNotFound => "The user has no BLOB with the content hash.",
+            ErrorCode::BlobArchived => "The BLOB is archived; unarchive its run to retrieve it.",
+            ErrorCode::BlobMissing => "The BLOB is recorded, but missing from storage; upload it again.",
+            ErrorCode::BlobTooLarge => "The BLOB, or the patch, is too large to diff or patch.",
+            ErrorCode::InvalidPatch => "The patch isn't a valid bsdiff patch for the base BLOB.",
+            ErrorCode::QuotaExceeded => "Storing the BLOB would exceed the storage quota.",
+            ErrorCode::InvalidResume => "The download can't be resumed; start it again without a resume token.",
+            ErrorCode::InvalidRange => "The Range header is malformed, or asks for bytes past the end of the BLOB.",
+            ErrorCode::DirectDownloadUnsupported => "BLOBs are stored where they can't be downloaded without the server.",
+            ErrorCode::InvalidBlobMetadata => "The BLOB's mime type or label is invalid.",
+            ErrorCode::DownloadsBusy => "The server is serving as many downloads as it can; retry after Retry-After.",
+            ErrorCode::BlobStoreError => "BLOB storage failed.",
+            ErrorCode::InvalidBlobQuery => "The query for BLOBs is invalid.",
+            ErrorCode::MissingPayload => "The request has no BLOB in it.",
+            ErrorCode::BlobStoreUnavailable => "BLOB storage is unavailable; retry later.",
+            ErrorCode::InvalidBlobEncoding => "The BLOB's encoding, or its metadata, is invalid.",
+            ErrorCode::UnexpectedEof => "The BLOB ended before all of it was sent.",
+            ErrorCode::UploadStalled => "No bytes of the BLOB were received for too long, so its upload was aborted.",
+            ErrorCode::MissingBlobProto => "The request doesn't say which BLOB protocol it speaks; upgrade the client.",
+            ErrorCode::UnsupportedBlobProto => "The server doesn't speak the request's BLOB protocol version.",
+            ErrorCode::FrameVersionMismatch => "A frame's version isn't the request's BLOB protocol version.",
+            ErrorCode::UploadNotFound => "The upload doesn't exist, or has expired.",
+            ErrorCode::UploadTooLarge => "The BLOB is too large to upload this way.",
+            ErrorCode::DirectUploadUnsupported => "BLOBs are stored where they can't be uploaded without the server.",
+            ErrorCode::NothingUploaded => "Nothing has been uploaded to the upload's URL yet.",
+            ErrorCode::InvalidPart => "The part's number or length is invalid.",
+            ErrorCode::PartOutOfOrder => "Parts must be sent in order; send the part the body names.",
+            ErrorCode::UploadIncomplete => "The upload has parts still to send; send the part the body names.",
+            ErrorCode::BatchRejected => "The server is busy, and turning batch requests away; retry after Retry-After.",
+            ErrorCode::EvalsNotFound => "No evals match the parameters.",
+            ErrorCode::UnresolvedPrincipal => "The credentials don't resolve to a user.",
+            ErrorCode::EvalMiss => "There's no eval of the function with the arguments. The body says why, and may have an eval of another version.",
+            ErrorCode::EvalsRejected => "The project's validation webhook rejected the evals.",
+            ErrorCode::ValidationFailed => "The project's validation webhook failed.",
+            ErrorCode::InvalidEvalQuery => "The query for evals is invalid.",
+            ErrorCode::InvalidCacheReport => "The cache report is invalid.",
+            ErrorCode::InvalidImport => "An eval in the import is invalid.",
+            ErrorCode::InvalidMultiEval => "The evals for one BLOB are invalid.",
+            ErrorCode::ImportClosed => "The import has already finished.",
+            ErrorCode::EvalsHeld => "The evals are under a legal hold.",
+            ErrorCode::SimilarityDisabled => "Similarity search isn't enabled.",
+            ErrorCode::EmbeddingFailed => "The eval's arguments couldn't be embedded.",
+            ErrorCode::CursorExpired => "Changes since the cursor have been pruned; list evals again, from the cursor given by /changes/head.",
+            ErrorCode::EmptySearchQuery => "The search query is empty.",
+            ErrorCode::NoAuthHeader => "The request has no Authorization header.",
+            ErrorCode::InvalidAuthHeader => "The Authorization header is malformed.",
+            ErrorCode::InvalidJwt => "The session's JWT is invalid, or has expired.",
+            ErrorCode::InvalidApiKey => "The API key is invalid, revoked or expired.",
+            ErrorCode::InvalidExchange => "A key exchange needs a device name, and may have a project name, each of at most 100 characters.",
+            ErrorCode::RunNotFound => "The user has no such run.",
+            ErrorCode::RunEvalNotFound => "The eval to link to the run isn't one of the user's.",
+            ErrorCode::InvalidRunTransition => "The run can't move from its state to the one asked for.",
+            ErrorCode::RunNotArchived => "The run isn't archived.",
+            ErrorCode::EmailExists => "The email address already belongs to a user.",
+            ErrorCode::EmailInUse => "The email address belongs to an account which isn't linked to the GitHub account.",
+            ErrorCode::InviteNotFound => "The user has no pending membership of the org.",
+            ErrorCode::GitHubLoginFailed => "Signing in with GitHub failed.",
+            ErrorCode::NoPrimaryEmail => "The GitHub account has no primary email address.",
+            ErrorCode::AlreadyOnWaitlist => "The email address is already on the waitlist.",
+            ErrorCode::SsoNotConfigured => "The org hasn't configured single sign-on.",
+            ErrorCode::InvalidSsoConfig => "The identity provider configuration is invalid, or can't be used.",
+            ErrorCode::LoginExpired => "The sign-in was never started, or took too long; start it again.",
+            ErrorCode::IdentityProviderFailed => "The identity provider couldn't be reached, or responded unexpectedly.",
+            ErrorCode::InvalidIdToken => "The identity provider's ID token failed validation.",
+            ErrorCode::NoVerifiedEmail => "The identity provider didn't supply a verified email address.",
+            ErrorCode::AccountExists => "An account with the email address exists, and isn't a member of the org, or hasn't accepted its membership.",
+            ErrorCode::Deprovisioned => "The user's membership of the org has been deactivated.",
+            ErrorCode::ScimNotFound => "The org has no such SCIM resource.",
+            ErrorCode::ScimUniqueness => "The SCIM resource already exists.",
+            ErrorCode::ScimMutability => "The attribute can't be changed.",
+            ErrorCode::ScimInvalidFilter => "The SCIM filter isn't supported.",
+            ErrorCode::ScimInvalidPath => "The SCIM patch path isn't supported.",
+            ErrorCode::ScimInvalidValue => "The SCIM value is invalid.",
+            ErrorCode::OrgNotFound => "The org doesn't exist, or the user can't manage it.",
+            ErrorCode::InvalidPolicyRules => "The policy's rules are invalid.",
+            ErrorCode::ProvisionNotFound => "The provisioned resource doesn't exist.",
+            ErrorCode::ProvisionConflict => "The resource conflicts with an existing resource.",
+            ErrorCode::ProvisionPreconditionFailed => "The resource doesn't match the request's preconditions.",
+            ErrorCode::InvalidResource => "The resource to provision is invalid.",
+            ErrorCode::InvalidResidency => "The data residency is invalid.",
+            ErrorCode::HoldNotFound => "The org, hold, run or eval doesn't exist.",
+            ErrorCode::InvalidHold => "A hold needs a reason, and at least one run or eval.",
+            ErrorCode::HoldReleased => "The hold has already been released.",
+            ErrorCode::CanaryNotFound => "The org or canary doesn't exist.",
+            ErrorCode::InvalidCanary => "A canary needs a label, and its networks must be valid CIDRs.",
+            ErrorCode::ProjectNotFound => "The user has no such project.",
+            ErrorCode::IntegrityDisabled => "Integrity manifests aren't enabled.",
+            ErrorCode::InvalidProjectSettings => "The project's settings are invalid.",
+            ErrorCode::MetricNotFound => "The project or metric rule doesn't exist.",
+            ErrorCode::InvalidJsonPath => "The metric rule's JSONPath expression is invalid.",
+            ErrorCode::AlertNotFound => "The alert rule or project doesn't exist.",
+            ErrorCode::InvalidAlertRule => "The alert rule is invalid.",
+            ErrorCode::CachePolicyNotFound => "The function has no cache policy.",
+            ErrorCode::InvalidFunctionKeys => "The function keys are invalid.",
+            ErrorCode::InvalidCachePolicy => "The cache policy is invalid.",
+            ErrorCode::FunctionNotFound => "The user has no such function.",
+            ErrorCode::FunctionTooLarge => "The function's source is too long.",
+            ErrorCode::DvcObjectNotFound => "The DVC remote has no such object.",
+            ErrorCode::InvalidDvcPath => "The DVC object path is invalid.",
+            ErrorCode::NotebookNotFound => "The notebook's run or BLOB doesn't exist.",
+            ErrorCode::MlflowNotFound => "The MLflow resource doesn't exist.",
+            ErrorCode::MlflowInvalidParameter => "An MLflow parameter is invalid.",
+            ErrorCode::S3AccessDenied => "The S3 request's credentials don't allow it.",
+            ErrorCode::S3SignatureDoesNotMatch => "The S3 request's signature doesn't match it.",
+            ErrorCode::S3InvalidObjectState => "The S3 object is archived.",
+            ErrorCode::S3NoSuchKey => "The S3 bucket has no such key.",
+            ErrorCode::InventoryNotFound => "The storage inventory doesn't exist.",
+            ErrorCode::InventoryRunning => "A storage inventory is already being taken.",
+            ErrorCode::DeadLetterNotFound => "The dead letter doesn't exist.",
+            ErrorCode::DeadLetterResolved => "The dead letter has already been re-driven or dismissed.",
+            ErrorCode::DeadLetterNotStored => "The dead letter's BLOB isn't stored, so there's nothing to re-drive.",
+            ErrorCode::DeadLetterRunDeleted => "The dead letter's run has been deleted; dismiss it instead.",
+            ErrorCode::CaptureNotFound => "The captured request doesn't exist.",
+            ErrorCode::CaptureDisabled => "Request capture isn't enabled.",
+            ErrorCode::InvalidCaptureDuration => "The capture's duration is out of range.",
+            ErrorCode::ChaosDisabled => "Fault injection isn't enabled.",
+            ErrorCode::InvalidFault => "Fault probabilities must be between 0 and 1, and their duration at most a day.",
+            ErrorCode::RegionNotFound => "The region doesn't exist.",
+            ErrorCode::RegionsNotConfigured => "No regions are configured; keep using this server.",
+            ErrorCode::InvalidRegion => "A region needs a name, a bucket, and a BLOB endpoint which is a URL.",
+            ErrorCode::RegionInUse => "Orgs pin their data to the region; unpin them first.",
+            ErrorCode::DiscrepancyNotFound => "The BLOB discrepancy doesn't exist.",
+            ErrorCode::BackfillRunning => "A BLOB backfill is already running.",
+            ErrorCode::BandwidthKeysNotFound => "No API keys match.",
+            ErrorCode::InvalidBandwidthCap => "Bandwidth caps must be positive.",
+            ErrorCode::AnomalyNotFound => "The anomaly doesn't exist.",
+            ErrorCode::IncidentNotFound => "The incident doesn't exist.",
+            ErrorCode::InvalidIncident => "An incident needs a known status, an impact of degraded or outage, and components.",
+            ErrorCode::JobNotFound => "The user has no such job.",
+        }
+    }
+
+    /// The general code of an error response with `status`.
+    pub fn for_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED => ErrorCode::Unauthorized,
+            StatusCode::FORBIDDEN => ErrorCode::Forbidden,
+            StatusCode::NOT_FOUND => ErrorCode::NotFound,
+            StatusCode::CONFLICT => ErrorCode::Conflict,
+            StatusCode::PAYLOAD_TOO_LARGE => ErrorCode::PayloadTooLarge,
+            StatusCode::RANGE_NOT_SATISFIABLE => ErrorCode::RangeNotSatisfiable,
+            StatusCode::TOO_MANY_REQUESTS => ErrorCode::TooManyRequests,
+            StatusCode::NOT_IMPLEMENTED => ErrorCode::NotImplemented,
+            StatusCode::SERVICE_UNAVAILABLE => ErrorCode::Unavailable,
+            s if s.is_server_error() => ErrorCode::Internal,
+            _ => ErrorCode::BadRequest,
+        }
+    }
+}
+
+/// The body of an error response.
+#[derive(Serialize, Debug)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    /// The code's name, e.g. `InvalidHash`.
+    pub name: ErrorCode,
+    pub message: String,
+}
+
+impl ErrorBody {
+    pub fn new(code: ErrorCode, message: String) -> Self {
+        Self {
+            code: code.as_str(),
+            name: code,
+            message,
+        }
+    }
+}
+
+/// A code, with its status and what it means, as listed at `GET /capabilities`.
+#[derive(Serialize, Debug)]
+pub struct ErrorCodeEntry {
+    pub code: &'static str,
+    pub name: ErrorCode,
+    pub status: u16,
+    pub description: &'static str,
+}
+
+/// Every code, with its status and what it means.
+pub fn catalog() -> Vec<ErrorCodeEntry> {
+    ERROR_CODES
+        .into_iter()
+        .map(|code| ErrorCodeEntry {
+            code: code.as_str(),
+            name: code,
+            status: code.status().as_u16(),
+            description: code.description(),
+        })
+        .collect()
+}
+
+/// A response to an error with `code`, with `code`'s status, for bodies of the error's own.
+pub fn coded_builder(code: ErrorCode) -> HttpResponseBuilder {
+    let mut res = HttpResponse::build(code.status());
+    res.insert_header((ERROR_CODE_HEADER, code.as_str()));
+    res
+}
+
+/// The response to an error with `code`, and `message`, with `code`'s status.
+pub fn coded_response(code: ErrorCode, message: &str) -> HttpResponse {
+    coded_builder(code).json(ErrorBody::new(code, message.to_string()))
+}
+
+/// An error with `code`, and `message`, with `code`'s status.
+pub fn coded(code: ErrorCode, message: impl Into<String>) -> actix_web::Error {
+    let message = message.into();
+    let res = coded_response(code, &message);
+    error::InternalError::from_response(message, res).into()
+}
+
+/// As [`coded`], asking the client to retry after `retry_after_ms`.
+pub fn coded_retry_after(
+    code: ErrorCode,
+    message: &'static str,
+    retry_after_ms: u64,
+) -> actix_web::Error {
+    let retry_after_secs = (retry_after_ms + 999) / 1000;
+    let mut res = coded_response(code, message);
+    if let Ok(value) = header::HeaderValue::from_str(&retry_after_secs.to_string()) {
+        res.headers_mut().insert(header::RETRY_AFTER, value);
+    }
+    error::InternalError::from_response(message, res).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn codes_are_unique() {
+        let codes: HashSet<&str> = ERROR_CODES.iter().map(|c| c.as_str()).collect();
+        assert_eq!(codes.len(), ERROR_CODES.len());
+        assert!(codes
+            .iter()
+            .all(|c| c.len() == 7 && c.starts_with("HS-") && c[3..].parse::<u16>().is_ok()));
+    }
+
+    #[test]
+    fn codes_are_errors() {
+        for code in ERROR_CODES {
+            assert!(code.status().is_client_error() || code.status().is_server_error());
+            assert!(!code.description().is_empty());
+        }
+    }
+
+    #[test]
+    fn general_codes_match_their_statuses() {
+        for status in [400, 401, 403, 404, 409, 413, 416, 429, 500, 501, 503] {
+            let status = StatusCode::from_u16(status).unwrap();
+            assert_eq!(ErrorCode::for_status(status).status(), status);
+        }
+        assert_eq!(
+            ErrorCode::for_status(StatusCode::METHOD_NOT_ALLOWED),
+            ErrorCode::BadRequest
+        );
+        assert_eq!(
+            ErrorCode::for_status(StatusCode::BAD_GATEWAY),
+            ErrorCode::Internal
+        );
+    }
+
+    #[test]
+    fn bodies_name_their_codes() {
+        let body = serde_json::to_value(ErrorBody::new(
+            ErrorCode::InvalidHash,
+            "invalid hash".to_string(),
+        ))
+        .unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({"code": "HS-1001", "name": "InvalidHash", "message": "invalid hash"})
+        );
+    }
+}
@@ -1,10 +1,40 @@
+use crate::error_code::{coded, ErrorCode};
+use crate::priority::Priority;
+use crate::CONFIG;
 use actix_web::{dev::Payload, error::PayloadError, FromRequest, HttpRequest, Result};
 use futures_core::{ready, Stream};
 use serde::de::DeserializeOwned;
+use tokio::time::{Instant, Sleep};
 
+use std::collections::VecDeque;
 use std::future::Future;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// The header clients send the version of the framing they speak in. Requests without it, or with
+/// a version the server doesn't speak, are rejected before any of the payload is read.
+pub const BLOB_PROTO_HEADER: &str = "x-hitsave-blob-proto";
+
+/// The original version of the framing. A frame is:
+///
+/// - 1 byte; the version, which must match the [`BLOB_PROTO_HEADER`] header,
+/// - 4 byte big-endian unsigned integer; the length of the metadata,
+/// - n bytes; the utf-8 encoded JSON metadata,
+/// - the BLOB's bytes.
+pub const BLOB_PROTO_RAW: u8 = 1;
+
+/// The version of the framing in which the BLOB's bytes are sent in chunks, each a 4 byte
+/// big-endian unsigned integer length followed by that many bytes. Otherwise, frames are as in
+/// [`BLOB_PROTO_RAW`].
+///
+/// Zero-length chunks are keep-alives, which a client can send while it has no bytes ready (e.g.
+/// while it produces them lazily) so that the connection isn't dropped for being idle. They're
+/// skipped, so never count towards the BLOB's length or hash.
+pub const BLOB_PROTO_CHUNKED: u8 = 2;
+
+/// The length of the frame's prefix: the version byte and the length of the metadata.
+const PREFIX_LEN: usize = 5;
 
 /// Represents an attempt to transfer a BLOB via our encoding scheme. This type implements
 /// `FromRequest`, so we can attempt to extract a `BlobTransfer` from any handler.
@@ -18,6 +48,8 @@ use std::task::{Context, Poll};
 pub struct WithBlob<M> {
     pub meta: M,
     pub blob: Option<BlobPayload>,
+    /// The priority the client hinted at for the transfer.
+    pub priority: Priority,
 }
 
 impl<M> WithBlob<M>
@@ -33,6 +65,7 @@ where
         WithBlob {
             meta: n,
             blob: self.blob,
+            priority: self.priority,
         }
     }
 }
@@ -45,6 +78,7 @@ where
         writeln!(f, "WithBlob {{")?;
         writeln!(f, "  meta: {:?},", self.meta)?;
         writeln!(f, "  blob: --- PAYLOAD ---,")?;
+        writeln!(f, "  priority: {:?},", self.priority)?;
         write!(f, "}}")
     }
 }
@@ -52,6 +86,16 @@ where
 pub struct BlobPayload {
     init_bytes: Option<Vec<u8>>,
     payload: Payload,
+    /// Strips the framing of the chunks of a chunked payload. This is `None` for raw payloads, and
+    /// once a chunked payload has failed, after which nothing more is read.
+    dechunker: Option<Dechunker>,
+    /// Bytes stripped of their framing which haven't been yielded yet.
+    pending: VecDeque<bytes::Bytes>,
+    /// Fires once no bytes have arrived for `stall_after`, at which point the upload is aborted.
+    /// A hung client would otherwise hold on to the request, and whatever it's streaming to,
+    /// until the connection is dropped.
+    stall: Pin<Box<Sleep>>,
+    stall_after: Duration,
 }
 
 // TODO: this is RIDDLED. We have fixed a serious synchronization problem by just setting the
@@ -61,11 +105,73 @@ unsafe impl Send for BlobPayload {}
 unsafe impl Sync for BlobPayload {}
 
 impl BlobPayload {
-    fn new(payload: Payload, init_bytes: &[u8]) -> Self {
+    fn new(payload: Payload, init_bytes: &[u8], version: u8) -> Self {
+        let stall_after = Duration::from_secs(CONFIG.upload_stall_secs);
         Self {
             init_bytes: Some(init_bytes.to_vec()),
             payload,
+            dechunker: (version == BLOB_PROTO_CHUNKED).then(Dechunker::default),
+            pending: VecDeque::new(),
+            stall: Box::pin(tokio::time::sleep(stall_after)),
+            stall_after,
+        }
+    }
+
+    /// Polls the underlying payload, failing with [`WithBlobError::Stalled`] if nothing has
+    /// arrived for too long. Anything arriving counts, keep-alives included. Once stalled, the
+    /// payload is dropped, so nothing more is read.
+    fn poll_payload(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<bytes::Bytes, WithBlobError>>> {
+        match Pin::new(&mut self.payload).poll_next(cx) {
+            Poll::Ready(next) => {
+                self.stall.as_mut().reset(Instant::now() + self.stall_after);
+                Poll::Ready(next.map(|r| r.map_err(WithBlobError::Payload)))
+            }
+            Poll::Pending => {
+                ready!(self.stall.as_mut().poll(cx));
+                self.payload = Payload::None;
+                Poll::Ready(Some(Err(WithBlobError::Stalled)))
+            }
+        }
+    }
+}
+
+/// Strips the framing from the chunks of a chunked payload, as it arrives.
+#[derive(Default)]
+struct Dechunker {
+    /// The bytes of the next chunk's length which have arrived.
+    len_buf: Vec<u8>,
+    /// How many bytes of the current chunk are yet to arrive.
+    remaining: usize,
+}
+
+impl Dechunker {
+    /// The BLOB's bytes in `input`, with the framing removed. Keep-alives are skipped.
+    fn strip(&mut self, mut input: bytes::Bytes) -> Vec<bytes::Bytes> {
+        let mut out = Vec::new();
+        while !input.is_empty() {
+            if self.remaining > 0 {
+                let n = self.remaining.min(input.len());
+                out.push(input.split_to(n));
+                self.remaining -= n;
+            } else {
+                let n = (4 - self.len_buf.len()).min(input.len());
+                self.len_buf.extend_from_slice(&input.split_to(n));
+                if self.len_buf.len() == 4 {
+                    let len: [u8; 4] = self.len_buf[..].try_into().expect("this works");
+                    self.remaining = u32::from_be_bytes(len) as usize;
+                    self.len_buf.clear();
+                }
+            }
         }
+        out
+    }
+
+    /// Whether the payload can end here, i.e. not part way through a chunk.
+    fn at_boundary(&self) -> bool {
+        self.remaining == 0 && self.len_buf.is_empty()
     }
 }
 
@@ -75,34 +181,69 @@ impl Stream for BlobPayload {
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = self.get_mut();
 
-        // First, we have to see whether we've yielded the initial bytes. If not, yield those, and
-        // then move on to yielding from the underlying payload by delegation.
-        if this.init_bytes.is_some() {
-            return Poll::Ready(Some(Ok(this.init_bytes.take().expect("this works").into())));
+        if this.dechunker.is_none() {
+            // First, we have to see whether we've yielded the initial bytes. If not, yield those,
+            // and then move on to yielding from the underlying payload by delegation.
+            if this.init_bytes.is_some() {
+                return Poll::Ready(Some(Ok(this.init_bytes.take().expect("this works").into())));
+            }
+
+            return this.poll_payload(cx);
         }
 
-        Pin::new(&mut this.payload)
-            .poll_next(cx)
-            .map(|p| p.map(|r| r.map_err(|e| WithBlobError::Payload(e))))
+        // A chunked payload is read until some of the BLOB's bytes turn up, since what's read may
+        // only be framing or keep-alives.
+        loop {
+            if let Some(bytes) = this.pending.pop_front() {
+                return Poll::Ready(Some(Ok(bytes)));
+            }
+
+            let next = match this.init_bytes.take() {
+                Some(init_bytes) => Some(Ok(init_bytes.into())),
+                None => ready!(this.poll_payload(cx)),
+            };
+            let dechunker = this.dechunker.as_mut().expect("this is a chunked payload");
+            match next {
+                Some(Ok(bytes)) => this.pending.extend(dechunker.strip(bytes)),
+                Some(Err(e)) => {
+                    this.dechunker = None;
+                    this.payload = Payload::None;
+                    return Poll::Ready(Some(Err(e)));
+                }
+                None if dechunker.at_boundary() => return Poll::Ready(None),
+                None => {
+                    this.dechunker = None;
+                    this.payload = Payload::None;
+                    return Poll::Ready(Some(Err(WithBlobError::UnexpectedEOF)));
+                }
+            }
+        }
     }
 }
 
-/// This future is responsible for accumulating the first 4 bytes of the payload, which are to be
-/// interpreted as the length, in bytes, of the metadata block following.
+/// This future is responsible for accumulating the first 5 bytes of the payload, which are to be
+/// interpreted as the framing version and the length, in bytes, of the metadata block following.
 pub struct BTExtractMetadataFut<M> {
     /// The `Payload` we are reading from actix.
     payload: Payload,
-    /// The buffer we use to accumulate the size of the metadata JSON string in bytes. This is the
-    /// first 4 bytes of the payload.
+    /// Set if the request's `BLOB_PROTO_HEADER` header was missing or unsupported, in which case
+    /// the future fails without reading the payload.
+    proto_error: Option<WithBlobError>,
+    /// The version of the framing the request's `BLOB_PROTO_HEADER` header says it uses.
+    version: u8,
+    /// The buffer we use to accumulate the frame's prefix: the version byte, then the size of the
+    /// metadata JSON string in bytes.
     size_buf: bytes::BytesMut,
     /// The size, in bytes, of the metadata. Before we have determined this value by reading the
-    /// first 4 bytes of the `Payload`, this is `None`. We can rely on the `Some` vs. `None` of
+    /// prefix of the `Payload`, this is `None`. We can rely on the `Some` vs. `None` of
     /// this value to know which phase of decoding we are in.
     metadata_len: Option<usize>,
     /// The amount of metadata we have actually received so far.
     metadata_received: usize,
     /// The buffer we use to accumulate the raw metadata bytes.
     metadata_buf: Vec<u8>,
+    /// The priority hinted at by the request.
+    priority: Priority,
     _phantom: std::marker::PhantomData<M>,
 }
 
@@ -111,6 +252,17 @@ pub enum WithBlobError {
     Payload(PayloadError),
     Deserialize(serde_json::Error),
     UnexpectedEOF,
+    /// No bytes arrived for longer than the upload stall timeout.
+    Stalled,
+    /// The request had no `BLOB_PROTO_HEADER` header.
+    MissingProto,
+    /// The request's `BLOB_PROTO_HEADER` header named a version the server doesn't speak.
+    UnsupportedProto(String),
+    /// The frame's version byte didn't match the `BLOB_PROTO_HEADER` header.
+    FrameVersion {
+        frame: u8,
+        header: u8,
+    },
 }
 
 impl std::fmt::Display for WithBlobError {
@@ -119,6 +271,10 @@ impl std::fmt::Display for WithBlobError {
             WithBlobError::Payload(_) => writeln!(f, "Payload error"),
             WithBlobError::Deserialize(_) => writeln!(f, "Deserialize error"),
             WithBlobError::UnexpectedEOF => writeln!(f, "Unexpected EOF error"),
+            WithBlobError::Stalled => writeln!(f, "Upload stalled"),
+            WithBlobError::MissingProto => writeln!(f, "Missing blob protocol version"),
+            WithBlobError::UnsupportedProto(_) => writeln!(f, "Unsupported blob protocol version"),
+            WithBlobError::FrameVersion { .. } => writeln!(f, "Frame version mismatch"),
         }
     }
 }
@@ -138,12 +294,40 @@ impl From<WithBlobError> for actix_web::Error {
                 actix_web::error::ErrorInternalServerError("error receiving blob")
             }
             WithBlobError::UnexpectedEOF => {
-                actix_web::error::ErrorBadRequest("unexpected end of byte stream")
+                coded(ErrorCode::UnexpectedEof, "unexpected end of byte stream")
             }
-            WithBlobError::Deserialize(e) => actix_web::error::ErrorBadRequest(format!(
-                "metadata deserialization error: {:?}",
-                e
-            )),
+            WithBlobError::Stalled => coded(
+                ErrorCode::UploadStalled,
+                format!(
+                    "no bytes received for {} seconds; upload aborted",
+                    CONFIG.upload_stall_secs
+                ),
+            ),
+            WithBlobError::Deserialize(e) => coded(
+                ErrorCode::InvalidBlobEncoding,
+                format!("metadata deserialization error: {:?}", e),
+            ),
+            WithBlobError::MissingProto => coded(
+                ErrorCode::MissingBlobProto,
+                format!(
+                    "missing {} header; this client is too old to upload blobs, please upgrade it",
+                    BLOB_PROTO_HEADER
+                ),
+            ),
+            WithBlobError::UnsupportedProto(v) => coded(
+                ErrorCode::UnsupportedBlobProto,
+                format!(
+                    "unsupported blob protocol version {:?}; this server speaks versions {} and {}",
+                    v, BLOB_PROTO_RAW, BLOB_PROTO_CHUNKED
+                ),
+            ),
+            WithBlobError::FrameVersion { frame, header } => coded(
+                ErrorCode::FrameVersionMismatch,
+                format!(
+                    "frame is version {} but the {} header says version {}",
+                    frame, BLOB_PROTO_HEADER, header
+                ),
+            ),
         }
     }
 }
@@ -167,6 +351,9 @@ where
         // TODO: what happens if there's an empty payload? This needs to be a gracefully handled
         // error.
         let this = self.get_mut();
+        if let Some(e) = this.proto_error.take() {
+            return Poll::Ready(Err(e));
+        }
         let buf = &mut this.size_buf;
 
         loop {
@@ -177,16 +364,24 @@ where
                     let chunk = chunk?;
 
                     if this.metadata_len == None {
-                        // Here, we are still trying to determine the length of the metadata.
-                        let buf_len = buf.len() + chunk.len();
-                        if buf_len > 4 {
-                            // Here we need to take just enough bytes to finish populating the sentinel
-                            // length byte, and transfer the remaining bytes into the start of `metadata_buf`.
-                            // After that, we need to deserialize the `size_buf` bytes, and set
-                            // `metadata_len` to `Some(n)`.
-                            buf.extend_from_slice(&chunk[..(buf_len - 4)]);
-                            let sentinel: [u8; 4] = buf[..4].try_into().expect("this works");
-                            let rem = &chunk[4..];
+                        // Here, we are still trying to determine the version of the frame and the
+                        // length of the metadata.
+                        let needed = PREFIX_LEN - buf.len();
+                        if chunk.len() > needed {
+                            // Here we need to take just enough bytes to finish populating the prefix,
+                            // and transfer the remaining bytes into the start of `metadata_buf`.
+                            // After that, we need to check the version, deserialize the length, and
+                            // set `metadata_len` to `Some(n)`.
+                            buf.extend_from_slice(&chunk[..needed]);
+                            if buf[0] != this.version {
+                                return Poll::Ready(Err(WithBlobError::FrameVersion {
+                                    frame: buf[0],
+                                    header: this.version,
+                                }));
+                            }
+                            let sentinel: [u8; 4] =
+                                buf[1..PREFIX_LEN].try_into().expect("this works");
+                            let rem = &chunk[needed..];
                             let metadata_len = u32::from_be_bytes(sentinel);
                             this.metadata_len = Some(metadata_len as usize);
 
@@ -207,7 +402,9 @@ where
                                     blob: Some(BlobPayload::new(
                                         this.payload.take(),
                                         first_blob_bytes,
+                                        this.version,
                                     )),
+                                    priority: this.priority,
                                 };
 
                                 return Poll::Ready(Ok(with_blob));
@@ -245,7 +442,12 @@ where
 
                             let with_blob = WithBlob {
                                 meta,
-                                blob: Some(BlobPayload::new(this.payload.take(), first_blob_bytes)),
+                                blob: Some(BlobPayload::new(
+                                    this.payload.take(),
+                                    first_blob_bytes,
+                                    this.version,
+                                )),
+                                priority: this.priority,
                             };
 
                             return Poll::Ready(Ok(with_blob));
@@ -275,18 +477,70 @@ where
     type Future = BTExtractMetadataFut<M>;
 
     #[inline]
-    fn from_request(_req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let version = match req.headers().get(BLOB_PROTO_HEADER) {
+            None => Err(WithBlobError::MissingProto),
+            Some(v) => match v.to_str().ok().and_then(|v| v.trim().parse::<u8>().ok()) {
+                Some(version @ (BLOB_PROTO_RAW | BLOB_PROTO_CHUNKED)) => Ok(version),
+                _ => Err(WithBlobError::UnsupportedProto(
+                    String::from_utf8_lossy(v.as_bytes()).into_owned(),
+                )),
+            },
+        };
+
         BTExtractMetadataFut {
             payload: payload.take(),
+            version: *version.as_ref().unwrap_or(&0),
+            proto_error: version.err(),
             // we know exactly how many bytes we need for this
-            size_buf: bytes::BytesMut::with_capacity(4),
+            size_buf: bytes::BytesMut::with_capacity(PREFIX_LEN),
             // we can avoid an unnecesary allocation by calling `with_capacity(0)`. Once we know
             // the length to expect, we'll call `try_reserve_exact`, and set it to the precise
             // amount we need.
             metadata_buf: Vec::with_capacity(0),
             metadata_len: None,
             metadata_received: 0,
+            priority: Priority::of(req),
             _phantom: std::marker::PhantomData,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn chunk(data: &[u8]) -> Vec<u8> {
+        [&(data.len() as u32).to_be_bytes()[..], data].concat()
+    }
+
+    #[test]
+    fn dechunks_across_reads_and_skips_keep_alives() {
+        let framed = [chunk(b"hello "), chunk(b""), chunk(b""), chunk(b"world")].concat();
+
+        // However the framed bytes are split up as they arrive, only the data comes out.
+        for split in 1..framed.len() {
+            let mut dechunker = Dechunker::default();
+            let mut data = Vec::new();
+            for read in framed.chunks(split) {
+                for bytes in dechunker.strip(Bytes::copy_from_slice(read)) {
+                    data.extend_from_slice(&bytes);
+                }
+            }
+            assert_eq!(data, b"hello world");
+            assert!(dechunker.at_boundary());
+        }
+    }
+
+    #[test]
+    fn notices_truncated_chunks() {
+        let mut dechunker = Dechunker::default();
+        dechunker.strip(Bytes::from(chunk(b"hello")[..7].to_vec()));
+        assert!(!dechunker.at_boundary());
+
+        let mut dechunker = Dechunker::default();
+        dechunker.strip(Bytes::from_static(&[0, 0]));
+        assert!(!dechunker.at_boundary());
+    }
+}
@@ -0,0 +1,121 @@
+//! Postgres notifications, fanned out to whatever in the server is waiting for them.
+//!
+//! One connection listens on every channel in [`CHANNELS`], in the `fanout` job, and each
+//! notification is sent on to the channel's subscribers, e.g. the `listing_cache` job. Features
+//! which wait on changes subscribe here, rather than each holding a connection of their own.
+//!
+//! A subscriber which falls behind by more than the buffer doesn't hold up the others, or the
+//! listener: it's sent [`Event::Missed`] in place of the notifications it missed, as every
+//! subscriber is when the listener loses its connection, and should resync from the database.
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// The channels listened on. Notifications on any other channel aren't seen.
+pub const CHANNELS: [&str; 1] = [crate::cache::CHANNEL];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A notification, with its payload.
+    Notification(String),
+    /// Notifications may have been missed since the last event.
+    Missed,
+}
+
+#[derive(Clone)]
+pub struct Fanout {
+    senders: Arc<HashMap<&'static str, broadcast::Sender<Event>>>,
+}
+
+impl Fanout {
+    /// Each subscriber can fall up to `buffer` events behind before it misses some.
+    pub fn new(buffer: usize) -> Self {
+        let senders = CHANNELS
+            .into_iter()
+            .map(|channel| (channel, broadcast::channel(buffer.max(1)).0))
+            .collect();
+        Self {
+            senders: Arc::new(senders),
+        }
+    }
+
+    /// Subscribes to the events on `channel`, from now on. Panics if `channel` isn't one of
+    /// [`CHANNELS`].
+    pub fn subscribe(&self, channel: &str) -> Subscription {
+        let sender = self
+            .senders
+            .get(channel)
+            .unwrap_or_else(|| panic!("{:?} isn't listened on", channel));
+        Subscription {
+            channel: sender.subscribe(),
+        }
+    }
+
+    /// Sends `event` to `channel`'s subscribers, if it has any.
+    pub fn publish(&self, channel: &str, event: Event) {
+        if let Some(sender) = self.senders.get(channel) {
+            // Fails only if there are no subscribers.
+            let _ = sender.send(event);
+        }
+    }
+
+    /// Tells every subscriber that they may have missed notifications.
+    pub fn missed(&self) {
+        for channel in CHANNELS {
+            self.publish(channel, Event::Missed);
+        }
+    }
+}
+
+pub struct Subscription {
+    channel: broadcast::Receiver<Event>,
+}
+
+impl Subscription {
+    pub async fn recv(&mut self) -> Event {
+        match self.channel.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                log::warn!("subscriber fell {} notifications behind", n);
+                Event::Missed
+            }
+            // The senders live as long as the server.
+            Err(RecvError::Closed) => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::CHANNEL;
+
+    #[actix_rt::test]
+    async fn every_subscriber_is_sent_every_notification() {
+        let fanout = Fanout::new(8);
+        let mut a = fanout.subscribe(CHANNEL);
+        let mut b = fanout.subscribe(CHANNEL);
+        fanout.publish(CHANNEL, Event::Notification("1".to_string()));
+        fanout.publish("unlistened", Event::Notification("2".to_string()));
+        fanout.missed();
+        for subscription in [&mut a, &mut b] {
+            assert_eq!(
+                subscription.recv().await,
+                Event::Notification("1".to_string())
+            );
+            assert_eq!(subscription.recv().await, Event::Missed);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn lagging_subscribers_miss_notifications() {
+        let fanout = Fanout::new(2);
+        let mut slow = fanout.subscribe(CHANNEL);
+        for i in 0..5 {
+            fanout.publish(CHANNEL, Event::Notification(i.to_string()));
+        }
+        assert_eq!(slow.recv().await, Event::Missed);
+        assert_eq!(slow.recv().await, Event::Notification("3".to_string()));
+        assert_eq!(slow.recv().await, Event::Notification("4".to_string()));
+    }
+}
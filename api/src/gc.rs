@@ -0,0 +1,109 @@
+//! Garbage collection of BLOBs which nothing references any more, left behind by deleted evals
+//! and by uploads whose eval was never put. See [`crate::jobs::blob_gc`].
+//!
+//! A BLOB is referenced by the evals, run artifacts, DVC objects and unexpired jobs which point at
+//! its row, and by
+//! any of its owner's evals whose arguments or result mention its content hash (e.g. file
+//! snapshots, and visualised images). Evals under a legal hold can't be deleted, so the BLOBs of
+//! held evals and runs are always referenced, and canaries' BLOBs are kept while they're canaries.
+//! Unreferenced rows are only collected once they're older than a grace period, which gives clients
+//! time to put the eval referencing a BLOB they've just uploaded. A BLOB's object is shared by every row of the same content stored in the same
+//! place, so it's only deleted along with the last of them, and not if it has been written again
+//! within the grace period.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// What a pass of the collector found.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Sweep {
+    /// Unreferenced `blobs` rows.
+    pub blobs: u64,
+    /// Bytes of the objects which no row is left referencing.
+    pub bytes: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    reclaimable_blobs: AtomicU64,
+    reclaimable_bytes: AtomicU64,
+    reclaimed_blobs: AtomicU64,
+    reclaimed_bytes: AtomicU64,
+}
+
+/// What the collector has found and reclaimed since the server started, for `/metrics`.
+#[derive(Clone, Default)]
+pub struct GcStats(Arc<Counters>);
+
+impl GcStats {
+    /// Records a pass of the collector. Nothing is deleted in a dry run, so only what could have
+    /// been reclaimed is recorded.
+    pub fn record(&self, sweep: Sweep, dry_run: bool) {
+        let c = &self.0;
+        c.reclaimable_blobs.store(sweep.blobs, Ordering::Relaxed);
+        c.reclaimable_bytes.store(sweep.bytes, Ordering::Relaxed);
+        if !dry_run {
+            c.reclaimed_blobs.fetch_add(sweep.blobs, Ordering::Relaxed);
+            c.reclaimed_bytes.fetch_add(sweep.bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// What the last pass found could be reclaimed.
+    pub fn reclaimable(&self) -> Sweep {
+        Sweep {
+            blobs: self.0.reclaimable_blobs.load(Ordering::Relaxed),
+            bytes: self.0.reclaimable_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    /// What has been reclaimed since the server started.
+    pub fn reclaimed(&self) -> Sweep {
+        Sweep {
+            blobs: self.0.reclaimed_blobs.load(Ordering::Relaxed),
+            bytes: self.0.reclaimed_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Whether an object last written at `written` (in seconds since the Unix epoch) is past the
+/// grace period at `now`. An object which S3 doesn't say the age of is kept.
+pub fn past_grace(written: Option<i64>, now: i64, grace_days: u64) -> bool {
+    match written {
+        Some(written) => now - written >= grace_days as i64 * 24 * 60 * 60,
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dry_runs_reclaim_nothing() {
+        let stats = GcStats::default();
+        let sweep = Sweep {
+            blobs: 3,
+            bytes: 1024,
+        };
+        stats.record(sweep, true);
+        assert_eq!(stats.reclaimable(), sweep);
+        assert_eq!(stats.reclaimed(), Sweep::default());
+
+        stats.record(sweep, false);
+        stats.record(sweep, false);
+        assert_eq!(
+            stats.reclaimed(),
+            Sweep {
+                blobs: 6,
+                bytes: 2048
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_recent_objects() {
+        let day = 24 * 60 * 60;
+        assert!(past_grace(Some(0), 7 * day, 7));
+        assert!(!past_grace(Some(1), 7 * day, 7));
+        assert!(!past_grace(None, 7 * day, 7));
+    }
+}
@@ -0,0 +1,58 @@
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::alert::{AlertError, AlertRule};
+use crate::persisters::{
+    alert::{AlertRuleDelete, AlertRuleInsert, AlertRulesGet},
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{delete, error, get, put, web, HttpResponse, Result};
+
+impl From<AlertError> for actix_web::Error {
+    fn from(e: AlertError) -> Self {
+        match e {
+            AlertError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            AlertError::NotFound => {
+                coded(ErrorCode::AlertNotFound, "alert rule or project not found")
+            }
+            AlertError::InvalidRule => coded(ErrorCode::InvalidAlertRule, "invalid alert rule"),
+            AlertError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[get("")]
+async fn get(auth: Auth, state: AppState) -> Result<Listing<AlertRule>> {
+    let res = AlertRulesGet {}.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res))
+}
+
+#[put("")]
+async fn put(
+    insert: web::Json<AlertRuleInsert>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<i64>> {
+    let id = insert.into_inner().persist(Some(&auth), &state).await?;
+    Ok(web::Json(id))
+}
+
+#[delete("/{id}")]
+async fn delete(id: web::Path<i64>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    AlertRuleDelete {
+        id: id.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+    cfg.service(put);
+    cfg.service(delete);
+}
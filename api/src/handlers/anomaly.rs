@@ -0,0 +1,51 @@
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::anomaly::{Anomaly, AnomalyError};
+use crate::persisters::{
+    anomaly::{AnomaliesGet, AnomalyAcknowledge},
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{error, get, post, web, HttpResponse, Result};
+
+impl From<AnomalyError> for actix_web::Error {
+    fn from(e: AnomalyError) -> Self {
+        match e {
+            AnomalyError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            AnomalyError::Forbidden => error::ErrorForbidden("admins only"),
+            AnomalyError::NotFound => coded(ErrorCode::AnomalyNotFound, "anomaly not found"),
+            AnomalyError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[get("")]
+async fn list(
+    params: web::Query<AnomaliesGet>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<Anomaly>> {
+    let params = params.into_inner();
+    let limit = params.limit;
+    let res = params.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res).paginated(limit))
+}
+
+#[post("/{id}/acknowledge")]
+async fn acknowledge(id: web::Path<i64>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    AnomalyAcknowledge {
+        id: id.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(list);
+    cfg.service(acknowledge);
+}
@@ -1,8 +1,11 @@
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
 use crate::middlewares::auth::Auth;
 use crate::models::api_key::{ApiKey, ApiKeyError};
-use crate::persisters::{api_key::KeyInsert, Persist};
+use crate::models::capture::KeyCapture;
+use crate::persisters::{api_key::KeyInsert, capture::KeyCaptureUpdate, Persist};
 use crate::state::AppState;
-use actix_web::{error, get, web, Error, Result};
+use actix_web::{error, get, put, web, Error, Result};
 
 impl From<ApiKeyError> for Error {
     fn from(e: ApiKeyError) -> Self {
@@ -10,6 +13,12 @@ impl From<ApiKeyError> for Error {
             ApiKeyError::Unauthorized => {
                 error::ErrorUnauthorized("not authorized to generate new API key")
             }
+            ApiKeyError::Forbidden => error::ErrorForbidden("forbidden by policy"),
+            ApiKeyError::InvalidExchange => coded(
+                ErrorCode::InvalidExchange,
+                "a device name of at most 100 characters, and a project name of at most 100 \
+                characters if any, are required",
+            ),
             _ => error::ErrorInternalServerError("could not generate new API key"),
         }
     }
@@ -43,6 +52,19 @@ async fn generate_new_api_key(
     Ok(api_key.key)
 }
 
+/// Turns capture of the failing requests made with the user's keys with a label on, for a number
+/// of hours, or off, so that problems with how a client encodes them can be reproduced.
+#[put("/capture")]
+async fn put_capture(
+    update: web::Json<KeyCaptureUpdate>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<KeyCapture>> {
+    let res = update.into_inner().persist(Some(&auth), &state).await?;
+    Ok(Listing::new(res))
+}
+
 pub fn init(cfg: &mut web::ServiceConfig) {
     cfg.service(generate_new_api_key);
+    cfg.service(put_capture);
 }
@@ -0,0 +1,64 @@
+//! Admin endpoints for capping the bandwidth of each user's BLOB transfers.
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::bandwidth::{BandwidthError, KeyBandwidth};
+use crate::persisters::{
+    bandwidth::{BandwidthCapsGet, BandwidthCapsUpdate},
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{error, get, put, web, Result};
+use sqlx::types::Uuid;
+
+impl From<BandwidthError> for actix_web::Error {
+    fn from(e: BandwidthError) -> Self {
+        match e {
+            BandwidthError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            BandwidthError::Forbidden => error::ErrorForbidden("admins only"),
+            BandwidthError::NotFound => {
+                coded(ErrorCode::BandwidthKeysNotFound, "no matching api keys")
+            }
+            BandwidthError::InvalidCap => coded(
+                ErrorCode::InvalidBandwidthCap,
+                "bandwidth caps must be positive",
+            ),
+            BandwidthError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[get("/{user_id}")]
+async fn get(
+    user_id: web::Path<Uuid>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<KeyBandwidth>> {
+    let res = BandwidthCapsGet {
+        user_id: user_id.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(Listing::new(res))
+}
+
+#[put("/{user_id}")]
+async fn put(
+    user_id: web::Path<Uuid>,
+    caps: web::Json<BandwidthCapsUpdate>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<KeyBandwidth>> {
+    let mut caps = caps.into_inner();
+    caps.user_id = user_id.into_inner();
+    let res = caps.persist(Some(&auth), &state).await?;
+    Ok(Listing::new(res))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+    cfg.service(put);
+}
@@ -1,17 +1,55 @@
+use crate::envelope::{etag_matches, Listing};
+use crate::error_code::{coded, ErrorCode};
 use crate::extractors::with_blob::WithBlob;
 use crate::middlewares::auth::Auth;
-use crate::persisters::blob::BlobInsert;
+use crate::models::blob_stats::{BlobStats, BlobStatsError};
+use crate::models::tensor::TensorSummaries;
+use crate::persisters::blob::{
+    resolve_address, BlobDiff, BlobError, BlobInsert, BlobPatchInsert, BlobStatsGet, BlobSummary,
+    BlobUsage, BlobUsageGet, BlobsGet, PresignedBlobGet, PresignedDownload, TensorSummariesGet,
+    VerifiedBlobGet, MAX_BLOB_LISTING,
+};
+use crate::persisters::s3store::StoreError;
 use crate::persisters::{Persist, Query};
+use crate::priority::Priority;
+use crate::resume::{range_start, RESUME_TOKEN_HEADER};
 use crate::state::AppState;
 use actix_web::{
-    error, get, head, put,
+    error, get, head,
+    http::header,
+    put,
     web::{self, Path},
-    Error, HttpResponse,
+    Error, HttpRequest, HttpResponse,
 };
+use sqlx::types::Uuid;
+
+impl From<BlobStatsError> for Error {
+    fn from(e: BlobStatsError) -> Self {
+        match e {
+            BlobStatsError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            BlobStatsError::NotFound => coded(ErrorCode::BlobNotFound, "blob not found"),
+            BlobStatsError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+/// The header a BLOB's label is sent in.
+pub const BLOB_LABEL_HEADER: &str = "x-hitsave-blob-label";
 
 #[derive(Deserialize, Debug)]
 pub struct BlobParams {
     pub content_hash: String,
+    #[serde(skip)]
+    pub priority: Priority,
+    /// The `Range` header, if only part of the BLOB was requested.
+    #[serde(skip)]
+    pub range: Option<String>,
+    /// The address the request came from, for checking downloads of canaries.
+    #[serde(skip)]
+    pub ip: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -19,39 +57,304 @@ pub struct BlobParamsHead {
     pub content_hash: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct BlobPatch {
+    /// The BLOB the patch applies to, by its content hash or by its digest under another
+    /// algorithm (see [`crate::hashing`]).
+    pub base_hash: String,
+    /// The hash of the patched BLOB.
+    pub content_hash: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DownloadParams {
+    /// Whether to verify the BLOB against its hash as it's downloaded.
+    #[serde(default)]
+    pub verify: bool,
+}
+
+/// A BLOB's ETag. BLOBs are addressed by their content, so their hashes are strong ETags.
+fn blob_etag(content_hash: &str) -> String {
+    format!("\"{}\"", content_hash)
+}
+
+/// Whether the request's `If-None-Match` header matches `etag`, i.e. the client has the BLOB.
+fn unchanged(req: &HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get_all(header::IF_NONE_MATCH)
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| etag_matches(v, etag))
+}
+
+/// Responds to a client which already has a BLOB, once it's been checked that the user owns it, so
+/// that whether a BLOB is stored isn't given away to those who don't.
+async fn not_modified(
+    content_hash: String,
+    etag: String,
+    auth: &Auth,
+    state: &AppState,
+) -> Result<HttpResponse, Error> {
+    BlobParamsHead { content_hash }
+        .fetch(Some(auth), state)
+        .await?;
+    Ok(HttpResponse::NotModified()
+        .insert_header((header::ETAG, etag))
+        .finish())
+}
+
+fn with_etag(mut res: HttpResponse, etag: &str) -> HttpResponse {
+    if let Ok(value) = header::HeaderValue::from_str(etag) {
+        res.headers_mut().insert(header::ETAG, value);
+    }
+    res
+}
+
+/// Downloads a BLOB, or the part of it asked for by a `Range` header. A verified download is sent
+/// with a resume token, which the client presents, along with a `Range` header for the rest of the
+/// BLOB, to resume the download if it's dropped. Clients which send the BLOB's ETag in
+/// `If-None-Match` are told they have it already (304), rather than sent it again. A BLOB can be
+/// addressed by its content hash, or as `<algorithm>:<digest>` by its digest under another hash
+/// algorithm, while BLOBs are being migrated to it; see [`crate::hashing`]. While the server is
+/// serving as many downloads as it can, it answers 503, with a `Retry-After`. When BLOBs are served
+/// from a CDN, an unverified download is redirected (302) to a signed URL for it there instead; see
+/// [`crate::cdn`].
 #[get("/{content_hash}")]
 async fn get_blob(
-    content_hash: Path<BlobParams>,
+    mut content_hash: Path<BlobParams>,
+    params: web::Query<DownloadParams>,
+    priority: Priority,
+    req: HttpRequest,
     auth: Auth,
     state: AppState,
 ) -> Result<HttpResponse, Error> {
-    let blob = content_hash.fetch(Some(&auth), &state).await?;
-    Ok(blob)
+    content_hash.content_hash = resolve_address(&state.db_conn, &content_hash.content_hash).await?;
+    let etag = blob_etag(&content_hash.content_hash);
+    if unchanged(&req, &etag) {
+        let content_hash = content_hash.into_inner().content_hash;
+        return not_modified(content_hash, etag, &auth, &state).await;
+    }
+    content_hash.priority = priority;
+    let ip = req
+        .connection_info()
+        .realip_remote_addr()
+        .map(str::to_string);
+    let resume_token = req
+        .headers()
+        .get(RESUME_TOKEN_HEADER)
+        .map(|v| {
+            v.to_str()
+                .ok()
+                .and_then(|t| t.parse::<Uuid>().ok())
+                .ok_or(BlobError::InvalidResume)
+        })
+        .transpose()?;
+    if !params.verify && resume_token.is_none() {
+        content_hash.range = req
+            .headers()
+            .get(header::RANGE)
+            .map(|v| {
+                v.to_str()
+                    .map(str::to_string)
+                    .map_err(|_| BlobError::InvalidRange)
+            })
+            .transpose()?;
+        content_hash.ip = ip;
+        let blob = content_hash.fetch(Some(&auth), &state).await?;
+        return Ok(with_etag(blob, &etag));
+    }
+
+    let from = req
+        .headers()
+        .get(header::RANGE)
+        .map(|v| {
+            v.to_str()
+                .ok()
+                .and_then(range_start)
+                .ok_or(BlobError::InvalidResume)
+        })
+        .transpose()?
+        .unwrap_or(0);
+    let blob = VerifiedBlobGet {
+        content_hash: content_hash.into_inner().content_hash,
+        resume: resume_token.map(|t| (t, from)),
+        priority,
+        ip,
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(with_etag(blob, &etag))
+}
+
+/// Returns a short-lived URL to download a BLOB straight from storage, for clients which would
+/// rather not have it sent through the server.
+#[get("/{content_hash}/presign")]
+async fn presign_blob(
+    content_hash: Path<BlobParamsHead>,
+    req: HttpRequest,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<PresignedDownload>, Error> {
+    let ip = req
+        .connection_info()
+        .realip_remote_addr()
+        .map(str::to_string);
+    let res = PresignedBlobGet {
+        content_hash: resolve_address(&state.db_conn, &content_hash.content_hash).await?,
+        ip,
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(web::Json(res))
 }
 
+/// Checks that the user has a BLOB, sending what's recorded of it as headers: its length, the
+/// kind of content it is as its `Content-Type`, and its label, form-urlencoded, as
+/// `x-hitsave-blob-label`, along with its ETag, which `If-None-Match` is checked against as for a
+/// `GET`. The BLOB can be addressed as it can for a `GET`.
 #[head("/{content_hash}")]
 async fn head_blob(
     content_hash: Path<BlobParamsHead>,
+    req: HttpRequest,
     auth: Auth,
     state: AppState,
 ) -> Result<HttpResponse, Error> {
-    let _blob = content_hash.fetch(Some(&auth), &state).await?;
-    Ok(HttpResponse::Ok().into())
+    let content_hash = resolve_address(&state.db_conn, &content_hash.content_hash).await?;
+    let etag = blob_etag(&content_hash);
+    if unchanged(&req, &etag) {
+        return not_modified(content_hash, etag, &auth, &state).await;
+    }
+    let blob = BlobParamsHead { content_hash }
+        .fetch(Some(&auth), &state)
+        .await?;
+    let mut res = HttpResponse::Ok();
+    res.insert_header((header::ETAG, etag));
+    if let Some(mime_type) = blob.mime_type {
+        res.insert_header((header::CONTENT_TYPE, mime_type));
+    }
+    if let Some(label) = blob.label {
+        let label = url::form_urlencoded::byte_serialize(label.as_bytes()).collect::<String>();
+        res.insert_header((BLOB_LABEL_HEADER, label));
+    }
+    if let Some(length) = blob.content_length {
+        res.no_chunking(length as u64);
+    }
+    Ok(res.finish())
 }
 
+/// Lists the user's BLOBs, newest first, for browsing what they store.
+#[get("")]
+async fn list_blobs(
+    params: web::Query<BlobsGet>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<BlobSummary>, Error> {
+    let params = params.into_inner();
+    let limit = params.limit.clamp(1, MAX_BLOB_LISTING);
+    let res = params.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res).paginated(limit))
+}
+
+/// Reports how many BLOBs the user stores and how many bytes they come to, in total and by the
+/// month they were created in.
+#[get("/usage")]
+async fn get_usage(auth: Auth, state: AppState) -> Result<web::Json<BlobUsage>, Error> {
+    let res = BlobUsageGet {}.fetch(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+/// Uploads a BLOB. Its metadata may give its mime type and a label, which `HEAD /blob/{hash}`
+/// returns.
 #[put("")]
 async fn put_blob(
     insert: WithBlob<BlobInsert>,
     auth: Auth,
     state: AppState,
 ) -> Result<String, error::Error> {
+    insert.meta.check_metadata()?;
     let res = insert.persist(Some(&auth), &state).await?;
 
     Ok(res.to_string())
 }
 
+/// Returns a binary diff, in bsdiff format, which turns the BLOB `from` into the BLOB `to`.
+#[get("/{from}/diff/{to}")]
+async fn get_diff(
+    hashes: Path<(String, String)>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse, Error> {
+    let (from_hash, to_hash) = hashes.into_inner();
+    let patch = BlobDiff { from_hash, to_hash }
+        .fetch(Some(&auth), &state)
+        .await?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .body(patch))
+}
+
+/// Uploads a BLOB as a binary diff against a BLOB which has already been uploaded. The payload is
+/// the patch, as returned by `GET /blob/{from}/diff/{to}`. The patched BLOB counts against the
+/// storage quota as if it had been uploaded whole.
+#[put("/patch")]
+async fn put_patch(
+    insert: WithBlob<BlobPatch>,
+    auth: Auth,
+    state: AppState,
+) -> Result<String, error::Error> {
+    let patch = insert.blob.ok_or(StoreError::MissingPayload)?;
+    let res = BlobPatchInsert {
+        base_hash: insert.meta.base_hash,
+        content_hash: insert.meta.content_hash,
+        patch,
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+
+    Ok(res.to_string())
+}
+
+/// Returns the row count and column statistics of a tabular BLOB (Parquet, Arrow or CSV), once the
+/// background job has computed them.
+#[get("/{content_hash}/stats")]
+async fn get_stats(
+    content_hash: Path<String>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<BlobStats>, Error> {
+    let res = BlobStatsGet {
+        content_hash: content_hash.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(web::Json(res))
+}
+
+/// Returns the dtype, shape and value statistics of each tensor in a tensor artifact (numpy or
+/// safetensors), once the background job has summarised it.
+#[get("/{content_hash}/tensors")]
+async fn get_tensors(
+    content_hash: Path<String>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<TensorSummaries>, Error> {
+    let res = TensorSummariesGet {
+        content_hash: content_hash.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(web::Json(res))
+}
+
 pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_stats);
+    cfg.service(get_tensors);
+    cfg.service(get_diff);
+    cfg.service(put_patch);
+    cfg.service(presign_blob);
+    // Before `get_blob`, which would take `usage` for a content hash.
+    cfg.service(get_usage);
     cfg.service(get_blob);
     cfg.service(head_blob);
     cfg.service(put_blob);
+    cfg.service(list_blobs);
 }
@@ -0,0 +1,74 @@
+//! Admin endpoints for verifying BLOB metadata against the objects stored in S3.
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
+use crate::jobs;
+use crate::middlewares::auth::Auth;
+use crate::models::blob_backfill::{BackfillError, BackfillStatus, Discrepancy};
+use crate::persisters::{
+    blob_backfill::{BackfillStart, BackfillStatusGet, DiscrepanciesGet, DiscrepancyResolve},
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{error, get, post, web, HttpResponse, Result};
+
+impl From<BackfillError> for actix_web::Error {
+    fn from(e: BackfillError) -> Self {
+        match e {
+            BackfillError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            BackfillError::Forbidden => error::ErrorForbidden("admins only"),
+            BackfillError::NotFound => {
+                coded(ErrorCode::DiscrepancyNotFound, "discrepancy not found")
+            }
+            BackfillError::AlreadyRunning => {
+                coded(ErrorCode::BackfillRunning, "backfill is already running")
+            }
+            BackfillError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+/// Starts verifying the hash and length of every unverified BLOB, in the background.
+#[post("/backfill")]
+async fn start(auth: Auth, state: AppState) -> Result<HttpResponse> {
+    BackfillStart {}.persist(Some(&auth), &state).await?;
+    actix_rt::spawn(jobs::blob_backfill::run(state.get_ref().clone()));
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[get("/backfill")]
+async fn status(auth: Auth, state: AppState) -> Result<web::Json<BackfillStatus>> {
+    let res = BackfillStatusGet {}.fetch(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+#[get("/discrepancies")]
+async fn discrepancies(
+    params: web::Query<DiscrepanciesGet>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<Discrepancy>> {
+    let params = params.into_inner();
+    let limit = params.limit;
+    let res = params.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res).paginated(limit))
+}
+
+#[post("/discrepancies/{id}/resolve")]
+async fn resolve(id: web::Path<i64>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    DiscrepancyResolve {
+        id: id.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(start);
+    cfg.service(status);
+    cfg.service(discrepancies);
+    cfg.service(resolve);
+}
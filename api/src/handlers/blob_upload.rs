@@ -0,0 +1,125 @@
+//! Uploads of BLOBs in parts, for clients which would rather resume an interrupted upload than
+//! send the whole BLOB again through `PUT /blob`. See [`crate::persisters::blob_upload`].
+use crate::error_code::{coded, coded_builder, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::blob_upload::{BlobUpload, BlobUploadError, UPLOAD_PART_SIZE};
+use crate::persisters::bandwidth::bucket_for;
+use crate::persisters::{
+    blob_upload::{BlobUploadComplete, BlobUploadGet, BlobUploadPart, BlobUploadStart},
+    Persist, Query,
+};
+use crate::priority::Priority;
+use crate::state::AppState;
+use crate::throttle::Direction;
+use actix_web::{error, get, post, put, web, Result};
+use sqlx::types::Uuid;
+
+impl From<BlobUploadError> for actix_web::Error {
+    fn from(e: BlobUploadError) -> Self {
+        // Clients read which part to send next from a JSON body.
+        let next_part_conflict =
+            |code, message: &'static str, next_part: i32| -> actix_web::Error {
+                let body = serde_json::json!({
+                    "error": "unexpected_part",
+                    "next_part": next_part,
+                    "message": message,
+                });
+                error::InternalError::from_response(message, coded_builder(code).json(body)).into()
+            };
+        match e {
+            BlobUploadError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            BlobUploadError::NotFound => {
+                coded(ErrorCode::UploadNotFound, "upload not found or expired")
+            }
+            BlobUploadError::InvalidHash => coded(ErrorCode::InvalidHash, "invalid hash"),
+            BlobUploadError::InvalidLength => coded(
+                ErrorCode::UploadTooLarge,
+                "blob is too large to upload in parts",
+            ),
+            BlobUploadError::InvalidPart => coded(ErrorCode::InvalidPart, "invalid part"),
+            BlobUploadError::OutOfOrder { next_part } => next_part_conflict(
+                ErrorCode::PartOutOfOrder,
+                "parts must be sent in order",
+                next_part,
+            ),
+            BlobUploadError::Incomplete { next_part } => next_part_conflict(
+                ErrorCode::UploadIncomplete,
+                "upload has parts still to send",
+                next_part,
+            ),
+            BlobUploadError::Store(e) => e.into(),
+            BlobUploadError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+/// Starts an upload in parts. If the user already has an upload of the BLOB under way, that's
+/// returned instead, so that a client which lost track of its upload can pick it up again.
+#[post("/start")]
+async fn start(
+    insert: web::Json<BlobUploadStart>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<BlobUpload>> {
+    let res = insert.into_inner().persist(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+/// Returns how far an upload has got, so that a client can resume it from the next part.
+#[get("/{id}")]
+async fn status(id: web::Path<Uuid>, auth: Auth, state: AppState) -> Result<web::Json<BlobUpload>> {
+    let res = BlobUploadGet {
+        id: id.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(web::Json(res))
+}
+
+/// Receives a part of an upload. Parts are numbered from 1, and each but the last is `part_size`
+/// bytes long.
+#[put("/{id}/part/{part_number}")]
+async fn put_part(
+    path: web::Path<(Uuid, i32)>,
+    body: web::Bytes,
+    priority: Priority,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<BlobUpload>> {
+    // The part is read in full before it's stored, so it's throttled once it has been.
+    if let Some(bucket) = bucket_for(&auth, &state, Direction::Upload, priority).await {
+        bucket.wait(body.len()).await;
+    }
+    let (id, part_number) = path.into_inner();
+    let res = BlobUploadPart {
+        id,
+        part_number,
+        bytes: body,
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(web::Json(res))
+}
+
+/// Completes an upload once all of its parts are received, storing the BLOB. Returns the BLOB's
+/// id, as `PUT /blob` does.
+#[post("/{id}/complete")]
+async fn complete(id: web::Path<Uuid>, auth: Auth, state: AppState) -> Result<String> {
+    let res = BlobUploadComplete {
+        id: id.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(res.to_string())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::PayloadConfig::new(UPLOAD_PART_SIZE as usize));
+    cfg.service(start);
+    cfg.service(status);
+    cfg.service(put_part);
+    cfg.service(complete);
+}
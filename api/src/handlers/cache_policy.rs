@@ -0,0 +1,72 @@
+//! Per-function caching policies, which clients fetch before evaluating functions so that the
+//! server decides how each one is cached. These share the `/policy` scope with authorization
+//! policies, and are registered ahead of them so that `/policy/function` isn't taken for an org id.
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::cache_policy::{CachePolicy, CachePolicyError};
+use crate::persisters::{
+    cache_policy::{CachePoliciesGet, CachePolicyDelete, CachePolicyPut},
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{delete, error, get, put, web, HttpResponse, Result};
+
+impl From<CachePolicyError> for actix_web::Error {
+    fn from(e: CachePolicyError) -> Self {
+        match e {
+            CachePolicyError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            CachePolicyError::Forbidden => error::ErrorForbidden("forbidden by policy"),
+            CachePolicyError::NotFound => coded(
+                ErrorCode::CachePolicyNotFound,
+                "function has no cache policy",
+            ),
+            CachePolicyError::InvalidQuery => {
+                coded(ErrorCode::InvalidFunctionKeys, "invalid function keys")
+            }
+            CachePolicyError::InvalidPolicy => {
+                coded(ErrorCode::InvalidCachePolicy, "invalid cache policy")
+            }
+            CachePolicyError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+/// Returns the caching policies of a batch of functions, leaving out those which have none.
+#[get("")]
+async fn get(
+    params: web::Query<CachePoliciesGet>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<Vec<CachePolicy>>> {
+    let res = params.into_inner().fetch(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+#[put("/function")]
+async fn put(
+    policy: web::Json<CachePolicyPut>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<CachePolicy>> {
+    let res = policy.into_inner().persist(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+#[delete("/function")]
+async fn delete(
+    params: web::Query<CachePolicyDelete>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    params.into_inner().persist(Some(&auth), &state).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+    cfg.service(put);
+    cfg.service(delete);
+}
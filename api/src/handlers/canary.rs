@@ -0,0 +1,90 @@
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::canary::{Canary, CanaryError, CanaryTrip};
+use crate::persisters::{
+    canary::{CanariesGet, CanaryDelete, CanaryInsert, CanarySpec, CanaryTripsGet, TripsParams},
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{delete, error, get, post, web, HttpResponse, Result};
+
+impl From<CanaryError> for actix_web::Error {
+    fn from(e: CanaryError) -> Self {
+        match e {
+            CanaryError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            CanaryError::NotFound => coded(ErrorCode::CanaryNotFound, "org or canary not found"),
+            CanaryError::InvalidCanary => coded(
+                ErrorCode::InvalidCanary,
+                "a canary needs a label, and its networks must be valid CIDRs",
+            ),
+            CanaryError::Store(e) => e.into(),
+            CanaryError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[get("/{org}")]
+async fn get(org: web::Path<String>, auth: Auth, state: AppState) -> Result<Listing<Canary>> {
+    let res = CanariesGet {
+        org: org.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(Listing::new(res))
+}
+
+#[post("/{org}")]
+async fn post(
+    org: web::Path<String>,
+    spec: web::Json<CanarySpec>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let canary = CanaryInsert {
+        org: org.into_inner(),
+        spec: spec.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::Created().json(canary))
+}
+
+#[delete("/{org}/{id}")]
+async fn remove(
+    path: web::Path<(String, i64)>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let (org, id) = path.into_inner();
+    CanaryDelete { org, id }
+        .persist(Some(&auth), &state)
+        .await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[get("/{org}/{id}/trips")]
+async fn trips(
+    path: web::Path<(String, i64)>,
+    params: web::Query<TripsParams>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<CanaryTrip>> {
+    let (org, id) = path.into_inner();
+    let params = params.into_inner();
+    let limit = params.limit;
+    let res = CanaryTripsGet { org, id, params }
+        .fetch(Some(&auth), &state)
+        .await?;
+    Ok(Listing::new(res).paginated(limit))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+    cfg.service(post);
+    cfg.service(remove);
+    cfg.service(trips);
+}
@@ -0,0 +1,20 @@
+//! What the server supports, for clients to check rather than assume. It isn't authenticated.
+use crate::error_code::{catalog, ErrorCodeEntry};
+use actix_web::{get, web};
+
+#[derive(Serialize, Debug)]
+pub struct Capabilities {
+    /// The codes error responses carry, with what they mean. See [`crate::error_code`].
+    pub error_codes: Vec<ErrorCodeEntry>,
+}
+
+#[get("/capabilities")]
+async fn get() -> web::Json<Capabilities> {
+    web::Json(Capabilities {
+        error_codes: catalog(),
+    })
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+}
@@ -0,0 +1,69 @@
+//! Admin endpoints for reading requests captured from API keys opted into capture. Keys are opted
+//! in by their owners, with `PUT /api_key/capture`.
+use crate::capture::MAX_CAPTURE_HOURS;
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::capture::{Capture, CaptureError};
+use crate::persisters::{
+    capture::{CaptureBodyGet, CapturesGet},
+    Query,
+};
+use crate::state::AppState;
+use actix_web::{error, get, web, HttpResponse, Result};
+
+impl From<CaptureError> for actix_web::Error {
+    fn from(e: CaptureError) -> Self {
+        match e {
+            CaptureError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            CaptureError::Forbidden => error::ErrorForbidden("admins only"),
+            CaptureError::NotFound => coded(ErrorCode::CaptureNotFound, "not found"),
+            CaptureError::Disabled => {
+                coded(ErrorCode::CaptureDisabled, "request capture is not enabled")
+            }
+            CaptureError::InvalidDuration => coded(
+                ErrorCode::InvalidCaptureDuration,
+                format!(
+                    "capture can be turned on for between 1 and {} hours",
+                    MAX_CAPTURE_HOURS
+                ),
+            ),
+            CaptureError::Seal => {
+                log::error!("could not seal or open captured request");
+                error::ErrorInternalServerError("unknown error")
+            }
+            CaptureError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[get("")]
+async fn get(
+    query: web::Query<CapturesGet>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<Capture>> {
+    let res = query.into_inner().fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res))
+}
+
+/// The captured bytes of a request's body, as the client sent them.
+#[get("/{id}/body")]
+async fn get_body(id: web::Path<i64>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    let (content_type, body) = CaptureBodyGet {
+        id: id.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::Ok()
+        .content_type(content_type.unwrap_or_else(|| "application/octet-stream".to_string()))
+        .body(body))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+    cfg.service(get_body);
+}
@@ -0,0 +1,63 @@
+//! The change feed of the user's eval keys, which local client caches follow to stay coherent
+//! with the server without listing every eval again.
+//!
+//! A client lists its evals in full once, starting from the cursor given by `GET /changes/head`,
+//! then follows `GET /changes?since=<cursor>`, using the id of the last change it saw as the next
+//! cursor. Once changes after its cursor have been pruned, it's told its cursor has expired, and
+//! starts again from a full listing.
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::change::{ChangeError, ChangesHead, EvalChange, MAX_CHANGES};
+use crate::persisters::{
+    change::{ChangesGet, ChangesHeadGet},
+    Query,
+};
+use crate::state::AppState;
+use actix_web::{error, get, web, Result};
+
+impl From<ChangeError> for actix_web::Error {
+    fn from(e: ChangeError) -> Self {
+        match e {
+            ChangeError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            ChangeError::Forbidden => error::ErrorForbidden("forbidden by policy"),
+            ChangeError::CursorExpired => coded(
+                ErrorCode::CursorExpired,
+                "changes since the cursor have been pruned; list evals again, from the cursor \
+                 given by /changes/head",
+            ),
+            ChangeError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[get("")]
+async fn list(
+    params: web::Query<ChangesGet>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<EvalChange>> {
+    let params = params.into_inner();
+    let limit = params.limit.clamp(1, MAX_CHANGES);
+    let requested = params.limit;
+    let res = params.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res).paginated(limit).warn_if(
+        requested > limit,
+        "limit_reduced",
+        format!("limit reduced to {}", limit),
+    ))
+}
+
+#[get("/head")]
+async fn head(auth: Auth, state: AppState) -> Result<web::Json<ChangesHead>> {
+    let res = ChangesHeadGet.fetch(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(list);
+    cfg.service(head);
+}
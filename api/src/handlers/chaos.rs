@@ -0,0 +1,63 @@
+//! Admin endpoints injecting faults into the database and BLOB storage. These are only served when
+//! the server is built with the `chaos` feature.
+use crate::chaos::{self, ChaosError, Fault, FaultUpdate, Layer};
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::state::AppState;
+use actix_web::{delete, error, get, put, web, HttpResponse, Result};
+
+impl From<ChaosError> for actix_web::Error {
+    fn from(e: ChaosError) -> Self {
+        match e {
+            ChaosError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            ChaosError::Forbidden => error::ErrorForbidden("admins only"),
+            ChaosError::Disabled => {
+                coded(ErrorCode::ChaosDisabled, "fault injection is not enabled")
+            }
+            ChaosError::InvalidFault => coded(
+                ErrorCode::InvalidFault,
+                "probabilities must be between 0 and 1, and the duration at most a day",
+            ),
+            ChaosError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ClearQuery {
+    layer: Option<Layer>,
+}
+
+#[get("")]
+async fn get(auth: Auth, state: AppState) -> Result<Listing<Fault>> {
+    let res = chaos::faults(Some(&auth), &state).await?;
+    Ok(Listing::new(res))
+}
+
+#[put("")]
+async fn put(
+    auth: Auth,
+    state: AppState,
+    update: web::Json<FaultUpdate>,
+) -> Result<web::Json<Fault>> {
+    let res = chaos::set(Some(&auth), &state, update.into_inner()).await?;
+    Ok(web::Json(res))
+}
+
+#[delete("")]
+async fn clear(auth: Auth, state: AppState, query: web::Query<ClearQuery>) -> Result<HttpResponse> {
+    chaos::clear(Some(&auth), &state, query.into_inner().layer).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    if cfg!(feature = "chaos") {
+        cfg.service(get);
+        cfg.service(put);
+        cfg.service(clear);
+    }
+}
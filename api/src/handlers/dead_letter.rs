@@ -0,0 +1,92 @@
+//! Admin endpoints for inspecting dead letters, and re-driving or dismissing them. See
+//! [`crate::persisters::dead_letter`].
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::dead_letter::{DeadLetter, DeadLetterError};
+use crate::persisters::{
+    dead_letter::{DeadLetterDismiss, DeadLetterGet, DeadLetterRedrive, DeadLettersGet},
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{error, get, post, web, HttpResponse, Result};
+
+impl From<DeadLetterError> for actix_web::Error {
+    fn from(e: DeadLetterError) -> Self {
+        match e {
+            DeadLetterError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            DeadLetterError::Forbidden => error::ErrorForbidden("admins only"),
+            DeadLetterError::NotFound => {
+                coded(ErrorCode::DeadLetterNotFound, "dead letter not found")
+            }
+            DeadLetterError::Resolved => coded(
+                ErrorCode::DeadLetterResolved,
+                "dead letter has already been re-driven or dismissed",
+            ),
+            DeadLetterError::NotStored => coded(
+                ErrorCode::DeadLetterNotStored,
+                "blob is not stored, so there is nothing to re-drive",
+            ),
+            DeadLetterError::RunDeleted => coded(
+                ErrorCode::DeadLetterRunDeleted,
+                "the artifact's run has been deleted; dismiss instead",
+            ),
+            DeadLetterError::Store(e) => e.into(),
+            DeadLetterError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[get("")]
+async fn list(
+    params: web::Query<DeadLettersGet>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<DeadLetter>> {
+    let params = params.into_inner();
+    let limit = params.limit;
+    let res = params.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res).paginated(limit))
+}
+
+#[get("/{id}")]
+async fn get(id: web::Path<i64>, auth: Auth, state: AppState) -> Result<web::Json<DeadLetter>> {
+    let res = DeadLetterGet {
+        id: id.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(web::Json(res))
+}
+
+/// Finishes what the dead letter's persist was doing. If it fails again, the dead letter stays
+/// open with the new error.
+#[post("/{id}/redrive")]
+async fn redrive(id: web::Path<i64>, auth: Auth, state: AppState) -> Result<web::Json<DeadLetter>> {
+    let res = DeadLetterRedrive {
+        id: id.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(web::Json(res))
+}
+
+#[post("/{id}/dismiss")]
+async fn dismiss(id: web::Path<i64>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    DeadLetterDismiss {
+        id: id.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(list);
+    cfg.service(get);
+    cfg.service(redrive);
+    cfg.service(dismiss);
+}
@@ -0,0 +1,87 @@
+//! Endpoints compatible with DVC's HTTP remote, so that `/dvc` can be used as a DVC remote cache:
+//!
+//! ```text
+//! dvc remote add -d hitsave https://api.hitsave.io/dvc
+//! dvc remote modify hitsave auth basic
+//! dvc remote modify --local hitsave password <API key>
+//! ```
+//!
+//! Both the DVC 3 (`files/md5/ab/cdef...`) and DVC 2 (`ab/cdef...`) cache layouts are served.
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::dvc::{object_md5, DvcError};
+use crate::persisters::{
+    dvc::{DvcObjectGet, DvcObjectHead, DvcObjectPut},
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{body::BodyStream, error, web, HttpResponse, Result};
+
+/// Objects are received in full before being stored, so they are limited in size.
+const MAX_OBJECT_SIZE: usize = 1 << 30;
+
+impl From<DvcError> for actix_web::Error {
+    fn from(e: DvcError) -> Self {
+        match e {
+            DvcError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            DvcError::NotFound => coded(ErrorCode::DvcObjectNotFound, "object not found"),
+            DvcError::InvalidPath => coded(ErrorCode::InvalidDvcPath, "invalid object path"),
+            DvcError::Store(e) => e.into(),
+            DvcError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ObjectPath {
+    prefix: String,
+    rest: String,
+}
+
+impl ObjectPath {
+    fn md5(&self) -> Result<String, DvcError> {
+        object_md5(&self.prefix, &self.rest).ok_or(DvcError::InvalidPath)
+    }
+}
+
+async fn head(path: web::Path<ObjectPath>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    let md5 = path.md5()?;
+    DvcObjectHead { md5 }.fetch(Some(&auth), &state).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+async fn get(path: web::Path<ObjectPath>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    let md5 = path.md5()?;
+    let byte_stream = DvcObjectGet { md5 }.fetch(Some(&auth), &state).await?;
+    Ok(HttpResponse::Ok().body(BodyStream::new(byte_stream)))
+}
+
+async fn upload(
+    path: web::Path<ObjectPath>,
+    body: web::Bytes,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let md5 = path.md5()?;
+    DvcObjectPut { md5, bytes: body }
+        .persist(Some(&auth), &state)
+        .await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::PayloadConfig::new(MAX_OBJECT_SIZE));
+    // DVC uploads with POST by default, or PUT if the remote's `method` is set to it.
+    for path in ["/files/md5/{prefix}/{rest}", "/{prefix}/{rest}"] {
+        cfg.service(
+            web::resource(path)
+                .route(web::head().to(head))
+                .route(web::get().to(get))
+                .route(web::post().to(upload))
+                .route(web::put().to(upload)),
+        );
+    }
+}
@@ -1,21 +1,111 @@
+use crate::canonical::{canonical_args, CanonicalArgs};
+use crate::envelope::Listing;
+use crate::error_code::{coded, coded_builder, ErrorCode};
+use crate::extractors::with_blob::WithBlob;
 use crate::middlewares::auth::Auth;
-use crate::models::eval::{Eval, EvalError};
-use crate::persisters::{eval::EvalInsert, Persist, Query};
-use crate::state::AppState;
-use actix_web::{error, get, put, web, Result};
+use crate::models::eval::{
+    Eval, EvalError, EvalImport, EvalPut, FnStatus, FnUsage, Resolution, SimilarEval, Suggestion,
+    IMPORT_CHUNK_ROWS, MAX_SIMILAR, MAX_SUGGESTIONS,
+};
+use crate::persisters::{
+    blob::{BlobError, EvalPayloadGet},
+    eval::{
+        EvalDelete, EvalImportChunk, EvalImportCreate, EvalImportFail, EvalImportFinish,
+        EvalImportGet, EvalInsert, EvalMultiInsert, EvalResolve, ReportBatch, SimilarEvalsGet,
+        SuggestionsGet,
+    },
+    Persist, Query,
+};
+use crate::priority::Priority;
+use crate::state::{AppState, State};
+use actix_web::{
+    delete, error, get, http::header, post, put, web, HttpRequest, HttpResponse, Result,
+};
+use bytes::BytesMut;
+use futures::StreamExt;
+use sqlx::types::{chrono, Uuid};
 
 impl From<EvalError> for actix_web::Error {
     fn from(e: EvalError) -> Self {
         match e {
             EvalError::NotFound(e) => {
                 log::error!("not found: {:?}", e);
-                error::ErrorNotFound("evals not found for params")
+                coded(ErrorCode::EvalsNotFound, "evals not found for params")
             }
             EvalError::Sqlx(e) => {
                 log::error!("sql error: {:?}", e);
                 error::ErrorInternalServerError("unknown error")
             }
             EvalError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            EvalError::Forbidden => error::ErrorForbidden("forbidden by policy"),
+            // Clients read why from a JSON body, to decide whether to compute the eval.
+            EvalError::UnresolvedPrincipal => {
+                let message = "credentials do not resolve to a user";
+                let body = serde_json::json!({
+                    "error": "unresolved_principal",
+                    "message": message,
+                });
+                error::InternalError::from_response(
+                    message,
+                    coded_builder(ErrorCode::UnresolvedPrincipal).json(body),
+                )
+                .into()
+            }
+            EvalError::Miss { reason, stale } => {
+                let message = "no eval of the function with these arguments";
+                let mut body = serde_json::json!({
+                    "error": "eval_not_found",
+                    "reason": reason.as_str(),
+                    "message": message,
+                });
+                // An eval of another version, for the client to use while it recomputes this one.
+                if let Some(eval) = stale {
+                    body["stale"] = true.into();
+                    body["eval"] = serde_json::to_value(eval).unwrap_or_default();
+                }
+                error::InternalError::from_response(
+                    message,
+                    coded_builder(ErrorCode::EvalMiss).json(body),
+                )
+                .into()
+            }
+            EvalError::Rejected(reason) => {
+                let message = "evals rejected by the project's validation webhook";
+                let body = serde_json::json!({
+                    "error": "eval_rejected",
+                    "reason": reason,
+                    "message": message,
+                });
+                error::InternalError::from_response(
+                    message,
+                    coded_builder(ErrorCode::EvalsRejected).json(body),
+                )
+                .into()
+            }
+            EvalError::ValidationFailed => coded(
+                ErrorCode::ValidationFailed,
+                "project's validation webhook failed",
+            ),
+            EvalError::InvalidQuery => coded(ErrorCode::InvalidEvalQuery, "invalid search query"),
+            EvalError::InvalidReport => {
+                coded(ErrorCode::InvalidCacheReport, "invalid cache report")
+            }
+            EvalError::InvalidImport => coded(ErrorCode::InvalidImport, "invalid eval in import"),
+            EvalError::InvalidMulti => {
+                coded(ErrorCode::InvalidMultiEval, "invalid evals for one blob")
+            }
+            EvalError::ImportClosed => {
+                coded(ErrorCode::ImportClosed, "import has already finished")
+            }
+            EvalError::Held => coded(ErrorCode::EvalsHeld, "evals are under a legal hold"),
+            EvalError::SimilarityDisabled => coded(
+                ErrorCode::SimilarityDisabled,
+                "similarity search is not enabled",
+            ),
+            EvalError::Embedding(e) => {
+                log::error!("error embedding eval arguments: {:?}", e);
+                coded(ErrorCode::EmbeddingFailed, "unable to embed arguments")
+            }
         }
     }
 }
@@ -27,35 +117,374 @@ pub struct Params {
     pub args_hash: Option<String>,
     pub is_experiment: Option<bool>,
     pub poll: Option<bool>,
+    /// Whether a lookup of one eval which misses returns, marked stale, the latest eval of another
+    /// version of the function with the same arguments, if there is one.
+    pub stale: Option<bool>,
+}
+
+/// Search parameters over the contents of `result_json`.
+///
+/// `contains` is a JSON document which the result must contain (e.g. `{"split": "val"}`), and
+/// `path` is a jsonpath predicate the result must satisfy (e.g. `$.accuracy > 0.9`).
+#[derive(Deserialize, Debug)]
+pub struct SearchParams {
+    pub project: Option<String>,
+    pub fn_key: Option<String>,
+    pub contains: Option<String>,
+    pub path: Option<String>,
+}
+
+/// Status parameters for a batch of functions. `fn_keys` is a JSON array of function keys (e.g.
+/// `["mod:train", "mod:evaluate"]`).
+#[derive(Deserialize, Debug)]
+pub struct StatusParams {
+    pub fn_keys: String,
 }
 
+/// Usage parameters. Usage is summed over hourly periods starting at or after `since`, if given.
+#[derive(Deserialize, Debug)]
+pub struct UsageParams {
+    pub fn_key: Option<String>,
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Looks up evals. Clients polling for an eval to appear are told how long to wait before polling
+/// again, which grows as the server gets busier.
+/// Lists the user's evals matching the filters. A lookup of one eval, by function, version and
+/// arguments, which finds nothing is a 404 giving the reason, rather than an empty list.
 #[get("")]
 async fn get_by_params(
     params: web::Query<Params>,
     auth: Auth,
     state: AppState,
-) -> Result<web::Json<Vec<Eval>>, error::Error> {
+) -> Result<Listing<Eval>, error::Error> {
+    let unfiltered_poll = params.poll == Some(true) && params.args_hash.is_none();
     let res = params.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res).poll_after(state.poll_after_ms()).warn_if(
+        unfiltered_poll,
+        "poll_unfiltered",
+        "poll without args_hash counts an access of every matching eval",
+    ))
+}
+
+/// Searches evals by their results. Searches are cached until evals are next inserted in the
+/// project.
+#[get("/search")]
+async fn search(
+    params: web::Query<SearchParams>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<Eval>, error::Error> {
+    let res = params.fetch(Some(&auth), &state).await?;
+    let empty = res.is_empty();
+    Ok(Listing::new(res)
+        .cache_for(state.config.listing_max_age_secs)
+        .warn_if(
+            empty,
+            "not_indexed",
+            "only evals of projects with result indexing enabled are searched",
+        ))
+}
+
+#[get("/status_by_fn")]
+async fn status_by_fn(
+    params: web::Query<StatusParams>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<FnStatus>, error::Error> {
+    let res = params.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res))
+}
+
+#[get("/usage")]
+async fn usage(
+    params: web::Query<UsageParams>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<FnUsage>, error::Error> {
+    let res = params.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res))
+}
+
+/// Returns the cached evals of a function whose arguments are nearest to the given ones, ordered by
+/// distance. Clients use this to find close matches when an exact lookup misses.
+#[post("/similar")]
+async fn similar(
+    query: web::Json<SimilarEvalsGet>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<SimilarEval>, error::Error> {
+    let query = query.into_inner();
+    let limit = query.limit.clamp(1, MAX_SIMILAR);
+    let requested = query.limit;
+    let res = query.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res).paginated(limit).warn_if(
+        requested > limit,
+        "limit_reduced",
+        format!("limit reduced to {}", limit),
+    ))
+}
+
+/// Lists cached evals of a function which differ from a lookup which missed, and which of their
+/// arguments differ, to help explain the miss.
+#[post("/suggestions")]
+async fn suggestions(
+    query: web::Json<SuggestionsGet>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<Suggestion>, error::Error> {
+    let query = query.into_inner();
+    let limit = query.limit.clamp(1, MAX_SUGGESTIONS);
+    let requested = query.limit;
+    let res = query.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res).paginated(limit).warn_if(
+        requested > limit,
+        "limit_reduced",
+        format!("limit reduced to {}", limit),
+    ))
+}
+
+/// Records a batch of cache outcomes from a client.
+#[post("/report")]
+async fn report(
+    batch: web::Json<ReportBatch>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse, error::Error> {
+    batch.into_inner().persist(Some(&auth), &state).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Resolves an eval in one round trip, for the memoization path: the eval if it's cached, otherwise
+/// the run computing it, registering one for the client to compute it with if there isn't one.
+#[post("/resolve")]
+async fn resolve(
+    req: web::Json<EvalResolve>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<Resolution>, error::Error> {
+    let res = req.into_inner().persist(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+/// Canonicalizes arguments, as the client serialized them, returning their canonical form and
+/// hash. Clients hash arguments this way so that equal arguments find the same evals whichever
+/// version of the client serialized them. See [`crate::canonical`].
+#[post("/canonicalize")]
+async fn canonicalize(body: web::Bytes, _auth: Auth) -> Result<web::Json<CanonicalArgs>> {
+    let text = std::str::from_utf8(&body).map_err(error::ErrorBadRequest)?;
+    let res = canonical_args(text).map_err(error::ErrorBadRequest)?;
     Ok(web::Json(res))
 }
 
+/// Deletes the user's evals matching the parameters. Clients learn of the deletion from the
+/// change feed, at `GET /changes`.
+#[delete("")]
+async fn delete(
+    params: web::Query<EvalDelete>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse, error::Error> {
+    params.into_inner().persist(Some(&auth), &state).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Puts an eval. Retrying a put is safe: if the eval is already stored with the same result,
+/// nothing is written and the stored eval's id is returned, marked `replayed` when its result's
+/// BLOB needn't be uploaded again. An eval put with `deferred` is stored before its result's BLOB
+/// is, and the response says where and by when to upload it.
 // TODO: get rid of the slash
 #[put("/")]
 async fn put(
     insert: web::Json<EvalInsert>,
     auth: Auth,
     state: AppState,
-) -> Result<String, error::Error> {
-    let _api_key = auth.allow_only_api_key()?;
+) -> Result<web::Json<EvalPut>, error::Error> {
     let insert = insert.into_inner();
 
     let res = insert.persist(Some(&auth), &state).await?;
 
+    Ok(web::Json(res))
+}
+
+/// Puts several evals, e.g. the named outputs of one function, with one BLOB holding all of their
+/// results, returning their ids in order. Each eval's result is the range of the BLOB at its
+/// `offset` and `length`, which clients download from `GET /eval/{id}/payload`. The evals are all
+/// put, or none are.
+#[put("/multi")]
+async fn put_multi(
+    insert: WithBlob<EvalMultiInsert>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<Vec<Uuid>>, error::Error> {
+    insert.meta.check()?;
+    insert.meta.validate(&auth, &state).await?;
+    let res = insert.persist(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+/// Starts a bulk import of evals, returning its id.
+#[post("/import")]
+async fn create_import(
+    create: web::Json<EvalImportCreate>,
+    auth: Auth,
+    state: AppState,
+) -> Result<String, error::Error> {
+    let res = create.into_inner().persist(Some(&auth), &state).await?;
     Ok(res.to_string())
 }
 
+/// Reads newline-delimited evals from `body`, inserting them a chunk at a time. On failure,
+/// returns the number of the line it failed at.
+async fn import_evals(
+    import_id: Uuid,
+    mut body: web::Payload,
+    auth: &Auth,
+    state: &State,
+) -> Result<(), (usize, EvalError)> {
+    let mut buf = BytesMut::new();
+    let mut evals = Vec::with_capacity(IMPORT_CHUNK_ROWS);
+    let mut line = 0;
+    let mut more = true;
+    while more {
+        match body.next().await {
+            Some(bytes) => {
+                buf.extend_from_slice(&bytes.map_err(|_| (line, EvalError::InvalidImport))?)
+            }
+            // The last line needn't end with a newline.
+            None => {
+                more = false;
+                buf.extend_from_slice(b"\n");
+            }
+        }
+
+        while let Some(end) = buf.iter().position(|&b| b == b'\n') {
+            let bytes = buf.split_to(end + 1);
+            line += 1;
+            if bytes.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let eval = serde_json::from_slice::<EvalInsert>(&bytes)
+                .map_err(|_| (line, EvalError::InvalidImport))?;
+            evals.push(eval);
+
+            if evals.len() == IMPORT_CHUNK_ROWS {
+                let chunk = std::mem::replace(&mut evals, Vec::with_capacity(IMPORT_CHUNK_ROWS));
+                EvalImportChunk {
+                    import_id,
+                    evals: chunk,
+                }
+                .persist(Some(auth), state)
+                .await
+                .map_err(|e| (line, e))?;
+            }
+        }
+    }
+
+    if !evals.is_empty() {
+        EvalImportChunk { import_id, evals }
+            .persist(Some(auth), state)
+            .await
+            .map_err(|e| (line, e))?;
+    }
+
+    Ok(())
+}
+
+/// Uploads the evals of an import as newline-delimited JSON, each line being an eval as accepted
+/// by `PUT /eval/`. Evals are inserted in chunks as they arrive, and progress can be followed with
+/// `GET /eval/import/{id}` meanwhile. The import is done once the upload completes; if it fails,
+/// the chunks inserted before the failure are kept.
+#[put("/import/{id}")]
+async fn upload_import(
+    id: web::Path<Uuid>,
+    body: web::Payload,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<EvalImport>, error::Error> {
+    let import_id = id.into_inner();
+    match import_evals(import_id, body, &auth, &state).await {
+        Ok(()) => {
+            let res = EvalImportFinish { import_id }
+                .persist(Some(&auth), &state)
+                .await?;
+            Ok(web::Json(res))
+        }
+        Err((line, e)) => {
+            EvalImportFail {
+                import_id,
+                error: format!("failed at line {}", line),
+            }
+            .persist(Some(&auth), &state)
+            .await?;
+            Err(e.into())
+        }
+    }
+}
+
+#[get("/import/{id}")]
+async fn get_import(
+    id: web::Path<Uuid>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<EvalImport>, error::Error> {
+    let res = EvalImportGet {
+        import_id: id.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(web::Json(res))
+}
+
+/// Downloads the result of an eval, which for an eval put with others is the range of their BLOB
+/// holding it, so clients needn't know where it is. A `Range` header asks for part of the result.
+#[get("/{id}/payload")]
+async fn get_payload(
+    id: web::Path<Uuid>,
+    priority: Priority,
+    req: HttpRequest,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse, error::Error> {
+    let range = req
+        .headers()
+        .get(header::RANGE)
+        .map(|v| {
+            v.to_str()
+                .map(str::to_string)
+                .map_err(|_| BlobError::InvalidRange)
+        })
+        .transpose()?;
+    let ip = req
+        .connection_info()
+        .realip_remote_addr()
+        .map(str::to_string);
+    let res = EvalPayloadGet {
+        eval_id: id.into_inner(),
+        range,
+        priority,
+        ip,
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(res)
+}
+
 pub fn init(cfg: &mut web::ServiceConfig) {
     // cfg.service(get_by_id);
+    cfg.service(search);
+    cfg.service(status_by_fn);
+    cfg.service(usage);
+    cfg.service(report);
+    cfg.service(resolve);
+    cfg.service(canonicalize);
+    cfg.service(similar);
+    cfg.service(suggestions);
+    cfg.service(create_import);
+    cfg.service(upload_import);
+    cfg.service(get_import);
+    cfg.service(get_payload);
     cfg.service(get_by_params);
     cfg.service(put);
+    cfg.service(put_multi);
 }
@@ -0,0 +1,33 @@
+use crate::middlewares::auth::Auth;
+use crate::models::openlineage::{ExportError, RunEvent};
+use crate::persisters::{export::OpenLineageExport, Query};
+use crate::state::AppState;
+use actix_web::{error, get, web, Result};
+
+impl From<ExportError> for actix_web::Error {
+    fn from(e: ExportError) -> Self {
+        match e {
+            ExportError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            ExportError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+/// Exports runs and evals as OpenLineage `RunEvent`s, in the order they happened. Large exports are
+/// better submitted as an `openlineage_export` job (`POST /jobs`), rather than held open here.
+#[get("/openlineage")]
+async fn openlineage(
+    params: web::Query<OpenLineageExport>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<Vec<RunEvent>>> {
+    let res = params.into_inner().fetch(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(openlineage);
+}
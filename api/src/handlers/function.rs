@@ -0,0 +1,67 @@
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::function::{Function, FunctionError, FunctionsChanged};
+use crate::persisters::{
+    function::{FunctionInsert, FunctionsChangedGet, FunctionsGet},
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{error, get, put, web, HttpResponse, Result};
+
+impl From<FunctionError> for actix_web::Error {
+    fn from(e: FunctionError) -> Self {
+        match e {
+            FunctionError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            FunctionError::Forbidden => error::ErrorForbidden("forbidden by policy"),
+            FunctionError::NotFound => coded(ErrorCode::FunctionNotFound, "function not found"),
+            FunctionError::TooLarge => {
+                coded(ErrorCode::FunctionTooLarge, "function source is too long")
+            }
+            FunctionError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[get("")]
+async fn get(
+    params: web::Query<FunctionsGet>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<Function>> {
+    let res = params.into_inner().fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res))
+}
+
+/// What has changed in the user's functions registry (versions of functions, and their caching
+/// policies) since `since`, for clients keeping a local view of it. A client syncs everything
+/// once, without `since`, then syncs from the `next_since` it's given each time. Changes may be
+/// sent more than once.
+#[get("/changed")]
+async fn changed(
+    params: web::Query<FunctionsChangedGet>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<FunctionsChanged>> {
+    let res = params.into_inner().fetch(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+#[put("")]
+async fn put(
+    insert: web::Json<FunctionInsert>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    insert.into_inner().persist(Some(&auth), &state).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+    cfg.service(changed);
+    cfg.service(put);
+}
@@ -0,0 +1,86 @@
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::hold::{HoldError, HoldEvent, LegalHold};
+use crate::persisters::{
+    hold::{HoldEventsGet, HoldInsert, HoldRelease, HoldSpec, HoldsGet},
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{delete, error, get, post, web, HttpResponse, Result};
+
+impl From<HoldError> for actix_web::Error {
+    fn from(e: HoldError) -> Self {
+        match e {
+            HoldError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            HoldError::NotFound => {
+                coded(ErrorCode::HoldNotFound, "org, hold, run or eval not found")
+            }
+            HoldError::InvalidHold => coded(
+                ErrorCode::InvalidHold,
+                "a hold needs a reason and at least one run or eval",
+            ),
+            HoldError::AlreadyReleased => {
+                coded(ErrorCode::HoldReleased, "hold has already been released")
+            }
+            HoldError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[get("/{org}")]
+async fn get(org: web::Path<String>, auth: Auth, state: AppState) -> Result<Listing<LegalHold>> {
+    let res = HoldsGet {
+        org: org.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(Listing::new(res))
+}
+
+#[post("/{org}")]
+async fn post(
+    org: web::Path<String>,
+    spec: web::Json<HoldSpec>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let hold = HoldInsert {
+        org: org.into_inner(),
+        spec: spec.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::Created().json(hold))
+}
+
+#[delete("/{org}/{id}")]
+async fn release(
+    path: web::Path<(String, i64)>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let (org, id) = path.into_inner();
+    HoldRelease { org, id }.persist(Some(&auth), &state).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[get("/{org}/events")]
+async fn events(org: web::Path<String>, auth: Auth, state: AppState) -> Result<Listing<HoldEvent>> {
+    let res = HoldEventsGet {
+        org: org.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(Listing::new(res))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+    cfg.service(post);
+    cfg.service(release);
+    cfg.service(events);
+}
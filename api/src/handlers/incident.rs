@@ -0,0 +1,66 @@
+//! Admin endpoints for posting incidents to the status feed, and following them up with notes.
+//! See [`crate::persisters::status`].
+use crate::envelope::Listing;
+use crate::middlewares::auth::Auth;
+use crate::models::status::{Incident, IncidentInsert, IncidentNoteInsert};
+use crate::persisters::{
+    status::{IncidentDelete, IncidentNotePost, IncidentsGet},
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{delete, get, post, web, HttpResponse, Result};
+
+#[get("")]
+async fn list(
+    params: web::Query<IncidentsGet>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<Incident>> {
+    let params = params.into_inner();
+    let limit = params.limit;
+    let res = params.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res).paginated(limit))
+}
+
+#[post("")]
+async fn post(
+    incident: web::Json<IncidentInsert>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<Incident>> {
+    let res = incident.into_inner().persist(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+#[post("/{id}/notes")]
+async fn post_note(
+    id: web::Path<i64>,
+    note: web::Json<IncidentNoteInsert>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<Incident>> {
+    let res = IncidentNotePost {
+        id: id.into_inner(),
+        note: note.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(web::Json(res))
+}
+
+#[delete("/{id}")]
+async fn delete(id: web::Path<i64>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    IncidentDelete {
+        id: id.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(list);
+    cfg.service(post);
+    cfg.service(post_note);
+    cfg.service(delete);
+}
@@ -0,0 +1,66 @@
+//! Endpoints for jobs: long-running productions, such as exports, which run in the background
+//! rather than while a response is held open. See [`crate::jobs::queued`].
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
+use crate::jobs;
+use crate::middlewares::auth::Auth;
+use crate::models::job::{Job, JobError};
+use crate::persisters::{
+    job::{JobGet, JobSpec, JobSubmit, JobsGet},
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{error, get, http::header, post, web, HttpResponse, Result};
+use sqlx::types::Uuid;
+
+impl From<JobError> for actix_web::Error {
+    fn from(e: JobError) -> Self {
+        match e {
+            JobError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            JobError::NotFound => coded(ErrorCode::JobNotFound, "job not found"),
+            JobError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+/// Submits a job, which starts straight away. Its progress is polled at the `Location` returned.
+#[post("")]
+async fn submit(spec: web::Json<JobSpec>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    let job = JobSubmit {
+        spec: spec.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    actix_rt::spawn(jobs::queued::work(state.get_ref().clone()));
+    Ok(HttpResponse::Accepted()
+        .insert_header((header::LOCATION, format!("/jobs/{}", job.id)))
+        .json(job))
+}
+
+#[get("")]
+async fn list(params: web::Query<JobsGet>, auth: Auth, state: AppState) -> Result<Listing<Job>> {
+    let params = params.into_inner();
+    let limit = params.limit;
+    let res = params.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res).paginated(limit))
+}
+
+/// A job's progress, and once it's finished, the content hash of the BLOB it produced.
+#[get("/{id}")]
+async fn get(id: web::Path<Uuid>, auth: Auth, state: AppState) -> Result<web::Json<Job>> {
+    let res = JobGet {
+        id: id.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(web::Json(res))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(submit);
+    cfg.service(list);
+    cfg.service(get);
+}
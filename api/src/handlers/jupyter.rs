@@ -0,0 +1,97 @@
+//! Endpoints tailored to the Jupyter notebook sidebar. Each answers one sidebar view in a single
+//! request, and list responses can be requested as MessagePack (`?format=msgpack`) to keep them
+//! small.
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::jupyter::{CompactEval, JupyterError, MAX_PREVIEW_BYTES};
+use crate::msg_pack::MsgPack;
+use crate::persisters::{
+    jupyter::{BlobPreview, EvalPin, SessionEvalsGet},
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{error, get, post, web, Either, HttpResponse, Result};
+
+impl From<JupyterError> for actix_web::Error {
+    fn from(e: JupyterError) -> Self {
+        match e {
+            JupyterError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            JupyterError::NotFound => coded(ErrorCode::NotebookNotFound, "resource not found"),
+            JupyterError::InvalidHash => coded(ErrorCode::InvalidHash, "invalid hash"),
+            JupyterError::Store(e) => e.into(),
+            JupyterError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Msgpack,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SessionParams {
+    limit: Option<i64>,
+    #[serde(default)]
+    format: Format,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PreviewParams {
+    bytes: Option<u64>,
+}
+
+#[get("/session/{session_id}/evals")]
+async fn session_evals(
+    session_id: web::Path<String>,
+    params: web::Query<SessionParams>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Either<web::Json<Vec<CompactEval>>, MsgPack<Vec<CompactEval>>>> {
+    let params = params.into_inner();
+    let get = SessionEvalsGet {
+        session_id: session_id.into_inner(),
+        limit: params.limit.unwrap_or(20).clamp(1, 200),
+    };
+    let res = get.fetch(Some(&auth), &state).await?;
+
+    Ok(match params.format {
+        Format::Json => Either::Left(web::Json(res)),
+        Format::Msgpack => Either::Right(MsgPack(res)),
+    })
+}
+
+#[get("/preview/{content_hash}")]
+async fn preview(
+    content_hash: web::Path<String>,
+    params: web::Query<PreviewParams>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let get = BlobPreview {
+        content_hash: content_hash.into_inner(),
+        len: params.bytes.unwrap_or(MAX_PREVIEW_BYTES),
+    };
+    let bytes = get.fetch(Some(&auth), &state).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .body(bytes))
+}
+
+#[post("/pin")]
+async fn pin(pin: web::Json<EvalPin>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    pin.into_inner().persist(Some(&auth), &state).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(session_evals);
+    cfg.service(preview);
+    cfg.service(pin);
+}
@@ -0,0 +1,30 @@
+//! Admin endpoint reporting which replica leads each job which runs on one replica at a time.
+use crate::envelope::Listing;
+use crate::leader::{JobLeader, LeaderError};
+use crate::middlewares::auth::Auth;
+use crate::persisters::{leader::JobLeadersGet, Query};
+use crate::state::AppState;
+use actix_web::{error, get, web, Result};
+
+impl From<LeaderError> for actix_web::Error {
+    fn from(e: LeaderError) -> Self {
+        match e {
+            LeaderError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            LeaderError::Forbidden => error::ErrorForbidden("admins only"),
+            LeaderError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[get("")]
+async fn get(auth: Auth, state: AppState) -> Result<Listing<JobLeader>> {
+    let res = JobLeadersGet {}.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+}
@@ -155,7 +155,8 @@ pub struct Claims {
     pub exp: i64,
 }
 
-fn generate_jwt(user_uuid: sqlx::types::Uuid) -> Result<String, LoginError> {
+/// Generates the JWT with which a signed in user authenticates.
+pub fn generate_jwt(user_uuid: sqlx::types::Uuid) -> Result<String, jsonwebtoken::errors::Error> {
     use chrono::{DateTime, Duration, Utc};
     use jsonwebtoken::{encode, EncodingKey, Header};
 
@@ -0,0 +1,88 @@
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::metric::{Metric, MetricError, MetricRule};
+use crate::persisters::{
+    metric::{MetricRuleDelete, MetricRuleUpsert, MetricRulesGet, MetricsGet},
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{delete, error, get, put, web, HttpResponse, Result};
+
+impl From<MetricError> for actix_web::Error {
+    fn from(e: MetricError) -> Self {
+        match e {
+            MetricError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            MetricError::ProjectNotFound => {
+                coded(ErrorCode::MetricNotFound, "project or rule not found")
+            }
+            MetricError::InvalidPath => {
+                coded(ErrorCode::InvalidJsonPath, "invalid jsonpath expression")
+            }
+            MetricError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+/// Lists metrics, for charting experiments in the dashboard. Listings are cached until metrics
+/// are next recorded in the project.
+#[get("")]
+async fn get_metrics(
+    params: web::Query<MetricsGet>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<Metric>> {
+    let res = params.into_inner().fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res).cache_for(state.config.listing_max_age_secs))
+}
+
+#[get("/rule/{project}")]
+async fn get_rules(
+    project: web::Path<String>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<MetricRule>> {
+    let get = MetricRulesGet {
+        project: project.into_inner(),
+    };
+    let res = get.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res))
+}
+
+#[put("/rule/{project}")]
+async fn put_rule(
+    project: web::Path<String>,
+    rule: web::Json<MetricRule>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let upsert = MetricRuleUpsert {
+        project: project.into_inner(),
+        rule: rule.into_inner(),
+    };
+    upsert.persist(Some(&auth), &state).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[delete("/rule/{project}/{name}")]
+async fn delete_rule(
+    path: web::Path<(String, String)>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let (project, name) = path.into_inner();
+    MetricRuleDelete { project, name }
+        .persist(Some(&auth), &state)
+        .await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_metrics);
+    cfg.service(get_rules);
+    cfg.service(put_rule);
+    cfg.service(delete_rule);
+}
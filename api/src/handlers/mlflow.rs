@@ -0,0 +1,206 @@
+use crate::error_code::{coded_builder, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::mlflow::{split_artifact_path, Experiment, MlflowError, MlflowRun, RunInfo};
+use crate::persisters::{
+    mlflow::{
+        ArtifactGet, ArtifactUpload, CreateExperiment, CreateRun, GetExperiment,
+        GetExperimentByName, GetRun, LogBatch, LogMetric, LogParam, UpdateRun,
+    },
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{body::BodyStream, error, get, post, put, web, HttpResponse, Result};
+
+/// Artifacts are received in full before being stored, so they are limited in size.
+const MAX_ARTIFACT_SIZE: usize = 1 << 30;
+
+impl From<MlflowError> for actix_web::Error {
+    fn from(e: MlflowError) -> Self {
+        let (code, message) = match &e {
+            MlflowError::Unauthorized => (ErrorCode::Unauthorized, "unauthorized".to_string()),
+            MlflowError::NotFound => (ErrorCode::MlflowNotFound, "resource not found".to_string()),
+            MlflowError::InvalidParameter(m) => (ErrorCode::MlflowInvalidParameter, m.clone()),
+            MlflowError::Store(e) => {
+                log::error!("store error: {:?}", e);
+                (ErrorCode::Internal, "unknown error".to_string())
+            }
+            MlflowError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                (ErrorCode::Internal, "unknown error".to_string())
+            }
+        };
+
+        // MLflow clients read the error code and message from a JSON body.
+        let body = serde_json::json!({
+            "error_code": e.error_code(),
+            "message": message,
+        });
+        error::InternalError::from_response(message, coded_builder(code).json(body)).into()
+    }
+}
+
+#[derive(Serialize)]
+struct ExperimentIdResponse {
+    experiment_id: String,
+}
+
+#[derive(Serialize)]
+struct ExperimentResponse {
+    experiment: Experiment,
+}
+
+#[derive(Serialize)]
+struct RunResponse {
+    run: MlflowRun,
+}
+
+#[derive(Serialize)]
+struct RunInfoResponse {
+    run_info: RunInfo,
+}
+
+#[derive(Serialize)]
+struct Empty {}
+
+#[post("/api/2.0/mlflow/experiments/create")]
+async fn create_experiment(
+    req: web::Json<CreateExperiment>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<ExperimentIdResponse>> {
+    let experiment_id = req.into_inner().persist(Some(&auth), &state).await?;
+    Ok(web::Json(ExperimentIdResponse { experiment_id }))
+}
+
+#[get("/api/2.0/mlflow/experiments/get")]
+async fn get_experiment(
+    req: web::Query<GetExperiment>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<ExperimentResponse>> {
+    let experiment = req.into_inner().fetch(Some(&auth), &state).await?;
+    Ok(web::Json(ExperimentResponse { experiment }))
+}
+
+#[get("/api/2.0/mlflow/experiments/get-by-name")]
+async fn get_experiment_by_name(
+    req: web::Query<GetExperimentByName>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<ExperimentResponse>> {
+    let experiment = req.into_inner().fetch(Some(&auth), &state).await?;
+    Ok(web::Json(ExperimentResponse { experiment }))
+}
+
+#[post("/api/2.0/mlflow/runs/create")]
+async fn create_run(
+    req: web::Json<CreateRun>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<RunResponse>> {
+    let run = req.into_inner().persist(Some(&auth), &state).await?;
+    Ok(web::Json(RunResponse { run }))
+}
+
+#[get("/api/2.0/mlflow/runs/get")]
+async fn get_run(
+    req: web::Query<GetRun>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<RunResponse>> {
+    let run = req.into_inner().fetch(Some(&auth), &state).await?;
+    Ok(web::Json(RunResponse { run }))
+}
+
+#[post("/api/2.0/mlflow/runs/update")]
+async fn update_run(
+    req: web::Json<UpdateRun>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<RunInfoResponse>> {
+    let run_info = req.into_inner().persist(Some(&auth), &state).await?;
+    Ok(web::Json(RunInfoResponse { run_info }))
+}
+
+#[post("/api/2.0/mlflow/runs/log-metric")]
+async fn log_metric(
+    req: web::Json<LogMetric>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<Empty>> {
+    req.into_inner().persist(Some(&auth), &state).await?;
+    Ok(web::Json(Empty {}))
+}
+
+#[post("/api/2.0/mlflow/runs/log-parameter")]
+async fn log_param(
+    req: web::Json<LogParam>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<Empty>> {
+    req.into_inner().persist(Some(&auth), &state).await?;
+    Ok(web::Json(Empty {}))
+}
+
+#[post("/api/2.0/mlflow/runs/log-batch")]
+async fn log_batch(
+    req: web::Json<LogBatch>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<Empty>> {
+    req.into_inner().persist(Some(&auth), &state).await?;
+    Ok(web::Json(Empty {}))
+}
+
+#[put("/api/2.0/mlflow-artifacts/artifacts/{path:.*}")]
+async fn put_artifact(
+    path: web::Path<String>,
+    body: web::Bytes,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<Empty>> {
+    let (run_id, path) = split_artifact_path(&path)
+        .ok_or_else(|| MlflowError::InvalidParameter("invalid artifact path".to_string()))?;
+
+    let upload = ArtifactUpload {
+        run_id: run_id.to_string(),
+        path: path.to_string(),
+        bytes: body,
+    };
+    upload.persist(Some(&auth), &state).await?;
+
+    Ok(web::Json(Empty {}))
+}
+
+#[get("/api/2.0/mlflow-artifacts/artifacts/{path:.*}")]
+async fn get_artifact(
+    path: web::Path<String>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let (run_id, path) = split_artifact_path(&path)
+        .ok_or_else(|| MlflowError::InvalidParameter("invalid artifact path".to_string()))?;
+
+    let get = ArtifactGet {
+        run_id: run_id.to_string(),
+        path: path.to_string(),
+    };
+    let byte_stream = get.fetch(Some(&auth), &state).await?;
+
+    Ok(HttpResponse::Ok().body(BodyStream::new(byte_stream)))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::PayloadConfig::new(MAX_ARTIFACT_SIZE));
+    cfg.service(create_experiment);
+    cfg.service(get_experiment);
+    cfg.service(get_experiment_by_name);
+    cfg.service(create_run);
+    cfg.service(get_run);
+    cfg.service(update_run);
+    cfg.service(log_metric);
+    cfg.service(log_param);
+    cfg.service(log_batch);
+    cfg.service(put_artifact);
+    cfg.service(get_artifact);
+}
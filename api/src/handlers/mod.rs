@@ -1,6 +1,44 @@
+pub mod alert;
+pub mod anomaly;
 pub mod api_key;
+pub mod bandwidth;
 pub mod blob;
+pub mod blob_backfill;
+pub mod blob_upload;
+pub mod cache_policy;
+pub mod canary;
+pub mod capabilities;
+pub mod capture;
+pub mod change;
+pub mod chaos;
+pub mod dead_letter;
+pub mod dvc;
 pub mod eval;
+pub mod export;
+pub mod function;
+pub mod hold;
+pub mod incident;
+pub mod job;
+pub mod jupyter;
+pub mod leader;
 pub mod login;
+pub mod metric;
+pub mod mlflow;
+pub mod ops;
+pub mod policy;
+pub mod presigned_upload;
+pub mod project;
+pub mod provision;
+pub mod route;
+pub mod run;
+pub mod s3gateway;
+pub mod scim;
+pub mod search;
+pub mod slo;
+pub mod sso;
+pub mod status;
+pub mod storage_report;
+pub mod topology;
+pub mod usage;
 pub mod user;
 pub mod waitlist;
@@ -0,0 +1,154 @@
+//! Operational endpoints, for health checks and monitoring. These are only served on the internal
+//! listener, so they aren't authenticated.
+use crate::leader::SINGLETONS;
+use crate::state::AppState;
+use actix_web::{get, web, HttpResponse};
+use std::fmt::Write;
+
+/// Whether the server can serve requests, i.e. whether it can reach the database.
+#[get("/healthz")]
+async fn healthz(state: AppState) -> HttpResponse {
+    match query!("SELECT 1 AS one").fetch_one(&state.db_conn).await {
+        Ok(_) => HttpResponse::Ok().body("ok"),
+        Err(e) => {
+            log::warn!("health check failed: {:?}", e);
+            HttpResponse::ServiceUnavailable().body("database unavailable")
+        }
+    }
+}
+
+/// The server's load, in the Prometheus text format.
+#[get("/metrics")]
+async fn metrics(state: AppState) -> HttpResponse {
+    let blob_cache = state.blob_cache.usage();
+    let gauges = [
+        (
+            "hitsave_requests_in_flight",
+            "Requests currently being served.",
+            state.load.in_flight() as u64,
+        ),
+        (
+            "hitsave_db_connections",
+            "Open database connections.",
+            state.db_conn.size() as u64,
+        ),
+        (
+            "hitsave_db_connections_idle",
+            "Open database connections not in use.",
+            state.db_conn.num_idle() as u64,
+        ),
+        (
+            "hitsave_poll_after_ms",
+            "How long polling clients are currently asked to wait between polls.",
+            state.poll_after_ms(),
+        ),
+        (
+            "hitsave_gc_reclaimable_blobs",
+            "Unreferenced BLOBs the garbage collector found in its last pass.",
+            state.gc.reclaimable().blobs,
+        ),
+        (
+            "hitsave_gc_reclaimable_bytes",
+            "Bytes of unreferenced BLOBs' objects the garbage collector found in its last pass.",
+            state.gc.reclaimable().bytes,
+        ),
+        (
+            "hitsave_blob_cache_blobs",
+            "BLOBs cached on local disk.",
+            blob_cache.blobs,
+        ),
+        (
+            "hitsave_blob_cache_bytes",
+            "Bytes of the BLOBs cached on local disk.",
+            blob_cache.bytes,
+        ),
+    ];
+
+    let mut body = String::new();
+    for (name, help, value) in gauges {
+        writeln!(body, "# HELP {} {}", name, help).unwrap();
+        writeln!(body, "# TYPE {} gauge", name).unwrap();
+        writeln!(body, "{} {}", name, value).unwrap();
+    }
+
+    // Counters of each route's requests by outcome, as tracked for its objective.
+    writeln!(
+        body,
+        "# HELP hitsave_requests_total Requests served, by route and outcome."
+    )
+    .unwrap();
+    writeln!(body, "# TYPE hitsave_requests_total counter").unwrap();
+    for (route, counts) in state.slo.lifetime() {
+        for (outcome, value) in [
+            ("good", counts.total - counts.bad()),
+            ("error", counts.errors),
+            ("slow", counts.slow),
+        ] {
+            writeln!(
+                body,
+                "hitsave_requests_total{{route=\"{}\",outcome=\"{}\"}} {}",
+                route, outcome, value
+            )
+            .unwrap();
+        }
+    }
+
+    // Which of the jobs run on one replica at a time this replica leads.
+    writeln!(
+        body,
+        "# HELP hitsave_job_leader Whether this replica leads the job."
+    )
+    .unwrap();
+    writeln!(body, "# TYPE hitsave_job_leader gauge").unwrap();
+    for job in SINGLETONS {
+        writeln!(
+            body,
+            "hitsave_job_leader{{job=\"{}\"}} {}",
+            job.as_str(),
+            state.leadership.since(job).is_some() as u8
+        )
+        .unwrap();
+    }
+
+    let reclaimed = state.gc.reclaimed();
+    for (name, help, value) in [
+        (
+            "hitsave_gc_reclaimed_blobs_total",
+            "Unreferenced BLOBs the garbage collector has deleted.",
+            reclaimed.blobs,
+        ),
+        (
+            "hitsave_gc_reclaimed_bytes_total",
+            "Bytes of BLOBs' objects the garbage collector has deleted.",
+            reclaimed.bytes,
+        ),
+        (
+            "hitsave_blob_cache_hits_total",
+            "BLOB retrievals served from the cache on local disk.",
+            blob_cache.hits,
+        ),
+        (
+            "hitsave_blob_cache_misses_total",
+            "BLOB retrievals which missed the cache on local disk and went to S3.",
+            blob_cache.misses,
+        ),
+        (
+            "hitsave_blob_cache_evictions_total",
+            "BLOBs evicted from the cache on local disk to make room for others.",
+            blob_cache.evictions,
+        ),
+    ] {
+        writeln!(body, "# HELP {} {}", name, help).unwrap();
+        writeln!(body, "# TYPE {} counter", name).unwrap();
+        writeln!(body, "{} {}", name, value).unwrap();
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(healthz);
+    cfg.service(metrics);
+}
@@ -0,0 +1,58 @@
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::persisters::{
+    policy::{PolicyGet, PolicyPut},
+    Persist, Query,
+};
+use crate::policy::{PolicyError, Rule};
+use crate::state::AppState;
+use actix_web::{error, get, put, web, Result};
+use sqlx::types::Uuid;
+
+impl From<PolicyError> for actix_web::Error {
+    fn from(e: PolicyError) -> Self {
+        match e {
+            PolicyError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            PolicyError::Forbidden => error::ErrorForbidden("forbidden by policy"),
+            PolicyError::NotFound => coded(ErrorCode::OrgNotFound, "org not found"),
+            PolicyError::InvalidRules => {
+                coded(ErrorCode::InvalidPolicyRules, "invalid policy rules")
+            }
+            PolicyError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[get("/{org_id}")]
+async fn get(org_id: web::Path<Uuid>, auth: Auth, state: AppState) -> Result<web::Json<Vec<Rule>>> {
+    let res = PolicyGet {
+        org_id: org_id.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(web::Json(res))
+}
+
+#[put("/{org_id}")]
+async fn put(
+    org_id: web::Path<Uuid>,
+    rules: web::Json<Vec<Rule>>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<Vec<Rule>>> {
+    let res = PolicyPut {
+        org_id: org_id.into_inner(),
+        rules: rules.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(web::Json(res))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+    cfg.service(put);
+}
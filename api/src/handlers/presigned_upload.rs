@@ -0,0 +1,72 @@
+//! Uploads of BLOBs straight to S3, for BLOBs too large to send through `PUT /blob` or
+//! `/blob/upload`. See [`crate::persisters::presigned_upload`].
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::presigned_upload::{PresignedUpload, PresignedUploadError};
+use crate::persisters::{
+    presigned_upload::{PresignedUploadConfirm, PresignedUploadStart},
+    Persist,
+};
+use crate::state::AppState;
+use actix_web::{error, post, web, Result};
+use sqlx::types::Uuid;
+
+impl From<PresignedUploadError> for actix_web::Error {
+    fn from(e: PresignedUploadError) -> Self {
+        match e {
+            PresignedUploadError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            PresignedUploadError::NotFound => {
+                coded(ErrorCode::UploadNotFound, "upload not found or expired")
+            }
+            PresignedUploadError::InvalidHash => coded(ErrorCode::InvalidHash, "invalid hash"),
+            PresignedUploadError::InvalidLength => coded(
+                ErrorCode::UploadTooLarge,
+                "blob is too large to upload in a single PUT",
+            ),
+            PresignedUploadError::QuotaExceeded => {
+                coded(ErrorCode::QuotaExceeded, "blob would exceed storage quota")
+            }
+            PresignedUploadError::Unsupported => coded(
+                ErrorCode::DirectUploadUnsupported,
+                "blobs can't be uploaded straight to storage here",
+            ),
+            PresignedUploadError::NotUploaded => {
+                coded(ErrorCode::NothingUploaded, "nothing has been uploaded yet")
+            }
+            PresignedUploadError::Store(e) => e.into(),
+            PresignedUploadError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+/// Starts an upload straight to S3, returning the URL to PUT the BLOB to, and the headers to send
+/// with it. If the BLOB is already stored, there's no URL, and the upload only needs confirming.
+#[post("")]
+async fn start(
+    insert: web::Json<PresignedUploadStart>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<PresignedUpload>> {
+    let res = insert.into_inner().persist(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+/// Confirms an upload once the BLOB has been PUT, checking and storing it. Returns the BLOB's id,
+/// as `PUT /blob` does.
+#[post("/{id}/confirm")]
+async fn confirm(id: web::Path<Uuid>, auth: Auth, state: AppState) -> Result<String> {
+    let res = PresignedUploadConfirm {
+        id: id.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(res.to_string())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(start);
+    cfg.service(confirm);
+}
@@ -0,0 +1,69 @@
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
+use crate::integrity::SignedManifest;
+use crate::middlewares::auth::Auth;
+use crate::models::project::{Project, ProjectError};
+use crate::persisters::{
+    project::{ProjectIntegrityGet, ProjectUpsert, ProjectsGet},
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{error, get, put, web, Result};
+use sqlx::types::Uuid;
+
+impl From<ProjectError> for actix_web::Error {
+    fn from(e: ProjectError) -> Self {
+        match e {
+            ProjectError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            ProjectError::NotFound => coded(ErrorCode::ProjectNotFound, "project not found"),
+            ProjectError::IntegrityDisabled => coded(
+                ErrorCode::IntegrityDisabled,
+                "integrity manifests are not enabled",
+            ),
+            ProjectError::InvalidSettings => coded(
+                ErrorCode::InvalidProjectSettings,
+                "invalid project settings",
+            ),
+            ProjectError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[get("")]
+async fn get(auth: Auth, state: AppState) -> Result<Listing<Project>> {
+    let res = ProjectsGet {}.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res))
+}
+
+#[put("")]
+async fn put(
+    upsert: web::Json<ProjectUpsert>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<Project>> {
+    let res = upsert.into_inner().persist(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+#[get("/{id}/integrity")]
+async fn get_integrity(
+    id: web::Path<Uuid>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<SignedManifest>> {
+    let res = ProjectIntegrityGet {
+        id: id.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(web::Json(res))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+    cfg.service(put);
+    cfg.service(get_integrity);
+}
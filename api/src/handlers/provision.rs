@@ -0,0 +1,303 @@
+//! Declarative endpoints for managing orgs, their projects and service accounts, and service
+//! account keys from infrastructure-as-code tools such as Terraform.
+//!
+//! Resources are addressed by external ids chosen by the client. `PUT` creates (201) or updates
+//! (200) a resource to match the body, and is idempotent. Every response carries the resource's
+//! version as its `ETag`, and `PUT` and `DELETE` honour `If-Match` and `If-None-Match` (412 when
+//! they don't hold). Changes which clash with other resources are rejected with 409.
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::provision::{etag, Precondition, ProvisionError, Provisioned};
+use crate::persisters::{
+    provision::{
+        KeyDelete, KeyGet, KeySpec, OrgDelete, OrgGet, OrgSpec, ProjectDelete, ProjectGet,
+        ProjectSpec, ResourcePath, ResourcePut, ServiceAccountDelete, ServiceAccountGet,
+        ServiceAccountSpec,
+    },
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{
+    delete, error, get,
+    http::header::{self, HeaderName},
+    put, web, HttpRequest, HttpResponse, Result,
+};
+use serde::Serialize;
+
+impl From<ProvisionError> for actix_web::Error {
+    fn from(e: ProvisionError) -> Self {
+        match e {
+            ProvisionError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            ProvisionError::NotFound => coded(ErrorCode::ProvisionNotFound, "resource not found"),
+            ProvisionError::Conflict => coded(
+                ErrorCode::ProvisionConflict,
+                "resource conflicts with an existing resource",
+            ),
+            ProvisionError::PreconditionFailed => coded(
+                ErrorCode::ProvisionPreconditionFailed,
+                "resource does not match the request's preconditions",
+            ),
+            ProvisionError::InvalidSpec => coded(ErrorCode::InvalidResource, "invalid resource"),
+            ProvisionError::InvalidResidency(reason) => coded(
+                ErrorCode::InvalidResidency,
+                format!("invalid data residency: {}", reason),
+            ),
+            ProvisionError::Hash => {
+                log::error!("could not hash new api key");
+                error::ErrorInternalServerError("unknown error")
+            }
+            ProvisionError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+fn precondition(req: &HttpRequest) -> Precondition {
+    let header = |name: HeaderName| {
+        req.headers()
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .map(String::from)
+    };
+    Precondition {
+        if_match: header(header::IF_MATCH),
+        if_none_match: header(header::IF_NONE_MATCH),
+    }
+}
+
+fn respond<T: Serialize>(resource: &T, version: i32, created: bool) -> HttpResponse {
+    let mut res = if created {
+        HttpResponse::Created()
+    } else {
+        HttpResponse::Ok()
+    };
+    res.insert_header((header::ETAG, etag(version)))
+        .json(resource)
+}
+
+fn path(org: String, service_account: Option<String>, external_id: String) -> ResourcePath {
+    ResourcePath {
+        org,
+        service_account,
+        external_id,
+    }
+}
+
+#[get("/orgs/{org}")]
+async fn get_org(org: web::Path<String>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    let org = org.into_inner();
+    let res = OrgGet {
+        path: path(org.clone(), None, org),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(respond(&res, res.version, false))
+}
+
+#[put("/orgs/{org}")]
+async fn put_org(
+    org: web::Path<String>,
+    spec: web::Json<OrgSpec>,
+    req: HttpRequest,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let org = org.into_inner();
+    let Provisioned { resource, created } = ResourcePut {
+        path: path(org.clone(), None, org),
+        spec: spec.into_inner(),
+        precondition: precondition(&req),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(respond(&resource, resource.version, created))
+}
+
+#[delete("/orgs/{org}")]
+async fn delete_org(
+    org: web::Path<String>,
+    req: HttpRequest,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let org = org.into_inner();
+    OrgDelete {
+        path: path(org.clone(), None, org),
+        precondition: precondition(&req),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/orgs/{org}/projects/{project}")]
+async fn get_project(
+    p: web::Path<(String, String)>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let (org, project) = p.into_inner();
+    let res = ProjectGet {
+        path: path(org, None, project),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(respond(&res, res.version, false))
+}
+
+#[put("/orgs/{org}/projects/{project}")]
+async fn put_project(
+    p: web::Path<(String, String)>,
+    spec: web::Json<ProjectSpec>,
+    req: HttpRequest,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let (org, project) = p.into_inner();
+    let Provisioned { resource, created } = ResourcePut {
+        path: path(org, None, project),
+        spec: spec.into_inner(),
+        precondition: precondition(&req),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(respond(&resource, resource.version, created))
+}
+
+#[delete("/orgs/{org}/projects/{project}")]
+async fn delete_project(
+    p: web::Path<(String, String)>,
+    req: HttpRequest,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let (org, project) = p.into_inner();
+    ProjectDelete {
+        path: path(org, None, project),
+        precondition: precondition(&req),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/orgs/{org}/service_accounts/{service_account}")]
+async fn get_service_account(
+    p: web::Path<(String, String)>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let (org, service_account) = p.into_inner();
+    let res = ServiceAccountGet {
+        path: path(org, None, service_account),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(respond(&res, res.version, false))
+}
+
+#[put("/orgs/{org}/service_accounts/{service_account}")]
+async fn put_service_account(
+    p: web::Path<(String, String)>,
+    spec: web::Json<ServiceAccountSpec>,
+    req: HttpRequest,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let (org, service_account) = p.into_inner();
+    let Provisioned { resource, created } = ResourcePut {
+        path: path(org, None, service_account),
+        spec: spec.into_inner(),
+        precondition: precondition(&req),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(respond(&resource, resource.version, created))
+}
+
+#[delete("/orgs/{org}/service_accounts/{service_account}")]
+async fn delete_service_account(
+    p: web::Path<(String, String)>,
+    req: HttpRequest,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let (org, service_account) = p.into_inner();
+    ServiceAccountDelete {
+        path: path(org, None, service_account),
+        precondition: precondition(&req),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/orgs/{org}/service_accounts/{service_account}/keys/{key}")]
+async fn get_key(
+    p: web::Path<(String, String, String)>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let (org, service_account, key) = p.into_inner();
+    let res = KeyGet {
+        path: path(org, Some(service_account), key),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(respond(&res, res.version, false))
+}
+
+/// Creates or relabels a service account key. The key itself is only included in the response
+/// when it is created.
+#[put("/orgs/{org}/service_accounts/{service_account}/keys/{key}")]
+async fn put_key(
+    p: web::Path<(String, String, String)>,
+    spec: web::Json<KeySpec>,
+    req: HttpRequest,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let (org, service_account, key) = p.into_inner();
+    let Provisioned { resource, created } = ResourcePut {
+        path: path(org, Some(service_account), key),
+        spec: spec.into_inner(),
+        precondition: precondition(&req),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(respond(&resource, resource.version, created))
+}
+
+#[delete("/orgs/{org}/service_accounts/{service_account}/keys/{key}")]
+async fn delete_key(
+    p: web::Path<(String, String, String)>,
+    req: HttpRequest,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let (org, service_account, key) = p.into_inner();
+    KeyDelete {
+        path: path(org, Some(service_account), key),
+        precondition: precondition(&req),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_org);
+    cfg.service(put_org);
+    cfg.service(delete_org);
+    cfg.service(get_project);
+    cfg.service(put_project);
+    cfg.service(delete_project);
+    cfg.service(get_service_account);
+    cfg.service(put_service_account);
+    cfg.service(delete_service_account);
+    cfg.service(get_key);
+    cfg.service(put_key);
+    cfg.service(delete_key);
+}
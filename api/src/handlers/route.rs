@@ -0,0 +1,22 @@
+//! Routing hints for clients of multi-region deployments. See [`crate::models::topology`].
+use crate::middlewares::auth::Auth;
+use crate::models::topology::Route;
+use crate::persisters::{topology::RouteGet, Query};
+use crate::state::AppState;
+use actix_web::{get, web, Result};
+
+/// Tells a client which region's BLOB endpoint and bucket to use, given its own region, along
+/// with every enabled region, so that it can pick the closest by latency instead.
+#[get("")]
+async fn get(
+    params: web::Query<RouteGet>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<Route>> {
+    let res = params.into_inner().fetch(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+}
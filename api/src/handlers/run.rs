@@ -0,0 +1,104 @@
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::run::{Run, RunError};
+use crate::persisters::{
+    run::{RunCancel, RunGet, RunInsert, RunStateUpdate, RunUnarchive, RunsGet, StateChange},
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{error, get, post, web, HttpResponse, Result};
+use sqlx::types::Uuid;
+
+impl From<RunError> for actix_web::Error {
+    fn from(e: RunError) -> Self {
+        match e {
+            RunError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            RunError::Forbidden => error::ErrorForbidden("forbidden by policy"),
+            RunError::NotFound => coded(ErrorCode::RunNotFound, "run not found"),
+            RunError::EvalNotFound => coded(ErrorCode::RunEvalNotFound, "eval not found"),
+            RunError::InvalidTransition { from, to } => coded(
+                ErrorCode::InvalidRunTransition,
+                format!("cannot move run from `{}` to `{}`", from, to.as_str()),
+            ),
+            RunError::NotArchived => coded(ErrorCode::RunNotArchived, "run is not archived"),
+            RunError::Store(e) => e.into(),
+            RunError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[post("")]
+async fn create(
+    insert: web::Json<RunInsert>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<Uuid>> {
+    let id = insert.into_inner().persist(Some(&auth), &state).await?;
+    Ok(web::Json(id))
+}
+
+#[get("")]
+async fn list(params: web::Query<RunsGet>, auth: Auth, state: AppState) -> Result<Listing<Run>> {
+    let res = params.into_inner().fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res))
+}
+
+#[get("/{id}")]
+async fn get(id: web::Path<Uuid>, auth: Auth, state: AppState) -> Result<web::Json<Run>> {
+    let res = RunGet {
+        id: id.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(web::Json(res))
+}
+
+#[post("/{id}/state")]
+async fn set_state(
+    id: web::Path<Uuid>,
+    change: web::Json<StateChange>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let update = RunStateUpdate {
+        id: id.into_inner(),
+        change: change.into_inner(),
+    };
+    update.persist(Some(&auth), &state).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[post("/{id}/cancel")]
+async fn cancel(id: web::Path<Uuid>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    RunCancel {
+        id: id.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[post("/{id}/unarchive")]
+async fn unarchive(id: web::Path<Uuid>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    RunUnarchive {
+        id: id.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    // Restoring from cold storage takes hours; the run's `unarchive_requested_dt` is cleared once
+    // it's done.
+    Ok(HttpResponse::Accepted().finish())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(create);
+    cfg.service(list);
+    cfg.service(get);
+    cfg.service(set_state);
+    cfg.service(cancel);
+    cfg.service(unarchive);
+}
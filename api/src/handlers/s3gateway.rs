@@ -0,0 +1,127 @@
+//! A minimal S3-compatible read API over the blob store, so that S3 clients can fetch BLOBs
+//! directly. Only path-style GetObject and HeadObject are supported, e.g. with boto3:
+//!
+//! ```python
+//! s3 = boto3.client(
+//!     "s3",
+//!     endpoint_url="https://api.hitsave.io/s3",
+//!     aws_access_key_id=api_key[:20],
+//!     aws_secret_access_key=api_key,
+//!     config=Config(s3={"addressing_style": "path"}),
+//! )
+//! s3.download_file("hitsave", content_hash, "result.bin")
+//! ```
+use crate::error_code::{coded_builder, ErrorCode};
+use crate::models::s3gateway::S3GatewayError;
+use crate::persisters::{
+    s3gateway::{S3ObjectGet, SignedRequest},
+    Query,
+};
+use crate::sigv4::Authorization;
+use crate::state::AppState;
+use actix_web::{body::SizedStream, error, get, head, web, HttpRequest, HttpResponse, Result};
+
+impl From<S3GatewayError> for actix_web::Error {
+    fn from(e: S3GatewayError) -> Self {
+        let code = match &e {
+            S3GatewayError::AccessDenied => ErrorCode::S3AccessDenied,
+            S3GatewayError::SignatureDoesNotMatch => ErrorCode::S3SignatureDoesNotMatch,
+            S3GatewayError::InvalidObjectState => ErrorCode::S3InvalidObjectState,
+            S3GatewayError::NoSuchKey => ErrorCode::S3NoSuchKey,
+            S3GatewayError::Store(e) => {
+                log::error!("store error: {:?}", e);
+                ErrorCode::Internal
+            }
+            S3GatewayError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                ErrorCode::Internal
+            }
+        };
+
+        let body = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Error><Code>{}</Code></Error>",
+            e.code()
+        );
+        let response = coded_builder(code)
+            .content_type("application/xml")
+            .body(body);
+        error::InternalError::from_response(e.code(), response).into()
+    }
+}
+
+/// Collects the parts of the request covered by its SigV4 signature.
+fn signed_request(req: &HttpRequest) -> Result<SignedRequest, S3GatewayError> {
+    let header = |name: &str| req.headers().get(name).and_then(|v| v.to_str().ok());
+
+    let authorization = header("authorization")
+        .and_then(Authorization::parse)
+        .ok_or(S3GatewayError::AccessDenied)?;
+
+    let connection_info = req.connection_info();
+    let header_values = authorization
+        .signed_headers
+        .iter()
+        .map(|name| match name.as_str() {
+            // HTTP/2 requests carry the host in the `:authority` pseudo-header instead.
+            "host" => Some(header("host").unwrap_or(connection_info.host()).to_string()),
+            name => header(name).map(str::to_string),
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or(S3GatewayError::SignatureDoesNotMatch)?;
+
+    Ok(SignedRequest {
+        method: req.method().to_string(),
+        path: req.uri().path().to_string(),
+        query: req.query_string().to_string(),
+        header_values,
+        payload_hash: header("x-amz-content-sha256")
+            .unwrap_or("UNSIGNED-PAYLOAD")
+            .to_string(),
+        amz_date: header("x-amz-date")
+            .ok_or(S3GatewayError::SignatureDoesNotMatch)?
+            .to_string(),
+        authorization,
+    })
+}
+
+#[get("/{bucket}/{key}")]
+async fn get_object(
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let (_bucket, key) = path.into_inner();
+    let get = S3ObjectGet {
+        request: signed_request(&req)?,
+        key,
+        head: false,
+    };
+    let object = get.fetch(None, &state).await?;
+    let body = object.body.expect("body is always fetched for GetObject");
+
+    Ok(HttpResponse::Ok().body(SizedStream::new(object.content_length as u64, body)))
+}
+
+#[head("/{bucket}/{key}")]
+async fn head_object(
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let (_bucket, key) = path.into_inner();
+    let head = S3ObjectGet {
+        request: signed_request(&req)?,
+        key,
+        head: true,
+    };
+    let object = head.fetch(None, &state).await?;
+
+    Ok(HttpResponse::Ok()
+        .no_chunking(object.content_length as u64)
+        .finish())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_object);
+    cfg.service(head_object);
+}
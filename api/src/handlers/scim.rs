@@ -0,0 +1,235 @@
+use crate::error_code::{coded_builder, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::scim::{GroupAttributes, PatchRequest, ScimError, UserAttributes, ERROR_SCHEMA};
+use crate::persisters::{
+    scim::{
+        GroupCreate, GroupDelete, GroupGet, GroupPatch, GroupReplace, GroupsGet, ListParams,
+        UserCreate, UserDelete, UserGet, UserPatch, UserReplace, UsersGet,
+    },
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{
+    delete, error, get, http::StatusCode, patch, post, put, web, HttpResponse, Result,
+};
+use serde::Serialize;
+use sqlx::types::Uuid;
+
+const CONTENT_TYPE: &str = "application/scim+json";
+
+impl From<ScimError> for actix_web::Error {
+    fn from(e: ScimError) -> Self {
+        let (code, detail) = match &e {
+            ScimError::Unauthorized => (ErrorCode::Unauthorized, "unauthorized"),
+            ScimError::NotFound => (ErrorCode::ScimNotFound, "resource not found"),
+            ScimError::Uniqueness => (ErrorCode::ScimUniqueness, "resource already exists"),
+            ScimError::Mutability => (ErrorCode::ScimMutability, "attribute is immutable"),
+            ScimError::InvalidFilter => (ErrorCode::ScimInvalidFilter, "unsupported filter"),
+            ScimError::InvalidPath => (ErrorCode::ScimInvalidPath, "unsupported path"),
+            ScimError::InvalidValue => (ErrorCode::ScimInvalidValue, "invalid value"),
+            ScimError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                (ErrorCode::Internal, "unknown error")
+            }
+        };
+
+        // SCIM clients read errors from a JSON body.
+        let body = serde_json::json!({
+            "schemas": [ERROR_SCHEMA],
+            "status": code.status().as_u16().to_string(),
+            "scimType": e.scim_type(),
+            "detail": detail,
+        });
+        let res = coded_builder(code)
+            .content_type(CONTENT_TYPE)
+            .body(body.to_string());
+        error::InternalError::from_response(detail, res).into()
+    }
+}
+
+fn respond<T: Serialize>(status: StatusCode, resource: &T) -> Result<HttpResponse> {
+    let body = serde_json::to_string(resource)?;
+    Ok(HttpResponse::build(status)
+        .content_type(CONTENT_TYPE)
+        .body(body))
+}
+
+#[get("/Users")]
+async fn list_users(
+    params: web::Query<ListParams>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let res = UsersGet {
+        params: params.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    respond(StatusCode::OK, &res)
+}
+
+#[get("/Users/{id}")]
+async fn get_user(id: web::Path<Uuid>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    let res = UserGet {
+        id: id.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    respond(StatusCode::OK, &res)
+}
+
+#[post("/Users")]
+async fn create_user(
+    attributes: web::Json<UserAttributes>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let res = UserCreate {
+        attributes: attributes.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    respond(StatusCode::CREATED, &res)
+}
+
+#[put("/Users/{id}")]
+async fn replace_user(
+    id: web::Path<Uuid>,
+    attributes: web::Json<UserAttributes>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let res = UserReplace {
+        id: id.into_inner(),
+        attributes: attributes.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    respond(StatusCode::OK, &res)
+}
+
+#[patch("/Users/{id}")]
+async fn patch_user(
+    id: web::Path<Uuid>,
+    patch: web::Json<PatchRequest>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let res = UserPatch {
+        id: id.into_inner(),
+        operations: patch.into_inner().operations,
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    respond(StatusCode::OK, &res)
+}
+
+#[delete("/Users/{id}")]
+async fn delete_user(id: web::Path<Uuid>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    UserDelete {
+        id: id.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/Groups")]
+async fn list_groups(
+    params: web::Query<ListParams>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let res = GroupsGet {
+        params: params.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    respond(StatusCode::OK, &res)
+}
+
+#[get("/Groups/{id}")]
+async fn get_group(id: web::Path<Uuid>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    let res = GroupGet {
+        id: id.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    respond(StatusCode::OK, &res)
+}
+
+#[post("/Groups")]
+async fn create_group(
+    attributes: web::Json<GroupAttributes>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let res = GroupCreate {
+        attributes: attributes.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    respond(StatusCode::CREATED, &res)
+}
+
+#[put("/Groups/{id}")]
+async fn replace_group(
+    id: web::Path<Uuid>,
+    attributes: web::Json<GroupAttributes>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let res = GroupReplace {
+        id: id.into_inner(),
+        attributes: attributes.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    respond(StatusCode::OK, &res)
+}
+
+#[patch("/Groups/{id}")]
+async fn patch_group(
+    id: web::Path<Uuid>,
+    patch: web::Json<PatchRequest>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let res = GroupPatch {
+        id: id.into_inner(),
+        operations: patch.into_inner().operations,
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    respond(StatusCode::OK, &res)
+}
+
+#[delete("/Groups/{id}")]
+async fn delete_group(id: web::Path<Uuid>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    GroupDelete {
+        id: id.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    // Identity providers send SCIM bodies as `application/scim+json`.
+    cfg.app_data(
+        web::JsonConfig::default()
+            .content_type(|m| m.subtype() == mime::JSON || m.suffix() == Some(mime::JSON)),
+    );
+    cfg.service(list_users);
+    cfg.service(get_user);
+    cfg.service(create_user);
+    cfg.service(replace_user);
+    cfg.service(patch_user);
+    cfg.service(delete_user);
+    cfg.service(list_groups);
+    cfg.service(get_group);
+    cfg.service(create_group);
+    cfg.service(replace_group);
+    cfg.service(patch_group);
+    cfg.service(delete_group);
+}
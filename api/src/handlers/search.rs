@@ -0,0 +1,37 @@
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::search::{SearchError, SearchResults};
+use crate::persisters::{search::SearchGet, Query};
+use crate::state::AppState;
+use actix_web::{error, get, web, Result};
+
+impl From<SearchError> for actix_web::Error {
+    fn from(e: SearchError) -> Self {
+        match e {
+            SearchError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            SearchError::InvalidQuery => {
+                coded(ErrorCode::EmptySearchQuery, "search query is empty")
+            }
+            SearchError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+/// Searches the names of the user's functions, runs, run artifacts and projects, returning the
+/// hits of each kind, best first.
+#[get("")]
+async fn get(
+    params: web::Query<SearchGet>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<SearchResults>> {
+    let res = params.into_inner().fetch(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+}
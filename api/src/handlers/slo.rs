@@ -0,0 +1,29 @@
+//! Admin endpoint reporting how each route is doing against its service level objective.
+use crate::envelope::Listing;
+use crate::middlewares::auth::Auth;
+use crate::slo::{self, RouteSlo, SloError};
+use crate::state::AppState;
+use actix_web::{error, get, web, Result};
+
+impl From<SloError> for actix_web::Error {
+    fn from(e: SloError) -> Self {
+        match e {
+            SloError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            SloError::Forbidden => error::ErrorForbidden("admins only"),
+            SloError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[get("")]
+async fn get(auth: Auth, state: AppState) -> Result<Listing<RouteSlo>> {
+    let res = slo::report(Some(&auth), &state).await?;
+    Ok(Listing::new(res))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+}
@@ -0,0 +1,351 @@
+//! Single sign-on through an org's OpenID Connect identity provider, as an alternative to signing
+//! in with GitHub.
+//!
+//! The frontend sends the user to `/sso/{org_id}/login`, which redirects them to the identity
+//! provider. The provider redirects them back to the frontend with a `code` and `state`, which the
+//! frontend exchanges for a JWT at `/sso/callback`, as with GitHub's `/user/login`.
+use crate::error_code::{coded, ErrorCode};
+use crate::handlers::login::generate_jwt;
+use crate::middlewares::auth::Auth;
+use crate::models::api_key::ApiKey;
+use crate::models::sso::{IdTokenClaims, ProviderConfig, SsoConfig, SsoError};
+use crate::persisters::{
+    sso::{
+        LoginStart, LoginTake, MemberUpsert, ProviderConfigGet, SsoConfigDelete, SsoConfigGet,
+        SsoConfigUpsert,
+    },
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{delete, error, get, http::header, put, web, HttpResponse, Result};
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use sqlx::types::Uuid;
+
+impl From<SsoError> for actix_web::Error {
+    fn from(e: SsoError) -> Self {
+        match e {
+            SsoError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            SsoError::NotFound => coded(
+                ErrorCode::SsoNotConfigured,
+                "single sign-on is not configured",
+            ),
+            SsoError::InvalidConfig => coded(
+                ErrorCode::InvalidSsoConfig,
+                "invalid identity provider configuration",
+            ),
+            SsoError::LoginExpired => {
+                coded(ErrorCode::LoginExpired, "sign-in expired; please try again")
+            }
+            SsoError::Provider(e) => {
+                log::error!("error communicating with identity provider: {:?}", e);
+                coded(
+                    ErrorCode::IdentityProviderFailed,
+                    "unable to sign in with identity provider",
+                )
+            }
+            SsoError::InvalidIdToken => coded(ErrorCode::InvalidIdToken, "invalid ID token"),
+            SsoError::NoVerifiedEmail => coded(
+                ErrorCode::NoVerifiedEmail,
+                "identity provider did not supply a verified email",
+            ),
+            SsoError::AccountExists => coded(
+                ErrorCode::AccountExists,
+                "an account with this email already exists and is not a member of the org",
+            ),
+            SsoError::Deprovisioned => {
+                coded(ErrorCode::Deprovisioned, "membership has been deactivated")
+            }
+            SsoError::Jwt(e) => {
+                log::error!("error generating JWT when signing in with SSO: {:?}", e);
+                error::ErrorInternalServerError("unable to sign in")
+            }
+            SsoError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Jwk {
+    kid: Option<String>,
+    kty: String,
+    n: Option<String>,
+    e: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct JwkSet {
+    keys: Vec<Jwk>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// Fetches the identity provider's metadata from its discovery document.
+async fn discover(config: &ProviderConfig) -> Result<ProviderMetadata, SsoError> {
+    let metadata = reqwest::Client::new()
+        .get(format!(
+            "{}/.well-known/openid-configuration",
+            config.issuer
+        ))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<ProviderMetadata>()
+        .await?;
+
+    if metadata.issuer.trim_end_matches('/') != config.issuer {
+        log::error!(
+            "identity provider issuer mismatch: configured {}, discovered {}",
+            config.issuer,
+            metadata.issuer
+        );
+        return Err(SsoError::InvalidConfig);
+    }
+
+    Ok(metadata)
+}
+
+/// Exchanges an authorization code for an ID token.
+async fn exchange_code(
+    config: &ProviderConfig,
+    metadata: &ProviderMetadata,
+    code: &str,
+    redirect_uri: &str,
+) -> Result<String, SsoError> {
+    let res = reqwest::Client::new()
+        .post(&metadata.token_endpoint)
+        .header(reqwest::header::ACCEPT, "application/json")
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("client_id", &config.client_id),
+            ("client_secret", &config.client_secret),
+        ])
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<TokenResponse>()
+        .await?;
+
+    Ok(res.id_token)
+}
+
+/// Checks the ID token's signature against the identity provider's published keys, along with
+/// its issuer, audience, expiry and nonce, and returns its claims.
+async fn validate_id_token(
+    config: &ProviderConfig,
+    metadata: &ProviderMetadata,
+    id_token: &str,
+    nonce: &str,
+) -> Result<IdTokenClaims, SsoError> {
+    let header = decode_header(id_token).map_err(|_| SsoError::InvalidIdToken)?;
+    if !matches!(
+        header.alg,
+        Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512
+    ) {
+        return Err(SsoError::InvalidIdToken);
+    }
+
+    let jwks = reqwest::Client::new()
+        .get(&metadata.jwks_uri)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<JwkSet>()
+        .await?;
+    let jwk = jwks
+        .keys
+        .iter()
+        .filter(|k| k.kty == "RSA")
+        .find(|k| header.kid.is_none() || k.kid == header.kid)
+        .ok_or(SsoError::InvalidIdToken)?;
+    let (n, e) = jwk
+        .n
+        .as_deref()
+        .zip(jwk.e.as_deref())
+        .ok_or(SsoError::InvalidIdToken)?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[&config.client_id]);
+    validation.iss = Some(config.issuer.clone());
+
+    let claims = decode::<IdTokenClaims>(
+        id_token,
+        &DecodingKey::from_rsa_components(n, e),
+        &validation,
+    )
+    .map_err(|e| {
+        log::warn!("rejected ID token: {:?}", e);
+        SsoError::InvalidIdToken
+    })?
+    .claims;
+
+    if claims.nonce.as_deref() != Some(nonce) {
+        return Err(SsoError::InvalidIdToken);
+    }
+
+    Ok(claims)
+}
+
+#[put("/{org_id}/config")]
+async fn put_config(
+    org_id: web::Path<Uuid>,
+    upsert: web::Json<SsoConfigUpsert>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<SsoConfig>> {
+    let upsert = SsoConfigUpsert {
+        org_id: org_id.into_inner(),
+        ..upsert.into_inner()
+    };
+    let res = upsert.persist(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+#[get("/{org_id}/config")]
+async fn get_config(
+    org_id: web::Path<Uuid>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<SsoConfig>> {
+    let res = SsoConfigGet {
+        org_id: org_id.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(web::Json(res))
+}
+
+#[delete("/{org_id}/config")]
+async fn delete_config(
+    org_id: web::Path<Uuid>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    SsoConfigDelete {
+        org_id: org_id.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize, Debug)]
+struct LoginParams {
+    /// Where the identity provider sends the user back to. This must be registered with the
+    /// identity provider.
+    redirect_uri: String,
+}
+
+/// Starts signing in to an org, by redirecting to its identity provider.
+#[get("/{org_id}/login")]
+async fn login(
+    org_id: web::Path<Uuid>,
+    params: web::Query<LoginParams>,
+    state: AppState,
+) -> Result<HttpResponse> {
+    let config = ProviderConfigGet {
+        org_id: org_id.into_inner(),
+    }
+    .fetch(None, &state)
+    .await?;
+    let metadata = discover(&config).await?;
+
+    let start = LoginStart {
+        org_id: config.org_id,
+        state: ApiKey::random().key,
+        nonce: ApiKey::random().key,
+        redirect_uri: params.into_inner().redirect_uri,
+    };
+
+    let location = url::Url::parse_with_params(
+        &metadata.authorization_endpoint,
+        &[
+            ("response_type", "code"),
+            ("scope", "openid email profile"),
+            ("client_id", &config.client_id),
+            ("redirect_uri", &start.redirect_uri),
+            ("state", &start.state),
+            ("nonce", &start.nonce),
+        ],
+    )
+    .map_err(|_| SsoError::InvalidConfig)?;
+
+    start.persist(None, &state).await?;
+
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, location.to_string()))
+        .finish())
+}
+
+#[derive(Deserialize, Debug)]
+struct CallbackParams {
+    code: String,
+    state: String,
+}
+
+/// Completes signing in to an org, returning a JWT for the user. The user is created, and made a
+/// member of the org, on their first sign-in.
+#[get("/callback")]
+async fn callback(params: web::Query<CallbackParams>, state: AppState) -> Result<String> {
+    let params = params.into_inner();
+
+    let pending = LoginTake {
+        state: params.state,
+    }
+    .persist(None, &state)
+    .await?;
+    let config = ProviderConfigGet {
+        org_id: pending.org_id,
+    }
+    .fetch(None, &state)
+    .await?;
+    let metadata = discover(&config).await?;
+
+    let id_token = exchange_code(&config, &metadata, &params.code, &pending.redirect_uri).await?;
+    let claims = validate_id_token(&config, &metadata, &id_token, &pending.nonce).await?;
+
+    // Only an email address the identity provider has verified can identify the user. Providers
+    // which don't say whether they have are taken not to have.
+    let email = claims
+        .email
+        .clone()
+        .filter(|_| claims.email_verified == Some(true))
+        .ok_or(SsoError::NoVerifiedEmail)?;
+    let role = config.role(&claims.groups(&config.groups_claim))?;
+
+    let user_id = MemberUpsert {
+        org_id: config.org_id,
+        email,
+        given_name: claims.given_name,
+        family_name: claims.family_name,
+        role,
+    }
+    .persist(None, &state)
+    .await?;
+
+    let jwt = generate_jwt(user_id).map_err(SsoError::from)?;
+    Ok(jwt)
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(put_config);
+    cfg.service(get_config);
+    cfg.service(delete_config);
+    cfg.service(login);
+    cfg.service(callback);
+}
@@ -0,0 +1,35 @@
+//! The public status feed, for customers to follow the service's health. See [`crate::status`].
+use crate::error_code::{coded, ErrorCode};
+use crate::models::status::{StatusError, StatusFeed};
+use crate::persisters::{status::StatusGet, Query};
+use crate::state::AppState;
+use actix_web::{error, get, web, Result};
+
+impl From<StatusError> for actix_web::Error {
+    fn from(e: StatusError) -> Self {
+        match e {
+            StatusError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            StatusError::Forbidden => error::ErrorForbidden("admins only"),
+            StatusError::NotFound => coded(ErrorCode::IncidentNotFound, "incident not found"),
+            StatusError::InvalidIncident => coded(
+                ErrorCode::InvalidIncident,
+                "incidents need a known status, an impact of degraded or outage, and components",
+            ),
+            StatusError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+/// The health of each component, and recent incidents. It isn't authenticated.
+#[get("/status.json")]
+async fn get(state: AppState) -> Result<web::Json<StatusFeed>> {
+    let res = StatusGet {}.fetch(None, &state).await?;
+    Ok(web::Json(res))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get);
+}
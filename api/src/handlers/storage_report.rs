@@ -0,0 +1,100 @@
+//! Admin endpoints for inventories of the objects stored in S3, and the drift they find between
+//! what's stored and what's recorded.
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
+use crate::jobs;
+use crate::middlewares::auth::Auth;
+use crate::models::storage_report::{
+    OrgStorageReport, StorageInventory, StorageReport, StorageReportError,
+};
+use crate::persisters::{
+    storage_report::{
+        InventoriesGet, InventoryStart, OrgStorageReportsGet, ReportParams, StorageReportsGet,
+    },
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{error, get, post, web, HttpResponse, Result};
+
+impl From<StorageReportError> for actix_web::Error {
+    fn from(e: StorageReportError) -> Self {
+        match e {
+            StorageReportError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            StorageReportError::Forbidden => error::ErrorForbidden("admins only"),
+            StorageReportError::NotFound => {
+                coded(ErrorCode::InventoryNotFound, "inventory not found")
+            }
+            StorageReportError::AlreadyRunning => coded(
+                ErrorCode::InventoryRunning,
+                "an inventory is already being taken",
+            ),
+            StorageReportError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+/// Takes an inventory now, in the background, rather than waiting for the next periodic one.
+#[post("/inventories")]
+async fn start(auth: Auth, state: AppState) -> Result<HttpResponse> {
+    InventoryStart {}.persist(Some(&auth), &state).await?;
+    actix_rt::spawn(jobs::inventory::take(state.get_ref().clone()));
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[get("/inventories")]
+async fn inventories(
+    params: web::Query<InventoriesGet>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<StorageInventory>> {
+    let params = params.into_inner();
+    let limit = params.limit;
+    let res = params.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res).paginated(limit))
+}
+
+#[get("/inventories/{id}/users")]
+async fn user_reports(
+    id: web::Path<i64>,
+    params: web::Query<ReportParams>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<StorageReport>> {
+    let params = params.into_inner();
+    let limit = params.limit;
+    let res = StorageReportsGet {
+        inventory_id: id.into_inner(),
+        params,
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(Listing::new(res).paginated(limit))
+}
+
+#[get("/inventories/{id}/orgs")]
+async fn org_reports(
+    id: web::Path<i64>,
+    params: web::Query<ReportParams>,
+    auth: Auth,
+    state: AppState,
+) -> Result<Listing<OrgStorageReport>> {
+    let params = params.into_inner();
+    let limit = params.limit;
+    let res = OrgStorageReportsGet {
+        inventory_id: id.into_inner(),
+        params,
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(Listing::new(res).paginated(limit))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(start);
+    cfg.service(inventories);
+    cfg.service(user_reports);
+    cfg.service(org_reports);
+}
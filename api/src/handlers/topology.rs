@@ -0,0 +1,72 @@
+//! Admin endpoints for the deployment topology, which clients are routed by at `GET /route`.
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::topology::{DeploymentRegion, TopologyError};
+use crate::persisters::{
+    topology::{RegionDelete, RegionUpsert, RegionsGet},
+    Persist, Query,
+};
+use crate::state::AppState;
+use actix_web::{delete, error, get, put, web, HttpResponse, Result};
+
+impl From<TopologyError> for actix_web::Error {
+    fn from(e: TopologyError) -> Self {
+        match e {
+            TopologyError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            TopologyError::Forbidden => error::ErrorForbidden("admins only"),
+            TopologyError::NotFound => coded(ErrorCode::RegionNotFound, "region not found"),
+            TopologyError::NotConfigured => coded(
+                ErrorCode::RegionsNotConfigured,
+                "no regions are configured; keep using this server",
+            ),
+            TopologyError::InvalidRegion => coded(
+                ErrorCode::InvalidRegion,
+                "a region needs a name, a bucket and a BLOB endpoint which is a URL",
+            ),
+            TopologyError::InUse => coded(
+                ErrorCode::RegionInUse,
+                "orgs pin their data to this region; unpin them first",
+            ),
+            TopologyError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[get("")]
+async fn list(auth: Auth, state: AppState) -> Result<Listing<DeploymentRegion>> {
+    let res = RegionsGet.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res))
+}
+
+#[put("/{name}")]
+async fn put(
+    name: web::Path<String>,
+    region: web::Json<RegionUpsert>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<DeploymentRegion>> {
+    let mut region = region.into_inner();
+    region.name = name.into_inner();
+    let res = region.persist(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+#[delete("/{name}")]
+async fn delete(name: web::Path<String>, auth: Auth, state: AppState) -> Result<HttpResponse> {
+    RegionDelete {
+        name: name.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(list);
+    cfg.service(put);
+    cfg.service(delete);
+}
@@ -0,0 +1,51 @@
+use crate::error_code::{coded, ErrorCode};
+use crate::middlewares::auth::Auth;
+use crate::models::usage::{DedupReport, StorageUsage, UsageError};
+use crate::persisters::{
+    usage::{DedupParams, DedupReportGet, StorageUsageGet},
+    Query,
+};
+use crate::state::AppState;
+use actix_web::{error, get, web, Result};
+
+impl From<UsageError> for actix_web::Error {
+    fn from(e: UsageError) -> Self {
+        match e {
+            UsageError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            UsageError::NotFound => coded(ErrorCode::OrgNotFound, "org not found"),
+            UsageError::Sqlx(e) => {
+                log::error!("sql error: {:?}", e);
+                error::ErrorInternalServerError("unknown error")
+            }
+        }
+    }
+}
+
+#[get("/storage")]
+async fn get_storage(auth: Auth, state: AppState) -> Result<web::Json<StorageUsage>> {
+    let res = StorageUsageGet {}.fetch(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+/// Reports how much computation an org's members duplicated, computing the same calls as each
+/// other because their caches weren't shared, and the process time sharing one would have saved.
+#[get("/duplicates/{org}")]
+async fn get_duplicates(
+    org: web::Path<String>,
+    params: web::Query<DedupParams>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<DedupReport>> {
+    let res = DedupReportGet {
+        org: org.into_inner(),
+        params: params.into_inner(),
+    }
+    .fetch(Some(&auth), &state)
+    .await?;
+    Ok(web::Json(res))
+}
+
+pub fn init(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_storage);
+    cfg.service(get_duplicates);
+}
@@ -1,17 +1,29 @@
+use crate::envelope::Listing;
+use crate::error_code::{coded, ErrorCode};
 use crate::handlers::login::{login_handler, LoginError};
 use crate::middlewares::auth::Auth;
-use crate::models::user::User;
+use crate::models::api_key::{ExchangedKey, KeySession};
+use crate::models::user::{OrgInvite, Preferences, User};
 use crate::persisters::{
-    user::{UserGet, UserGetError, UserUpsert, UserUpsertError},
+    api_key::{KeyExchange, SessionsGet, SessionsRevoke},
+    user::{
+        OrgInviteAccept, OrgInviteDecline, OrgInvitesGet, PreferencesGet, UserGet, UserGetError,
+        UserUpsert, UserUpsertError,
+    },
     Persist, Query,
 };
 use crate::state::AppState;
-use actix_web::{error, get, post, put, web, Error, Result};
+use actix_web::{delete, error, get, post, put, web, Error, HttpResponse, Result};
+use sqlx::types::Uuid;
 
 impl From<UserUpsertError> for Error {
     fn from(e: UserUpsertError) -> Self {
         match e {
-            UserUpsertError::AlreadyExists => error::ErrorBadRequest("email already exists"),
+            UserUpsertError::AlreadyExists => coded(ErrorCode::EmailExists, "email already exists"),
+            UserUpsertError::EmailInUse => coded(
+                ErrorCode::EmailInUse,
+                "an account with this email already exists and is not linked to this GitHub account",
+            ),
             UserUpsertError::Unreachable => {
                 error::ErrorInternalServerError("unknown error: could not insert new user")
             }
@@ -32,7 +44,7 @@ impl From<LoginError> for Error {
         match e {
             LoginError::GHComms(e) => {
                 log::error!("GitHub comms error when attempting to log in user: {:?}", e);
-                error::ErrorInternalServerError("unable to login with GitHub")
+                coded(ErrorCode::GitHubLoginFailed, "unable to login with GitHub")
             }
             LoginError::JwtError(e) => {
                 log::error!(
@@ -53,14 +65,15 @@ impl From<LoginError> for Error {
                     "error retrieving GitHub access token when attempting to log in user: {:?}",
                     e
                 );
-                error::ErrorInternalServerError("unable to login with GitHub")
+                coded(ErrorCode::GitHubLoginFailed, "unable to login with GitHub")
             }
             LoginError::UserInfoNotAvailable => {
                 log::error!(
                     "error retrieving GitHub user info when attempting to log in user: {:?}",
                     e
                 );
-                error::ErrorInternalServerError(
+                coded(
+                    ErrorCode::GitHubLoginFailed,
                     "unable to login with GitHub; user information not available",
                 )
             }
@@ -69,7 +82,8 @@ impl From<LoginError> for Error {
                     "error retrieving GitHub primary email when attempting to log in user: {:?}",
                     e
                 );
-                error::ErrorInternalServerError(
+                coded(
+                    ErrorCode::NoPrimaryEmail,
                     "unable to login with GitHub; primary email not available",
                 )
             }
@@ -81,6 +95,8 @@ impl From<UserGetError> for Error {
     fn from(e: UserGetError) -> Self {
         match e {
             UserGetError::Unauthorized => error::ErrorUnauthorized("Error: Unauthorized"),
+            UserGetError::Forbidden => error::ErrorForbidden("Error: Forbidden by policy"),
+            UserGetError::NotFound => coded(ErrorCode::InviteNotFound, "invite not found"),
             UserGetError::Sqlx(e) => {
                 log::error!("error retrieving user from database: {:?}", e);
                 error::ErrorInternalServerError("unable to retrieve user")
@@ -107,6 +123,96 @@ async fn login(form: web::Query<Login>, state: AppState) -> Result<String> {
     Ok(jwt)
 }
 
+/// Exchanges the signed in session for a short-lived key for one device, and optionally one
+/// project, so that clients can keep the key rather than the session's JWT.
+#[post("/exchange")]
+async fn exchange(
+    exchange: web::Json<KeyExchange>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<ExchangedKey>> {
+    let key = exchange.into_inner().persist(Some(&auth), &state).await?;
+    Ok(web::Json(key))
+}
+
+/// Lists the user's keys, with the devices they were issued to and when and from where they were
+/// last used.
+#[get("/sessions")]
+async fn get_sessions(auth: Auth, state: AppState) -> Result<Listing<KeySession>> {
+    let sessions = SessionsGet {}.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(sessions))
+}
+
+/// Revokes all of the user's keys, or only those of one device, e.g. after a laptop is stolen.
+#[delete("/sessions")]
+async fn delete_sessions(
+    params: web::Query<SessionsRevoke>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    params.into_inner().persist(Some(&auth), &state).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Returns the user's preferences, e.g. whether they're sent weekly digests.
+#[get("/preferences")]
+async fn get_preferences(auth: Auth, state: AppState) -> Result<web::Json<Preferences>> {
+    let res = PreferencesGet {}.fetch(Some(&auth), &state).await?;
+    Ok(web::Json(res))
+}
+
+/// Replaces the user's preferences. Preferences left out are reset to their defaults.
+#[put("/preferences")]
+async fn put_preferences(
+    preferences: web::Json<Preferences>,
+    auth: Auth,
+    state: AppState,
+) -> Result<web::Json<Preferences>> {
+    let res = preferences
+        .into_inner()
+        .persist(Some(&auth), &state)
+        .await?;
+    Ok(web::Json(res))
+}
+
+/// Lists the memberships orgs have provisioned for the user's account, which have no effect until
+/// the user accepts them.
+#[get("/invites")]
+async fn get_invites(auth: Auth, state: AppState) -> Result<Listing<OrgInvite>> {
+    let res = OrgInvitesGet {}.fetch(Some(&auth), &state).await?;
+    Ok(Listing::new(res))
+}
+
+/// Accepts an org's membership, after which the org's policies apply to the user and they can sign
+/// in with the org's SSO.
+#[post("/invites/{org_id}")]
+async fn accept_invite(
+    org_id: web::Path<Uuid>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    OrgInviteAccept {
+        org_id: org_id.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[delete("/invites/{org_id}")]
+async fn decline_invite(
+    org_id: web::Path<Uuid>,
+    auth: Auth,
+    state: AppState,
+) -> Result<HttpResponse> {
+    OrgInviteDecline {
+        org_id: org_id.into_inner(),
+    }
+    .persist(Some(&auth), &state)
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
 // TODO: this can be deleted once the real flow is built.
 #[put("/")]
 async fn put(form: web::Json<UserUpsert>, state: AppState) -> Result<web::Json<sqlx::types::Uuid>> {
@@ -121,4 +227,12 @@ pub fn init(cfg: &mut web::ServiceConfig) {
     cfg.service(put);
     cfg.service(get);
     cfg.service(login);
+    cfg.service(exchange);
+    cfg.service(get_sessions);
+    cfg.service(delete_sessions);
+    cfg.service(get_preferences);
+    cfg.service(put_preferences);
+    cfg.service(get_invites);
+    cfg.service(accept_invite);
+    cfg.service(decline_invite);
 }
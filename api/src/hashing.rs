@@ -0,0 +1,152 @@
+//! Content hash algorithms, and migrating the content-address space from one to another.
+//!
+//! BLOBs are addressed by their blake3 hash, which is what `content_hash` is everywhere. To move
+//! to another algorithm without stranding what's already stored, a secondary algorithm is
+//! configured with `CONTENT_HASH_SECONDARY`, and for as long as it is, each stored object's
+//! digest under it is recorded alongside its content hash, in `blob_digests`. Objects stored
+//! before then are rehashed in the background by [`crate::jobs::rehashing`]. Meanwhile, a BLOB
+//! can be looked up by either hash: an address of the form `<algorithm>:<hex digest>` is resolved
+//! to the BLOB's content hash through its recorded digests, and a bare hex digest is a content
+//! hash as it always has been.
+//!
+//! Once every object has a digest under the new algorithm, it can become the primary one.
+use std::str::FromStr;
+
+/// A hash algorithm BLOBs can be addressed by.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    Blake3,
+    Sha256,
+}
+
+/// The algorithm `content_hash` is computed with.
+pub const PRIMARY: HashAlgorithm = HashAlgorithm::Blake3;
+
+impl HashAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    /// The length of the algorithm's digests, in hex characters.
+    pub fn hex_len(&self) -> usize {
+        match self {
+            HashAlgorithm::Blake3 | HashAlgorithm::Sha256 => 64,
+        }
+    }
+
+    /// A hasher to feed a BLOB's bytes to.
+    pub fn hasher(&self) -> Digester {
+        match self {
+            HashAlgorithm::Blake3 => Digester::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::Sha256 => {
+                Digester::Sha256(ring::digest::Context::new(&ring::digest::SHA256))
+            }
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            _ => Err(()),
+        }
+    }
+}
+
+/// An incremental hash of a BLOB under one of the [`HashAlgorithm`]s.
+pub enum Digester {
+    Blake3(Box<blake3::Hasher>),
+    Sha256(ring::digest::Context),
+}
+
+impl Digester {
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Digester::Blake3(h) => {
+                h.update(bytes);
+            }
+            Digester::Sha256(c) => c.update(bytes),
+        }
+    }
+
+    /// The hex-encoded digest of the bytes fed in.
+    pub fn finish(self) -> String {
+        match self {
+            Digester::Blake3(h) => h.finalize().to_hex().to_string(),
+            Digester::Sha256(c) => hex::encode(c.finish()),
+        }
+    }
+}
+
+/// How a BLOB is addressed in a request.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Address<'a> {
+    /// By its content hash.
+    Content(&'a str),
+    /// By its digest under an algorithm other than the primary one, which has to be resolved to
+    /// its content hash.
+    Digest(HashAlgorithm, &'a str),
+}
+
+/// Parses an address of a BLOB: a content hash, or `<algorithm>:<hex digest>`. An address naming
+/// the primary algorithm is its content hash. Returns `None` if the algorithm isn't known, or the
+/// digest isn't the right length of hex.
+pub fn parse_address(address: &str) -> Option<Address<'_>> {
+    let (algorithm, digest) = match address.split_once(':') {
+        Some((algorithm, digest)) => (algorithm.parse().ok()?, digest),
+        None => (PRIMARY, address),
+    };
+    if digest.len() != algorithm.hex_len() || !digest.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    match algorithm {
+        PRIMARY => Some(Address::Content(digest)),
+        _ => Some(Address::Digest(algorithm, digest)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digests_match_the_algorithms() {
+        let mut blake3 = HashAlgorithm::Blake3.hasher();
+        blake3.update(b"hel");
+        blake3.update(b"lo");
+        assert_eq!(blake3.finish(), blake3::hash(b"hello").to_hex().to_string());
+
+        let mut sha256 = HashAlgorithm::Sha256.hasher();
+        sha256.update(b"hello");
+        assert_eq!(
+            sha256.finish(),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
+    #[test]
+    fn parses_addresses() {
+        let hash = blake3::hash(b"hello").to_hex().to_string();
+        assert_eq!(parse_address(&hash), Some(Address::Content(&hash)));
+        assert_eq!(
+            parse_address(&format!("blake3:{}", hash)),
+            Some(Address::Content(&hash))
+        );
+        assert_eq!(
+            parse_address(&format!("sha256:{}", hash)),
+            Some(Address::Digest(HashAlgorithm::Sha256, &hash))
+        );
+        assert_eq!(parse_address(&format!("md5:{}", hash)), None);
+        assert_eq!(parse_address("sha256:abc"), None);
+        assert_eq!(parse_address(&hash.replace('a', "z")), None);
+    }
+}
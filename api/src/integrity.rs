@@ -0,0 +1,160 @@
+//! Signed integrity manifests of projects, for compliance audits.
+//!
+//! A manifest lists the content hash and size of every BLOB a project's evals and run artifacts
+//! refer to at the time it's generated. The server signs it with its integrity key (Ed25519), so
+//! an auditor holding the server's public key can check later that the artifacts they're given
+//! are the ones, and all of the ones, the project had. The public key should be obtained out of
+//! band; the one sent along with each manifest is only for convenience.
+//!
+//! What's signed is the manifest's canonical text: a header line, then one line per BLOB, in
+//! order of content hash.
+//!
+//! ```text
+//! hitsave-integrity-v1 <project id> <generated, RFC 3339 in UTC>
+//! <content hash> <size in bytes, or - if it isn't known>
+//! ```
+use chrono::SecondsFormat;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use sqlx::types::{
+    chrono::{DateTime, Utc},
+    Uuid,
+};
+use std::sync::Arc;
+
+/// The first word of a manifest's canonical text, which changes whenever the format does.
+pub const MANIFEST_VERSION: &str = "hitsave-integrity-v1";
+
+/// A BLOB listed in an integrity manifest.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub content_hash: String,
+    /// The size of the BLOB, if it has been recorded.
+    pub content_length: Option<i64>,
+}
+
+/// The BLOBs of a project at a point in time.
+#[derive(Serialize, Debug)]
+pub struct IntegrityManifest {
+    pub project_id: Uuid,
+    pub generated_dt: DateTime<Utc>,
+    /// The project's BLOBs, in order of content hash.
+    pub blobs: Vec<ManifestEntry>,
+}
+
+impl IntegrityManifest {
+    /// The canonical text of the manifest, which is what's signed.
+    pub fn canonical(&self) -> String {
+        let mut text = format!(
+            "{} {} {}\n",
+            MANIFEST_VERSION,
+            self.project_id,
+            self.generated_dt
+                .to_rfc3339_opts(SecondsFormat::Micros, true)
+        );
+        for blob in &self.blobs {
+            let length = blob
+                .content_length
+                .map_or_else(|| "-".to_string(), |l| l.to_string());
+            text.push_str(&format!("{} {}\n", blob.content_hash, length));
+        }
+        text
+    }
+}
+
+/// A manifest along with the server's signature of its canonical text.
+#[derive(Serialize, Debug)]
+pub struct SignedManifest {
+    pub manifest: IntegrityManifest,
+    pub algorithm: &'static str,
+    /// The hex-encoded public key the manifest can be verified with.
+    pub public_key: String,
+    /// The hex-encoded signature of the manifest's canonical text.
+    pub signature: String,
+}
+
+/// The key integrity manifests are signed with.
+#[derive(Clone)]
+pub struct IntegrityKey(Arc<Ed25519KeyPair>);
+
+impl std::fmt::Debug for IntegrityKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "IntegrityKey({})", self.public_key())
+    }
+}
+
+impl IntegrityKey {
+    /// Parses a hex-encoded 256 bit Ed25519 seed.
+    pub fn from_hex(s: &str) -> Option<Self> {
+        let seed = hex::decode(s.trim()).ok()?;
+        let key = Ed25519KeyPair::from_seed_unchecked(&seed).ok()?;
+        Some(Self(Arc::new(key)))
+    }
+
+    /// The hex-encoded public key.
+    pub fn public_key(&self) -> String {
+        hex::encode(self.0.public_key().as_ref())
+    }
+
+    /// Signs the manifest.
+    pub fn sign(&self, manifest: IntegrityManifest) -> SignedManifest {
+        let signature = self.0.sign(manifest.canonical().as_bytes());
+        SignedManifest {
+            manifest,
+            algorithm: "ed25519",
+            public_key: self.public_key(),
+            signature: hex::encode(signature.as_ref()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{UnparsedPublicKey, ED25519};
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    fn manifest() -> IntegrityManifest {
+        IntegrityManifest {
+            project_id: Uuid::nil(),
+            generated_dt: DateTime::parse_from_rfc3339("2022-12-29T10:00:00Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            blobs: vec![
+                ManifestEntry {
+                    content_hash: "aa".to_string(),
+                    content_length: Some(3),
+                },
+                ManifestEntry {
+                    content_hash: "bb".to_string(),
+                    content_length: None,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn canonical_text() {
+        assert_eq!(
+            manifest().canonical(),
+            "hitsave-integrity-v1 00000000-0000-0000-0000-000000000000 \
+             2022-12-29T10:00:00.000000Z\naa 3\nbb -\n"
+        );
+    }
+
+    #[test]
+    fn signs_and_verifies() {
+        let key = IntegrityKey::from_hex(KEY).unwrap();
+        let signed = key.sign(manifest());
+
+        let public_key = hex::decode(&signed.public_key).unwrap();
+        let signature = hex::decode(&signed.signature).unwrap();
+        let verifier = UnparsedPublicKey::new(&ED25519, public_key);
+        let text = signed.manifest.canonical();
+        assert!(verifier.verify(text.as_bytes(), &signature).is_ok());
+        assert!(verifier
+            .verify(text.replace("aa 3", "aa 4").as_bytes(), &signature)
+            .is_err());
+        assert!(IntegrityKey::from_hex("0011").is_none());
+    }
+}
@@ -0,0 +1,114 @@
+use crate::leader::Singleton;
+use crate::state::AppStateRaw;
+
+use std::time::Duration;
+
+/// An alert rule which has just fired, along with the value which breached its threshold.
+#[derive(Serialize, Debug)]
+struct FiredAlert {
+    id: i64,
+    metric: String,
+    comparison: String,
+    threshold: f64,
+    value: Option<f64>,
+    #[serde(skip)]
+    channel: String,
+    #[serde(skip)]
+    target: String,
+}
+
+/// Periodically evaluates all alert rules against recently recorded metrics, and sends a
+/// notification for each rule that fires.
+pub async fn run(state: AppStateRaw) {
+    let mut interval = tokio::time::interval(Duration::from_secs(state.config.alert_interval_secs));
+
+    loop {
+        interval.tick().await;
+        if !state.leadership.leads(Singleton::Alerts).await {
+            continue;
+        }
+
+        match evaluate(&state).await {
+            Ok(fired) => {
+                for alert in fired {
+                    notify(&state, &alert).await;
+                }
+            }
+            Err(e) => log::error!("error evaluating alert rules: {:?}", e),
+        }
+    }
+}
+
+/// Finds the rules which are in breach, and marks them as fired.
+///
+/// For `>` and `>=` the largest value in the window is compared against the threshold, and for `<`
+/// and `<=` the smallest, so a rule fires if any value in the window breaches it. Marking rules as
+/// fired in the same statement means a rule can't fire twice, even with several servers running.
+async fn evaluate(state: &AppStateRaw) -> Result<Vec<FiredAlert>, sqlx::Error> {
+    query_as!(
+        FiredAlert,
+        r#"
+        WITH breached AS (
+            SELECT a.id, w.value
+            FROM alert_rules a
+            CROSS JOIN LATERAL (
+                SELECT CASE WHEN a.comparison IN ('>', '>=') THEN max(m.value)
+                            ELSE min(m.value) END AS value
+                FROM metrics m
+                LEFT JOIN evals e
+                    ON e.id = m.eval_id
+                LEFT JOIN runs r
+                    ON r.id = m.run_id
+                WHERE COALESCE(e.user_id, r.user_id) = a.user_id
+                    AND (COALESCE(e.project_id, r.project_id) = a.project_id
+                        OR a.project_id IS NULL)
+                    AND m.name = a.metric
+                    AND m.create_dt > now() - make_interval(secs => a.window_secs)
+            ) w
+            WHERE (a.last_fired_dt IS NULL
+                    OR a.last_fired_dt < now() - make_interval(secs => a.window_secs))
+                AND CASE a.comparison
+                    WHEN '>' THEN w.value > a.threshold
+                    WHEN '>=' THEN w.value >= a.threshold
+                    WHEN '<' THEN w.value < a.threshold
+                    WHEN '<=' THEN w.value <= a.threshold
+                END
+        )
+        UPDATE alert_rules a
+        SET last_fired_dt = now()
+        FROM breached b
+        WHERE a.id = b.id
+        RETURNING a.id, a.metric, a.comparison, a.threshold, b.value, a.channel, a.target
+        "#,
+    )
+    .fetch_all(&state.db_conn)
+    .await
+}
+
+async fn notify(state: &AppStateRaw, alert: &FiredAlert) {
+    let res = match alert.channel.as_str() {
+        "email" => {
+            let subject = format!(
+                "HitSave alert: {} {} {}",
+                alert.metric, alert.comparison, alert.threshold
+            );
+            let text = format!(
+                "Your alert rule fired: metric `{}` recorded a value of {} (threshold {} {}).",
+                alert.metric,
+                alert.value.unwrap_or_default(),
+                alert.comparison,
+                alert.threshold,
+            );
+            state.notifier.email(&alert.target, &subject, &text).await
+        }
+        _ => state.notifier.webhook(&alert.target, alert).await,
+    };
+
+    if let Err(e) = res {
+        log::warn!(
+            "could not send notification for alert rule {}: {:?}",
+            alert.id,
+            e
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use crate::leader::Singleton;
+use crate::models::anomaly::{baseline, Anomaly, AnomalyKind, BASELINE_HOURS, WINDOW_MINS};
+use crate::state::AppStateRaw;
+
+use sqlx::types::Uuid;
+use std::time::Duration;
+
+/// Activity within the last window and before it, for one subject: a function for miss storms,
+/// and a key for the other kinds of anomaly.
+struct ActivityCount {
+    user_id: Uuid,
+    api_key: Option<String>,
+    fn_key: Option<String>,
+    recent: i64,
+    earlier: i64,
+}
+
+/// Periodically looks for anomalies in recent cache activity, records them, and sends an event
+/// for each new one.
+pub async fn run(state: AppStateRaw) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.anomaly_interval_secs));
+
+    loop {
+        interval.tick().await;
+        if !state.leadership.leads(Singleton::Anomalies).await {
+            continue;
+        }
+
+        for kind in [
+            AnomalyKind::MissStorm,
+            AnomalyKind::DownloadSpike,
+            AnomalyKind::InvalidHashUploads,
+        ] {
+            match detect(&state, kind).await {
+                Ok(anomalies) => {
+                    for anomaly in anomalies {
+                        notify(&state, &anomaly).await;
+                    }
+                }
+                Err(e) => log::error!("error detecting {} anomalies: {:?}", kind.as_str(), e),
+            }
+        }
+
+        if let Err(e) = prune(&state).await {
+            log::error!("error pruning cache activity: {:?}", e);
+        }
+    }
+}
+
+/// Counts activity of the kind `kind` is detected from, per subject, for subjects with enough
+/// recent activity to possibly be flagged.
+async fn count(state: &AppStateRaw, kind: AnomalyKind) -> Result<Vec<ActivityCount>, sqlx::Error> {
+    let by_fn_key = kind == AnomalyKind::MissStorm;
+
+    query_as!(
+        ActivityCount,
+        r#"
+        SELECT user_id AS "user_id!",
+            CASE WHEN $4 THEN NULL ELSE api_key END AS api_key,
+            CASE WHEN $4 THEN fn_key END AS fn_key,
+            count(*) FILTER (WHERE create_dt > now() - make_interval(mins => $2)) AS "recent!",
+            count(*) FILTER (WHERE create_dt <= now() - make_interval(mins => $2)) AS "earlier!"
+        FROM cache_activity
+        WHERE kind = $1
+            AND create_dt > now() - make_interval(hours => $3)
+        GROUP BY 1, 2, 3
+        HAVING count(*) FILTER (WHERE create_dt > now() - make_interval(mins => $2)) >= $5
+        "#,
+        kind.activity().as_str(),
+        WINDOW_MINS,
+        BASELINE_HOURS,
+        by_fn_key,
+        kind.min_observed(),
+    )
+    .fetch_all(&state.db_conn)
+    .await
+}
+
+/// Records the anomalies of the kind `kind`, returning those which are new. A subject which was
+/// flagged within the last window isn't flagged again.
+async fn detect(state: &AppStateRaw, kind: AnomalyKind) -> Result<Vec<Anomaly>, sqlx::Error> {
+    let mut anomalies = vec![];
+
+    for c in count(state, kind).await? {
+        let baseline = baseline(c.earlier);
+        if !kind.is_anomalous(c.recent, baseline) {
+            continue;
+        }
+
+        let anomaly = query_as!(
+            Anomaly,
+            r#"
+            WITH i AS (
+                INSERT INTO anomalies (kind, user_id, api_key, fn_key, observed, baseline)
+                SELECT $1::varchar, $2, $3::varchar, $4, $5, $6
+                WHERE NOT EXISTS (
+                    SELECT 1
+                    FROM anomalies
+                    WHERE kind = $1::varchar
+                        AND user_id = $2
+                        AND api_key IS NOT DISTINCT FROM $3::varchar
+                        AND fn_key IS NOT DISTINCT FROM $4
+                        AND create_dt > now() - make_interval(mins => $7)
+                )
+                RETURNING *
+            )
+            SELECT i.id, i.kind, i.user_id, u.gh_login, k.label AS "key_label?", i.fn_key,
+                i.observed, i.baseline, i.create_dt, i.acknowledged_dt
+            FROM i
+            JOIN users u
+                ON u.id = i.user_id
+            LEFT JOIN api_keys k
+                ON k.key = i.api_key
+            "#,
+            kind.as_str(),
+            c.user_id,
+            c.api_key,
+            c.fn_key,
+            c.recent,
+            baseline,
+            WINDOW_MINS,
+        )
+        .fetch_optional(&state.db_conn)
+        .await?;
+
+        anomalies.extend(anomaly);
+    }
+
+    Ok(anomalies)
+}
+
+/// Deletes activity which is too old to count towards the baseline.
+async fn prune(state: &AppStateRaw) -> Result<(), sqlx::Error> {
+    query!(
+        "DELETE FROM cache_activity WHERE create_dt < now() - make_interval(hours => $1)",
+        BASELINE_HOURS,
+    )
+    .execute(&state.db_conn)
+    .await?;
+
+    Ok(())
+}
+
+async fn notify(state: &AppStateRaw, anomaly: &Anomaly) {
+    log::warn!(
+        "anomaly {}: {} for user {} ({} in the last {} minutes, usually {:.1})",
+        anomaly.id,
+        anomaly.kind,
+        anomaly.gh_login,
+        anomaly.observed,
+        WINDOW_MINS,
+        anomaly.baseline,
+    );
+
+    if let Some(url) = &state.config.anomaly_webhook_url {
+        if let Err(e) = state.notifier.operator_webhook(url, anomaly).await {
+            log::warn!("could not send event for anomaly {}: {:?}", anomaly.id, e);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use crate::leader::Singleton;
+use crate::persisters::s3store::{StoreError, Target};
+use crate::state::AppStateRaw;
+
+use aws_sdk_s3::model::StorageClass;
+use blake3::Hash;
+use sqlx::types::Uuid;
+use std::time::Duration;
+
+/// How many runs are archived or unarchived on each pass.
+const BATCH_SIZE: i64 = 100;
+
+struct RunBlob {
+    run_id: Uuid,
+    blob_id: i64,
+    content_hash: String,
+    storage_region: Option<String>,
+    storage_bucket: Option<String>,
+    storage_prefix: Option<String>,
+}
+
+impl RunBlob {
+    fn target(&self) -> Target {
+        Target {
+            region: self.storage_region.clone(),
+            bucket: self.storage_bucket.clone(),
+            prefix: self.storage_prefix.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum ArchiveError {
+    Store(StoreError),
+    Sqlx(sqlx::Error),
+}
+
+impl From<StoreError> for ArchiveError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<sqlx::Error> for ArchiveError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+impl From<blake3::HexError> for ArchiveError {
+    fn from(e: blake3::HexError) -> Self {
+        Self::Store(e.into())
+    }
+}
+
+/// Periodically moves the artifacts of old runs to cold storage, according to each project's
+/// `archive_after_days`, and completes requested unarchives once S3 has restored the objects.
+pub async fn run(state: AppStateRaw) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.archive_interval_secs));
+
+    loop {
+        interval.tick().await;
+        if !state.leadership.leads(Singleton::Archive).await {
+            continue;
+        }
+
+        if let Err(e) = archive(&state).await {
+            log::error!("error archiving runs: {:?}", e);
+        }
+        if let Err(e) = unarchive(&state).await {
+            log::error!("error unarchiving runs: {:?}", e);
+        }
+    }
+}
+
+async fn archive(state: &AppStateRaw) -> Result<(), ArchiveError> {
+    // A BLOB is only archived when every eval referencing it is an experiment. Otherwise we would
+    // be archiving an entry which is still in use as a cache. Runs under a legal hold are left as
+    // they are until the hold is released.
+    let due = query_as!(
+        RunBlob,
+        r#"
+        SELECT r.id AS run_id, b.id AS blob_id, b.content_hash, b.storage_region,
+            b.storage_bucket, b.storage_prefix
+        FROM runs r
+        JOIN projects p
+            ON p.id = r.project_id
+        JOIN evals e
+            ON e.id = r.eval_id
+        JOIN blobs b
+            ON b.id = e.blob_id
+        WHERE r.archived_dt IS NULL
+            AND r.unarchive_requested_dt IS NULL
+            AND r.state = 'succeeded'
+            AND p.archive_after_days IS NOT NULL
+            AND r.update_dt < now() - make_interval(days => p.archive_after_days)
+            AND NOT run_held(r.id)
+            AND NOT EXISTS (
+                SELECT 1 FROM evals e2
+                WHERE e2.blob_id = b.id
+                    AND NOT e2.is_experiment
+            )
+        LIMIT $1
+        "#,
+        BATCH_SIZE,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    for run in due {
+        let hash = Hash::from_hex(&run.content_hash)?;
+        state
+            .blob_store
+            .set_storage_class(&run.target(), hash, StorageClass::Glacier)
+            .await?;
+
+        let mut tx = state.db_conn.begin().await?;
+        query!(
+            "UPDATE blobs SET storage_class = 'GLACIER' WHERE id = $1",
+            run.blob_id
+        )
+        .execute(&mut tx)
+        .await?;
+        query!(
+            "UPDATE runs SET archived_dt = current_timestamp WHERE id = $1",
+            run.run_id
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        log::info!("archived run {}", run.run_id);
+    }
+
+    Ok(())
+}
+
+async fn unarchive(state: &AppStateRaw) -> Result<(), ArchiveError> {
+    let requested = query_as!(
+        RunBlob,
+        r#"
+        SELECT r.id AS run_id, b.id AS blob_id, b.content_hash, b.storage_region,
+            b.storage_bucket, b.storage_prefix
+        FROM runs r
+        JOIN evals e
+            ON e.id = r.eval_id
+        JOIN blobs b
+            ON b.id = e.blob_id
+        WHERE r.unarchive_requested_dt IS NOT NULL
+        LIMIT $1
+        "#,
+        BATCH_SIZE,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    for run in requested {
+        let hash = Hash::from_hex(&run.content_hash)?;
+        if !state
+            .blob_store
+            .restore_complete(&run.target(), hash)
+            .await?
+        {
+            continue;
+        }
+
+        // Copying the restored object onto itself makes it permanently readable again.
+        state
+            .blob_store
+            .set_storage_class(&run.target(), hash, StorageClass::Standard)
+            .await?;
+
+        // Bumping `update_dt` restarts the archive period, so the run isn't immediately archived
+        // again.
+        let mut tx = state.db_conn.begin().await?;
+        query!(
+            "UPDATE blobs SET storage_class = 'STANDARD' WHERE id = $1",
+            run.blob_id
+        )
+        .execute(&mut tx)
+        .await?;
+        query!(
+            r#"
+            UPDATE runs
+            SET archived_dt = NULL,
+                unarchive_requested_dt = NULL,
+                update_dt = current_timestamp
+            WHERE id = $1
+            "#,
+            run.run_id
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        log::info!("unarchived run {}", run.run_id);
+    }
+
+    Ok(())
+}
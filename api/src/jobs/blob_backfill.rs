@@ -0,0 +1,221 @@
+use crate::models::blob_backfill::{
+    verify, BlobStatus, DiscrepancyKind, Verification, BACKFILL_LOCK,
+};
+use crate::persisters::s3store::{StoreError, Target};
+use crate::state::AppStateRaw;
+
+use blake3::Hash;
+
+/// How many BLOBs are verified between progress reports.
+const BATCH_SIZE: i64 = 100;
+
+#[derive(Debug)]
+enum BackfillJobError {
+    Store(StoreError),
+    Sqlx(sqlx::Error),
+}
+
+impl From<StoreError> for BackfillJobError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<sqlx::Error> for BackfillJobError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+struct Unverified {
+    content_hash: String,
+    content_length: Option<i64>,
+    storage_region: Option<String>,
+    storage_bucket: Option<String>,
+    storage_prefix: Option<String>,
+}
+
+/// Verifies every BLOB which hasn't been verified yet against its stored object, then returns.
+/// This is started by an admin, rather than running periodically; if a backfill is already
+/// running, it returns straight away.
+pub async fn run(state: AppStateRaw) {
+    if let Err(e) = backfill(&state).await {
+        log::error!("error backfilling blob metadata: {:?}", e);
+    }
+}
+
+async fn backfill(state: &AppStateRaw) -> Result<(), BackfillJobError> {
+    // Advisory locks belong to a connection, so one is held for the whole backfill.
+    let mut conn = state.db_conn.acquire().await?;
+    let locked = query_scalar!(
+        r#"SELECT pg_try_advisory_lock($1) AS "locked!""#,
+        BACKFILL_LOCK
+    )
+    .fetch_one(&mut conn)
+    .await?;
+    if !locked {
+        log::info!("blob backfill is already running");
+        return Ok(());
+    }
+
+    let res = verify_all(state).await;
+
+    query_scalar!("SELECT pg_advisory_unlock($1)", BACKFILL_LOCK)
+        .fetch_one(&mut conn)
+        .await?;
+
+    res
+}
+
+async fn verify_all(state: &AppStateRaw) -> Result<(), BackfillJobError> {
+    let mut verified = 0;
+    let mut discrepancies = 0;
+
+    loop {
+        // Rows of the same BLOB owned by different users are verified together, unless their
+        // recorded lengths differ or they're stored in different places. Archived BLOBs can't be
+        // read until they're restored.
+        let batch = query_as!(
+            Unverified,
+            r#"
+            SELECT content_hash, content_length, storage_region, storage_bucket, storage_prefix
+            FROM blobs
+            WHERE verified_dt IS NULL
+                AND storage_class = 'STANDARD'
+            GROUP BY content_hash, content_length, storage_region, storage_bucket, storage_prefix
+            LIMIT $1
+            "#,
+            BATCH_SIZE,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        if batch.is_empty() {
+            break;
+        }
+
+        for blob in batch {
+            let found = examine(state, &blob).await?;
+            discrepancies += found.discrepancies.len();
+            record(state, &blob, found).await?;
+            verified += 1;
+        }
+
+        log::info!(
+            "blob backfill: verified {} blobs, found {} discrepancies",
+            verified,
+            discrepancies
+        );
+    }
+
+    log::info!(
+        "blob backfill finished: verified {} blobs, found {} discrepancies",
+        verified,
+        discrepancies
+    );
+
+    Ok(())
+}
+
+async fn examine(state: &AppStateRaw, blob: &Unverified) -> Result<Verification, StoreError> {
+    let missing = || Verification {
+        fill_length: None,
+        discrepancies: vec![(
+            DiscrepancyKind::MissingObject,
+            blob.content_hash.clone(),
+            String::new(),
+        )],
+    };
+
+    // A hash which isn't valid hex can't have been stored under.
+    let hash = match Hash::from_hex(&blob.content_hash) {
+        Ok(hash) => hash,
+        Err(_) => return Ok(missing()),
+    };
+    let target = Target {
+        region: blob.storage_region.clone(),
+        bucket: blob.storage_bucket.clone(),
+        prefix: blob.storage_prefix.clone(),
+    };
+    if state.blob_store.find_blob(&target, hash).await?.is_none() {
+        return Ok(missing());
+    }
+
+    let (actual_hash, actual_length) = state.blob_store.hash_blob(&target, hash).await?;
+
+    Ok(verify(
+        &blob.content_hash,
+        blob.content_length,
+        actual_hash.to_hex().as_str(),
+        actual_length,
+    ))
+}
+
+async fn record(
+    state: &AppStateRaw,
+    blob: &Unverified,
+    found: Verification,
+) -> Result<(), sqlx::Error> {
+    let mut tx = state.db_conn.begin().await?;
+
+    let status = if found
+        .discrepancies
+        .iter()
+        .any(|(kind, _, _)| *kind == DiscrepancyKind::MissingObject)
+    {
+        BlobStatus::Missing
+    } else {
+        BlobStatus::Available
+    };
+
+    for (kind, recorded, actual) in found.discrepancies {
+        log::warn!(
+            "blob {}: {} (recorded {}, actual {})",
+            blob.content_hash,
+            kind.as_str(),
+            recorded,
+            actual
+        );
+        query!(
+            r#"
+            INSERT INTO blob_discrepancies (content_hash, kind, recorded, actual)
+            VALUES ($1, $2, $3, nullif($4, ''))
+            "#,
+            blob.content_hash,
+            kind.as_str(),
+            recorded,
+            actual,
+        )
+        .execute(&mut tx)
+        .await?;
+    }
+
+    query!(
+        r#"
+        UPDATE blobs
+        SET content_length = coalesce(content_length, $3),
+            verified_dt = current_timestamp,
+            object_status = $6,
+            checked_dt = current_timestamp
+        WHERE content_hash = $1
+            AND content_length IS NOT DISTINCT FROM $2
+            AND storage_region IS NOT DISTINCT FROM $4
+            AND storage_bucket IS NOT DISTINCT FROM $5
+            AND storage_prefix IS NOT DISTINCT FROM $7
+            AND verified_dt IS NULL
+        "#,
+        blob.content_hash,
+        blob.content_length,
+        found.fill_length,
+        blob.storage_region,
+        blob.storage_bucket,
+        status.as_str(),
+        blob.storage_prefix,
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
@@ -0,0 +1,278 @@
+use crate::gc::{past_grace, Sweep};
+use crate::leader::Singleton;
+use crate::persisters::s3store::{StoreError, Target};
+use crate::state::AppStateRaw;
+
+use blake3::Hash;
+use sqlx::types::chrono::Utc;
+use std::time::Duration;
+
+/// How often unreferenced BLOBs are looked for.
+const GC_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// How many content hashes are looked at in each page of a pass.
+const PAGE_SIZE: i64 = 500;
+
+/// The unreferenced rows of a BLOB stored in one place.
+struct Orphans {
+    content_hash: String,
+    storage_region: Option<String>,
+    storage_bucket: Option<String>,
+    storage_prefix: Option<String>,
+    ids: Vec<i64>,
+    /// Whether every row of the BLOB stored in this place is unreferenced, so that its object
+    /// can go too.
+    all: bool,
+}
+
+/// Periodically deletes BLOBs which nothing references, along with their objects once no rows are
+/// left referencing them. In a dry run, what would be deleted is only logged and reported in
+/// `/metrics`. See [`crate::gc`].
+pub async fn run(state: AppStateRaw) {
+    let mut interval = tokio::time::interval(GC_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        if !state.leadership.leads(Singleton::BlobGc).await {
+            continue;
+        }
+
+        let dry_run = state.config.blob_gc_dry_run;
+        match collect(&state, dry_run).await {
+            Ok(sweep) => {
+                state.gc.record(sweep, dry_run);
+                if sweep.blobs > 0 {
+                    log::info!(
+                        "{} {} unreferenced blobs, {} bytes",
+                        if dry_run { "found" } else { "deleted" },
+                        sweep.blobs,
+                        sweep.bytes
+                    );
+                }
+            }
+            Err(e) => log::error!("error collecting unreferenced blobs: {:?}", e),
+        }
+    }
+}
+
+async fn collect(state: &AppStateRaw, dry_run: bool) -> Result<Sweep, sqlx::Error> {
+    let grace_days = state.config.blob_gc_grace_days;
+    let mut sweep = Sweep::default();
+    let mut after = String::new();
+
+    loop {
+        let page = query_scalar!(
+            r#"
+            SELECT content_hash AS "content_hash!"
+            FROM blobs
+            WHERE content_hash > $1
+            GROUP BY content_hash
+            ORDER BY content_hash
+            LIMIT $2
+            "#,
+            after,
+            PAGE_SIZE,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+        let last = match page.last() {
+            Some(last) => last.clone(),
+            None => break,
+        };
+
+        // The cheap checks come first. A BLOB is also referenced by its owner's evals whose
+        // arguments or result contain its hash, such as file snapshots.
+        let orphans = query_as!(
+            Orphans,
+            r#"
+            WITH candidates AS (
+                SELECT b.id, b.content_hash, b.storage_region, b.storage_bucket,
+                    b.storage_prefix,
+                    CASE
+                        WHEN b.create_dt >= now() - make_interval(days => $2) THEN false
+                        WHEN b.canary THEN false
+                        WHEN EXISTS (SELECT 1 FROM evals e WHERE e.blob_id = b.id) THEN false
+                        WHEN EXISTS (SELECT 1 FROM run_artifacts a WHERE a.blob_id = b.id)
+                            THEN false
+                        WHEN EXISTS (SELECT 1 FROM dvc_objects d WHERE d.blob_id = b.id) THEN false
+                        WHEN EXISTS (SELECT 1 FROM jobs j WHERE j.blob_id = b.id) THEN false
+                        WHEN EXISTS (
+                            SELECT 1 FROM evals e
+                            WHERE e.user_id = b.user_id
+                                AND (jsonb_path_exists(e.args, '$.** ? (@ == $h)',
+                                        jsonb_build_object('h', b.content_hash::text))
+                                    OR jsonb_path_exists(e.result_json, '$.** ? (@ == $h)',
+                                        jsonb_build_object('h', b.content_hash::text)))
+                        ) THEN false
+                        ELSE true
+                    END AS orphaned
+                FROM blobs b
+                WHERE b.content_hash = ANY($1)
+            )
+            SELECT content_hash AS "content_hash!", storage_region, storage_bucket,
+                storage_prefix,
+                array_agg(id) FILTER (WHERE orphaned) AS "ids!",
+                bool_and(orphaned) AS "all!"
+            FROM candidates
+            GROUP BY content_hash, storage_region, storage_bucket, storage_prefix
+            HAVING bool_or(orphaned)
+            "#,
+            &page,
+            grace_days as i32,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        for orphans in orphans {
+            match reclaim(state, &orphans, dry_run).await {
+                Ok(bytes) => {
+                    sweep.blobs += orphans.ids.len() as u64;
+                    sweep.bytes += bytes;
+                }
+                Err(e) => log::warn!(
+                    "error collecting unreferenced blob {}: {:?}",
+                    orphans.content_hash,
+                    e
+                ),
+            }
+        }
+
+        after = last;
+    }
+
+    Ok(sweep)
+}
+
+/// Deletes a BLOB's unreferenced rows, and its object if none are left, returning the bytes the
+/// object took up if it was deleted (or would have been, in a dry run).
+async fn reclaim(state: &AppStateRaw, orphans: &Orphans, dry_run: bool) -> Result<u64, StoreError> {
+    let target = Target {
+        region: orphans.storage_region.clone(),
+        bucket: orphans.storage_bucket.clone(),
+        prefix: orphans.storage_prefix.clone(),
+    };
+    let hash = Hash::from_hex(&orphans.content_hash).ok();
+
+    // An object is only deleted if it hasn't been written within the grace period, in case it
+    // has just been uploaded again under a row the page didn't see.
+    let object = match (orphans.all, hash) {
+        (true, Some(hash)) => state
+            .blob_store
+            .find_blob_written(&target, hash)
+            .await?
+            .filter(|(_, written)| {
+                past_grace(
+                    *written,
+                    Utc::now().timestamp(),
+                    state.config.blob_gc_grace_days,
+                )
+            })
+            .map(|(length, _)| (hash, length as u64)),
+        _ => None,
+    };
+
+    if dry_run {
+        log::info!(
+            "would delete {} unreferenced rows of blob {}{}",
+            orphans.ids.len(),
+            orphans.content_hash,
+            if object.is_some() {
+                " and its object"
+            } else {
+                ""
+            }
+        );
+        return Ok(object.map(|(_, length)| length).unwrap_or_default());
+    }
+
+    // If a row has been referenced since it was looked at, the foreign keys stop it being deleted,
+    // and the BLOB is left for the next pass.
+    let mut tx = state.db_conn.begin().await?;
+    query!("DELETE FROM blobs WHERE id = ANY($1)", &orphans.ids)
+        .execute(&mut tx)
+        .await?;
+    let remaining = query_scalar!(
+        r#"
+        SELECT count(*) AS "count!"
+        FROM blobs
+        WHERE content_hash = $1
+            AND storage_region IS NOT DISTINCT FROM $2
+            AND storage_bucket IS NOT DISTINCT FROM $3
+            AND storage_prefix IS NOT DISTINCT FROM $4
+        "#,
+        orphans.content_hash,
+        orphans.storage_region,
+        orphans.storage_bucket,
+        orphans.storage_prefix,
+    )
+    .fetch_one(&mut tx)
+    .await?;
+    // An object which an upload has just found stored may be about to be owned again.
+    let mut shared = false;
+    if remaining == 0 {
+        query!(
+            r#"
+            DELETE FROM blob_objects
+            WHERE content_hash = $1
+                AND coalesce(storage_region, '') = coalesce($2, '')
+                AND coalesce(storage_bucket, '') = coalesce($3, '')
+                AND storage_prefix IS NOT DISTINCT FROM $4
+                AND used_dt < now() - make_interval(days => $5)
+            "#,
+            orphans.content_hash,
+            orphans.storage_region,
+            orphans.storage_bucket,
+            orphans.storage_prefix,
+            state.config.blob_gc_grace_days as i32,
+        )
+        .execute(&mut tx)
+        .await?;
+        shared = query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM blob_objects
+                WHERE content_hash = $1
+                    AND coalesce(storage_region, '') = coalesce($2, '')
+                    AND coalesce(storage_bucket, '') = coalesce($3, '')
+                    AND storage_prefix IS NOT DISTINCT FROM $4
+            ) AS "shared!"
+            "#,
+            orphans.content_hash,
+            orphans.storage_region,
+            orphans.storage_bucket,
+            orphans.storage_prefix,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+    }
+    // Stats and summaries are keyed by content hash, wherever the BLOB is stored.
+    query!(
+        r#"
+        DELETE FROM blob_stats
+        WHERE content_hash = $1
+            AND NOT EXISTS (SELECT 1 FROM blobs WHERE content_hash = $1)
+        "#,
+        orphans.content_hash,
+    )
+    .execute(&mut tx)
+    .await?;
+    query!(
+        r#"
+        DELETE FROM tensor_summaries
+        WHERE content_hash = $1
+            AND NOT EXISTS (SELECT 1 FROM blobs WHERE content_hash = $1)
+        "#,
+        orphans.content_hash,
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    match object {
+        Some((hash, length)) if remaining == 0 && !shared => {
+            state.blob_store.delete_blob(&target, hash).await?;
+            Ok(length)
+        }
+        _ => Ok(0),
+    }
+}
@@ -0,0 +1,158 @@
+use crate::leader::Singleton;
+use crate::models::blob_backfill::PENDING_GRACE_HOURS;
+use crate::models::dead_letter::DeadLetterOp;
+use crate::persisters::dead_letter::{record_for, DeadLetterInsert};
+use crate::persisters::s3store::Target;
+use crate::state::AppStateRaw;
+
+use blake3::Hash;
+use std::time::Duration;
+
+/// How often BLOBs which aren't known to be stored are looked for.
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(600);
+
+/// How many BLOBs are looked for each time.
+const BATCH_SIZE: i64 = 500;
+
+/// How often a BLOB which has been reported missing is looked for again, in hours.
+const MISSING_RECHECK_HOURS: i32 = 24;
+
+struct Unsettled {
+    content_hash: String,
+    storage_region: Option<String>,
+    storage_bucket: Option<String>,
+    storage_prefix: Option<String>,
+}
+
+/// Periodically looks in S3 for the objects of BLOBs which are pending or missing, marking them
+/// available once they're found, and pending ones missing once they're past their grace period,
+/// or awaiting ones once they're past their due date. A dead letter is recorded for each BLOB as it goes missing.
+pub async fn run(state: AppStateRaw) {
+    let mut interval = tokio::time::interval(RECONCILE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        if !state.leadership.leads(Singleton::BlobReconcile).await {
+            continue;
+        }
+
+        if let Err(e) = reconcile(&state).await {
+            log::error!("error reconciling blob statuses: {:?}", e);
+        }
+    }
+}
+
+async fn reconcile(state: &AppStateRaw) -> Result<(), sqlx::Error> {
+    // Rows of the same BLOB owned by different users are looked for together, unless they're
+    // stored in different places. Archived BLOBs are reported as such whatever their status.
+    let batch = query_as!(
+        Unsettled,
+        r#"
+        SELECT content_hash, storage_region, storage_bucket, storage_prefix
+        FROM blobs
+        WHERE storage_class = 'STANDARD'
+            AND (
+                (object_status IN ('pending', 'awaiting_payload') AND (checked_dt IS NULL
+                    OR checked_dt < now() - make_interval(secs => $1)))
+                OR (object_status = 'missing'
+                    AND checked_dt < now() - make_interval(hours => $2))
+            )
+        GROUP BY content_hash, storage_region, storage_bucket, storage_prefix
+        ORDER BY min(checked_dt) NULLS FIRST
+        LIMIT $3
+        "#,
+        RECONCILE_INTERVAL.as_secs_f64(),
+        MISSING_RECHECK_HOURS,
+        BATCH_SIZE,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    let mut found = 0;
+    let mut missing = 0;
+    for blob in batch {
+        let target = Target {
+            region: blob.storage_region.clone(),
+            bucket: blob.storage_bucket.clone(),
+            prefix: blob.storage_prefix.clone(),
+        };
+        // A hash which isn't valid hex can't have been stored under.
+        let stored = match Hash::from_hex(&blob.content_hash) {
+            Ok(hash) => match state.blob_store.find_blob(&target, hash).await {
+                Ok(length) => length.is_some(),
+                Err(e) => {
+                    log::warn!("error looking for blob {}: {:?}", blob.content_hash, e);
+                    continue;
+                }
+            },
+            Err(_) => false,
+        };
+
+        // `prev` is joined to see each row's status before the update.
+        let res = query!(
+            r#"
+            UPDATE blobs b
+            SET object_status = CASE
+                    WHEN $4 THEN 'available'
+                    WHEN b.object_status = 'awaiting_payload' THEN CASE
+                        WHEN b.payload_due_dt < now() THEN 'missing'
+                        ELSE 'awaiting_payload'
+                    END
+                    WHEN b.create_dt < now() - make_interval(hours => $5) THEN 'missing'
+                    ELSE 'pending'
+                END,
+                checked_dt = now()
+            FROM blobs prev
+            WHERE prev.id = b.id
+                AND b.content_hash = $1
+                AND b.storage_region IS NOT DISTINCT FROM $2
+                AND b.storage_bucket IS NOT DISTINCT FROM $3
+                AND b.storage_prefix IS NOT DISTINCT FROM $6
+                AND b.object_status <> 'available'
+                AND b.storage_class = 'STANDARD'
+            RETURNING b.user_id, b.object_status, prev.object_status AS prev_status
+            "#,
+            blob.content_hash,
+            blob.storage_region,
+            blob.storage_bucket,
+            stored,
+            PENDING_GRACE_HOURS,
+            blob.storage_prefix,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        if stored {
+            found += 1;
+        } else if res.iter().any(|r| r.object_status == "missing") {
+            missing += 1;
+        }
+
+        // The eval or artifact was recorded, but its BLOB never arrived.
+        for row in res {
+            if row.object_status == "missing" && row.prev_status != "missing" {
+                let error = match row.prev_status.as_str() {
+                    "awaiting_payload" => "not stored by its due date".to_string(),
+                    _ => format!("not stored within {} hours", PENDING_GRACE_HOURS),
+                };
+                let letter = DeadLetterInsert {
+                    content_hash: blob.content_hash.clone(),
+                    target: target.clone(),
+                    op: DeadLetterOp::MissingBlob,
+                    error,
+                };
+                record_for(state, row.user_id, letter).await;
+            }
+        }
+    }
+
+    if found > 0 || missing > 0 {
+        log::info!(
+            "reconciled blob statuses: {} found, {} missing",
+            found,
+            missing
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,179 @@
+use crate::leader::Singleton;
+use crate::models::blob_stats::Format;
+use crate::persisters::s3store::{StoreError, Target};
+use crate::state::AppStateRaw;
+use crate::tabular::{self, SNIFF_LEN};
+
+use blake3::Hash;
+use sqlx::types::JsonValue;
+use std::time::Duration;
+
+/// How many BLOBs are looked at on each pass.
+const BATCH_SIZE: i64 = 10;
+
+/// The largest BLOB, in bytes, which stats are computed for. The whole table is held in memory.
+const MAX_BLOB_LEN: i64 = 256 * 1024 * 1024;
+
+#[derive(Debug)]
+enum StatsError {
+    Store(StoreError),
+    Sqlx(sqlx::Error),
+}
+
+impl From<StoreError> for StatsError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<sqlx::Error> for StatsError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+impl From<blake3::HexError> for StatsError {
+    fn from(e: blake3::HexError) -> Self {
+        Self::Store(e.into())
+    }
+}
+
+/// The outcome of looking at a BLOB, as recorded in `blob_stats`.
+struct Outcome {
+    status: &'static str,
+    format: Option<Format>,
+    row_count: Option<i64>,
+    columns: Option<JsonValue>,
+    error: Option<String>,
+}
+
+impl Outcome {
+    fn unsupported(format: Option<Format>, error: &str) -> Self {
+        Self {
+            status: "unsupported",
+            format,
+            row_count: None,
+            columns: None,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// Periodically computes stats for BLOBs which haven't been looked at yet.
+pub async fn run(state: AppStateRaw) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.blob_stats_interval_secs));
+
+    loop {
+        interval.tick().await;
+        if !state.leadership.leads(Singleton::BlobStats).await {
+            continue;
+        }
+
+        if let Err(e) = compute_pending(&state).await {
+            log::error!("error computing blob stats: {:?}", e);
+        }
+    }
+}
+
+async fn compute_pending(state: &AppStateRaw) -> Result<(), StatsError> {
+    // Archived BLOBs can't be read until they're restored, so they wait until then.
+    // The same content can be stored by several users, in different places; any copy will do.
+    let pending = query!(
+        r#"
+        SELECT DISTINCT ON (b.content_hash) b.content_hash, b.storage_region, b.storage_bucket,
+            b.storage_prefix
+        FROM blobs b
+        WHERE b.storage_class = 'STANDARD'
+            AND NOT EXISTS (
+                SELECT 1 FROM blob_stats s
+                WHERE s.content_hash = b.content_hash
+            )
+        LIMIT $1
+        "#,
+        BATCH_SIZE,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    for blob in pending {
+        let content_hash = blob.content_hash;
+        let target = Target {
+            region: blob.storage_region,
+            bucket: blob.storage_bucket,
+            prefix: blob.storage_prefix,
+        };
+        let outcome = examine(state, &target, Hash::from_hex(&content_hash)?).await?;
+
+        query!(
+            r#"
+            INSERT INTO blob_stats (content_hash, status, format, row_count, columns, error)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (content_hash) DO NOTHING
+            "#,
+            content_hash,
+            outcome.status,
+            outcome.format.map(|f| f.as_str()),
+            outcome.row_count,
+            outcome.columns,
+            outcome.error,
+        )
+        .execute(&state.db_conn)
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn examine(state: &AppStateRaw, target: &Target, hash: Hash) -> Result<Outcome, StatsError> {
+    let len = state.blob_store.blob_length(target, hash).await?;
+    if len == 0 {
+        return Ok(Outcome::unsupported(None, "empty"));
+    }
+
+    // Only the start of the BLOB is needed to tell whether it's tabular at all.
+    let sample = state
+        .blob_store
+        .retrieve_blob_prefix(target, hash, SNIFF_LEN, len)
+        .await?;
+    let format = match tabular::sniff(&sample) {
+        Some(format) => format,
+        None => return Ok(Outcome::unsupported(None, "not a tabular format")),
+    };
+    if len > MAX_BLOB_LEN {
+        return Ok(Outcome::unsupported(Some(format), "too large"));
+    }
+
+    let bytes = state.blob_store.retrieve_blob_bytes(target, hash).await?;
+
+    let res = tokio::task::spawn_blocking(move || tabular::compute(format, bytes)).await;
+
+    let outcome = match res {
+        Ok(Ok((row_count, columns))) => Outcome {
+            status: "done",
+            format: Some(format),
+            row_count: Some(row_count),
+            columns: serde_json::to_value(columns).ok(),
+            error: None,
+        },
+        Ok(Err(e)) => Outcome {
+            status: "failed",
+            format: Some(format),
+            row_count: None,
+            columns: None,
+            error: Some(e.to_string()),
+        },
+        Err(e) => {
+            log::error!("blob stats task panicked: {:?}", e);
+            Outcome {
+                status: "failed",
+                format: Some(format),
+                row_count: None,
+                columns: None,
+                error: Some("internal error".to_string()),
+            }
+        }
+    };
+
+    Ok(outcome)
+}
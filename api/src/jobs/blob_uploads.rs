@@ -0,0 +1,34 @@
+use crate::leader::Singleton;
+use crate::persisters::{blob_upload, presigned_upload};
+use crate::state::AppStateRaw;
+
+use std::time::Duration;
+
+/// How often expired uploads are aborted.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Periodically aborts uploads in parts which have expired, so that S3 doesn't keep their parts,
+/// and abandons expired uploads straight to S3, deleting whatever they staged.
+pub async fn run(state: AppStateRaw) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        if !state.leadership.leads(Singleton::BlobUploads).await {
+            continue;
+        }
+
+        match blob_upload::prune_expired(&state).await {
+            Ok(pruned) if pruned > 0 => log::info!("aborted {} expired uploads", pruned),
+            Ok(_) => {}
+            Err(e) => log::error!("error aborting expired uploads: {:?}", e),
+        }
+        match presigned_upload::prune_expired(&state).await {
+            Ok(pruned) if pruned > 0 => {
+                log::info!("abandoned {} expired presigned uploads", pruned)
+            }
+            Ok(_) => {}
+            Err(e) => log::error!("error abandoning expired presigned uploads: {:?}", e),
+        }
+    }
+}
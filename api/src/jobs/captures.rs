@@ -0,0 +1,30 @@
+use crate::leader::Singleton;
+use crate::state::AppStateRaw;
+
+use std::time::Duration;
+
+/// How often expired captures are deleted.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Periodically deletes captured requests which have expired.
+pub async fn run(state: AppStateRaw) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        if !state.leadership.leads(Singleton::Captures).await {
+            continue;
+        }
+
+        match query!("DELETE FROM request_captures WHERE expire_dt <= now()")
+            .execute(&state.db_conn)
+            .await
+        {
+            Ok(res) if res.rows_affected() > 0 => {
+                log::info!("deleted {} expired captures", res.rows_affected())
+            }
+            Ok(_) => {}
+            Err(e) => log::error!("error deleting expired captures: {:?}", e),
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use crate::leader::Singleton;
+use crate::state::AppStateRaw;
+
+use std::time::Duration;
+
+/// How often old changes are pruned.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Periodically prunes the change feed of eval keys, recording how far it got so that clients
+/// reading from before it are told their cursor has expired.
+pub async fn run(state: AppStateRaw) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        if !state.leadership.leads(Singleton::Changes).await {
+            continue;
+        }
+
+        match query_scalar!(
+            r#"
+            WITH d AS (
+                DELETE FROM eval_changes
+                WHERE create_dt < now() - make_interval(days => $1)
+                RETURNING id
+            ), u AS (
+                UPDATE eval_changes_pruned
+                SET pruned_to = greatest(pruned_to, (SELECT max(id) FROM d))
+                WHERE EXISTS (SELECT 1 FROM d)
+            )
+            SELECT count(*) AS "count!"
+            FROM d
+            "#,
+            state.config.change_retention_days as i32,
+        )
+        .fetch_one(&state.db_conn)
+        .await
+        {
+            Ok(pruned) if pruned > 0 => log::info!("pruned {} eval changes", pruned),
+            Ok(_) => {}
+            Err(e) => log::error!("error pruning eval changes: {:?}", e),
+        }
+    }
+}
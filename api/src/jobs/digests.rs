@@ -0,0 +1,259 @@
+use crate::leader::Singleton;
+use crate::models::digest::{
+    last_week_start, Digest, DigestFn, DigestRun, DIGEST_NOTABLE_RUNS, DIGEST_TOP_FUNCTIONS,
+};
+use crate::notify::digest::render;
+use crate::state::AppStateRaw;
+
+use ::chrono::Duration as ChronoDuration;
+use sqlx::types::{
+    chrono::{self, Utc},
+    Uuid,
+};
+use std::time::Duration;
+
+/// How often digests which are due are looked for.
+const CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// How many digests are sent each time.
+const BATCH_SIZE: i64 = 100;
+
+/// Who a digest is sent to, and what it's of: a user's own week, or an org's.
+struct Recipient {
+    recipient_id: Uuid,
+    email: String,
+    org_id: Option<Uuid>,
+    /// The user's login, or the org's name.
+    name: String,
+}
+
+/// Sends each user a digest of their last full week, and each org's owner one of its members'
+/// week, unless they've opted out, once a week. Nothing is sent when no mailer is configured, or
+/// when nothing happened over the week.
+pub async fn run(state: AppStateRaw) {
+    if state.config.mailer_url.is_none() {
+        return;
+    }
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        if !state.leadership.leads(Singleton::Digests).await {
+            continue;
+        }
+
+        let period_start = last_week_start(Utc::now());
+        loop {
+            match send_batch(&state, period_start).await {
+                Ok(0) => break,
+                Ok(settled) => log::info!("settled {} weekly digests", settled),
+                Err(e) => {
+                    log::error!("error sending weekly digests: {:?}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Sends the digests of the week which are due, returning how many were settled: sent, or found
+/// to have nothing in them. Those which couldn't be sent are left for the next check, so that a
+/// failing mailer stops the batches rather than being retried straight away.
+async fn send_batch(
+    state: &AppStateRaw,
+    period_start: chrono::DateTime<Utc>,
+) -> Result<usize, sqlx::Error> {
+    let recipients = query_as!(
+        Recipient,
+        r#"
+        WITH recipients AS (
+            SELECT u.id AS recipient_id, u.gh_email AS email, NULL::uuid AS org_id,
+                u.gh_login AS name
+            FROM users u
+            UNION ALL
+            SELECT u.id, u.gh_email, o.id, o.name
+            FROM orgs o
+            JOIN users u ON u.id = o.owner_id
+        )
+        SELECT r.recipient_id AS "recipient_id!", r.email AS "email!", r.org_id,
+            r.name AS "name!"
+        FROM recipients r
+        LEFT JOIN user_preferences p ON p.user_id = r.recipient_id
+        WHERE r.email IS NOT NULL
+            AND coalesce(p.digest_emails, true)
+            AND NOT EXISTS (
+                SELECT 1 FROM digest_sends d
+                WHERE d.recipient_id = r.recipient_id
+                    AND d.org_id IS NOT DISTINCT FROM r.org_id
+                    AND d.period_start = $1
+            )
+        LIMIT $2
+        "#,
+        period_start,
+        BATCH_SIZE,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    let mut settled = 0;
+    for recipient in recipients {
+        // Claiming the digest first means it's only sent once, even with several servers running.
+        let claimed = query_scalar!(
+            r#"
+            INSERT INTO digest_sends (recipient_id, org_id, period_start)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            RETURNING id
+            "#,
+            recipient.recipient_id,
+            recipient.org_id,
+            period_start,
+        )
+        .fetch_optional(&state.db_conn)
+        .await?;
+        let claimed = match claimed {
+            Some(id) => id,
+            None => continue,
+        };
+
+        let digest = digest(state, &recipient, period_start).await?;
+        if digest.is_empty() {
+            settled += 1;
+            continue;
+        }
+        let (subject, text) = render(&digest);
+        if let Err(e) = state
+            .notifier
+            .email(&recipient.email, &subject, &text)
+            .await
+        {
+            log::warn!(
+                "error sending weekly digest to {}: {:?}",
+                recipient.recipient_id,
+                e
+            );
+            // Unclaimed, it's tried again next time.
+            query!("DELETE FROM digest_sends WHERE id = $1", claimed)
+                .execute(&state.db_conn)
+                .await?;
+            continue;
+        }
+        settled += 1;
+    }
+
+    Ok(settled)
+}
+
+/// What happened over the week to the recipient's own evals, runs and BLOBs, or to those of the
+/// org's owner and active members.
+async fn digest(
+    state: &AppStateRaw,
+    recipient: &Recipient,
+    period_start: chrono::DateTime<Utc>,
+) -> Result<Digest, sqlx::Error> {
+    let period_end = period_start + ChronoDuration::weeks(1);
+    let user_ids = match recipient.org_id {
+        None => vec![recipient.recipient_id],
+        Some(org_id) => {
+            query_scalar!(
+                r#"
+                SELECT owner_id AS "user_id!" FROM orgs WHERE id = $1
+                UNION
+                SELECT user_id FROM org_members WHERE org_id = $1 AND active AND accepted
+                "#,
+                org_id,
+            )
+            .fetch_all(&state.db_conn)
+            .await?
+        }
+    };
+
+    let usage = query!(
+        r#"
+        SELECT coalesce(sum(hits + local_hits), 0)::bigint AS "hits!",
+            coalesce(sum(misses), 0)::bigint AS "misses!",
+            coalesce(sum(saved_time), 0)::bigint AS "saved_time!"
+        FROM eval_usage
+        WHERE user_id = ANY($1)
+            AND period_start >= $2
+            AND period_start < $3
+        "#,
+        &user_ids,
+        period_start,
+        period_end,
+    )
+    .fetch_one(&state.db_conn)
+    .await?;
+
+    let top_functions = query_as!(
+        DigestFn,
+        r#"
+        SELECT fn_key,
+            sum(hits + local_hits)::bigint AS "hits!",
+            sum(saved_time)::bigint AS "saved_time!"
+        FROM eval_usage
+        WHERE user_id = ANY($1)
+            AND period_start >= $2
+            AND period_start < $3
+        GROUP BY fn_key
+        HAVING sum(saved_time) > 0
+        ORDER BY 3 DESC, fn_key
+        LIMIT $4
+        "#,
+        &user_ids,
+        period_start,
+        period_end,
+        DIGEST_TOP_FUNCTIONS,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    let storage = query!(
+        r#"
+        SELECT count(*) AS "blobs!", coalesce(sum(content_length), 0)::bigint AS "bytes!"
+        FROM blobs
+        WHERE user_id = ANY($1)
+            AND create_dt >= $2
+            AND create_dt < $3
+        "#,
+        &user_ids,
+        period_start,
+        period_end,
+    )
+    .fetch_one(&state.db_conn)
+    .await?;
+
+    // Failed runs first, then the longest to succeed.
+    let notable_runs = query_as!(
+        DigestRun,
+        r#"
+        SELECT id, name, fn_key, state,
+            extract(epoch FROM update_dt - create_dt)::bigint AS "duration_secs!"
+        FROM runs
+        WHERE user_id = ANY($1)
+            AND update_dt >= $2
+            AND update_dt < $3
+            AND state IN ('succeeded', 'failed')
+        ORDER BY state = 'failed' DESC, update_dt - create_dt DESC
+        LIMIT $4
+        "#,
+        &user_ids,
+        period_start,
+        period_end,
+        DIGEST_NOTABLE_RUNS,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    Ok(Digest {
+        name: recipient.name.clone(),
+        period_start,
+        hits: usage.hits,
+        misses: usage.misses,
+        saved_time: usage.saved_time,
+        top_functions,
+        blobs_added: storage.blobs,
+        bytes_added: storage.bytes,
+        notable_runs,
+    })
+}
@@ -0,0 +1,101 @@
+use crate::embed::{to_vector_literal, EmbedError};
+use crate::leader::Singleton;
+use crate::state::AppStateRaw;
+
+use sqlx::types::{JsonValue, Uuid};
+use std::time::Duration;
+
+/// How many evals are embedded on each pass.
+const BATCH_SIZE: i64 = 50;
+
+#[derive(Debug)]
+enum EmbeddingError {
+    Embed(EmbedError),
+    Sqlx(sqlx::Error),
+}
+
+impl From<EmbedError> for EmbeddingError {
+    fn from(e: EmbedError) -> Self {
+        Self::Embed(e)
+    }
+}
+
+impl From<sqlx::Error> for EmbeddingError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+struct PendingEval {
+    id: Uuid,
+    args: JsonValue,
+}
+
+/// Periodically embeds the arguments of evals which haven't been embedded with the configured
+/// model yet. Does nothing when no embedding provider is configured.
+pub async fn run(state: AppStateRaw) {
+    if !state.embedder.is_enabled() {
+        return;
+    }
+
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.embedding_interval_secs));
+
+    loop {
+        interval.tick().await;
+        if !state.leadership.leads(Singleton::Embeddings).await {
+            continue;
+        }
+
+        if let Err(e) = embed_pending(&state).await {
+            log::error!("error embedding eval arguments: {:?}", e);
+        }
+    }
+}
+
+async fn embed_pending(state: &AppStateRaw) -> Result<(), EmbeddingError> {
+    let model = state.embedder.model();
+
+    // The most recent evals are embedded first, as they're the likeliest to be searched for.
+    let pending = query_as!(
+        PendingEval,
+        r#"
+        SELECT e.id, e.args AS "args!"
+        FROM evals e
+        WHERE e.args IS NOT NULL
+            AND NOT EXISTS (
+                SELECT 1 FROM eval_embeddings m
+                WHERE m.eval_id = e.id
+                    AND m.model = $1
+            )
+        ORDER BY e.start_time DESC
+        LIMIT $2
+        "#,
+        model,
+        BATCH_SIZE,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    for eval in pending {
+        let embedding = state.embedder.embed_args(&eval.args).await?;
+
+        query!(
+            r#"
+            INSERT INTO eval_embeddings (eval_id, model, embedding)
+            VALUES ($1, $2, $3::text::vector)
+            ON CONFLICT (eval_id) DO UPDATE
+                SET model = EXCLUDED.model,
+                    embedding = EXCLUDED.embedding,
+                    create_dt = current_timestamp
+            "#,
+            eval.id,
+            model,
+            to_vector_literal(&embedding),
+        )
+        .execute(&state.db_conn)
+        .await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,39 @@
+use crate::fanout::{Event, CHANNELS};
+use crate::state::AppStateRaw;
+
+use sqlx::postgres::PgListener;
+use std::time::Duration;
+
+/// How long to wait before listening again after losing the listener.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Listens for Postgres notifications, and sends them on to their subscribers. See
+/// [`crate::fanout`].
+pub async fn run(state: AppStateRaw) {
+    loop {
+        if let Err(e) = listen(&state).await {
+            log::error!("error listening for notifications: {:?}", e);
+        }
+        state.fanout.missed();
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn listen(state: &AppStateRaw) -> Result<(), sqlx::Error> {
+    let mut listener = PgListener::connect_with(&state.db_conn).await?;
+    listener.listen_all(CHANNELS).await?;
+    // Anything notified before we started listening was missed.
+    state.fanout.missed();
+
+    loop {
+        match listener.try_recv().await? {
+            Some(notification) => state.fanout.publish(
+                notification.channel(),
+                Event::Notification(notification.payload().to_string()),
+            ),
+            // The connection was lost, and is re-established by the next `try_recv`. Any
+            // notifications in the meantime were missed.
+            None => state.fanout.missed(),
+        }
+    }
+}
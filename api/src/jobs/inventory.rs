@@ -0,0 +1,243 @@
+use crate::leader::Singleton;
+use crate::models::storage_report::{listed_hash, INVENTORY_LOCK};
+use crate::persisters::chunked_store::CHUNKED;
+use crate::persisters::s3store::{StoreError, Target};
+use crate::state::AppStateRaw;
+
+use std::time::Duration;
+
+#[derive(Debug)]
+enum InventoryError {
+    Store(StoreError),
+    Sqlx(sqlx::Error),
+}
+
+impl From<StoreError> for InventoryError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<sqlx::Error> for InventoryError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+/// Periodically takes an inventory of the objects stored; see [`take`].
+pub async fn run(state: AppStateRaw) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.inventory_interval_secs));
+
+    loop {
+        interval.tick().await;
+        if !state.leadership.leads(Singleton::Inventory).await {
+            continue;
+        }
+        take(state.clone()).await;
+    }
+}
+
+/// Lists every object stored where BLOBs are recorded as stored, and reports, for each user, what
+/// their BLOBs should take up against what was found. If an inventory is already being taken, it
+/// returns straight away.
+pub async fn take(state: AppStateRaw) {
+    if let Err(e) = inventory(&state).await {
+        log::error!("error taking storage inventory: {:?}", e);
+    }
+}
+
+async fn inventory(state: &AppStateRaw) -> Result<(), InventoryError> {
+    // Advisory locks belong to a connection, so one is held for the whole inventory.
+    let mut conn = state.db_conn.acquire().await?;
+    let locked = query_scalar!(
+        r#"SELECT pg_try_advisory_lock($1) AS "locked!""#,
+        INVENTORY_LOCK
+    )
+    .fetch_one(&mut conn)
+    .await?;
+    if !locked {
+        log::info!("storage inventory is already being taken");
+        return Ok(());
+    }
+
+    // The lock is released however the inventory ends.
+    let res = async {
+        let id = query_scalar!("INSERT INTO storage_inventories DEFAULT VALUES RETURNING id")
+            .fetch_one(&state.db_conn)
+            .await?;
+        let res = list_and_report(state, id).await;
+        if let Err(e) = &res {
+            query!(
+                r#"
+                UPDATE storage_inventories
+                SET status = 'failed', error = $2, finish_dt = now()
+                WHERE id = $1
+                "#,
+                id,
+                format!("{:?}", e),
+            )
+            .execute(&state.db_conn)
+            .await?;
+        }
+        res
+    }
+    .await;
+
+    query_scalar!("SELECT pg_advisory_unlock($1)", INVENTORY_LOCK)
+        .fetch_one(&mut conn)
+        .await?;
+
+    res
+}
+
+async fn list_and_report(state: &AppStateRaw, id: i64) -> Result<(), InventoryError> {
+    // Everywhere BLOBs are recorded as stored, and wherever new ones are stored by default.
+    let mut targets: Vec<Target> = query!(
+        r#"
+        SELECT DISTINCT storage_region, storage_bucket, storage_prefix
+        FROM blobs
+        "#,
+    )
+    .fetch_all(&state.db_conn)
+    .await?
+    .into_iter()
+    .map(|row| Target {
+        region: row.storage_region,
+        bucket: row.storage_bucket,
+        prefix: row.storage_prefix,
+    })
+    .collect();
+    if !targets.contains(&Target::server()) {
+        targets.push(Target::server());
+    }
+
+    for target in &targets {
+        list(state, id, target).await?;
+    }
+
+    report(state, id).await?;
+
+    log::info!("took storage inventory {}", id);
+
+    Ok(())
+}
+
+/// Records the BLOBs' objects stored under the target's prefix, a page at a time.
+async fn list(state: &AppStateRaw, id: i64, target: &Target) -> Result<(), InventoryError> {
+    let mut continuation = None;
+    loop {
+        let (objects, next) = state.blob_store.list_objects(target, continuation).await?;
+        let (hashes, sizes): (Vec<String>, Vec<i64>) = objects
+            .into_iter()
+            .filter_map(|(key, size)| Some((listed_hash(&key, target.prefix.as_deref())?, size)))
+            .unzip();
+
+        query!(
+            r#"
+            INSERT INTO inventory_objects (inventory_id, storage_region, storage_bucket,
+                storage_prefix, content_hash, size)
+            SELECT $1, $2, $3, $4, o.content_hash, o.size
+            FROM unnest($5::text[], $6::bigint[]) AS o(content_hash, size)
+            "#,
+            id,
+            target.region,
+            target.bucket,
+            target.prefix,
+            &hashes,
+            &sizes,
+        )
+        .execute(&state.db_conn)
+        .await?;
+
+        match next {
+            Some(next) => continuation = Some(next),
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Compares what was listed with the BLOBs recorded as stored, and finishes the inventory. BLOBs
+/// recorded after the inventory started may not have been listed, so they're left for the next.
+/// Compressed BLOBs are expected to take up their length as stored. BLOBs stored in chunks have no
+/// object of their own, so they aren't missing, and chunks are owned by the BLOBs they're in.
+async fn report(state: &AppStateRaw, id: i64) -> Result<(), sqlx::Error> {
+    let mut tx = state.db_conn.begin().await?;
+
+    query!(
+        r#"
+        INSERT INTO storage_reports (inventory_id, user_id, expected_objects, expected_bytes,
+            actual_bytes, missing_objects, mismatched_objects)
+        SELECT $1, b.user_id, count(*),
+            coalesce(sum(coalesce(b.stored_length, b.content_length)), 0)::bigint,
+            coalesce(sum(o.size), 0)::bigint,
+            count(*) FILTER (WHERE o.content_hash IS NULL
+                AND b.compression IS DISTINCT FROM $2),
+            count(*) FILTER (WHERE o.size <> coalesce(b.stored_length, b.content_length))
+        FROM blobs b
+        JOIN storage_inventories i
+            ON i.id = $1
+        LEFT JOIN inventory_objects o
+            ON o.inventory_id = $1
+            AND o.content_hash = b.content_hash
+            AND o.storage_region IS NOT DISTINCT FROM b.storage_region
+            AND o.storage_bucket IS NOT DISTINCT FROM b.storage_bucket
+            AND o.storage_prefix IS NOT DISTINCT FROM b.storage_prefix
+        WHERE b.object_status = 'available'
+            AND b.create_dt < i.start_dt
+        GROUP BY b.user_id
+        "#,
+        id,
+        CHUNKED,
+    )
+    .execute(&mut tx)
+    .await?;
+
+    query!(
+        r#"
+        UPDATE storage_inventories i
+        SET status = 'finished',
+            finish_dt = now(),
+            objects = t.objects,
+            bytes = t.bytes,
+            unowned_objects = t.unowned_objects,
+            unowned_bytes = t.unowned_bytes
+        FROM (
+            SELECT count(*) AS objects,
+                coalesce(sum(o.size), 0)::bigint AS bytes,
+                count(*) FILTER (WHERE NOT o.owned) AS unowned_objects,
+                coalesce(sum(o.size) FILTER (WHERE NOT o.owned), 0)::bigint AS unowned_bytes
+            FROM (
+                SELECT o.size, EXISTS (
+                    SELECT 1 FROM blobs b
+                    WHERE b.content_hash = o.content_hash
+                        AND b.storage_region IS NOT DISTINCT FROM o.storage_region
+                        AND b.storage_bucket IS NOT DISTINCT FROM o.storage_bucket
+                        AND b.storage_prefix IS NOT DISTINCT FROM o.storage_prefix
+                ) OR EXISTS (
+                    SELECT 1 FROM blob_chunks c
+                    WHERE c.chunk_hash = o.content_hash
+                        AND c.storage_region IS NOT DISTINCT FROM o.storage_region
+                        AND c.storage_bucket IS NOT DISTINCT FROM o.storage_bucket
+                        AND c.storage_prefix IS NOT DISTINCT FROM o.storage_prefix
+                ) AS owned
+                FROM inventory_objects o
+                WHERE o.inventory_id = $1
+            ) o
+        ) t
+        WHERE i.id = $1
+        "#,
+        id,
+    )
+    .execute(&mut tx)
+    .await?;
+
+    // Only the latest inventory's objects are kept.
+    query!("DELETE FROM inventory_objects WHERE inventory_id < $1", id)
+        .execute(&mut tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
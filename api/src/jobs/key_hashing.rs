@@ -0,0 +1,75 @@
+use crate::keys::StoredKey;
+use crate::state::AppStateRaw;
+
+use std::time::Duration;
+
+/// How many keys are hashed at a time.
+const BATCH_SIZE: i64 = 100;
+
+/// How long to wait before trying again when keys can't be hashed.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+struct UnhashedKey {
+    key: String,
+}
+
+/// Hashes the API keys which were issued before keys were hashed at rest, and are still stored
+/// whole. See [`crate::keys`]. Stops once there are none left.
+pub async fn run(state: AppStateRaw) {
+    loop {
+        match hash_batch(&state).await {
+            Ok(0) => return,
+            Ok(hashed) => log::info!("hashed {} api keys", hashed),
+            Err(e) => {
+                log::error!("error hashing api keys: {:?}", e);
+                tokio::time::sleep(RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum HashError {
+    Hash,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for HashError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+async fn hash_batch(state: &AppStateRaw) -> Result<u64, HashError> {
+    let keys = query_as!(
+        UnhashedKey,
+        "SELECT key FROM api_keys WHERE key_hash IS NULL LIMIT $1",
+        BATCH_SIZE,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    let mut hashed = 0;
+    for UnhashedKey { key } in keys {
+        let stored = StoredKey::new_blocking(key.clone(), state.gateway_key.clone())
+            .await
+            .ok_or(HashError::Hash)?;
+        // The key's references follow it to its id.
+        hashed += query!(
+            r#"
+            UPDATE api_keys
+            SET key = $2, key_hash = $3, sealed_key = $4
+            WHERE key = $1 AND key_hash IS NULL
+            "#,
+            key,
+            stored.id,
+            stored.hash,
+            stored.sealed,
+        )
+        .execute(&state.db_conn)
+        .await?
+        .rows_affected();
+    }
+
+    Ok(hashed)
+}
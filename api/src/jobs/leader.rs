@@ -0,0 +1,56 @@
+use crate::leader::{Singleton, ELECTION_INTERVAL, LEADER_LOCK, SINGLETONS};
+use crate::state::AppStateRaw;
+
+use sqlx::postgres::PgConnection;
+use sqlx::types::chrono::Utc;
+use sqlx::Connection;
+
+/// Holds elections for the leadership of jobs which run on one replica at a time. See
+/// [`crate::leader`].
+pub async fn run(state: AppStateRaw) {
+    loop {
+        if let Err(e) = lead(&state).await {
+            log::error!("error electing job leaders: {:?}", e);
+        }
+        // The connection holding the locks is gone, and the locks with it.
+        state.leadership.lost();
+        state.leadership.held();
+        tokio::time::sleep(ELECTION_INTERVAL).await;
+    }
+}
+
+async fn lead(state: &AppStateRaw) -> Result<(), sqlx::Error> {
+    // Advisory locks belong to a connection, so they're held on one of their own, rather than one
+    // which goes back to the pool.
+    let mut conn = PgConnection::connect(&state.config.database_url).await?;
+    let mut interval = tokio::time::interval(ELECTION_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        // Locks are only tried for once, as a lock taken again has to be released twice. The
+        // query is made even when every job is led from here, to check the connection is alive.
+        let candidates: Vec<i32> = SINGLETONS
+            .into_iter()
+            .filter(|job| state.leadership.since(*job).is_none())
+            .map(|job| job.lock_id())
+            .collect();
+        let won = query_scalar!(
+            r#"
+            SELECT j AS "lock_id!"
+            FROM unnest($2::int[]) j
+            WHERE pg_try_advisory_lock($1, j)
+            "#,
+            LEADER_LOCK,
+            &candidates,
+        )
+        .fetch_all(&mut conn)
+        .await?;
+
+        let now = Utc::now();
+        for job in won.into_iter().filter_map(Singleton::from_lock_id) {
+            state.leadership.won(job, now);
+        }
+        state.leadership.held();
+    }
+}
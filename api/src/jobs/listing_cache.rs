@@ -0,0 +1,22 @@
+use crate::cache::{Change, CHANNEL};
+use crate::fanout::Event;
+use crate::state::AppStateRaw;
+
+/// Invalidates cached listings as Postgres notifies us of changes to them.
+pub async fn run(state: AppStateRaw) {
+    let mut changes = state.fanout.subscribe(CHANNEL);
+
+    loop {
+        match changes.recv().await {
+            Event::Notification(payload) => match serde_json::from_str::<Change>(&payload) {
+                Ok(change) => state.listing_cache.invalidate(&change),
+                Err(e) => {
+                    log::warn!("invalid listing change {:?}: {:?}", payload, e);
+                    state.listing_cache.clear();
+                }
+            },
+            // Anything cached may be stale.
+            Event::Missed => state.listing_cache.clear(),
+        }
+    }
+}
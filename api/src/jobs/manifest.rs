@@ -0,0 +1,137 @@
+use crate::leader::Singleton;
+use crate::manifest::{user_manifest_key, IndexedKey, KeyIndex, UserManifest, KEY_INDEX_KEY};
+use crate::persisters::s3store::StoreError;
+use crate::state::AppStateRaw;
+
+use sqlx::types::{
+    chrono::{self, Utc},
+    Uuid,
+};
+use std::time::Duration;
+
+#[derive(Debug)]
+enum ExportError {
+    Store(StoreError),
+    Json(serde_json::Error),
+    Sqlx(sqlx::Error),
+}
+
+impl From<StoreError> for ExportError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<serde_json::Error> for ExportError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+impl From<sqlx::Error> for ExportError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+struct UserBlobs {
+    user_id: Uuid,
+    blobs: Vec<String>,
+}
+
+struct KeyOwner {
+    key: String,
+    user_id: Uuid,
+    key_hash: String,
+    expire_dt: Option<chrono::DateTime<Utc>>,
+}
+
+/// Periodically exports the manifests used to serve downloads while Postgres is down.
+pub async fn run(state: AppStateRaw) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.manifest_interval_secs));
+
+    loop {
+        interval.tick().await;
+        if !state.leadership.leads(Singleton::Manifest).await {
+            continue;
+        }
+
+        if let Err(e) = export(&state).await {
+            log::error!("error exporting blob manifests: {:?}", e);
+        }
+    }
+}
+
+async fn export(state: &AppStateRaw) -> Result<(), ExportError> {
+    let generated_dt = Utc::now();
+
+    // Archived BLOBs can't be downloaded anyway, so they're left out. So are BLOBs pinned to a
+    // region for data residency, and BLOBs stored under a key prefix other than the server's:
+    // where they're stored is only known from Postgres, so they can't be downloaded while it's
+    // down.
+    let users = query_as!(
+        UserBlobs,
+        r#"
+        SELECT user_id, array_agg(content_hash ORDER BY content_hash) AS "blobs!"
+        FROM blobs
+        WHERE storage_class = 'STANDARD'
+            AND storage_region IS NULL
+            AND coalesce(storage_prefix, '') = $1
+        GROUP BY user_id
+        "#,
+        state.config.s3_key_prefix,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    for user in users {
+        let manifest = UserManifest {
+            user_id: user.user_id,
+            generated_dt,
+            blobs: user.blobs,
+        };
+        state
+            .blob_store
+            .store_object(
+                &user_manifest_key(user.user_id),
+                serde_json::to_vec(&manifest)?.into(),
+            )
+            .await?;
+    }
+
+    // Keys which haven't been hashed yet are left out.
+    let keys = query_as!(
+        KeyOwner,
+        r#"
+        SELECT key, user_id, key_hash AS "key_hash!", expire_dt
+        FROM api_keys
+        WHERE key_hash IS NOT NULL
+            AND (expire_dt IS NULL OR expire_dt > now())
+        "#
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+    let index = KeyIndex {
+        generated_dt,
+        keys: keys
+            .into_iter()
+            .map(|k| {
+                (
+                    k.key,
+                    IndexedKey {
+                        user_id: k.user_id,
+                        key_hash: k.key_hash,
+                        expire_dt: k.expire_dt,
+                    },
+                )
+            })
+            .collect(),
+    };
+    state
+        .blob_store
+        .store_object(KEY_INDEX_KEY, serde_json::to_vec(&index)?.into())
+        .await?;
+
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use crate::leader::Singleton;
+use crate::models::project::{is_miss_storm, MissStorm, MISS_STORM_MIN_LOOKUPS};
+use crate::state::AppStateRaw;
+
+use sqlx::types::Uuid;
+use std::time::Duration;
+
+/// How many of the functions which missed the most are named in an event.
+const TOP_FN_KEYS: i32 = 5;
+
+/// The lookups of a project's functions within its window.
+struct ProjectLookups {
+    id: Uuid,
+    name: String,
+    webhook_url: String,
+    threshold: f64,
+    window_hours: i32,
+    misses: i64,
+    lookups: i64,
+    fn_keys: Vec<String>,
+}
+
+/// Periodically looks for projects whose cache lookups are missing far more than usual, and sends
+/// an event to each one's webhook.
+pub async fn run(state: AppStateRaw) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.miss_storm_interval_secs));
+
+    loop {
+        interval.tick().await;
+        if !state.leadership.leads(Singleton::MissStorms).await {
+            continue;
+        }
+
+        match detect(&state).await {
+            Ok(storms) => {
+                for (storm, url) in storms {
+                    notify(&state, &storm, &url).await;
+                }
+            }
+            Err(e) => log::error!("error detecting miss storms: {:?}", e),
+        }
+    }
+}
+
+/// Counts the lookups reported for the functions of projects with a webhook, within each one's
+/// window, for projects which haven't been reported within it and have had enough lookups to be.
+async fn count(state: &AppStateRaw) -> Result<Vec<ProjectLookups>, sqlx::Error> {
+    query_as!(
+        ProjectLookups,
+        r#"
+        WITH f AS (
+            SELECT p.id, u.fn_key, sum(u.misses) AS misses,
+                sum(u.hits + u.local_hits + u.misses) AS lookups
+            FROM projects p
+            JOIN eval_usage u
+                ON u.user_id = p.user_id
+                AND u.period_start > date_trunc('hour', now())
+                    - make_interval(hours => p.miss_storm_window_hours)
+            WHERE p.miss_storm_webhook_url IS NOT NULL
+                AND (p.miss_storm_fired_dt IS NULL
+                    OR p.miss_storm_fired_dt < now()
+                        - make_interval(hours => p.miss_storm_window_hours))
+                AND EXISTS (
+                    SELECT 1 FROM evals e
+                    WHERE e.project_id = p.id
+                        AND e.fn_key = u.fn_key
+                )
+            GROUP BY p.id, u.fn_key
+        )
+        SELECT p.id, p.name, p.miss_storm_webhook_url AS "webhook_url!",
+            p.miss_storm_threshold AS threshold, p.miss_storm_window_hours AS window_hours,
+            sum(f.misses)::bigint AS "misses!",
+            sum(f.lookups)::bigint AS "lookups!",
+            coalesce((array_agg(f.fn_key ORDER BY f.misses DESC)
+                FILTER (WHERE f.misses > 0))[1:$2], '{}') AS "fn_keys!"
+        FROM f
+        JOIN projects p
+            ON p.id = f.id
+        GROUP BY p.id
+        HAVING sum(f.lookups)::bigint >= $1
+        "#,
+        MISS_STORM_MIN_LOOKUPS,
+        TOP_FN_KEYS,
+    )
+    .fetch_all(&state.db_conn)
+    .await
+}
+
+/// Finds the projects in a miss storm, and marks them as reported, returning the events to send
+/// and where. Marking a project in the same statement which checks it hasn't been means it can't
+/// be reported twice, even with several servers running.
+async fn detect(state: &AppStateRaw) -> Result<Vec<(MissStorm, String)>, sqlx::Error> {
+    let mut storms = vec![];
+
+    for p in count(state).await? {
+        if !is_miss_storm(p.misses, p.lookups, p.threshold) {
+            continue;
+        }
+
+        let marked = query_scalar!(
+            r#"
+            UPDATE projects
+            SET miss_storm_fired_dt = now()
+            WHERE id = $1
+                AND (miss_storm_fired_dt IS NULL
+                    OR miss_storm_fired_dt < now() - make_interval(hours => miss_storm_window_hours))
+            RETURNING id
+            "#,
+            p.id,
+        )
+        .fetch_optional(&state.db_conn)
+        .await?;
+        if marked.is_none() {
+            continue;
+        }
+
+        let storm = MissStorm {
+            event: "miss_storm",
+            project_id: p.id,
+            project: p.name,
+            misses: p.misses,
+            lookups: p.lookups,
+            miss_rate: p.misses as f64 / p.lookups as f64,
+            threshold: p.threshold,
+            window_hours: p.window_hours,
+            fn_keys: p.fn_keys,
+        };
+        storms.push((storm, p.webhook_url));
+    }
+
+    Ok(storms)
+}
+
+async fn notify(state: &AppStateRaw, storm: &MissStorm, url: &str) {
+    log::info!(
+        "miss storm in project {}: {} of {} lookups missed in the last {} hours",
+        storm.project_id,
+        storm.misses,
+        storm.lookups,
+        storm.window_hours,
+    );
+
+    if let Err(e) = state.notifier.webhook(url, storm).await {
+        log::warn!(
+            "could not send miss storm event for project {}: {:?}",
+            storm.project_id,
+            e
+        );
+    }
+}
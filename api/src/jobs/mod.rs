@@ -0,0 +1,35 @@
+//! Background jobs which run alongside the web server.
+//!
+//! Each job is a long-running future, spawned onto the actix runtime at startup, except for the
+//! blob backfill, which is started by an admin. An admin can also take a storage inventory
+//! between the periodic ones. Jobs submitted by users through `POST /jobs` are run by
+//! [`queued`], as they're submitted.
+//!
+//! Scheduled jobs which mustn't run on several replicas at once only run on the replica which
+//! leads them; see [`crate::leader`].
+
+pub mod alerts;
+pub mod anomalies;
+pub mod archive;
+pub mod blob_backfill;
+pub mod blob_gc;
+pub mod blob_reconcile;
+pub mod blob_stats;
+pub mod blob_uploads;
+pub mod captures;
+pub mod changes;
+pub mod digests;
+pub mod embeddings;
+pub mod fanout;
+pub mod inventory;
+pub mod key_hashing;
+pub mod leader;
+pub mod listing_cache;
+pub mod manifest;
+pub mod miss_storms;
+pub mod queued;
+pub mod rehashing;
+pub mod slo;
+pub mod status_checks;
+pub mod storage_usage;
+pub mod tensor_summaries;
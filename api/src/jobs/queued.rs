@@ -0,0 +1,209 @@
+use crate::persisters::blob::upsert_user_blob;
+use crate::persisters::job::JobSpec;
+use crate::persisters::s3store::{store_shared, user_upload_target, StoreError};
+use crate::state::AppStateRaw;
+
+use sqlx::types::{JsonValue, Uuid};
+use std::time::Duration;
+
+/// How often jobs which weren't run as they were submitted are looked for, e.g. those interrupted
+/// by a restart.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long, in minutes, a running job can go without making progress before it's taken to have
+/// been interrupted, and is run again.
+const STALE_MINS: i32 = 30;
+
+/// How many times a job is started before it's given up on.
+const MAX_ATTEMPTS: i32 = 3;
+
+#[derive(Debug)]
+enum JobRunError {
+    Store(StoreError),
+    Sqlx(sqlx::Error),
+    Json(serde_json::Error),
+}
+
+impl From<StoreError> for JobRunError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<sqlx::Error> for JobRunError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+impl From<serde_json::Error> for JobRunError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+struct Claimed {
+    id: Uuid,
+    user_id: Uuid,
+    spec: JsonValue,
+}
+
+/// Periodically runs jobs which are still waiting; see [`work`].
+pub async fn run(state: AppStateRaw) {
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        work(state.clone()).await;
+    }
+}
+
+/// Runs waiting jobs, one after the other, until there are none left, and drops expired ones.
+/// Each job is claimed by one worker, so this can run while it's already running, e.g. as each
+/// job is submitted.
+pub async fn work(state: AppStateRaw) {
+    if let Err(e) = expire(&state).await {
+        log::error!("error expiring jobs: {:?}", e);
+    }
+
+    loop {
+        let job = match claim(&state).await {
+            Ok(Some(job)) => job,
+            Ok(None) => return,
+            Err(e) => {
+                log::error!("error claiming job: {:?}", e);
+                return;
+            }
+        };
+
+        if let Err(e) = produce(&state, &job).await {
+            log::warn!("job {} failed: {:?}", job.id, e);
+            let res = query!(
+                r#"
+                UPDATE jobs
+                SET status = 'failed', error = $2, update_dt = now(), finish_dt = now()
+                WHERE id = $1
+                "#,
+                job.id,
+                format!("{:?}", e),
+            )
+            .execute(&state.db_conn)
+            .await;
+            if let Err(e) = res {
+                log::error!("error recording failure of job {}: {:?}", job.id, e);
+            }
+        }
+    }
+}
+
+/// Claims the oldest job waiting to run, if there is one. Jobs which were interrupted are run
+/// again, unless they've been interrupted too often.
+async fn claim(state: &AppStateRaw) -> Result<Option<Claimed>, sqlx::Error> {
+    query!(
+        r#"
+        UPDATE jobs
+        SET status = 'failed', error = 'interrupted too many times', finish_dt = now()
+        WHERE status = 'running'
+            AND update_dt < now() - make_interval(mins => $1)
+            AND attempts >= $2
+        "#,
+        STALE_MINS,
+        MAX_ATTEMPTS,
+    )
+    .execute(&state.db_conn)
+    .await?;
+
+    query_as!(
+        Claimed,
+        r#"
+        UPDATE jobs
+        SET status = 'running', attempts = attempts + 1, update_dt = now()
+        WHERE id = (
+            SELECT id FROM jobs
+            WHERE status = 'pending'
+                OR (status = 'running' AND update_dt < now() - make_interval(mins => $1))
+            ORDER BY create_dt
+            LIMIT 1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING id, user_id, spec
+        "#,
+        STALE_MINS,
+    )
+    .fetch_optional(&state.db_conn)
+    .await
+}
+
+/// Produces what the job asks for, and stores it as a BLOB owned by whoever submitted the job.
+async fn produce(state: &AppStateRaw, job: &Claimed) -> Result<(), JobRunError> {
+    let spec: JobSpec = serde_json::from_value(job.spec.clone())?;
+    let bytes = match spec {
+        JobSpec::OpenlineageExport(export) => {
+            let events = export.events(job.user_id, state).await?;
+            progress(state, job.id, events.len() as i64).await?;
+            serde_json::to_vec(&events)?
+        }
+    };
+
+    let content_hash = blake3::hash(&bytes);
+    let target = user_upload_target(job.user_id, state).await?;
+    let (target, stored) = store_shared(state, target, content_hash, bytes.into()).await?;
+
+    let mut tx = state.db_conn.begin().await?;
+    let blob_id = upsert_user_blob(
+        &mut tx,
+        job.user_id,
+        content_hash.to_hex().as_str(),
+        &target,
+        stored,
+    )
+    .await?;
+    query!(
+        r#"
+        UPDATE jobs
+        SET status = 'finished',
+            blob_id = $2,
+            progress_done = coalesce(progress_total, progress_done),
+            update_dt = now(),
+            finish_dt = now()
+        WHERE id = $1
+        "#,
+        job.id,
+        blob_id,
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Records how much there is to produce, once it's known, which also marks the job as still
+/// making progress.
+async fn progress(state: &AppStateRaw, id: Uuid, total: i64) -> Result<(), sqlx::Error> {
+    query!(
+        "UPDATE jobs SET progress_total = $2, update_dt = now() WHERE id = $1",
+        id,
+        total,
+    )
+    .execute(&state.db_conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Drops jobs which finished longer ago than `JOB_TTL_HOURS`. The BLOBs they produced are left to
+/// the garbage collector.
+async fn expire(state: &AppStateRaw) -> Result<(), sqlx::Error> {
+    query!(
+        r#"
+        DELETE FROM jobs
+        WHERE finish_dt < now() - make_interval(hours => $1)
+        "#,
+        state.config.job_ttl_hours as i32,
+    )
+    .execute(&state.db_conn)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use crate::hashing::HashAlgorithm;
+use crate::persisters::s3store::{StoreError, Target};
+use crate::state::AppStateRaw;
+
+use blake3::Hash;
+use futures::stream::StreamExt;
+use std::time::Duration;
+
+/// How many objects are rehashed at a time.
+const BATCH_SIZE: i64 = 50;
+
+/// How long to wait before looking again once every object has been rehashed, or when objects
+/// can't be rehashed.
+const RETRY_INTERVAL: Duration = Duration::from_secs(600);
+
+struct Unhashed {
+    content_hash: String,
+    storage_region: Option<String>,
+    storage_bucket: Option<String>,
+    storage_prefix: Option<String>,
+}
+
+#[derive(Debug)]
+enum RehashError {
+    Store(StoreError),
+    Sqlx(sqlx::Error),
+}
+
+impl From<StoreError> for RehashError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<sqlx::Error> for RehashError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+/// Records the digest of each stored object under the secondary hash algorithm, while one is
+/// configured, including objects stored since the last pass. See [`crate::hashing`]. Does
+/// nothing when there isn't one.
+pub async fn run(state: AppStateRaw) {
+    let algorithm = match state.config.content_hash_secondary {
+        Some(algorithm) => algorithm,
+        None => return,
+    };
+
+    loop {
+        match rehash_batch(&state, algorithm).await {
+            Ok(0) => tokio::time::sleep(RETRY_INTERVAL).await,
+            Ok(rehashed) => log::info!("rehashed {} blobs as {}", rehashed, algorithm.as_str()),
+            Err(e) => {
+                log::error!("error rehashing blobs: {:?}", e);
+                tokio::time::sleep(RETRY_INTERVAL).await;
+            }
+        }
+    }
+}
+
+async fn rehash_batch(state: &AppStateRaw, algorithm: HashAlgorithm) -> Result<u64, RehashError> {
+    // Objects which are stored in more than one place have the same digest, so are only read once.
+    // Archived objects can't be read until they're restored.
+    let batch = query_as!(
+        Unhashed,
+        r#"
+        SELECT DISTINCT ON (o.content_hash) o.content_hash, o.storage_region,
+            o.storage_bucket, o.storage_prefix
+        FROM blob_objects o
+        JOIN blobs b ON b.object_id = o.id
+        WHERE b.object_status = 'available'
+            AND b.storage_class = 'STANDARD'
+            AND NOT EXISTS (
+                SELECT 1
+                FROM blob_digests d
+                WHERE d.content_hash = o.content_hash
+                    AND d.algorithm = $1
+            )
+        ORDER BY o.content_hash, o.used_dt DESC
+        LIMIT $2
+        "#,
+        algorithm.as_str(),
+        BATCH_SIZE,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    let mut rehashed = 0;
+    for object in batch {
+        // A hash which isn't valid hex can't have been stored under.
+        let hash = match Hash::from_hex(&object.content_hash) {
+            Ok(hash) => hash,
+            Err(_) => continue,
+        };
+        let target = Target {
+            region: object.storage_region,
+            bucket: object.storage_bucket,
+            prefix: object.storage_prefix,
+        };
+
+        // The primary hash is checked as the object is read, so that a digest is never recorded
+        // for the wrong bytes.
+        let mut stream = state.blob_store.retrieve_blob(&target, hash).await?;
+        let mut primary = blake3::Hasher::new();
+        let mut secondary = algorithm.hasher();
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| StoreError::S3Other(Box::new(e)))?;
+            primary.update(&chunk);
+            secondary.update(&chunk);
+        }
+        if primary.finalize() != hash {
+            log::warn!(
+                "not rehashing blob {}: its object doesn't match its hash",
+                object.content_hash
+            );
+            continue;
+        }
+
+        rehashed += query!(
+            r#"
+            INSERT INTO blob_digests (content_hash, algorithm, digest)
+            VALUES ($1, $2, $3)
+            ON CONFLICT DO NOTHING
+            "#,
+            object.content_hash,
+            algorithm.as_str(),
+            secondary.finish(),
+        )
+        .execute(&state.db_conn)
+        .await?
+        .rows_affected();
+    }
+
+    Ok(rehashed)
+}
@@ -0,0 +1,44 @@
+use crate::slo::{should_alert, RouteSlo, SHORT_WINDOW_MINS};
+use crate::state::AppStateRaw;
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Every minute, checks how fast each route is burning its error budget, and sends an event for
+/// each route which has started burning it too fast. A route is alerted on again only once it
+/// has recovered.
+pub async fn run(state: AppStateRaw) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+    let mut alerting = HashSet::new();
+
+    loop {
+        interval.tick().await;
+
+        let report = state.slo.report(state.config.slo_target);
+        let burning = report
+            .into_iter()
+            .filter(|slo| should_alert(slo, state.config.slo_burn_rate_alert))
+            .collect::<Vec<_>>();
+
+        for slo in burning.iter().filter(|slo| !alerting.contains(&slo.route)) {
+            notify(&state, slo).await;
+        }
+        alerting = burning.into_iter().map(|slo| slo.route).collect();
+    }
+}
+
+async fn notify(state: &AppStateRaw, slo: &RouteSlo) {
+    log::warn!(
+        "route {} is burning its error budget {:.1}x too fast ({:.1}x in the last {} minutes)",
+        slo.route,
+        slo.burn_rate_long,
+        slo.burn_rate_short,
+        SHORT_WINDOW_MINS,
+    );
+
+    if let Some(url) = &state.config.slo_webhook_url {
+        if let Err(e) = state.notifier.operator_webhook(url, slo).await {
+            log::warn!("could not send event for route {}: {:?}", slo.route, e);
+        }
+    }
+}
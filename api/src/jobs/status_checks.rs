@@ -0,0 +1,113 @@
+use crate::persisters::s3store::Target;
+use crate::slo::should_alert;
+use crate::state::AppStateRaw;
+use crate::status::{check_health, job_queue_health, Component, ComponentStatus, Health};
+
+use std::time::{Duration, Instant};
+
+/// How often the components are checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a check waits before the component is taken to be down.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Checks each component of the service for the status feed, every [`CHECK_INTERVAL`]. See
+/// [`crate::status`].
+pub async fn run(state: AppStateRaw) {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        state.status.record(check_api(&state));
+        state.status.record(check_database(&state).await);
+        state.status.record(check_blob_store(&state).await);
+        if let Some(status) = check_job_queue(&state).await {
+            state.status.record(status);
+        }
+    }
+}
+
+/// The API is degraded while any route is burning its error budget fast enough to alert on.
+fn check_api(state: &AppStateRaw) -> ComponentStatus {
+    let burning: Vec<String> = state
+        .slo
+        .report(state.config.slo_target)
+        .into_iter()
+        .filter(|slo| should_alert(slo, state.config.slo_burn_rate_alert))
+        .map(|slo| slo.route)
+        .collect();
+    match burning.is_empty() {
+        true => ComponentStatus::new(Component::Api, Health::Operational, None),
+        false => ComponentStatus::new(
+            Component::Api,
+            Health::Degraded,
+            Some(format!(
+                "elevated errors or latency on {}",
+                burning.join(", ")
+            )),
+        ),
+    }
+}
+
+async fn check_database(state: &AppStateRaw) -> ComponentStatus {
+    let start = Instant::now();
+    let res = tokio::time::timeout(
+        CHECK_TIMEOUT,
+        query!("SELECT 1 AS one").fetch_one(&state.db_conn),
+    )
+    .await;
+    let error = match res {
+        Ok(Ok(_)) => None,
+        Ok(Err(e)) => {
+            log::warn!("status check of the database failed: {:?}", e);
+            Some("unavailable".to_string())
+        }
+        Err(_) => Some("timed out".to_string()),
+    };
+    let (health, detail) = check_health(start.elapsed(), error);
+    ComponentStatus::new(Component::Database, health, detail)
+}
+
+async fn check_blob_store(state: &AppStateRaw) -> ComponentStatus {
+    let start = Instant::now();
+    let res = tokio::time::timeout(
+        CHECK_TIMEOUT,
+        state.blob_store.check_target(&Target::server()),
+    )
+    .await;
+    let error = match res {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => {
+            log::warn!("status check of BLOB storage failed: {:?}", e);
+            Some("unavailable".to_string())
+        }
+        Err(_) => Some("timed out".to_string()),
+    };
+    let (health, detail) = check_health(start.elapsed(), error);
+    ComponentStatus::new(Component::BlobStore, health, detail)
+}
+
+/// The job queue is as healthy as the wait of its longest waiting job. It can't be checked while
+/// the database is down, so it's left as it was last checked.
+async fn check_job_queue(state: &AppStateRaw) -> Option<ComponentStatus> {
+    let res = query_scalar!(
+        r#"
+        SELECT extract(epoch FROM now() - min(create_dt))::bigint
+        FROM jobs
+        WHERE status = 'pending'
+        "#,
+    )
+    .fetch_one(&state.db_conn)
+    .await;
+    match res {
+        Ok(longest_wait_secs) => {
+            let (health, detail) = job_queue_health(longest_wait_secs);
+            Some(ComponentStatus::new(Component::JobQueue, health, detail))
+        }
+        Err(e) => {
+            log::warn!("status check of the job queue failed: {:?}", e);
+            None
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use crate::leader::Singleton;
+use crate::metering::TierBytes;
+use crate::state::AppStateRaw;
+
+use sqlx::types::Uuid;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// How often stored bytes are counted.
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Periodically counts the bytes each user stores in each storage tier, into the current hour's
+/// period of `storage_usage`.
+pub async fn run(state: AppStateRaw) {
+    let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+
+    loop {
+        interval.tick().await;
+        if !state.leadership.leads(Singleton::StorageUsage).await {
+            continue;
+        }
+
+        if let Err(e) = snapshot(&state).await {
+            log::error!("error counting storage usage: {:?}", e);
+        }
+    }
+}
+
+async fn snapshot(state: &AppStateRaw) -> Result<(), sqlx::Error> {
+    // BLOBs whose length hasn't been recorded yet count for nothing until the blob backfill fills
+    // it in.
+    let rows = query!(
+        r#"
+        SELECT b.user_id, b.storage_class,
+            EXISTS (
+                SELECT 1 FROM blobs o
+                WHERE o.content_hash = b.content_hash
+                    AND o.storage_bucket IS NOT DISTINCT FROM b.storage_bucket
+                    AND o.storage_prefix IS NOT DISTINCT FROM b.storage_prefix
+                    AND o.id < b.id
+            ) AS "deduplicated!",
+            sum(coalesce(b.content_length, 0))::bigint AS "bytes!"
+        FROM blobs b
+        GROUP BY 1, 2, 3
+        "#,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    let mut usage: HashMap<Uuid, TierBytes> = HashMap::new();
+    for row in rows {
+        usage
+            .entry(row.user_id)
+            .or_default()
+            .add(&row.storage_class, row.deduplicated, row.bytes);
+    }
+
+    let mut user_ids = vec![];
+    let mut tiers = vec![];
+    let mut bytes = vec![];
+    for (user_id, tier_bytes) in usage {
+        for (tier, b) in tier_bytes.0 {
+            user_ids.push(user_id);
+            tiers.push(tier.as_str().to_string());
+            bytes.push(b);
+        }
+    }
+
+    let mut tx = state.db_conn.begin().await?;
+    // Tiers a user no longer has bytes in are dropped from the current period.
+    query!("DELETE FROM storage_usage WHERE period_start = date_trunc('hour', now())")
+        .execute(&mut tx)
+        .await?;
+    query!(
+        r#"
+        INSERT INTO storage_usage (user_id, period_start, tier, bytes)
+        SELECT u.user_id, date_trunc('hour', now()), u.tier, u.bytes
+        FROM unnest($1::uuid[], $2::text[], $3::bigint[]) AS u(user_id, tier, bytes)
+        "#,
+        &user_ids,
+        &tiers,
+        &bytes,
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(())
+}
@@ -0,0 +1,221 @@
+use crate::leader::Singleton;
+use crate::models::tensor::{TensorFormat, TensorSummary};
+use crate::persisters::s3store::{StoreError, Target};
+use crate::state::AppStateRaw;
+use crate::tensor::{self, TensorError, SNIFF_LEN};
+
+use blake3::Hash;
+use std::time::Duration;
+
+/// How many BLOBs are looked at on each pass.
+const BATCH_SIZE: i64 = 10;
+
+/// The largest BLOB, in bytes, which is summarised. The whole BLOB is held in memory.
+const MAX_BLOB_LEN: i64 = 1024 * 1024 * 1024;
+
+#[derive(Debug)]
+enum SummaryError {
+    Store(StoreError),
+    Sqlx(sqlx::Error),
+}
+
+impl From<StoreError> for SummaryError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<sqlx::Error> for SummaryError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+impl From<blake3::HexError> for SummaryError {
+    fn from(e: blake3::HexError) -> Self {
+        Self::Store(e.into())
+    }
+}
+
+/// The outcome of looking at a BLOB, as recorded in `tensor_summaries`.
+struct Outcome {
+    status: &'static str,
+    format: Option<TensorFormat>,
+    tensors: Option<Vec<TensorSummary>>,
+    error: Option<String>,
+}
+
+impl Outcome {
+    fn unsupported(format: Option<TensorFormat>, error: &str) -> Self {
+        Self {
+            status: "unsupported",
+            format,
+            tensors: None,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// Periodically summarises tensor BLOBs which haven't been looked at yet.
+pub async fn run(state: AppStateRaw) {
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(state.config.blob_stats_interval_secs));
+
+    loop {
+        interval.tick().await;
+        if !state.leadership.leads(Singleton::TensorSummaries).await {
+            continue;
+        }
+
+        if let Err(e) = summarise_pending(&state).await {
+            log::error!("error summarising tensors: {:?}", e);
+        }
+    }
+}
+
+async fn summarise_pending(state: &AppStateRaw) -> Result<(), SummaryError> {
+    // Archived BLOBs can't be read until they're restored, so they wait until then.
+    // The same content can be stored by several users, in different places; any copy will do.
+    let pending = query!(
+        r#"
+        SELECT DISTINCT ON (b.content_hash) b.content_hash, b.storage_region, b.storage_bucket,
+            b.storage_prefix
+        FROM blobs b
+        WHERE b.storage_class = 'STANDARD'
+            AND NOT EXISTS (
+                SELECT 1 FROM tensor_summaries s
+                WHERE s.content_hash = b.content_hash
+            )
+        LIMIT $1
+        "#,
+        BATCH_SIZE,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    for blob in pending {
+        let content_hash = blob.content_hash;
+        let target = Target {
+            region: blob.storage_region,
+            bucket: blob.storage_bucket,
+            prefix: blob.storage_prefix,
+        };
+        let outcome = examine(state, &target, Hash::from_hex(&content_hash)?).await?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        query!(
+            r#"
+            INSERT INTO tensor_summaries (content_hash, status, format, tensors, error)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (content_hash) DO NOTHING
+            "#,
+            content_hash,
+            outcome.status,
+            outcome.format.map(|f| f.as_str()),
+            outcome
+                .tensors
+                .as_ref()
+                .and_then(|t| serde_json::to_value(t).ok()),
+            outcome.error,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        // Evals stored before the summary get its metrics here; later ones get them from
+        // `derive_metrics`.
+        if let Some(tensors) = &outcome.tensors {
+            let nan_count = tensors.iter().map(|t| t.nan_count).sum::<u64>() as f64;
+            let inf_count = tensors.iter().map(|t| t.inf_count).sum::<u64>() as f64;
+            query!(
+                r#"
+                INSERT INTO metrics (eval_id, name, value)
+                SELECT e.id, m.name, m.value
+                FROM evals e
+                JOIN blobs b
+                    ON b.id = e.blob_id
+                CROSS JOIN (VALUES ('tensor.nan_count', $2::float8), ('tensor.inf_count', $3))
+                    AS m(name, value)
+                WHERE b.content_hash = $1
+                ON CONFLICT (eval_id, name) DO NOTHING
+                "#,
+                content_hash,
+                nan_count,
+                inf_count,
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+    }
+
+    Ok(())
+}
+
+async fn examine(
+    state: &AppStateRaw,
+    target: &Target,
+    hash: Hash,
+) -> Result<Outcome, SummaryError> {
+    let len = state.blob_store.blob_length(target, hash).await?;
+    if len == 0 {
+        return Ok(Outcome::unsupported(None, "empty"));
+    }
+
+    // Only the start of the BLOB is needed to tell whether it's a tensor at all.
+    let sample = state
+        .blob_store
+        .retrieve_blob_prefix(target, hash, SNIFF_LEN, len)
+        .await?;
+    let format = match tensor::sniff(&sample) {
+        // Pickles can't be summarised, so there's no point fetching the rest.
+        Some(TensorFormat::Torch) => {
+            return Ok(Outcome::unsupported(
+                Some(TensorFormat::Torch),
+                "pickled PyTorch tensors",
+            ))
+        }
+        Some(format) => format,
+        None => return Ok(Outcome::unsupported(None, "not a tensor format")),
+    };
+    if len > MAX_BLOB_LEN {
+        return Ok(Outcome::unsupported(Some(format), "too large"));
+    }
+
+    let bytes = state.blob_store.retrieve_blob_bytes(target, hash).await?;
+
+    let res = tokio::task::spawn_blocking(move || tensor::summarise_all(format, &bytes)).await;
+
+    let outcome = match res {
+        Ok(Ok(tensors)) => Outcome {
+            status: "done",
+            format: Some(format),
+            tensors: Some(tensors),
+            error: None,
+        },
+        Ok(Err(e @ TensorError::Unsupported(_))) => Outcome {
+            status: "unsupported",
+            format: Some(format),
+            tensors: None,
+            error: Some(e.to_string()),
+        },
+        Ok(Err(e)) => Outcome {
+            status: "failed",
+            format: Some(format),
+            tensors: None,
+            error: Some(e.to_string()),
+        },
+        Err(e) => {
+            log::error!("tensor summary task panicked: {:?}", e);
+            Outcome {
+                status: "failed",
+                format: Some(format),
+                tensors: None,
+                error: Some("internal error".to_string()),
+            }
+        }
+    };
+
+    Ok(outcome)
+}
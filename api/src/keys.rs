@@ -0,0 +1,135 @@
+//! Hashed storage of API keys, so that a leak of the database doesn't leak keys which can be used.
+//!
+//! A key is stored under its id, its first [`KEY_ID_LEN`] characters, which is indexed and isn't
+//! secret, along with an Argon2 hash of the whole key. A request's key is looked up by its id and
+//! checked against the hash here, rather than in SQL, and from then on the request is
+//! authenticated as the id: queries only ever see ids. Since checking a hash is deliberately slow,
+//! keys which have been checked are remembered, by a digest, for the life of the process. Keys
+//! which are revoked stop working regardless, as their ids no longer resolve.
+//!
+//! The S3 gateway checks request signatures, which needs the whole key, so keys are also kept
+//! sealed with the server's gateway key when one is configured.
+use crate::capture::CaptureKey;
+use crate::models::s3gateway::ACCESS_KEY_ID_LEN;
+use actix_web::web;
+use argon2::password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString};
+use argon2::{Argon2, PasswordVerifier};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// The length of the id a key is stored under. It's the key's S3 access key id, too.
+pub const KEY_ID_LEN: usize = ACCESS_KEY_ID_LEN;
+
+/// The most checked keys which are remembered. The cache is cleared when it fills up.
+const MAX_VERIFIED_KEYS: usize = 10_000;
+
+/// The id a key is stored under.
+pub fn key_id(key: &str) -> &str {
+    key.get(..KEY_ID_LEN).unwrap_or(key)
+}
+
+/// The Argon2 hash of a key, in PHC string format.
+pub fn hash(key: &str) -> Option<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(key.as_bytes(), &salt)
+        .map(|h| h.to_string())
+        .ok()
+}
+
+/// Whether a key matches a hash made by [`hash`]. The comparison is constant time.
+pub fn verify(key: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(key.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Checks a key against its hash on the blocking thread pool, so as not to hold up other requests.
+pub async fn verify_blocking(key: String, hash: String) -> bool {
+    web::block(move || verify(&key, &hash))
+        .await
+        .unwrap_or(false)
+}
+
+/// What's stored of a key.
+pub struct StoredKey {
+    pub id: String,
+    pub hash: String,
+    /// The key sealed with the S3 gateway key, if there is one.
+    pub sealed: Option<Vec<u8>>,
+}
+
+impl StoredKey {
+    pub fn new(key: &str, gateway_key: Option<&CaptureKey>) -> Option<Self> {
+        Some(Self {
+            id: key_id(key).to_string(),
+            hash: hash(key)?,
+            sealed: match gateway_key {
+                Some(gateway_key) => Some(gateway_key.seal(key.as_bytes())?),
+                None => None,
+            },
+        })
+    }
+
+    /// Hashes a new key on the blocking thread pool.
+    pub async fn new_blocking(key: String, gateway_key: Option<CaptureKey>) -> Option<Self> {
+        web::block(move || Self::new(&key, gateway_key.as_ref()))
+            .await
+            .ok()
+            .flatten()
+    }
+}
+
+/// The ids of keys which have been checked, by the digest of each key.
+#[derive(Clone, Default)]
+pub struct VerifiedKeys(Arc<Mutex<HashMap<blake3::Hash, String>>>);
+
+impl VerifiedKeys {
+    /// The id of `key`, if it has been checked.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.0
+            .lock()
+            .unwrap()
+            .get(&blake3::hash(key.as_bytes()))
+            .cloned()
+    }
+
+    /// Remembers that `key` was checked, and is stored under `id`.
+    pub fn insert(&self, key: &str, id: String) {
+        let mut keys = self.0.lock().unwrap();
+        if keys.len() >= MAX_VERIFIED_KEYS {
+            keys.clear();
+        }
+        keys.insert(blake3::hash(key.as_bytes()), id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "AbCdEfGhIjKlMnOpQrStUvWxYz0123456789AbCdEfGhIjKlMnOpQrStUvWxYz01";
+
+    #[test]
+    fn hashes_and_verifies() {
+        let hashed = hash(KEY).unwrap();
+        assert!(!hashed.contains(KEY));
+        assert!(verify(KEY, &hashed));
+        assert!(!verify(&KEY.replace('A', "B"), &hashed));
+        assert!(!verify(KEY, "not a hash"));
+    }
+
+    #[test]
+    fn ids_are_prefixes() {
+        assert_eq!(key_id(KEY), "AbCdEfGhIjKlMnOpQrSt");
+        assert_eq!(key_id("short"), "short");
+
+        let verified = VerifiedKeys::default();
+        assert_eq!(verified.get(KEY), None);
+        verified.insert(KEY, key_id(KEY).to_string());
+        assert_eq!(verified.get(KEY).as_deref(), Some("AbCdEfGhIjKlMnOpQrSt"));
+    }
+}
@@ -0,0 +1,222 @@
+//! Leadership of the scheduled background jobs, so that each runs on one replica at a time.
+//!
+//! Each [`Singleton`] job is led by whichever replica holds its advisory lock. The `leader` job
+//! (see [`crate::jobs::leader`]) tries for the locks of the jobs it doesn't lead every
+//! [`ELECTION_INTERVAL`], on a connection of its own, and replicas skip the passes of jobs they
+//! don't lead. Advisory locks are released when their connection closes, so when a leader stops,
+//! or loses its connection, another replica takes its jobs over at its next election. A pass which
+//! has already started when leadership is lost runs to its end.
+//!
+//! Jobs which keep state of the replica they run on, such as the listing cache and SLO tracking,
+//! and the queue of submitted jobs, whose jobs are each claimed by one worker, run on every
+//! replica.
+use sqlx::types::chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// The `classid` half of the advisory locks of jobs' leadership. The `objid` half is the job's
+/// [`Singleton::lock_id`].
+pub const LEADER_LOCK: i32 = 0x6c65_6164;
+
+/// How often replicas try to lead the jobs they don't, and check the connection holding the locks
+/// of those they do.
+pub const ELECTION_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A scheduled job which runs on one replica at a time.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Singleton {
+    Alerts = 1,
+    Anomalies = 2,
+    Archive = 3,
+    BlobGc = 4,
+    BlobReconcile = 5,
+    BlobStats = 6,
+    BlobUploads = 7,
+    Captures = 8,
+    Changes = 9,
+    Digests = 10,
+    Embeddings = 11,
+    Inventory = 12,
+    Manifest = 13,
+    MissStorms = 14,
+    StorageUsage = 15,
+    TensorSummaries = 16,
+}
+
+/// Every job which runs on one replica at a time.
+pub const SINGLETONS: [Singleton; 16] = [
+    Singleton::Alerts,
+    Singleton::Anomalies,
+    Singleton::Archive,
+    Singleton::BlobGc,
+    Singleton::BlobReconcile,
+    Singleton::BlobStats,
+    Singleton::BlobUploads,
+    Singleton::Captures,
+    Singleton::Changes,
+    Singleton::Digests,
+    Singleton::Embeddings,
+    Singleton::Inventory,
+    Singleton::Manifest,
+    Singleton::MissStorms,
+    Singleton::StorageUsage,
+    Singleton::TensorSummaries,
+];
+
+impl Singleton {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Singleton::Alerts => "alerts",
+            Singleton::Anomalies => "anomalies",
+            Singleton::Archive => "archive",
+            Singleton::BlobGc => "blob_gc",
+            Singleton::BlobReconcile => "blob_reconcile",
+            Singleton::BlobStats => "blob_stats",
+            Singleton::BlobUploads => "blob_uploads",
+            Singleton::Captures => "captures",
+            Singleton::Changes => "changes",
+            Singleton::Digests => "digests",
+            Singleton::Embeddings => "embeddings",
+            Singleton::Inventory => "inventory",
+            Singleton::Manifest => "manifest",
+            Singleton::MissStorms => "miss_storms",
+            Singleton::StorageUsage => "storage_usage",
+            Singleton::TensorSummaries => "tensor_summaries",
+        }
+    }
+
+    /// The `objid` half of the job's advisory lock. These mustn't change while replicas of
+    /// different versions may be running together.
+    pub fn lock_id(&self) -> i32 {
+        *self as i32
+    }
+
+    pub fn from_lock_id(id: i32) -> Option<Self> {
+        SINGLETONS.into_iter().find(|job| job.lock_id() == id)
+    }
+}
+
+struct Inner {
+    /// The jobs this replica leads, and since when.
+    led: Mutex<HashMap<Singleton, DateTime<Utc>>>,
+    /// Whether an election has been held yet.
+    held: watch::Sender<bool>,
+}
+
+/// Which jobs this replica leads.
+#[derive(Clone)]
+pub struct Leadership {
+    inner: Arc<Inner>,
+    held: watch::Receiver<bool>,
+}
+
+impl Default for Leadership {
+    fn default() -> Self {
+        let (sender, held) = watch::channel(false);
+        Self {
+            inner: Arc::new(Inner {
+                led: Mutex::default(),
+                held: sender,
+            }),
+            held,
+        }
+    }
+}
+
+impl Leadership {
+    /// Whether this replica leads the job, once the first election has been held, so that the
+    /// leader doesn't skip passes while it's starting up.
+    pub async fn leads(&self, job: Singleton) -> bool {
+        let mut held = self.held.clone();
+        while !*held.borrow() {
+            if held.changed().await.is_err() {
+                break;
+            }
+        }
+        self.since(job).is_some()
+    }
+
+    /// When this replica became the job's leader, if it leads it.
+    pub fn since(&self, job: Singleton) -> Option<DateTime<Utc>> {
+        self.inner.led.lock().unwrap().get(&job).copied()
+    }
+
+    /// Records that this replica has taken the job's lock.
+    pub fn won(&self, job: Singleton, at: DateTime<Utc>) {
+        log::info!("leading {} jobs", job.as_str());
+        self.inner.led.lock().unwrap().insert(job, at);
+    }
+
+    /// Records that this replica has lost the connection holding its locks, and so leads nothing.
+    pub fn lost(&self) {
+        let mut led = self.inner.led.lock().unwrap();
+        for job in led.keys() {
+            log::warn!("no longer leading {} jobs", job.as_str());
+        }
+        led.clear();
+    }
+
+    /// Records that an election has been held, whether or not it was won.
+    pub fn held(&self) {
+        // Fails only if there are no receivers, and `self` is one.
+        let _ = self.inner.held.send(true);
+    }
+}
+
+/// Who leads a job, as reported to admins.
+#[derive(Serialize, Debug)]
+pub struct JobLeader {
+    pub job: Singleton,
+    /// Whether the replica answering leads the job.
+    pub leader: bool,
+    /// When the replica answering became the job's leader.
+    pub since: Option<DateTime<Utc>>,
+    /// The database backend of the replica which leads the job, if one does, and its address.
+    pub leader_pid: Option<i32>,
+    pub leader_addr: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum LeaderError {
+    Unauthorized,
+    /// Only admins can see which replicas lead the jobs.
+    Forbidden,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for LeaderError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::types::chrono::TimeZone;
+
+    #[test]
+    fn lock_ids_are_distinct() {
+        for job in SINGLETONS {
+            assert_eq!(Singleton::from_lock_id(job.lock_id()), Some(job));
+        }
+        assert_eq!(Singleton::from_lock_id(0), None);
+    }
+
+    #[actix_rt::test]
+    async fn leads_what_it_has_won_until_lost() {
+        let leadership = Leadership::default();
+        let at = Utc.with_ymd_and_hms(2023, 1, 30, 12, 0, 0).unwrap();
+        leadership.won(Singleton::BlobGc, at);
+        leadership.held();
+        assert!(leadership.leads(Singleton::BlobGc).await);
+        assert!(!leadership.leads(Singleton::Digests).await);
+        assert_eq!(leadership.since(Singleton::BlobGc), Some(at));
+
+        leadership.lost();
+        assert!(!leadership.leads(Singleton::BlobGc).await);
+    }
+}
@@ -16,14 +16,46 @@ extern crate serde;
 #[macro_use]
 extern crate lazy_static;
 
+pub mod cache;
+pub mod canonical;
+pub mod capture;
+pub mod cdn;
+pub mod chaos;
+pub mod chunking;
 pub mod config;
+pub mod downloads;
+pub mod embed;
+pub mod envelope;
+pub mod error_code;
 pub mod extractors;
+pub mod fanout;
+pub mod gc;
 pub mod handlers;
+pub mod hashing;
+pub mod integrity;
+pub mod jobs;
+pub mod keys;
+pub mod leader;
+pub mod load;
+pub mod manifest;
+pub mod metering;
 pub mod middlewares;
 pub mod models;
 pub mod msg_pack;
+pub mod notify;
 pub mod persisters;
+pub mod pgcopy;
+pub mod policy;
+pub mod priority;
+pub mod resume;
+pub mod retry;
+pub mod sigv4;
+pub mod slo;
 pub mod state;
+pub mod status;
+pub mod tabular;
+pub mod tensor;
+pub mod throttle;
 
 use config::Config;
 
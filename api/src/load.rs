@@ -0,0 +1,77 @@
+//! Tracking of the server's load, used to hint to polling clients how long to wait before polling
+//! again.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// The longest a polling client is asked to wait, in milliseconds.
+pub const MAX_POLL_AFTER_MS: u64 = 30_000;
+
+/// Utilization is capped below 1 so that the backoff stays finite.
+const MAX_UTILIZATION: f64 = 0.95;
+
+/// Counts the requests currently being served.
+#[derive(Clone, Default)]
+pub struct Load {
+    in_flight: Arc<AtomicUsize>,
+}
+
+/// Marks a request as being served until dropped.
+pub struct LoadGuard {
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl Drop for LoadGuard {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Load {
+    pub fn start(&self) -> LoadGuard {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        LoadGuard {
+            in_flight: self.in_flight.clone(),
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+}
+
+/// How long a polling client should wait before polling again, given the fraction of the
+/// server's request capacity and database connections in use. The wait grows with the busier of
+/// the two, from `base_ms` when idle, doubling at half utilization, up to [`MAX_POLL_AFTER_MS`].
+pub fn poll_after_ms(base_ms: u64, request_utilization: f64, db_utilization: f64) -> u64 {
+    let utilization = request_utilization
+        .max(db_utilization)
+        .clamp(0.0, MAX_UTILIZATION);
+    let wait = base_ms as f64 / (1.0 - utilization);
+    (wait.round() as u64).min(MAX_POLL_AFTER_MS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_with_load() {
+        assert_eq!(poll_after_ms(1000, 0.0, 0.0), 1000);
+        assert_eq!(poll_after_ms(1000, 0.5, 0.1), 2000);
+        assert_eq!(poll_after_ms(1000, 0.2, 0.75), 4000);
+        assert_eq!(poll_after_ms(1000, 3.0, 0.0), 20_000);
+        assert_eq!(poll_after_ms(5000, 1.0, 1.0), MAX_POLL_AFTER_MS);
+    }
+
+    #[test]
+    fn counts_in_flight_requests() {
+        let load = Load::default();
+        let a = load.start();
+        let b = load.start();
+        assert_eq!(load.in_flight(), 2);
+        drop(a);
+        assert_eq!(load.in_flight(), 1);
+        drop(b);
+        assert_eq!(load.in_flight(), 0);
+    }
+}
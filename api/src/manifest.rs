@@ -0,0 +1,169 @@
+//! Manifests of BLOB ownership, exported to S3 so that BLOBs can still be downloaded while
+//! Postgres is down.
+//!
+//! The export job periodically writes, for each user, the content hashes of the BLOBs they can
+//! download, along with an index from the ids of API keys to their users and hashes. When a
+//! download can't reach the database, its key is checked and the request is authorized against
+//! these instead. Manifests lag behind the database,
+//! so they're only trusted for [`MAX_MANIFEST_AGE_HOURS`] after they were written.
+use crate::keys;
+use crate::middlewares::auth::Auth;
+use crate::persisters::s3store::StoreError;
+use crate::state::State;
+
+use sqlx::types::{
+    chrono::{self, Utc},
+    Uuid,
+};
+use std::collections::HashMap;
+
+/// How long after it was written a manifest is trusted for.
+pub const MAX_MANIFEST_AGE_HOURS: i64 = 24;
+
+/// The S3 key of the index of API keys.
+pub const KEY_INDEX_KEY: &str = "manifest/keys.json";
+
+/// The S3 key of a user's manifest.
+pub fn user_manifest_key(user_id: Uuid) -> String {
+    format!("manifest/users/{}.json", user_id)
+}
+
+/// The BLOBs a user can download.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UserManifest {
+    pub user_id: Uuid,
+    pub generated_dt: chrono::DateTime<Utc>,
+    /// Content hashes, sorted.
+    pub blobs: Vec<String>,
+}
+
+impl UserManifest {
+    pub fn contains(&self, content_hash: &str) -> bool {
+        self.blobs
+            .binary_search_by(|b| b.as_str().cmp(content_hash))
+            .is_ok()
+    }
+}
+
+/// An API key, as listed in the key index. Only hashes of keys are ever written to S3.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IndexedKey {
+    pub user_id: Uuid,
+    pub key_hash: String,
+    /// When the key stops authenticating, if it expires. Indexes written before expiries were
+    /// indexed have none.
+    #[serde(default)]
+    pub expire_dt: Option<chrono::DateTime<Utc>>,
+}
+
+impl IndexedKey {
+    /// Whether the key hasn't expired since the index was written.
+    fn is_live(&self) -> bool {
+        self.expire_dt
+            .map_or(true, |expire_dt| expire_dt > Utc::now())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeyIndex {
+    pub generated_dt: chrono::DateTime<Utc>,
+    /// API keys by their ids.
+    pub keys: HashMap<String, IndexedKey>,
+}
+
+fn is_fresh(generated_dt: chrono::DateTime<Utc>) -> bool {
+    Utc::now() - generated_dt < ::chrono::Duration::hours(MAX_MANIFEST_AGE_HOURS)
+}
+
+/// Whether an error from the database means it couldn't be reached, rather than that the query
+/// failed.
+pub fn is_unavailable(e: &sqlx::Error) -> bool {
+    matches!(
+        e,
+        sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::WorkerCrashed
+    )
+}
+
+async fn retrieve<T: serde::de::DeserializeOwned>(
+    state: &State,
+    key: &str,
+) -> Result<T, StoreError> {
+    let bytes = state.blob_store.retrieve_object(key).await?;
+    serde_json::from_slice(&bytes).map_err(|e| StoreError::S3Other(Box::new(e)))
+}
+
+/// Checks an API key against its hash in the key index. Stale indexes check nothing.
+pub async fn verify_key(state: &State, key: &str) -> Result<bool, StoreError> {
+    let index = retrieve::<KeyIndex>(state, KEY_INDEX_KEY).await?;
+    if !is_fresh(index.generated_dt) {
+        return Ok(false);
+    }
+    Ok(match index.keys.get(keys::key_id(key)) {
+        Some(indexed) if indexed.is_live() => {
+            keys::verify_blocking(key.to_string(), indexed.key_hash.clone()).await
+        }
+        _ => false,
+    })
+}
+
+/// Checks, against the exported manifests, that the principal can download the BLOB. Stale
+/// manifests authorize nothing.
+pub async fn authorize(state: &State, auth: &Auth, content_hash: &str) -> Result<bool, StoreError> {
+    let user_id = match auth {
+        Auth::Jwt(claims) => claims.sub,
+        Auth::ApiKey(key) => {
+            let index = retrieve::<KeyIndex>(state, KEY_INDEX_KEY).await?;
+            if !is_fresh(index.generated_dt) {
+                return Ok(false);
+            }
+            match index.keys.get(key) {
+                Some(indexed) if indexed.is_live() => indexed.user_id,
+                _ => return Ok(false),
+            }
+        }
+    };
+
+    let manifest = retrieve::<UserManifest>(state, &user_manifest_key(user_id)).await?;
+
+    Ok(is_fresh(manifest.generated_dt) && manifest.contains(content_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_sorted_blobs() {
+        let manifest = UserManifest {
+            user_id: Uuid::nil(),
+            generated_dt: Utc::now(),
+            blobs: vec!["aa".to_string(), "bb".to_string(), "cc".to_string()],
+        };
+        assert!(manifest.contains("bb"));
+        assert!(!manifest.contains("bc"));
+    }
+
+    #[test]
+    fn distrusts_stale_manifests() {
+        assert!(is_fresh(Utc::now() - ::chrono::Duration::hours(1)));
+        assert!(!is_fresh(
+            Utc::now() - ::chrono::Duration::hours(MAX_MANIFEST_AGE_HOURS + 1)
+        ));
+    }
+
+    #[test]
+    fn distrusts_expired_keys() {
+        let key = |expire_dt| IndexedKey {
+            user_id: Uuid::nil(),
+            key_hash: String::new(),
+            expire_dt,
+        };
+        assert!(key(None).is_live());
+        assert!(key(Some(Utc::now() + ::chrono::Duration::hours(1))).is_live());
+        assert!(!key(Some(Utc::now() - ::chrono::Duration::seconds(1))).is_live());
+    }
+}
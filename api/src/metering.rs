@@ -0,0 +1,184 @@
+//! Metering of stored bytes, for billing and quotas.
+//!
+//! Each user's BLOBs are counted by the tier they're stored in, and tiers are weighted so that
+//! cheaper storage counts for less. All of the arithmetic lives here, so that billing and quota
+//! enforcement agree on what a byte in each tier is worth.
+//!
+//! A BLOB with the same content as one stored earlier by another user, in the same place, is
+//! counted as deduplicated whatever its storage class: only one copy of it is actually stored,
+//! and that's paid for by whoever stored it first.
+use crate::config::Config;
+use std::collections::BTreeMap;
+
+/// A tier of storage, which bytes are counted in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageTier {
+    Hot,
+    InfrequentAccess,
+    Archive,
+    Deduplicated,
+}
+
+impl StorageTier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StorageTier::Hot => "hot",
+            StorageTier::InfrequentAccess => "infrequent_access",
+            StorageTier::Archive => "archive",
+            StorageTier::Deduplicated => "deduplicated",
+        }
+    }
+
+    /// The tier of a BLOB in the given S3 storage class. Unknown classes are counted as hot.
+    pub fn of(storage_class: &str, deduplicated: bool) -> Self {
+        if deduplicated {
+            return StorageTier::Deduplicated;
+        }
+        match storage_class {
+            "STANDARD_IA" | "ONEZONE_IA" | "GLACIER_IR" => StorageTier::InfrequentAccess,
+            "GLACIER" | "DEEP_ARCHIVE" => StorageTier::Archive,
+            _ => StorageTier::Hot,
+        }
+    }
+}
+
+impl std::str::FromStr for StorageTier {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hot" => Ok(StorageTier::Hot),
+            "infrequent_access" => Ok(StorageTier::InfrequentAccess),
+            "archive" => Ok(StorageTier::Archive),
+            "deduplicated" => Ok(StorageTier::Deduplicated),
+            _ => Err(()),
+        }
+    }
+}
+
+/// What a byte stored in each tier counts for, relative to a hot byte.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct TierWeights {
+    pub hot: f64,
+    pub infrequent_access: f64,
+    pub archive: f64,
+    pub deduplicated: f64,
+}
+
+impl TierWeights {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            hot: config.storage_weight_hot,
+            infrequent_access: config.storage_weight_infrequent_access,
+            archive: config.storage_weight_archive,
+            deduplicated: config.storage_weight_deduplicated,
+        }
+    }
+
+    pub fn weight(&self, tier: StorageTier) -> f64 {
+        match tier {
+            StorageTier::Hot => self.hot,
+            StorageTier::InfrequentAccess => self.infrequent_access,
+            StorageTier::Archive => self.archive,
+            StorageTier::Deduplicated => self.deduplicated,
+        }
+    }
+}
+
+/// Stored bytes, by tier.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct TierBytes(pub BTreeMap<StorageTier, i64>);
+
+impl TierBytes {
+    /// Counts `bytes` stored in the given storage class.
+    pub fn add(&mut self, storage_class: &str, deduplicated: bool, bytes: i64) {
+        *self
+            .0
+            .entry(StorageTier::of(storage_class, deduplicated))
+            .or_default() += bytes;
+    }
+
+    /// The total of the bytes, unweighted.
+    pub fn total(&self) -> i64 {
+        self.0.values().sum()
+    }
+
+    /// The bytes billed for, and counted against quotas: the bytes of each tier, weighted.
+    pub fn billable(&self, weights: &TierWeights) -> i64 {
+        let billable: f64 = self
+            .0
+            .iter()
+            .map(|(tier, bytes)| *bytes as f64 * weights.weight(*tier))
+            .sum();
+        billable.ceil() as i64
+    }
+}
+
+/// Whether `billable` bytes are still within `quota` once `content_length` more are stored. New
+/// BLOBs are stored hot.
+pub fn within_quota(billable: i64, content_length: i64, weights: &TierWeights, quota: i64) -> bool {
+    let added = (content_length as f64 * weights.hot).ceil() as i64;
+    billable.saturating_add(added) <= quota
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WEIGHTS: TierWeights = TierWeights {
+        hot: 1.0,
+        infrequent_access: 0.5,
+        archive: 0.25,
+        deduplicated: 0.0,
+    };
+
+    #[test]
+    fn tiers_of_storage_classes() {
+        assert_eq!(StorageTier::of("STANDARD", false), StorageTier::Hot);
+        assert_eq!(
+            StorageTier::of("STANDARD_IA", false),
+            StorageTier::InfrequentAccess
+        );
+        assert_eq!(StorageTier::of("GLACIER", false), StorageTier::Archive);
+        assert_eq!(StorageTier::of("GLACIER", true), StorageTier::Deduplicated);
+        assert_eq!(StorageTier::of("SOMETHING_NEW", false), StorageTier::Hot);
+        for tier in [
+            StorageTier::Hot,
+            StorageTier::InfrequentAccess,
+            StorageTier::Archive,
+            StorageTier::Deduplicated,
+        ] {
+            assert_eq!(tier.as_str().parse(), Ok(tier));
+        }
+    }
+
+    #[test]
+    fn weights_bytes() {
+        let mut bytes = TierBytes::default();
+        bytes.add("STANDARD", false, 1000);
+        bytes.add("STANDARD", false, 24);
+        bytes.add("STANDARD_IA", false, 100);
+        bytes.add("GLACIER", false, 3);
+        bytes.add("STANDARD", true, 5000);
+
+        assert_eq!(bytes.0[&StorageTier::Hot], 1024);
+        assert_eq!(bytes.total(), 6127);
+        // 1024 + 50 + 0.75, rounded up.
+        assert_eq!(bytes.billable(&WEIGHTS), 1075);
+        assert_eq!(TierBytes::default().billable(&WEIGHTS), 0);
+    }
+
+    #[test]
+    fn checks_quotas() {
+        assert!(within_quota(0, 1000, &WEIGHTS, 1000));
+        assert!(!within_quota(1, 1000, &WEIGHTS, 1000));
+        let half = TierWeights {
+            hot: 0.5,
+            ..WEIGHTS
+        };
+        assert!(within_quota(500, 1000, &half, 1000));
+        assert!(!within_quota(501, 1000, &half, 1000));
+        assert!(!within_quota(i64::MAX, 1, &WEIGHTS, i64::MAX - 1));
+    }
+}
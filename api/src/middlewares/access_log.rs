@@ -0,0 +1,318 @@
+//! A structured access log, written to stdout as JSON lines, for usage analytics and incident
+//! forensics.
+//!
+//! A request is logged once its response body has been sent (or dropped), so that the bytes sent
+//! are known. Each request is given an id, sent back in the [`REQUEST_ID_HEADER`] header, unless
+//! the client (or a load balancer) already sent one.
+//!
+//! When, and from where, API keys were last used is recorded along the way, at most once every
+//! [`LAST_SEEN_INTERVAL`] for each key, so that users can tell which of their keys are in use.
+use crate::middlewares::auth::Auth;
+use crate::models::SqlDateTime;
+use crate::persisters::api_key::touch;
+use crate::state::SqlPool;
+use actix_http::BoxedPayloadStream;
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue},
+    Error, FromRequest, HttpMessage,
+};
+use bytes::Bytes;
+use futures::future::{ok, LocalBoxFuture, Ready};
+use futures::StreamExt;
+use sqlx::types::{chrono::Utc, Uuid};
+use std::collections::HashMap;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// The header a request's id is sent in.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The most API keys whose owners are remembered. The cache is cleared when it fills up.
+const MAX_CACHED_KEYS: usize = 10_000;
+
+/// How often the last use of each API key is recorded.
+pub const LAST_SEEN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The owner of an API key, as logged. The key itself is never logged.
+#[derive(Clone)]
+struct KeyPrincipal {
+    user_id: Uuid,
+    label: String,
+}
+
+#[derive(Serialize, Debug)]
+struct AccessLogEntry {
+    time: SqlDateTime,
+    request_id: String,
+    /// The authenticated user, if any.
+    user_id: Option<Uuid>,
+    /// The label of the API key the request was authenticated with, if any.
+    key_label: Option<String>,
+    method: String,
+    /// The route template matched, e.g. `/blob/{content_hash}`, rather than the path, so that
+    /// requests can be grouped by route.
+    route: Option<String>,
+    status: u16,
+    latency_ms: f64,
+    bytes_in: u64,
+    bytes_out: u64,
+}
+
+/// Middleware writing the access log.
+#[derive(Clone)]
+pub struct AccessLog {
+    db_conn: SqlPool,
+    keys: Arc<Mutex<HashMap<String, KeyPrincipal>>>,
+    /// When the last use of each API key was last recorded.
+    seen: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl AccessLog {
+    pub fn new(db_conn: SqlPool) -> Self {
+        Self {
+            db_conn,
+            keys: Default::default(),
+            seen: Default::default(),
+        }
+    }
+
+    /// Records the use of an API key, unless it was recorded recently.
+    async fn see(&self, key: &str, ip: Option<&str>) {
+        {
+            let mut seen = self.seen.lock().unwrap();
+            if let Some(last) = seen.get(key) {
+                if last.elapsed() < LAST_SEEN_INTERVAL {
+                    return;
+                }
+            }
+            if seen.len() >= MAX_CACHED_KEYS {
+                seen.clear();
+            }
+            seen.insert(key.to_string(), Instant::now());
+        }
+
+        if let Err(e) = touch(&self.db_conn, key, ip).await {
+            log::warn!("could not record the last use of an api key: {:?}", e);
+        }
+    }
+
+    /// The owner of an API key. Lookups which fail are logged without an owner.
+    async fn key_principal(&self, key: &str) -> Option<KeyPrincipal> {
+        if let Some(principal) = self.keys.lock().unwrap().get(key) {
+            return Some(principal.clone());
+        }
+
+        let principal = query_as!(
+            KeyPrincipal,
+            "SELECT user_id, label FROM api_keys WHERE key = $1",
+            key,
+        )
+        .fetch_optional(&self.db_conn)
+        .await
+        .map_err(|e| log::warn!("could not look up api key for access log: {:?}", e))
+        .ok()
+        .flatten()?;
+
+        let mut keys = self.keys.lock().unwrap();
+        if keys.len() >= MAX_CACHED_KEYS {
+            keys.clear();
+        }
+        keys.insert(key.to_string(), principal.clone());
+        Some(principal)
+    }
+
+    /// Writes the entry of a request, once its principal is known.
+    fn write(&self, mut entry: AccessLogEntry, auth: Option<Auth>, ip: Option<String>) {
+        let log = self.clone();
+        actix_rt::spawn(async move {
+            match auth {
+                Some(Auth::Jwt(claims)) => entry.user_id = Some(claims.sub),
+                Some(Auth::ApiKey(key)) => {
+                    if let Some(principal) = log.key_principal(&key).await {
+                        entry.user_id = Some(principal.user_id);
+                        entry.key_label = Some(principal.label);
+                        log.see(&key, ip.as_deref()).await;
+                    }
+                }
+                None => {}
+            }
+
+            match serde_json::to_string(&entry) {
+                Ok(line) => {
+                    let _ = writeln!(std::io::stdout().lock(), "{}", line);
+                }
+                Err(e) => log::error!("could not serialize access log entry: {:?}", e),
+            }
+        });
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for AccessLog
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<LoggedBody>;
+    type Error = Error;
+    type Transform = AccessLogMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AccessLogMiddleware {
+            service,
+            log: self.clone(),
+        })
+    }
+}
+
+pub struct AccessLogMiddleware<S> {
+    service: S,
+    log: AccessLog,
+}
+
+impl<S, B> Service<ServiceRequest> for AccessLogMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<LoggedBody>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let time = Utc::now();
+        let start = Instant::now();
+
+        let request_id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| Uuid::new_v4().to_string());
+        // Requests which fail to authenticate are logged without a principal.
+        let auth = Auth::from_request(req.request(), &mut dev::Payload::None)
+            .into_inner()
+            .ok();
+        let ip = req
+            .connection_info()
+            .realip_remote_addr()
+            .map(|a| a.to_string());
+        let method = req.method().to_string();
+
+        let bytes_in = Arc::new(AtomicU64::new(0));
+        let payload = req.take_payload().inspect({
+            let bytes_in = bytes_in.clone();
+            move |chunk| {
+                if let Ok(chunk) = chunk {
+                    bytes_in.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                }
+            }
+        });
+        req.set_payload(dev::Payload::from(Box::pin(payload) as BoxedPayloadStream));
+
+        let res = self.service.call(req);
+        let log = self.log.clone();
+
+        Box::pin(async move {
+            // Inner middleware answers errors with responses, so they're logged. The request can't
+            // be held on to here to answer others with, as routing needs it to itself.
+            let mut res = res.await?.map_body(|_, body| body.boxed());
+            if let Ok(value) = HeaderValue::from_str(&request_id) {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+
+            let entry = AccessLogEntry {
+                time,
+                request_id,
+                user_id: None,
+                key_label: None,
+                method,
+                route: res.request().match_pattern(),
+                status: res.status().as_u16(),
+                latency_ms: 0.0,
+                bytes_in: 0,
+                bytes_out: 0,
+            };
+            Ok(res.map_body(|_, body| LoggedBody {
+                body,
+                bytes_out: 0,
+                pending: Some(Pending {
+                    entry,
+                    auth,
+                    ip,
+                    start,
+                    bytes_in,
+                    log,
+                }),
+            }))
+        })
+    }
+}
+
+/// What's needed to write a request's entry once its body has been sent.
+struct Pending {
+    entry: AccessLogEntry,
+    auth: Option<Auth>,
+    ip: Option<String>,
+    start: Instant,
+    bytes_in: Arc<AtomicU64>,
+    log: AccessLog,
+}
+
+/// A response body which counts the bytes sent, and writes the request's entry when it's dropped.
+pub struct LoggedBody {
+    body: BoxBody,
+    bytes_out: u64,
+    pending: Option<Pending>,
+}
+
+impl MessageBody for LoggedBody {
+    type Error = <BoxBody as MessageBody>::Error;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.body).poll_next(cx);
+        if let Poll::Ready(Some(Ok(ref chunk))) = res {
+            this.bytes_out += chunk.len() as u64;
+        }
+        res
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        if let Some(Pending {
+            mut entry,
+            auth,
+            ip,
+            start,
+            bytes_in,
+            log,
+        }) = self.pending.take()
+        {
+            entry.latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+            entry.bytes_in = bytes_in.load(Ordering::Relaxed);
+            entry.bytes_out = self.bytes_out;
+            log.write(entry, auth, ip);
+        }
+    }
+}
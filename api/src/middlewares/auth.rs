@@ -1,13 +1,15 @@
+use crate::error_code::{coded, ErrorCode};
 use crate::handlers::login::Claims;
 use crate::CONFIG;
 
-use actix_web::{dev, error, FromRequest, HttpRequest};
+use actix_web::{dev, FromRequest, HttpMessage, HttpRequest};
 use futures::future::{err, ok, Ready};
 use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
 
 #[derive(Debug)]
 pub enum Auth {
-    /// API-key based auth. This string is not validated until SQL queries use it.
+    /// API-key based auth, by the id of a key which has been checked against its hash by the
+    /// [`KeyAuth`](crate::middlewares::key_auth::KeyAuth) middleware. See [`crate::keys`].
     ApiKey(String),
     /// JWT based auth. If a JWT is provided, it is immediately decoded and checked
     /// for validity. If this process succeeds, the claims are stored here.
@@ -19,18 +21,29 @@ pub enum AuthError {
     NoAuthHeader,
     InvalidAuthHeader(String),
     InvalidJwt(jsonwebtoken::errors::Error),
+    /// The API key doesn't exist, or doesn't match its hash.
+    InvalidApiKey,
 }
 
+/// The id of the request's API key, once it has been checked.
+#[derive(Debug, Clone)]
+pub struct VerifiedKey(pub String);
+
 impl From<AuthError> for actix_web::Error {
     fn from(e: AuthError) -> Self {
         match e {
-            AuthError::NoAuthHeader => {
-                error::ErrorUnauthorized("Error: No `Authorization` header present on request.")
-            }
-            AuthError::InvalidAuthHeader(s) => {
-                error::ErrorUnauthorized(format!("Error: Invalid `Authorization` header. {}", s))
+            AuthError::NoAuthHeader => coded(
+                ErrorCode::NoAuthHeader,
+                "Error: No `Authorization` header present on request.",
+            ),
+            AuthError::InvalidAuthHeader(s) => coded(
+                ErrorCode::InvalidAuthHeader,
+                format!("Error: Invalid `Authorization` header. {}", s),
+            ),
+            AuthError::InvalidJwt(_) => {
+                coded(ErrorCode::InvalidJwt, "Error: Invalid JWT provided.")
             }
-            AuthError::InvalidJwt(_) => error::ErrorForbidden("Error: Invalid JWT provided."),
+            AuthError::InvalidApiKey => coded(ErrorCode::InvalidApiKey, "Error: Invalid API key."),
         }
     }
 }
@@ -48,13 +61,35 @@ impl Auth {
                     "Header should be of the form `Bearer {token}`".to_string(),
                 ))?;
 
-            Auth::from_jwt(token)
+            // Some clients (e.g. MLflow's `MLFLOW_TRACKING_TOKEN`) can only send bearer tokens,
+            // so a bearer token which isn't shaped like a JWT is taken to be an API key.
+            if token.contains('.') {
+                Auth::from_jwt(token)
+            } else {
+                Auth::from_api_key(token)
+            }
+        } else if let Some(credentials) = s.strip_prefix("Basic ") {
+            // HTTP basic auth, for tools which only support usernames and passwords (e.g. DVC's
+            // HTTP remote). The username is ignored, and the password is the API key.
+            Auth::from_basic(credentials.trim())
         } else {
             // The token is an API key.
             Auth::from_api_key(s)
         }
     }
 
+    fn from_basic(s: &str) -> Result<Self, AuthError> {
+        let invalid = || {
+            AuthError::InvalidAuthHeader(
+                "Header should be of the form `Basic {base64(username:api_key)}`".to_string(),
+            )
+        };
+        let decoded = base64::decode(s).map_err(|_| invalid())?;
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        let (_username, password) = decoded.split_once(':').ok_or_else(invalid)?;
+        Auth::from_api_key(password)
+    }
+
     // Assumes that the string begins with "Bearer " (i.e. including the space).
     fn from_jwt(s: &str) -> Result<Self, AuthError> {
         let key = &*CONFIG.jwt_priv.as_bytes();
@@ -100,54 +135,38 @@ impl Auth {
             Auth::Jwt(c) => Some(&c),
         }
     }
-
-    /// Call this on the `Auth` extracted from a request if you wish to accept only the JWT
-    /// authentication strategy. In the error case, this function returns an
-    /// `actix_web::Error`, so the result can be early returned from the handler with `?`.
-    pub fn allow_only_jwt(&self) -> Result<&Claims, actix_web::Error> {
-        match self {
-            Auth::Jwt(c) => Ok(c),
-            Auth::ApiKey(_) => Err(error::ErrorForbidden(
-                "Invalid `Authorization` header. Expected JWT.",
-            )),
-        }
-    }
-
-    /// Call this on the `Auth` extracted from a request if you wish to accept only the API key
-    /// authentication strategy. In the error case, this function returns an
-    /// `actix_web::Error`, so the result can be early returned from the handler with `?`.
-    pub fn allow_only_api_key(&self) -> Result<&str, actix_web::Error> {
-        match self {
-            Auth::Jwt(_) => Err(error::ErrorForbidden(
-                "Invalid `Authorization` header. Expected API key.",
-            )),
-            Auth::ApiKey(k) => Ok(k),
-        }
-    }
 }
 
-impl FromRequest for Auth {
-    type Error = AuthError;
-    type Future = Ready<Result<Auth, Self::Error>>;
-
-    fn from_request(req: &HttpRequest, _payload: &mut dev::Payload) -> Self::Future {
+impl Auth {
+    /// The auth sent with a request, with the API key, if any, as sent rather than checked.
+    pub fn from_headers(req: &HttpRequest) -> Result<Self, AuthError> {
         // Need to check both `Authorization` header, and cookie, for the JWT.
         if let Some(cookie) = req.cookie("jwt") {
-            match Auth::from_jwt(cookie.value()) {
-                Ok(auth) => ok(auth),
-                Err(e) => err(e),
-            }
+            Auth::from_jwt(cookie.value())
         } else if let Some(auth_header) = req
             .headers()
             .get("Authorization")
             .and_then(|h| h.to_str().ok())
         {
-            match Auth::from_auth_header(auth_header) {
-                Ok(auth) => ok(auth),
-                Err(e) => err(e),
-            }
+            Auth::from_auth_header(auth_header)
         } else {
-            err(AuthError::NoAuthHeader)
+            Err(AuthError::NoAuthHeader)
+        }
+    }
+}
+
+impl FromRequest for Auth {
+    type Error = AuthError;
+    type Future = Ready<Result<Auth, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut dev::Payload) -> Self::Future {
+        match Auth::from_headers(req) {
+            Ok(Auth::ApiKey(_)) => match req.extensions().get::<VerifiedKey>() {
+                Some(VerifiedKey(id)) => ok(Auth::ApiKey(id.clone())),
+                None => err(AuthError::InvalidApiKey),
+            },
+            Ok(auth) => ok(auth),
+            Err(e) => err(e),
         }
     }
 }
@@ -0,0 +1,149 @@
+//! Middleware capturing the raw bytes of failing requests made with API keys opted into capture.
+//! See [`crate::capture`].
+//!
+//! The start of a capturable request's body, up to the size cap, is read before the request is
+//! handled and then replayed to the handler, so that it's captured however much of the body the
+//! handler gets through before failing.
+use crate::capture;
+use crate::middlewares::auth::Auth;
+use crate::persisters::capture::{capturing, record, CaptureInsert};
+use crate::state::AppStateRaw;
+use actix_http::BoxedPayloadStream;
+use actix_web::{
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header,
+    Error, FromRequest, HttpMessage,
+};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use futures::{stream, StreamExt};
+use std::rc::Rc;
+
+/// Middleware capturing failing requests.
+#[derive(Clone)]
+pub struct Capture {
+    state: AppStateRaw,
+}
+
+impl Capture {
+    pub fn new(state: AppStateRaw) -> Self {
+        Self { state }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Capture
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = CaptureMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CaptureMiddleware {
+            service: Rc::new(service),
+            state: self.state.clone(),
+        })
+    }
+}
+
+pub struct CaptureMiddleware<S> {
+    service: Rc<S>,
+    state: AppStateRaw,
+}
+
+impl<S, B> Service<ServiceRequest> for CaptureMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let state = self.state.clone();
+
+        let key = match Auth::from_request(req.request(), &mut dev::Payload::None).into_inner() {
+            Ok(Auth::ApiKey(key))
+                if capture::capturable(req.method(), req.path(), &req.content_type()) =>
+            {
+                key
+            }
+            _ => return Box::pin(service.call(req)),
+        };
+
+        Box::pin(async move {
+            if !capturing(&key, &state).await {
+                return service.call(req).await;
+            }
+
+            let max_bytes = state.config.capture_max_bytes;
+            let mut payload = req.take_payload();
+            let mut chunks = Vec::new();
+            let mut read = 0;
+            while read < max_bytes {
+                match payload.next().await {
+                    Some(chunk) => {
+                        if let Ok(chunk) = &chunk {
+                            read += chunk.len();
+                        }
+                        let failed = chunk.is_err();
+                        chunks.push(chunk);
+                        if failed {
+                            break;
+                        }
+                    }
+                    None => break,
+                }
+            }
+
+            let mut body = Vec::with_capacity(read.min(max_bytes));
+            for chunk in chunks.iter().flatten() {
+                body.extend_from_slice(chunk);
+            }
+            body.truncate(max_bytes);
+
+            let replay = stream::iter(chunks).chain(payload);
+            req.set_payload(dev::Payload::from(Box::pin(replay) as BoxedPayloadStream));
+
+            let header_value = |name: header::HeaderName| {
+                req.headers()
+                    .get(name)
+                    .and_then(|v| v.to_str().ok())
+                    .map(String::from)
+            };
+            let mut capture = CaptureInsert {
+                api_key: key,
+                method: req.method().to_string(),
+                path: req.path().to_string(),
+                content_type: header_value(header::CONTENT_TYPE),
+                user_agent: header_value(header::USER_AGENT),
+                status: 0,
+                body_len: header_value(header::CONTENT_LENGTH).and_then(|l| l.parse().ok()),
+                body,
+            };
+
+            let res = service.call(req).await;
+            capture.status = match &res {
+                Ok(res) => res.status().as_u16(),
+                Err(e) => e.as_response_error().status_code().as_u16(),
+            };
+            if capture.status >= 400 {
+                actix_rt::spawn(async move {
+                    if let Err(e) = record(&state, capture).await {
+                        log::error!("could not record captured request: {:?}", e);
+                    }
+                });
+            }
+            res
+        })
+    }
+}
@@ -0,0 +1,168 @@
+//! Middleware giving every error response a code. See [`crate::error_code`].
+//!
+//! Responses which already have a code are passed on as they are. Any other error response is
+//! given the general code of its status, and, if its body is plain text, a JSON body with the code
+//! and its message in place of it. Bodies in other formats are a protocol's own (e.g. SCIM's, or
+//! S3's), so they're left as they are.
+use crate::error_code::{ErrorBody, ErrorCode, ERROR_CODE_HEADER};
+use actix_web::{
+    body::{BoxBody, EitherBody, MessageBody},
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{self, HeaderValue},
+    Error,
+};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use std::rc::Rc;
+
+/// Middleware giving error responses codes.
+#[derive(Clone, Default)]
+pub struct ErrorCodes;
+
+impl<S, B> Transform<S, ServiceRequest> for ErrorCodes
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = ErrorCodesMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ErrorCodesMiddleware {
+            service: Rc::new(service),
+        })
+    }
+}
+
+pub struct ErrorCodesMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for ErrorCodesMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let res = self.service.call(req);
+
+        Box::pin(async move {
+            // Handlers' errors arrive as responses. The request can't be held on to to answer
+            // other errors with, as routing needs it to itself, so they're left to the server.
+            let res = res.await?.map_into_left_body();
+            Ok(with_code(res))
+        })
+    }
+}
+
+fn with_code<B>(mut res: ServiceResponse<EitherBody<B>>) -> ServiceResponse<EitherBody<B>> {
+    let status = res.status();
+    let is_error = status.is_client_error() || status.is_server_error();
+    if !is_error || res.headers().contains_key(ERROR_CODE_HEADER) {
+        return res;
+    }
+
+    let code = ErrorCode::for_status(status);
+    res.headers_mut().insert(
+        header::HeaderName::from_static(ERROR_CODE_HEADER),
+        HeaderValue::from_static(code.as_str()),
+    );
+    let plain_text = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .map_or(true, |t| t.as_bytes().starts_with(b"text/plain"));
+    if !plain_text {
+        return res;
+    }
+
+    let message = match res.response().error() {
+        Some(e) => e.to_string(),
+        None => status.canonical_reason().unwrap_or_default().to_string(),
+    };
+    let body = match serde_json::to_vec(&ErrorBody::new(code, message)) {
+        Ok(body) => body,
+        Err(_) => return res,
+    };
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    res.map_body(|_, _| EitherBody::right(BoxBody::new(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error_code::coded;
+    use actix_http::Request;
+    use actix_web::{error, test, web, App, HttpResponse};
+
+    async fn body_of<S, B>(app: &S, path: &str) -> (Option<String>, serde_json::Value)
+    where
+        S: Service<Request, Response = ServiceResponse<B>, Error = Error>,
+        B: MessageBody,
+    {
+        let res = test::call_service(app, test::TestRequest::get().uri(path).to_request()).await;
+        let code = res
+            .headers()
+            .get(ERROR_CODE_HEADER)
+            .map(|c| c.to_str().unwrap().to_string());
+        let body = test::read_body(res).await;
+        (code, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[actix_rt::test]
+    async fn gives_error_responses_codes() {
+        let app = test::init_service(
+            App::new()
+                .wrap(ErrorCodes)
+                .route(
+                    "/plain",
+                    web::get()
+                        .to(|| async { Err::<HttpResponse, _>(error::ErrorNotFound("gone")) }),
+                )
+                .route(
+                    "/coded",
+                    web::get().to(|| async {
+                        Err::<HttpResponse, _>(coded(ErrorCode::InvalidHash, "invalid hash"))
+                    }),
+                )
+                .route(
+                    "/json",
+                    web::get().to(|| async {
+                        HttpResponse::BadRequest().json(serde_json::json!({"detail": "scim"}))
+                    }),
+                )
+                .route("/ok", web::get().to(|| async { "ok" })),
+        )
+        .await;
+
+        let (code, body) = body_of(&app, "/plain").await;
+        assert_eq!(code.as_deref(), Some("HS-0404"));
+        assert_eq!(
+            body,
+            serde_json::json!({"code": "HS-0404", "name": "NotFound", "message": "gone"})
+        );
+
+        let (code, body) = body_of(&app, "/coded").await;
+        assert_eq!(code.as_deref(), Some("HS-1001"));
+        assert_eq!(body["name"], "InvalidHash");
+
+        let (code, body) = body_of(&app, "/json").await;
+        assert_eq!(code.as_deref(), Some("HS-0400"));
+        assert_eq!(body, serde_json::json!({"detail": "scim"}));
+
+        let (code, _) = body_of(&app, "/ok").await;
+        assert_eq!(code, None);
+    }
+}
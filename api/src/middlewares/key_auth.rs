@@ -0,0 +1,81 @@
+//! Middleware checking the API key sent with a request against its hash. See [`crate::keys`].
+//!
+//! Once a key is checked, the id it's stored under is attached to the request, and the [`Auth`]
+//! extractor authenticates the request as that id. Requests with keys which don't check out are
+//! passed on, and fail to authenticate wherever auth is needed.
+use crate::middlewares::auth::{Auth, VerifiedKey};
+use crate::persisters::api_key::verify_key;
+use crate::state::AppStateRaw;
+use actix_web::{
+    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
+    Error, HttpMessage,
+};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use std::rc::Rc;
+
+/// Middleware checking API keys.
+#[derive(Clone)]
+pub struct KeyAuth {
+    state: AppStateRaw,
+}
+
+impl KeyAuth {
+    pub fn new(state: AppStateRaw) -> Self {
+        Self { state }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for KeyAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = KeyAuthMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(KeyAuthMiddleware {
+            service: Rc::new(service),
+            state: self.state.clone(),
+        })
+    }
+}
+
+pub struct KeyAuthMiddleware<S> {
+    service: Rc<S>,
+    state: AppStateRaw,
+}
+
+impl<S, B> Service<ServiceRequest> for KeyAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    dev::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let state = self.state.clone();
+
+        let key = match Auth::from_headers(req.request()) {
+            Ok(Auth::ApiKey(key)) => key,
+            _ => return Box::pin(service.call(req)),
+        };
+
+        Box::pin(async move {
+            if let Some(id) = verify_key(&state, &key).await {
+                req.extensions_mut().insert(VerifiedKey(id));
+            }
+            service.call(req).await
+        })
+    }
+}
@@ -1 +1,5 @@
+pub mod access_log;
 pub mod auth;
+pub mod capture;
+pub mod error_codes;
+pub mod key_auth;
@@ -0,0 +1,79 @@
+use sqlx::types::chrono;
+
+/// How a metric value is compared against the threshold of an alert rule.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    Gte,
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    Lte,
+}
+
+impl Comparison {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Comparison::Gt => ">",
+            Comparison::Gte => ">=",
+            Comparison::Lt => "<",
+            Comparison::Lte => "<=",
+        }
+    }
+}
+
+/// Where the notification is sent when an alert rule fires.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    Email,
+    Webhook,
+}
+
+impl Channel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Channel::Email => "email",
+            Channel::Webhook => "webhook",
+        }
+    }
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug)]
+pub struct AlertRule {
+    pub id: i64,
+    pub project: Option<String>,
+    pub metric: String,
+    pub comparison: String,
+    pub threshold: f64,
+    pub window_secs: i32,
+    pub channel: String,
+    pub target: String,
+    pub last_fired_dt: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug)]
+pub enum AlertError {
+    Unauthorized,
+    NotFound,
+    /// The rule was rejected, e.g. because of a non-positive window.
+    InvalidRule,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for AlertError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            // check_violation
+            sqlx::Error::Database(ref err)
+                if err.code() == Some(std::borrow::Cow::Borrowed("23514")) =>
+            {
+                Self::InvalidRule
+            }
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use sqlx::types::{chrono, Uuid};
+
+/// The length of the window in which recent activity is counted.
+pub const WINDOW_MINS: i32 = 15;
+
+/// How far back activity is kept, to establish what is usual.
+pub const BASELINE_HOURS: i32 = 24;
+
+/// How many times the usual activity per window counts as a spike.
+pub const SPIKE_FACTOR: f64 = 10.0;
+
+/// Something which happens to the cache, as recorded in `cache_activity`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+    /// A lookup of an eval by its function and argument hashes which found it.
+    Hit,
+    /// A lookup of an eval by its function and argument hashes which found nothing.
+    Miss,
+    /// A BLOB was downloaded.
+    Download,
+    /// An upload was rejected because the BLOB didn't match its claimed hash.
+    InvalidHash,
+    /// An upload was aborted because no bytes arrived for too long.
+    StalledUpload,
+}
+
+impl Activity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Activity::Hit => "hit",
+            Activity::Miss => "miss",
+            Activity::Download => "download",
+            Activity::InvalidHash => "invalid_hash",
+            Activity::StalledUpload => "stalled_upload",
+        }
+    }
+}
+
+/// A suspicious pattern of cache activity.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AnomalyKind {
+    /// Many more lookups of a function miss than usual, which usually means its hash is unstable.
+    MissStorm,
+    /// A key downloads many more BLOBs than usual.
+    DownloadSpike,
+    /// A key repeatedly uploads BLOBs which don't match their hashes.
+    InvalidHashUploads,
+}
+
+impl AnomalyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnomalyKind::MissStorm => "miss_storm",
+            AnomalyKind::DownloadSpike => "download_spike",
+            AnomalyKind::InvalidHashUploads => "invalid_hash_uploads",
+        }
+    }
+
+    /// The activity the anomaly is detected from.
+    pub fn activity(&self) -> Activity {
+        match self {
+            AnomalyKind::MissStorm => Activity::Miss,
+            AnomalyKind::DownloadSpike => Activity::Download,
+            AnomalyKind::InvalidHashUploads => Activity::InvalidHash,
+        }
+    }
+
+    /// The least activity within a window which can be flagged, so that quiet users aren't
+    /// flagged for small fluctuations.
+    pub fn min_observed(&self) -> i64 {
+        match self {
+            AnomalyKind::MissStorm => 50,
+            AnomalyKind::DownloadSpike => 500,
+            AnomalyKind::InvalidHashUploads => 5,
+        }
+    }
+
+    /// Whether `observed` activity within the last window is anomalous, given a `baseline` of
+    /// usual activity per window. Invalid hashes are never usual, so repeated ones are always
+    /// flagged.
+    pub fn is_anomalous(&self, observed: i64, baseline: f64) -> bool {
+        observed >= self.min_observed()
+            && (*self == AnomalyKind::InvalidHashUploads
+                || observed as f64 > SPIKE_FACTOR * baseline.max(1.0))
+    }
+}
+
+/// The average activity per window, from the activity counted before the last window.
+pub fn baseline(earlier: i64) -> f64 {
+    let windows = (BASELINE_HOURS * 60 - WINDOW_MINS) as f64 / WINDOW_MINS as f64;
+    earlier as f64 / windows
+}
+
+#[derive(Serialize, Debug)]
+pub struct Anomaly {
+    pub id: i64,
+    pub kind: String,
+    pub user_id: Uuid,
+    pub gh_login: String,
+    /// The label of the key involved, for anomalies about a key.
+    pub key_label: Option<String>,
+    /// The function involved, for anomalies about a function.
+    pub fn_key: Option<String>,
+    pub observed: i64,
+    pub baseline: f64,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+    pub acknowledged_dt: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug)]
+pub enum AnomalyError {
+    Unauthorized,
+    /// Only admins can see anomalies.
+    Forbidden,
+    NotFound,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for AnomalyError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_spikes_over_baseline() {
+        let storm = AnomalyKind::MissStorm;
+        assert!(!storm.is_anomalous(49, 0.0));
+        assert!(storm.is_anomalous(50, 0.0));
+        assert!(!storm.is_anomalous(50, 5.0));
+        assert!(storm.is_anomalous(51, 5.0));
+
+        assert!(AnomalyKind::InvalidHashUploads.is_anomalous(5, 100.0));
+        assert!(!AnomalyKind::InvalidHashUploads.is_anomalous(4, 0.0));
+
+        assert_eq!(baseline(95), 1.0);
+    }
+}
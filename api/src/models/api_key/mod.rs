@@ -1,3 +1,5 @@
+use super::SqlDateTime;
+use crate::policy::PolicyError;
 use rand::distributions::Alphanumeric;
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha20Rng;
@@ -24,6 +26,29 @@ impl ApiKey {
     }
 }
 
+/// A short-lived key exchanged for a signed in session, for one device and optionally one project.
+#[derive(Serialize, Debug)]
+pub struct ExchangedKey {
+    pub key: String,
+    pub device_name: String,
+    pub project: Option<String>,
+    pub expire_dt: SqlDateTime,
+}
+
+/// One of a user's own keys, as listed at `/user/sessions`. The key itself isn't listed, only its
+/// first characters, so that it can be told apart from others with the same label.
+#[derive(Serialize, Debug)]
+pub struct KeySession {
+    pub key_hint: String,
+    pub label: String,
+    pub device_name: Option<String>,
+    pub project: Option<String>,
+    pub create_dt: SqlDateTime,
+    pub expire_dt: Option<SqlDateTime>,
+    pub last_seen_dt: Option<SqlDateTime>,
+    pub last_seen_ip: Option<String>,
+}
+
 #[derive(Debug)]
 pub enum ApiKeyError {
     /// Passes through sqlx errors.
@@ -31,6 +56,12 @@ pub enum ApiKeyError {
     /// Represents scenario when a request is made to generate an API key for an email address not
     /// known to the database.
     Unauthorized,
+    /// The authorization policy doesn't allow the request.
+    Forbidden,
+    /// A new key couldn't be hashed.
+    Hash,
+    /// A key exchange names no device, or a device or project name which is too long.
+    InvalidExchange,
 }
 
 impl From<sqlx::Error> for ApiKeyError {
@@ -39,6 +70,15 @@ impl From<sqlx::Error> for ApiKeyError {
     }
 }
 
+impl From<PolicyError> for ApiKeyError {
+    fn from(err: PolicyError) -> Self {
+        match err {
+            PolicyError::Sqlx(e) => ApiKeyError::Sqlx(e),
+            _ => ApiKeyError::Forbidden,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
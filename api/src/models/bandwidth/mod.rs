@@ -0,0 +1,38 @@
+use super::SqlDateTime;
+
+/// The bandwidth caps of an API key, in bytes per second. A key without a cap is limited by the
+/// server's default cap, if one is configured.
+#[derive(Serialize, Debug)]
+pub struct KeyBandwidth {
+    pub label: String,
+    pub create_dt: SqlDateTime,
+    pub upload_bytes_per_sec: Option<i64>,
+    pub download_bytes_per_sec: Option<i64>,
+}
+
+#[derive(Debug)]
+pub enum BandwidthError {
+    Unauthorized,
+    /// Only admins can see and change bandwidth caps.
+    Forbidden,
+    /// The user has no keys (with the given label).
+    NotFound,
+    /// Caps must be positive.
+    InvalidCap,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for BandwidthError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            // check_violation
+            sqlx::Error::Database(ref err)
+                if err.code() == Some(std::borrow::Cow::Borrowed("23514")) =>
+            {
+                Self::InvalidCap
+            }
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use sqlx::types::chrono;
+
+/// The advisory lock held while a backfill runs, so that only one runs at a time.
+pub const BACKFILL_LOCK: i64 = 0x6869_7473_6176_6501;
+
+/// How long a BLOB can be recorded without its object being stored before it's reported missing.
+/// Clients upload a BLOB straight after putting the eval which references it.
+pub const PENDING_GRACE_HOURS: i32 = 24;
+
+/// How long the BLOB of an eval put with `deferred` has to be uploaded in before it's reported
+/// missing, since its result may take much longer to serialize than the eval to put.
+pub const DEFERRED_PAYLOAD_HOURS: i32 = 7 * 24;
+
+/// Whether a BLOB's object can be downloaded, as reported with each eval which references it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BlobStatus {
+    /// The object is stored.
+    Available,
+    /// The object hasn't been seen yet, most likely because it's still being uploaded, or it's yet
+    /// to be for an eval put with `deferred`.
+    Pending,
+    /// The object wasn't uploaded within [`PENDING_GRACE_HOURS`], or it has gone since.
+    Missing,
+    /// The object is in cold storage, and the run it belongs to has to be unarchived first.
+    Archived,
+}
+
+impl BlobStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BlobStatus::Available => "available",
+            BlobStatus::Pending => "pending",
+            BlobStatus::Missing => "missing",
+            BlobStatus::Archived => "archived",
+        }
+    }
+}
+
+/// A way in which a stored object disagrees with the metadata recorded for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiscrepancyKind {
+    /// The object's contents don't hash to the content hash it's stored under.
+    HashMismatch,
+    /// The object's length differs from the recorded length.
+    LengthMismatch,
+    /// There's no object stored under the content hash.
+    MissingObject,
+}
+
+impl DiscrepancyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DiscrepancyKind::HashMismatch => "hash_mismatch",
+            DiscrepancyKind::LengthMismatch => "length_mismatch",
+            DiscrepancyKind::MissingObject => "missing_object",
+        }
+    }
+}
+
+/// What verifying a stored object found.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Verification {
+    /// The length to record, when none was recorded before.
+    pub fill_length: Option<i64>,
+    /// Disagreements to report, with the recorded and actual values.
+    pub discrepancies: Vec<(DiscrepancyKind, String, String)>,
+}
+
+/// Compares the recorded metadata of a BLOB with the hash and length of its stored object.
+/// Recorded values are never corrected, only reported; a missing length is filled in.
+pub fn verify(
+    recorded_hash: &str,
+    recorded_length: Option<i64>,
+    actual_hash: &str,
+    actual_length: i64,
+) -> Verification {
+    let mut discrepancies = vec![];
+
+    if !recorded_hash.eq_ignore_ascii_case(actual_hash) {
+        discrepancies.push((
+            DiscrepancyKind::HashMismatch,
+            recorded_hash.to_string(),
+            actual_hash.to_string(),
+        ));
+    }
+
+    let fill_length = match recorded_length {
+        None => Some(actual_length),
+        Some(len) if len != actual_length => {
+            discrepancies.push((
+                DiscrepancyKind::LengthMismatch,
+                len.to_string(),
+                actual_length.to_string(),
+            ));
+            None
+        }
+        Some(_) => None,
+    };
+
+    Verification {
+        fill_length,
+        discrepancies,
+    }
+}
+
+/// A disagreement between a stored object and its recorded metadata, for an admin to look into.
+#[derive(Serialize, Debug)]
+pub struct Discrepancy {
+    pub id: i64,
+    pub content_hash: String,
+    pub kind: String,
+    pub recorded: Option<String>,
+    pub actual: Option<String>,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+    pub resolved_dt: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// How far the backfill has got.
+#[derive(Serialize, Debug)]
+pub struct BackfillStatus {
+    pub running: bool,
+    /// BLOBs whose stored object hasn't been verified yet.
+    pub unverified: i64,
+    /// Discrepancies which haven't been marked as resolved.
+    pub unresolved: i64,
+}
+
+#[derive(Debug)]
+pub enum BackfillError {
+    Unauthorized,
+    /// Only admins can run the backfill and see its results.
+    Forbidden,
+    NotFound,
+    AlreadyRunning,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for BackfillError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262";
+    const OTHER: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+    #[test]
+    fn fills_missing_length() {
+        assert_eq!(
+            verify(HASH, None, HASH, 10),
+            Verification {
+                fill_length: Some(10),
+                discrepancies: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn reports_rather_than_overwrites() {
+        let v = verify(HASH, Some(12), OTHER, 10);
+        assert_eq!(v.fill_length, None);
+        assert_eq!(
+            v.discrepancies,
+            vec![
+                (
+                    DiscrepancyKind::HashMismatch,
+                    HASH.to_string(),
+                    OTHER.to_string()
+                ),
+                (
+                    DiscrepancyKind::LengthMismatch,
+                    "12".to_string(),
+                    "10".to_string()
+                ),
+            ]
+        );
+    }
+}
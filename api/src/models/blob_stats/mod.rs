@@ -0,0 +1,61 @@
+use sqlx::types::{chrono, JsonValue};
+
+/// A tabular file format which stats can be computed for.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Parquet,
+    /// The Arrow IPC file format.
+    Arrow,
+    /// Comma or tab separated values, with a header row.
+    Csv,
+}
+
+impl Format {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Format::Parquet => "parquet",
+            Format::Arrow => "arrow",
+            Format::Csv => "csv",
+        }
+    }
+}
+
+/// Statistics of one column of a table. `min`, `max` and `mean` are only computed for numeric
+/// columns.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ColumnStats {
+    pub name: String,
+    pub data_type: String,
+    pub null_count: i64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+}
+
+/// The statistics of a BLOB. `status` is `pending` until the background job has looked at it.
+#[derive(Serialize, Debug)]
+pub struct BlobStats {
+    pub status: String,
+    pub format: Option<String>,
+    pub row_count: Option<i64>,
+    pub columns: Option<JsonValue>,
+    pub error: Option<String>,
+    pub create_dt: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug)]
+pub enum BlobStatsError {
+    Unauthorized,
+    NotFound,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for BlobStatsError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
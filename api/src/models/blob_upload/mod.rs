@@ -0,0 +1,117 @@
+use crate::persisters::s3store::StoreError;
+use blake3::HexError;
+use sqlx::types::{chrono, Uuid};
+
+/// The length of each part of an upload but the last, in bytes. S3 needs parts but the last to be
+/// at least 5 MiB, and the BLOB's hash is checkpointed after each part, so this must be a multiple
+/// of BLAKE3's chunk length.
+pub const UPLOAD_PART_SIZE: i64 = 8 * 1024 * 1024;
+
+/// The most parts an upload can have, which is the most S3 allows.
+pub const MAX_UPLOAD_PARTS: i64 = 10_000;
+
+/// How long an upload can sit without a part being received before it's abandoned.
+pub const UPLOAD_TTL_HOURS: i32 = 24;
+
+/// The number of parts a BLOB of `content_length` bytes is uploaded in. An empty BLOB is uploaded
+/// in one empty part.
+pub fn part_count(content_length: i64) -> i64 {
+    ((content_length + UPLOAD_PART_SIZE - 1) / UPLOAD_PART_SIZE).max(1)
+}
+
+/// The offset and length of part `part_number` of a BLOB of `content_length` bytes, if it has
+/// such a part. Parts are numbered from 1, as in S3.
+pub fn part_range(content_length: i64, part_number: i32) -> Option<(i64, i64)> {
+    let part_number = part_number as i64;
+    if part_number < 1 || part_number > part_count(content_length) {
+        return None;
+    }
+    let offset = (part_number - 1) * UPLOAD_PART_SIZE;
+    Some((offset, UPLOAD_PART_SIZE.min(content_length - offset)))
+}
+
+/// How far an upload has got. A client which loses its connection gets this to find out which
+/// part to send next.
+#[derive(Serialize, Debug)]
+pub struct BlobUpload {
+    pub id: Uuid,
+    pub content_hash: String,
+    pub content_length: i64,
+    pub part_size: i64,
+    pub part_count: i64,
+    /// Parts are received in order, so these are parts `1..=parts_received`.
+    pub parts_received: i32,
+    pub expire_dt: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug)]
+pub enum BlobUploadError {
+    Unauthorized,
+    /// There's no unexpired upload with the id.
+    NotFound,
+    /// The BLOB didn't match its claimed hash. The upload is abandoned.
+    InvalidHash,
+    /// The BLOB's length is negative, or it has more parts than S3 allows.
+    InvalidLength,
+    /// There's no part with the number, or the part isn't the length it should be.
+    InvalidPart,
+    /// A part was sent before the parts preceding it. The upload continues from `next_part`.
+    OutOfOrder {
+        next_part: i32,
+    },
+    /// The upload can't be completed until all of its parts are received.
+    Incomplete {
+        next_part: i32,
+    },
+    Store(StoreError),
+    Sqlx(sqlx::Error),
+}
+
+impl From<HexError> for BlobUploadError {
+    fn from(_: HexError) -> Self {
+        Self::InvalidHash
+    }
+}
+
+impl From<StoreError> for BlobUploadError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<sqlx::Error> for BlobUploadError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_into_parts() {
+        assert_eq!(part_count(0), 1);
+        assert_eq!(part_range(0, 1), Some((0, 0)));
+
+        assert_eq!(part_count(UPLOAD_PART_SIZE), 1);
+        assert_eq!(part_count(UPLOAD_PART_SIZE + 1), 2);
+        assert_eq!(
+            part_range(UPLOAD_PART_SIZE + 1, 2),
+            Some((UPLOAD_PART_SIZE, 1))
+        );
+
+        let len = 3 * UPLOAD_PART_SIZE - 5;
+        assert_eq!(part_count(len), 3);
+        assert_eq!(part_range(len, 1), Some((0, UPLOAD_PART_SIZE)));
+        assert_eq!(
+            part_range(len, 3),
+            Some((2 * UPLOAD_PART_SIZE, UPLOAD_PART_SIZE - 5))
+        );
+        assert_eq!(part_range(len, 0), None);
+        assert_eq!(part_range(len, 4), None);
+    }
+}
@@ -0,0 +1,71 @@
+use crate::policy::PolicyError;
+use sqlx::types::chrono;
+
+/// The maximum number of function keys whose policies can be requested at once.
+pub const MAX_POLICY_FN_KEYS: usize = 500;
+
+/// How clients cache a function's evals.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheMode {
+    /// Evals are looked up and stored on the server, as functions without a policy are.
+    Cache,
+    /// Evals are always computed, and never stored.
+    Never,
+    /// Evals are only cached by the client computing them, never stored on the server.
+    LocalOnly,
+}
+
+impl CacheMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheMode::Cache => "cache",
+            CacheMode::Never => "never",
+            CacheMode::LocalOnly => "local_only",
+        }
+    }
+}
+
+/// The caching policy of a function, which clients follow when they evaluate it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CachePolicy {
+    pub fn_key: String,
+    /// One of `cache`, `never` or `local_only`. See [`CacheMode`].
+    pub mode: String,
+    /// How long, in seconds, an eval is used for before it's computed again, if it expires.
+    pub ttl_secs: Option<i64>,
+    /// The largest result, in bytes, which is cached, if there's a limit.
+    pub max_result_bytes: Option<i64>,
+    pub update_dt: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug)]
+pub enum CachePolicyError {
+    Unauthorized,
+    /// The authorization policy doesn't allow the request.
+    Forbidden,
+    NotFound,
+    /// The function keys aren't a JSON array of strings, or there are too many of them.
+    InvalidQuery,
+    /// The TTL isn't positive, or the maximum result size is negative.
+    InvalidPolicy,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for CachePolicyError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+impl From<PolicyError> for CachePolicyError {
+    fn from(e: PolicyError) -> Self {
+        match e {
+            PolicyError::Sqlx(e) => Self::Sqlx(e),
+            _ => Self::Forbidden,
+        }
+    }
+}
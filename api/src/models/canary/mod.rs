@@ -0,0 +1,79 @@
+use crate::persisters::s3store::StoreError;
+use sqlx::types::{chrono, Uuid};
+
+/// How many random bytes a canary's BLOB is made of.
+pub const CANARY_LEN: usize = 4096;
+
+/// A BLOB generated for an org, whose download by anything the org doesn't expect trips it.
+#[derive(Serialize, Debug)]
+pub struct Canary {
+    pub id: i64,
+    pub label: String,
+    /// The hash of the canary's BLOB, to plant wherever a leak would be found.
+    pub content_hash: String,
+    /// The ids of the API keys expected to download the BLOB.
+    pub allowed_keys: Vec<String>,
+    /// The networks downloads are expected from.
+    pub allowed_cidrs: Vec<String>,
+    pub webhook_url: Option<String>,
+    pub trips: i64,
+    pub last_trip_dt: Option<chrono::DateTime<chrono::Utc>>,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+}
+
+/// An unexpected download of a canary's BLOB.
+#[derive(Serialize, Debug)]
+pub struct CanaryTrip {
+    pub id: i64,
+    pub canary_id: i64,
+    pub user_id: Uuid,
+    /// The label of the API key the BLOB was downloaded with, if it was downloaded with one.
+    pub api_key_label: Option<String>,
+    pub ip: Option<String>,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+}
+
+/// The event sent to a canary's webhook when it's tripped.
+#[derive(Serialize, Debug)]
+pub struct CanaryTripped {
+    /// Always `canary_tripped`.
+    pub event: &'static str,
+    /// The org's external id.
+    pub org: String,
+    pub canary_id: i64,
+    pub label: String,
+    pub content_hash: String,
+    #[serde(flatten)]
+    pub trip: CanaryTrip,
+}
+
+#[derive(Debug)]
+pub enum CanaryError {
+    Unauthorized,
+    /// The org or canary doesn't exist, or isn't the authenticated user's.
+    NotFound,
+    /// The canary was rejected, e.g. because of an empty label or an invalid network.
+    InvalidCanary,
+    Store(StoreError),
+    Sqlx(sqlx::Error),
+}
+
+impl From<StoreError> for CanaryError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<sqlx::Error> for CanaryError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            sqlx::Error::Database(ref err) => match err.code().as_deref() {
+                // check_violation, invalid_text_representation
+                Some("23514") | Some("22P02") => Self::InvalidCanary,
+                _ => Self::Sqlx(e),
+            },
+            _ => Self::Sqlx(e),
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use super::SqlDateTime;
+
+/// Whether an API key's failing requests are being captured, and until when.
+#[derive(Serialize, Debug)]
+pub struct KeyCapture {
+    pub label: String,
+    pub capture_until: Option<SqlDateTime>,
+}
+
+/// A captured request. Its body is fetched separately.
+#[derive(Serialize, Debug)]
+pub struct Capture {
+    pub id: i64,
+    pub user_id: sqlx::types::Uuid,
+    pub key_label: String,
+    pub method: String,
+    pub path: String,
+    pub content_type: Option<String>,
+    pub user_agent: Option<String>,
+    pub status: i16,
+    /// The request's content length, if the client sent one.
+    pub body_len: Option<i64>,
+    /// How many bytes of the body were captured.
+    pub captured_len: i64,
+    pub create_dt: SqlDateTime,
+    pub expire_dt: SqlDateTime,
+}
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Unauthorized,
+    /// Only admins can read captures.
+    Forbidden,
+    /// The user has no keys with the given label, or there's no such capture.
+    NotFound,
+    /// The server has no capture key configured.
+    Disabled,
+    /// Keys can be opted into capture for between an hour and `MAX_CAPTURE_HOURS`.
+    InvalidDuration,
+    /// The bytes couldn't be sealed, or couldn't be opened with the server's key, e.g. because it
+    /// was changed since they were captured.
+    Seal,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for CaptureError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use super::SqlDateTime;
+use crate::policy::PolicyError;
+
+/// The most changes returned at once.
+pub const MAX_CHANGES: i64 = 1000;
+
+/// The `classid` half of the advisory lock taken while a user's changes are recorded. The `objid`
+/// half is a hash of the user's id. Holding it until the recording transaction commits means a
+/// user's changes become visible in the order of their ids, so a client reading from a cursor
+/// never skips a change which commits late.
+pub const CHANGES_LOCK: i32 = 0x6368_6e67;
+
+/// What happened to an eval key.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    /// The key got its first result.
+    Inserted,
+    /// The key got a different result from the one it had, so cached results are stale.
+    Invalidated,
+    /// The key's evals were deleted.
+    Deleted,
+}
+
+impl ChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Inserted => "inserted",
+            ChangeKind::Invalidated => "invalidated",
+            ChangeKind::Deleted => "deleted",
+        }
+    }
+}
+
+/// A change to one of the user's eval keys. Its id is the cursor to read the following changes
+/// from.
+#[derive(Serialize, Debug)]
+pub struct EvalChange {
+    pub id: i64,
+    /// One of `inserted`, `invalidated` or `deleted`.
+    pub kind: String,
+    pub fn_key: String,
+    pub fn_hash: String,
+    pub args_hash: String,
+    pub create_dt: SqlDateTime,
+}
+
+/// The cursor of the latest change, to read changes from after listing evals in full.
+#[derive(Serialize, Debug)]
+pub struct ChangesHead {
+    pub cursor: i64,
+}
+
+#[derive(Debug)]
+pub enum ChangeError {
+    Unauthorized,
+    /// The authorization policy doesn't allow the request.
+    Forbidden,
+    /// Changes since the cursor have been pruned, so the client has to list its evals again.
+    CursorExpired,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for ChangeError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+impl From<PolicyError> for ChangeError {
+    fn from(e: PolicyError) -> Self {
+        match e {
+            PolicyError::Sqlx(e) => Self::Sqlx(e),
+            _ => Self::Forbidden,
+        }
+    }
+}
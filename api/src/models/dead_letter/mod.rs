@@ -0,0 +1,102 @@
+use crate::persisters::s3store::StoreError;
+use sqlx::types::{chrono, JsonValue, Uuid};
+
+/// What a persist which failed partway was doing, so that it can be re-driven. A BLOB's object and
+/// its row are written separately, so either can be left without the other.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DeadLetterOp {
+    /// A BLOB's object was stored, but its row wasn't recorded.
+    Blob,
+    /// A DVC object's BLOB was stored, but neither the BLOB's row nor the object's MD5 were
+    /// recorded.
+    DvcObject { md5: String },
+    /// An MLflow artifact's BLOB was stored, but neither the BLOB's row nor the artifact were
+    /// recorded.
+    RunArtifact { run_id: Uuid, path: String },
+    /// A BLOB's row was recorded, but its object wasn't stored within the grace period.
+    MissingBlob,
+}
+
+impl DeadLetterOp {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeadLetterOp::Blob => "blob",
+            DeadLetterOp::DvcObject { .. } => "dvc_object",
+            DeadLetterOp::RunArtifact { .. } => "run_artifact",
+            DeadLetterOp::MissingBlob => "missing_blob",
+        }
+    }
+}
+
+/// A persist which failed partway, for an admin to re-drive or dismiss.
+#[derive(Serialize, Debug)]
+pub struct DeadLetter {
+    pub id: i64,
+    pub user_id: Uuid,
+    pub kind: String,
+    pub content_hash: String,
+    pub storage_region: Option<String>,
+    pub storage_bucket: Option<String>,
+    pub storage_prefix: Option<String>,
+    /// The [`DeadLetterOp`], with everything needed to re-drive it.
+    pub detail: JsonValue,
+    /// Why the persist failed, or why it was last re-driven unsuccessfully.
+    pub error: String,
+    /// `open`, `redriven` or `dismissed`.
+    pub status: String,
+    /// How many times it has been re-driven.
+    pub attempts: i32,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+    pub resolve_dt: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug)]
+pub enum DeadLetterError {
+    Unauthorized,
+    /// Only admins can see and re-drive dead letters.
+    Forbidden,
+    NotFound,
+    /// The dead letter has already been re-driven or dismissed.
+    Resolved,
+    /// The BLOB's object isn't stored, so there's nothing to re-drive yet.
+    NotStored,
+    /// The run an artifact was logged to has been deleted since, so the artifact can't be
+    /// recorded.
+    RunDeleted,
+    Store(StoreError),
+    Sqlx(sqlx::Error),
+}
+
+impl From<StoreError> for DeadLetterError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<sqlx::Error> for DeadLetterError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detail_is_tagged_with_kind() {
+        let op = DeadLetterOp::DvcObject {
+            md5: "d41d8cd98f00b204e9800998ecf8427e".to_string(),
+        };
+        let detail = serde_json::to_value(&op).unwrap();
+        assert_eq!(detail["kind"], op.as_str());
+        assert_eq!(serde_json::from_value::<DeadLetterOp>(detail).unwrap(), op);
+
+        let detail = serde_json::to_value(&DeadLetterOp::MissingBlob).unwrap();
+        assert_eq!(detail, serde_json::json!({ "kind": "missing_blob" }));
+    }
+}
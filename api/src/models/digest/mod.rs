@@ -0,0 +1,87 @@
+use ::chrono::{Datelike, Duration, TimeZone};
+use sqlx::types::{chrono, Uuid};
+
+/// The most functions listed in a digest.
+pub const DIGEST_TOP_FUNCTIONS: i64 = 5;
+
+/// The most runs listed in a digest.
+pub const DIGEST_NOTABLE_RUNS: i64 = 5;
+
+/// A function which the cache saved the most time computing, over a digest's week.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct DigestFn {
+    pub fn_key: String,
+    pub hits: i64,
+    /// In nanoseconds of process time.
+    pub saved_time: i64,
+}
+
+/// A run worth pointing out in a digest: one which failed, or one of the longest to succeed.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct DigestRun {
+    pub id: Uuid,
+    pub name: Option<String>,
+    pub fn_key: String,
+    pub state: String,
+    pub duration_secs: i64,
+}
+
+/// What happened over a week, for a user or the members of an org.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Digest {
+    /// The user's login, or the org's name.
+    pub name: String,
+    pub period_start: chrono::DateTime<chrono::Utc>,
+    pub hits: i64,
+    pub misses: i64,
+    /// In nanoseconds of process time.
+    pub saved_time: i64,
+    pub top_functions: Vec<DigestFn>,
+    pub blobs_added: i64,
+    pub bytes_added: i64,
+    pub notable_runs: Vec<DigestRun>,
+}
+
+impl Digest {
+    pub fn period_end(&self) -> chrono::DateTime<chrono::Utc> {
+        self.period_start + Duration::weeks(1)
+    }
+
+    /// Whether nothing happened over the week, in which case the digest isn't sent.
+    pub fn is_empty(&self) -> bool {
+        self.hits == 0 && self.misses == 0 && self.blobs_added == 0 && self.notable_runs.is_empty()
+    }
+}
+
+/// The start of the last full week before `now`, at midnight UTC on its Monday. Digests are of
+/// that week.
+pub fn last_week_start(now: chrono::DateTime<chrono::Utc>) -> chrono::DateTime<chrono::Utc> {
+    let today = now.date_naive();
+    let this_week = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+    let start = (this_week - Duration::weeks(1))
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+    chrono::Utc.from_utc_datetime(&start)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::types::chrono::{TimeZone, Utc};
+
+    #[test]
+    fn digests_are_of_the_last_full_week() {
+        // A Wednesday.
+        let now = Utc.with_ymd_and_hms(2023, 1, 25, 15, 30, 0).unwrap();
+        assert_eq!(
+            last_week_start(now),
+            Utc.with_ymd_and_hms(2023, 1, 16, 0, 0, 0).unwrap()
+        );
+        // Just after midnight on a Monday, the week just finished is the last full week.
+        let now = Utc.with_ymd_and_hms(2023, 1, 23, 0, 0, 1).unwrap();
+        assert_eq!(
+            last_week_start(now),
+            Utc.with_ymd_and_hms(2023, 1, 16, 0, 0, 0).unwrap()
+        );
+    }
+}
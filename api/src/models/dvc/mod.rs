@@ -0,0 +1,59 @@
+use crate::persisters::s3store::StoreError;
+
+/// Reassembles and validates the MD5 of a DVC cache object from its path, which splits the hash
+/// after the first two hex digits (e.g. `ab/cdef...`). Directory objects carry a `.dir` suffix.
+pub fn object_md5(prefix: &str, rest: &str) -> Option<String> {
+    let digits = rest.strip_suffix(".dir").unwrap_or(rest);
+    let is_hex = |s: &str| s.chars().all(|c| c.is_ascii_hexdigit());
+
+    if prefix.len() == 2 && digits.len() == 30 && is_hex(prefix) && is_hex(digits) {
+        Some(format!("{}{}", prefix, rest).to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub enum DvcError {
+    Unauthorized,
+    NotFound,
+    InvalidPath,
+    Store(StoreError),
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for DvcError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+impl From<StoreError> for DvcError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_object_paths() {
+        let rest = "0123456789abcdef0123456789abcd";
+        assert_eq!(object_md5("AB", rest), Some(format!("ab{}", rest)));
+        assert_eq!(
+            object_md5("ab", &format!("{}.dir", rest)),
+            Some(format!("ab{}.dir", rest))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_paths() {
+        assert_eq!(object_md5("ab", "0123"), None);
+        assert_eq!(object_md5("zz", "0123456789abcdef0123456789abcd"), None);
+    }
+}
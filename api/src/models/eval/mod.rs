@@ -1,10 +1,12 @@
+use crate::embed::EmbedError;
+use crate::models::run::Run;
 use serde::{Deserialize, Serialize};
-use sqlx::types::{chrono, JsonValue};
+use sqlx::types::{chrono, JsonValue, Uuid};
 
 // https://docs.rs/sqlx/0.5.7/sqlx/trait.FromRow.html
 // Extend derive(FromRow): https://github.com/launchbadge/sqlx/issues/156
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Eval {
     pub fn_key: String,
     pub fn_hash: String,
@@ -16,11 +18,423 @@ pub struct Eval {
     pub start_time: chrono::DateTime<chrono::Utc>,
     pub elapsed_process_time: i64,
     pub accesses: i64,
+    /// Whether the result BLOB can be downloaded. See [`BlobStatus`].
+    ///
+    /// [`BlobStatus`]: crate::models::blob_backfill::BlobStatus
+    pub blob_status: String,
+    /// Where the result is in the BLOB, when the BLOB holds the results of several evals put
+    /// together. Otherwise the result is the whole BLOB.
+    pub blob_offset: Option<i64>,
+    pub blob_length: Option<i64>,
+}
+
+/// An eval found by similarity search, with the cosine distance between its arguments and the
+/// searched for arguments (0 is identical).
+#[derive(Serialize, Deserialize)]
+pub struct SimilarEval {
+    pub fn_key: String,
+    pub fn_hash: String,
+    pub args: Option<JsonValue>,
+    pub args_hash: String,
+    pub result_json: Option<JsonValue>,
+    pub content_hash: String,
+    pub is_experiment: bool,
+    pub start_time: chrono::DateTime<chrono::Utc>,
+    pub elapsed_process_time: i64,
+    pub accesses: i64,
+    pub blob_status: String,
+    pub distance: f64,
+}
+
+/// The maximum number of evals returned by one similarity search.
+pub const MAX_SIMILAR: i64 = 50;
+
+/// The maximum number of suggestions returned for one cache miss.
+pub const MAX_SUGGESTIONS: i64 = 20;
+
+/// How many of a function's most recent evals are considered for suggestions.
+pub const SUGGESTION_CANDIDATES: i64 = 500;
+
+/// A cached eval of the same function as a lookup which missed, and how it differs from the
+/// lookup.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Suggestion {
+    pub fn_hash: String,
+    pub args_hash: String,
+    /// Whether the eval was made by the same version of the function as the lookup.
+    pub same_fn_hash: bool,
+    /// The fraction of arguments which are the same in the eval and the lookup.
+    pub overlap: f64,
+    /// The names (or positions, for positional arguments) of the arguments which differ. The name
+    /// is empty when the arguments are neither an object nor an array, and differ.
+    pub differing_args: Vec<String>,
+    pub start_time: chrono::DateTime<chrono::Utc>,
+}
+
+/// Compares the arguments of a lookup with those of a cached eval, returning the fraction of
+/// arguments which are equal and the names of those which aren't. Objects are compared by key and
+/// arrays by position; any other value counts as a single argument.
+pub fn compare_args(lookup: &JsonValue, cached: &JsonValue) -> (f64, Vec<String>) {
+    let (total, differing) = match (lookup, cached) {
+        (JsonValue::Object(a), JsonValue::Object(b)) => {
+            let mut keys = a.keys().chain(b.keys()).collect::<Vec<_>>();
+            keys.sort();
+            keys.dedup();
+            let differing = keys
+                .iter()
+                .filter(|k| a.get(k.as_str()) != b.get(k.as_str()))
+                .map(|k| k.to_string())
+                .collect::<Vec<_>>();
+            (keys.len(), differing)
+        }
+        (JsonValue::Array(a), JsonValue::Array(b)) => {
+            let len = a.len().max(b.len());
+            let differing = (0..len)
+                .filter(|&i| a.get(i) != b.get(i))
+                .map(|i| i.to_string())
+                .collect::<Vec<_>>();
+            (len, differing)
+        }
+        (a, b) if a == b => (1, vec![]),
+        _ => (1, vec![String::new()]),
+    };
+
+    if total == 0 {
+        return (1.0, differing);
+    }
+    let overlap = (total - differing.len()) as f64 / total as f64;
+    (overlap, differing)
+}
+
+/// The cache status of a single function, as shown next to its definition in an editor.
+#[derive(Serialize, Deserialize)]
+pub struct FnStatus {
+    pub fn_key: String,
+    /// The number of cached evals for the function, across all of its versions.
+    pub evals: i64,
+    /// The start time of the most recently cached eval, if there is one.
+    pub last_eval_time: Option<chrono::DateTime<chrono::Utc>>,
+    /// The total process time saved by cache hits, i.e. the sum of each eval's elapsed process
+    /// time multiplied by the number of times it was accessed.
+    pub saved_process_time: i64,
+}
+
+/// The maximum number of function keys which can be requested in one status query.
+pub const MAX_STATUS_FN_KEYS: usize = 500;
+
+/// The maximum number of cache outcomes which can be reported in one batch.
+pub const MAX_REPORTS: usize = 1000;
+
+/// The outcome of a client looking up an eval.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// Found in the server's cache.
+    Hit,
+    /// Found in the client's local cache, without asking the server.
+    LocalHit,
+    /// Not found, so the client computed the result.
+    Miss,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Hit => "hit",
+            Outcome::LocalHit => "local_hit",
+            Outcome::Miss => "miss",
+        }
+    }
+}
+
+/// A cache outcome reported by a client.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CacheReport {
+    pub fn_key: String,
+    pub outcome: Outcome,
+    /// For a miss, the process time spent computing the result. For a hit, the process time it
+    /// originally took to compute, i.e. the time saved.
+    pub elapsed_process_time: i64,
+    /// When the lookup happened. Defaults to when the report is received.
+    pub time: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// How a function has used the cache, from the outcomes clients have reported.
+#[derive(Serialize, Deserialize)]
+pub struct FnUsage {
+    pub fn_key: String,
+    pub hits: i64,
+    pub local_hits: i64,
+    pub misses: i64,
+    /// The fraction of lookups which hit either cache, if there were any lookups.
+    pub hit_rate: Option<f64>,
+    pub compute_time: i64,
+    pub saved_time: i64,
+}
+
+/// The `classid` half of the advisory lock taken while an eval is put, so that concurrent retries
+/// of the same put don't both insert it. The `objid` half is a hash of the eval's identity.
+pub const EVAL_PUT_LOCK: i32 = 0x6576_616c;
+
+/// The result of putting an eval. If an eval with the same function, arguments and result already
+/// existed, nothing is written and its id is returned. `replayed` is then set if the result's BLOB
+/// is also stored, so that a client retrying a put knows it needn't upload the BLOB again.
+#[derive(Serialize, Deserialize)]
+pub struct EvalPut {
+    pub id: Uuid,
+    pub replayed: bool,
+    /// Where to upload the result's BLOB, if the eval was put with `deferred` and the BLOB isn't
+    /// stored yet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload: Option<PayloadUpload>,
+}
+
+/// Where, and by when, the result BLOB of an eval put without it is to be uploaded. It's uploaded
+/// as any other BLOB is, and until it is, lookups of the eval find it with `blob_status` pending.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PayloadUpload {
+    pub content_hash: String,
+    pub content_length: i64,
+    /// The path to `PUT` the BLOB to.
+    pub path: String,
+    /// The path to start an upload straight to S3 at, for BLOBs too large to send through the
+    /// server.
+    pub presign_path: String,
+    /// When the BLOB is reported missing, if it hasn't been uploaded by then. Putting the eval
+    /// again pushes this back.
+    pub due_dt: chrono::DateTime<chrono::Utc>,
+}
+
+/// An eval as it's sent to its project's validation webhook, before it's stored. An eval put along
+/// with others has its result at `blob_offset` and `blob_length` of their BLOB.
+#[derive(Serialize, Debug)]
+pub struct ValidatedEval<'a> {
+    pub fn_key: &'a str,
+    pub fn_hash: &'a str,
+    pub args: Option<&'a JsonValue>,
+    pub args_hash: &'a str,
+    pub result_json: &'a JsonValue,
+    pub content_hash: &'a str,
+    pub content_length: i64,
+    pub blob_offset: Option<i64>,
+    pub blob_length: Option<i64>,
+    pub is_experiment: bool,
+    pub start_time: chrono::DateTime<chrono::Utc>,
+    pub elapsed_process_time: i64,
+}
+
+/// The event sent to a project's validation webhook when evals are put into it, so that teams can
+/// enforce their own rules on what's cached. The webhook accepts the evals by answering with a 2xx
+/// status, and rejects them by answering with a 4xx status, with a [`ValidationRejection`] body
+/// saying why if it likes. Any other answer, or none in time, is a failure of the webhook.
+#[derive(Serialize, Debug)]
+pub struct EvalValidation<'a> {
+    /// Always `eval_validation`.
+    pub event: &'static str,
+    pub project_id: Uuid,
+    pub project: &'a str,
+    pub evals: Vec<ValidatedEval<'a>>,
+}
+
+/// Why a validation webhook rejected evals, which is passed on to the client.
+#[derive(Deserialize, Debug, Default)]
+pub struct ValidationRejection {
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+/// The `classid` half of the advisory lock taken while an uncached eval is claimed, so that
+/// concurrent resolutions of it register one run between them. The `objid` half is a hash of the
+/// eval's identity.
+pub const EVAL_CLAIM_LOCK: i32 = 0x636c_6d73;
+
+/// The result of resolving an eval in one round trip: the eval if it's cached, otherwise the run
+/// computing it, which is registered for the caller if there isn't one already.
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Resolution {
+    Hit {
+        eval: Eval,
+    },
+    /// Another run is already computing the eval. The client polls it rather than computing the
+    /// eval too.
+    Pending {
+        run: Run,
+        poll_after_ms: u64,
+        /// The stale eval, if one was asked for and there is one. See [`EvalError::Miss`].
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stale: Option<Box<Eval>>,
+    },
+    /// A run has been registered for the client to compute the eval with.
+    Claimed {
+        run_id: Uuid,
+        reason: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        stale: Option<Box<Eval>>,
+    },
+}
+
+/// The most evals which can be put together, with one BLOB holding all of their results.
+pub const MAX_MULTI_EVALS: usize = 100;
+
+/// Whether each of the byte ranges, given as offsets and lengths, is within a BLOB of
+/// `content_length` bytes.
+pub fn ranges_within(content_length: i64, ranges: &[(i64, i64)]) -> bool {
+    ranges.iter().all(|&(offset, length)| {
+        offset >= 0
+            && length >= 0
+            && offset
+                .checked_add(length)
+                .map_or(false, |end| end <= content_length)
+    })
+}
+
+/// How many evals of an import are inserted in each transaction.
+pub const IMPORT_CHUNK_ROWS: usize = 5000;
+
+/// A bulk import of evals, and how far it has got.
+#[derive(Serialize, Deserialize)]
+pub struct EvalImport {
+    pub id: Uuid,
+    pub project: Option<String>,
+    /// One of `pending`, `running`, `done` or `failed`.
+    pub status: String,
+    /// The number of evals uploaded so far.
+    pub rows_received: i64,
+    /// The number of evals inserted so far. Evals which already exist aren't inserted again.
+    pub rows_inserted: i64,
+    /// The number of chunks committed so far.
+    pub chunks: i32,
+    /// Why the import failed, if it did.
+    pub error: Option<String>,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+    pub update_dt: chrono::DateTime<chrono::Utc>,
+}
+
+/// Why a lookup of one eval, by function, version and arguments, found nothing, so that clients
+/// can tell whether to compute it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissReason {
+    /// The user has no evals of the function.
+    UnknownFunction,
+    /// The user has evals of the function, but not of this version of it.
+    UnknownVersion,
+    /// The user has evals of this version of the function, but not with these arguments.
+    NotComputed,
+    /// The eval exists, but the other filters of the lookup exclude it.
+    Filtered,
+}
+
+impl MissReason {
+    /// The reason, given which of the user's evals exist: any of the function, any of its version,
+    /// and any with these arguments.
+    pub fn of(function: bool, version: bool, args: bool) -> Self {
+        match (function, version, args) {
+            (false, _, _) => MissReason::UnknownFunction,
+            (true, false, _) => MissReason::UnknownVersion,
+            (true, true, false) => MissReason::NotComputed,
+            (true, true, true) => MissReason::Filtered,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MissReason::UnknownFunction => "unknown_function",
+            MissReason::UnknownVersion => "unknown_version",
+            MissReason::NotComputed => "not_computed",
+            MissReason::Filtered => "filtered",
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum EvalError {
     Unauthorized,
+    /// The authorization policy doesn't allow the request.
+    Forbidden,
+    /// The search parameters could not be interpreted as a JSON value or jsonpath predicate.
+    InvalidQuery,
+    /// A batch of cache reports was too large, or had a negative process time.
+    InvalidReport,
+    /// A line of an import couldn't be read as an eval, or was for a different project.
+    InvalidImport,
+    /// An eval of a multi-eval put has its result outside the BLOB, or is of the same function and
+    /// arguments as another, or there are too many of them.
+    InvalidMulti,
+    /// The import has already finished or failed, so no more evals can be added to it.
+    ImportClosed,
+    /// No embedding provider is configured, so similarity search isn't available.
+    SimilarityDisabled,
+    /// The embedding provider couldn't embed the searched for arguments.
+    Embedding(EmbedError),
+    /// Some of the evals are under a legal hold, so they can't be deleted.
+    Held,
+    /// The request's credentials are well formed, but don't resolve to a user.
+    UnresolvedPrincipal,
+    /// The project's validation webhook rejected the evals, saying why if it said.
+    Rejected(Option<String>),
+    /// The project's validation webhook failed, or didn't answer in time, and the project refuses
+    /// evals when it does.
+    ValidationFailed,
+    /// A lookup of one eval found nothing. If the lookup asked for it, `stale` is the latest eval
+    /// of another version of the function with the same arguments, which the client may use while
+    /// it computes the eval.
+    Miss {
+        reason: MissReason,
+        stale: Option<Box<Eval>>,
+    },
     NotFound(sqlx::Error),
     Sqlx(sqlx::Error),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn explains_misses() {
+        assert_eq!(
+            MissReason::of(false, false, false),
+            MissReason::UnknownFunction
+        );
+        assert_eq!(
+            MissReason::of(true, false, false),
+            MissReason::UnknownVersion
+        );
+        assert_eq!(MissReason::of(true, true, false), MissReason::NotComputed);
+        assert_eq!(MissReason::of(true, true, true), MissReason::Filtered);
+    }
+
+    #[test]
+    fn compares_args_by_key_and_position() {
+        let lookup = serde_json::json!({ "lr": 0.1, "epochs": 10, "seed": 1 });
+        let cached = serde_json::json!({ "lr": 0.1, "epochs": 20, "batch": 32 });
+        let (overlap, differing) = compare_args(&lookup, &cached);
+        assert_eq!(overlap, 0.25);
+        assert_eq!(differing, vec!["batch", "epochs", "seed"]);
+
+        let (overlap, differing) =
+            compare_args(&serde_json::json!([1, 2]), &serde_json::json!([1, 3, 4]));
+        assert!((overlap - 1.0 / 3.0).abs() < 1e-9);
+        assert_eq!(differing, vec!["1", "2"]);
+
+        assert_eq!(
+            compare_args(&serde_json::json!({}), &serde_json::json!({})),
+            (1.0, vec![])
+        );
+        assert_eq!(
+            compare_args(&serde_json::json!(1), &serde_json::json!(2)).0,
+            0.0
+        );
+    }
+
+    #[test]
+    fn checks_ranges() {
+        assert!(ranges_within(10, &[(0, 4), (4, 6), (10, 0)]));
+        assert!(ranges_within(10, &[(0, 4), (2, 4)]));
+        assert!(!ranges_within(10, &[(6, 5)]));
+        assert!(!ranges_within(10, &[(-1, 2)]));
+        assert!(!ranges_within(10, &[(0, -1)]));
+        assert!(!ranges_within(i64::MAX, &[(i64::MAX, 1)]));
+    }
+}
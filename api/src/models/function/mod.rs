@@ -0,0 +1,129 @@
+use crate::models::cache_policy::CachePolicy;
+use crate::policy::PolicyError;
+use sqlx::types::chrono;
+
+/// The maximum length, in bytes, of a function's source snippet. This leaves room for the rest
+/// of the upload within the default JSON payload limit.
+pub const MAX_SOURCE_LEN: usize = 16 * 1024;
+
+/// A version of a function, as uploaded by a client.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Function {
+    pub fn_key: String,
+    pub fn_hash: String,
+    pub source: String,
+    pub docstring: Option<String>,
+    pub signature: Option<String>,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+}
+
+/// The most function versions sent in one sync. A client which is sent this many syncs again, from
+/// the `next_since` it's given.
+pub const MAX_SYNC_FUNCTIONS: i64 = 5000;
+
+/// How far behind the time of a sync the next one starts, in seconds, so that changes committed
+/// just after the sync, but timestamped before it, aren't missed. Changes in this window are sent
+/// twice, which clients can ignore.
+pub const SYNC_LAG_SECS: i64 = 5;
+
+/// A version of a function, as synced to clients. Its source is left out, as a client only needs
+/// the source of the versions it shows.
+#[derive(Serialize, Debug)]
+pub struct FunctionVersion {
+    pub fn_key: String,
+    pub fn_hash: String,
+    pub docstring: Option<String>,
+    pub signature: Option<String>,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+    pub update_dt: chrono::DateTime<chrono::Utc>,
+}
+
+/// What has changed in the user's functions registry since a client last synced.
+#[derive(Serialize, Debug)]
+pub struct FunctionsChanged {
+    /// The versions registered or changed, oldest change first.
+    pub functions: Vec<FunctionVersion>,
+    /// The caching policies set.
+    pub policies: Vec<CachePolicy>,
+    /// The functions whose caching policies were removed.
+    pub removed_policies: Vec<String>,
+    /// Whether there were more versions than could be sent. The rest are sent by syncing again.
+    pub truncated: bool,
+    /// Where the next sync starts.
+    pub next_since: chrono::DateTime<chrono::Utc>,
+}
+
+/// Where the sync after one at `now` starts: at the last version sent, if not all of them could be,
+/// and otherwise a little before `now`. See [`SYNC_LAG_SECS`].
+pub fn next_since(
+    now: chrono::DateTime<chrono::Utc>,
+    functions: &[FunctionVersion],
+    truncated: bool,
+) -> chrono::DateTime<chrono::Utc> {
+    match functions.last() {
+        Some(last) if truncated => last.update_dt,
+        _ => now - ::chrono::Duration::seconds(SYNC_LAG_SECS),
+    }
+}
+
+#[derive(Debug)]
+pub enum FunctionError {
+    Unauthorized,
+    /// The authorization policy doesn't allow the request.
+    Forbidden,
+    NotFound,
+    /// The source snippet is too long.
+    TooLarge,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for FunctionError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+impl From<PolicyError> for FunctionError {
+    fn from(e: PolicyError) -> Self {
+        match e {
+            PolicyError::Sqlx(e) => Self::Sqlx(e),
+            _ => Self::Forbidden,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::types::chrono::{TimeZone, Utc};
+
+    fn version(update_dt: chrono::DateTime<Utc>) -> FunctionVersion {
+        FunctionVersion {
+            fn_key: "mod:train".to_string(),
+            fn_hash: "abc".to_string(),
+            docstring: None,
+            signature: None,
+            create_dt: update_dt,
+            update_dt,
+        }
+    }
+
+    #[test]
+    fn next_sync_starts_behind_the_last() {
+        let now = Utc.with_ymd_and_hms(2023, 1, 29, 12, 0, 0).unwrap();
+        let last = Utc.with_ymd_and_hms(2023, 1, 28, 9, 0, 0).unwrap();
+        let functions = vec![version(last - ::chrono::Duration::hours(1)), version(last)];
+        assert_eq!(next_since(now, &functions, true), last);
+        assert_eq!(
+            next_since(now, &functions, false),
+            Utc.with_ymd_and_hms(2023, 1, 29, 11, 59, 55).unwrap()
+        );
+        assert_eq!(
+            next_since(now, &[], true),
+            now - ::chrono::Duration::seconds(5)
+        );
+    }
+}
@@ -0,0 +1,69 @@
+use sqlx::types::{chrono, Uuid};
+
+/// A legal hold placed by an org on some of its owner's runs and evals, which can't be deleted or
+/// archived until the hold is released.
+#[derive(Serialize, Debug)]
+pub struct LegalHold {
+    pub id: i64,
+    pub reason: String,
+    pub runs: Vec<Uuid>,
+    pub evals: Vec<Uuid>,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+    pub release_dt: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// What was done to a legal hold.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HoldAction {
+    Placed,
+    Released,
+}
+
+impl HoldAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HoldAction::Placed => "placed",
+            HoldAction::Released => "released",
+        }
+    }
+}
+
+/// An entry in the audit log of an org's legal holds, with what the hold covered at the time.
+#[derive(Serialize, Debug)]
+pub struct HoldEvent {
+    pub id: i64,
+    pub hold_id: i64,
+    pub action: String,
+    pub user_id: Uuid,
+    /// The label of the API key the change was made with, if it was made with one.
+    pub api_key_label: Option<String>,
+    pub runs: Vec<Uuid>,
+    pub evals: Vec<Uuid>,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug)]
+pub enum HoldError {
+    Unauthorized,
+    /// The org or hold doesn't exist, or one of the runs or evals to hold isn't the org's.
+    NotFound,
+    /// A hold needs a reason and something to hold.
+    InvalidHold,
+    AlreadyReleased,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for HoldError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            sqlx::Error::Database(ref err) => match err.code().as_deref() {
+                // check_violation
+                Some("23514") => Self::InvalidHold,
+                _ => Self::Sqlx(e),
+            },
+            _ => Self::Sqlx(e),
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use sqlx::types::{chrono, Uuid};
+
+/// A long-running production submitted to run in the background, such as an export.
+#[derive(Serialize, Debug)]
+pub struct Job {
+    pub id: Uuid,
+    pub kind: String,
+    /// `pending`, `running`, `finished` or `failed`.
+    pub status: String,
+    /// How much of the job is done, out of `progress_total` once that's known.
+    pub progress_done: i64,
+    pub progress_total: Option<i64>,
+    /// What the job produced, once it's finished: a BLOB, downloaded from
+    /// `GET /blob/{content_hash}`.
+    pub content_hash: Option<String>,
+    pub error: Option<String>,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+    pub update_dt: chrono::DateTime<chrono::Utc>,
+    pub finish_dt: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug)]
+pub enum JobError {
+    Unauthorized,
+    NotFound,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for JobError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use crate::persisters::s3store::StoreError;
+use sqlx::types::chrono;
+
+/// A condensed view of an eval for the notebook sidebar. Arguments are omitted and the result is
+/// truncated, so that listing a session's evals stays cheap.
+#[derive(FromRow, Serialize, Deserialize, Debug)]
+pub struct CompactEval {
+    pub fn_key: String,
+    pub fn_hash: String,
+    pub args_hash: String,
+    pub content_hash: String,
+    pub start_time: chrono::DateTime<chrono::Utc>,
+    pub elapsed_process_time: i64,
+    pub pinned: bool,
+    /// The start of the JSON-encoded result, truncated to `RESULT_PREVIEW_LEN` characters.
+    pub result_preview: Option<String>,
+}
+
+/// How many characters of `result_json` are included in a `CompactEval`.
+pub const RESULT_PREVIEW_LEN: i32 = 200;
+
+/// The most bytes of a BLOB returned by an inline preview.
+pub const MAX_PREVIEW_BYTES: u64 = 64 * 1024;
+
+#[derive(Debug)]
+pub enum JupyterError {
+    Unauthorized,
+    NotFound,
+    InvalidHash,
+    Store(StoreError),
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for JupyterError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+impl From<StoreError> for JupyterError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
@@ -0,0 +1,45 @@
+use sqlx::types::chrono;
+
+/// A rule deriving the metric `name` from the value found at the jsonpath `path` within an
+/// eval's `result_json`.
+#[derive(FromRow, Serialize, Deserialize, Debug)]
+pub struct MetricRule {
+    pub name: String,
+    pub path: String,
+}
+
+/// A single metric value recorded against an eval or run.
+#[derive(FromRow, Serialize, Deserialize, Debug, Clone)]
+pub struct Metric {
+    pub name: String,
+    pub value: f64,
+    /// The training step the value was logged at. Always 0 for metrics derived from evals.
+    pub step: i64,
+    pub fn_key: String,
+    pub args_hash: String,
+    pub start_time: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug)]
+pub enum MetricError {
+    Unauthorized,
+    /// The project named in the request doesn't exist.
+    ProjectNotFound,
+    /// The rule's path is not a valid jsonpath expression.
+    InvalidPath,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for MetricError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::Database(ref err)
+                if err.code() == Some(std::borrow::Cow::Borrowed("42601")) =>
+            {
+                Self::InvalidPath
+            }
+            sqlx::Error::RowNotFound => Self::ProjectNotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! Wire types for the subset of the [MLflow REST API](https://mlflow.org/docs/latest/rest-api.html)
+//! served under `/mlflow`.
+//!
+//! MLflow experiments map onto projects, with the project's UUID as the experiment ID, and the
+//! default experiment `"0"` standing for evals without a project. MLflow runs map onto runs,
+//! metrics onto metrics recorded against the run, and artifacts onto BLOBs.
+use crate::models::run::RunState;
+use crate::persisters::s3store::StoreError;
+
+/// The ID MLflow uses for the default experiment.
+pub const DEFAULT_EXPERIMENT_ID: &str = "0";
+pub const DEFAULT_EXPERIMENT_NAME: &str = "Default";
+
+/// Maps a HitSave run state to the MLflow `RunStatus` name.
+pub fn status_from_state(state: RunState) -> &'static str {
+    match state {
+        RunState::Queued => "SCHEDULED",
+        RunState::Running => "RUNNING",
+        RunState::Succeeded => "FINISHED",
+        RunState::Failed => "FAILED",
+        RunState::Cancelled => "KILLED",
+    }
+}
+
+/// Maps an MLflow `RunStatus` name to a HitSave run state.
+pub fn state_from_status(status: &str) -> Option<RunState> {
+    match status {
+        "SCHEDULED" => Some(RunState::Queued),
+        "RUNNING" => Some(RunState::Running),
+        "FINISHED" => Some(RunState::Succeeded),
+        "FAILED" => Some(RunState::Failed),
+        "KILLED" => Some(RunState::Cancelled),
+        _ => None,
+    }
+}
+
+/// The artifact root handed to clients, which makes them upload artifacts through the
+/// `mlflow-artifacts` proxy endpoints.
+pub fn artifact_uri(experiment_id: &str, run_id: &str) -> String {
+    format!("mlflow-artifacts:/{}/{}/artifacts", experiment_id, run_id)
+}
+
+/// Splits a path below the artifact root handed out by `artifact_uri`, of the form
+/// `{experiment_id}/{run_id}/artifacts/{path}`, into the run ID and the artifact's path.
+pub fn split_artifact_path(path: &str) -> Option<(&str, &str)> {
+    let mut parts = path.splitn(4, '/');
+    let _experiment_id = parts.next()?;
+    let run_id = parts.next()?;
+    if parts.next()? != "artifacts" {
+        return None;
+    }
+    let artifact = parts.next().filter(|p| !p.is_empty())?;
+    Some((run_id, artifact))
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetricValue {
+    pub key: String,
+    pub value: f64,
+    /// Milliseconds since the UNIX epoch.
+    pub timestamp: i64,
+    #[serde(default)]
+    pub step: i64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Experiment {
+    pub experiment_id: String,
+    pub name: String,
+    pub artifact_location: String,
+    pub lifecycle_stage: &'static str,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RunInfo {
+    pub run_id: String,
+    /// Deprecated alias of `run_id`, still read by older clients.
+    pub run_uuid: String,
+    pub run_name: Option<String>,
+    pub experiment_id: String,
+    pub status: &'static str,
+    pub start_time: i64,
+    pub end_time: Option<i64>,
+    pub artifact_uri: String,
+    pub lifecycle_stage: &'static str,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct RunData {
+    pub metrics: Vec<MetricValue>,
+    pub params: Vec<KeyValue>,
+    pub tags: Vec<KeyValue>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct MlflowRun {
+    pub info: RunInfo,
+    pub data: RunData,
+}
+
+#[derive(Debug)]
+pub enum MlflowError {
+    Unauthorized,
+    /// The run, experiment or artifact doesn't exist (MLflow's `RESOURCE_DOES_NOT_EXIST`).
+    NotFound,
+    /// A request field was invalid (MLflow's `INVALID_PARAMETER_VALUE`).
+    InvalidParameter(String),
+    Store(StoreError),
+    Sqlx(sqlx::Error),
+}
+
+impl MlflowError {
+    /// The MLflow `error_code` reported to clients.
+    pub fn error_code(&self) -> &'static str {
+        match self {
+            MlflowError::Unauthorized => "UNAUTHENTICATED",
+            MlflowError::NotFound => "RESOURCE_DOES_NOT_EXIST",
+            MlflowError::InvalidParameter(_) => "INVALID_PARAMETER_VALUE",
+            MlflowError::Store(_) | MlflowError::Sqlx(_) => "INTERNAL_ERROR",
+        }
+    }
+}
+
+impl From<sqlx::Error> for MlflowError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+impl From<StoreError> for MlflowError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_every_state_to_a_status_and_back() {
+        for state in [
+            RunState::Queued,
+            RunState::Running,
+            RunState::Succeeded,
+            RunState::Failed,
+            RunState::Cancelled,
+        ] {
+            assert_eq!(state_from_status(status_from_state(state)), Some(state));
+        }
+    }
+
+    #[test]
+    fn splits_artifact_paths() {
+        assert_eq!(
+            split_artifact_path("0/abc/artifacts/model/weights.pt"),
+            Some(("abc", "model/weights.pt"))
+        );
+        assert_eq!(split_artifact_path("0/abc/artifacts/"), None);
+        assert_eq!(split_artifact_path("0/abc/other/file"), None);
+    }
+}
@@ -1,5 +1,38 @@
+pub mod alert;
+pub mod anomaly;
 pub mod api_key;
+pub mod bandwidth;
+pub mod blob_backfill;
+pub mod blob_stats;
+pub mod blob_upload;
+pub mod cache_policy;
+pub mod canary;
+pub mod capture;
+pub mod change;
+pub mod dead_letter;
+pub mod digest;
+pub mod dvc;
 pub mod eval;
+pub mod function;
+pub mod hold;
+pub mod job;
+pub mod jupyter;
+pub mod metric;
+pub mod mlflow;
+pub mod openlineage;
+pub mod presigned_upload;
+pub mod project;
+pub mod provision;
+pub mod run;
+pub mod s3gateway;
+pub mod scim;
+pub mod search;
+pub mod sso;
+pub mod status;
+pub mod storage_report;
+pub mod tensor;
+pub mod topology;
+pub mod usage;
 pub mod user;
 
 pub type SqlDateTime = chrono::DateTime<chrono::Utc>;
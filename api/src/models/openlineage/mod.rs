@@ -0,0 +1,162 @@
+//! A subset of the [OpenLineage](https://openlineage.io) `RunEvent` schema, used to export eval
+//! provenance to lineage tooling such as Marquez or DataHub.
+//!
+//! Each HitSave run maps to an OpenLineage run of a job named after the function's `fn_key`, and
+//! the BLOB holding the result is the run's output dataset. Evals recorded without a run are
+//! exported as runs which completed when the eval started.
+use crate::models::run::RunState;
+use sqlx::types::{chrono, Uuid};
+
+pub const PRODUCER: &str = "https://github.com/hitsave-io/xyz";
+pub const SCHEMA_URL: &str =
+    "https://openlineage.io/spec/1-0-5/OpenLineage.json#/definitions/RunEvent";
+
+/// The namespace of jobs belonging to evals without a project.
+const DEFAULT_NAMESPACE: &str = "hitsave";
+/// The namespace of output datasets, which are addressed by content hash.
+const BLOB_NAMESPACE: &str = "hitsave-blobs";
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum EventType {
+    Start,
+    Running,
+    Complete,
+    Abort,
+    Fail,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RunEvent {
+    pub event_type: EventType,
+    pub event_time: chrono::DateTime<chrono::Utc>,
+    pub run: LineageRun,
+    pub job: Job,
+    pub inputs: Vec<Dataset>,
+    pub outputs: Vec<Dataset>,
+    pub producer: &'static str,
+    #[serde(rename = "schemaURL")]
+    pub schema_url: &'static str,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LineageRun {
+    pub run_id: Uuid,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Job {
+    pub namespace: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Dataset {
+    pub namespace: String,
+    pub name: String,
+}
+
+/// A run, or an eval recorded without a run, as read from the database for export.
+#[derive(FromRow, Debug)]
+pub struct ProvenanceRow {
+    pub id: Uuid,
+    pub project: Option<String>,
+    pub fn_key: String,
+    pub state: String,
+    pub content_hash: Option<String>,
+    pub start_time: chrono::DateTime<chrono::Utc>,
+    pub end_time: chrono::DateTime<chrono::Utc>,
+}
+
+impl ProvenanceRow {
+    /// Translates the row into OpenLineage events: a `START` event, followed by an event for the
+    /// run's current state if it has moved on from being queued.
+    pub fn into_events(self) -> Vec<RunEvent> {
+        let state = self.state.parse::<RunState>().unwrap_or(RunState::Queued);
+        let last = match state {
+            RunState::Queued => None,
+            RunState::Running => Some(EventType::Running),
+            RunState::Succeeded => Some(EventType::Complete),
+            RunState::Failed => Some(EventType::Fail),
+            RunState::Cancelled => Some(EventType::Abort),
+        };
+
+        let event = |event_type, event_time| RunEvent {
+            event_type,
+            event_time,
+            run: LineageRun { run_id: self.id },
+            job: Job {
+                namespace: self
+                    .project
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_NAMESPACE.to_string()),
+                name: self.fn_key.clone(),
+            },
+            // HitSave doesn't record which datasets an eval read, so there are no inputs.
+            inputs: vec![],
+            outputs: self
+                .content_hash
+                .iter()
+                .map(|h| Dataset {
+                    namespace: BLOB_NAMESPACE.to_string(),
+                    name: h.clone(),
+                })
+                .collect(),
+            producer: PRODUCER,
+            schema_url: SCHEMA_URL,
+        };
+
+        let mut events = vec![event(EventType::Start, self.start_time)];
+        if let Some(t) = last {
+            events.push(event(t, self.end_time));
+        }
+        events
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Unauthorized,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for ExportError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(state: &str) -> ProvenanceRow {
+        ProvenanceRow {
+            id: Uuid::new_v4(),
+            project: None,
+            fn_key: "train:fit".to_string(),
+            state: state.to_string(),
+            content_hash: Some("ab".repeat(32)),
+            start_time: chrono::Utc::now(),
+            end_time: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn succeeded_run_starts_and_completes() {
+        let events = row("succeeded").into_events();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event_type, EventType::Start);
+        assert_eq!(events[1].event_type, EventType::Complete);
+        assert_eq!(events[1].job.namespace, DEFAULT_NAMESPACE);
+        assert_eq!(events[1].outputs.len(), 1);
+    }
+
+    #[test]
+    fn queued_run_only_starts() {
+        let events = row("queued").into_events();
+        assert_eq!(events.len(), 1);
+    }
+}
@@ -0,0 +1,67 @@
+use crate::persisters::blob_store::PresignedPut;
+use crate::persisters::s3store::StoreError;
+use blake3::HexError;
+use sqlx::types::{chrono, Uuid};
+
+/// The longest BLOB which can be uploaded straight to S3, which is the most a single PUT can be.
+pub const MAX_PRESIGNED_LENGTH: i64 = 5 * 1024 * 1024 * 1024;
+
+/// How long, in seconds, a presigned upload URL is valid for.
+pub const PRESIGNED_URL_TTL_SECS: u64 = 60 * 60;
+
+/// How long, in hours, an upload can go unconfirmed before it's abandoned. This is longer than its
+/// URL is valid for, so that an upload which finishes just in time can still be confirmed.
+pub const PRESIGNED_UPLOAD_TTL_HOURS: i32 = 24;
+
+/// An upload straight to S3. The client PUTs the BLOB to `put.url`, with `put.headers`, then
+/// confirms the upload. When `put` is `None`, the BLOB is already stored where it would have been
+/// uploaded to, and the upload only needs confirming.
+#[derive(Serialize, Debug)]
+pub struct PresignedUpload {
+    pub id: Uuid,
+    pub content_hash: String,
+    pub content_length: i64,
+    pub put: Option<PresignedPut>,
+    pub expire_dt: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug)]
+pub enum PresignedUploadError {
+    Unauthorized,
+    /// There's no unexpired upload with the id.
+    NotFound,
+    /// The hash isn't a valid hash, or what was uploaded didn't match it. An upload which didn't
+    /// match is abandoned.
+    InvalidHash,
+    /// The BLOB's length is negative, or longer than a single PUT can be.
+    InvalidLength,
+    /// Storing the BLOB would take the user over their storage quota.
+    QuotaExceeded,
+    /// BLOBs are stored where they can't be uploaded to without going through the server.
+    Unsupported,
+    /// The upload was confirmed before anything was uploaded.
+    NotUploaded,
+    Store(StoreError),
+    Sqlx(sqlx::Error),
+}
+
+impl From<HexError> for PresignedUploadError {
+    fn from(_: HexError) -> Self {
+        Self::InvalidHash
+    }
+}
+
+impl From<StoreError> for PresignedUploadError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<sqlx::Error> for PresignedUploadError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
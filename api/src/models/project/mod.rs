@@ -0,0 +1,97 @@
+use sqlx::types::{chrono, Uuid};
+
+/// A named group of evals belonging to a user, along with its settings.
+#[derive(FromRow, Serialize, Deserialize, Debug)]
+pub struct Project {
+    pub id: Uuid,
+    pub name: String,
+    /// Whether `result_json` of the project's evals is indexed for content search.
+    pub index_results: bool,
+    /// Number of days after which the artifacts of finished runs are moved to cold storage. Runs
+    /// are never archived when this is `None`.
+    pub archive_after_days: Option<i32>,
+    /// Webhook which is sent a [`MissStorm`] event when the miss rate of the project's cache
+    /// lookups spikes. No events are sent when this is `None`.
+    pub miss_storm_webhook_url: Option<String>,
+    /// The fraction of lookups which have to miss, within the window, for a storm.
+    pub miss_storm_threshold: f64,
+    /// How many hours, including the current one, lookups are counted over.
+    pub miss_storm_window_hours: i32,
+    /// When a storm was last reported.
+    pub miss_storm_fired_dt: Option<chrono::DateTime<chrono::Utc>>,
+    /// Webhook which is sent each put of the project's evals, as an [`EvalValidation`], before
+    /// they're stored, and may reject them. Evals aren't validated when this is `None`.
+    ///
+    /// [`EvalValidation`]: crate::models::eval::EvalValidation
+    pub validation_webhook_url: Option<String>,
+    /// How long, in milliseconds, the validation webhook has to answer.
+    pub validation_timeout_ms: i32,
+    /// Whether evals are stored when the validation webhook doesn't answer in time, or fails,
+    /// rather than refused.
+    pub validation_fail_open: bool,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+}
+
+/// The fewest lookups within a window which can be a miss storm, so that a project which is
+/// barely used isn't reported for a handful of misses.
+pub const MISS_STORM_MIN_LOOKUPS: i64 = 50;
+
+/// Whether `misses` out of `lookups` within a window is a miss storm.
+pub fn is_miss_storm(misses: i64, lookups: i64, threshold: f64) -> bool {
+    lookups >= MISS_STORM_MIN_LOOKUPS && misses as f64 / lookups as f64 > threshold
+}
+
+/// The event sent to a project's webhook when the miss rate of its cache lookups spikes, which
+/// usually means a refactor changed the hashes of its functions and invalidated its cache.
+#[derive(Serialize, Debug)]
+pub struct MissStorm {
+    /// Always `miss_storm`.
+    pub event: &'static str,
+    pub project_id: Uuid,
+    pub project: String,
+    pub misses: i64,
+    pub lookups: i64,
+    pub miss_rate: f64,
+    pub threshold: f64,
+    pub window_hours: i32,
+    /// The functions which missed the most within the window, most first.
+    pub fn_keys: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum ProjectError {
+    Unauthorized,
+    NotFound,
+    /// No integrity key is configured, so integrity manifests can't be signed.
+    IntegrityDisabled,
+    /// The settings were rejected, e.g. because of a non-positive archive period.
+    InvalidSettings,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for ProjectError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            // check_violation
+            sqlx::Error::Database(ref err)
+                if err.code() == Some(std::borrow::Cow::Borrowed("23514")) =>
+            {
+                Self::InvalidSettings
+            }
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn storms_need_enough_lookups() {
+        assert!(!is_miss_storm(49, 49, 0.5));
+        assert!(is_miss_storm(26, 50, 0.5));
+        assert!(!is_miss_storm(25, 50, 0.5));
+        assert!(!is_miss_storm(0, 0, 0.5));
+    }
+}
@@ -0,0 +1,165 @@
+use sqlx::types::{chrono, Uuid};
+
+/// An organisation, grouping projects and service accounts under one owner.
+#[derive(Serialize, Debug)]
+pub struct Org {
+    /// The org's HitSave ID, which identifies it globally (e.g. when signing in with SSO).
+    pub id: Uuid,
+    pub external_id: String,
+    pub name: String,
+    /// The deployment region the org's BLOBs are pinned to, if any.
+    pub residency_region: Option<String>,
+    pub version: i32,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+    pub update_dt: chrono::DateTime<chrono::Utc>,
+}
+
+/// A project which belongs to an org.
+#[derive(Serialize, Debug)]
+pub struct OrgProject {
+    #[serde(skip)]
+    pub id: Uuid,
+    pub external_id: String,
+    pub name: String,
+    pub index_results: bool,
+    pub archive_after_days: Option<i32>,
+    pub version: i32,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+}
+
+/// A non-human principal of an org, which holds API keys.
+#[derive(Serialize, Debug)]
+pub struct ServiceAccount {
+    #[serde(skip)]
+    pub id: Uuid,
+    pub external_id: String,
+    pub name: String,
+    pub version: i32,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+    pub update_dt: chrono::DateTime<chrono::Utc>,
+}
+
+/// An API key held by a service account.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ServiceAccountKey {
+    pub external_id: String,
+    pub label: String,
+    pub version: i32,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+    /// The key itself. This is only ever returned in the response which creates the key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+/// The outcome of a PUT of a provisioned resource.
+#[derive(Debug)]
+pub struct Provisioned<T> {
+    pub resource: T,
+    /// Whether the resource was created, rather than updated or left as it was.
+    pub created: bool,
+}
+
+/// Formats the version of a resource as its (strong) ETag.
+pub fn etag(version: i32) -> String {
+    format!("\"{}\"", version)
+}
+
+/// The conditions of a request, taken from its `If-Match` and `If-None-Match` headers, which the
+/// current version of the resource must satisfy for the request to go ahead.
+#[derive(Debug, Default)]
+pub struct Precondition {
+    pub if_match: Option<String>,
+    pub if_none_match: Option<String>,
+}
+
+impl Precondition {
+    /// Checks the precondition against the current version of the resource, which is `None` when
+    /// the resource doesn't exist.
+    pub fn check(&self, version: Option<i32>) -> Result<(), ProvisionError> {
+        let current = version.map(etag);
+        let matches = |header: &str| {
+            header.split(',').map(str::trim).any(|tag| match tag {
+                "*" => current.is_some(),
+                tag => current.as_deref() == Some(tag),
+            })
+        };
+
+        if let Some(if_match) = &self.if_match {
+            if !matches(if_match) {
+                return Err(ProvisionError::PreconditionFailed);
+            }
+        }
+        if let Some(if_none_match) = &self.if_none_match {
+            if matches(if_none_match) {
+                return Err(ProvisionError::PreconditionFailed);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum ProvisionError {
+    Unauthorized,
+    NotFound,
+    /// The resource clashes with another one (e.g. a project name already in use), or can't be
+    /// deleted because other resources still refer to it.
+    Conflict,
+    /// The `If-Match` or `If-None-Match` precondition of the request doesn't hold.
+    PreconditionFailed,
+    /// The resource specification was rejected, e.g. because of a non-positive archive period.
+    InvalidSpec,
+    /// The org's data can't be pinned to the region it asks for, for the given reason.
+    InvalidResidency(&'static str),
+    /// A new key couldn't be hashed.
+    Hash,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for ProvisionError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            sqlx::Error::Database(ref err) => match err.code().as_deref() {
+                // foreign_key_violation, unique_violation
+                Some("23503") | Some("23505") => Self::Conflict,
+                // check_violation
+                Some("23514") => Self::InvalidSpec,
+                _ => Self::Sqlx(e),
+            },
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn precondition(if_match: Option<&str>, if_none_match: Option<&str>) -> Precondition {
+        Precondition {
+            if_match: if_match.map(String::from),
+            if_none_match: if_none_match.map(String::from),
+        }
+    }
+
+    #[test]
+    fn if_match() {
+        assert!(precondition(Some("\"2\""), None).check(Some(2)).is_ok());
+        assert!(precondition(Some("\"1\", \"2\""), None)
+            .check(Some(2))
+            .is_ok());
+        assert!(precondition(Some("\"1\""), None).check(Some(2)).is_err());
+        assert!(precondition(Some("*"), None).check(Some(2)).is_ok());
+        assert!(precondition(Some("*"), None).check(None).is_err());
+    }
+
+    #[test]
+    fn if_none_match() {
+        assert!(precondition(None, Some("*")).check(None).is_ok());
+        assert!(precondition(None, Some("*")).check(Some(1)).is_err());
+        assert!(precondition(None, Some("\"1\"")).check(Some(2)).is_ok());
+        assert!(precondition(None, None).check(None).is_ok());
+    }
+}
@@ -0,0 +1,154 @@
+use crate::persisters::s3store::StoreError;
+use crate::policy::PolicyError;
+use sqlx::types::{chrono, Uuid};
+
+/// The lifecycle state of an experiment run.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RunState {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl RunState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunState::Queued => "queued",
+            RunState::Running => "running",
+            RunState::Succeeded => "succeeded",
+            RunState::Failed => "failed",
+            RunState::Cancelled => "cancelled",
+        }
+    }
+
+    /// Terminal states can't be left once entered.
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            RunState::Succeeded | RunState::Failed | RunState::Cancelled
+        )
+    }
+
+    /// Whether a run may move from this state to `next`. A run can only succeed once it is
+    /// running, but can fail or be cancelled at any point before it finishes.
+    pub fn can_transition_to(&self, next: RunState) -> bool {
+        match (self, next) {
+            (RunState::Queued, RunState::Running) => true,
+            (RunState::Running, RunState::Succeeded) => true,
+            (s, RunState::Failed | RunState::Cancelled) => !s.is_terminal(),
+            _ => false,
+        }
+    }
+}
+
+impl std::str::FromStr for RunState {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "queued" => Ok(RunState::Queued),
+            "running" => Ok(RunState::Running),
+            "succeeded" => Ok(RunState::Succeeded),
+            "failed" => Ok(RunState::Failed),
+            "cancelled" => Ok(RunState::Cancelled),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(FromRow, Serialize, Deserialize, Debug)]
+pub struct Run {
+    pub id: Uuid,
+    pub project: Option<String>,
+    pub fn_key: String,
+    pub fn_hash: String,
+    pub args_hash: String,
+    pub state: String,
+    pub cancel_requested: bool,
+    pub eval_id: Option<Uuid>,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+    pub update_dt: chrono::DateTime<chrono::Utc>,
+    /// When the run's artifacts were moved to cold storage, if they have been.
+    pub archived_dt: Option<chrono::DateTime<chrono::Utc>>,
+    /// When unarchiving was requested, if it's still in progress.
+    pub unarchive_requested_dt: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug)]
+pub enum RunError {
+    Unauthorized,
+    /// The authorization policy doesn't allow the request.
+    Forbidden,
+    NotFound,
+    /// The eval a state change links to the run isn't one of the caller's.
+    EvalNotFound,
+    /// The requested state change isn't allowed from the run's current state.
+    InvalidTransition {
+        from: String,
+        to: RunState,
+    },
+    /// Only archived runs have outputs to restore.
+    NotArchived,
+    Store(StoreError),
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for RunError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+impl From<PolicyError> for RunError {
+    fn from(e: PolicyError) -> Self {
+        match e {
+            PolicyError::Sqlx(e) => Self::Sqlx(e),
+            _ => Self::Forbidden,
+        }
+    }
+}
+
+impl From<StoreError> for RunError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_forward_transitions() {
+        assert!(RunState::Queued.can_transition_to(RunState::Running));
+        assert!(RunState::Running.can_transition_to(RunState::Succeeded));
+        assert!(RunState::Queued.can_transition_to(RunState::Cancelled));
+        assert!(RunState::Running.can_transition_to(RunState::Failed));
+    }
+
+    #[test]
+    fn rejects_leaving_terminal_states() {
+        assert!(!RunState::Succeeded.can_transition_to(RunState::Failed));
+        assert!(!RunState::Cancelled.can_transition_to(RunState::Running));
+        assert!(!RunState::Queued.can_transition_to(RunState::Succeeded));
+    }
+
+    #[test]
+    fn round_trips_names() {
+        for s in [
+            RunState::Queued,
+            RunState::Running,
+            RunState::Succeeded,
+            RunState::Failed,
+            RunState::Cancelled,
+        ] {
+            assert_eq!(s.as_str().parse::<RunState>(), Ok(s));
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use crate::persisters::s3store::StoreError;
+
+/// API keys double as S3 credentials: the secret access key is the API key itself, and the access
+/// key ID is its first `ACCESS_KEY_ID_LEN` characters.
+pub const ACCESS_KEY_ID_LEN: usize = 20;
+
+/// How far, in minutes, the signing time may be from the server's clock.
+pub const MAX_CLOCK_SKEW_MINS: i64 = 15;
+
+/// Errors reported by the S3-compatible gateway. These are rendered as S3 XML error documents,
+/// so that S3 clients can interpret them.
+#[derive(Debug)]
+pub enum S3GatewayError {
+    /// The request wasn't signed, or the access key is unknown.
+    AccessDenied,
+    /// The signature didn't match, or the request was signed too long ago.
+    SignatureDoesNotMatch,
+    NoSuchKey,
+    InvalidObjectState,
+    Store(StoreError),
+    Sqlx(sqlx::Error),
+}
+
+impl S3GatewayError {
+    /// The S3 error code, e.g. `NoSuchKey`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            S3GatewayError::AccessDenied => "AccessDenied",
+            S3GatewayError::SignatureDoesNotMatch => "SignatureDoesNotMatch",
+            S3GatewayError::NoSuchKey => "NoSuchKey",
+            S3GatewayError::InvalidObjectState => "InvalidObjectState",
+            S3GatewayError::Store(_) | S3GatewayError::Sqlx(_) => "InternalError",
+        }
+    }
+}
+
+impl From<sqlx::Error> for S3GatewayError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+impl From<StoreError> for S3GatewayError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
@@ -0,0 +1,524 @@
+//! Wire types for the subset of [SCIM 2.0](https://www.rfc-editor.org/rfc/rfc7644) served under
+//! `/scim/v2`, with which an org's identity provider provisions its members.
+//!
+//! SCIM users map onto org memberships (with the user's email address as their `userName`), and
+//! SCIM groups onto the org's groups. Requests are authenticated with a key of one of the org's
+//! service accounts.
+use sqlx::types::{chrono, JsonValue, Uuid};
+
+pub const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+pub const GROUP_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+pub const LIST_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+pub const ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+
+/// The maximum number of resources returned by one list request.
+pub const MAX_RESULTS: i64 = 200;
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Name {
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+}
+
+fn default_active() -> bool {
+    true
+}
+
+/// The attributes of a user which the identity provider manages.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct UserAttributes {
+    pub user_name: String,
+    pub external_id: Option<String>,
+    #[serde(default)]
+    pub name: Name,
+    #[serde(default = "default_active")]
+    pub active: bool,
+}
+
+/// The attributes of a group which the identity provider manages.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct GroupAttributes {
+    pub display_name: String,
+    pub external_id: Option<String>,
+    #[serde(default)]
+    pub members: Vec<MemberRef>,
+}
+
+/// A reference to a group member, by the member's user ID.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MemberRef {
+    pub value: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub display: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+    pub resource_type: &'static str,
+    pub created: chrono::DateTime<chrono::Utc>,
+    pub last_modified: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Email {
+    pub value: String,
+    pub primary: bool,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    pub schemas: [&'static str; 1],
+    pub id: Uuid,
+    pub user_name: String,
+    pub external_id: Option<String>,
+    pub name: Name,
+    pub emails: Vec<Email>,
+    pub active: bool,
+    pub meta: Meta,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Group {
+    pub schemas: [&'static str; 1],
+    pub id: Uuid,
+    pub display_name: String,
+    pub external_id: Option<String>,
+    pub members: Vec<MemberRef>,
+    pub meta: Meta,
+}
+
+/// An org membership, as stored.
+#[derive(Debug)]
+pub struct UserRow {
+    pub id: Uuid,
+    pub email: String,
+    pub external_id: Option<String>,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub active: bool,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+    pub update_dt: chrono::DateTime<chrono::Utc>,
+}
+
+impl UserRow {
+    pub fn attributes(&self) -> UserAttributes {
+        UserAttributes {
+            user_name: self.email.clone(),
+            external_id: self.external_id.clone(),
+            name: Name {
+                given_name: self.given_name.clone(),
+                family_name: self.family_name.clone(),
+            },
+            active: self.active,
+        }
+    }
+}
+
+impl From<UserRow> for User {
+    fn from(row: UserRow) -> Self {
+        Self {
+            schemas: [USER_SCHEMA],
+            id: row.id,
+            emails: vec![Email {
+                value: row.email.clone(),
+                primary: true,
+            }],
+            user_name: row.email,
+            external_id: row.external_id,
+            name: Name {
+                given_name: row.given_name,
+                family_name: row.family_name,
+            },
+            active: row.active,
+            meta: Meta {
+                resource_type: "User",
+                created: row.create_dt,
+                last_modified: row.update_dt,
+            },
+        }
+    }
+}
+
+/// An org group, as stored, along with the IDs and email addresses of its members.
+#[derive(Debug)]
+pub struct GroupRow {
+    pub id: Uuid,
+    pub display_name: String,
+    pub external_id: Option<String>,
+    pub member_ids: Vec<Uuid>,
+    pub member_emails: Vec<String>,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+    pub update_dt: chrono::DateTime<chrono::Utc>,
+}
+
+impl GroupRow {
+    fn members(&self) -> Vec<MemberRef> {
+        self.member_ids
+            .iter()
+            .zip(&self.member_emails)
+            .map(|(id, email)| MemberRef {
+                value: id.to_string(),
+                display: Some(email.clone()),
+            })
+            .collect()
+    }
+
+    pub fn attributes(&self) -> GroupAttributes {
+        GroupAttributes {
+            display_name: self.display_name.clone(),
+            external_id: self.external_id.clone(),
+            members: self.members(),
+        }
+    }
+}
+
+impl From<GroupRow> for Group {
+    fn from(row: GroupRow) -> Self {
+        Self {
+            schemas: [GROUP_SCHEMA],
+            id: row.id,
+            members: row.members(),
+            display_name: row.display_name,
+            external_id: row.external_id,
+            meta: Meta {
+                resource_type: "Group",
+                created: row.create_dt,
+                last_modified: row.update_dt,
+            },
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListResponse<T> {
+    pub schemas: [&'static str; 1],
+    pub total_results: i64,
+    pub start_index: i64,
+    pub items_per_page: i64,
+    #[serde(rename = "Resources")]
+    pub resources: Vec<T>,
+}
+
+impl<T> ListResponse<T> {
+    pub fn new(resources: Vec<T>, total_results: i64, start_index: i64) -> Self {
+        Self {
+            schemas: [LIST_SCHEMA],
+            total_results,
+            start_index,
+            items_per_page: resources.len() as i64,
+            resources,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PatchRequest {
+    #[serde(rename = "Operations")]
+    pub operations: Vec<PatchOperation>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PatchOperation {
+    pub op: String,
+    pub path: Option<String>,
+    pub value: Option<JsonValue>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Add,
+    Replace,
+    Remove,
+}
+
+impl PatchOperation {
+    fn op(&self) -> Result<Op, ScimError> {
+        // Some identity providers capitalise the operation.
+        match self.op.to_ascii_lowercase().as_str() {
+            "add" => Ok(Op::Add),
+            "replace" => Ok(Op::Replace),
+            "remove" => Ok(Op::Remove),
+            _ => Err(ScimError::InvalidValue),
+        }
+    }
+
+    /// The operation as a list of `(attribute, value)` pairs: the path and value when there is a
+    /// path, or else the members of the value, which must be an object.
+    fn assignments(&self) -> Result<Vec<(String, Option<&JsonValue>)>, ScimError> {
+        match (&self.path, &self.value) {
+            (Some(path), value) => Ok(vec![(path.clone(), value.as_ref())]),
+            (None, Some(JsonValue::Object(values))) => {
+                Ok(values.iter().map(|(k, v)| (k.clone(), Some(v))).collect())
+            }
+            (None, _) => Err(ScimError::InvalidValue),
+        }
+    }
+}
+
+fn string_value(value: Option<&JsonValue>) -> Result<Option<String>, ScimError> {
+    match value {
+        None | Some(JsonValue::Null) => Ok(None),
+        Some(JsonValue::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(ScimError::InvalidValue),
+    }
+}
+
+fn bool_value(value: Option<&JsonValue>) -> Result<bool, ScimError> {
+    // Azure AD sends booleans as the strings "True" and "False".
+    match value {
+        Some(JsonValue::Bool(b)) => Ok(*b),
+        Some(JsonValue::String(s)) if s.eq_ignore_ascii_case("true") => Ok(true),
+        Some(JsonValue::String(s)) if s.eq_ignore_ascii_case("false") => Ok(false),
+        _ => Err(ScimError::InvalidValue),
+    }
+}
+
+fn member_values(value: Option<&JsonValue>) -> Result<Vec<MemberRef>, ScimError> {
+    match value {
+        None | Some(JsonValue::Null) => Ok(vec![]),
+        Some(members @ JsonValue::Array(_)) => {
+            serde_json::from_value(members.clone()).map_err(|_| ScimError::InvalidValue)
+        }
+        Some(member) => Ok(vec![
+            serde_json::from_value(member.clone()).map_err(|_| ScimError::InvalidValue)?
+        ]),
+    }
+}
+
+impl UserAttributes {
+    /// Applies the operations of a PATCH request. Attributes which HitSave doesn't store (e.g. a
+    /// user's title) are ignored.
+    pub fn apply(&mut self, operations: &[PatchOperation]) -> Result<(), ScimError> {
+        for operation in operations {
+            let op = operation.op()?;
+            for (attribute, value) in operation.assignments()? {
+                let value = if op == Op::Remove { None } else { value };
+                match attribute.to_ascii_lowercase().as_str() {
+                    "username" => {
+                        self.user_name = string_value(value)?.ok_or(ScimError::Mutability)?
+                    }
+                    "externalid" => self.external_id = string_value(value)?,
+                    "name.givenname" => self.name.given_name = string_value(value)?,
+                    "name.familyname" => self.name.family_name = string_value(value)?,
+                    "name" => {
+                        self.name = match value {
+                            None | Some(JsonValue::Null) => Name::default(),
+                            Some(v) => serde_json::from_value(v.clone())
+                                .map_err(|_| ScimError::InvalidValue)?,
+                        }
+                    }
+                    "active" => self.active = bool_value(value)?,
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl GroupAttributes {
+    /// Applies the operations of a PATCH request.
+    pub fn apply(&mut self, operations: &[PatchOperation]) -> Result<(), ScimError> {
+        for operation in operations {
+            let op = operation.op()?;
+            for (attribute, value) in operation.assignments()? {
+                let lower = attribute.to_ascii_lowercase();
+                match (op, lower.as_str()) {
+                    (Op::Remove, "displayname") => return Err(ScimError::Mutability),
+                    (_, "displayname") => {
+                        self.display_name = string_value(value)?.ok_or(ScimError::InvalidValue)?
+                    }
+                    (Op::Remove, "externalid") => self.external_id = None,
+                    (_, "externalid") => self.external_id = string_value(value)?,
+                    (Op::Add, "members") => {
+                        for member in member_values(value)? {
+                            if !self.members.iter().any(|m| m.value == member.value) {
+                                self.members.push(member);
+                            }
+                        }
+                    }
+                    (Op::Replace, "members") => self.members = member_values(value)?,
+                    // Without a value, all members are removed.
+                    (Op::Remove, "members") if value.is_none() => self.members.clear(),
+                    (Op::Remove, "members") => {
+                        let removed = member_values(value)?;
+                        self.members
+                            .retain(|m| !removed.iter().any(|r| r.value == m.value));
+                    }
+                    // A path selecting a single member, e.g. `members[value eq "{id}"]`.
+                    (Op::Remove, path) if path.starts_with("members[") => {
+                        let filter = attribute["members[".len()..]
+                            .strip_suffix(']')
+                            .ok_or(ScimError::InvalidPath)?;
+                        let filter = Filter::parse(filter).map_err(|_| ScimError::InvalidPath)?;
+                        if !filter.attribute.eq_ignore_ascii_case("value") {
+                            return Err(ScimError::InvalidPath);
+                        }
+                        self.members.retain(|m| m.value != filter.value);
+                    }
+                    _ => return Err(ScimError::InvalidPath),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An equality filter, of the form `{attribute} eq "{value}"`, which is the only kind of filter
+/// identity providers use when provisioning.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Filter {
+    pub attribute: String,
+    pub value: String,
+}
+
+impl Filter {
+    pub fn parse(s: &str) -> Result<Self, ScimError> {
+        let (attribute, rest) = s
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or(ScimError::InvalidFilter)?;
+        let (op, value) = rest
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .ok_or(ScimError::InvalidFilter)?;
+        if !op.eq_ignore_ascii_case("eq") {
+            return Err(ScimError::InvalidFilter);
+        }
+        let value = value
+            .trim()
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .ok_or(ScimError::InvalidFilter)?;
+
+        Ok(Self {
+            attribute: attribute.to_string(),
+            value: value.to_string(),
+        })
+    }
+
+    /// Returns the value the filter requires of `attribute`, or `None` if it doesn't filter on
+    /// the attribute.
+    pub fn value_of(&self, attribute: &str) -> Option<&str> {
+        self.attribute
+            .eq_ignore_ascii_case(attribute)
+            .then_some(self.value.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub enum ScimError {
+    /// The request wasn't authenticated with a key of one of an org's service accounts.
+    Unauthorized,
+    NotFound,
+    /// The user is already a member of the org, or the group name is already in use.
+    Uniqueness,
+    /// An attempt to change an immutable attribute, such as a user's `userName`.
+    Mutability,
+    InvalidFilter,
+    InvalidPath,
+    InvalidValue,
+    Sqlx(sqlx::Error),
+}
+
+impl ScimError {
+    /// The SCIM `scimType` reported to clients, for errors which have one.
+    pub fn scim_type(&self) -> Option<&'static str> {
+        match self {
+            ScimError::Uniqueness => Some("uniqueness"),
+            ScimError::Mutability => Some("mutability"),
+            ScimError::InvalidFilter => Some("invalidFilter"),
+            ScimError::InvalidPath => Some("invalidPath"),
+            ScimError::InvalidValue => Some("invalidValue"),
+            ScimError::Unauthorized | ScimError::NotFound | ScimError::Sqlx(_) => None,
+        }
+    }
+}
+
+impl From<sqlx::Error> for ScimError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            sqlx::Error::Database(ref err) => match err.code().as_deref() {
+                // unique_violation
+                Some("23505") => Self::Uniqueness,
+                // foreign_key_violation, e.g. a group member who isn't a member of the org
+                Some("23503") => Self::InvalidValue,
+                _ => Self::Sqlx(e),
+            },
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn operations(ops: JsonValue) -> Vec<PatchOperation> {
+        serde_json::from_value::<PatchRequest>(json!({ "Operations": ops }))
+            .unwrap()
+            .operations
+    }
+
+    #[test]
+    fn parses_filter() {
+        let filter = Filter::parse(r#"userName eq "ada@example.com""#).unwrap();
+        assert_eq!(filter.value_of("username"), Some("ada@example.com"));
+        assert_eq!(filter.value_of("externalId"), None);
+        assert_eq!(
+            Filter::parse(r#"displayName eq "Data Science""#)
+                .unwrap()
+                .value,
+            "Data Science"
+        );
+        assert!(Filter::parse(r#"userName sw "ada""#).is_err());
+        assert!(Filter::parse("userName eq ada").is_err());
+    }
+
+    #[test]
+    fn deactivates_user() {
+        let mut user = UserAttributes {
+            user_name: "ada@example.com".to_string(),
+            external_id: None,
+            name: Name::default(),
+            active: true,
+        };
+        user.apply(&operations(json!([
+            { "op": "Replace", "path": "active", "value": "False" },
+            { "op": "add", "value": { "name.givenName": "Ada", "title": "Countess" } },
+        ])))
+        .unwrap();
+        assert!(!user.active);
+        assert_eq!(user.name.given_name.as_deref(), Some("Ada"));
+    }
+
+    #[test]
+    fn patches_group_members() {
+        let mut group = GroupAttributes {
+            display_name: "Data Science".to_string(),
+            external_id: None,
+            members: vec![],
+        };
+        group
+            .apply(&operations(json!([
+                { "op": "add", "path": "members", "value": [{ "value": "a" }, { "value": "b" }] },
+                { "op": "remove", "path": "members[value eq \"a\"]" },
+                { "op": "replace", "path": "displayName", "value": "ML" },
+            ])))
+            .unwrap();
+        assert_eq!(group.display_name, "ML");
+        assert_eq!(
+            group.members.iter().map(|m| &m.value).collect::<Vec<_>>(),
+            vec!["b"]
+        );
+    }
+}
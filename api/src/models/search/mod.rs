@@ -0,0 +1,91 @@
+use sqlx::types::{chrono, Uuid};
+
+/// The most hits of each kind a search returns.
+pub const MAX_SEARCH_HITS: i64 = 50;
+
+/// A function whose key matched, among those the user has evals of.
+#[derive(Serialize, Debug)]
+pub struct FunctionHit {
+    pub fn_key: String,
+    pub evals: i64,
+    pub score: f64,
+}
+
+/// A run whose name or function key matched.
+#[derive(Serialize, Debug)]
+pub struct RunHit {
+    pub id: Uuid,
+    pub name: Option<String>,
+    pub fn_key: String,
+    pub state: String,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+    pub score: f64,
+}
+
+/// An artifact of a run whose path matched.
+#[derive(Serialize, Debug)]
+pub struct ArtifactHit {
+    pub run_id: Uuid,
+    pub path: String,
+    pub content_hash: String,
+    pub score: f64,
+}
+
+/// A project whose name matched.
+#[derive(Serialize, Debug)]
+pub struct ProjectHit {
+    pub id: Uuid,
+    pub name: String,
+    pub score: f64,
+}
+
+/// The hits of a search, by the kind of thing hit, each best first. `score` is how closely the
+/// query matched, from 0 to 1, with 1 meaning the name contains it whole. Kinds of things the
+/// user's policy doesn't let them read aren't searched, and have no hits.
+#[derive(Serialize, Debug, Default)]
+pub struct SearchResults {
+    pub functions: Vec<FunctionHit>,
+    pub runs: Vec<RunHit>,
+    pub artifacts: Vec<ArtifactHit>,
+    pub projects: Vec<ProjectHit>,
+}
+
+/// A pattern for `ILIKE` which matches anything containing `q`, with `q` taken literally.
+pub fn contains_pattern(q: &str) -> String {
+    let mut pattern = String::with_capacity(q.len() + 2);
+    pattern.push('%');
+    for c in q.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+#[derive(Debug)]
+pub enum SearchError {
+    Unauthorized,
+    /// The query is empty.
+    InvalidQuery,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for SearchError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_patterns() {
+        assert_eq!(contains_pattern("resnet"), "%resnet%");
+        assert_eq!(contains_pattern("50%_off\\"), "%50\\%\\_off\\\\%");
+        assert_eq!(contains_pattern(""), "%%");
+    }
+}
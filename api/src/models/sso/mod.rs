@@ -0,0 +1,196 @@
+use sqlx::types::{chrono, JsonValue, Uuid};
+use std::collections::HashMap;
+
+/// How long a user has to complete a sign-in once it has been started.
+pub const LOGIN_TTL_MINS: i32 = 10;
+
+/// The role of a member within an org.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // Ordered from least to most privileged.
+    Viewer,
+    Member,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Member => "member",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = SsoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "member" => Ok(Role::Member),
+            "admin" => Ok(Role::Admin),
+            _ => Err(SsoError::InvalidConfig),
+        }
+    }
+}
+
+/// Returns the most privileged role any of `groups` maps to, or `default` when none of them are
+/// mapped.
+pub fn role_for_groups(groups: &[String], mappings: &HashMap<String, Role>, default: Role) -> Role {
+    groups
+        .iter()
+        .filter_map(|g| mappings.get(g))
+        .copied()
+        .max()
+        .unwrap_or(default)
+}
+
+/// An org's identity provider configuration, as shown to the org's owner. The client secret is
+/// never returned.
+#[derive(Serialize, Debug)]
+pub struct SsoConfig {
+    pub org_id: Uuid,
+    pub protocol: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub groups_claim: String,
+    pub role_mappings: JsonValue,
+    pub default_role: String,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+    pub update_dt: chrono::DateTime<chrono::Utc>,
+}
+
+/// An org's identity provider configuration, as needed to sign users in.
+#[derive(Debug)]
+pub struct ProviderConfig {
+    pub org_id: Uuid,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub groups_claim: String,
+    pub role_mappings: JsonValue,
+    pub default_role: String,
+}
+
+impl ProviderConfig {
+    /// The role of a user who belongs to `groups`.
+    pub fn role(&self, groups: &[String]) -> Result<Role, SsoError> {
+        let mappings = serde_json::from_value::<HashMap<String, Role>>(self.role_mappings.clone())
+            .map_err(|_| SsoError::InvalidConfig)?;
+        Ok(role_for_groups(
+            groups,
+            &mappings,
+            self.default_role.parse()?,
+        ))
+    }
+}
+
+/// The claims of an OpenID Connect ID token which are used to sign a user in. The signature,
+/// issuer, audience and expiry are checked while decoding the token.
+#[derive(Deserialize, Debug)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub nonce: Option<String>,
+    #[serde(flatten)]
+    pub other: HashMap<String, JsonValue>,
+}
+
+impl IdTokenClaims {
+    /// The groups listed in `claim`, which identity providers send either as a list or, for a
+    /// single group, as a string.
+    pub fn groups(&self, claim: &str) -> Vec<String> {
+        match self.other.get(claim) {
+            Some(JsonValue::Array(groups)) => groups
+                .iter()
+                .filter_map(|g| g.as_str().map(String::from))
+                .collect(),
+            Some(JsonValue::String(group)) => vec![group.clone()],
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SsoError {
+    Unauthorized,
+    /// The org doesn't exist, or has no identity provider configured.
+    NotFound,
+    /// The identity provider configuration was rejected, or can't be used.
+    InvalidConfig,
+    /// The sign-in was never started, or took too long to complete.
+    LoginExpired,
+    /// The identity provider couldn't be reached, or responded unexpectedly.
+    Provider(reqwest::Error),
+    /// The ID token failed validation.
+    InvalidIdToken,
+    /// The ID token has no verified email address to identify the user by.
+    NoVerifiedEmail,
+    /// The email address belongs to an existing account which isn't a member of the org, or
+    /// hasn't accepted its membership, so the org's identity provider can't vouch for it.
+    AccountExists,
+    /// The user's membership of the org has been deactivated.
+    Deprovisioned,
+    Jwt(jsonwebtoken::errors::Error),
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for SsoError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            // check_violation
+            sqlx::Error::Database(ref err)
+                if err.code() == Some(std::borrow::Cow::Borrowed("23514")) =>
+            {
+                Self::InvalidConfig
+            }
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+impl From<reqwest::Error> for SsoError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Provider(e)
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for SsoError {
+    fn from(e: jsonwebtoken::errors::Error) -> Self {
+        Self::Jwt(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_groups_to_most_privileged_role() {
+        let mappings = HashMap::from([
+            ("ml".to_string(), Role::Member),
+            ("platform".to_string(), Role::Admin),
+        ]);
+        let groups = |gs: &[&str]| gs.iter().map(|g| g.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            role_for_groups(&groups(&["ml", "platform"]), &mappings, Role::Viewer),
+            Role::Admin
+        );
+        assert_eq!(
+            role_for_groups(&groups(&["ml", "sales"]), &mappings, Role::Viewer),
+            Role::Member
+        );
+        assert_eq!(
+            role_for_groups(&groups(&["sales"]), &mappings, Role::Viewer),
+            Role::Viewer
+        );
+    }
+}
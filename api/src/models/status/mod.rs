@@ -0,0 +1,200 @@
+use crate::models::SqlDateTime;
+use crate::status::{Component, ComponentStatus, Health};
+
+use sqlx::types::chrono::{self, Utc};
+
+/// How long resolved incidents stay in the status feed, in days.
+pub const INCIDENT_HISTORY_DAYS: i32 = 14;
+
+/// Where an incident is in being dealt with, from first to last.
+pub const INCIDENT_STATUSES: [&str; 4] = ["investigating", "identified", "monitoring", "resolved"];
+
+/// A note on an incident, posted as it's dealt with.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct IncidentNote {
+    #[serde(skip)]
+    pub incident_id: i64,
+    /// The incident's status as of the note.
+    pub status: String,
+    pub message: String,
+    pub create_dt: SqlDateTime,
+}
+
+/// Something wrong with the service, posted by an admin for customers to see.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Incident {
+    pub id: i64,
+    pub title: String,
+    /// The [`Component`]s it affects.
+    pub components: Vec<String>,
+    /// How badly the components are affected: `degraded` or `outage`.
+    pub impact: String,
+    /// One of [`INCIDENT_STATUSES`].
+    pub status: String,
+    pub create_dt: SqlDateTime,
+    pub update_dt: SqlDateTime,
+    pub resolve_dt: Option<SqlDateTime>,
+    /// Its notes, most recent first.
+    pub notes: Vec<IncidentNote>,
+}
+
+impl Incident {
+    pub fn is_resolved(&self) -> bool {
+        self.resolve_dt.is_some()
+    }
+}
+
+/// The status of the service, as published at `/status.json`.
+#[derive(Serialize, Debug, Clone)]
+pub struct StatusFeed {
+    /// The worst health of any component.
+    pub status: Health,
+    pub components: Vec<ComponentStatus>,
+    /// Unresolved incidents, and those resolved recently, most recent first.
+    pub incidents: Vec<Incident>,
+    pub generated_dt: chrono::DateTime<Utc>,
+}
+
+impl StatusFeed {
+    /// The feed of the components as last checked, made at least as bad as the unresolved
+    /// incidents affecting them say.
+    pub fn new(mut components: Vec<ComponentStatus>, incidents: Vec<Incident>) -> Self {
+        for incident in incidents.iter().filter(|i| !i.is_resolved()) {
+            let impact = incident.impact.parse().unwrap_or(Health::Degraded);
+            for status in components.iter_mut() {
+                if incident
+                    .components
+                    .iter()
+                    .any(|c| c == status.component.as_str())
+                    && impact > status.health
+                {
+                    status.health = impact;
+                    status.detail = Some(incident.title.clone());
+                }
+            }
+        }
+        let status = components
+            .iter()
+            .map(|c| c.health)
+            .max()
+            .unwrap_or(Health::Operational);
+
+        Self {
+            status,
+            components,
+            incidents,
+            generated_dt: Utc::now(),
+        }
+    }
+}
+
+/// An incident to post.
+#[derive(Deserialize, Debug)]
+pub struct IncidentInsert {
+    pub title: String,
+    pub components: Vec<Component>,
+    pub impact: Health,
+    #[serde(default = "default_status")]
+    pub status: String,
+    pub message: String,
+}
+
+fn default_status() -> String {
+    INCIDENT_STATUSES[0].to_string()
+}
+
+/// A note to post on an incident, which moves it to the note's status, and to a new impact if
+/// one is given. A note with the status `resolved` resolves it.
+#[derive(Deserialize, Debug)]
+pub struct IncidentNoteInsert {
+    pub status: String,
+    pub message: String,
+    pub impact: Option<Health>,
+}
+
+#[derive(Debug)]
+pub enum StatusError {
+    Unauthorized,
+    /// Only admins can post incidents.
+    Forbidden,
+    NotFound,
+    /// The incident's status isn't one of [`INCIDENT_STATUSES`], its impact is `operational`, or it
+    /// affects no components.
+    InvalidIncident,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for StatusError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::COMPONENTS;
+
+    fn incident(components: &[&str], impact: &str, resolved: bool) -> Incident {
+        let now = Utc::now();
+        Incident {
+            id: 1,
+            title: "Uploads are failing".to_string(),
+            components: components.iter().map(|c| c.to_string()).collect(),
+            impact: impact.to_string(),
+            status: "identified".to_string(),
+            create_dt: now,
+            update_dt: now,
+            resolve_dt: resolved.then(|| now),
+            notes: vec![],
+        }
+    }
+
+    fn operational() -> Vec<ComponentStatus> {
+        COMPONENTS
+            .into_iter()
+            .map(|c| ComponentStatus::new(c, Health::Operational, None))
+            .collect()
+    }
+
+    #[test]
+    fn unresolved_incidents_affect_their_components() {
+        let feed = StatusFeed::new(
+            operational(),
+            vec![
+                incident(&["blob_store"], "outage", false),
+                incident(&["api", "database"], "outage", true),
+            ],
+        );
+        assert_eq!(feed.status, Health::Outage);
+        let blob_store = &feed.components[2];
+        assert_eq!(blob_store.component, Component::BlobStore);
+        assert_eq!(blob_store.health, Health::Outage);
+        assert_eq!(blob_store.detail.as_deref(), Some("Uploads are failing"));
+        assert_eq!(feed.components[0].health, Health::Operational);
+        assert_eq!(feed.incidents.len(), 2);
+    }
+
+    #[test]
+    fn incidents_never_improve_checks() {
+        let mut components = operational();
+        components[1] = ComponentStatus::new(
+            Component::Database,
+            Health::Outage,
+            Some("connection refused".to_string()),
+        );
+        let feed = StatusFeed::new(components, vec![incident(&["database"], "degraded", false)]);
+        assert_eq!(feed.components[1].health, Health::Outage);
+        assert_eq!(
+            feed.components[1].detail.as_deref(),
+            Some("connection refused")
+        );
+        assert_eq!(
+            StatusFeed::new(operational(), vec![]).status,
+            Health::Operational
+        );
+    }
+}
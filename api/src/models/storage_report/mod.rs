@@ -0,0 +1,95 @@
+use sqlx::types::{chrono, Uuid};
+
+/// The advisory lock held while an inventory is taken, so that only one is taken at a time.
+pub const INVENTORY_LOCK: i64 = 0x6869_7473_6176_6502;
+
+/// A listing of every object stored in the buckets BLOBs are stored in.
+#[derive(Serialize, Debug)]
+pub struct StorageInventory {
+    pub id: i64,
+    /// `running`, `finished` or `failed`.
+    pub status: String,
+    pub objects: i64,
+    pub bytes: i64,
+    /// Objects which no BLOB is recorded as stored as.
+    pub unowned_objects: i64,
+    pub unowned_bytes: i64,
+    pub error: Option<String>,
+    pub start_dt: chrono::DateTime<chrono::Utc>,
+    pub finish_dt: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// What a user's BLOBs should take up, as recorded, against what an inventory found.
+#[derive(Serialize, Debug)]
+pub struct StorageReport {
+    pub user_id: Uuid,
+    pub expected_objects: i64,
+    pub expected_bytes: i64,
+    pub actual_bytes: i64,
+    /// BLOBs recorded as available whose object wasn't found.
+    pub missing_objects: i64,
+    /// BLOBs whose object was found with a different length than recorded.
+    pub mismatched_objects: i64,
+}
+
+/// A [`StorageReport`] for an org, whose BLOBs are those of its owner.
+#[derive(Serialize, Debug)]
+pub struct OrgStorageReport {
+    pub org_id: Uuid,
+    pub name: String,
+    pub owner_id: Uuid,
+    pub expected_objects: i64,
+    pub expected_bytes: i64,
+    pub actual_bytes: i64,
+    pub missing_objects: i64,
+    pub mismatched_objects: i64,
+}
+
+#[derive(Debug)]
+pub enum StorageReportError {
+    Unauthorized,
+    /// Only admins can take inventories and see their reports.
+    Forbidden,
+    NotFound,
+    AlreadyRunning,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for StorageReportError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+/// The content hash a listed object's key is stored under, if it's a BLOB's key: the key is the
+/// prefix, then the hash. Anything else in the bucket (manifests, or objects under other
+/// prefixes) isn't a BLOB.
+pub fn listed_hash(key: &str, prefix: Option<&str>) -> Option<String> {
+    let hash = key.strip_prefix(prefix.unwrap_or_default())?;
+    if hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Some(hash.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262";
+
+    #[test]
+    fn only_blob_keys_are_listed() {
+        assert_eq!(listed_hash(HASH, None), Some(HASH.to_string()));
+        assert_eq!(
+            listed_hash(&format!("staging/{}", HASH), Some("staging/")),
+            Some(HASH.to_string())
+        );
+        assert_eq!(listed_hash(&format!("staging/{}", HASH), None), None);
+        assert_eq!(listed_hash("manifests/users.json", None), None);
+    }
+}
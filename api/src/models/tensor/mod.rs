@@ -0,0 +1,49 @@
+use sqlx::types::{chrono, JsonValue};
+
+/// A tensor file format which summaries can be extracted from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TensorFormat {
+    /// A single numpy array, as written by `numpy.save`.
+    Npy,
+    Safetensors,
+    /// A PyTorch archive, as written by `torch.save`. These are recognised, but their tensors are
+    /// pickled, so they can't be summarised.
+    Torch,
+}
+
+impl TensorFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TensorFormat::Npy => "npy",
+            TensorFormat::Safetensors => "safetensors",
+            TensorFormat::Torch => "torch",
+        }
+    }
+}
+
+/// The summary of one tensor. `min`, `max` and `mean` are over the finite elements, and are only
+/// computed for little-endian numeric and boolean dtypes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TensorSummary {
+    /// The name of the tensor within the file, for formats which hold several.
+    pub name: Option<String>,
+    pub dtype: String,
+    pub shape: Vec<u64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>,
+    pub nan_count: u64,
+    pub inf_count: u64,
+}
+
+/// The tensor summaries of a BLOB. `status` is `pending` until the background job has looked at
+/// it.
+#[derive(Serialize, Debug)]
+pub struct TensorSummaries {
+    pub status: String,
+    pub format: Option<String>,
+    pub tensors: Option<JsonValue>,
+    pub error: Option<String>,
+    pub create_dt: Option<chrono::DateTime<chrono::Utc>>,
+}
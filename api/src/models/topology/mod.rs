@@ -0,0 +1,173 @@
+use super::SqlDateTime;
+
+/// A region the service is deployed in, with the BLOB endpoint and bucket serving it.
+#[derive(Serialize, Debug, Clone)]
+pub struct DeploymentRegion {
+    pub name: String,
+    pub blob_endpoint: String,
+    pub bucket: String,
+    /// The client regions routed here. An entry ending in `*` matches every region starting with
+    /// what comes before it, e.g. `eu-*`.
+    pub serves: Vec<String>,
+    /// Clients whose region isn't served anywhere are routed to the default region.
+    pub is_default: bool,
+    /// Disabled regions are never routed to, e.g. while their replica catches up.
+    pub enabled: bool,
+    pub update_dt: SqlDateTime,
+}
+
+/// A region a client may use, so that it can measure its latency to each.
+#[derive(Serialize, Debug)]
+pub struct RouteCandidate {
+    pub region: String,
+    pub blob_endpoint: String,
+}
+
+/// Where a client should send its BLOB requests.
+#[derive(Serialize, Debug)]
+pub struct Route {
+    pub region: String,
+    pub blob_endpoint: String,
+    pub bucket: String,
+    /// Whether the client's region is served by the region, rather than falling back to the
+    /// default.
+    pub matched: bool,
+    /// Every enabled region. A client which measures its latency to them may pick the closest
+    /// instead.
+    pub candidates: Vec<RouteCandidate>,
+}
+
+impl Route {
+    /// Routes a client in `client_region` (if it said) to one of `regions`: a region of the same
+    /// name, else the region serving it most specifically, else the default region. Returns
+    /// `None` if no region is enabled.
+    pub fn choose(regions: &[DeploymentRegion], client_region: Option<&str>) -> Option<Self> {
+        let enabled: Vec<&DeploymentRegion> = regions.iter().filter(|r| r.enabled).collect();
+
+        let matched = client_region.and_then(|client| {
+            enabled
+                .iter()
+                .copied()
+                .find(|r| r.name == client)
+                .or_else(|| {
+                    enabled
+                        .iter()
+                        .copied()
+                        .filter_map(|r| {
+                            r.serves
+                                .iter()
+                                .filter_map(|pattern| specificity(pattern, client))
+                                .max()
+                                .map(|s| (s, r))
+                        })
+                        .max_by_key(|(s, _)| *s)
+                        .map(|(_, r)| r)
+                })
+        });
+        let region = matched
+            .or_else(|| enabled.iter().copied().find(|r| r.is_default))
+            .or_else(|| enabled.first().copied())?;
+
+        Some(Self {
+            region: region.name.clone(),
+            blob_endpoint: region.blob_endpoint.clone(),
+            bucket: region.bucket.clone(),
+            matched: matched.is_some(),
+            candidates: enabled
+                .iter()
+                .map(|r| RouteCandidate {
+                    region: r.name.clone(),
+                    blob_endpoint: r.blob_endpoint.clone(),
+                })
+                .collect(),
+        })
+    }
+}
+
+/// How specifically `pattern` matches `client`, or `None` if it doesn't. An exact match beats
+/// any prefix, and longer prefixes beat shorter ones.
+fn specificity(pattern: &str, client: &str) -> Option<usize> {
+    match pattern.strip_suffix('*') {
+        Some(prefix) if client.starts_with(prefix) => Some(prefix.len()),
+        Some(_) => None,
+        None if pattern == client => Some(usize::MAX),
+        None => None,
+    }
+}
+
+#[derive(Debug)]
+pub enum TopologyError {
+    Unauthorized,
+    /// Only admins can change the topology.
+    Forbidden,
+    NotFound,
+    /// No region is configured and enabled, so clients should keep using this server.
+    NotConfigured,
+    /// A region needs a name, BLOB endpoint and bucket, and the endpoint has to be a URL.
+    InvalidRegion,
+    /// The region can't be deleted while orgs pin their data to it.
+    InUse,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for TopologyError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            sqlx::Error::Database(ref err) if err.code().as_deref() == Some("23503") => Self::InUse,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn region(name: &str, serves: &[&str], is_default: bool) -> DeploymentRegion {
+        DeploymentRegion {
+            name: name.to_string(),
+            blob_endpoint: format!("https://blobs.{}.example.com", name),
+            bucket: format!("hitsave-{}", name),
+            serves: serves.iter().map(|s| s.to_string()).collect(),
+            is_default,
+            enabled: true,
+            update_dt: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn routes_to_the_most_specific_region() {
+        let regions = vec![
+            region("us-east-1", &["us-*", "ca-*"], true),
+            region("eu-west-2", &["eu-*", "GB"], false),
+            region("eu-central-1", &["eu-central-*"], false),
+        ];
+
+        let route = |client| Route::choose(&regions, client).map(|r| (r.region, r.matched));
+        assert_eq!(route(Some("eu-west-2")), Some(("eu-west-2".into(), true)));
+        assert_eq!(route(Some("GB")), Some(("eu-west-2".into(), true)));
+        assert_eq!(route(Some("eu-north-1")), Some(("eu-west-2".into(), true)));
+        assert_eq!(
+            route(Some("eu-central-2")),
+            Some(("eu-central-1".into(), true))
+        );
+        assert_eq!(route(Some("ap-south-1")), Some(("us-east-1".into(), false)));
+        assert_eq!(route(None), Some(("us-east-1".into(), false)));
+    }
+
+    #[test]
+    fn skips_disabled_regions() {
+        let mut regions = vec![
+            region("us-east-1", &[], true),
+            region("eu-west-2", &["eu-*"], false),
+        ];
+        regions[1].enabled = false;
+        let route = Route::choose(&regions, Some("eu-west-1")).unwrap();
+        assert_eq!(route.region, "us-east-1");
+        assert_eq!(route.candidates.len(), 1);
+
+        regions[0].enabled = false;
+        assert!(Route::choose(&regions, Some("eu-west-1")).is_none());
+    }
+}
@@ -0,0 +1,120 @@
+use crate::metering::{TierBytes, TierWeights};
+use sqlx::types::{chrono, Uuid};
+
+/// The bytes a user stores, by storage tier, as of the latest hourly count, and what they come to
+/// once each tier is weighted.
+#[derive(Serialize, Debug)]
+pub struct StorageUsage {
+    /// The start of the hour the bytes were counted in, or `None` if they haven't been yet.
+    pub period_start: Option<chrono::DateTime<chrono::Utc>>,
+    pub tiers: TierBytes,
+    pub total_bytes: i64,
+    /// The bytes billed for, and counted against quotas.
+    pub billable_bytes: i64,
+    pub weights: TierWeights,
+}
+
+/// Nanoseconds of process time in an hour. Clients report evals' elapsed process time in
+/// nanoseconds.
+const NANOS_PER_HOUR: f64 = 3_600.0 * 1e9;
+
+/// The most functions listed in a deduplication report.
+pub const MAX_DEDUP_FUNCTIONS: i64 = 100;
+
+/// Computation duplicated across a function's calls by members of a team: calls with the same
+/// function version and arguments, computed by more than one member because their caches
+/// weren't shared.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct DuplicatedFn {
+    pub fn_key: String,
+    /// The distinct calls (by version and arguments) computed by more than one member.
+    pub calls: i64,
+    /// The computations of those calls after each one's first, which sharing would have saved.
+    pub duplicate_evals: i64,
+    /// The process time those computations took, in nanoseconds.
+    pub saved_process_time: i64,
+}
+
+/// What sharing one cache across an org would have saved its members, by function, most saved
+/// first.
+#[derive(Serialize, Debug)]
+pub struct DedupReport {
+    pub org_id: Uuid,
+    /// The start of the period computations were counted from, if it was limited.
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+    pub duplicate_evals: i64,
+    pub saved_process_time: i64,
+    pub saved_hours: f64,
+    /// The functions most was duplicated in, up to the requested limit. The totals count every
+    /// function.
+    pub functions: Vec<DuplicatedFn>,
+}
+
+impl DedupReport {
+    /// Totals the duplication in `functions`, which are in order of time saved, keeping `limit`
+    /// of them.
+    pub fn new(
+        org_id: Uuid,
+        since: Option<chrono::DateTime<chrono::Utc>>,
+        mut functions: Vec<DuplicatedFn>,
+        limit: usize,
+    ) -> Self {
+        let duplicate_evals = functions.iter().map(|f| f.duplicate_evals).sum();
+        let saved_process_time = functions
+            .iter()
+            .fold(0i64, |t, f| t.saturating_add(f.saved_process_time));
+        functions.truncate(limit);
+
+        Self {
+            org_id,
+            since,
+            duplicate_evals,
+            saved_process_time,
+            saved_hours: saved_process_time as f64 / NANOS_PER_HOUR,
+            functions,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum UsageError {
+    Unauthorized,
+    /// The org doesn't exist, or isn't the user's.
+    NotFound,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for UsageError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn duplicated(fn_key: &str, saved_process_time: i64) -> DuplicatedFn {
+        DuplicatedFn {
+            fn_key: fn_key.to_string(),
+            calls: 1,
+            duplicate_evals: 2,
+            saved_process_time,
+        }
+    }
+
+    #[test]
+    fn reports_total_every_function() {
+        let functions = vec![
+            duplicated("mod:train", 3 * 3_600_000_000_000),
+            duplicated("mod:evaluate", 3_600_000_000_000 / 2),
+        ];
+        let report = DedupReport::new(Uuid::nil(), None, functions, 1);
+        assert_eq!(report.duplicate_evals, 4);
+        assert_eq!(report.saved_hours, 3.5);
+        assert_eq!(
+            report.functions,
+            vec![duplicated("mod:train", 3 * 3_600_000_000_000)]
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use sqlx::types::Uuid;
+use sqlx::types::{chrono, Uuid};
 
 #[derive(FromRow, Serialize, Deserialize, Debug)]
 pub struct User {
@@ -10,3 +10,44 @@ pub struct User {
     pub gh_avatar_url: Option<String>,
     pub email_verified: Option<bool>,
 }
+
+/// What a user has chosen to be sent. A user who hasn't chosen has the defaults.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Preferences {
+    /// Whether the user is sent weekly digests, of their own week and of their orgs'.
+    #[serde(default = "default_digest_emails")]
+    pub digest_emails: bool,
+}
+
+fn default_digest_emails() -> bool {
+    true
+}
+
+/// A membership an org has provisioned over SCIM for an account which already existed. It has no
+/// effect until the account's user accepts it.
+#[derive(Serialize, Debug)]
+pub struct OrgInvite {
+    pub org_id: Uuid,
+    pub org_name: String,
+    pub create_dt: chrono::DateTime<chrono::Utc>,
+}
+
+/// An email address as it's stored and compared: without surrounding whitespace, and lowercase.
+/// Addresses which differ only by case are taken to be the same address, as providers treat them.
+pub fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emails_differing_by_case_are_the_same() {
+        assert_eq!(normalize_email(" Ada@Example.COM\n"), "ada@example.com");
+        assert_eq!(
+            normalize_email("ada@example.com"),
+            normalize_email("ADA@example.com")
+        );
+    }
+}
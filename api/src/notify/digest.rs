@@ -0,0 +1,125 @@
+//! Rendering of weekly digest emails, from the plain text template in `templates/digest.txt`.
+//!
+//! Placeholders in the template are names in braces, e.g. `{saved_time}`, each replaced with its
+//! value as rendered here.
+use crate::models::digest::Digest;
+
+const TEMPLATE: &str = include_str!("templates/digest.txt");
+
+/// Nanoseconds in an hour, since process time is reported in nanoseconds.
+const NANOS_PER_HOUR: f64 = 3_600.0 * 1e9;
+
+fn hours(nanos: i64) -> String {
+    format!("{:.1} hours", nanos as f64 / NANOS_PER_HOUR)
+}
+
+fn bytes(bytes: i64) -> String {
+    const UNITS: [&str; 5] = ["bytes", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} {}", bytes, UNITS[0]),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
+
+/// Lines of a list, or a line saying there's nothing in it.
+fn list(lines: Vec<String>) -> String {
+    match lines.is_empty() {
+        true => "  (none)".to_string(),
+        false => lines.join("\n"),
+    }
+}
+
+/// The subject and text of a digest's email.
+pub fn render(digest: &Digest) -> (String, String) {
+    let subject = format!("Your HitSave week: {} saved", hours(digest.saved_time));
+
+    let top_functions = digest
+        .top_functions
+        .iter()
+        .map(|f| {
+            format!(
+                "  {}: {} saved, {} hits",
+                f.fn_key,
+                hours(f.saved_time),
+                f.hits
+            )
+        })
+        .collect();
+    let notable_runs = digest
+        .notable_runs
+        .iter()
+        .map(|r| {
+            format!(
+                "  {} ({}): {} after {}s",
+                r.name.as_deref().unwrap_or(&r.fn_key),
+                r.id,
+                r.state,
+                r.duration_secs
+            )
+        })
+        .collect();
+
+    let values = [
+        ("name", digest.name.clone()),
+        (
+            "period_start",
+            digest.period_start.format("%Y-%m-%d").to_string(),
+        ),
+        (
+            "period_end",
+            digest.period_end().format("%Y-%m-%d").to_string(),
+        ),
+        ("saved_time", hours(digest.saved_time)),
+        ("hits", digest.hits.to_string()),
+        ("misses", digest.misses.to_string()),
+        ("top_functions", list(top_functions)),
+        ("blobs_added", digest.blobs_added.to_string()),
+        ("bytes_added", bytes(digest.bytes_added)),
+        ("notable_runs", list(notable_runs)),
+    ];
+    let mut text = TEMPLATE.to_string();
+    for (name, value) in values {
+        text = text.replace(&format!("{{{}}}", name), &value);
+    }
+
+    (subject, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::digest::DigestFn;
+    use sqlx::types::chrono::{TimeZone, Utc};
+
+    #[test]
+    fn renders_every_placeholder() {
+        let digest = Digest {
+            name: "ada".to_string(),
+            period_start: Utc.with_ymd_and_hms(2023, 1, 16, 0, 0, 0).unwrap(),
+            hits: 12,
+            misses: 3,
+            saved_time: 9_000_000_000_000,
+            top_functions: vec![DigestFn {
+                fn_key: "mod:train".to_string(),
+                hits: 10,
+                saved_time: 7_200_000_000_000,
+            }],
+            blobs_added: 4,
+            bytes_added: 2_500_000,
+            notable_runs: vec![],
+        };
+        let (subject, text) = render(&digest);
+        assert_eq!(subject, "Your HitSave week: 2.5 hours saved");
+        assert!(text.contains("2023-01-16 to 2023-01-23"));
+        assert!(text.contains("  mod:train: 2.0 hours saved, 10 hits"));
+        assert!(text.contains("4 BLOBs added, 2.5 MB"));
+        assert!(text.contains("Notable runs:\n  (none)"));
+        assert!(!text.contains('{'));
+    }
+}
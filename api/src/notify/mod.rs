@@ -0,0 +1,259 @@
+pub mod digest;
+
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use url::{Host, Url};
+
+/// How long a webhook's host has to resolve.
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends notifications to users, either by email or by webhook.
+///
+/// Emails are handed to an HTTP mail relay (configured by `MAILER_URL`), which takes care of
+/// actually delivering them. This keeps SMTP out of the API server.
+///
+/// Users' webhooks are only sent to public addresses, and redirects from them aren't followed, so
+/// that they can't be used to reach the server's own network, or its cloud's metadata service.
+#[derive(Clone)]
+pub struct Notifier {
+    client: reqwest::Client,
+    mailer_url: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum NotifyError {
+    /// No mail relay has been configured, so emails can't be sent.
+    NoMailer,
+    /// The webhook's URL isn't an HTTP(S) URL, or its host resolves to an address which isn't
+    /// public.
+    ForbiddenUrl,
+    Resolve(std::io::Error),
+    Http(reqwest::Error),
+}
+
+impl From<reqwest::Error> for NotifyError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Http(e)
+    }
+}
+
+/// The request body accepted by the mail relay.
+#[derive(Serialize, Debug)]
+struct Email<'a> {
+    to: &'a str,
+    subject: &'a str,
+    text: &'a str,
+}
+
+impl Notifier {
+    pub fn new(mailer_url: Option<String>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            mailer_url,
+        }
+    }
+
+    /// Sends a plain text email to `to`.
+    pub async fn email(&self, to: &str, subject: &str, text: &str) -> Result<(), NotifyError> {
+        let url = self.mailer_url.as_ref().ok_or(NotifyError::NoMailer)?;
+
+        self.client
+            .post(url)
+            .json(&Email { to, subject, text })
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    /// POSTs `payload` as JSON to the user's webhook at `url`.
+    pub async fn webhook<T: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        payload: &T,
+    ) -> Result<(), NotifyError> {
+        webhook_client(url)
+            .await?
+            .post(url)
+            .json(payload)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    /// POSTs `payload` as JSON to the user's webhook at `url`, for webhooks whose answer matters,
+    /// returning the answer whatever its status. The webhook has `timeout` to answer in full.
+    pub async fn call<T: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        payload: &T,
+        timeout: Duration,
+    ) -> Result<reqwest::Response, NotifyError> {
+        let res = webhook_client(url)
+            .await?
+            .post(url)
+            .json(payload)
+            .timeout(timeout)
+            .send()
+            .await?;
+
+        Ok(res)
+    }
+
+    /// POSTs `payload` as JSON to a webhook the operator has configured, which may be on the
+    /// server's own network.
+    pub async fn operator_webhook<T: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        payload: &T,
+    ) -> Result<(), NotifyError> {
+        self.client
+            .post(url)
+            .json(payload)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// A client for the webhook at `url`, which connects only to the addresses its host resolves to
+/// now, all of which must be public, so that the host can't resolve elsewhere when connected to.
+async fn webhook_client(url: &str) -> Result<reqwest::Client, NotifyError> {
+    let url = Url::parse(url).map_err(|_| NotifyError::ForbiddenUrl)?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(NotifyError::ForbiddenUrl);
+    }
+    let port = url
+        .port_or_known_default()
+        .ok_or(NotifyError::ForbiddenUrl)?;
+
+    let client = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none());
+    let client = match url.host().ok_or(NotifyError::ForbiddenUrl)? {
+        Host::Ipv4(ip) if is_public(ip.into()) => client,
+        Host::Ipv6(ip) if is_public(ip.into()) => client,
+        Host::Ipv4(_) | Host::Ipv6(_) => return Err(NotifyError::ForbiddenUrl),
+        Host::Domain(domain) => {
+            let addrs: Vec<SocketAddr> =
+                tokio::time::timeout(RESOLVE_TIMEOUT, tokio::net::lookup_host((domain, port)))
+                    .await
+                    .map_err(|e| NotifyError::Resolve(e.into()))?
+                    .map_err(NotifyError::Resolve)?
+                    .collect();
+            if addrs.is_empty() || !addrs.iter().all(|addr| is_public(addr.ip())) {
+                return Err(NotifyError::ForbiddenUrl);
+            }
+            client.resolve_to_addrs(domain, &addrs)
+        }
+    };
+
+    Ok(client.build()?)
+}
+
+/// Whether `ip` is on the public internet, rather than e.g. loopback, a private network, or a
+/// link-local one, which cloud metadata services are on.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_v4(ip),
+            None => is_public_v6(ip),
+        },
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "This network", 0.0.0.0/8.
+        || a == 0
+        // Shared address space, behind carrier-grade NATs, 100.64.0.0/10.
+        || (a == 100 && (b & 0xc0) == 64)
+        // Protocol assignments, 192.0.0.0/24.
+        || (a == 192 && b == 0 && c == 0)
+        // Benchmarking, 198.18.0.0/15.
+        || (a == 198 && (b & 0xfe) == 18)
+        // Reserved, 240.0.0.0/4.
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    let [a, b, ..] = ip.segments();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local, fc00::/7, which AWS's metadata service is on too.
+        || (a & 0xfe00) == 0xfc00
+        // Link-local, fe80::/10, and the deprecated site-local, fec0::/10.
+        || (a & 0xffc0) == 0xfe80
+        || (a & 0xffc0) == 0xfec0
+        // Documentation, 2001:db8::/32.
+        || (a == 0x2001 && b == 0xdb8)
+        // IPv4 addresses translated by NAT64, 64:ff9b::/96, which may be private.
+        || (a == 0x64 && b == 0xff9b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_public_addresses_are_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00:ec2::254",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "64:ff9b::a00:1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{} is public", ip);
+        }
+        for ip in [
+            "93.184.216.34",
+            "8.8.8.8",
+            "2606:4700:4700::1111",
+            "::ffff:8.8.8.8",
+        ] {
+            assert!(is_public(ip.parse().unwrap()), "{} is not public", ip);
+        }
+    }
+
+    #[actix_rt::test]
+    async fn webhooks_must_be_public_http() {
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://[::1]/hook",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://localhost/hook",
+            "file:///etc/passwd",
+            "not a url",
+        ] {
+            assert!(
+                matches!(webhook_client(url).await, Err(NotifyError::ForbiddenUrl)),
+                "{} is allowed",
+                url
+            );
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use crate::middlewares::auth::Auth;
+use crate::models::alert::{AlertError, AlertRule, Channel, Comparison};
+use crate::persisters::{Persist, Query};
+use crate::state::State;
+
+/// A new alert rule, e.g. "email me if `val_loss` exceeds 0.5 within the last hour".
+#[derive(Deserialize, Debug)]
+pub struct AlertRuleInsert {
+    pub project: Option<String>,
+    pub metric: String,
+    pub comparison: Comparison,
+    pub threshold: f64,
+    pub window_secs: i32,
+    pub channel: Channel,
+    pub target: String,
+}
+
+pub struct AlertRulesGet {}
+
+pub struct AlertRuleDelete {
+    pub id: i64,
+}
+
+struct AlertRuleInsertResult {
+    id: i64,
+}
+
+#[async_trait]
+impl Persist for AlertRuleInsert {
+    type Ret = i64;
+    type Error = AlertError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(AlertError::Unauthorized)?;
+
+        // If a project is named, it must exist; otherwise no row is inserted and we report it as
+        // not found.
+        let res = query_as!(
+            AlertRuleInsertResult,
+            r#"
+            INSERT INTO alert_rules (user_id, project_id, metric, comparison, threshold,
+                window_secs, channel, target)
+            SELECT u.id, p.id, $4, $5, $6, $7, $8, $9
+            FROM (SELECT get_user_id($1, $2) AS id) u
+            LEFT JOIN projects p
+                ON p.user_id = u.id
+                AND p.name = $3
+            WHERE $3 IS NULL OR p.id IS NOT NULL
+            RETURNING id
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.project,
+            self.metric,
+            self.comparison.as_str(),
+            self.threshold,
+            self.window_secs,
+            self.channel.as_str(),
+            self.target,
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(res.id)
+    }
+}
+
+#[async_trait]
+impl Query for AlertRulesGet {
+    type Resolve = Vec<AlertRule>;
+    type Error = AlertError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(AlertError::Unauthorized)?;
+
+        let res = query_as!(
+            AlertRule,
+            r#"
+            SELECT a.id, p.name AS "project?", a.metric, a.comparison, a.threshold,
+                a.window_secs, a.channel, a.target, a.last_fired_dt
+            FROM alert_rules a
+            LEFT JOIN projects p
+                ON p.id = a.project_id
+            WHERE a.user_id = get_user_id($1, $2)
+            ORDER BY a.id
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Persist for AlertRuleDelete {
+    type Ret = ();
+    type Error = AlertError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(AlertError::Unauthorized)?;
+
+        query!(
+            r#"
+            DELETE FROM alert_rules
+            WHERE id = $1
+                AND user_id = get_user_id($2, $3)
+            RETURNING id
+            "#,
+            self.id,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(())
+    }
+}
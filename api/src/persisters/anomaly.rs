@@ -0,0 +1,117 @@
+use crate::middlewares::auth::Auth;
+use crate::models::anomaly::{Activity, Anomaly, AnomalyError};
+use crate::persisters::user::is_admin;
+use crate::persisters::{Persist, Query};
+use crate::state::State;
+
+/// Records cache activity for the anomaly job. Failing to record it shouldn't fail the request it
+/// happened in, so errors are only logged.
+pub async fn record_activity(state: &State, auth: &Auth, activity: Activity, fn_key: Option<&str>) {
+    let res = query!(
+        r#"
+        INSERT INTO cache_activity (user_id, api_key, kind, fn_key)
+        VALUES (get_user_id($1, $2), $2, $3, $4)
+        "#,
+        auth.jwt().map(|c| c.sub),
+        auth.api_key(),
+        activity.as_str(),
+        fn_key,
+    )
+    .execute(&state.db_conn)
+    .await;
+
+    if let Err(e) = res {
+        log::warn!("could not record cache activity: {:?}", e);
+    }
+}
+
+/// Checks that the authenticated user is an admin.
+async fn require_admin(auth: Option<&Auth>, state: &State) -> Result<(), AnomalyError> {
+    let auth = auth.ok_or(AnomalyError::Unauthorized)?;
+
+    if !is_admin(auth, state).await? {
+        return Err(AnomalyError::Forbidden);
+    }
+
+    Ok(())
+}
+
+fn default_limit() -> i64 {
+    100
+}
+
+/// Lists anomalies, most recent first. Only admins can list them.
+#[derive(Deserialize, Debug)]
+pub struct AnomaliesGet {
+    /// Only list anomalies which have (or haven't) been acknowledged.
+    pub acknowledged: Option<bool>,
+    pub kind: Option<String>,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+/// Marks an anomaly as looked into.
+pub struct AnomalyAcknowledge {
+    pub id: i64,
+}
+
+#[async_trait]
+impl Query for AnomaliesGet {
+    type Resolve = Vec<Anomaly>;
+    type Error = AnomalyError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        require_admin(auth, state).await?;
+
+        let res = query_as!(
+            Anomaly,
+            r#"
+            SELECT a.id, a.kind, a.user_id, u.gh_login, k.label AS "key_label?", a.fn_key,
+                a.observed, a.baseline, a.create_dt, a.acknowledged_dt
+            FROM anomalies a
+            JOIN users u
+                ON u.id = a.user_id
+            LEFT JOIN api_keys k
+                ON k.key = a.api_key
+            WHERE ($1::boolean IS NULL OR (a.acknowledged_dt IS NOT NULL) = $1)
+                AND (a.kind = $2 OR $2 IS NULL)
+            ORDER BY a.create_dt DESC
+            LIMIT $3
+            "#,
+            self.acknowledged,
+            self.kind,
+            self.limit,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Persist for AnomalyAcknowledge {
+    type Ret = ();
+    type Error = AnomalyError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        require_admin(auth, state).await?;
+
+        let res = query!(
+            r#"
+            UPDATE anomalies
+            SET acknowledged_dt = coalesce(acknowledged_dt, current_timestamp)
+            WHERE id = $1
+            "#,
+            self.id,
+        )
+        .execute(&state.db_conn)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(AnomalyError::NotFound);
+        }
+
+        Ok(())
+    }
+}
@@ -1,18 +1,18 @@
+use crate::keys::{self, StoredKey};
+use crate::manifest;
 use crate::middlewares::auth::Auth;
-use crate::models::api_key::ApiKeyError;
-use crate::persisters::Persist;
-use crate::state::State;
+use crate::models::api_key::{ApiKey, ApiKeyError, ExchangedKey, KeySession};
+use crate::persisters::{Persist, Query};
+use crate::policy::{self, Action, Request};
+use crate::state::{SqlPool, State};
 
 /// The data required to insert a new hashed API key into the database.
 ///
-// Note: Originally, the idea was to stored a bcrypt hashed version of the API key, rather than the
-// plaintext, in the same way as one would always avoid hash passwords provided by users. However,
-// this actually isn't really necessary for API keys, because they are randomly generated strings
-// which can't be guessed and are unlikely to be reused by end users on other services. See:
-// https://security.stackexchange.com/questions/38566/how-is-storing-an-api-secret-key-in-plaintext-in-a-database-secure
-// for a detailed discussion. The tradeoff is favourable, because hashing the API key on every
-// request to verify it matches the stored hash is expensive (bcrypt deliberately introduces a cost).
-// Instead, we use API keys more like session tokens, as described in the link.
+// Note: API keys used to be stored in plaintext, on the grounds that they are randomly generated
+// strings which can't be guessed and are unlikely to be reused by end users on other services, and
+// that hashing the key on every request to verify it is expensive. They are now stored hashed, as
+// defense in depth should the database leak, and the cost is paid once per key per process rather
+// than on every request. See `crate::keys`.
 #[derive(Serialize, Debug)]
 pub struct KeyInsert<'a> {
     pub label: String,
@@ -30,18 +30,24 @@ impl Persist for KeyInsert<'_> {
     type Error = ApiKeyError;
 
     async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
-        let jwt = auth
-            .ok_or(ApiKeyError::Unauthorized)?
-            .allow_only_jwt()
-            .map_err(|_| ApiKeyError::Unauthorized)?;
+        let auth = auth.ok_or(ApiKeyError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::ApiKeyCreate), state).await?;
+        // The built-in policy only lets signed in users create keys.
+        let jwt = auth.jwt().ok_or(ApiKeyError::Unauthorized)?;
+        let stored = StoredKey::new_blocking(self.key.clone(), state.gateway_key.clone())
+            .await
+            .ok_or(ApiKeyError::Hash)?;
 
         let res = query_as!(
             KeyInsertResult,
-            r#"INSERT INTO api_keys AS a (user_id, label, key) VALUES ($1, $2, $3)
+            r#"INSERT INTO api_keys AS a (user_id, label, key, key_hash, sealed_key)
+            VALUES ($1, $2, $3, $4, $5)
             RETURNING key, user_id"#,
             jwt.sub,
             self.label,
-            self.key,
+            stored.id,
+            stored.hash,
+            stored.sealed,
         )
         .fetch_one(&state.db_conn)
         .await;
@@ -49,9 +55,9 @@ impl Persist for KeyInsert<'_> {
         match res {
             Ok(r) => {
                 log::debug!(
-                    "inserted API key: user_id: {:?}, key: {:?}",
+                    "inserted API key: user_id: {:?}, key id: {:?}",
                     r.user_id,
-                    format!("...{}", &r.key[r.key.len() - 5..])
+                    r.key,
                 );
                 Ok(())
             }
@@ -68,3 +74,265 @@ impl Persist for KeyInsert<'_> {
         }
     }
 }
+
+/// The longest device and project names can be.
+const MAX_NAME_LEN: usize = 100;
+
+/// Exchanges a signed in session for a new key, which is recorded with the name of the device it's
+/// for and expires after `ttl_secs`, or the server's longest exchanged key lifetime if that's
+/// shorter. A key exchanged for a project can only act on that project. The project is created if
+/// it doesn't exist.
+#[derive(Deserialize, Debug)]
+pub struct KeyExchange {
+    pub device_name: String,
+    pub project: Option<String>,
+    pub ttl_secs: Option<u64>,
+}
+
+#[async_trait]
+impl Persist for KeyExchange {
+    type Ret = ExchangedKey;
+    type Error = ApiKeyError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(ApiKeyError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::ApiKeyCreate), state).await?;
+        // Only sessions can be exchanged, so that a key can't be used to mint more keys.
+        let jwt = auth.jwt().ok_or(ApiKeyError::Unauthorized)?;
+
+        let device_name = self.device_name.trim();
+        if device_name.is_empty()
+            || device_name.len() > MAX_NAME_LEN
+            || self
+                .project
+                .as_ref()
+                .map_or(false, |p| p.is_empty() || p.len() > MAX_NAME_LEN)
+            || self.ttl_secs == Some(0)
+        {
+            return Err(ApiKeyError::InvalidExchange);
+        }
+        let ttl_secs = self
+            .ttl_secs
+            .unwrap_or(state.config.exchange_key_ttl_secs)
+            .min(state.config.exchange_key_ttl_secs);
+
+        let mut tx = state.db_conn.begin().await?;
+
+        let project_id = match &self.project {
+            Some(name) => Some(
+                query_scalar!(
+                    r#"
+                    INSERT INTO projects (user_id, name)
+                    VALUES ($1, $2)
+                    ON CONFLICT (user_id, name) DO UPDATE
+                        SET name = EXCLUDED.name
+                    RETURNING id
+                    "#,
+                    jwt.sub,
+                    name,
+                )
+                .fetch_one(&mut tx)
+                .await?,
+            ),
+            None => None,
+        };
+
+        let api_key = ApiKey::random();
+        let stored = StoredKey::new_blocking(api_key.key.clone(), state.gateway_key.clone())
+            .await
+            .ok_or(ApiKeyError::Hash)?;
+        let expire_dt = query_scalar!(
+            r#"
+            INSERT INTO api_keys (user_id, label, key, key_hash, sealed_key, device_name,
+                project_id, expire_dt)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, now() + make_interval(secs => $8))
+            RETURNING expire_dt AS "expire_dt!"
+            "#,
+            jwt.sub,
+            // The device's name is its key's label too.
+            device_name,
+            stored.id,
+            stored.hash,
+            stored.sealed,
+            device_name,
+            project_id,
+            ttl_secs as f64,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        log::debug!(
+            "exchanged session for API key: user_id: {:?}, device: {:?}",
+            jwt.sub,
+            device_name,
+        );
+
+        Ok(ExchangedKey {
+            key: api_key.key,
+            device_name: device_name.to_string(),
+            project: self.project,
+            expire_dt,
+        })
+    }
+}
+
+/// Lists the signed in user's own keys, most recently used first, with the devices they were
+/// issued to and when and from where they were last used. Service account keys aren't listed.
+#[derive(Deserialize, Debug)]
+pub struct SessionsGet {}
+
+/// Revokes the signed in user's own keys, or only those issued to one device: those with the
+/// device name, or, for keys which weren't exchanged for a session, the label.
+#[derive(Deserialize, Debug)]
+pub struct SessionsRevoke {
+    pub device_name: Option<String>,
+}
+
+#[async_trait]
+impl Query for SessionsGet {
+    type Resolve = Vec<KeySession>;
+    type Error = ApiKeyError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(ApiKeyError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::UserRead), state).await?;
+        let jwt = auth.jwt().ok_or(ApiKeyError::Unauthorized)?;
+
+        let res = query_as!(
+            KeySession,
+            r#"
+            SELECT left(k.key, 8) AS "key_hint!", k.label, k.device_name, p.name AS "project?",
+                k.create_dt, k.expire_dt, k.last_seen_dt, k.last_seen_ip
+            FROM api_keys k
+            LEFT JOIN projects p
+                ON p.id = k.project_id
+            WHERE k.user_id = $1
+                AND k.service_account_id IS NULL
+                AND (k.expire_dt IS NULL OR k.expire_dt > now())
+            ORDER BY k.last_seen_dt DESC NULLS LAST, k.create_dt DESC
+            "#,
+            jwt.sub,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Persist for SessionsRevoke {
+    type Ret = u64;
+    type Error = ApiKeyError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(ApiKeyError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::ApiKeyRevoke), state).await?;
+        let jwt = auth.jwt().ok_or(ApiKeyError::Unauthorized)?;
+
+        let res = query!(
+            r#"
+            DELETE FROM api_keys
+            WHERE user_id = $1
+                AND service_account_id IS NULL
+                AND ($2::text IS NULL OR device_name = $2 OR label = $2)
+            "#,
+            jwt.sub,
+            self.device_name,
+        )
+        .execute(&state.db_conn)
+        .await?;
+
+        log::info!(
+            "revoked {} API keys of user {:?}, device: {:?}",
+            res.rows_affected(),
+            jwt.sub,
+            self.device_name,
+        );
+
+        Ok(res.rows_affected())
+    }
+}
+
+/// Records that `key` was just used from `ip`.
+pub async fn touch(db_conn: &SqlPool, key: &str, ip: Option<&str>) -> Result<(), sqlx::Error> {
+    query!(
+        r#"
+        UPDATE api_keys
+        SET last_seen_dt = now(), last_seen_ip = $2
+        WHERE key = $1
+        "#,
+        key,
+        ip,
+    )
+    .execute(db_conn)
+    .await?;
+
+    Ok(())
+}
+
+struct KeyHashResult {
+    key: String,
+    key_hash: Option<String>,
+}
+
+/// Checks an API key sent with a request against its hash, returning the id it's stored under.
+/// While the database can't be reached, keys are checked against the exported key index instead.
+pub async fn verify_key(state: &State, key: &str) -> Option<String> {
+    // Keys are alphanumeric, so anything else, e.g. a SigV4 `Authorization` header, isn't looked
+    // up.
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    if let Some(id) = state.verified_keys.get(key) {
+        return Some(id);
+    }
+
+    // Keys which haven't been hashed yet are still stored whole.
+    let res = query_as!(
+        KeyHashResult,
+        "SELECT key, key_hash FROM api_keys WHERE key = $1 OR (key = $2 AND key_hash IS NULL)",
+        keys::key_id(key),
+        key,
+    )
+    .fetch_optional(&state.db_conn)
+    .await;
+
+    let (id, hash) = match res {
+        Ok(Some(KeyHashResult {
+            key: id,
+            key_hash: Some(hash),
+        })) => (id, hash),
+        // Keys which haven't been hashed aren't remembered, as their ids change once they are.
+        Ok(Some(KeyHashResult {
+            key: id,
+            key_hash: None,
+        })) => {
+            return ring::constant_time::verify_slices_are_equal(id.as_bytes(), key.as_bytes())
+                .is_ok()
+                .then_some(id);
+        }
+        Ok(None) => return None,
+        Err(e) if manifest::is_unavailable(&e) => {
+            let id = keys::key_id(key).to_string();
+            let verified = manifest::verify_key(state, key)
+                .await
+                .map_err(|e| log::warn!("could not check api key against key index: {:?}", e))
+                .unwrap_or(false);
+            // Keys checked against the index aren't remembered, as it may be stale.
+            return verified.then_some(id);
+        }
+        Err(e) => {
+            log::error!("could not look up api key: {:?}", e);
+            return None;
+        }
+    };
+
+    if !keys::verify_blocking(key.to_string(), hash).await {
+        return None;
+    }
+    state.verified_keys.insert(key, id.clone());
+    Some(id)
+}
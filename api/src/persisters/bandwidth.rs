@@ -0,0 +1,135 @@
+use crate::middlewares::auth::Auth;
+use crate::models::bandwidth::{BandwidthError, KeyBandwidth};
+use crate::persisters::user::is_admin;
+use crate::persisters::{Persist, Query};
+use crate::priority::Priority;
+use crate::state::State;
+use crate::throttle::{Bucket, Direction};
+use sqlx::types::Uuid;
+
+async fn require_admin(auth: Option<&Auth>, state: &State) -> Result<(), BandwidthError> {
+    let auth = auth.ok_or(BandwidthError::Unauthorized)?;
+
+    if !is_admin(auth, state).await? {
+        return Err(BandwidthError::Forbidden);
+    }
+
+    Ok(())
+}
+
+/// Lists the bandwidth caps of a user's keys.
+pub struct BandwidthCapsGet {
+    pub user_id: Uuid,
+}
+
+/// Sets the bandwidth caps of a user's keys with the given label, or of all of the user's keys.
+/// A cap left out is cleared, so that the server's default applies.
+#[derive(Deserialize, Debug)]
+pub struct BandwidthCapsUpdate {
+    #[serde(skip)]
+    pub user_id: Uuid,
+    pub label: Option<String>,
+    pub upload_bytes_per_sec: Option<i64>,
+    pub download_bytes_per_sec: Option<i64>,
+}
+
+#[async_trait]
+impl Query for BandwidthCapsGet {
+    type Resolve = Vec<KeyBandwidth>;
+    type Error = BandwidthError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        require_admin(auth, state).await?;
+
+        let res = query_as!(
+            KeyBandwidth,
+            r#"
+            SELECT label, create_dt, upload_bytes_per_sec, download_bytes_per_sec
+            FROM api_keys
+            WHERE user_id = $1
+            ORDER BY create_dt
+            "#,
+            self.user_id,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Persist for BandwidthCapsUpdate {
+    type Ret = Vec<KeyBandwidth>;
+    type Error = BandwidthError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        require_admin(auth, state).await?;
+
+        let res = query_as!(
+            KeyBandwidth,
+            r#"
+            UPDATE api_keys
+            SET upload_bytes_per_sec = $3,
+                download_bytes_per_sec = $4
+            WHERE user_id = $1
+                AND ($2::text IS NULL OR label = $2)
+            RETURNING label, create_dt, upload_bytes_per_sec, download_bytes_per_sec
+            "#,
+            self.user_id,
+            self.label,
+            self.upload_bytes_per_sec,
+            self.download_bytes_per_sec,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        if res.is_empty() {
+            return Err(BandwidthError::NotFound);
+        }
+
+        Ok(res)
+    }
+}
+
+/// The bucket limiting the authenticated principal's transfers of `priority` in `direction`, or
+/// `None` if they're uncapped. An API key's own cap takes precedence over the server's default;
+/// a signed in user is limited by the default. Transfers aren't failed for want of a cap: if the
+/// key's cap can't be looked up, the default applies.
+pub async fn bucket_for(
+    auth: &Auth,
+    state: &State,
+    direction: Direction,
+    priority: Priority,
+) -> Option<Bucket> {
+    let default = match direction {
+        Direction::Upload => state.config.bandwidth_upload_bytes_per_sec,
+        Direction::Download => state.config.bandwidth_download_bytes_per_sec,
+    };
+
+    let (principal, cap) = match auth {
+        Auth::ApiKey(key) => {
+            let caps = query!(
+                r#"
+                SELECT upload_bytes_per_sec, download_bytes_per_sec
+                FROM api_keys
+                WHERE key = $1
+                "#,
+                key,
+            )
+            .fetch_optional(&state.db_conn)
+            .await
+            .map_err(|e| log::warn!("could not look up bandwidth caps: {:?}", e))
+            .ok()
+            .flatten();
+            let cap = caps.and_then(|c| match direction {
+                Direction::Upload => c.upload_bytes_per_sec,
+                Direction::Download => c.download_bytes_per_sec,
+            });
+            (key.clone(), cap.map(|c| c as u64).or(default))
+        }
+        Auth::Jwt(claims) => (format!("user:{}", claims.sub), default),
+    };
+
+    cap.map(|cap| state.throttle.bucket(&principal, direction, priority, cap))
+}
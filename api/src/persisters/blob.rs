@@ -1,17 +1,83 @@
+use crate::downloads::{limited, DownloadPermit};
+use crate::error_code::{coded, coded_retry_after, ErrorCode};
+use crate::extractors::with_blob::{BlobPayload, WithBlobError};
 use crate::handlers::blob::{BlobParams, BlobParamsHead};
+use crate::hashing::{parse_address, Address};
+use crate::manifest;
 use crate::middlewares::auth::Auth;
-use crate::persisters::s3store::BlobMetadata;
+use crate::models::anomaly::Activity;
+use crate::models::blob_backfill::BlobStatus;
+use crate::models::blob_stats::{BlobStats, BlobStatsError};
+use crate::models::tensor::TensorSummaries;
+use crate::models::SqlDateTime;
+use crate::persisters::anomaly::record_activity;
+use crate::persisters::bandwidth::bucket_for;
+use crate::persisters::canary;
+use crate::persisters::s3store::{
+    shared_object, store_shared, upload_target, BlobMetadata, Stored, Target,
+};
+use crate::persisters::usage::within_storage_quota;
+use crate::persisters::user::user_id;
 use crate::persisters::{s3store::StoreError, Persist, Query};
-use crate::state::State;
+use crate::priority::Priority;
+use crate::resume::{
+    requested_range, Checkpoint, ResumableHasher, CHECKPOINT_INTERVAL, RESUME_TOKEN_HEADER,
+    RESUME_TOKEN_TTL_HOURS,
+};
+use crate::state::{SqlPool, State};
+use crate::throttle::{throttled, Direction};
 use actix_web::{
-    body::BodyStream, error, http::StatusCode, web::Path, Error, HttpResponse, HttpResponseBuilder,
+    body::{BodyStream, SizedStream},
+    error,
+    http::{header, StatusCode},
+    web::{self, Path},
+    Error, HttpResponse, HttpResponseBuilder,
 };
+use aws_sdk_s3::types::ByteStream;
 use blake3::{Hash, HexError};
+use futures::stream::StreamExt;
+use qbsdiff::{Bsdiff, Bspatch};
+use sqlx::{
+    types::{chrono::Utc, Uuid},
+    Postgres, Transaction,
+};
+use std::io::{self, Cursor};
+
+/// The largest BLOB, in bytes, which can be diffed or patched. Both versions are held in memory.
+pub const MAX_DIFF_BLOB_LEN: i64 = 64 * 1024 * 1024;
+
+/// The longest label a BLOB can be given, in characters.
+pub const MAX_BLOB_LABEL_LEN: usize = 256;
 
 #[derive(Deserialize, Debug)]
 pub struct BlobInsert {
     pub content_length: i64,
     pub content_hash: String,
+    /// The kind of content the BLOB is, e.g. `application/x-parquet`.
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    /// A label of the client's own, e.g. the name of the file the BLOB was read from.
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+impl BlobInsert {
+    /// Checks the metadata given with the BLOB, so that it can be rejected before the BLOB is
+    /// uploaded.
+    pub fn check_metadata(&self) -> Result<(), BlobError> {
+        if let Some(mime_type) = &self.mime_type {
+            if mime_type.len() > 255 || mime_type.parse::<mime::Mime>().is_err() {
+                return Err(BlobError::InvalidMetadata);
+            }
+        }
+        if let Some(label) = &self.label {
+            if label.chars().count() > MAX_BLOB_LABEL_LEN || label.chars().any(char::is_control) {
+                return Err(BlobError::InvalidMetadata);
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl BlobMetadata for BlobInsert {
@@ -24,8 +90,94 @@ impl BlobMetadata for BlobInsert {
     }
 }
 
-struct BlobInsertResult {
-    id: Option<i64>,
+/// Records ownership of the BLOB with the given content hash by the authenticated user, and where
+/// it's stored, returning the blob's ID. If the user already owns the BLOB, the existing ID is
+/// returned.
+///
+/// This is for BLOBs which arrive through routes other than `PUT /blob` (e.g. MLflow artifacts),
+/// and which have already been stored in `target` (as `stored`), or found to be stored there
+/// already (in which case `stored` is `None`). The object it's stored as is recorded too, for later
+/// uploads of the same BLOB to share.
+pub async fn upsert_blob(
+    tx: &mut Transaction<'_, Postgres>,
+    auth: &Auth,
+    content_hash: &str,
+    target: &Target,
+    stored: Option<Stored>,
+) -> Result<i64, sqlx::Error> {
+    upsert_owned_blob(
+        tx,
+        auth.jwt().map(|c| c.sub),
+        auth.api_key(),
+        content_hash,
+        target,
+        stored,
+    )
+    .await
+}
+
+/// Records ownership of a BLOB the server produced for a user, e.g. an export, as [`upsert_blob`]
+/// does for the authenticated user's.
+pub async fn upsert_user_blob(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    content_hash: &str,
+    target: &Target,
+    stored: Option<Stored>,
+) -> Result<i64, sqlx::Error> {
+    upsert_owned_blob(tx, Some(user_id), None, content_hash, target, stored).await
+}
+
+async fn upsert_owned_blob(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: Option<Uuid>,
+    api_key: Option<&str>,
+    content_hash: &str,
+    target: &Target,
+    stored: Option<Stored>,
+) -> Result<i64, sqlx::Error> {
+    let id = query_scalar!(
+        r#"
+        WITH object AS (
+            INSERT INTO blob_objects (content_hash, storage_region, storage_bucket, storage_prefix,
+                compression, stored_length)
+            VALUES ($3, $4, $5, $6, $7, $8)
+            ON CONFLICT (content_hash, coalesce(storage_region, ''), coalesce(storage_bucket, ''))
+                DO UPDATE SET used_dt = now(),
+                    compression = CASE WHEN EXCLUDED.stored_length IS NULL
+                        THEN blob_objects.compression ELSE EXCLUDED.compression END,
+                    stored_length = coalesce(EXCLUDED.stored_length, blob_objects.stored_length)
+            RETURNING id, compression, stored_length
+        )
+        INSERT INTO blobs (user_id, content_hash, storage_region, storage_bucket, storage_prefix,
+            object_id, compression, stored_length, object_status, checked_dt)
+        SELECT get_user_id($1, $2), $3, $4, $5, $6, object.id, object.compression,
+            object.stored_length, 'available', now()
+        FROM object
+        ON CONFLICT (user_id, content_hash) DO UPDATE
+            SET storage_region = EXCLUDED.storage_region,
+                storage_bucket = EXCLUDED.storage_bucket,
+                storage_prefix = EXCLUDED.storage_prefix,
+                object_id = EXCLUDED.object_id,
+                compression = EXCLUDED.compression,
+                stored_length = EXCLUDED.stored_length,
+                object_status = EXCLUDED.object_status,
+                checked_dt = EXCLUDED.checked_dt
+        RETURNING id
+        "#,
+        user_id,
+        api_key,
+        content_hash,
+        target.region,
+        target.bucket,
+        target.prefix,
+        stored.and_then(|s| s.compression),
+        stored.map(|s| s.length),
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    Ok(id)
 }
 
 #[async_trait]
@@ -39,37 +191,132 @@ impl Persist for BlobInsert {
             .api_key()
             .ok_or(BlobError::Unauthorized)?;
 
-        // Insert blob.
-        let blob_res = query_as!(
-            BlobInsertResult,
+        // Insert blob, or update the metadata of the one the user already owns.
+        let id = query_scalar!(
             r#"
-            WITH s AS (
-                SELECT id 
-                FROM blobs 
-                WHERE user_id = user_from_key($1) 
-                AND content_hash = $2
-            ), i AS (
-                INSERT INTO blobs (user_id, content_hash)
-                VALUES (user_from_key($1), $2)
-                ON CONFLICT DO NOTHING
-                RETURNING id
-            )
-            SELECT id
-            FROM i UNION ALL
-            SELECT id
-            FROM s
+            INSERT INTO blobs (user_id, content_hash, content_length, mime_type, label)
+            VALUES (user_from_key($1), $2, $3, $4, $5)
+            ON CONFLICT (user_id, content_hash) DO UPDATE
+                SET content_length = coalesce(blobs.content_length, EXCLUDED.content_length),
+                    mime_type = coalesce(EXCLUDED.mime_type, blobs.mime_type),
+                    label = coalesce(EXCLUDED.label, blobs.label)
+            RETURNING id
             "#,
             api_key,
             self.content_hash,
+            self.content_length,
+            self.mime_type,
+            self.label,
         )
         .fetch_one(&state.db_conn)
         .await?;
 
-        // TODO: get rid of the expect
-        Ok(blob_res.id.expect("should always be some"))
+        Ok(id)
     }
 }
 
+/// Resolves the address a BLOB was requested by to its content hash. A BLOB addressed by its
+/// digest under another algorithm is looked up by the digest recorded for it, which it only has
+/// once it's been rehashed. See [`crate::hashing`]. A digest which isn't known is unauthorized, as
+/// a BLOB the user doesn't own is, so as not to give away which BLOBs are stored.
+pub async fn resolve_address(db: &SqlPool, address: &str) -> Result<String, BlobError> {
+    match parse_address(address).ok_or(BlobError::InvalidHash)? {
+        Address::Content(content_hash) => Ok(content_hash.to_string()),
+        Address::Digest(algorithm, digest) => query_scalar!(
+            r#"
+            SELECT content_hash
+            FROM blob_digests
+            WHERE algorithm = $1
+                AND digest = lower($2)
+            "#,
+            algorithm.as_str(),
+            digest,
+        )
+        .fetch_optional(db)
+        .await?
+        .ok_or(BlobError::Unauthorized),
+    }
+}
+
+/// Where a BLOB the user owns is stored, and whether it can be downloaded.
+#[derive(Debug)]
+struct DownloadRow {
+    id: i64,
+    storage_class: String,
+    storage_region: Option<String>,
+    storage_bucket: Option<String>,
+    storage_prefix: Option<String>,
+    object_status: String,
+    content_length: Option<i64>,
+    compression: Option<String>,
+    canary: bool,
+}
+
+impl DownloadRow {
+    fn target(&self) -> Target {
+        Target {
+            region: self.storage_region.clone(),
+            bucket: self.storage_bucket.clone(),
+            prefix: self.storage_prefix.clone(),
+        }
+    }
+
+    /// Whether the BLOB is a single, uncompressed object in the server's own bucket, which a CDN in
+    /// front of the bucket can serve. See [`crate::cdn`].
+    fn servable_from_cdn(&self) -> bool {
+        self.storage_region.is_none() && self.storage_bucket.is_none() && self.compression.is_none()
+    }
+
+    /// Checks that the BLOB can be downloaded from storage, and checks the download against the
+    /// BLOB's canaries, if it's a canary's.
+    async fn check(&self, state: &State, auth: &Auth, ip: Option<&str>) -> Result<(), BlobError> {
+        // Archived BLOBs can't be read from cold storage directly; the run has to be unarchived.
+        if self.storage_class != "STANDARD" {
+            return Err(BlobError::Archived);
+        }
+        if self.object_status == BlobStatus::Missing.as_str() {
+            return Err(BlobError::Missing);
+        }
+        if self.canary {
+            canary::check_download(state, auth, self.id, ip).await;
+        }
+
+        Ok(())
+    }
+}
+
+/// Looks up the BLOB with the content hash, if the authenticated user owns it, to download it.
+async fn download_row(
+    state: &State,
+    auth: &Auth,
+    content_hash: &str,
+) -> Result<Option<DownloadRow>, sqlx::Error> {
+    query_as!(
+        DownloadRow,
+        r#"
+        SELECT id, storage_class, storage_region, storage_bucket, storage_prefix, object_status,
+            content_length, compression, canary
+        FROM blobs
+        WHERE content_hash = $1
+            AND user_id = get_user_id($2, $3)
+        "#,
+        content_hash,
+        auth.jwt().map(|c| c.sub),
+        auth.api_key(),
+    )
+    .fetch_optional(&state.db_conn)
+    .await
+}
+
+/// A permit to download a BLOB through the server, or [`BlobError::Busy`] if it's serving as many
+/// downloads as it can. See [`crate::downloads`].
+fn start_download(state: &State) -> Result<DownloadPermit, BlobError> {
+    state
+        .downloads
+        .start()
+        .ok_or_else(|| BlobError::Busy(state.poll_after_ms()))
+}
+
 #[async_trait]
 impl Query for Path<BlobParams> {
     type Resolve = HttpResponse;
@@ -82,78 +329,988 @@ impl Query for Path<BlobParams> {
         dbg!(auth.jwt().map(|c| c.sub));
         dbg!(auth.api_key());
 
-        let content_hash = self.into_inner().content_hash;
+        let BlobParams {
+            content_hash,
+            priority,
+            range,
+            ip,
+        } = self.into_inner();
 
         // 1. Check the hash is valid.
         let hash = Hash::from_hex(&content_hash)?;
 
         // 2. Check postgres to make sure they are authed.
-        let res = query!(
+        let res = download_row(state, auth, &content_hash).await;
+
+        dbg!(&res);
+
+        let res = match res {
+            Ok(res) => res,
+            // When the database is down, the exported manifests stand in for it.
+            Err(e) if manifest::is_unavailable(&e) => {
+                log::warn!(
+                    "database unavailable, authorizing download from manifest: {:?}",
+                    e
+                );
+                if !manifest::authorize(state, auth, &content_hash).await? {
+                    return Err(BlobError::Unauthorized);
+                }
+                let permit = start_download(state)?;
+                // Only BLOBs in the server's own bucket, under its key prefix, are in the manifests.
+                // Their lengths aren't, so the whole BLOB is sent whatever range was requested.
+                let byte_stream = state
+                    .blob_store
+                    .retrieve_blob(&Target::server(), hash)
+                    .await?;
+                let bucket = bucket_for(auth, state, Direction::Download, priority).await;
+                let body_stream = BodyStream::new(throttled(limited(byte_stream, permit), bucket));
+                return Ok(HttpResponseBuilder::new(StatusCode::OK).body(body_stream));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let res = res.ok_or(BlobError::Unauthorized)?;
+        res.check(state, auth, ip.as_deref()).await?;
+
+        // 3. Send the client to the CDN for the BLOB, if it can be served from there.
+        let target = res.target();
+        if let Some(cdn) = state.cdn.as_ref().filter(|_| res.servable_from_cdn()) {
+            record_activity(state, auth, Activity::Download, None).await;
+            let expires = Utc::now().timestamp() + state.config.cdn_url_ttl_secs;
+            return Ok(HttpResponse::Found()
+                .insert_header((header::LOCATION, cdn.signed_url(&target.key(hash), expires)))
+                .insert_header((header::CACHE_CONTROL, "no-store"))
+                .finish());
+        }
+
+        // 4. Ping S3 for the BLOB, or the range of it requested, and send it.
+        let permit = start_download(state)?;
+        let range = match range {
+            Some(range) => {
+                // BLOBs recorded before their lengths were have them looked up.
+                let length = match res.content_length {
+                    Some(length) => length as u64,
+                    None => state.blob_store.blob_length(&target, hash).await? as u64,
+                };
+                let (first, last) =
+                    requested_range(&range, length).ok_or(BlobError::InvalidRange)?;
+                Some((first, last, length))
+            }
+            None => None,
+        };
+        let byte_stream = match range {
+            Some((first, last, _)) => {
+                state
+                    .blob_store
+                    .retrieve_blob_range(&target, hash, first, last)
+                    .await?
+            }
+            None => state.blob_store.retrieve_blob(&target, hash).await?,
+        };
+        record_activity(state, auth, Activity::Download, None).await;
+        let bucket = bucket_for(auth, state, Direction::Download, priority).await;
+        let body_stream = BodyStream::new(throttled(limited(byte_stream, permit), bucket));
+        let mut res = match range {
+            Some((first, last, length)) => {
+                let mut res = HttpResponseBuilder::new(StatusCode::PARTIAL_CONTENT);
+                res.insert_header((
+                    header::CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", first, last, length),
+                ));
+                res
+            }
+            None => HttpResponseBuilder::new(StatusCode::OK),
+        };
+        res.insert_header((header::ACCEPT_RANGES, "bytes"));
+        Ok(res.body(body_stream))
+    }
+}
+
+/// Downloads the result of one of the user's evals: its BLOB, or the range of it holding the
+/// result, when the BLOB holds the results of several evals. A `Range` header is taken to be
+/// within the result, rather than the BLOB.
+#[derive(Debug)]
+pub struct EvalPayloadGet {
+    pub eval_id: Uuid,
+    pub range: Option<String>,
+    pub priority: Priority,
+    pub ip: Option<String>,
+}
+
+/// Where the result of an eval is stored.
+struct PayloadRow {
+    id: i64,
+    content_hash: String,
+    storage_class: String,
+    storage_region: Option<String>,
+    storage_bucket: Option<String>,
+    storage_prefix: Option<String>,
+    object_status: String,
+    content_length: Option<i64>,
+    compression: Option<String>,
+    canary: bool,
+    blob_offset: Option<i64>,
+    blob_length: Option<i64>,
+}
+
+#[async_trait]
+impl Query for EvalPayloadGet {
+    type Resolve = HttpResponse;
+    type Error = BlobError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(BlobError::Unauthorized)?;
+        let permit = start_download(state)?;
+
+        let row = query_as!(
+            PayloadRow,
             r#"
-                SELECT count(id) FROM blobs
-                WHERE   content_hash = $1
-                    AND user_id = get_user_id($2, $3)
-           "#,
-            content_hash,
+            SELECT b.id, b.content_hash, b.storage_class, b.storage_region, b.storage_bucket,
+                b.storage_prefix, b.object_status, b.content_length, b.compression, b.canary,
+                e.blob_offset, e.blob_length
+            FROM evals e
+            JOIN blobs b
+                ON b.id = e.blob_id
+            WHERE e.id = $1
+                AND e.user_id = get_user_id($2, $3)
+            "#,
+            self.eval_id,
             auth.jwt().map(|c| c.sub),
             auth.api_key(),
         )
-        .fetch_one(&state.db_conn)
-        .await?;
+        .fetch_optional(&state.db_conn)
+        .await?
+        .ok_or(BlobError::NotFound)?;
 
-        dbg!(&res);
+        let hash = Hash::from_hex(&row.content_hash)?;
+        let download = DownloadRow {
+            id: row.id,
+            storage_class: row.storage_class,
+            storage_region: row.storage_region,
+            storage_bucket: row.storage_bucket,
+            storage_prefix: row.storage_prefix,
+            object_status: row.object_status,
+            content_length: row.content_length,
+            compression: row.compression,
+            canary: row.canary,
+        };
+        download.check(state, auth, self.ip.as_deref()).await?;
+        let target = download.target();
 
-        if res.count != Some(1) {
-            return Err(BlobError::Unauthorized);
+        // The result is the whole BLOB, unless it's a range of one.
+        let (offset, length) = match (row.blob_offset, row.blob_length) {
+            (Some(offset), Some(length)) => (offset as u64, length as u64),
+            _ => match download.content_length {
+                Some(length) => (0, length as u64),
+                None => (0, state.blob_store.blob_length(&target, hash).await? as u64),
+            },
+        };
+        let range = match self.range {
+            Some(range) => Some(requested_range(&range, length).ok_or(BlobError::InvalidRange)?),
+            None => None,
+        };
+
+        // An empty result has no range to read.
+        if length == 0 {
+            return Ok(payload_response(range, length).finish());
         }
 
-        // 3. Ping S3 for the BLOB and send it.
-        let byte_stream = state.s3_store.retrieve_blob(hash).await?;
-        let body_stream = BodyStream::new(byte_stream);
-        let http_response = HttpResponseBuilder::new(StatusCode::OK).body(body_stream);
-        Ok(http_response)
+        let byte_stream = match range {
+            Some((first, last)) => {
+                state
+                    .blob_store
+                    .retrieve_blob_range(&target, hash, offset + first, offset + last)
+                    .await?
+            }
+            None if row.blob_offset.is_none() => {
+                state.blob_store.retrieve_blob(&target, hash).await?
+            }
+            None => {
+                state
+                    .blob_store
+                    .retrieve_blob_range(&target, hash, offset, offset + length - 1)
+                    .await?
+            }
+        };
+        record_activity(state, auth, Activity::Download, None).await;
+        let bucket = bucket_for(auth, state, Direction::Download, self.priority).await;
+        let body_stream = BodyStream::new(throttled(limited(byte_stream, permit), bucket));
+        Ok(payload_response(range, length).body(body_stream))
     }
 }
 
+/// The response for an eval's payload, or the range of it requested, before its body. The builder
+/// isn't `Send`, so it's only made once nothing else is awaited.
+fn payload_response(range: Option<(u64, u64)>, length: u64) -> HttpResponseBuilder {
+    let mut res = match range {
+        Some((first, last)) => {
+            let mut res = HttpResponseBuilder::new(StatusCode::PARTIAL_CONTENT);
+            res.insert_header((
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", first, last, length),
+            ));
+            res
+        }
+        None => HttpResponseBuilder::new(StatusCode::OK),
+    };
+    res.insert_header((header::ACCEPT_RANGES, "bytes"));
+    res
+}
+
+/// What's recorded of a BLOB the user owns, for clients deciding whether to download it. BLOBs
+/// recorded before their metadata was have none, until the backfill finds their lengths.
+pub struct BlobHead {
+    pub content_length: Option<i64>,
+    pub mime_type: Option<String>,
+    pub label: Option<String>,
+}
+
 #[async_trait]
-impl Query for Path<BlobParamsHead> {
-    type Resolve = ();
+impl Query for BlobParamsHead {
+    type Resolve = BlobHead;
     type Error = BlobError;
 
     async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
         let auth = auth.ok_or(BlobError::Unauthorized)?;
 
-        let content_hash = self.into_inner().content_hash;
+        let content_hash = self.content_hash;
 
         // 1. Check the hash is valid.
         let _hash = Hash::from_hex(&content_hash)?;
 
         // 2. Check postgres to make sure they are authed.
-        let res = query!(
+        let res = query_as!(
+            BlobHead,
             r#"
-                SELECT count(id) FROM blobs
+                SELECT content_length, mime_type, label FROM blobs
                 WHERE   content_hash = $1
                     AND user_id = get_user_id($2, $3)
+                    AND object_status <> 'missing'
            "#,
             content_hash,
             auth.jwt().map(|c| c.sub),
             auth.api_key(),
         )
-        .fetch_one(&state.db_conn)
+        .fetch_optional(&state.db_conn)
+        .await?
+        .ok_or(BlobError::NotFound)?;
+
+        Ok(res)
+    }
+}
+
+fn default_limit() -> i64 {
+    100
+}
+
+/// The most BLOBs listed at once.
+pub const MAX_BLOB_LISTING: i64 = 1000;
+
+/// Lists the authenticated user's BLOBs, newest first. The next page is listed from `before`, the
+/// id of the last BLOB of the page before.
+#[derive(Deserialize, Debug)]
+pub struct BlobsGet {
+    pub before: Option<i64>,
+    /// Only list BLOBs whose labels start with this.
+    pub label_prefix: Option<String>,
+    /// Only list BLOBs created at or after this.
+    pub since: Option<SqlDateTime>,
+    /// Only list BLOBs created before this.
+    pub until: Option<SqlDateTime>,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+/// A BLOB the user owns, as listed.
+#[derive(Serialize, Debug)]
+pub struct BlobSummary {
+    pub id: i64,
+    pub content_hash: String,
+    pub content_length: Option<i64>,
+    pub mime_type: Option<String>,
+    pub label: Option<String>,
+    /// See [`BlobStatus`].
+    pub blob_status: String,
+    pub create_dt: SqlDateTime,
+}
+
+#[async_trait]
+impl Query for BlobsGet {
+    type Resolve = Vec<BlobSummary>;
+    type Error = BlobError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(BlobError::Unauthorized)?;
+
+        let res = query_as!(
+            BlobSummary,
+            r#"
+            SELECT id, content_hash, content_length, mime_type, label,
+                blob_status(object_status, storage_class) AS "blob_status!", create_dt
+            FROM blobs
+            WHERE user_id = get_user_id($1, $2)
+                AND (id < $3 OR $3 IS NULL)
+                AND (left(label, length($4)) = $4 OR $4 IS NULL)
+                AND (create_dt >= $5 OR $5 IS NULL)
+                AND (create_dt < $6 OR $6 IS NULL)
+            ORDER BY id DESC
+            LIMIT $7
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.before,
+            self.label_prefix,
+            self.since,
+            self.until,
+            self.limit.clamp(1, MAX_BLOB_LISTING),
+        )
+        .fetch_all(&state.db_conn)
         .await?;
 
-        if res.count != Some(1) {
-            return Err(BlobError::NotFound);
+        Ok(res)
+    }
+}
+
+/// What the authenticated user stores in BLOBs, in total and by the month they were created in.
+pub struct BlobUsageGet {}
+
+/// The BLOBs a user created in a month.
+#[derive(Serialize, Debug)]
+pub struct MonthlyBlobUsage {
+    /// The start of the month, in UTC.
+    pub month: SqlDateTime,
+    pub objects: i64,
+    pub bytes: i64,
+}
+
+/// What a user stores in BLOBs. A BLOB's bytes are its length before any compression, and BLOBs
+/// whose length isn't recorded count for none.
+#[derive(Serialize, Debug)]
+pub struct BlobUsage {
+    pub objects: i64,
+    pub total_bytes: i64,
+    /// Oldest first, leaving out months with no BLOBs.
+    pub months: Vec<MonthlyBlobUsage>,
+}
+
+#[async_trait]
+impl Query for BlobUsageGet {
+    type Resolve = BlobUsage;
+    type Error = BlobError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(BlobError::Unauthorized)?;
+
+        let months = query_as!(
+            MonthlyBlobUsage,
+            r#"
+            SELECT date_trunc('month', create_dt, 'UTC') AS "month!",
+                count(*) AS "objects!",
+                coalesce(sum(content_length), 0)::bigint AS "bytes!"
+            FROM blobs
+            WHERE user_id = get_user_id($1, $2)
+            GROUP BY 1
+            ORDER BY 1
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(BlobUsage {
+            objects: months.iter().map(|m| m.objects).sum(),
+            total_bytes: months.iter().map(|m| m.bytes).sum(),
+            months,
+        })
+    }
+}
+
+/// How long, in seconds, a presigned download URL is valid for.
+const PRESIGNED_DOWNLOAD_TTL_SECS: u64 = 5 * 60;
+
+/// A short-lived URL to download a BLOB the user owns straight from storage, rather than through
+/// the server, after the same checks as `GET /blob/{content_hash}`. The server never sees the
+/// download, so it isn't throttled by bandwidth caps.
+pub struct PresignedBlobGet {
+    pub content_hash: String,
+    /// The address the request came from, for checking downloads of canaries.
+    pub ip: Option<String>,
+}
+
+/// Where to download a BLOB from.
+#[derive(Serialize, Debug)]
+pub struct PresignedDownload {
+    pub url: String,
+    /// The length of the BLOB, if it's recorded.
+    pub content_length: Option<i64>,
+    /// How the object at `url` is compressed, if it is. The client decompresses it, since the
+    /// BLOB's hash is of what it was before compression.
+    pub compression: Option<String>,
+    pub expire_dt: SqlDateTime,
+}
+
+#[async_trait]
+impl Query for PresignedBlobGet {
+    type Resolve = PresignedDownload;
+    type Error = BlobError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(BlobError::Unauthorized)?;
+        let hash = Hash::from_hex(&self.content_hash)?;
+
+        let res = download_row(state, auth, &self.content_hash)
+            .await?
+            .ok_or(BlobError::Unauthorized)?;
+        res.check(state, auth, self.ip.as_deref()).await?;
+
+        let expires_in = std::time::Duration::from_secs(PRESIGNED_DOWNLOAD_TTL_SECS);
+        let url = state
+            .blob_store
+            .presign_get(&res.target(), hash, expires_in)
+            .await?
+            .ok_or(BlobError::Unsupported)?;
+        record_activity(state, auth, Activity::Download, None).await;
+
+        Ok(PresignedDownload {
+            url,
+            content_length: res.content_length,
+            compression: res.compression,
+            expire_dt: Utc::now() + ::chrono::Duration::seconds(PRESIGNED_DOWNLOAD_TTL_SECS as i64),
+        })
+    }
+}
+
+/// Downloads a BLOB the user owns, verifying its hash as it's streamed. The response is aborted if
+/// the BLOB doesn't match its hash. See [`crate::resume`] for how dropped downloads are resumed.
+pub struct VerifiedBlobGet {
+    pub content_hash: String,
+    /// The resume token of a dropped download, and the first byte the client still needs.
+    pub resume: Option<(Uuid, u64)>,
+    pub priority: Priority,
+    /// The address the request came from, for checking downloads of canaries.
+    pub ip: Option<String>,
+}
+
+struct CheckpointRow {
+    byte_offset: i64,
+    cv_stack: Vec<u8>,
+}
+
+/// Streams a BLOB from S3 starting at a checkpoint, hashing all of it but only sending the bytes
+/// from `from` on, and checkpointing the hash as it goes.
+struct VerifiedStream {
+    body: ByteStream,
+    hasher: ResumableHasher,
+    expected: Hash,
+    from: u64,
+    length: u64,
+    next_checkpoint: u64,
+    token: Uuid,
+    user_id: Uuid,
+    db_conn: SqlPool,
+    done: bool,
+}
+
+impl VerifiedStream {
+    async fn record_checkpoint(&self) {
+        let checkpoint = match self.hasher.checkpoint() {
+            Some(checkpoint) => checkpoint,
+            None => return,
+        };
+        // A checkpoint which isn't recorded only means a resumed download re-hashes more.
+        let hex = self.expected.to_hex();
+        let res = query!(
+            r#"
+            INSERT INTO download_checkpoints (token, user_id, content_hash, byte_offset, cv_stack)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT DO NOTHING
+            "#,
+            self.token,
+            self.user_id,
+            hex.as_str(),
+            checkpoint.offset as i64,
+            checkpoint.cv_bytes(),
+        )
+        .execute(&self.db_conn)
+        .await;
+        if let Err(e) = res {
+            log::warn!("error recording download checkpoint: {:?}", e);
         }
+    }
 
-        Ok(())
+    async fn next_chunk(&mut self) -> Option<Result<bytes::Bytes, io::Error>> {
+        if self.done {
+            return None;
+        }
+        loop {
+            let bytes = match self.body.next().await {
+                Some(Ok(bytes)) => bytes,
+                Some(Err(e)) => {
+                    self.done = true;
+                    return Some(Err(io::Error::new(io::ErrorKind::Other, e)));
+                }
+                None => {
+                    self.done = true;
+                    if self.hasher.finalize() == self.expected {
+                        return None;
+                    }
+                    log::error!("BLOB {} failed verification", self.expected.to_hex());
+                    return Some(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "BLOB failed verification",
+                    )));
+                }
+            };
+
+            let start = self.hasher.offset();
+            let mut rest = &bytes[..];
+            while !rest.is_empty() {
+                let take = (self.next_checkpoint - self.hasher.offset()).min(rest.len() as u64);
+                self.hasher.update(&rest[..take as usize]);
+                rest = &rest[take as usize..];
+                if self.hasher.offset() == self.next_checkpoint {
+                    // A checkpoint at the very end could never be resumed.
+                    if self.next_checkpoint < self.length {
+                        self.record_checkpoint().await;
+                    }
+                    self.next_checkpoint += CHECKPOINT_INTERVAL;
+                }
+            }
+
+            let skip = self.from.saturating_sub(start).min(bytes.len() as u64) as usize;
+            if skip < bytes.len() {
+                return Some(Ok(bytes.slice(skip..)));
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Query for VerifiedBlobGet {
+    type Resolve = HttpResponse;
+    type Error = BlobError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(BlobError::Unauthorized)?;
+        let permit = start_download(state)?;
+        let expected = Hash::from_hex(&self.content_hash)?;
+        let user_id = user_id(auth, state).await?;
+
+        let res = query!(
+            r#"
+            SELECT id, storage_class, storage_region, storage_bucket, storage_prefix,
+                object_status, canary
+            FROM blobs
+            WHERE content_hash = $1
+                AND user_id = $2
+            "#,
+            self.content_hash,
+            user_id,
+        )
+        .fetch_optional(&state.db_conn)
+        .await?
+        .ok_or(BlobError::Unauthorized)?;
+
+        if res.storage_class != "STANDARD" {
+            return Err(BlobError::Archived);
+        }
+        if res.object_status == BlobStatus::Missing.as_str() {
+            return Err(BlobError::Missing);
+        }
+        // A resumed download was checked when it started.
+        if res.canary && self.resume.is_none() {
+            canary::check_download(state, auth, res.id, self.ip.as_deref()).await;
+        }
+
+        let target = Target {
+            region: res.storage_region,
+            bucket: res.storage_bucket,
+            prefix: res.storage_prefix,
+        };
+        let length = state.blob_store.blob_length(&target, expected).await? as u64;
+
+        let (token, checkpoint, from) = match self.resume {
+            Some((token, from)) => {
+                if from >= length {
+                    return Err(BlobError::InvalidResume);
+                }
+                let row = query_as!(
+                    CheckpointRow,
+                    r#"
+                    SELECT byte_offset, cv_stack
+                    FROM download_checkpoints
+                    WHERE token = $1
+                        AND user_id = $2
+                        AND content_hash = $3
+                        AND byte_offset <= $4
+                        AND create_dt >= current_timestamp - make_interval(hours => $5)
+                    ORDER BY byte_offset DESC
+                    LIMIT 1
+                    "#,
+                    token,
+                    user_id,
+                    self.content_hash,
+                    from as i64,
+                    RESUME_TOKEN_TTL_HOURS,
+                )
+                .fetch_optional(&state.db_conn)
+                .await?
+                .ok_or(BlobError::InvalidResume)?;
+                let checkpoint = Checkpoint::from_cv_bytes(row.byte_offset as u64, &row.cv_stack)
+                    .ok_or(BlobError::InvalidResume)?;
+                (token, checkpoint, from)
+            }
+            None => {
+                // Expired checkpoints are cleared out as new downloads start.
+                query!(
+                    r#"
+                    DELETE FROM download_checkpoints
+                    WHERE create_dt < current_timestamp - make_interval(hours => $1)
+                    "#,
+                    RESUME_TOKEN_TTL_HOURS,
+                )
+                .execute(&state.db_conn)
+                .await?;
+
+                let checkpoint = Checkpoint::start();
+                let token = query_scalar!(
+                    r#"
+                    INSERT INTO download_checkpoints (token, user_id, content_hash, byte_offset,
+                        cv_stack)
+                    VALUES (uuid_generate_v4(), $1, $2, 0, $3)
+                    RETURNING token
+                    "#,
+                    user_id,
+                    self.content_hash,
+                    checkpoint.cv_bytes(),
+                )
+                .fetch_one(&state.db_conn)
+                .await?;
+                (token, checkpoint, 0)
+            }
+        };
+
+        let body = if checkpoint.offset == 0 {
+            state.blob_store.retrieve_blob(&target, expected).await?
+        } else {
+            state
+                .blob_store
+                .retrieve_blob_range(&target, expected, checkpoint.offset, length - 1)
+                .await?
+        };
+        record_activity(state, auth, Activity::Download, None).await;
+
+        let stream = VerifiedStream {
+            body,
+            hasher: ResumableHasher::resume(&checkpoint),
+            expected,
+            from,
+            length,
+            next_checkpoint: (checkpoint.offset / CHECKPOINT_INTERVAL + 1) * CHECKPOINT_INTERVAL,
+            token,
+            user_id,
+            db_conn: state.db_conn.clone(),
+            done: false,
+        };
+        let stream = futures::stream::unfold(stream, |mut s| async move {
+            s.next_chunk().await.map(|item| (item, s))
+        });
+        let bucket = bucket_for(auth, state, Direction::Download, self.priority).await;
+        let stream = throttled(limited(stream, permit), bucket);
+
+        let mut res = if self.resume.is_some() {
+            let mut res = HttpResponseBuilder::new(StatusCode::PARTIAL_CONTENT);
+            res.insert_header((
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", from, length - 1, length),
+            ));
+            res
+        } else {
+            HttpResponseBuilder::new(StatusCode::OK)
+        };
+        res.insert_header((header::ACCEPT_RANGES, "bytes"));
+        res.insert_header((RESUME_TOKEN_HEADER, token.to_string()));
+        Ok(res.body(SizedStream::new(length - from, stream)))
+    }
+}
+
+/// Computes a binary diff from the BLOB `from_hash` to the BLOB `to_hash`, in bsdiff format.
+pub struct BlobDiff {
+    pub from_hash: String,
+    pub to_hash: String,
+}
+
+/// Stores a BLOB uploaded as a binary diff, in bsdiff format, against a BLOB the user already
+/// has. The patched BLOB must match `content_hash`.
+pub struct BlobPatchInsert {
+    pub base_hash: String,
+    pub content_hash: String,
+    pub patch: BlobPayload,
+}
+
+/// Checks that the user owns each of `content_hashes`, none of which is archived, and that none
+/// is too large to diff, returning each one's hash and where it's stored.
+async fn check_diffable(
+    auth: &Auth,
+    state: &State,
+    content_hashes: &[&str],
+) -> Result<Vec<(Hash, Target)>, BlobError> {
+    let mut hashes = vec![];
+    for content_hash in content_hashes {
+        let hash = Hash::from_hex(content_hash)?;
+
+        let res = query!(
+            r#"
+            SELECT storage_class, storage_region, storage_bucket, storage_prefix, object_status
+            FROM blobs
+            WHERE content_hash = $1
+                AND user_id = get_user_id($2, $3)
+            "#,
+            content_hash,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_optional(&state.db_conn)
+        .await?
+        .ok_or(BlobError::NotFound)?;
+
+        if res.storage_class != "STANDARD" {
+            return Err(BlobError::Archived);
+        }
+        if res.object_status == BlobStatus::Missing.as_str() {
+            return Err(BlobError::Missing);
+        }
+        let target = Target {
+            region: res.storage_region,
+            bucket: res.storage_bucket,
+            prefix: res.storage_prefix,
+        };
+        if state.blob_store.blob_length(&target, hash).await? > MAX_DIFF_BLOB_LEN {
+            return Err(BlobError::TooLarge);
+        }
+
+        hashes.push((hash, target));
+    }
+
+    Ok(hashes)
+}
+
+#[async_trait]
+impl Query for BlobDiff {
+    type Resolve = Vec<u8>;
+    type Error = BlobError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(BlobError::Unauthorized)?;
+
+        let hashes = check_diffable(auth, state, &[&self.from_hash, &self.to_hash]).await?;
+        let (from_hash, from_target) = &hashes[0];
+        let (to_hash, to_target) = &hashes[1];
+        let from = state
+            .blob_store
+            .retrieve_blob_bytes(from_target, *from_hash)
+            .await?;
+        let to = state
+            .blob_store
+            .retrieve_blob_bytes(to_target, *to_hash)
+            .await?;
+        record_activity(state, auth, Activity::Download, None).await;
+
+        // Diffing is CPU bound, so keep it off the async workers.
+        let patch = web::block(move || {
+            let mut patch = vec![];
+            Bsdiff::new(&from, &to)
+                .compare(Cursor::new(&mut patch))
+                .map(|_| patch)
+        })
+        .await
+        .map_err(|_| BlobError::StoreError)?
+        .map_err(|e| {
+            log::error!("error diffing blobs: {:?}", e);
+            BlobError::StoreError
+        })?;
+
+        Ok(patch)
+    }
+}
+
+#[async_trait]
+impl Persist for BlobPatchInsert {
+    type Ret = i64;
+    type Error = BlobError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(BlobError::Unauthorized)?;
+
+        let hash_claim = Hash::from_hex(&self.content_hash)?;
+        let base_hash = resolve_address(&state.db_conn, &self.base_hash).await?;
+        let (base_hash, base_target) = check_diffable(auth, state, &[&base_hash]).await?.remove(0);
+
+        let mut patch = bytes::BytesMut::new();
+        let mut payload = self.patch;
+        while let Some(chunk) = payload.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(WithBlobError::Stalled) => {
+                    record_activity(state, auth, Activity::StalledUpload, None).await;
+                    return Err(BlobError::Stalled);
+                }
+                Err(_) => return Err(BlobError::InvalidPatch),
+            };
+            if (patch.len() + chunk.len()) as i64 > MAX_DIFF_BLOB_LEN {
+                return Err(BlobError::TooLarge);
+            }
+            patch.extend_from_slice(&chunk);
+        }
+
+        let base = state
+            .blob_store
+            .retrieve_blob_bytes(&base_target, base_hash)
+            .await?;
+
+        let patched = web::block(move || {
+            let patcher = Bspatch::new(&patch).map_err(|_| BlobError::InvalidPatch)?;
+            // The patch declares the size of the result; don't allocate for an unreasonable one.
+            if patcher.hint_target_size() > MAX_DIFF_BLOB_LEN as u64 {
+                return Err(BlobError::TooLarge);
+            }
+            let mut patched = Vec::with_capacity(patcher.hint_target_size() as usize);
+            patcher
+                .apply(&base, Cursor::new(&mut patched))
+                .map_err(|_| BlobError::InvalidPatch)?;
+            Ok(bytes::Bytes::from(patched))
+        })
+        .await
+        .map_err(|_| BlobError::StoreError)??;
+
+        if blake3::hash(&patched) != hash_claim {
+            record_activity(state, auth, Activity::InvalidHash, None).await;
+            return Err(BlobError::InvalidHash);
+        }
+
+        let target = upload_target(auth, state).await?;
+        // The patched BLOB adds to what's stored as any other upload does, unless it's stored
+        // already.
+        if state.config.storage_quota_bytes.is_some()
+            && shared_object(&target, &self.content_hash, state)
+                .await?
+                .is_none()
+        {
+            let user_id = user_id(auth, state).await?;
+            if !within_storage_quota(state, user_id, patched.len() as i64).await? {
+                return Err(BlobError::QuotaExceeded);
+            }
+        }
+        let (target, stored) = store_shared(state, target, hash_claim, patched).await?;
+
+        let mut tx = state.db_conn.begin().await?;
+        let id = upsert_blob(&mut tx, auth, &self.content_hash, &target, stored).await?;
+        tx.commit().await?;
+
+        Ok(id)
+    }
+}
+
+/// Looks up the stats of a BLOB the user owns.
+pub struct BlobStatsGet {
+    pub content_hash: String,
+}
+
+#[async_trait]
+impl Query for BlobStatsGet {
+    type Resolve = BlobStats;
+    type Error = BlobStatsError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(BlobStatsError::Unauthorized)?;
+
+        let res = query_as!(
+            BlobStats,
+            r#"
+            SELECT coalesce(s.status, 'pending') AS "status!", s.format AS "format?",
+                s.row_count AS "row_count?", s.columns AS "columns?", s.error AS "error?",
+                s.create_dt AS "create_dt?"
+            FROM blobs b
+            LEFT JOIN blob_stats s
+                ON s.content_hash = b.content_hash
+            WHERE b.content_hash = $1
+                AND b.user_id = get_user_id($2, $3)
+            "#,
+            self.content_hash,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+/// Looks up the tensor summaries of a BLOB the user owns.
+pub struct TensorSummariesGet {
+    pub content_hash: String,
+}
+
+#[async_trait]
+impl Query for TensorSummariesGet {
+    type Resolve = TensorSummaries;
+    type Error = BlobStatsError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(BlobStatsError::Unauthorized)?;
+
+        let res = query_as!(
+            TensorSummaries,
+            r#"
+            SELECT coalesce(s.status, 'pending') AS "status!", s.format AS "format?",
+                s.tensors AS "tensors?", s.error AS "error?", s.create_dt AS "create_dt?"
+            FROM blobs b
+            LEFT JOIN tensor_summaries s
+                ON s.content_hash = b.content_hash
+            WHERE b.content_hash = $1
+                AND b.user_id = get_user_id($2, $3)
+            "#,
+            self.content_hash,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(res)
     }
 }
 
 pub enum BlobError {
     Unauthorized,
     NotFound,
+    Archived,
     InvalidHash,
+    /// The BLOB, or the patch, is too large to diff or patch.
+    TooLarge,
+    /// The patch isn't a valid bsdiff patch for the base BLOB.
+    InvalidPatch,
+    /// Storing the BLOB would take the user over the storage quota.
+    QuotaExceeded,
+    /// The BLOB is recorded, but its object has gone missing from storage. Uploading it again
+    /// restores it.
+    Missing,
+    /// The resume token is unknown or has expired, or the download can't be resumed from the
+    /// requested byte.
+    InvalidResume,
+    /// The `Range` header is malformed, or asks for bytes past the end of the BLOB.
+    InvalidRange,
+    /// The upload was aborted because no bytes arrived for too long.
+    Stalled,
+    /// BLOBs are stored where they can't be downloaded without going through the server.
+    Unsupported,
+    /// The mime type given with the BLOB can't be parsed, or its label is too long.
+    InvalidMetadata,
+    /// The server is already serving as many downloads as it can. The client should retry after
+    /// this many milliseconds.
+    Busy(u64),
     StoreError,
     Sqlx(sqlx::Error),
 }
@@ -177,8 +1334,17 @@ impl From<BlobError> for StoreError {
             BlobError::Unauthorized => StoreError::Unauthorized,
             BlobError::InvalidHash => StoreError::InvalidHash,
             BlobError::NotFound => StoreError::NotFound,
+            BlobError::Archived | BlobError::Missing => StoreError::NotFound,
+            BlobError::TooLarge
+            | BlobError::InvalidPatch
+            | BlobError::InvalidResume
+            | BlobError::InvalidRange
+            | BlobError::Unsupported
+            | BlobError::InvalidMetadata => StoreError::InvalidQuery,
             // ...especially this!
-            BlobError::StoreError => StoreError::Unauthorized,
+            BlobError::Stalled => StoreError::WithBlob(WithBlobError::Stalled),
+            BlobError::QuotaExceeded => StoreError::QuotaExceeded,
+            BlobError::Busy(_) | BlobError::StoreError => StoreError::Unauthorized,
             BlobError::Sqlx(e) => StoreError::Sqlx(e),
         }
     }
@@ -194,9 +1360,40 @@ impl From<BlobError> for Error {
     fn from(e: BlobError) -> Self {
         match e {
             BlobError::Unauthorized => error::ErrorUnauthorized("unauthorized access"),
-            BlobError::InvalidHash => error::ErrorBadRequest("invalid hash"),
-            BlobError::NotFound => error::ErrorNotFound("resource not found"),
-            BlobError::StoreError => error::ErrorInternalServerError("could not retrieve blob"),
+            BlobError::InvalidHash => coded(ErrorCode::InvalidHash, "invalid hash"),
+            BlobError::NotFound => coded(ErrorCode::BlobNotFound, "resource not found"),
+            BlobError::Archived => coded(
+                ErrorCode::BlobArchived,
+                "blob is archived; unarchive the run to retrieve it",
+            ),
+            BlobError::Missing => coded(
+                ErrorCode::BlobMissing,
+                "blob is missing from storage; upload it again",
+            ),
+            BlobError::TooLarge => coded(ErrorCode::BlobTooLarge, "blob is too large to diff"),
+            BlobError::InvalidPatch => coded(ErrorCode::InvalidPatch, "invalid patch"),
+            BlobError::QuotaExceeded => {
+                coded(ErrorCode::QuotaExceeded, "blob would exceed storage quota")
+            }
+            BlobError::InvalidResume => coded(
+                ErrorCode::InvalidResume,
+                "download can't be resumed; start it again",
+            ),
+            BlobError::InvalidRange => coded(ErrorCode::InvalidRange, "invalid range"),
+            BlobError::Stalled => WithBlobError::Stalled.into(),
+            BlobError::Unsupported => coded(
+                ErrorCode::DirectDownloadUnsupported,
+                "blobs can't be downloaded straight from storage here",
+            ),
+            BlobError::InvalidMetadata => {
+                coded(ErrorCode::InvalidBlobMetadata, "invalid mime type or label")
+            }
+            BlobError::Busy(retry_after_ms) => coded_retry_after(
+                ErrorCode::DownloadsBusy,
+                "server is busy serving downloads; retry later",
+                retry_after_ms,
+            ),
+            BlobError::StoreError => coded(ErrorCode::BlobStoreError, "could not retrieve blob"),
             BlobError::Sqlx(_) => error::ErrorInternalServerError("could not retrieve blob"),
         }
     }
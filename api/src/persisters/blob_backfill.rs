@@ -0,0 +1,155 @@
+use crate::middlewares::auth::Auth;
+use crate::models::blob_backfill::{BackfillError, BackfillStatus, Discrepancy, BACKFILL_LOCK};
+use crate::persisters::user::is_admin;
+use crate::persisters::{Persist, Query};
+use crate::state::State;
+
+async fn require_admin(auth: Option<&Auth>, state: &State) -> Result<(), BackfillError> {
+    let auth = auth.ok_or(BackfillError::Unauthorized)?;
+
+    if !is_admin(auth, state).await? {
+        return Err(BackfillError::Forbidden);
+    }
+
+    Ok(())
+}
+
+/// Whether a backfill currently holds the lock.
+async fn is_running(state: &State) -> Result<bool, sqlx::Error> {
+    // A bigint advisory lock key is split across `classid` (high half) and `objid` (low half).
+    query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM pg_locks
+            WHERE locktype = 'advisory'
+                AND objsubid = 1
+                AND (classid::bigint << 32) | objid::bigint = $1
+        ) AS "running!"
+        "#,
+        BACKFILL_LOCK,
+    )
+    .fetch_one(&state.db_conn)
+    .await
+}
+
+/// Checks that a backfill can be started. The backfill itself is run by the caller, in the
+/// background.
+pub struct BackfillStart {}
+
+pub struct BackfillStatusGet {}
+
+fn default_limit() -> i64 {
+    100
+}
+
+/// Lists discrepancies found by the backfill, most recent first.
+#[derive(Deserialize, Debug)]
+pub struct DiscrepanciesGet {
+    /// Only list discrepancies which have (or haven't) been resolved.
+    pub resolved: Option<bool>,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+/// Marks a discrepancy as dealt with.
+pub struct DiscrepancyResolve {
+    pub id: i64,
+}
+
+#[async_trait]
+impl Persist for BackfillStart {
+    type Ret = ();
+    type Error = BackfillError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        require_admin(auth, state).await?;
+
+        if is_running(state).await? {
+            return Err(BackfillError::AlreadyRunning);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Query for BackfillStatusGet {
+    type Resolve = BackfillStatus;
+    type Error = BackfillError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        require_admin(auth, state).await?;
+
+        let running = is_running(state).await?;
+        let res = query!(
+            r#"
+            SELECT
+                (SELECT count(*) FROM blobs WHERE verified_dt IS NULL) AS "unverified!",
+                (SELECT count(*) FROM blob_discrepancies WHERE resolved_dt IS NULL)
+                    AS "unresolved!"
+            "#,
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(BackfillStatus {
+            running,
+            unverified: res.unverified,
+            unresolved: res.unresolved,
+        })
+    }
+}
+
+#[async_trait]
+impl Query for DiscrepanciesGet {
+    type Resolve = Vec<Discrepancy>;
+    type Error = BackfillError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        require_admin(auth, state).await?;
+
+        let res = query_as!(
+            Discrepancy,
+            r#"
+            SELECT id, content_hash, kind, recorded, actual, create_dt, resolved_dt
+            FROM blob_discrepancies
+            WHERE ($1::boolean IS NULL OR (resolved_dt IS NOT NULL) = $1)
+            ORDER BY create_dt DESC
+            LIMIT $2
+            "#,
+            self.resolved,
+            self.limit,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Persist for DiscrepancyResolve {
+    type Ret = ();
+    type Error = BackfillError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        require_admin(auth, state).await?;
+
+        let res = query!(
+            r#"
+            UPDATE blob_discrepancies
+            SET resolved_dt = coalesce(resolved_dt, current_timestamp)
+            WHERE id = $1
+            "#,
+            self.id,
+        )
+        .execute(&state.db_conn)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(BackfillError::NotFound);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+//! Where BLOBs are stored. The server stores them in S3 ([`S3Store`]), or, for self-hosting and
+//! tests, in a directory ([`FsStore`]), as `BLOB_STORE_DIR` says.
+//!
+//! [`S3Store`]: crate::persisters::s3store::S3Store
+//! [`FsStore`]: crate::persisters::fs_store::FsStore
+use crate::extractors::with_blob::WithBlobError;
+use crate::persisters::s3store::{StoreError, Stored, Target};
+
+use aws_sdk_s3::{model::StorageClass, types::ByteStream};
+use blake3::{Hash, Hasher};
+use futures::stream::{BoxStream, StreamExt};
+use sqlx::types::Uuid;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// A BLOB received as it's uploaded.
+pub type Payload = BoxStream<'static, Result<bytes::Bytes, WithBlobError>>;
+
+/// A request which uploads an object straight to storage, without going through the server.
+#[derive(Serialize, Debug)]
+pub struct PresignedPut {
+    pub url: String,
+    /// Headers which have to be sent with the request, as they were signed.
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Storage of BLOBs, addressed by where they're stored and their content hash.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Checks that the target's bucket exists and can be reached, before BLOBs are pinned to it.
+    async fn check_target(&self, target: &Target) -> Result<(), StoreError>;
+
+    /// Stores a BLOB as it's received, checking that it's the BLOB claimed.
+    async fn store_blob(
+        &self,
+        target: &Target,
+        payload: Payload,
+        hash_claim: Hash,
+        content_length: i64,
+    ) -> Result<Stored, StoreError>;
+
+    /// Stores a BLOB which has already been received in full. The caller is responsible for
+    /// computing `content_hash` from `bytes`.
+    async fn store_bytes(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        bytes: bytes::Bytes,
+    ) -> Result<Stored, StoreError>;
+
+    /// Starts a multipart upload of a BLOB, returning the upload's id. The BLOB isn't stored until
+    /// the upload is completed, and the parts uploaded so far are kept until it's aborted.
+    async fn create_multipart_upload(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<String, StoreError>;
+
+    /// Uploads a part of a multipart upload, returning the part's ETag, which is needed to
+    /// complete the upload. Uploading a part again replaces it.
+    async fn upload_part(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        upload_id: &str,
+        part_number: i32,
+        bytes: bytes::Bytes,
+    ) -> Result<String, StoreError>;
+
+    /// Completes a multipart upload, storing the BLOB from its parts. `e_tags` are those of parts
+    /// `1..=e_tags.len()`, in order.
+    async fn complete_multipart_upload(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        upload_id: &str,
+        e_tags: &[String],
+    ) -> Result<(), StoreError>;
+
+    /// Aborts a multipart upload, deleting the parts uploaded so far.
+    async fn abort_multipart_upload(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        upload_id: &str,
+    ) -> Result<(), StoreError>;
+
+    /// Retrieves the BLOB, as it was before any compression at rest.
+    async fn retrieve_blob(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<ByteStream, StoreError>;
+
+    /// Retrieves the bytes `first..=last` of the BLOB.
+    async fn retrieve_blob_range(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        first: u64,
+        last: u64,
+    ) -> Result<ByteStream, StoreError>;
+
+    /// Returns the length, in bytes, of the stored BLOB.
+    async fn blob_length(&self, target: &Target, content_hash: Hash) -> Result<i64, StoreError>;
+
+    /// Moves the BLOB to a different storage class.
+    async fn set_storage_class(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        storage_class: StorageClass,
+    ) -> Result<(), StoreError>;
+
+    /// Asks for a temporary copy of an archived BLOB to be restored, which remains readable for
+    /// `days` days. Restoration may happen asynchronously; poll `restore_complete` to find out
+    /// when it's done.
+    async fn request_restore(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        days: i32,
+    ) -> Result<(), StoreError>;
+
+    /// Whether a restore requested by `request_restore` has finished.
+    async fn restore_complete(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<bool, StoreError>;
+
+    /// Stores an object which isn't a BLOB, such as a manifest, under `key`, after the server's key
+    /// prefix. Keys of other objects must not look like content hashes.
+    async fn store_object(&self, key: &str, bytes: bytes::Bytes) -> Result<(), StoreError>;
+
+    /// Retrieves an object stored by `store_object` into memory.
+    async fn retrieve_object(&self, key: &str) -> Result<bytes::Bytes, StoreError>;
+
+    /// Returns the length, in bytes, of the stored BLOB and when it was last written, in seconds
+    /// since the Unix epoch, if that's known, or `None` when nothing is stored under the hash.
+    async fn find_blob_written(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<Option<(i64, Option<i64>)>, StoreError>;
+
+    /// Lists a page of the objects stored under the target's prefix, as their keys and lengths,
+    /// along with where the next page starts, if there's one.
+    async fn list_objects(
+        &self,
+        target: &Target,
+        continuation: Option<String>,
+    ) -> Result<(Vec<(String, i64)>, Option<String>), StoreError>;
+
+    /// Deletes the stored BLOB. Deleting a BLOB which isn't stored isn't an error.
+    async fn delete_blob(&self, target: &Target, content_hash: Hash) -> Result<(), StoreError>;
+
+    /// Presigns a GET of the BLOB's object, valid for `expires_in`, or returns `None` if the store
+    /// can't be downloaded from directly. The object is as stored, so it's compressed if the BLOB
+    /// was compressed at rest. No headers need sending with it.
+    async fn presign_get(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        expires_in: Duration,
+    ) -> Result<Option<String>, StoreError>;
+
+    /// Presigns a PUT of `content_length` bytes to the staging key of an upload, valid for
+    /// `expires_in`, or returns `None` if the store can't be uploaded to directly.
+    async fn presign_staged_put(
+        &self,
+        target: &Target,
+        upload_id: Uuid,
+        content_length: i64,
+        expires_in: Duration,
+    ) -> Result<Option<PresignedPut>, StoreError>;
+
+    /// Retrieves what was uploaded to the staging key of an upload.
+    async fn retrieve_staged(
+        &self,
+        target: &Target,
+        upload_id: Uuid,
+    ) -> Result<ByteStream, StoreError>;
+
+    /// Moves what was uploaded to the staging key of an upload to the key of the BLOB, once it's
+    /// been checked to be the BLOB.
+    async fn promote_staged(
+        &self,
+        target: &Target,
+        upload_id: Uuid,
+        content_hash: Hash,
+    ) -> Result<(), StoreError>;
+
+    /// Deletes what was uploaded to the staging key of an upload. Deleting an object which isn't
+    /// there isn't an error.
+    async fn delete_staged(&self, target: &Target, upload_id: Uuid) -> Result<(), StoreError>;
+
+    /// Retrieves the whole BLOB into memory.
+    async fn retrieve_blob_bytes(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<bytes::Bytes, StoreError> {
+        let bytes = self
+            .retrieve_blob(target, content_hash)
+            .await?
+            .collect()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?
+            .into_bytes();
+
+        Ok(bytes)
+    }
+
+    /// Retrieves the first `len` bytes of a BLOB which is `blob_len` bytes long into memory.
+    async fn retrieve_blob_prefix(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        len: usize,
+        blob_len: i64,
+    ) -> Result<bytes::Bytes, StoreError> {
+        let last = (len as i64).min(blob_len) - 1;
+        let bytes = self
+            .retrieve_blob_range(target, content_hash, 0, last.max(0) as u64)
+            .await?
+            .collect()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?
+            .into_bytes();
+
+        Ok(bytes)
+    }
+
+    /// Returns the length, in bytes, of the stored BLOB, or `None` when nothing is stored under
+    /// the hash. Unlike `blob_length`, a missing BLOB isn't an error.
+    async fn find_blob(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<Option<i64>, StoreError> {
+        let found = self.find_blob_written(target, content_hash).await?;
+        Ok(found.map(|(length, _)| length))
+    }
+
+    /// Streams the stored BLOB, returning its blake3 hash and length as actually stored.
+    async fn hash_blob(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<(Hash, i64), StoreError> {
+        let mut stream = self.retrieve_blob(target, content_hash).await?;
+        let mut hasher = Hasher::new();
+        let mut len = 0;
+        while let Some(chunk) = stream.next().await {
+            let chunk = chunk.map_err(|e| StoreError::S3Other(Box::new(e)))?;
+            hasher.update(&chunk);
+            len += chunk.len() as i64;
+        }
+
+        Ok((hasher.finalize(), len))
+    }
+}
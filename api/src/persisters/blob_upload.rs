@@ -0,0 +1,398 @@
+//! Uploads of BLOBs in parts, which a client on a flaky connection can resume from the last part
+//! received rather than sending the whole BLOB again.
+//!
+//! Each upload is an S3 multipart upload. Parts are received in order, and the BLOB's hash is
+//! checkpointed after each one (see [`crate::resume`]), so that the BLOB is verified against its
+//! claimed hash by the time its last part arrives, without any part being held onto or read back.
+use crate::middlewares::auth::Auth;
+use crate::models::anomaly::Activity;
+use crate::models::blob_upload::{
+    part_count, part_range, BlobUpload, BlobUploadError, MAX_UPLOAD_PARTS, UPLOAD_PART_SIZE,
+    UPLOAD_TTL_HOURS,
+};
+use crate::models::dead_letter::DeadLetterOp;
+use crate::models::SqlDateTime;
+use crate::persisters::anomaly::record_activity;
+use crate::persisters::blob::upsert_blob;
+use crate::persisters::dead_letter::{self, DeadLetterInsert};
+use crate::persisters::s3store::{shared_object, upload_target, Stored, Target};
+use crate::persisters::user::user_id;
+use crate::persisters::{Persist, Query};
+use crate::resume::{Checkpoint, ResumableHasher};
+use crate::state::State;
+use blake3::Hash;
+use sqlx::types::Uuid;
+
+/// Starts an upload of a BLOB, or picks up the user's unexpired upload of it if there is one.
+#[derive(Deserialize, Debug)]
+pub struct BlobUploadStart {
+    pub content_hash: String,
+    pub content_length: i64,
+}
+
+/// How far an upload has got.
+pub struct BlobUploadGet {
+    pub id: Uuid,
+}
+
+/// A part of an upload. A part which was already received is acknowledged without being stored
+/// again, so that a client can retry a part whose response it didn't get.
+pub struct BlobUploadPart {
+    pub id: Uuid,
+    pub part_number: i32,
+    pub bytes: bytes::Bytes,
+}
+
+/// Completes an upload once all of its parts are received, returning the BLOB's id.
+pub struct BlobUploadComplete {
+    pub id: Uuid,
+}
+
+struct UploadRow {
+    id: Uuid,
+    content_hash: String,
+    content_length: i64,
+    storage_region: Option<String>,
+    storage_bucket: Option<String>,
+    storage_prefix: Option<String>,
+    s3_upload_id: String,
+    parts_received: i32,
+    part_etags: Vec<String>,
+    cv_stack: Vec<u8>,
+    expire_dt: SqlDateTime,
+}
+
+impl UploadRow {
+    fn status(&self) -> BlobUpload {
+        BlobUpload {
+            id: self.id,
+            content_hash: self.content_hash.clone(),
+            content_length: self.content_length,
+            part_size: UPLOAD_PART_SIZE,
+            part_count: part_count(self.content_length),
+            parts_received: self.parts_received,
+            expire_dt: self.expire_dt,
+        }
+    }
+
+    fn target(&self) -> Target {
+        Target {
+            region: self.storage_region.clone(),
+            bucket: self.storage_bucket.clone(),
+            prefix: self.storage_prefix.clone(),
+        }
+    }
+}
+
+async fn fetch_upload(
+    state: &State,
+    user_id: Uuid,
+    id: Uuid,
+) -> Result<UploadRow, BlobUploadError> {
+    let upload = query_as!(
+        UploadRow,
+        r#"
+        SELECT id, content_hash, content_length, storage_region, storage_bucket, storage_prefix,
+            s3_upload_id, parts_received, part_etags, cv_stack, expire_dt
+        FROM blob_uploads
+        WHERE id = $1 AND user_id = $2 AND expire_dt > now()
+        "#,
+        id,
+        user_id,
+    )
+    .fetch_optional(&state.db_conn)
+    .await?
+    .ok_or(BlobUploadError::NotFound)?;
+
+    Ok(upload)
+}
+
+/// Aborts an upload, deleting the parts received so far.
+async fn abandon(state: &State, upload: &UploadRow) -> Result<(), BlobUploadError> {
+    let hash = Hash::from_hex(&upload.content_hash)?;
+    state
+        .blob_store
+        .abort_multipart_upload(&upload.target(), hash, &upload.s3_upload_id)
+        .await?;
+    query!("DELETE FROM blob_uploads WHERE id = $1", upload.id)
+        .execute(&state.db_conn)
+        .await?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl Persist for BlobUploadStart {
+    type Ret = BlobUpload;
+    type Error = BlobUploadError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(BlobUploadError::Unauthorized)?;
+        let hash = Hash::from_hex(&self.content_hash)?;
+        let hex = hash.to_hex();
+        if self.content_length < 0 || part_count(self.content_length) > MAX_UPLOAD_PARTS {
+            return Err(BlobUploadError::InvalidLength);
+        }
+
+        let user_id = user_id(auth, state).await?;
+        let existing = query_as!(
+            UploadRow,
+            r#"
+            SELECT id, content_hash, content_length, storage_region, storage_bucket,
+                storage_prefix, s3_upload_id, parts_received, part_etags, cv_stack, expire_dt
+            FROM blob_uploads
+            WHERE user_id = $1 AND content_hash = $2 AND content_length = $3
+                AND expire_dt > now()
+            ORDER BY create_dt DESC
+            LIMIT 1
+            "#,
+            user_id,
+            hex.as_str(),
+            self.content_length,
+        )
+        .fetch_optional(&state.db_conn)
+        .await?;
+        if let Some(upload) = existing {
+            return Ok(upload.status());
+        }
+
+        let target = upload_target(auth, state).await?;
+        let s3_upload_id = state
+            .blob_store
+            .create_multipart_upload(&target, hash)
+            .await?;
+        let upload = query_as!(
+            UploadRow,
+            r#"
+            INSERT INTO blob_uploads (user_id, content_hash, content_length, storage_region,
+                storage_bucket, storage_prefix, s3_upload_id, expire_dt)
+            VALUES ($1, $2, $3, $4, $5, $8, $6, now() + make_interval(hours => $7))
+            RETURNING id, content_hash, content_length, storage_region, storage_bucket,
+                storage_prefix, s3_upload_id, parts_received, part_etags, cv_stack, expire_dt
+            "#,
+            user_id,
+            hex.as_str(),
+            self.content_length,
+            target.region,
+            target.bucket,
+            s3_upload_id,
+            UPLOAD_TTL_HOURS,
+            target.prefix,
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(upload.status())
+    }
+}
+
+#[async_trait]
+impl Query for BlobUploadGet {
+    type Resolve = BlobUpload;
+    type Error = BlobUploadError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(BlobUploadError::Unauthorized)?;
+        let user_id = user_id(auth, state).await?;
+        let upload = fetch_upload(state, user_id, self.id).await?;
+
+        Ok(upload.status())
+    }
+}
+
+#[async_trait]
+impl Persist for BlobUploadPart {
+    type Ret = BlobUpload;
+    type Error = BlobUploadError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(BlobUploadError::Unauthorized)?;
+        let user_id = user_id(auth, state).await?;
+        let upload = fetch_upload(state, user_id, self.id).await?;
+
+        let (offset, len) = part_range(upload.content_length, self.part_number)
+            .ok_or(BlobUploadError::InvalidPart)?;
+        let next_part = upload.parts_received + 1;
+        if self.part_number < next_part {
+            return Ok(upload.status());
+        }
+        if self.part_number > next_part {
+            return Err(BlobUploadError::OutOfOrder { next_part });
+        }
+        if self.bytes.len() as i64 != len {
+            return Err(BlobUploadError::InvalidPart);
+        }
+
+        let hash = Hash::from_hex(&upload.content_hash)?;
+        let checkpoint = Checkpoint::from_cv_bytes(offset as u64, &upload.cv_stack)
+            .ok_or_else(|| sqlx::Error::Decode("invalid upload checkpoint".into()))?;
+        let mut hasher = ResumableHasher::resume(&checkpoint);
+        hasher.update(&self.bytes);
+        let cv_stack = if self.part_number as i64 == part_count(upload.content_length) {
+            if hasher.finalize() != hash {
+                record_activity(state, auth, Activity::InvalidHash, None).await;
+                // If it can't be aborted now, it's aborted once it expires.
+                if let Err(e) = abandon(state, &upload).await {
+                    log::warn!("error aborting upload {}: {:?}", upload.id, e);
+                }
+                return Err(BlobUploadError::InvalidHash);
+            }
+            vec![]
+        } else {
+            // Parts are a whole number of chunks long, so the hash can always be checkpointed.
+            hasher
+                .checkpoint()
+                .map(|c| c.cv_bytes())
+                .unwrap_or_default()
+        };
+
+        let e_tag = state
+            .blob_store
+            .upload_part(
+                &upload.target(),
+                hash,
+                &upload.s3_upload_id,
+                self.part_number,
+                self.bytes,
+            )
+            .await?;
+
+        // If a concurrent retry of the part got here first, its record of the part stands. S3
+        // keeps whichever copy of the part was uploaded last, and won't complete the upload if
+        // that isn't the copy recorded here, so a BLOB is never stored from parts it wasn't
+        // hashed from.
+        let updated = query_as!(
+            UploadRow,
+            r#"
+            UPDATE blob_uploads
+            SET parts_received = $3,
+                part_etags = array_append(part_etags, $4),
+                cv_stack = $5,
+                expire_dt = now() + make_interval(hours => $6)
+            WHERE id = $1 AND user_id = $2 AND parts_received = $3 - 1
+            RETURNING id, content_hash, content_length, storage_region, storage_bucket,
+                storage_prefix, s3_upload_id, parts_received, part_etags, cv_stack, expire_dt
+            "#,
+            upload.id,
+            user_id,
+            self.part_number,
+            e_tag,
+            cv_stack,
+            UPLOAD_TTL_HOURS,
+        )
+        .fetch_optional(&state.db_conn)
+        .await?;
+
+        match updated {
+            Some(upload) => Ok(upload.status()),
+            None => Ok(fetch_upload(state, user_id, self.id).await?.status()),
+        }
+    }
+}
+
+#[async_trait]
+impl Persist for BlobUploadComplete {
+    type Ret = i64;
+    type Error = BlobUploadError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(BlobUploadError::Unauthorized)?;
+        let user_id = user_id(auth, state).await?;
+        let upload = fetch_upload(state, user_id, self.id).await?;
+
+        if (upload.parts_received as i64) < part_count(upload.content_length) {
+            return Err(BlobUploadError::Incomplete {
+                next_part: upload.parts_received + 1,
+            });
+        }
+
+        // The hash was checked as the last part arrived. If the BLOB has been stored by someone
+        // else meanwhile, their object is shared and the parts are thrown away. Uploads in parts
+        // are stored as they are.
+        let hash = Hash::from_hex(&upload.content_hash)?;
+        let (target, stored) =
+            match shared_object(&upload.target(), &upload.content_hash, state).await? {
+                Some(shared) => {
+                    let abort = state
+                        .blob_store
+                        .abort_multipart_upload(&upload.target(), hash, &upload.s3_upload_id)
+                        .await;
+                    if let Err(e) = abort {
+                        log::warn!("could not abort upload {}: {:?}", upload.id, e);
+                    }
+                    (shared, None)
+                }
+                None => {
+                    let target = upload.target();
+                    state
+                        .blob_store
+                        .complete_multipart_upload(
+                            &target,
+                            hash,
+                            &upload.s3_upload_id,
+                            &upload.part_etags,
+                        )
+                        .await?;
+                    (target, Some(Stored::raw(upload.content_length)))
+                }
+            };
+
+        match record_blob(auth, &upload, &target, stored, state).await {
+            Ok(id) => Ok(id),
+            Err(e) => {
+                let letter = DeadLetterInsert {
+                    content_hash: upload.content_hash.clone(),
+                    target,
+                    op: DeadLetterOp::Blob,
+                    error: e.to_string(),
+                };
+                dead_letter::record(state, auth, letter).await;
+                Err(e.into())
+            }
+        }
+    }
+}
+
+/// Records a completed upload's BLOB, once it's stored, and closes the upload.
+async fn record_blob(
+    auth: &Auth,
+    upload: &UploadRow,
+    target: &Target,
+    stored: Option<Stored>,
+    state: &State,
+) -> Result<i64, sqlx::Error> {
+    let mut tx = state.db_conn.begin().await?;
+    let id = upsert_blob(&mut tx, auth, &upload.content_hash, target, stored).await?;
+    query!("DELETE FROM blob_uploads WHERE id = $1", upload.id)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(id)
+}
+
+/// Aborts uploads which have expired, returning how many were aborted. Uploads whose S3 upload
+/// can't be aborted are left to be tried again.
+pub async fn prune_expired(state: &State) -> Result<u64, BlobUploadError> {
+    let expired = query_as!(
+        UploadRow,
+        r#"
+        SELECT id, content_hash, content_length, storage_region, storage_bucket, storage_prefix,
+            s3_upload_id, parts_received, part_etags, cv_stack, expire_dt
+        FROM blob_uploads
+        WHERE expire_dt <= now()
+        "#,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    let mut pruned = 0;
+    for upload in expired {
+        match abandon(state, &upload).await {
+            Ok(()) => pruned += 1,
+            Err(e) => log::warn!("error aborting expired upload {}: {:?}", upload.id, e),
+        }
+    }
+
+    Ok(pruned)
+}
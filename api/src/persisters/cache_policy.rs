@@ -0,0 +1,161 @@
+use crate::middlewares::auth::Auth;
+use crate::models::cache_policy::{CacheMode, CachePolicy, CachePolicyError, MAX_POLICY_FN_KEYS};
+use crate::persisters::{Persist, Query};
+use crate::policy::{self, Action, Request};
+use crate::state::State;
+
+/// Looks up the caching policies of a batch of functions. `fn_keys` is a JSON array of function
+/// keys (e.g. `["mod:train", "mod:evaluate"]`). Functions without a policy are left out.
+#[derive(Deserialize, Debug)]
+pub struct CachePoliciesGet {
+    pub fn_keys: String,
+}
+
+/// Sets the caching policy of a function, replacing any it had.
+#[derive(Deserialize, Debug)]
+pub struct CachePolicyPut {
+    pub fn_key: String,
+    pub mode: CacheMode,
+    #[serde(default)]
+    pub ttl_secs: Option<i64>,
+    #[serde(default)]
+    pub max_result_bytes: Option<i64>,
+}
+
+/// Removes the caching policy of a function, so that it's cached as usual.
+#[derive(Deserialize, Debug)]
+pub struct CachePolicyDelete {
+    pub fn_key: String,
+}
+
+#[async_trait]
+impl Query for CachePoliciesGet {
+    type Resolve = Vec<CachePolicy>;
+    type Error = CachePolicyError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(CachePolicyError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::EvalRead), state).await?;
+
+        let fn_keys = serde_json::from_str::<Vec<String>>(&self.fn_keys)
+            .map_err(|_| CachePolicyError::InvalidQuery)?;
+        if fn_keys.len() > MAX_POLICY_FN_KEYS {
+            return Err(CachePolicyError::InvalidQuery);
+        }
+
+        let res = query_as!(
+            CachePolicy,
+            r#"
+            SELECT fn_key, mode, ttl_secs, max_result_bytes, update_dt
+            FROM cache_policies
+            WHERE user_id = get_user_id($1, $2)
+                AND fn_key = ANY($3)
+            ORDER BY fn_key
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            &fn_keys,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Persist for CachePolicyPut {
+    type Ret = CachePolicy;
+    type Error = CachePolicyError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(CachePolicyError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::EvalWrite), state).await?;
+
+        if self.ttl_secs.map_or(false, |t| t <= 0) || self.max_result_bytes.map_or(false, |b| b < 0)
+        {
+            return Err(CachePolicyError::InvalidPolicy);
+        }
+
+        let mut tx = state.db_conn.begin().await?;
+        let res = query_as!(
+            CachePolicy,
+            r#"
+            INSERT INTO cache_policies (user_id, fn_key, mode, ttl_secs, max_result_bytes)
+            VALUES (get_user_id($1, $2), $3, $4, $5, $6)
+            ON CONFLICT (user_id, fn_key) DO UPDATE
+                SET mode = EXCLUDED.mode,
+                    ttl_secs = EXCLUDED.ttl_secs,
+                    max_result_bytes = EXCLUDED.max_result_bytes,
+                    update_dt = current_timestamp
+            RETURNING fn_key, mode, ttl_secs, max_result_bytes, update_dt
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.fn_key,
+            self.mode.as_str(),
+            self.ttl_secs,
+            self.max_result_bytes,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        query!(
+            r#"
+            DELETE FROM cache_policy_removals
+            WHERE user_id = get_user_id($1, $2)
+                AND fn_key = $3
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            res.fn_key,
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Persist for CachePolicyDelete {
+    type Ret = ();
+    type Error = CachePolicyError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(CachePolicyError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::EvalWrite), state).await?;
+
+        // Removals are recorded for clients syncing the functions registry.
+        let mut tx = state.db_conn.begin().await?;
+        query_scalar!(
+            r#"
+            DELETE FROM cache_policies
+            WHERE user_id = get_user_id($1, $2)
+                AND fn_key = $3
+            RETURNING fn_key
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.fn_key,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        query!(
+            r#"
+            INSERT INTO cache_policy_removals (user_id, fn_key)
+            VALUES (get_user_id($1, $2), $3)
+            ON CONFLICT (user_id, fn_key) DO UPDATE
+                SET remove_dt = current_timestamp
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.fn_key,
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,670 @@
+//! A cache of BLOBs on local disk, in front of S3, so that BLOBs which are fetched over and over,
+//! such as those of a team's shared caches, aren't fetched from S3 every time.
+//!
+//! [`CachedStore`] wraps the server's [`S3Store`] when `BLOB_CACHE_DIR` is set. A BLOB retrieved
+//! whole is written to the cache as it's streamed to the client, and kept once it's been retrieved
+//! in full, unless it's longer than `BLOB_CACHE_MAX_BLOB_BYTES`. Later retrievals of it, whole or
+//! in ranges, are read from the cache. Once the cached BLOBs come to more than
+//! `BLOB_CACHE_MAX_BYTES`, the least recently used are evicted. BLOBs are addressed by their
+//! content, so a cached BLOB can't go stale; it's only dropped early when it's deleted.
+//!
+//! Which BLOBs are cached, and when each was last used, is kept in memory. BLOBs cached by an
+//! earlier run of the server are picked up as it starts, as if used in the order they were cached.
+//!
+//! [`S3Store`]: crate::persisters::s3store::S3Store
+use crate::persisters::blob_store::{BlobStore, Payload, PresignedPut};
+use crate::persisters::fs_store::file_stream;
+use crate::persisters::s3store::{StoreError, Stored, Target};
+
+use aws_sdk_s3::{model::StorageClass, types::ByteStream};
+use blake3::Hash;
+use futures::stream::{self, StreamExt};
+use sqlx::types::Uuid;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, ErrorKind, SeekFrom};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Where cached BLOBs are kept, under the cache's directory.
+const BLOBS_DIR: &str = "blobs";
+/// Where BLOBs are written as they're retrieved, until they've been retrieved in full.
+const INCOMING_DIR: &str = "incoming";
+
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    blobs: AtomicU64,
+    bytes: AtomicU64,
+}
+
+/// How the BLOB cache has fared since the server started, for `/metrics`.
+#[derive(Clone, Default)]
+pub struct BlobCacheStats(Arc<Counters>);
+
+/// A snapshot of [`BlobCacheStats`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BlobCacheUsage {
+    /// Retrievals served from the cache.
+    pub hits: u64,
+    /// Retrievals which went to S3.
+    pub misses: u64,
+    /// BLOBs evicted to make room for others.
+    pub evictions: u64,
+    /// BLOBs currently cached.
+    pub blobs: u64,
+    /// Bytes of the BLOBs currently cached.
+    pub bytes: u64,
+}
+
+impl BlobCacheStats {
+    pub fn usage(&self) -> BlobCacheUsage {
+        let c = &self.0;
+        BlobCacheUsage {
+            hits: c.hits.load(Ordering::Relaxed),
+            misses: c.misses.load(Ordering::Relaxed),
+            evictions: c.evictions.load(Ordering::Relaxed),
+            blobs: c.blobs.load(Ordering::Relaxed),
+            bytes: c.bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    fn hit(&self) {
+        self.0.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn miss(&self) {
+        self.0.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records what's cached after a change, and how many BLOBs it evicted.
+    fn record(&self, lru: &Lru, evicted: usize) {
+        let c = &self.0;
+        c.evictions.fetch_add(evicted as u64, Ordering::Relaxed);
+        c.blobs.store(lru.entries.len() as u64, Ordering::Relaxed);
+        c.bytes.store(lru.bytes, Ordering::Relaxed);
+    }
+}
+
+/// Which BLOBs are cached, by name, and the order they were last used in.
+#[derive(Default)]
+struct Lru {
+    /// Each cached BLOB's length, and when it was last used.
+    entries: HashMap<String, (u64, u64)>,
+    /// The cached BLOBs, by when they were last used.
+    order: BTreeMap<u64, String>,
+    /// Counts uses, so that later uses sort after earlier ones.
+    clock: u64,
+    /// The total length of the cached BLOBs.
+    bytes: u64,
+}
+
+impl Lru {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Marks the BLOB as just used, returning whether it's cached.
+    fn touch(&mut self, name: &str) -> bool {
+        let used = self.tick();
+        match self.entries.get_mut(name) {
+            Some((_, last_used)) => {
+                self.order.remove(last_used);
+                *last_used = used;
+                self.order.insert(used, name.to_string());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds the BLOB as just used, then evicts the least recently used BLOBs until the cached ones
+    /// come to at most `max_bytes`, returning the names of those evicted.
+    fn insert(&mut self, name: String, len: u64, max_bytes: u64) -> Vec<String> {
+        self.remove(&name);
+        let used = self.tick();
+        self.entries.insert(name.clone(), (len, used));
+        self.order.insert(used, name);
+        self.bytes += len;
+
+        let mut evicted = vec![];
+        while self.bytes > max_bytes {
+            let oldest = match self.order.keys().next() {
+                Some(&oldest) => oldest,
+                None => break,
+            };
+            let name = self.order.remove(&oldest).unwrap_or_default();
+            if let Some((len, _)) = self.entries.remove(&name) {
+                self.bytes -= len;
+            }
+            evicted.push(name);
+        }
+        evicted
+    }
+
+    /// Forgets the BLOB, returning whether it was cached.
+    fn remove(&mut self, name: &str) -> bool {
+        match self.entries.remove(name) {
+            Some((len, last_used)) => {
+                self.order.remove(&last_used);
+                self.bytes -= len;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// The cache's files and index, shared with the BLOBs being written to it.
+struct Cache {
+    dir: PathBuf,
+    max_bytes: u64,
+    max_blob_bytes: u64,
+    lru: Mutex<Lru>,
+    stats: BlobCacheStats,
+}
+
+impl Cache {
+    /// The name the BLOB is cached under. Names are distinct for every bucket and key, so that
+    /// BLOBs with the same content in different places are cached apart, as they're stored.
+    fn name(target: &Target, content_hash: Hash) -> String {
+        let location = format!(
+            "{}\n{}\n{}",
+            target.region.as_deref().unwrap_or_default(),
+            target.bucket(),
+            target.key(content_hash)
+        );
+        blake3::hash(location.as_bytes()).to_hex().to_string()
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(BLOBS_DIR).join(name)
+    }
+
+    /// Opens the cached BLOB, if it's cached, marking it as just used.
+    async fn open(&self, name: &str) -> Option<File> {
+        if !self.lru.lock().unwrap().touch(name) {
+            return None;
+        }
+        match File::open(self.path(name)).await {
+            Ok(file) => Some(file),
+            Err(e) => {
+                log::warn!("could not open cached BLOB {}: {:?}", name, e);
+                self.forget(name);
+                None
+            }
+        }
+    }
+
+    /// Adds a BLOB which has been moved into place, evicting others to make room for it.
+    async fn insert(&self, name: String, len: u64) {
+        let evicted = {
+            let mut lru = self.lru.lock().unwrap();
+            let evicted = lru.insert(name, len, self.max_bytes);
+            self.stats.record(&lru, evicted.len());
+            evicted
+        };
+        for name in evicted {
+            self.delete_file(&name).await;
+        }
+    }
+
+    /// Drops the BLOB from the cache, if it's cached.
+    async fn remove(&self, name: &str) {
+        if self.forget(name) {
+            self.delete_file(name).await;
+        }
+    }
+
+    fn forget(&self, name: &str) -> bool {
+        let mut lru = self.lru.lock().unwrap();
+        let removed = lru.remove(name);
+        self.stats.record(&lru, 0);
+        removed
+    }
+
+    /// Deletes a cached BLOB's file. Anything still reading it can carry on.
+    async fn delete_file(&self, name: &str) {
+        match fs::remove_file(self.path(name)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                log::warn!("could not delete cached BLOB {}: {:?}", name, e)
+            }
+            _ => {}
+        }
+    }
+
+    /// Starts writing a BLOB to the cache as it's retrieved.
+    async fn fill(self: &Arc<Self>, name: String) -> Option<Fill> {
+        let incoming = self.dir.join(INCOMING_DIR).join(Uuid::new_v4().to_string());
+        match File::create(&incoming).await {
+            Ok(file) => Some(Fill {
+                cache: self.clone(),
+                name,
+                incoming,
+                file,
+                len: 0,
+            }),
+            Err(e) => {
+                log::warn!("could not create {}: {:?}", incoming.display(), e);
+                None
+            }
+        }
+    }
+}
+
+/// A BLOB being written to the cache as it's retrieved. Its file is removed if it's dropped before
+/// the BLOB has been written in full.
+struct Fill {
+    cache: Arc<Cache>,
+    name: String,
+    incoming: PathBuf,
+    file: File,
+    len: u64,
+}
+
+impl Fill {
+    /// Writes the next bytes of the BLOB, or gives up on caching it if it's too long to cache or
+    /// can't be written.
+    async fn write(mut self, bytes: &[u8]) -> Option<Self> {
+        self.len += bytes.len() as u64;
+        if self.len > self.cache.max_blob_bytes {
+            return None;
+        }
+        match self.file.write_all(bytes).await {
+            Ok(()) => Some(self),
+            Err(e) => {
+                log::warn!("could not write {}: {:?}", self.incoming.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Moves the BLOB into place, once it's been retrieved in full.
+    async fn finish(mut self) {
+        let path = self.cache.path(&self.name);
+        let res = match self.file.flush().await {
+            Ok(()) => fs::rename(&self.incoming, &path).await,
+            Err(e) => Err(e),
+        };
+        match res {
+            Ok(()) => self.cache.insert(self.name.clone(), self.len).await,
+            Err(e) => log::warn!("could not cache {}: {:?}", path.display(), e),
+        }
+    }
+}
+
+impl Drop for Fill {
+    fn drop(&mut self) {
+        // Once the BLOB's been moved into place, there's nothing left to remove.
+        match std::fs::remove_file(&self.incoming) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                log::warn!("could not remove {}: {:?}", self.incoming.display(), e)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Passes on the next bytes of a BLOB retrieved from the wrapped store, writing them to the cache
+/// as they go, and caching the BLOB at its end.
+async fn next_piece(
+    (mut body, fill): (ByteStream, Option<Fill>),
+) -> Option<(io::Result<bytes::Bytes>, (ByteStream, Option<Fill>))> {
+    match body.next().await {
+        Some(Ok(bytes)) => {
+            let fill = match fill {
+                Some(fill) => fill.write(&bytes).await,
+                None => None,
+            };
+            Some((Ok(bytes), (body, fill)))
+        }
+        Some(Err(e)) => {
+            let e = io::Error::new(ErrorKind::Other, format!("{:?}", e));
+            Some((Err(e), (body, None)))
+        }
+        None => {
+            if let Some(fill) = fill {
+                fill.finish().await;
+            }
+            None
+        }
+    }
+}
+
+/// A [`BlobStore`] which caches BLOBs retrieved from the store it wraps on local disk.
+pub struct CachedStore {
+    inner: Arc<dyn BlobStore>,
+    cache: Arc<Cache>,
+}
+
+impl CachedStore {
+    /// Caches up to `max_bytes` of BLOBs in `dir`, each of at most `max_blob_bytes`, picking up
+    /// those already cached there. The cache's progress is recorded in `stats`.
+    pub fn new(
+        inner: Arc<dyn BlobStore>,
+        dir: impl Into<PathBuf>,
+        max_bytes: u64,
+        max_blob_bytes: u64,
+        stats: BlobCacheStats,
+    ) -> io::Result<CachedStore> {
+        let dir = dir.into();
+        let incoming = dir.join(INCOMING_DIR);
+        match std::fs::remove_dir_all(&incoming) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        std::fs::create_dir_all(&incoming)?;
+        std::fs::create_dir_all(dir.join(BLOBS_DIR))?;
+
+        let mut cached = vec![];
+        for entry in std::fs::read_dir(dir.join(BLOBS_DIR))? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            if let (true, Some(name)) = (meta.is_file(), entry.file_name().to_str()) {
+                cached.push((meta.modified()?, name.to_string(), meta.len()));
+            }
+        }
+        cached.sort();
+
+        let cache = Cache {
+            dir,
+            max_bytes,
+            max_blob_bytes: max_blob_bytes.min(max_bytes),
+            lru: Mutex::default(),
+            stats,
+        };
+        {
+            let mut lru = cache.lru.lock().unwrap();
+            for (_, name, len) in cached {
+                let evicted = lru.insert(name, len, max_bytes);
+                cache.stats.record(&lru, evicted.len());
+                for name in evicted {
+                    std::fs::remove_file(cache.path(&name))?;
+                }
+            }
+        }
+
+        Ok(Self {
+            inner,
+            cache: Arc::new(cache),
+        })
+    }
+}
+
+#[async_trait]
+impl BlobStore for CachedStore {
+    async fn check_target(&self, target: &Target) -> Result<(), StoreError> {
+        self.inner.check_target(target).await
+    }
+
+    async fn store_blob(
+        &self,
+        target: &Target,
+        payload: Payload,
+        hash_claim: Hash,
+        content_length: i64,
+    ) -> Result<Stored, StoreError> {
+        self.inner
+            .store_blob(target, payload, hash_claim, content_length)
+            .await
+    }
+
+    async fn store_bytes(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        bytes: bytes::Bytes,
+    ) -> Result<Stored, StoreError> {
+        self.inner.store_bytes(target, content_hash, bytes).await
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<String, StoreError> {
+        self.inner
+            .create_multipart_upload(target, content_hash)
+            .await
+    }
+
+    async fn upload_part(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        upload_id: &str,
+        part_number: i32,
+        bytes: bytes::Bytes,
+    ) -> Result<String, StoreError> {
+        self.inner
+            .upload_part(target, content_hash, upload_id, part_number, bytes)
+            .await
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        upload_id: &str,
+        e_tags: &[String],
+    ) -> Result<(), StoreError> {
+        self.inner
+            .complete_multipart_upload(target, content_hash, upload_id, e_tags)
+            .await
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        upload_id: &str,
+    ) -> Result<(), StoreError> {
+        self.inner
+            .abort_multipart_upload(target, content_hash, upload_id)
+            .await
+    }
+
+    /// Reads the BLOB from the cache if it's there, or else retrieves it from the wrapped store,
+    /// caching it as it's streamed.
+    async fn retrieve_blob(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<ByteStream, StoreError> {
+        let name = Cache::name(target, content_hash);
+        if let Some(file) = self.cache.open(&name).await {
+            let len = file.metadata().await?.len();
+            self.cache.stats.hit();
+            return Ok(file_stream(file, len));
+        }
+
+        self.cache.stats.miss();
+        let body = self.inner.retrieve_blob(target, content_hash).await?;
+        let fill = self.cache.fill(name).await;
+        let pieces = stream::unfold((body, fill), next_piece);
+
+        Ok(ByteStream::new(hyper::Body::wrap_stream(pieces).into()))
+    }
+
+    /// Reads the range from the cache if the BLOB's there. Ranges retrieved from the wrapped store
+    /// aren't cached.
+    async fn retrieve_blob_range(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        first: u64,
+        last: u64,
+    ) -> Result<ByteStream, StoreError> {
+        let name = Cache::name(target, content_hash);
+        if let Some(mut file) = self.cache.open(&name).await {
+            let len = file.metadata().await?.len();
+            let end = last.saturating_add(1).min(len);
+            file.seek(SeekFrom::Start(first.min(end))).await?;
+            self.cache.stats.hit();
+            return Ok(file_stream(file, end.saturating_sub(first)));
+        }
+
+        self.cache.stats.miss();
+        self.inner
+            .retrieve_blob_range(target, content_hash, first, last)
+            .await
+    }
+
+    async fn blob_length(&self, target: &Target, content_hash: Hash) -> Result<i64, StoreError> {
+        self.inner.blob_length(target, content_hash).await
+    }
+
+    async fn set_storage_class(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        storage_class: StorageClass,
+    ) -> Result<(), StoreError> {
+        self.inner
+            .set_storage_class(target, content_hash, storage_class)
+            .await
+    }
+
+    async fn request_restore(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        days: i32,
+    ) -> Result<(), StoreError> {
+        self.inner.request_restore(target, content_hash, days).await
+    }
+
+    async fn restore_complete(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<bool, StoreError> {
+        self.inner.restore_complete(target, content_hash).await
+    }
+
+    async fn store_object(&self, key: &str, bytes: bytes::Bytes) -> Result<(), StoreError> {
+        self.inner.store_object(key, bytes).await
+    }
+
+    async fn retrieve_object(&self, key: &str) -> Result<bytes::Bytes, StoreError> {
+        self.inner.retrieve_object(key).await
+    }
+
+    async fn find_blob_written(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<Option<(i64, Option<i64>)>, StoreError> {
+        self.inner.find_blob_written(target, content_hash).await
+    }
+
+    async fn list_objects(
+        &self,
+        target: &Target,
+        continuation: Option<String>,
+    ) -> Result<(Vec<(String, i64)>, Option<String>), StoreError> {
+        self.inner.list_objects(target, continuation).await
+    }
+
+    async fn delete_blob(&self, target: &Target, content_hash: Hash) -> Result<(), StoreError> {
+        self.inner.delete_blob(target, content_hash).await?;
+        self.cache.remove(&Cache::name(target, content_hash)).await;
+        Ok(())
+    }
+
+    async fn presign_get(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        expires_in: Duration,
+    ) -> Result<Option<String>, StoreError> {
+        self.inner
+            .presign_get(target, content_hash, expires_in)
+            .await
+    }
+
+    async fn presign_staged_put(
+        &self,
+        target: &Target,
+        upload_id: Uuid,
+        content_length: i64,
+        expires_in: Duration,
+    ) -> Result<Option<PresignedPut>, StoreError> {
+        self.inner
+            .presign_staged_put(target, upload_id, content_length, expires_in)
+            .await
+    }
+
+    async fn retrieve_staged(
+        &self,
+        target: &Target,
+        upload_id: Uuid,
+    ) -> Result<ByteStream, StoreError> {
+        self.inner.retrieve_staged(target, upload_id).await
+    }
+
+    async fn promote_staged(
+        &self,
+        target: &Target,
+        upload_id: Uuid,
+        content_hash: Hash,
+    ) -> Result<(), StoreError> {
+        self.inner
+            .promote_staged(target, upload_id, content_hash)
+            .await
+    }
+
+    async fn delete_staged(&self, target: &Target, upload_id: Uuid) -> Result<(), StoreError> {
+        self.inner.delete_staged(target, upload_id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_the_least_recently_used() {
+        let mut lru = Lru::default();
+        assert!(lru.insert("a".into(), 4, 10).is_empty());
+        assert!(lru.insert("b".into(), 4, 10).is_empty());
+        assert!(lru.touch("a"));
+        assert_eq!(lru.insert("c".into(), 4, 10), vec!["b"]);
+        assert_eq!(lru.bytes, 8);
+        assert!(!lru.touch("b"));
+
+        // Replacing a BLOB doesn't count it twice.
+        assert!(lru.insert("c".into(), 4, 10).is_empty());
+        assert_eq!(lru.bytes, 8);
+        assert_eq!(lru.insert("d".into(), 9, 10), vec!["a", "c"]);
+        assert_eq!((lru.bytes, lru.entries.len()), (9, 1));
+
+        assert!(lru.remove("d"));
+        assert!(!lru.remove("d"));
+        assert_eq!((lru.bytes, lru.order.len()), (0, 0));
+    }
+
+    #[test]
+    fn names_blobs_by_where_theyre_stored() {
+        let hash = blake3::hash(b"blob");
+        let own = Target {
+            bucket: Some("own".into()),
+            prefix: Some("p/".into()),
+            ..Target::default()
+        };
+        let other = Target {
+            bucket: Some("other".into()),
+            ..own.clone()
+        };
+        assert_eq!(Cache::name(&own, hash), Cache::name(&own.clone(), hash));
+        assert_ne!(Cache::name(&own, hash), Cache::name(&other, hash));
+        assert_eq!(Cache::name(&own, hash).len(), 64);
+    }
+}
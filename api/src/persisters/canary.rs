@@ -0,0 +1,389 @@
+use crate::middlewares::auth::Auth;
+use crate::models::canary::{Canary, CanaryError, CanaryTrip, CanaryTripped, CANARY_LEN};
+use crate::persisters::blob::upsert_user_blob;
+use crate::persisters::s3store::{store_shared, user_upload_target};
+use crate::persisters::{Persist, Query};
+use crate::state::State;
+use rand::RngCore;
+use sqlx::types::{chrono, Uuid};
+use std::net::{IpAddr, SocketAddr};
+
+/// What a canary is expected to be downloaded with, and where its events are sent.
+#[derive(Deserialize, Debug)]
+pub struct CanarySpec {
+    pub label: String,
+    #[serde(default)]
+    pub allowed_keys: Vec<String>,
+    /// Networks in CIDR notation, e.g. `10.0.0.0/8`.
+    #[serde(default)]
+    pub allowed_cidrs: Vec<String>,
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+}
+
+/// Generates a canary for an org, as a new BLOB owned by the org's owner.
+pub struct CanaryInsert {
+    pub org: String,
+    pub spec: CanarySpec,
+}
+
+/// Lists an org's canaries, newest first.
+pub struct CanariesGet {
+    pub org: String,
+}
+
+/// Deletes one of an org's canaries, along with its trips. Its BLOB is left to the garbage
+/// collector.
+pub struct CanaryDelete {
+    pub org: String,
+    pub id: i64,
+}
+
+fn default_limit() -> i64 {
+    100
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TripsParams {
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+/// Lists the trips of one of an org's canaries, newest first.
+pub struct CanaryTripsGet {
+    pub org: String,
+    pub id: i64,
+    pub params: TripsParams,
+}
+
+struct OrgResult {
+    id: Uuid,
+    owner_id: Uuid,
+}
+
+/// Looks up an org of the authenticated user by its external id.
+async fn org(auth: &Auth, org: &str, state: &State) -> Result<OrgResult, CanaryError> {
+    let org = query_as!(
+        OrgResult,
+        r#"
+        SELECT id, owner_id
+        FROM orgs
+        WHERE owner_id = get_user_id($1, $2)
+            AND external_id = $3
+        "#,
+        auth.jwt().map(|c| c.sub),
+        auth.api_key(),
+        org,
+    )
+    .fetch_one(&state.db_conn)
+    .await?;
+
+    Ok(org)
+}
+
+/// The org's canaries, or just the one with the id.
+async fn canaries(
+    org_id: Uuid,
+    id: Option<i64>,
+    state: &State,
+) -> Result<Vec<Canary>, sqlx::Error> {
+    query_as!(
+        Canary,
+        r#"
+        SELECT c.id, c.label, b.content_hash, c.allowed_keys,
+            c.allowed_cidrs::text[] AS "allowed_cidrs!", c.webhook_url,
+            count(t.id) AS "trips!", max(t.create_dt) AS last_trip_dt, c.create_dt
+        FROM canaries c
+        JOIN blobs b
+            ON b.id = c.blob_id
+        LEFT JOIN canary_trips t
+            ON t.canary_id = c.id
+        WHERE c.org_id = $1
+            AND (c.id = $2 OR $2 IS NULL)
+        GROUP BY c.id, b.content_hash
+        ORDER BY c.create_dt DESC
+        "#,
+        org_id,
+        id,
+    )
+    .fetch_all(&state.db_conn)
+    .await
+}
+
+#[async_trait]
+impl Persist for CanaryInsert {
+    type Ret = Canary;
+    type Error = CanaryError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(CanaryError::Unauthorized)?;
+        let org = org(auth, &self.org, state).await?;
+
+        // Random bytes, so that the canary's hash can't be come by without its BLOB.
+        let mut bytes = vec![0; CANARY_LEN];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let content_hash = blake3::hash(&bytes);
+        let target = user_upload_target(org.owner_id, state).await?;
+        let (target, stored) = store_shared(state, target, content_hash, bytes.into()).await?;
+
+        let mut tx = state.db_conn.begin().await?;
+        let blob_id = upsert_user_blob(
+            &mut tx,
+            org.owner_id,
+            content_hash.to_hex().as_str(),
+            &target,
+            stored,
+        )
+        .await?;
+        query!("UPDATE blobs SET canary = true WHERE id = $1", blob_id)
+            .execute(&mut tx)
+            .await?;
+        let id = query_scalar!(
+            r#"
+            INSERT INTO canaries (org_id, blob_id, label, allowed_keys, allowed_cidrs,
+                webhook_url)
+            VALUES ($1, $2, $3, $4, $5::text[]::cidr[], $6)
+            RETURNING id
+            "#,
+            org.id,
+            blob_id,
+            self.spec.label,
+            &self.spec.allowed_keys,
+            &self.spec.allowed_cidrs,
+            self.spec.webhook_url,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        canaries(org.id, Some(id), state)
+            .await?
+            .pop()
+            .ok_or(CanaryError::NotFound)
+    }
+}
+
+#[async_trait]
+impl Query for CanariesGet {
+    type Resolve = Vec<Canary>;
+    type Error = CanaryError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(CanaryError::Unauthorized)?;
+        let org = org(auth, &self.org, state).await?;
+
+        Ok(canaries(org.id, None, state).await?)
+    }
+}
+
+#[async_trait]
+impl Persist for CanaryDelete {
+    type Ret = ();
+    type Error = CanaryError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(CanaryError::Unauthorized)?;
+        let org = org(auth, &self.org, state).await?;
+
+        // The BLOB stays flagged while any other canary uses it. The canary being deleted is
+        // still visible to the rest of the statement, so it's left out explicitly.
+        query_scalar!(
+            r#"
+            WITH c AS (
+                DELETE FROM canaries
+                WHERE id = $1
+                    AND org_id = $2
+                RETURNING blob_id
+            )
+            UPDATE blobs b
+            SET canary = EXISTS (
+                SELECT 1 FROM canaries o
+                WHERE o.blob_id = b.id
+                    AND o.id <> $1
+            )
+            FROM c
+            WHERE b.id = c.blob_id
+            RETURNING b.id
+            "#,
+            self.id,
+            org.id,
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Query for CanaryTripsGet {
+    type Resolve = Vec<CanaryTrip>;
+    type Error = CanaryError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(CanaryError::Unauthorized)?;
+        let org = org(auth, &self.org, state).await?;
+
+        let res = query_as!(
+            CanaryTrip,
+            r#"
+            SELECT t.id, t.canary_id, t.user_id, t.api_key_label, t.ip, t.create_dt
+            FROM canary_trips t
+            JOIN canaries c
+                ON c.id = t.canary_id
+            WHERE c.id = $1
+                AND c.org_id = $2
+            ORDER BY t.create_dt DESC
+            LIMIT $3
+            "#,
+            self.id,
+            org.id,
+            self.params.limit,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+/// The address a request came from, given as an address, or an address and port.
+fn client_ip(addr: &str) -> Option<IpAddr> {
+    addr.parse::<IpAddr>()
+        .ok()
+        .or_else(|| addr.parse::<SocketAddr>().ok().map(|a| a.ip()))
+}
+
+struct TripRow {
+    id: i64,
+    canary_id: i64,
+    user_id: Uuid,
+    api_key_label: Option<String>,
+    ip: Option<String>,
+    create_dt: chrono::DateTime<chrono::Utc>,
+    org: String,
+    label: String,
+    content_hash: String,
+    webhook_url: Option<String>,
+}
+
+/// Checks a download of a canary's BLOB, from `ip`, against what each of its canaries expects,
+/// recording a trip of each one which didn't expect it and sending its event. The download goes
+/// ahead either way, so failures here are logged rather than returned.
+pub async fn check_download(state: &State, auth: &Auth, blob_id: i64, ip: Option<&str>) {
+    let trips = match record_trips(state, auth, blob_id, ip).await {
+        Ok(trips) => trips,
+        Err(e) => {
+            log::error!(
+                "error checking download of canary BLOB {}: {:?}",
+                blob_id,
+                e
+            );
+            return;
+        }
+    };
+
+    for trip in trips {
+        log::warn!(
+            "canary {} of org {} tripped by user {} from {}",
+            trip.canary_id,
+            trip.org,
+            trip.user_id,
+            trip.ip.as_deref().unwrap_or("an unknown address"),
+        );
+        let url = match trip.webhook_url {
+            Some(url) => url,
+            None => continue,
+        };
+        let event = CanaryTripped {
+            event: "canary_tripped",
+            org: trip.org,
+            canary_id: trip.canary_id,
+            label: trip.label,
+            content_hash: trip.content_hash,
+            trip: CanaryTrip {
+                id: trip.id,
+                canary_id: trip.canary_id,
+                user_id: trip.user_id,
+                api_key_label: trip.api_key_label,
+                ip: trip.ip,
+                create_dt: trip.create_dt,
+            },
+        };
+        // Sent in the background, so the download isn't held up by it.
+        let notifier = state.notifier.clone();
+        actix_rt::spawn(async move {
+            if let Err(e) = notifier.webhook(&url, &event).await {
+                log::warn!(
+                    "could not send event for trip of canary {}: {:?}",
+                    event.canary_id,
+                    e
+                );
+            }
+        });
+    }
+}
+
+async fn record_trips(
+    state: &State,
+    auth: &Auth,
+    blob_id: i64,
+    ip: Option<&str>,
+) -> Result<Vec<TripRow>, sqlx::Error> {
+    // An address which can't be parsed is outside every network.
+    let parsed_ip = ip.and_then(client_ip).map(|ip| ip.to_string());
+
+    query_as!(
+        TripRow,
+        r#"
+        WITH tripped AS (
+            SELECT c.id
+            FROM canaries c
+            WHERE c.blob_id = $1
+                AND NOT (
+                    (cardinality(c.allowed_keys) > 0 OR cardinality(c.allowed_cidrs) > 0)
+                    AND (cardinality(c.allowed_keys) = 0
+                        OR coalesce($3 = ANY(c.allowed_keys), false))
+                    AND (cardinality(c.allowed_cidrs) = 0
+                        OR coalesce($5::text::inet <<= ANY(c.allowed_cidrs), false))
+                )
+        ),
+        t AS (
+            INSERT INTO canary_trips (canary_id, user_id, api_key, api_key_label, ip)
+            SELECT id, get_user_id($2, $3), $3, (SELECT label FROM api_keys WHERE key = $3), $4
+            FROM tripped
+            RETURNING *
+        )
+        SELECT t.id, t.canary_id, t.user_id, t.api_key_label, t.ip, t.create_dt,
+            o.external_id AS org, c.label, b.content_hash, c.webhook_url
+        FROM t
+        JOIN canaries c
+            ON c.id = t.canary_id
+        JOIN orgs o
+            ON o.id = c.org_id
+        JOIN blobs b
+            ON b.id = c.blob_id
+        "#,
+        blob_id,
+        auth.jwt().map(|c| c.sub),
+        auth.api_key(),
+        ip,
+        parsed_ip,
+    )
+    .fetch_all(&state.db_conn)
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_client_addresses() {
+        assert_eq!(client_ip("10.1.2.3"), "10.1.2.3".parse().ok());
+        assert_eq!(client_ip("10.1.2.3:8080"), "10.1.2.3".parse().ok());
+        assert_eq!(client_ip("[::1]:8080"), "::1".parse().ok());
+        assert_eq!(client_ip("::1"), "::1".parse().ok());
+        assert_eq!(client_ip("unknown"), None);
+    }
+}
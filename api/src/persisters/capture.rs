@@ -0,0 +1,198 @@
+use crate::capture::MAX_CAPTURE_HOURS;
+use crate::middlewares::auth::Auth;
+use crate::models::capture::{Capture, CaptureError, KeyCapture};
+use crate::persisters::user::{is_admin, user_id};
+use crate::persisters::{Persist, Query};
+use crate::state::State;
+use sqlx::types::Uuid;
+
+async fn require_admin(auth: Option<&Auth>, state: &State) -> Result<(), CaptureError> {
+    let auth = auth.ok_or(CaptureError::Unauthorized)?;
+
+    if !is_admin(auth, state).await? {
+        return Err(CaptureError::Forbidden);
+    }
+
+    Ok(())
+}
+
+/// Opts the authenticated user's keys with the given label into capture for `hours` hours. If
+/// `hours` is left out, capture stops.
+#[derive(Deserialize, Debug)]
+pub struct KeyCaptureUpdate {
+    pub label: String,
+    pub hours: Option<i64>,
+}
+
+/// Lists the unexpired captures, newest first, optionally only those of one user.
+#[derive(Deserialize, Debug)]
+pub struct CapturesGet {
+    pub user_id: Option<Uuid>,
+}
+
+/// The captured body of a request, opened, along with the content type it was sent with.
+pub struct CaptureBodyGet {
+    pub id: i64,
+}
+
+/// A failing request to capture.
+pub struct CaptureInsert {
+    pub api_key: String,
+    pub method: String,
+    pub path: String,
+    pub content_type: Option<String>,
+    pub user_agent: Option<String>,
+    pub status: u16,
+    pub body_len: Option<i64>,
+    pub body: Vec<u8>,
+}
+
+#[async_trait]
+impl Persist for KeyCaptureUpdate {
+    type Ret = Vec<KeyCapture>;
+    type Error = CaptureError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(CaptureError::Unauthorized)?;
+
+        if let Some(hours) = self.hours {
+            if state.capture_key.is_none() {
+                return Err(CaptureError::Disabled);
+            }
+            if !(1..=MAX_CAPTURE_HOURS).contains(&hours) {
+                return Err(CaptureError::InvalidDuration);
+            }
+        }
+
+        let user_id = user_id(auth, state).await?;
+        let res = query_as!(
+            KeyCapture,
+            r#"
+            UPDATE api_keys
+            SET capture_until = now() + make_interval(hours => $3)
+            WHERE user_id = $1 AND label = $2
+            RETURNING label, capture_until
+            "#,
+            user_id,
+            self.label,
+            self.hours.map(|h| h as i32),
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        if res.is_empty() {
+            return Err(CaptureError::NotFound);
+        }
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for CapturesGet {
+    type Resolve = Vec<Capture>;
+    type Error = CaptureError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        require_admin(auth, state).await?;
+
+        let res = query_as!(
+            Capture,
+            r#"
+            SELECT c.id, c.user_id, k.label AS key_label, c.method, c.path, c.content_type,
+                c.user_agent, c.status, c.body_len, c.captured_len, c.create_dt, c.expire_dt
+            FROM request_captures c
+            JOIN api_keys k ON k.key = c.api_key
+            WHERE c.expire_dt > now()
+                AND ($1::uuid IS NULL OR c.user_id = $1)
+            ORDER BY c.create_dt DESC
+            "#,
+            self.user_id,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for CaptureBodyGet {
+    type Resolve = (Option<String>, Vec<u8>);
+    type Error = CaptureError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        require_admin(auth, state).await?;
+        let key = state.capture_key.as_ref().ok_or(CaptureError::Disabled)?;
+
+        let res = query!(
+            r#"
+            SELECT content_type, sealed_body
+            FROM request_captures
+            WHERE id = $1 AND expire_dt > now()
+            "#,
+            self.id,
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        let body = key.open(&res.sealed_body).ok_or(CaptureError::Seal)?;
+
+        Ok((res.content_type, body))
+    }
+}
+
+/// Whether failing requests made with `key` are being captured. Requests aren't failed for want
+/// of knowing: if the key can't be looked up, they aren't captured.
+pub async fn capturing(key: &str, state: &State) -> bool {
+    if state.capture_key.is_none() {
+        return false;
+    }
+
+    query_scalar!(
+        r#"
+        SELECT capture_until > now() AS "capturing!"
+        FROM api_keys
+        WHERE key = $1 AND capture_until IS NOT NULL
+        "#,
+        key,
+    )
+    .fetch_optional(&state.db_conn)
+    .await
+    .map_err(|e| log::warn!("could not look up whether to capture requests: {:?}", e))
+    .ok()
+    .flatten()
+    .unwrap_or(false)
+}
+
+/// Seals and stores a failing request's captured bytes.
+pub async fn record(state: &State, capture: CaptureInsert) -> Result<(), CaptureError> {
+    let key = state.capture_key.as_ref().ok_or(CaptureError::Disabled)?;
+    let captured_len = capture.body.len() as i64;
+    let sealed_body = key.seal(&capture.body).ok_or(CaptureError::Seal)?;
+
+    query!(
+        r#"
+        INSERT INTO request_captures (user_id, api_key, method, path, content_type, user_agent,
+            status, body_len, captured_len, sealed_body, expire_dt)
+        SELECT user_id, key, $2, $3, $4, $5, $6, $7, $8, $9,
+            now() + make_interval(hours => $10)
+        FROM api_keys
+        WHERE key = $1
+        "#,
+        capture.api_key,
+        capture.method,
+        capture.path,
+        capture.content_type,
+        capture.user_agent,
+        capture.status as i16,
+        capture.body_len,
+        captured_len,
+        sealed_body,
+        state.config.capture_retention_hours as i32,
+    )
+    .execute(&state.db_conn)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,135 @@
+use crate::middlewares::auth::Auth;
+use crate::models::change::{
+    ChangeError, ChangeKind, ChangesHead, EvalChange, CHANGES_LOCK, MAX_CHANGES,
+};
+use crate::persisters::user::user_id;
+use crate::persisters::Query;
+use crate::policy::{self, Action, Request};
+use crate::state::State;
+use sqlx::{types::Uuid, Postgres, Transaction};
+
+fn default_limit() -> i64 {
+    MAX_CHANGES
+}
+
+/// Lists the changes to the user's eval keys after the cursor `since`, oldest first. Listing from
+/// the start (a cursor of 0) is only possible until the first changes are pruned.
+#[derive(Deserialize, Debug)]
+pub struct ChangesGet {
+    #[serde(default)]
+    pub since: i64,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+/// The cursor of the user's latest change.
+pub struct ChangesHeadGet;
+
+#[async_trait]
+impl Query for ChangesGet {
+    type Resolve = Vec<EvalChange>;
+    type Error = ChangeError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(ChangeError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::EvalRead), state).await?;
+        let user_id = user_id(auth, state).await?;
+
+        let pruned_to = query_scalar!("SELECT pruned_to FROM eval_changes_pruned")
+            .fetch_one(&state.db_conn)
+            .await?;
+        if self.since < pruned_to {
+            return Err(ChangeError::CursorExpired);
+        }
+
+        let res = query_as!(
+            EvalChange,
+            r#"
+            SELECT id, kind, fn_key, fn_hash, args_hash, create_dt
+            FROM eval_changes
+            WHERE user_id = $1
+                AND id > $2
+            ORDER BY id
+            LIMIT $3
+            "#,
+            user_id,
+            self.since,
+            self.limit.clamp(1, MAX_CHANGES),
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for ChangesHeadGet {
+    type Resolve = ChangesHead;
+    type Error = ChangeError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(ChangeError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::EvalRead), state).await?;
+        let user_id = user_id(auth, state).await?;
+
+        // With no changes of their own left, the user's cursor is wherever pruning got to.
+        let cursor = query_scalar!(
+            r#"
+            SELECT greatest(
+                (SELECT max(id) FROM eval_changes WHERE user_id = $1),
+                (SELECT pruned_to FROM eval_changes_pruned)
+            ) AS "cursor!"
+            "#,
+            user_id,
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(ChangesHead { cursor })
+    }
+}
+
+/// Records a change to the keys of the given evals, as part of the transaction which changes
+/// them. Deletions have to be recorded before the evals are deleted.
+pub async fn record_changes(
+    tx: &mut Transaction<'_, Postgres>,
+    kind: ChangeKind,
+    eval_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    if eval_ids.is_empty() {
+        return Ok(());
+    }
+
+    // `pg_advisory_xact_lock` returns `void`, which sqlx can't decode, so it's called in `FROM`.
+    query_scalar!(
+        r#"
+        SELECT true AS "locked!"
+        FROM (
+            SELECT DISTINCT user_id
+            FROM evals
+            WHERE id = ANY($2)
+            ORDER BY user_id
+        ) u, pg_advisory_xact_lock($1, hashtext(u.user_id::text))
+        "#,
+        CHANGES_LOCK,
+        eval_ids,
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    query!(
+        r#"
+        INSERT INTO eval_changes (user_id, kind, fn_key, fn_hash, args_hash)
+        SELECT DISTINCT user_id, $1, fn_key, fn_hash, args_hash
+        FROM evals
+        WHERE id = ANY($2)
+        "#,
+        kind.as_str(),
+        eval_ids,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,580 @@
+//! Storage of large BLOBs in content-defined chunks, so that chunks which BLOBs have in common are
+//! stored once. See [`crate::chunking`] for how BLOBs are cut up.
+//!
+//! [`ChunkedStore`] wraps the server's [`BlobStore`]. BLOBs of at least `BLOB_CHUNK_MIN_BYTES`
+//! which are uploaded through the server are cut into chunks, each stored as an object under its
+//! own hash, unless it's already stored in the bucket, and the BLOB's manifest is recorded in
+//! `blob_manifests`. Reads of a BLOB with a manifest are served from its chunks; everything else
+//! is passed through, so smaller BLOBs, multipart uploads and uploads straight to storage are
+//! stored whole as before.
+//!
+//! Chunks may be shared by several BLOBs, so they always stay in the standard storage class:
+//! archiving a chunked BLOB only marks it archived. Chunked BLOBs can't be downloaded from storage
+//! directly, since there's no single object to presign.
+use crate::chunking::{spans, ChunkParams, DEFAULT_PARAMS};
+use crate::persisters::blob_store::{BlobStore, Payload, PresignedPut};
+use crate::persisters::s3store::{StoreError, Stored, Target};
+use crate::state::SqlPool;
+
+use aws_sdk_s3::{model::StorageClass, types::ByteStream};
+use blake3::{Hash, Hasher};
+use futures::stream::{self, StreamExt, TryStreamExt};
+use sqlx::types::{chrono, Uuid};
+
+use std::io::{self, ErrorKind};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// What's recorded as the compression of a BLOB stored in chunks. Its stored length is that of the
+/// chunks which weren't already stored.
+pub const CHUNKED: &str = "chunked";
+
+/// A chunk of a BLOB, as listed in its manifest.
+struct ManifestChunk {
+    chunk_hash: String,
+    storage_prefix: Option<String>,
+    length: i64,
+    create_dt: chrono::DateTime<chrono::Utc>,
+}
+
+/// A chunk which no manifest lists any more.
+struct Orphan {
+    chunk_hash: String,
+    storage_prefix: Option<String>,
+}
+
+/// The chunks of a BLOB stored so far.
+#[derive(Default)]
+struct Chunks {
+    ids: Vec<i64>,
+    offsets: Vec<i64>,
+    len: i64,
+    /// The length of the chunks which were stored for the BLOB, rather than already stored.
+    stored_length: i64,
+}
+
+/// Where a chunk of a BLOB stored at `target` is stored.
+fn chunk_target(target: &Target, storage_prefix: Option<String>) -> Target {
+    Target {
+        region: target.region.clone(),
+        bucket: target.bucket.clone(),
+        prefix: storage_prefix,
+    }
+}
+
+fn io_error(e: impl std::fmt::Debug) -> io::Error {
+    io::Error::new(ErrorKind::Other, format!("{:?}", e))
+}
+
+/// A [`BlobStore`] which stores large BLOBs in chunks, in the store it wraps.
+pub struct ChunkedStore {
+    inner: Arc<dyn BlobStore>,
+    db: SqlPool,
+    params: ChunkParams,
+    /// BLOBs shorter than this are stored whole.
+    min_blob_len: i64,
+}
+
+impl ChunkedStore {
+    pub fn new(inner: Arc<dyn BlobStore>, db: SqlPool, min_blob_len: i64) -> ChunkedStore {
+        Self {
+            inner,
+            db,
+            params: DEFAULT_PARAMS,
+            min_blob_len,
+        }
+    }
+
+    fn chunks(&self, content_length: i64) -> bool {
+        content_length >= self.min_blob_len
+    }
+
+    /// The chunks of the BLOB, in order, or `None` if it isn't stored in chunks.
+    async fn manifest(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<Option<Vec<ManifestChunk>>, StoreError> {
+        let hex = content_hash.to_hex();
+        let chunks = query_as!(
+            ManifestChunk,
+            r#"
+            SELECT c.chunk_hash, c.storage_prefix, c.length, m.create_dt
+            FROM blob_manifests m
+            JOIN blob_chunks c ON c.id = m.chunk_id
+            WHERE m.content_hash = $1
+                AND coalesce(m.storage_region, '') = coalesce($2, '')
+                AND coalesce(m.storage_bucket, '') = coalesce($3, '')
+            ORDER BY m.seq
+            "#,
+            hex.as_str(),
+            target.region.as_deref(),
+            target.bucket.as_deref(),
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok((!chunks.is_empty()).then(|| chunks))
+    }
+
+    /// Stores a chunk of a BLOB, unless it's already stored in the bucket.
+    async fn add_chunk(
+        &self,
+        target: &Target,
+        chunks: &mut Chunks,
+        chunk: bytes::Bytes,
+    ) -> Result<(), StoreError> {
+        let hash = blake3::hash(&chunk);
+        let hex = hash.to_hex();
+        let len = chunk.len() as i64;
+        let existing = query_scalar!(
+            r#"
+            SELECT id
+            FROM blob_chunks
+            WHERE chunk_hash = $1
+                AND coalesce(storage_region, '') = coalesce($2, '')
+                AND coalesce(storage_bucket, '') = coalesce($3, '')
+            "#,
+            hex.as_str(),
+            target.region.as_deref(),
+            target.bucket.as_deref(),
+        )
+        .fetch_optional(&self.db)
+        .await?;
+
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                let stored = self.inner.store_bytes(target, hash, chunk).await?;
+                chunks.stored_length += stored.length;
+                // Another upload may have stored the chunk meanwhile, in which case either object
+                // will do.
+                query_scalar!(
+                    r#"
+                    INSERT INTO blob_chunks (chunk_hash, storage_region, storage_bucket,
+                        storage_prefix, length)
+                    VALUES ($1, $2, $3, $4, $5)
+                    ON CONFLICT (chunk_hash, coalesce(storage_region, ''),
+                        coalesce(storage_bucket, ''))
+                        DO UPDATE SET length = EXCLUDED.length
+                    RETURNING id
+                    "#,
+                    hex.as_str(),
+                    target.region,
+                    target.bucket,
+                    target.prefix,
+                    len,
+                )
+                .fetch_one(&self.db)
+                .await?
+            }
+        };
+        chunks.ids.push(id);
+        chunks.offsets.push(chunks.len);
+        chunks.len += len;
+
+        Ok(())
+    }
+
+    /// Records the BLOB's manifest, replacing any it had.
+    async fn record_manifest(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        chunks: Chunks,
+    ) -> Result<Stored, StoreError> {
+        let content_hash = content_hash.to_hex();
+        let mut tx = self.db.begin().await?;
+        query!(
+            r#"
+            DELETE FROM blob_manifests
+            WHERE content_hash = $1
+                AND coalesce(storage_region, '') = coalesce($2, '')
+                AND coalesce(storage_bucket, '') = coalesce($3, '')
+            "#,
+            content_hash.as_str(),
+            target.region.as_deref(),
+            target.bucket.as_deref(),
+        )
+        .execute(&mut tx)
+        .await?;
+        // A chunk which was deleted as this BLOB was being stored fails the foreign key, rather
+        // than being listed in the manifest without an object.
+        query!(
+            r#"
+            INSERT INTO blob_manifests (content_hash, storage_region, storage_bucket, seq,
+                chunk_id, chunk_offset)
+            SELECT $1, $2, $3, m.seq::int, m.chunk_id, m.chunk_offset
+            FROM unnest($4::bigint[], $5::bigint[])
+                WITH ORDINALITY AS m(chunk_id, chunk_offset, seq)
+            "#,
+            content_hash.as_str(),
+            target.region,
+            target.bucket,
+            &chunks.ids,
+            &chunks.offsets,
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(Stored {
+            compression: Some(CHUNKED),
+            length: chunks.stored_length,
+        })
+    }
+
+    /// Streams the bytes `first..=last` of a chunked BLOB from its chunks.
+    fn assemble(
+        &self,
+        target: &Target,
+        manifest: Vec<ManifestChunk>,
+        first: u64,
+        last: u64,
+    ) -> Result<ByteStream, StoreError> {
+        let lengths: Vec<u64> = manifest.iter().map(|c| c.length as u64).collect();
+        let mut pieces = vec![];
+        for span in spans(&lengths, first, last) {
+            let chunk = &manifest[span.chunk];
+            let hash = Hash::from_hex(&chunk.chunk_hash)?;
+            let target = chunk_target(target, chunk.storage_prefix.clone());
+            pieces.push((target, hash, span.first, span.last));
+        }
+
+        let inner = self.inner.clone();
+        let bytes = stream::iter(pieces)
+            .then(move |(target, hash, first, last)| {
+                let inner = inner.clone();
+                async move {
+                    inner
+                        .retrieve_blob_range(&target, hash, first, last)
+                        .await
+                        .map_err(io_error)
+                }
+            })
+            .map_ok(|chunk| chunk.map_err(io_error))
+            .try_flatten();
+
+        Ok(ByteStream::new(hyper::Body::wrap_stream(bytes).into()))
+    }
+}
+
+#[async_trait]
+impl BlobStore for ChunkedStore {
+    async fn check_target(&self, target: &Target) -> Result<(), StoreError> {
+        self.inner.check_target(target).await
+    }
+
+    /// Chunks the BLOB as it's received, storing each chunk once it's cut. Chunks of an upload
+    /// which turns out not to be the BLOB claimed are kept, for other BLOBs to use.
+    async fn store_blob(
+        &self,
+        target: &Target,
+        mut payload: Payload,
+        hash_claim: Hash,
+        content_length: i64,
+    ) -> Result<Stored, StoreError> {
+        if !self.chunks(content_length) {
+            return self
+                .inner
+                .store_blob(target, payload, hash_claim, content_length)
+                .await;
+        }
+
+        let mut chunks = Chunks::default();
+        let mut hasher = Hasher::new();
+        let mut len = 0;
+        let mut buf = bytes::BytesMut::new();
+        while let Some(bytes) = payload.next().await {
+            let bytes = bytes.map_err(StoreError::WithBlob)?;
+            hasher.update(&bytes);
+            len += bytes.len() as i64;
+            if len > content_length {
+                return Err(StoreError::InvalidHash);
+            }
+            buf.extend_from_slice(&bytes);
+            // A chunk can be cut once there's a chunk's most in the buffer.
+            while buf.len() >= self.params.max_len {
+                let chunk = buf.split_to(self.params.next_cut(&buf)).freeze();
+                self.add_chunk(target, &mut chunks, chunk).await?;
+            }
+        }
+        if len != content_length || hasher.finalize() != hash_claim {
+            return Err(StoreError::InvalidHash);
+        }
+        while !buf.is_empty() {
+            let chunk = buf.split_to(self.params.next_cut(&buf)).freeze();
+            self.add_chunk(target, &mut chunks, chunk).await?;
+        }
+
+        self.record_manifest(target, hash_claim, chunks).await
+    }
+
+    async fn store_bytes(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        bytes: bytes::Bytes,
+    ) -> Result<Stored, StoreError> {
+        if !self.chunks(bytes.len() as i64) {
+            return self.inner.store_bytes(target, content_hash, bytes).await;
+        }
+
+        let mut chunks = Chunks::default();
+        for chunk in self.params.chunks(&bytes) {
+            self.add_chunk(target, &mut chunks, bytes.slice_ref(chunk))
+                .await?;
+        }
+
+        self.record_manifest(target, content_hash, chunks).await
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<String, StoreError> {
+        self.inner
+            .create_multipart_upload(target, content_hash)
+            .await
+    }
+
+    async fn upload_part(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        upload_id: &str,
+        part_number: i32,
+        bytes: bytes::Bytes,
+    ) -> Result<String, StoreError> {
+        self.inner
+            .upload_part(target, content_hash, upload_id, part_number, bytes)
+            .await
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        upload_id: &str,
+        e_tags: &[String],
+    ) -> Result<(), StoreError> {
+        self.inner
+            .complete_multipart_upload(target, content_hash, upload_id, e_tags)
+            .await
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        upload_id: &str,
+    ) -> Result<(), StoreError> {
+        self.inner
+            .abort_multipart_upload(target, content_hash, upload_id)
+            .await
+    }
+
+    async fn retrieve_blob(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<ByteStream, StoreError> {
+        match self.manifest(target, content_hash).await? {
+            Some(manifest) => self.assemble(target, manifest, 0, u64::MAX),
+            None => self.inner.retrieve_blob(target, content_hash).await,
+        }
+    }
+
+    async fn retrieve_blob_range(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        first: u64,
+        last: u64,
+    ) -> Result<ByteStream, StoreError> {
+        match self.manifest(target, content_hash).await? {
+            Some(manifest) => self.assemble(target, manifest, first, last),
+            None => {
+                self.inner
+                    .retrieve_blob_range(target, content_hash, first, last)
+                    .await
+            }
+        }
+    }
+
+    async fn blob_length(&self, target: &Target, content_hash: Hash) -> Result<i64, StoreError> {
+        match self.manifest(target, content_hash).await? {
+            Some(manifest) => Ok(manifest.iter().map(|c| c.length).sum()),
+            None => self.inner.blob_length(target, content_hash).await,
+        }
+    }
+
+    /// Chunked BLOBs stay where they are.
+    async fn set_storage_class(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        storage_class: StorageClass,
+    ) -> Result<(), StoreError> {
+        match self.manifest(target, content_hash).await? {
+            Some(_) => Ok(()),
+            None => {
+                self.inner
+                    .set_storage_class(target, content_hash, storage_class)
+                    .await
+            }
+        }
+    }
+
+    async fn request_restore(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        days: i32,
+    ) -> Result<(), StoreError> {
+        match self.manifest(target, content_hash).await? {
+            Some(_) => Ok(()),
+            None => self.inner.request_restore(target, content_hash, days).await,
+        }
+    }
+
+    async fn restore_complete(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<bool, StoreError> {
+        match self.manifest(target, content_hash).await? {
+            Some(_) => Ok(true),
+            None => self.inner.restore_complete(target, content_hash).await,
+        }
+    }
+
+    async fn store_object(&self, key: &str, bytes: bytes::Bytes) -> Result<(), StoreError> {
+        self.inner.store_object(key, bytes).await
+    }
+
+    async fn retrieve_object(&self, key: &str) -> Result<bytes::Bytes, StoreError> {
+        self.inner.retrieve_object(key).await
+    }
+
+    /// A chunked BLOB was written when its manifest was recorded.
+    async fn find_blob_written(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<Option<(i64, Option<i64>)>, StoreError> {
+        match self.manifest(target, content_hash).await? {
+            Some(manifest) => {
+                let length = manifest.iter().map(|c| c.length).sum();
+                let written = manifest.iter().map(|c| c.create_dt.timestamp()).max();
+                Ok(Some((length, written)))
+            }
+            None => self.inner.find_blob_written(target, content_hash).await,
+        }
+    }
+
+    async fn list_objects(
+        &self,
+        target: &Target,
+        continuation: Option<String>,
+    ) -> Result<(Vec<(String, i64)>, Option<String>), StoreError> {
+        self.inner.list_objects(target, continuation).await
+    }
+
+    /// Deletes the BLOB's manifest, and its chunks which no other BLOB has. A chunk which another
+    /// upload is about to list in its manifest fails that upload's foreign key once it's deleted,
+    /// so no manifest lists a chunk whose object is gone.
+    async fn delete_blob(&self, target: &Target, content_hash: Hash) -> Result<(), StoreError> {
+        let hex = content_hash.to_hex();
+        let mut tx = self.db.begin().await?;
+        let chunk_ids = query_scalar!(
+            r#"
+            DELETE FROM blob_manifests
+            WHERE content_hash = $1
+                AND coalesce(storage_region, '') = coalesce($2, '')
+                AND coalesce(storage_bucket, '') = coalesce($3, '')
+            RETURNING chunk_id
+            "#,
+            hex.as_str(),
+            target.region.as_deref(),
+            target.bucket.as_deref(),
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        let orphans = query_as!(
+            Orphan,
+            r#"
+            DELETE FROM blob_chunks c
+            WHERE c.id = ANY($1)
+                AND NOT EXISTS (SELECT 1 FROM blob_manifests m WHERE m.chunk_id = c.id)
+            RETURNING c.chunk_hash, c.storage_prefix
+            "#,
+            &chunk_ids,
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        for orphan in orphans {
+            let hash = Hash::from_hex(&orphan.chunk_hash)?;
+            self.inner
+                .delete_blob(&chunk_target(target, orphan.storage_prefix), hash)
+                .await?;
+        }
+
+        // The BLOB may have been stored whole too, before it was chunked.
+        self.inner.delete_blob(target, content_hash).await
+    }
+
+    async fn presign_get(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        expires_in: Duration,
+    ) -> Result<Option<String>, StoreError> {
+        match self.manifest(target, content_hash).await? {
+            Some(_) => Ok(None),
+            None => {
+                self.inner
+                    .presign_get(target, content_hash, expires_in)
+                    .await
+            }
+        }
+    }
+
+    async fn presign_staged_put(
+        &self,
+        target: &Target,
+        upload_id: Uuid,
+        content_length: i64,
+        expires_in: Duration,
+    ) -> Result<Option<PresignedPut>, StoreError> {
+        self.inner
+            .presign_staged_put(target, upload_id, content_length, expires_in)
+            .await
+    }
+
+    async fn retrieve_staged(
+        &self,
+        target: &Target,
+        upload_id: Uuid,
+    ) -> Result<ByteStream, StoreError> {
+        self.inner.retrieve_staged(target, upload_id).await
+    }
+
+    async fn promote_staged(
+        &self,
+        target: &Target,
+        upload_id: Uuid,
+        content_hash: Hash,
+    ) -> Result<(), StoreError> {
+        self.inner
+            .promote_staged(target, upload_id, content_hash)
+            .await
+    }
+
+    async fn delete_staged(&self, target: &Target, upload_id: Uuid) -> Result<(), StoreError> {
+        self.inner.delete_staged(target, upload_id).await
+    }
+}
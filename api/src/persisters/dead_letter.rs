@@ -0,0 +1,366 @@
+//! Dead letters: persists which failed after a BLOB's object was stored but before its row was
+//! recorded, or whose object never arrived. See [`DeadLetterOp`].
+use crate::middlewares::auth::Auth;
+use crate::models::dead_letter::{DeadLetter, DeadLetterError, DeadLetterOp};
+use crate::persisters::s3store::Target;
+use crate::persisters::user::{is_admin, user_id};
+use crate::persisters::{Persist, Query};
+use crate::state::State;
+use blake3::Hash;
+use sqlx::types::Uuid;
+
+/// The admin's user id.
+async fn require_admin(auth: Option<&Auth>, state: &State) -> Result<Uuid, DeadLetterError> {
+    let auth = auth.ok_or(DeadLetterError::Unauthorized)?;
+
+    if !is_admin(auth, state).await? {
+        return Err(DeadLetterError::Forbidden);
+    }
+
+    Ok(user_id(auth, state).await?)
+}
+
+fn default_limit() -> i64 {
+    100
+}
+
+/// Lists dead letters, most recent first.
+#[derive(Deserialize, Debug)]
+pub struct DeadLettersGet {
+    /// Only list dead letters with the status, e.g. `open`.
+    pub status: Option<String>,
+    pub kind: Option<String>,
+    pub user_id: Option<Uuid>,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+pub struct DeadLetterGet {
+    pub id: i64,
+}
+
+/// Finishes what an open dead letter's persist was doing, as its user, returning the dead letter
+/// as it's left. If it fails again, the error is recorded and it stays open.
+pub struct DeadLetterRedrive {
+    pub id: i64,
+}
+
+/// Closes an open dead letter without re-driving it.
+pub struct DeadLetterDismiss {
+    pub id: i64,
+}
+
+/// A persist which failed partway.
+pub struct DeadLetterInsert {
+    pub content_hash: String,
+    pub target: Target,
+    pub op: DeadLetterOp,
+    pub error: String,
+}
+
+async fn fetch(state: &State, id: i64) -> Result<DeadLetter, DeadLetterError> {
+    let res = query_as!(
+        DeadLetter,
+        r#"
+        SELECT id, user_id, kind, content_hash, storage_region, storage_bucket, storage_prefix,
+            detail, error, status, attempts, create_dt, resolve_dt
+        FROM dead_letters
+        WHERE id = $1
+        "#,
+        id,
+    )
+    .fetch_one(&state.db_conn)
+    .await?;
+
+    Ok(res)
+}
+
+#[async_trait]
+impl Query for DeadLettersGet {
+    type Resolve = Vec<DeadLetter>;
+    type Error = DeadLetterError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        require_admin(auth, state).await?;
+
+        let res = query_as!(
+            DeadLetter,
+            r#"
+            SELECT id, user_id, kind, content_hash, storage_region, storage_bucket,
+                storage_prefix, detail, error, status, attempts, create_dt, resolve_dt
+            FROM dead_letters
+            WHERE ($1::text IS NULL OR status = $1)
+                AND ($2::text IS NULL OR kind = $2)
+                AND ($3::uuid IS NULL OR user_id = $3)
+            ORDER BY create_dt DESC
+            LIMIT $4
+            "#,
+            self.status,
+            self.kind,
+            self.user_id,
+            self.limit,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for DeadLetterGet {
+    type Resolve = DeadLetter;
+    type Error = DeadLetterError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        require_admin(auth, state).await?;
+        fetch(state, self.id).await
+    }
+}
+
+#[async_trait]
+impl Persist for DeadLetterRedrive {
+    type Ret = DeadLetter;
+    type Error = DeadLetterError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let admin_id = require_admin(auth, state).await?;
+        let letter = fetch(state, self.id).await?;
+        if letter.status != "open" {
+            return Err(DeadLetterError::Resolved);
+        }
+
+        if let Err(e) = redrive(state, &letter, admin_id).await {
+            query!(
+                r#"
+                UPDATE dead_letters
+                SET attempts = attempts + 1, error = $2
+                WHERE id = $1
+                "#,
+                letter.id,
+                format!("{:?}", e),
+            )
+            .execute(&state.db_conn)
+            .await?;
+            return Err(e);
+        }
+
+        fetch(state, self.id).await
+    }
+}
+
+/// Records the BLOB's row as available, along with whatever else the persist would have recorded,
+/// once its object is found to be stored.
+async fn redrive(
+    state: &State,
+    letter: &DeadLetter,
+    admin_id: Uuid,
+) -> Result<(), DeadLetterError> {
+    let op: DeadLetterOp =
+        serde_json::from_value(letter.detail.clone()).map_err(|e| sqlx::Error::Decode(e.into()))?;
+    let hash = Hash::from_hex(&letter.content_hash).map_err(|e| sqlx::Error::Decode(e.into()))?;
+    let target = Target {
+        region: letter.storage_region.clone(),
+        bucket: letter.storage_bucket.clone(),
+        prefix: letter.storage_prefix.clone(),
+    };
+    if state.blob_store.find_blob(&target, hash).await?.is_none() {
+        return Err(DeadLetterError::NotStored);
+    }
+
+    let mut tx = state.db_conn.begin().await?;
+    let blob_id = query_scalar!(
+        r#"
+        WITH object AS (
+            INSERT INTO blob_objects (content_hash, storage_region, storage_bucket, storage_prefix)
+            VALUES ($2, $3, $4, $5)
+            ON CONFLICT (content_hash, coalesce(storage_region, ''), coalesce(storage_bucket, ''))
+                DO UPDATE SET used_dt = now()
+            RETURNING id
+        )
+        INSERT INTO blobs (user_id, content_hash, storage_region, storage_bucket, storage_prefix,
+            object_id, object_status, checked_dt)
+        SELECT $1, $2, $3, $4, $5, object.id, 'available', now()
+        FROM object
+        ON CONFLICT (user_id, content_hash) DO UPDATE
+            SET storage_region = EXCLUDED.storage_region,
+                storage_bucket = EXCLUDED.storage_bucket,
+                storage_prefix = EXCLUDED.storage_prefix,
+                object_id = EXCLUDED.object_id,
+                object_status = EXCLUDED.object_status,
+                checked_dt = EXCLUDED.checked_dt
+        RETURNING id
+        "#,
+        letter.user_id,
+        letter.content_hash,
+        letter.storage_region,
+        letter.storage_bucket,
+        letter.storage_prefix,
+    )
+    .fetch_one(&mut tx)
+    .await?;
+
+    match op {
+        DeadLetterOp::Blob | DeadLetterOp::MissingBlob => {}
+        DeadLetterOp::DvcObject { md5 } => {
+            query!(
+                r#"
+                INSERT INTO dvc_objects (user_id, md5, blob_id)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (user_id, md5) DO UPDATE
+                    SET blob_id = EXCLUDED.blob_id
+                "#,
+                letter.user_id,
+                md5,
+                blob_id,
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+        DeadLetterOp::RunArtifact { run_id, path } => {
+            let res = query!(
+                r#"
+                INSERT INTO run_artifacts (run_id, path, blob_id)
+                SELECT id, $3, $4
+                FROM runs
+                WHERE id = $1 AND user_id = $2
+                ON CONFLICT (run_id, path) DO UPDATE
+                    SET blob_id = EXCLUDED.blob_id,
+                        create_dt = current_timestamp
+                "#,
+                run_id,
+                letter.user_id,
+                path,
+                blob_id,
+            )
+            .execute(&mut tx)
+            .await?;
+            if res.rows_affected() == 0 {
+                return Err(DeadLetterError::RunDeleted);
+            }
+        }
+    }
+
+    query!(
+        r#"
+        UPDATE dead_letters
+        SET status = 'redriven', attempts = attempts + 1, resolve_dt = now(), resolved_by = $2
+        WHERE id = $1
+        "#,
+        letter.id,
+        admin_id,
+    )
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl Persist for DeadLetterDismiss {
+    type Ret = ();
+    type Error = DeadLetterError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let admin_id = require_admin(auth, state).await?;
+
+        let res = query!(
+            r#"
+            UPDATE dead_letters
+            SET status = 'dismissed', resolve_dt = now(), resolved_by = $2
+            WHERE id = $1 AND status = 'open'
+            "#,
+            self.id,
+            admin_id,
+        )
+        .execute(&state.db_conn)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            // Either there's no such dead letter, or it's already closed.
+            fetch(state, self.id).await?;
+            return Err(DeadLetterError::Resolved);
+        }
+
+        Ok(())
+    }
+}
+
+/// Records a persist by the authenticated user which failed partway. Recording never fails the
+/// request any further: if the dead letter can't be recorded (most likely because the database
+/// is what failed), everything in it is logged instead.
+pub async fn record(state: &State, auth: &Auth, letter: DeadLetterInsert) {
+    match user_id(auth, state).await {
+        Ok(user_id) => record_for(state, user_id, letter).await,
+        Err(e) => log::error!(
+            "could not record dead letter {:?} for blob {} in {:?} ({}): {:?}",
+            letter.op,
+            letter.content_hash,
+            letter.target,
+            letter.error,
+            e
+        ),
+    }
+}
+
+/// Records a persist by the user which failed partway, as [`record`] does, and sends it to the
+/// dead letter webhook if there is one. A persist which already has an open dead letter isn't
+/// recorded again.
+pub async fn record_for(state: &State, user_id: Uuid, letter: DeadLetterInsert) {
+    let detail = serde_json::to_value(&letter.op).unwrap_or_default();
+    let res = query_as!(
+        DeadLetter,
+        r#"
+        INSERT INTO dead_letters (user_id, kind, content_hash, storage_region, storage_bucket,
+            storage_prefix, detail, error)
+        SELECT $1, $2, $3, $4, $5, $8, $6, $7
+        WHERE NOT EXISTS (
+            SELECT 1 FROM dead_letters
+            WHERE user_id = $1 AND content_hash = $3 AND detail = $6 AND status = 'open'
+        )
+        RETURNING id, user_id, kind, content_hash, storage_region, storage_bucket, storage_prefix,
+            detail, error, status, attempts, create_dt, resolve_dt
+        "#,
+        user_id,
+        letter.op.as_str(),
+        letter.content_hash,
+        letter.target.region,
+        letter.target.bucket,
+        detail,
+        letter.error,
+        letter.target.prefix,
+    )
+    .fetch_optional(&state.db_conn)
+    .await;
+
+    let dead_letter = match res {
+        Ok(Some(dead_letter)) => dead_letter,
+        Ok(None) => return,
+        Err(e) => {
+            log::error!(
+                "could not record dead letter {:?} for blob {} of user {} in {:?} ({}): {:?}",
+                letter.op,
+                letter.content_hash,
+                user_id,
+                letter.target,
+                letter.error,
+                e
+            );
+            return;
+        }
+    };
+
+    log::warn!(
+        "recorded dead letter {} ({}) for blob {}: {}",
+        dead_letter.id,
+        dead_letter.kind,
+        dead_letter.content_hash,
+        dead_letter.error
+    );
+    if let Some(url) = &state.config.dead_letter_webhook_url {
+        if let Err(e) = state.notifier.operator_webhook(url, &dead_letter).await {
+            log::warn!("could not send dead letter {}: {:?}", dead_letter.id, e);
+        }
+    }
+}
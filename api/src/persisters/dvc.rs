@@ -0,0 +1,148 @@
+use crate::middlewares::auth::Auth;
+use crate::models::dead_letter::DeadLetterOp;
+use crate::models::dvc::DvcError;
+use crate::persisters::dead_letter::{self, DeadLetterInsert};
+use crate::persisters::s3store::{store_shared, upload_target, Stored, Target};
+use crate::persisters::{blob::upsert_blob, Persist, Query};
+use crate::state::State;
+
+use aws_sdk_s3::types::ByteStream;
+use blake3::Hash;
+
+/// Checks whether the user's DVC remote holds the object with the given MD5.
+pub struct DvcObjectHead {
+    pub md5: String,
+}
+
+/// Downloads the object with the given MD5.
+pub struct DvcObjectGet {
+    pub md5: String,
+}
+
+/// Uploads an object, claimed by the client to have the given MD5.
+pub struct DvcObjectPut {
+    pub md5: String,
+    pub bytes: bytes::Bytes,
+}
+
+struct ContentHashResult {
+    content_hash: String,
+    storage_region: Option<String>,
+    storage_bucket: Option<String>,
+    storage_prefix: Option<String>,
+}
+
+/// The content hash of the object with the given MD5, and where its BLOB is stored.
+async fn content_hash(md5: &str, auth: &Auth, state: &State) -> Result<(String, Target), DvcError> {
+    let res = query_as!(
+        ContentHashResult,
+        r#"
+        SELECT b.content_hash, b.storage_region, b.storage_bucket, b.storage_prefix
+        FROM dvc_objects d
+        JOIN blobs b
+            ON b.id = d.blob_id
+        WHERE d.md5 = $1
+            AND d.user_id = get_user_id($2, $3)
+        "#,
+        md5,
+        auth.jwt().map(|c| c.sub),
+        auth.api_key(),
+    )
+    .fetch_one(&state.db_conn)
+    .await?;
+
+    let target = Target {
+        region: res.storage_region,
+        bucket: res.storage_bucket,
+        prefix: res.storage_prefix,
+    };
+    Ok((res.content_hash, target))
+}
+
+#[async_trait]
+impl Query for DvcObjectHead {
+    type Resolve = ();
+    type Error = DvcError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(DvcError::Unauthorized)?;
+        content_hash(&self.md5, auth, state).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Query for DvcObjectGet {
+    type Resolve = ByteStream;
+    type Error = DvcError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(DvcError::Unauthorized)?;
+        let (content_hash, target) = content_hash(&self.md5, auth, state).await?;
+        let hash = Hash::from_hex(&content_hash).map_err(|_| DvcError::InvalidPath)?;
+        Ok(state.blob_store.retrieve_blob(&target, hash).await?)
+    }
+}
+
+#[async_trait]
+impl Persist for DvcObjectPut {
+    type Ret = ();
+    type Error = DvcError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(DvcError::Unauthorized)?;
+
+        // Objects with the same contents share a BLOB, whatever MD5 they were uploaded under.
+        let hash = blake3::hash(&self.bytes);
+        let target = upload_target(auth, state).await?;
+        let (target, stored) = store_shared(state, target, hash, self.bytes).await?;
+
+        let content_hash = hash.to_hex().to_string();
+        if let Err(e) = record_object(auth, &self.md5, &content_hash, &target, stored, state).await
+        {
+            let letter = DeadLetterInsert {
+                content_hash,
+                target,
+                op: DeadLetterOp::DvcObject { md5: self.md5 },
+                error: e.to_string(),
+            };
+            dead_letter::record(state, auth, letter).await;
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Records the object's BLOB, and the MD5 it was uploaded under, once the BLOB is stored.
+async fn record_object(
+    auth: &Auth,
+    md5: &str,
+    content_hash: &str,
+    target: &Target,
+    stored: Option<Stored>,
+    state: &State,
+) -> Result<(), sqlx::Error> {
+    let mut tx = state.db_conn.begin().await?;
+
+    let blob_id = upsert_blob(&mut tx, auth, content_hash, target, stored).await?;
+
+    query!(
+        r#"
+        INSERT INTO dvc_objects (user_id, md5, blob_id)
+        VALUES (get_user_id($1, $2), $3, $4)
+        ON CONFLICT (user_id, md5) DO UPDATE
+            SET blob_id = EXCLUDED.blob_id
+        "#,
+        auth.jwt().map(|c| c.sub),
+        auth.api_key(),
+        md5,
+        blob_id,
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
@@ -1,17 +1,38 @@
-use crate::handlers::eval::Params;
+use crate::cache::CacheKey;
+use crate::embed::{to_vector_literal, EmbedError};
+use crate::handlers::eval::{Params, SearchParams, StatusParams, UsageParams};
 use crate::middlewares::auth::Auth;
-use crate::models::eval::{Eval, EvalError};
-use crate::persisters::s3store::BlobMetadata;
+use crate::models::anomaly::Activity;
+use crate::models::blob_backfill::DEFERRED_PAYLOAD_HOURS;
+use crate::models::change::ChangeKind;
+use crate::models::eval::{
+    compare_args, ranges_within, CacheReport, Eval, EvalError, EvalImport, EvalPut, EvalValidation,
+    FnStatus, FnUsage, MissReason, PayloadUpload, Resolution, SimilarEval, Suggestion,
+    ValidatedEval, ValidationRejection, EVAL_CLAIM_LOCK, EVAL_PUT_LOCK, MAX_MULTI_EVALS,
+    MAX_REPORTS, MAX_SIMILAR, MAX_STATUS_FN_KEYS, MAX_SUGGESTIONS, SUGGESTION_CANDIDATES,
+};
+use crate::models::run::Run;
+use crate::persisters::anomaly::record_activity;
+use crate::persisters::change::record_changes;
+use crate::persisters::hold::evals_held;
+use crate::persisters::metric::{derive_metrics, derive_metrics_many};
+use crate::persisters::s3store::{BlobMetadata, Target};
+use crate::persisters::user::user_id;
 use crate::persisters::{Persist, Query};
+use crate::pgcopy::BinaryCopyWriter;
+use crate::policy::{self, Action, PolicyError, Request};
 use crate::state::State;
 use actix_web::web;
+use blake3::Hash;
 use sqlx::{
     types::{
         chrono::{DateTime, Utc},
         JsonValue, Uuid,
     },
-    Error,
+    Error, Postgres, Transaction,
 };
+use std::collections::HashSet;
+use std::time::Duration;
 
 impl From<Error> for EvalError {
     fn from(e: Error) -> Self {
@@ -19,6 +40,24 @@ impl From<Error> for EvalError {
     }
 }
 
+impl From<EmbedError> for EvalError {
+    fn from(e: EmbedError) -> Self {
+        match e {
+            EmbedError::Disabled => Self::SimilarityDisabled,
+            _ => Self::Embedding(e),
+        }
+    }
+}
+
+impl From<PolicyError> for EvalError {
+    fn from(e: PolicyError) -> Self {
+        match e {
+            PolicyError::Sqlx(e) => Self::Sqlx(e),
+            _ => Self::Forbidden,
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct EvalInsert {
     pub fn_key: String,
@@ -31,6 +70,16 @@ pub struct EvalInsert {
     pub is_experiment: bool,
     pub start_time: DateTime<Utc>,
     pub elapsed_process_time: i64,
+    /// The name of the project the eval belongs to. The project is created if it doesn't exist.
+    #[serde(default)]
+    pub project: Option<String>,
+    /// The kernel session the eval was computed in, when computed from a notebook.
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// Whether the result's BLOB is to be uploaded later, once it's been serialized, rather than
+    /// straight after the eval is put. It's then given longer to arrive.
+    #[serde(default)]
+    pub deferred: bool,
 }
 
 struct EvalInsertResult {
@@ -41,6 +90,141 @@ struct BlobInsertResult {
     id: Option<i64>,
 }
 
+struct ProjectInsertResult {
+    id: Uuid,
+    index_results: bool,
+}
+
+impl EvalInsert {
+    fn payload_upload(&self, due_dt: DateTime<Utc>) -> PayloadUpload {
+        PayloadUpload {
+            content_hash: self.content_hash.clone(),
+            content_length: self.content_length,
+            path: "/blob".to_string(),
+            presign_path: "/blob/presign-upload".to_string(),
+            due_dt,
+        }
+    }
+
+    fn validated(&self) -> ValidatedEval<'_> {
+        ValidatedEval {
+            fn_key: &self.fn_key,
+            fn_hash: &self.fn_hash,
+            args: self.args.as_ref(),
+            args_hash: &self.args_hash,
+            result_json: &self.result_json,
+            content_hash: &self.content_hash,
+            content_length: self.content_length,
+            blob_offset: None,
+            blob_length: None,
+            is_experiment: self.is_experiment,
+            start_time: self.start_time,
+            elapsed_process_time: self.elapsed_process_time,
+        }
+    }
+}
+
+/// Marks a BLOB as awaiting the upload of an eval put with `deferred`, unless it's already stored,
+/// returning when it's due.
+async fn await_payload(
+    tx: &mut Transaction<'_, Postgres>,
+    blob_id: i64,
+) -> Result<Option<DateTime<Utc>>, Error> {
+    query_scalar!(
+        r#"
+        UPDATE blobs
+        SET object_status = 'awaiting_payload',
+            payload_due_dt = greatest(payload_due_dt, now() + make_interval(hours => $2))
+        WHERE id = $1
+            AND object_status <> 'available'
+        RETURNING payload_due_dt AS "payload_due_dt!"
+        "#,
+        blob_id,
+        DEFERRED_PAYLOAD_HOURS,
+    )
+    .fetch_optional(tx)
+    .await
+}
+
+/// A project's validation webhook.
+struct ValidationHook {
+    id: Uuid,
+    webhook_url: String,
+    timeout_ms: i32,
+    fail_open: bool,
+}
+
+/// Sends evals being put into a project to its validation webhook, if it has one, before they're
+/// stored. Fails if the webhook rejects them, or if it fails and the project refuses evals when it
+/// does.
+async fn validate(
+    auth: &Auth,
+    state: &State,
+    project: Option<&str>,
+    evals: Vec<ValidatedEval<'_>>,
+) -> Result<(), EvalError> {
+    let project = match project {
+        Some(project) => project,
+        None => return Ok(()),
+    };
+    let hook = query_as!(
+        ValidationHook,
+        r#"
+        SELECT id, validation_webhook_url AS "webhook_url!",
+            validation_timeout_ms AS timeout_ms, validation_fail_open AS fail_open
+        FROM projects
+        WHERE user_id = get_user_id($1, $2)
+            AND name = $3
+            AND validation_webhook_url IS NOT NULL
+        "#,
+        auth.jwt().map(|c| c.sub),
+        auth.api_key(),
+        project,
+    )
+    .fetch_optional(&state.db_conn)
+    .await?;
+    let hook = match hook {
+        Some(hook) => hook,
+        None => return Ok(()),
+    };
+
+    let event = EvalValidation {
+        event: "eval_validation",
+        project_id: hook.id,
+        project,
+        evals,
+    };
+    let timeout = Duration::from_millis(hook.timeout_ms as u64);
+    let failure = match state
+        .notifier
+        .call(&hook.webhook_url, &event, timeout)
+        .await
+    {
+        Ok(res) if res.status().is_success() => return Ok(()),
+        Ok(res) if res.status().is_client_error() => {
+            let rejection = res.json::<ValidationRejection>().await.unwrap_or_default();
+            return Err(EvalError::Rejected(rejection.reason));
+        }
+        Ok(res) => format!("answered {}", res.status()),
+        Err(e) => format!("{:?}", e),
+    };
+
+    if hook.fail_open {
+        log::warn!(
+            "validation webhook of project {} failed, storing evals anyway: {}",
+            hook.id,
+            failure
+        );
+        return Ok(());
+    }
+    log::warn!(
+        "validation webhook of project {} failed, refusing evals: {}",
+        hook.id,
+        failure
+    );
+    Err(EvalError::ValidationFailed)
+}
+
 impl BlobMetadata for EvalInsert {
     fn content_length(&self) -> i64 {
         self.content_length
@@ -52,18 +236,93 @@ impl BlobMetadata for EvalInsert {
 
 #[async_trait]
 impl Persist for EvalInsert {
-    type Ret = Uuid;
+    type Ret = EvalPut;
     type Error = EvalError;
 
     async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
-        let api_key = auth
-            .ok_or(EvalError::Unauthorized)?
-            .api_key()
-            .ok_or(EvalError::Unauthorized)?;
+        let auth = auth.ok_or(EvalError::Unauthorized)?;
+        policy::authorize(
+            auth,
+            Request::in_project(Action::EvalWrite, self.project.as_deref()),
+            state,
+        )
+        .await?;
+        let api_key = auth.api_key().ok_or(EvalError::Unauthorized)?;
+
+        // The webhook is called before anything is locked, so that a slow one holds nothing up.
+        validate(auth, state, self.project.as_deref(), vec![self.validated()]).await?;
 
         // Use a transaction as we have to modify two tables.
         let mut tx = state.db_conn.begin().await?;
 
+        // Serialize puts of the same eval, so that concurrent retries don't both insert it.
+        // `pg_advisory_xact_lock` returns `void`, which sqlx can't decode, so it's called in `FROM`.
+        query_scalar!(
+            r#"
+            SELECT true AS "locked!"
+            FROM pg_advisory_xact_lock($1, hashtext(
+                user_from_key($2)::text || ':' || $3 || ':' || $4 || ':' || $5
+            ))
+            "#,
+            EVAL_PUT_LOCK,
+            api_key,
+            self.fn_key,
+            self.fn_hash,
+            self.args_hash,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        // A retried put of an eval which is already stored with the same result writes nothing.
+        let latest = query!(
+            r#"
+            SELECT e.id, e.blob_id, b.content_hash, b.storage_region, b.storage_bucket,
+                b.storage_prefix, b.stored_length
+            FROM evals e
+            JOIN blobs b ON b.id = e.blob_id
+            WHERE e.user_id = user_from_key($1)
+            AND e.fn_key = $2
+            AND e.fn_hash = $3
+            AND e.args_hash = $4
+            ORDER BY e.start_time DESC
+            LIMIT 1
+            "#,
+            api_key,
+            self.fn_key,
+            self.fn_hash,
+            self.args_hash,
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+
+        let change = match latest {
+            Some(latest) if latest.content_hash == self.content_hash => {
+                // A retried deferred put still awaits the BLOB, and gives it longer to arrive.
+                let due_dt = match self.deferred {
+                    true => await_payload(&mut tx, latest.blob_id).await?,
+                    false => None,
+                };
+                tx.commit().await?;
+                let target = Target {
+                    region: latest.storage_region,
+                    bucket: latest.storage_bucket,
+                    prefix: latest.storage_prefix,
+                };
+                // A compressed BLOB's object is shorter than the BLOB.
+                let stored_length = latest.stored_length.unwrap_or(self.content_length);
+                let replayed = blob_stored(&target, &self.content_hash, stored_length, state).await;
+                return Ok(EvalPut {
+                    id: latest.id,
+                    replayed,
+                    upload: due_dt
+                        .filter(|_| !replayed)
+                        .map(|due_dt| self.payload_upload(due_dt)),
+                });
+            }
+            Some(_) => ChangeKind::Invalidated,
+            None => ChangeKind::Inserted,
+        };
+
         // Insert blob.
         let blob_res = query_as!(
             BlobInsertResult,
@@ -90,11 +349,32 @@ impl Persist for EvalInsert {
         .fetch_one(&mut tx)
         .await?;
 
+        // Resolve the project, creating it on first use.
+        let project = match &self.project {
+            Some(name) => Some(
+                query_as!(
+                    ProjectInsertResult,
+                    r#"
+                    INSERT INTO projects (user_id, name)
+                    VALUES (user_from_key($1), $2)
+                    ON CONFLICT (user_id, name) DO UPDATE
+                        SET name = EXCLUDED.name
+                    RETURNING id, index_results
+                    "#,
+                    api_key,
+                    name,
+                )
+                .fetch_one(&mut tx)
+                .await?,
+            ),
+            None => None,
+        };
+
         // Insert new eval.
         // NOTE: the "ON CONFLICT" clause in the below query would prevent insertions if the row
         // already existed and caused a conflict. But we don't get conflicts right now because
         // there is now unique constraint enforced across the three critical rows (fn_key, fn_hash,
-        // args_hash).
+        // args_hash). Puts of an eval with the same result are short-circuited above instead.
         // TODO: but what if you attempt an upsert which changes the value of `is_experiment`?!?
         let eval_res = query_as!(
             EvalInsertResult,
@@ -108,8 +388,8 @@ impl Persist for EvalInsert {
                 AND args_hash = $4
             ), i AS (
                 INSERT INTO evals (fn_key, fn_hash, args, args_hash, result_json, is_experiment, start_time, 
-                    elapsed_process_time, blob_id, user_id) 
-                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, user_from_key($10))
+                    elapsed_process_time, blob_id, user_id, project_id, result_indexed, session_id) 
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, user_from_key($10), $11, $12, $13)
                 ON CONFLICT DO NOTHING
                 RETURNING id
             )
@@ -127,18 +407,761 @@ impl Persist for EvalInsert {
             self.start_time,
             self.elapsed_process_time,
             blob_res.id.expect("huh"),
-            api_key
+            api_key,
+            project.as_ref().map(|p| p.id),
+            project.as_ref().map_or(false, |p| p.index_results),
+            self.session_id,
         )
         .fetch_one(&mut tx)
         .await?;
 
+        let eval_id = eval_res.id.expect("huh");
+
+        let due_dt = match self.deferred {
+            true => await_payload(&mut tx, blob_res.id.expect("huh")).await?,
+            false => None,
+        };
+
+        // Record any metrics the project's rules derive from the result.
+        if project.is_some() {
+            derive_metrics(&mut tx, eval_id).await?;
+        }
+
+        record_changes(&mut tx, change, &[eval_id]).await?;
+
         // Commit transaction.
         tx.commit().await?;
 
-        Ok(eval_res.id.expect("huh"))
+        Ok(EvalPut {
+            id: eval_id,
+            replayed: false,
+            upload: due_dt.map(|due_dt| self.payload_upload(due_dt)),
+        })
+    }
+}
+
+/// An eval put along with others, whose result is the range of their shared BLOB starting at
+/// `offset`.
+#[derive(Deserialize, Debug)]
+pub struct EvalPart {
+    pub fn_key: String,
+    pub fn_hash: String,
+    pub args: Option<JsonValue>,
+    pub args_hash: String,
+    pub result_json: JsonValue,
+    pub is_experiment: bool,
+    pub start_time: DateTime<Utc>,
+    pub elapsed_process_time: i64,
+    #[serde(default)]
+    pub session_id: Option<String>,
+    pub offset: i64,
+    pub length: i64,
+}
+
+/// Puts several evals, e.g. the named outputs of one function, whose results are ranges of one
+/// BLOB, uploaded once for all of them. The evals are put together, or not at all.
+#[derive(Deserialize, Debug)]
+pub struct EvalMultiInsert {
+    pub content_hash: String,
+    pub content_length: i64,
+    #[serde(default)]
+    pub project: Option<String>,
+    pub evals: Vec<EvalPart>,
+}
+
+impl BlobMetadata for EvalMultiInsert {
+    fn content_length(&self) -> i64 {
+        self.content_length
+    }
+    fn content_hash(&self) -> &str {
+        &self.content_hash
+    }
+}
+
+impl EvalMultiInsert {
+    /// Checks the evals before their BLOB is uploaded: each result has to be within the BLOB, and
+    /// each eval of a different function or arguments.
+    pub fn check(&self) -> Result<(), EvalError> {
+        let ranges = self
+            .evals
+            .iter()
+            .map(|e| (e.offset, e.length))
+            .collect::<Vec<_>>();
+        let keys = self
+            .evals
+            .iter()
+            .map(|e| (&e.fn_key, &e.fn_hash, &e.args_hash))
+            .collect::<HashSet<_>>();
+        if self.evals.is_empty()
+            || self.evals.len() > MAX_MULTI_EVALS
+            || keys.len() != self.evals.len()
+            || !ranges_within(self.content_length, &ranges)
+        {
+            return Err(EvalError::InvalidMulti);
+        }
+
+        Ok(())
+    }
+
+    /// Sends the evals to their project's validation webhook, if it has one, before their BLOB is
+    /// uploaded.
+    pub async fn validate(&self, auth: &Auth, state: &State) -> Result<(), EvalError> {
+        let evals = self
+            .evals
+            .iter()
+            .map(|e| ValidatedEval {
+                fn_key: &e.fn_key,
+                fn_hash: &e.fn_hash,
+                args: e.args.as_ref(),
+                args_hash: &e.args_hash,
+                result_json: &e.result_json,
+                content_hash: &self.content_hash,
+                content_length: self.content_length,
+                blob_offset: Some(e.offset),
+                blob_length: Some(e.length),
+                is_experiment: e.is_experiment,
+                start_time: e.start_time,
+                elapsed_process_time: e.elapsed_process_time,
+            })
+            .collect();
+        validate(auth, state, self.project.as_deref(), evals).await
+    }
+}
+
+#[async_trait]
+impl Persist for EvalMultiInsert {
+    type Ret = Vec<Uuid>;
+    type Error = EvalError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(EvalError::Unauthorized)?;
+        policy::authorize(
+            auth,
+            Request::in_project(Action::EvalWrite, self.project.as_deref()),
+            state,
+        )
+        .await?;
+        let api_key = auth.api_key().ok_or(EvalError::Unauthorized)?;
+        self.check()?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        // Serialize puts of the same evals, as single puts are. The locks are taken in order, so
+        // that puts sharing some evals can't deadlock.
+        let mut keys = self
+            .evals
+            .iter()
+            .map(|e| (&e.fn_key, &e.fn_hash, &e.args_hash))
+            .collect::<Vec<_>>();
+        keys.sort();
+        for (fn_key, fn_hash, args_hash) in keys {
+            query_scalar!(
+                r#"
+                SELECT true AS "locked!"
+                FROM pg_advisory_xact_lock($1, hashtext(
+                    user_from_key($2)::text || ':' || $3 || ':' || $4 || ':' || $5
+                ))
+                "#,
+                EVAL_PUT_LOCK,
+                api_key,
+                fn_key,
+                fn_hash,
+                args_hash,
+            )
+            .fetch_one(&mut tx)
+            .await?;
+        }
+
+        let blob_id = query_scalar!(
+            r#"
+            INSERT INTO blobs (user_id, content_hash, content_length)
+            VALUES (user_from_key($1), $2, $3)
+            ON CONFLICT (user_id, content_hash) DO UPDATE
+                SET content_length = coalesce(blobs.content_length, EXCLUDED.content_length)
+            RETURNING id
+            "#,
+            api_key,
+            self.content_hash,
+            self.content_length,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        let project = match &self.project {
+            Some(name) => Some(
+                query_as!(
+                    ProjectInsertResult,
+                    r#"
+                    INSERT INTO projects (user_id, name)
+                    VALUES (user_from_key($1), $2)
+                    ON CONFLICT (user_id, name) DO UPDATE
+                        SET name = EXCLUDED.name
+                    RETURNING id, index_results
+                    "#,
+                    api_key,
+                    name,
+                )
+                .fetch_one(&mut tx)
+                .await?,
+            ),
+            None => None,
+        };
+
+        let mut ids = Vec::with_capacity(self.evals.len());
+        let mut inserted = vec![];
+        let mut invalidated = vec![];
+        for part in self.evals {
+            // An eval which already has this result, at the same place, is left as it is.
+            let latest = query!(
+                r#"
+                SELECT id, blob_id, blob_offset, blob_length
+                FROM evals
+                WHERE user_id = user_from_key($1)
+                    AND fn_key = $2
+                    AND fn_hash = $3
+                    AND args_hash = $4
+                ORDER BY start_time DESC
+                LIMIT 1
+                "#,
+                api_key,
+                part.fn_key,
+                part.fn_hash,
+                part.args_hash,
+            )
+            .fetch_optional(&mut tx)
+            .await?;
+            let changed = match latest {
+                Some(latest)
+                    if latest.blob_id == blob_id
+                        && latest.blob_offset == Some(part.offset)
+                        && latest.blob_length == Some(part.length) =>
+                {
+                    ids.push(latest.id);
+                    continue;
+                }
+                Some(_) => &mut invalidated,
+                None => &mut inserted,
+            };
+
+            let id = query_scalar!(
+                r#"
+                INSERT INTO evals (fn_key, fn_hash, args, args_hash, result_json, is_experiment,
+                    start_time, elapsed_process_time, blob_id, user_id, project_id,
+                    result_indexed, session_id, blob_offset, blob_length)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, user_from_key($10), $11, $12, $13,
+                    $14, $15)
+                RETURNING id
+                "#,
+                part.fn_key,
+                part.fn_hash,
+                part.args,
+                part.args_hash,
+                part.result_json,
+                part.is_experiment,
+                part.start_time,
+                part.elapsed_process_time,
+                blob_id,
+                api_key,
+                project.as_ref().map(|p| p.id),
+                project.as_ref().map_or(false, |p| p.index_results),
+                part.session_id,
+                part.offset,
+                part.length,
+            )
+            .fetch_one(&mut tx)
+            .await?;
+
+            if project.is_some() {
+                derive_metrics(&mut tx, id).await?;
+            }
+            changed.push(id);
+            ids.push(id);
+        }
+
+        record_changes(&mut tx, ChangeKind::Inserted, &inserted).await?;
+        record_changes(&mut tx, ChangeKind::Invalidated, &invalidated).await?;
+        tx.commit().await?;
+
+        Ok(ids)
+    }
+}
+
+/// Whether the result BLOB of a replayed eval put is stored, so that the client needn't upload it
+/// again. Puts aren't failed for want of knowing: if the store can't be reached, the client is
+/// told to upload the BLOB, which is skipped when it is already stored. `stored_length` is the
+/// length of the BLOB's object, as it was stored.
+async fn blob_stored(
+    target: &Target,
+    content_hash: &str,
+    stored_length: i64,
+    state: &State,
+) -> bool {
+    let hash = match Hash::from_hex(content_hash) {
+        Ok(hash) => hash,
+        Err(_) => return false,
+    };
+
+    match state.blob_store.find_blob(target, hash).await {
+        Ok(length) => length == Some(stored_length),
+        Err(e) => {
+            log::warn!("could not look up a replayed eval's BLOB: {:?}", e);
+            false
+        }
+    }
+}
+
+/// Deletes the user's evals of a function, optionally only those of one version of it, or with
+/// some arguments. Their BLOBs are kept. Nothing is deleted if any of the evals is under a legal
+/// hold. Returns how many evals were deleted.
+#[derive(Deserialize, Debug)]
+pub struct EvalDelete {
+    pub fn_key: String,
+    pub fn_hash: Option<String>,
+    pub args_hash: Option<String>,
+}
+
+#[async_trait]
+impl Persist for EvalDelete {
+    type Ret = u64;
+    type Error = EvalError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(EvalError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::EvalWrite), state).await?;
+        let user_id = user_id(auth, state).await?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        let eval_ids = query_scalar!(
+            r#"
+            SELECT id
+            FROM evals
+            WHERE user_id = $1
+                AND fn_key = $2
+                AND (fn_hash = $3 OR $3 IS NULL)
+                AND (args_hash = $4 OR $4 IS NULL)
+            FOR UPDATE
+            "#,
+            user_id,
+            self.fn_key,
+            self.fn_hash,
+            self.args_hash,
+        )
+        .fetch_all(&mut tx)
+        .await?;
+
+        if eval_ids.is_empty() {
+            return Err(EvalError::NotFound(Error::RowNotFound));
+        }
+        if evals_held(&mut tx, &eval_ids).await? {
+            return Err(EvalError::Held);
+        }
+
+        record_changes(&mut tx, ChangeKind::Deleted, &eval_ids).await?;
+
+        let res = query!("DELETE FROM evals WHERE id = ANY($1)", &eval_ids)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(res.rows_affected())
+    }
+}
+
+/// Starts a bulk import of evals into a project, or into no project. The evals are then uploaded
+/// in chunks with [`EvalImportChunk`].
+#[derive(Deserialize, Debug)]
+pub struct EvalImportCreate {
+    #[serde(default)]
+    pub project: Option<String>,
+}
+
+/// Inserts a chunk of an import's evals in one transaction, skipping evals which already exist.
+/// The evals are copied into a staging table in Postgres' binary `COPY` format, and inserted from
+/// there with a single statement.
+pub struct EvalImportChunk {
+    pub import_id: Uuid,
+    pub evals: Vec<EvalInsert>,
+}
+
+/// Marks an import as done, once all of its chunks have been inserted.
+pub struct EvalImportFinish {
+    pub import_id: Uuid,
+}
+
+/// Marks an import as failed. The chunks inserted before the failure are kept.
+pub struct EvalImportFail {
+    pub import_id: Uuid,
+    pub error: String,
+}
+
+pub struct EvalImportGet {
+    pub import_id: Uuid,
+}
+
+struct ImportTarget {
+    user_id: Uuid,
+    project_id: Option<Uuid>,
+    project: Option<String>,
+}
+
+#[async_trait]
+impl Persist for EvalImportCreate {
+    type Ret = Uuid;
+    type Error = EvalError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(EvalError::Unauthorized)?;
+        policy::authorize(
+            auth,
+            Request::in_project(Action::EvalWrite, self.project.as_deref()),
+            state,
+        )
+        .await?;
+        let api_key = auth.api_key().ok_or(EvalError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        let project_id = match &self.project {
+            Some(name) => Some(
+                query_scalar!(
+                    r#"
+                    INSERT INTO projects (user_id, name)
+                    VALUES (user_from_key($1), $2)
+                    ON CONFLICT (user_id, name) DO UPDATE
+                        SET name = EXCLUDED.name
+                    RETURNING id
+                    "#,
+                    api_key,
+                    name,
+                )
+                .fetch_one(&mut tx)
+                .await?,
+            ),
+            None => None,
+        };
+
+        let id = query_scalar!(
+            r#"
+            INSERT INTO eval_imports (user_id, project_id)
+            VALUES (user_from_key($1), $2)
+            RETURNING id
+            "#,
+            api_key,
+            project_id,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(id)
+    }
+}
+
+#[async_trait]
+impl Persist for EvalImportChunk {
+    type Ret = ();
+    type Error = EvalError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(EvalError::Unauthorized)?;
+        let api_key = auth.api_key().ok_or(EvalError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        // Locking the import's row serializes concurrent uploads to it.
+        let target = query_as!(
+            ImportTarget,
+            r#"
+            UPDATE eval_imports i
+            SET status = 'running',
+                update_dt = current_timestamp
+            WHERE i.id = $1
+                AND i.user_id = user_from_key($2)
+                AND i.status IN ('pending', 'running')
+            RETURNING i.user_id, i.project_id,
+                (SELECT p.name FROM projects p WHERE p.id = i.project_id) AS "project?"
+            "#,
+            self.import_id,
+            api_key,
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(EvalError::ImportClosed)?;
+
+        // The import's project applies to all of its evals.
+        if self
+            .evals
+            .iter()
+            .any(|e| e.project.is_some() && e.project != target.project)
+        {
+            return Err(EvalError::InvalidImport);
+        }
+
+        let mut rows = BinaryCopyWriter::new();
+        for e in &self.evals {
+            rows.row(11)
+                .uuid(self.import_id)
+                .text(&e.fn_key)
+                .text(&e.fn_hash)
+                .jsonb(e.args.as_ref())
+                .text(&e.args_hash)
+                .jsonb(Some(&e.result_json))
+                .text(&e.content_hash)
+                .bool(e.is_experiment)
+                .timestamptz(e.start_time)
+                .int8(e.elapsed_process_time)
+                .opt_text(e.session_id.as_deref());
+        }
+        let mut copy = tx
+            .copy_in_raw(
+                r#"
+                COPY eval_import_rows (import_id, fn_key, fn_hash, args, args_hash, result_json,
+                    content_hash, is_experiment, start_time, elapsed_process_time, session_id)
+                FROM STDIN WITH (FORMAT binary)
+                "#,
+            )
+            .await?;
+        copy.send(rows.finish()).await?;
+        copy.finish().await?;
+
+        query!(
+            r#"
+            INSERT INTO blobs (user_id, content_hash)
+            SELECT DISTINCT $2::uuid, content_hash
+            FROM eval_import_rows
+            WHERE import_id = $1
+            ON CONFLICT DO NOTHING
+            "#,
+            self.import_id,
+            target.user_id,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        // As with single inserts, an eval which already exists is left as it is. When an eval
+        // appears more than once in the chunk, the most recent is kept.
+        let eval_ids = query_scalar!(
+            r#"
+            INSERT INTO evals (fn_key, fn_hash, args, args_hash, result_json, is_experiment,
+                start_time, elapsed_process_time, blob_id, user_id, project_id, result_indexed,
+                session_id)
+            SELECT DISTINCT ON (r.fn_key, r.fn_hash, r.args_hash)
+                r.fn_key, r.fn_hash, r.args, r.args_hash, r.result_json, r.is_experiment,
+                r.start_time, r.elapsed_process_time, b.id, $2, $3,
+                coalesce(p.index_results, false), r.session_id
+            FROM eval_import_rows r
+            JOIN blobs b
+                ON b.user_id = $2
+                AND b.content_hash = r.content_hash
+            LEFT JOIN projects p
+                ON p.id = $3
+            WHERE r.import_id = $1
+                AND NOT EXISTS (
+                    SELECT 1
+                    FROM evals e
+                    WHERE e.user_id = $2
+                        AND e.fn_key = r.fn_key
+                        AND e.fn_hash = r.fn_hash
+                        AND e.args_hash = r.args_hash
+                )
+            ORDER BY r.fn_key, r.fn_hash, r.args_hash, r.start_time DESC
+            RETURNING id
+            "#,
+            self.import_id,
+            target.user_id,
+            target.project_id,
+        )
+        .fetch_all(&mut tx)
+        .await?;
+
+        query!(
+            "DELETE FROM eval_import_rows WHERE import_id = $1",
+            self.import_id,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        if target.project_id.is_some() {
+            derive_metrics_many(&mut tx, &eval_ids).await?;
+        }
+
+        // Evals whose keys already exist are skipped, so every eval imported is a new key.
+        record_changes(&mut tx, ChangeKind::Inserted, &eval_ids).await?;
+
+        query!(
+            r#"
+            UPDATE eval_imports
+            SET rows_received = rows_received + $2,
+                rows_inserted = rows_inserted + $3,
+                chunks = chunks + 1,
+                update_dt = current_timestamp
+            WHERE id = $1
+            "#,
+            self.import_id,
+            self.evals.len() as i64,
+            eval_ids.len() as i64,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
     }
 }
 
+#[async_trait]
+impl Persist for EvalImportFinish {
+    type Ret = EvalImport;
+    type Error = EvalError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(EvalError::Unauthorized)?;
+        let api_key = auth.api_key().ok_or(EvalError::Unauthorized)?;
+
+        let res = query_as!(
+            EvalImport,
+            r#"
+            UPDATE eval_imports i
+            SET status = 'done',
+                update_dt = current_timestamp
+            WHERE i.id = $1
+                AND i.user_id = user_from_key($2)
+                AND i.status IN ('pending', 'running')
+            RETURNING i.id,
+                (SELECT p.name FROM projects p WHERE p.id = i.project_id) AS "project?",
+                i.status, i.rows_received, i.rows_inserted, i.chunks, i.error, i.create_dt,
+                i.update_dt
+            "#,
+            self.import_id,
+            api_key,
+        )
+        .fetch_optional(&state.db_conn)
+        .await?
+        .ok_or(EvalError::ImportClosed)?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Persist for EvalImportFail {
+    type Ret = ();
+    type Error = EvalError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(EvalError::Unauthorized)?;
+        let api_key = auth.api_key().ok_or(EvalError::Unauthorized)?;
+
+        query!(
+            r#"
+            UPDATE eval_imports
+            SET status = 'failed',
+                error = $3,
+                update_dt = current_timestamp
+            WHERE id = $1
+                AND user_id = user_from_key($2)
+                AND status IN ('pending', 'running')
+            "#,
+            self.import_id,
+            api_key,
+            self.error,
+        )
+        .execute(&state.db_conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Query for EvalImportGet {
+    type Resolve = EvalImport;
+    type Error = EvalError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(EvalError::Unauthorized)?;
+
+        let res = query_as!(
+            EvalImport,
+            r#"
+            SELECT i.id, p.name AS "project?", i.status, i.rows_received, i.rows_inserted,
+                i.chunks, i.error, i.create_dt, i.update_dt
+            FROM eval_imports i
+            LEFT JOIN projects p
+                ON p.id = i.project_id
+            WHERE i.id = $1
+                AND i.user_id = get_user_id($2, $3)
+            "#,
+            self.import_id,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_optional(&state.db_conn)
+        .await?
+        .ok_or(EvalError::NotFound(Error::RowNotFound))?;
+
+        Ok(res)
+    }
+}
+
+/// The id of the authenticated user, telling credentials which don't resolve to a user apart from
+/// other failures.
+async fn resolve_user(auth: &Auth, state: &State) -> Result<Uuid, EvalError> {
+    user_id(auth, state).await.map_err(|e| match &e {
+        // `get_user_id` raises `invalid_password` when it can't resolve the user.
+        Error::Database(d) if d.code().as_deref() == Some("28P01") => {
+            EvalError::UnresolvedPrincipal
+        }
+        _ => EvalError::Sqlx(e),
+    })
+}
+
+/// The latest of the user's evals of the function with the arguments, of a version other than
+/// the one looked up, i.e. what a lookup of the function and arguments would have found before the
+/// looked up version superseded it.
+async fn stale_eval(
+    state: &State,
+    user_id: Uuid,
+    (fn_key, fn_hash, args_hash): (&str, &str, &str),
+    is_experiment: Option<bool>,
+) -> Result<Option<Eval>, Error> {
+    query_as!(
+        Eval,
+        r#"
+        SELECT fn_key, fn_hash, args, args_hash, result_json, content_hash, is_experiment,
+            start_time, elapsed_process_time, accesses, e.blob_offset, e.blob_length,
+            blob_status(b.object_status, b.storage_class) AS "blob_status!"
+        FROM evals e
+        JOIN blobs b
+            ON b.id = e.blob_id
+        WHERE e.user_id = $1
+            AND fn_key = $2
+            AND fn_hash <> $3
+            AND args_hash = $4
+            AND (is_experiment = $5 OR $5 IS NULL)
+        ORDER BY start_time DESC
+        LIMIT 1
+        "#,
+        user_id,
+        fn_key,
+        fn_hash,
+        args_hash,
+        is_experiment,
+    )
+    .fetch_optional(&state.db_conn)
+    .await
+}
+
+struct MissResult {
+    function: bool,
+    version: bool,
+    args: bool,
+}
+
 #[async_trait]
 impl Query for web::Query<Params> {
     type Resolve = Vec<Eval>;
@@ -146,6 +1169,10 @@ impl Query for web::Query<Params> {
 
     async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
         let auth = auth.ok_or(EvalError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::EvalRead), state).await?;
+        // The user is resolved up front, so that credentials which don't resolve to one aren't
+        // mistaken for a lookup which matched nothing.
+        let user_id = resolve_user(auth, state).await?;
 
         let params = self.into_inner();
 
@@ -158,14 +1185,13 @@ impl Query for web::Query<Params> {
                 AND (fn_hash = $2 OR $2 IS NULL)
                 AND (args_hash = $3 OR $3 IS NULL)
                 AND (is_experiment = $4 OR $4 IS NULL)
-                AND e.user_id = get_user_id($5, $6)
+                AND e.user_id = $5
             "#,
                 params.fn_key,
                 params.fn_hash,
                 params.args_hash,
                 params.is_experiment,
-                auth.jwt().map(|c| c.sub),
-                auth.api_key(),
+                user_id,
             )
             .execute(&state.db_conn)
             .await?;
@@ -175,7 +1201,8 @@ impl Query for web::Query<Params> {
             Eval,
             r#"
             SELECT fn_key, fn_hash, args, args_hash, result_json, content_hash, is_experiment, start_time, 
-                elapsed_process_time, accesses 
+                elapsed_process_time, accesses, e.blob_offset, e.blob_length,
+                blob_status(b.object_status, b.storage_class) AS "blob_status!"
             FROM evals e 
             JOIN blobs b
                 ON b.id = e.blob_id
@@ -183,14 +1210,567 @@ impl Query for web::Query<Params> {
                 AND (fn_hash = $2 OR $2 IS NULL)
                 AND (args_hash = $3 OR $3 IS NULL)
                 AND (is_experiment = $4 OR $4 IS NULL)
-                AND e.user_id = get_user_id($5, $6)
+                AND e.user_id = $5
             "#,
             params.fn_key,
             params.fn_hash,
             params.args_hash,
             params.is_experiment,
+            user_id,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        // A lookup of one eval is a use of the cache, which the anomaly job watches for misses.
+        // Only lookups of one eval miss: other queries list what they match, which may be nothing.
+        if let (Some(fn_key), Some(fn_hash), Some(args_hash)) =
+            (&params.fn_key, &params.fn_hash, &params.args_hash)
+        {
+            let activity = if res.is_empty() {
+                Activity::Miss
+            } else {
+                Activity::Hit
+            };
+            record_activity(state, auth, activity, Some(fn_key)).await;
+
+            if res.is_empty() {
+                let miss = query_as!(
+                    MissResult,
+                    r#"
+                    SELECT
+                        EXISTS (SELECT 1 FROM evals WHERE user_id = $1 AND fn_key = $2)
+                            AS "function!",
+                        EXISTS (
+                            SELECT 1 FROM evals
+                            WHERE user_id = $1 AND fn_key = $2 AND fn_hash = $3
+                        ) AS "version!",
+                        EXISTS (
+                            SELECT 1 FROM evals
+                            WHERE user_id = $1 AND fn_key = $2 AND fn_hash = $3
+                                AND args_hash = $4
+                        ) AS "args!"
+                    "#,
+                    user_id,
+                    fn_key,
+                    fn_hash,
+                    args_hash,
+                )
+                .fetch_one(&state.db_conn)
+                .await?;
+                let reason = MissReason::of(miss.function, miss.version, miss.args);
+                // Only evals of other versions are superseded. An eval which the other filters
+                // exclude isn't stale, just not asked for.
+                let stale = match reason {
+                    MissReason::NotComputed | MissReason::UnknownVersion
+                        if params.stale == Some(true) =>
+                    {
+                        let lookup = (fn_key.as_str(), fn_hash.as_str(), args_hash.as_str());
+                        stale_eval(state, user_id, lookup, params.is_experiment)
+                            .await?
+                            .map(Box::new)
+                    }
+                    _ => None,
+                };
+                return Err(EvalError::Miss { reason, stale });
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+/// Resolves an eval in one round trip: looks it up, and if it isn't cached, claims it by
+/// registering a run to compute it, unless one is already computing it.
+#[derive(Deserialize, Debug)]
+pub struct EvalResolve {
+    pub project: Option<String>,
+    pub fn_key: String,
+    pub fn_hash: String,
+    pub args_hash: String,
+    /// Whether, if the eval isn't cached, to return the latest eval of another version of the
+    /// function with the same arguments, for the client to use while the eval is computed.
+    #[serde(default)]
+    pub stale: bool,
+}
+
+#[async_trait]
+impl Persist for EvalResolve {
+    type Ret = Resolution;
+    type Error = EvalError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(EvalError::Unauthorized)?;
+        // Looked up as a `GET /eval` of the one eval is, so that hits and misses are counted the
+        // same way.
+        let lookup = web::Query(Params {
+            fn_key: Some(self.fn_key.clone()),
+            fn_hash: Some(self.fn_hash.clone()),
+            args_hash: Some(self.args_hash.clone()),
+            is_experiment: None,
+            poll: None,
+            stale: Some(self.stale),
+        });
+        let (reason, stale) = match lookup.fetch(Some(auth), state).await {
+            Ok(evals) => match evals.into_iter().max_by_key(|e| e.start_time) {
+                Some(eval) => return Ok(Resolution::Hit { eval }),
+                None => (MissReason::NotComputed, None),
+            },
+            Err(EvalError::Miss { reason, stale }) => (reason, stale),
+            Err(e) => return Err(e),
+        };
+
+        policy::authorize(
+            auth,
+            Request::in_project(Action::RunWrite, self.project.as_deref()),
+            state,
+        )
+        .await?;
+        let api_key = auth.api_key().ok_or(EvalError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        // Serialize claims of the same eval, so that concurrent misses register one run.
+        query_scalar!(
+            r#"
+            SELECT true AS "locked!"
+            FROM pg_advisory_xact_lock($1, hashtext(
+                user_from_key($2)::text || ':' || $3 || ':' || $4 || ':' || $5
+            ))
+            "#,
+            EVAL_CLAIM_LOCK,
+            api_key,
+            self.fn_key,
+            self.fn_hash,
+            self.args_hash,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        let pending = query_as!(
+            Run,
+            r#"
+            SELECT r.id, p.name AS "project?", r.fn_key, r.fn_hash, r.args_hash, r.state,
+                r.cancel_requested, r.eval_id, r.create_dt, r.update_dt, r.archived_dt,
+                r.unarchive_requested_dt
+            FROM runs r
+            LEFT JOIN projects p
+                ON p.id = r.project_id
+            WHERE r.user_id = user_from_key($1)
+                AND r.fn_key = $2
+                AND r.fn_hash = $3
+                AND r.args_hash = $4
+                AND r.state IN ('queued', 'running')
+                AND NOT r.cancel_requested
+            ORDER BY r.create_dt DESC
+            LIMIT 1
+            "#,
+            api_key,
+            self.fn_key,
+            self.fn_hash,
+            self.args_hash,
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+        if let Some(run) = pending {
+            tx.commit().await?;
+            return Ok(Resolution::Pending {
+                run,
+                poll_after_ms: state.poll_after_ms(),
+                stale,
+            });
+        }
+
+        let project_id = match &self.project {
+            Some(name) => Some(
+                query_scalar!(
+                    r#"
+                    INSERT INTO projects (user_id, name)
+                    VALUES (user_from_key($1), $2)
+                    ON CONFLICT (user_id, name) DO UPDATE
+                        SET name = EXCLUDED.name
+                    RETURNING id
+                    "#,
+                    api_key,
+                    name,
+                )
+                .fetch_one(&mut tx)
+                .await?,
+            ),
+            None => None,
+        };
+
+        let run_id = query_scalar!(
+            r#"
+            INSERT INTO runs (user_id, project_id, fn_key, fn_hash, args_hash)
+            VALUES (user_from_key($1), $2, $3, $4, $5)
+            RETURNING id
+            "#,
+            api_key,
+            project_id,
+            self.fn_key,
+            self.fn_hash,
+            self.args_hash,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(Resolution::Claimed {
+            run_id,
+            reason: reason.as_str(),
+            stale,
+        })
+    }
+}
+
+#[async_trait]
+impl Query for web::Query<SearchParams> {
+    type Resolve = Vec<Eval>;
+    type Error = EvalError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(EvalError::Unauthorized)?;
+
+        let params = self.into_inner();
+
+        let contains = params
+            .contains
+            .map(|c| serde_json::from_str::<JsonValue>(&c))
+            .transpose()
+            .map_err(|_| EvalError::InvalidQuery)?;
+
+        let key = CacheKey {
+            user_id: user_id(auth, state).await?,
+            project: params.project.clone(),
+            listing: "eval_search",
+            params: format!("{:?}", (&params.fn_key, &contains, &params.path)),
+        };
+
+        // Only evals from projects which have opted in to result indexing are searchable. This
+        // keeps the query on the partial GIN index over `result_json`.
+        let query = async {
+            query_as!(
+                Eval,
+                r#"
+                SELECT fn_key, fn_hash, args, args_hash, result_json, content_hash, is_experiment, start_time, 
+                    elapsed_process_time, accesses, e.blob_offset, e.blob_length,
+                    blob_status(b.object_status, b.storage_class) AS "blob_status!"
+                FROM evals e 
+                JOIN blobs b
+                    ON b.id = e.blob_id
+                JOIN projects p
+                    ON p.id = e.project_id
+                WHERE   e.result_indexed
+                    AND (p.name = $1 OR $1 IS NULL)
+                    AND (fn_key = $2 OR $2 IS NULL)
+                    AND (e.result_json @> $3 OR $3 IS NULL)
+                    AND (e.result_json @@ $4::text::jsonpath OR $4 IS NULL)
+                    AND e.user_id = get_user_id($5, $6)
+                "#,
+                params.project,
+                params.fn_key,
+                contains,
+                params.path,
+                auth.jwt().map(|c| c.sub),
+                auth.api_key(),
+            )
+            .fetch_all(&state.db_conn)
+            .await
+            .map_err(|e| match e {
+                // Raised by Postgres when the jsonpath predicate doesn't parse.
+                Error::Database(ref err) if err.code() == Some(std::borrow::Cow::Borrowed("42601")) => {
+                    EvalError::InvalidQuery
+                }
+                e => EvalError::Sqlx(e),
+            })
+        };
+        let res = state.listing_cache.get_or_fetch(key, query).await?;
+
+        Ok(res)
+    }
+}
+
+fn default_similar_limit() -> i64 {
+    10
+}
+
+/// Finds the user's evals of a function whose arguments are nearest to `args`, for when an exact
+/// lookup by `args_hash` misses. Only evals which the background job has embedded are found.
+#[derive(Deserialize, Debug)]
+pub struct SimilarEvalsGet {
+    pub fn_key: String,
+    pub fn_hash: Option<String>,
+    pub args: JsonValue,
+    #[serde(default = "default_similar_limit")]
+    pub limit: i64,
+}
+
+#[async_trait]
+impl Query for SimilarEvalsGet {
+    type Resolve = Vec<SimilarEval>;
+    type Error = EvalError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(EvalError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::EvalRead), state).await?;
+
+        let embedding = state.embedder.embed_args(&self.args).await?;
+
+        let res = query_as!(
+            SimilarEval,
+            r#"
+            SELECT fn_key, fn_hash, args, args_hash, result_json, content_hash, is_experiment,
+                start_time, elapsed_process_time, accesses,
+                blob_status(b.object_status, b.storage_class) AS "blob_status!",
+                m.embedding <=> $3::text::vector AS "distance!"
+            FROM evals e
+            JOIN blobs b
+                ON b.id = e.blob_id
+            JOIN eval_embeddings m
+                ON m.eval_id = e.id
+            WHERE   e.fn_key = $1
+                AND (e.fn_hash = $2 OR $2 IS NULL)
+                AND m.model = $4
+                AND vector_dims(m.embedding) = $5
+                AND e.user_id = get_user_id($6, $7)
+            ORDER BY m.embedding <=> $3::text::vector
+            LIMIT $8
+            "#,
+            self.fn_key,
+            self.fn_hash,
+            to_vector_literal(&embedding),
+            state.embedder.model(),
+            embedding.len() as i32,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.limit.clamp(1, MAX_SIMILAR),
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+fn default_suggestion_limit() -> i64 {
+    5
+}
+
+/// Suggests cached evals of a function for a lookup which missed, ranked by how many arguments
+/// they share with the lookup, then by recency. Only the function's most recent evals are
+/// considered.
+#[derive(Deserialize, Debug)]
+pub struct SuggestionsGet {
+    pub fn_key: String,
+    pub fn_hash: String,
+    pub args: JsonValue,
+    #[serde(default = "default_suggestion_limit")]
+    pub limit: i64,
+}
+
+struct SuggestionCandidate {
+    fn_hash: String,
+    args: Option<JsonValue>,
+    args_hash: String,
+    start_time: DateTime<Utc>,
+}
+
+#[async_trait]
+impl Query for SuggestionsGet {
+    type Resolve = Vec<Suggestion>;
+    type Error = EvalError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(EvalError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::EvalRead), state).await?;
+
+        let candidates = query_as!(
+            SuggestionCandidate,
+            r#"
+            SELECT fn_hash, args, args_hash, start_time
+            FROM evals
+            WHERE fn_key = $1
+                AND user_id = get_user_id($2, $3)
+            ORDER BY start_time DESC
+            LIMIT $4
+            "#,
+            self.fn_key,
             auth.jwt().map(|c| c.sub),
             auth.api_key(),
+            SUGGESTION_CANDIDATES,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        let mut res = candidates
+            .into_iter()
+            .map(|c| {
+                let (overlap, differing_args) =
+                    compare_args(&self.args, c.args.as_ref().unwrap_or(&JsonValue::Null));
+                Suggestion {
+                    same_fn_hash: c.fn_hash == self.fn_hash,
+                    fn_hash: c.fn_hash,
+                    args_hash: c.args_hash,
+                    overlap,
+                    differing_args,
+                    start_time: c.start_time,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Candidates are already in order of recency, and the sort is stable.
+        res.sort_by(|a, b| b.overlap.total_cmp(&a.overlap));
+        res.truncate(self.limit.clamp(1, MAX_SUGGESTIONS) as usize);
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for web::Query<StatusParams> {
+    type Resolve = Vec<FnStatus>;
+    type Error = EvalError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(EvalError::Unauthorized)?;
+
+        let fn_keys = serde_json::from_str::<Vec<String>>(&self.fn_keys)
+            .map_err(|_| EvalError::InvalidQuery)?;
+        if fn_keys.len() > MAX_STATUS_FN_KEYS {
+            return Err(EvalError::InvalidQuery);
+        }
+
+        // One row per requested key, including keys with no evals, so the whole batch is a single
+        // round trip.
+        let res = query_as!(
+            FnStatus,
+            r#"
+            SELECT k.fn_key AS "fn_key!",
+                count(e.id) AS "evals!",
+                max(e.start_time) AS last_eval_time,
+                coalesce(sum(e.accesses * e.elapsed_process_time), 0)::bigint AS "saved_process_time!"
+            FROM unnest($1::text[]) AS k(fn_key)
+            LEFT JOIN evals e
+                ON e.fn_key = k.fn_key
+                AND e.user_id = get_user_id($2, $3)
+            GROUP BY k.fn_key
+            ORDER BY k.fn_key
+            "#,
+            &fn_keys,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+/// A batch of cache outcomes reported by a client.
+#[derive(Deserialize, Debug)]
+pub struct ReportBatch {
+    pub reports: Vec<CacheReport>,
+}
+
+#[async_trait]
+impl Persist for ReportBatch {
+    type Ret = ();
+    type Error = EvalError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(EvalError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::EvalWrite), state).await?;
+
+        if self.reports.len() > MAX_REPORTS
+            || self.reports.iter().any(|r| r.elapsed_process_time < 0)
+        {
+            return Err(EvalError::InvalidReport);
+        }
+
+        let now = Utc::now();
+        let mut fn_keys = Vec::with_capacity(self.reports.len());
+        let mut outcomes = Vec::with_capacity(self.reports.len());
+        let mut elapsed = Vec::with_capacity(self.reports.len());
+        let mut times = Vec::with_capacity(self.reports.len());
+        for r in self.reports {
+            fn_keys.push(r.fn_key);
+            outcomes.push(r.outcome.as_str().to_string());
+            elapsed.push(r.elapsed_process_time);
+            // Clients' clocks can't place outcomes in the future.
+            times.push(r.time.map_or(now, |t| t.min(now)));
+        }
+
+        // Reports are summed into their hourly periods before upserting, so that each period is
+        // only updated once per batch.
+        query!(
+            r#"
+            INSERT INTO eval_usage AS u (user_id, fn_key, period_start, hits, local_hits, misses,
+                compute_time, saved_time)
+            SELECT get_user_id($1, $2), r.fn_key, date_trunc('hour', r.time),
+                count(*) FILTER (WHERE r.outcome = 'hit'),
+                count(*) FILTER (WHERE r.outcome = 'local_hit'),
+                count(*) FILTER (WHERE r.outcome = 'miss'),
+                coalesce(sum(r.elapsed) FILTER (WHERE r.outcome = 'miss'), 0),
+                coalesce(sum(r.elapsed) FILTER (WHERE r.outcome <> 'miss'), 0)
+            FROM unnest($3::text[], $4::text[], $5::bigint[], $6::timestamptz[])
+                AS r(fn_key, outcome, elapsed, time)
+            GROUP BY r.fn_key, date_trunc('hour', r.time)
+            ON CONFLICT (user_id, fn_key, period_start) DO UPDATE
+                SET hits = u.hits + EXCLUDED.hits,
+                    local_hits = u.local_hits + EXCLUDED.local_hits,
+                    misses = u.misses + EXCLUDED.misses,
+                    compute_time = u.compute_time + EXCLUDED.compute_time,
+                    saved_time = u.saved_time + EXCLUDED.saved_time
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            &fn_keys,
+            &outcomes,
+            &elapsed,
+            &times,
+        )
+        .execute(&state.db_conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Query for web::Query<UsageParams> {
+    type Resolve = Vec<FnUsage>;
+    type Error = EvalError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(EvalError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::EvalRead), state).await?;
+
+        let params = self.into_inner();
+
+        let res = query_as!(
+            FnUsage,
+            r#"
+            SELECT fn_key,
+                sum(hits)::bigint AS "hits!",
+                sum(local_hits)::bigint AS "local_hits!",
+                sum(misses)::bigint AS "misses!",
+                sum(hits + local_hits)::float8 / nullif(sum(hits + local_hits + misses), 0)
+                    AS hit_rate,
+                sum(compute_time)::bigint AS "compute_time!",
+                sum(saved_time)::bigint AS "saved_time!"
+            FROM eval_usage
+            WHERE user_id = get_user_id($1, $2)
+                AND (fn_key = $3 OR $3 IS NULL)
+                AND (period_start >= date_trunc('hour', $4::timestamptz) OR $4 IS NULL)
+            GROUP BY fn_key
+            ORDER BY fn_key
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            params.fn_key,
+            params.since,
         )
         .fetch_all(&state.db_conn)
         .await?;
@@ -0,0 +1,77 @@
+use crate::middlewares::auth::Auth;
+use crate::models::openlineage::{ExportError, ProvenanceRow, RunEvent};
+use crate::persisters::user::user_id;
+use crate::persisters::Query;
+use crate::state::State;
+
+use sqlx::types::chrono::{DateTime, Utc};
+use sqlx::types::Uuid;
+
+/// Parameters for exporting provenance as OpenLineage events.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OpenLineageExport {
+    pub project: Option<String>,
+    /// Only export runs and evals which changed at or after this time.
+    pub since: Option<DateTime<Utc>>,
+}
+
+#[async_trait]
+impl Query for OpenLineageExport {
+    type Resolve = Vec<RunEvent>;
+    type Error = ExportError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(ExportError::Unauthorized)?;
+        let user_id = user_id(auth, state).await?;
+
+        Ok(self.events(user_id, state).await?)
+    }
+}
+
+impl OpenLineageExport {
+    /// The user's runs and evals as events, in the order they happened.
+    pub async fn events(&self, user_id: Uuid, state: &State) -> Result<Vec<RunEvent>, sqlx::Error> {
+        // Runs carry their own lifecycle. Evals which weren't recorded by a run are exported as
+        // runs which succeeded as soon as they started.
+        let rows = query_as!(
+            ProvenanceRow,
+            r#"
+            SELECT r.id AS "id!", p.name AS "project?", r.fn_key AS "fn_key!", r.state AS "state!",
+                b.content_hash AS "content_hash?", r.create_dt AS "start_time!",
+                r.update_dt AS "end_time!"
+            FROM runs r
+            LEFT JOIN projects p
+                ON p.id = r.project_id
+            LEFT JOIN evals e
+                ON e.id = r.eval_id
+            LEFT JOIN blobs b
+                ON b.id = e.blob_id
+            WHERE   (p.name = $1 OR $1 IS NULL)
+                AND (r.update_dt >= $2 OR $2 IS NULL)
+                AND r.user_id = $3
+            UNION ALL
+            SELECT e.id, p.name, e.fn_key, 'succeeded', b.content_hash, e.start_time, e.start_time
+            FROM evals e
+            JOIN blobs b
+                ON b.id = e.blob_id
+            LEFT JOIN projects p
+                ON p.id = e.project_id
+            WHERE   (p.name = $1 OR $1 IS NULL)
+                AND (e.start_time >= $2 OR $2 IS NULL)
+                AND e.user_id = $3
+                AND NOT EXISTS (SELECT 1 FROM runs r WHERE r.eval_id = e.id)
+            ORDER BY 6
+            "#,
+            self.project,
+            self.since,
+            user_id,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .flat_map(ProvenanceRow::into_events)
+            .collect())
+    }
+}
@@ -0,0 +1,544 @@
+use crate::chaos::{Chaos, Layer};
+use crate::persisters::blob_store::{BlobStore, Payload, PresignedPut};
+use crate::persisters::s3store::{StoreError, Stored, Target};
+use crate::CONFIG;
+
+use aws_sdk_s3::{model::StorageClass, types::ByteStream};
+use blake3::{Hash, Hasher};
+use futures::stream::{self, StreamExt};
+use sqlx::types::Uuid;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use std::io::{self, ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// How much of a BLOB is read from its file at a time as it's retrieved.
+const CHUNK_LEN: u64 = 64 * 1024;
+
+/// BLOB storage in a directory, for when there's no S3. Each bucket is a directory in it, and each
+/// object a file named by its key. Regions are ignored, and there are no storage classes, so
+/// archived BLOBs can always be read.
+///
+/// Files are written in `.incoming` and moved into place once they're complete, so a BLOB is never
+/// read half-written. The parts of multipart uploads are kept in `.uploads`.
+#[derive(Clone)]
+pub struct FsStore {
+    root: PathBuf,
+    chaos: Chaos,
+}
+
+impl FsStore {
+    pub fn new(root: impl Into<PathBuf>, chaos: Chaos) -> FsStore {
+        Self {
+            root: root.into(),
+            chaos,
+        }
+    }
+
+    /// The file the BLOB with the content hash is stored in.
+    fn path(&self, target: &Target, content_hash: Hash) -> PathBuf {
+        self.root
+            .join(target.bucket())
+            .join(target.key(content_hash))
+    }
+
+    /// The file an upload straight to storage is staged in.
+    fn staged_path(&self, target: &Target, upload_id: Uuid) -> PathBuf {
+        self.root
+            .join(target.bucket())
+            .join(target.staged_key(upload_id))
+    }
+
+    /// The file an object which isn't a BLOB is stored in.
+    fn object_path(&self, key: &str) -> PathBuf {
+        self.root
+            .join(&CONFIG.aws_s3_blob_bucket)
+            .join(format!("{}{}", CONFIG.s3_key_prefix, key))
+    }
+
+    /// The directory the parts of a multipart upload are kept in.
+    fn upload_dir(&self, upload_id: &str) -> PathBuf {
+        self.root.join(".uploads").join(upload_id)
+    }
+
+    /// Injects any faults set on BLOB storage into a filesystem operation.
+    async fn inject_faults(&self) -> Result<(), StoreError> {
+        self.chaos
+            .inject(Layer::Blob)
+            .await
+            .map_err(|e| StoreError::Io(io::Error::new(ErrorKind::Other, e)))
+    }
+
+    /// Creates a file in `.incoming`, to be moved into place by [`commit`] once it's written.
+    async fn incoming(&self) -> Result<(PathBuf, File), StoreError> {
+        let dir = self.root.join(".incoming");
+        fs::create_dir_all(&dir).await?;
+        let path = dir.join(Uuid::new_v4().to_string());
+        let file = File::create(&path).await?;
+
+        Ok((path, file))
+    }
+
+    /// Writes the file at `path` in full, or not at all.
+    async fn write(&self, path: &Path, bytes: &[u8]) -> Result<(), StoreError> {
+        let (incoming, mut file) = self.incoming().await?;
+        let res = async {
+            file.write_all(bytes).await?;
+            file.sync_all().await?;
+            commit(&incoming, path).await
+        }
+        .await;
+        discard_on_error(&incoming, res).await
+    }
+}
+
+/// Moves a file written in `.incoming` into place, replacing whatever's there.
+async fn commit(incoming: &Path, path: &Path) -> Result<(), StoreError> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    fs::rename(incoming, path).await?;
+
+    Ok(())
+}
+
+/// Removes a file from `.incoming` which won't be moved into place after all.
+async fn discard_on_error<T>(incoming: &Path, res: Result<T, StoreError>) -> Result<T, StoreError> {
+    if res.is_err() {
+        if let Err(e) = fs::remove_file(incoming).await {
+            log::warn!("could not remove {}: {:?}", incoming.display(), e);
+        }
+    }
+    res
+}
+
+/// Writes a BLOB to the file as it's received, checking that it's the BLOB claimed.
+async fn receive_into(
+    file: &mut File,
+    payload: &mut Payload,
+    hash_claim: Hash,
+    content_length: i64,
+) -> Result<(), StoreError> {
+    let mut hasher = Hasher::new();
+    let mut len = 0;
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(StoreError::WithBlob)?;
+        hasher.update(&chunk);
+        len += chunk.len() as i64;
+        if len > content_length {
+            return Err(StoreError::InvalidHash);
+        }
+        file.write_all(&chunk).await?;
+    }
+
+    if len != content_length || hasher.finalize() != hash_claim {
+        return Err(StoreError::InvalidHash);
+    }
+    file.sync_all().await?;
+
+    Ok(())
+}
+
+/// Streams the next `len` bytes of the file.
+pub(crate) fn file_stream(file: File, len: u64) -> ByteStream {
+    let chunks = stream::try_unfold((file, len), next_chunk);
+    ByteStream::new(hyper::Body::wrap_stream(chunks).into())
+}
+
+async fn next_chunk(
+    (mut file, left): (File, u64),
+) -> io::Result<Option<(bytes::Bytes, (File, u64))>> {
+    if left == 0 {
+        return Ok(None);
+    }
+    let mut buf = bytes::BytesMut::with_capacity(left.min(CHUNK_LEN) as usize);
+    if file.read_buf(&mut buf).await? == 0 {
+        return Err(ErrorKind::UnexpectedEof.into());
+    }
+    let left = left - buf.len() as u64;
+
+    Ok(Some((buf.freeze(), (file, left))))
+}
+
+/// Treats a file which isn't there as `None`.
+fn found<T>(res: io::Result<T>) -> Result<Option<T>, StoreError> {
+    match res {
+        Ok(t) => Ok(Some(t)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[async_trait]
+impl BlobStore for FsStore {
+    /// Makes sure the target's bucket has a directory.
+    async fn check_target(&self, target: &Target) -> Result<(), StoreError> {
+        fs::create_dir_all(self.root.join(target.bucket())).await?;
+        Ok(())
+    }
+
+    /// Writes the BLOB to its file as it's received. BLOBs are stored as they are, without
+    /// compression.
+    async fn store_blob(
+        &self,
+        target: &Target,
+        mut payload: Payload,
+        hash_claim: Hash,
+        content_length: i64,
+    ) -> Result<Stored, StoreError> {
+        self.inject_faults().await?;
+        let (incoming, mut file) = self.incoming().await?;
+        let res = async {
+            receive_into(&mut file, &mut payload, hash_claim, content_length).await?;
+            commit(&incoming, &self.path(target, hash_claim)).await
+        }
+        .await;
+        discard_on_error(&incoming, res).await?;
+
+        Ok(Stored::raw(content_length))
+    }
+
+    async fn store_bytes(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        bytes: bytes::Bytes,
+    ) -> Result<Stored, StoreError> {
+        self.inject_faults().await?;
+        self.write(&self.path(target, content_hash), &bytes).await?;
+
+        Ok(Stored::raw(bytes.len() as i64))
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        _target: &Target,
+        _content_hash: Hash,
+    ) -> Result<String, StoreError> {
+        self.inject_faults().await?;
+        let upload_id = Uuid::new_v4().to_string();
+        fs::create_dir_all(self.upload_dir(&upload_id)).await?;
+
+        Ok(upload_id)
+    }
+
+    /// Keeps the part in the upload's directory. Its ETag is its blake3 hash, which is checked as
+    /// the upload is completed.
+    async fn upload_part(
+        &self,
+        _target: &Target,
+        _content_hash: Hash,
+        upload_id: &str,
+        part_number: i32,
+        bytes: bytes::Bytes,
+    ) -> Result<String, StoreError> {
+        self.inject_faults().await?;
+        let dir = self.upload_dir(upload_id);
+        // Writing into a directory which isn't there fails, as for an upload which was aborted.
+        fs::write(dir.join(part_number.to_string()), &bytes).await?;
+
+        Ok(blake3::hash(&bytes).to_hex().to_string())
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        upload_id: &str,
+        e_tags: &[String],
+    ) -> Result<(), StoreError> {
+        self.inject_faults().await?;
+        let dir = self.upload_dir(upload_id);
+        let (incoming, mut file) = self.incoming().await?;
+        let res = async {
+            for (e_tag, part_number) in e_tags.iter().zip(1..) {
+                let part = fs::read(dir.join(part_number.to_string())).await?;
+                if blake3::hash(&part).to_hex().as_str() != e_tag {
+                    return Err(StoreError::Io(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("part {} doesn't match its ETag", part_number),
+                    )));
+                }
+                file.write_all(&part).await?;
+            }
+            file.sync_all().await?;
+            commit(&incoming, &self.path(target, content_hash)).await
+        }
+        .await;
+        discard_on_error(&incoming, res).await?;
+        fs::remove_dir_all(&dir).await?;
+
+        Ok(())
+    }
+
+    async fn abort_multipart_upload(
+        &self,
+        _target: &Target,
+        _content_hash: Hash,
+        upload_id: &str,
+    ) -> Result<(), StoreError> {
+        self.inject_faults().await?;
+        found(fs::remove_dir_all(self.upload_dir(upload_id)).await)?;
+
+        Ok(())
+    }
+
+    async fn retrieve_blob(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<ByteStream, StoreError> {
+        self.inject_faults().await?;
+        let file = File::open(self.path(target, content_hash)).await?;
+        let len = file.metadata().await?.len();
+
+        Ok(file_stream(file, len))
+    }
+
+    async fn retrieve_blob_range(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        first: u64,
+        last: u64,
+    ) -> Result<ByteStream, StoreError> {
+        self.inject_faults().await?;
+        let mut file = File::open(self.path(target, content_hash)).await?;
+        let len = file.metadata().await?.len();
+        let end = last.saturating_add(1).min(len);
+        file.seek(SeekFrom::Start(first.min(end))).await?;
+
+        Ok(file_stream(file, end.saturating_sub(first)))
+    }
+
+    async fn blob_length(&self, target: &Target, content_hash: Hash) -> Result<i64, StoreError> {
+        self.inject_faults().await?;
+        let meta = fs::metadata(self.path(target, content_hash)).await?;
+
+        Ok(meta.len() as i64)
+    }
+
+    /// There are no storage classes in a directory, so this does nothing.
+    async fn set_storage_class(
+        &self,
+        _target: &Target,
+        _content_hash: Hash,
+        _storage_class: StorageClass,
+    ) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    /// BLOBs are never really archived, so there's nothing to restore.
+    async fn request_restore(
+        &self,
+        _target: &Target,
+        _content_hash: Hash,
+        _days: i32,
+    ) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    async fn restore_complete(
+        &self,
+        _target: &Target,
+        _content_hash: Hash,
+    ) -> Result<bool, StoreError> {
+        Ok(true)
+    }
+
+    async fn store_object(&self, key: &str, bytes: bytes::Bytes) -> Result<(), StoreError> {
+        self.inject_faults().await?;
+        self.write(&self.object_path(key), &bytes).await
+    }
+
+    async fn retrieve_object(&self, key: &str) -> Result<bytes::Bytes, StoreError> {
+        self.inject_faults().await?;
+        let bytes = fs::read(self.object_path(key)).await?;
+
+        Ok(bytes.into())
+    }
+
+    async fn find_blob_written(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<Option<(i64, Option<i64>)>, StoreError> {
+        self.inject_faults().await?;
+        let meta = found(fs::metadata(self.path(target, content_hash)).await)?;
+
+        Ok(meta.map(|meta| {
+            let written = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs() as i64);
+            (meta.len() as i64, written)
+        }))
+    }
+
+    /// Lists the files in the directory of the target's prefix which start with the rest of it,
+    /// all in one page. Unlike S3, the listing doesn't descend into directories beneath that.
+    async fn list_objects(
+        &self,
+        target: &Target,
+        _continuation: Option<String>,
+    ) -> Result<(Vec<(String, i64)>, Option<String>), StoreError> {
+        self.inject_faults().await?;
+        let prefix = target.prefix.as_deref().unwrap_or_default();
+        let (dir, name_prefix) = prefix.split_at(prefix.rfind('/').map_or(0, |i| i + 1));
+        let mut entries =
+            match found(fs::read_dir(self.root.join(target.bucket()).join(dir)).await)? {
+                Some(entries) => entries,
+                None => return Ok((vec![], None)),
+            };
+
+        let mut objects = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let meta = entry.metadata().await?;
+            match entry.file_name().to_str() {
+                Some(name) if meta.is_file() && name.starts_with(name_prefix) => {
+                    objects.push((format!("{}{}", dir, name), meta.len() as i64));
+                }
+                _ => {}
+            }
+        }
+
+        Ok((objects, None))
+    }
+
+    async fn delete_blob(&self, target: &Target, content_hash: Hash) -> Result<(), StoreError> {
+        self.inject_faults().await?;
+        found(fs::remove_file(self.path(target, content_hash)).await)?;
+
+        Ok(())
+    }
+
+    /// A directory can't be downloaded from without going through the server.
+    async fn presign_get(
+        &self,
+        _target: &Target,
+        _content_hash: Hash,
+        _expires_in: Duration,
+    ) -> Result<Option<String>, StoreError> {
+        Ok(None)
+    }
+
+    /// A directory can't be uploaded to without going through the server.
+    async fn presign_staged_put(
+        &self,
+        _target: &Target,
+        _upload_id: Uuid,
+        _content_length: i64,
+        _expires_in: Duration,
+    ) -> Result<Option<PresignedPut>, StoreError> {
+        Ok(None)
+    }
+
+    async fn retrieve_staged(
+        &self,
+        target: &Target,
+        upload_id: Uuid,
+    ) -> Result<ByteStream, StoreError> {
+        self.inject_faults().await?;
+        let file = found(File::open(self.staged_path(target, upload_id)).await)?
+            .ok_or(StoreError::NotFound)?;
+        let len = file.metadata().await?.len();
+
+        Ok(file_stream(file, len))
+    }
+
+    async fn promote_staged(
+        &self,
+        target: &Target,
+        upload_id: Uuid,
+        content_hash: Hash,
+    ) -> Result<(), StoreError> {
+        self.inject_faults().await?;
+        commit(
+            &self.staged_path(target, upload_id),
+            &self.path(target, content_hash),
+        )
+        .await
+    }
+
+    async fn delete_staged(&self, target: &Target, upload_id: Uuid) -> Result<(), StoreError> {
+        self.inject_faults().await?;
+        found(fs::remove_file(self.staged_path(target, upload_id)).await)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target() -> Target {
+        Target {
+            region: None,
+            bucket: Some("blobs".into()),
+            prefix: Some("staging/".into()),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn stores_and_retrieves_blobs() {
+        let root = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let store = FsStore::new(&root, Chaos::default());
+        let target = target();
+        let bytes = bytes::Bytes::from_static(b"0123456789");
+        let hash = blake3::hash(&bytes);
+
+        let payload: Payload =
+            stream::iter(vec![Ok(bytes.slice(..4)), Ok(bytes.slice(4..))]).boxed();
+        let stored = store.store_blob(&target, payload, hash, 10).await.unwrap();
+        assert_eq!(stored, Stored::raw(10));
+        assert!(root
+            .join("blobs/staging")
+            .join(hash.to_hex().as_str())
+            .is_file());
+
+        assert_eq!(
+            store.retrieve_blob_bytes(&target, hash).await.unwrap(),
+            bytes
+        );
+        let range = store
+            .retrieve_blob_range(&target, hash, 2, 4)
+            .await
+            .unwrap();
+        assert_eq!(&range.collect().await.unwrap().into_bytes()[..], b"234");
+        assert_eq!(store.hash_blob(&target, hash).await.unwrap(), (hash, 10));
+
+        let (objects, next) = store.list_objects(&target, None).await.unwrap();
+        assert_eq!(objects, vec![(target.key(hash), 10)]);
+        assert_eq!(next, None);
+
+        store.delete_blob(&target, hash).await.unwrap();
+        assert_eq!(store.find_blob(&target, hash).await.unwrap(), None);
+        store.delete_blob(&target, hash).await.unwrap();
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[actix_rt::test]
+    async fn rejects_blobs_which_are_not_as_claimed() {
+        let root = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let store = FsStore::new(&root, Chaos::default());
+        let target = target();
+        let hash = blake3::hash(b"0123456789");
+
+        let payload: Payload = stream::iter(vec![Ok(bytes::Bytes::from_static(b"01234"))]).boxed();
+        let res = store.store_blob(&target, payload, hash, 10).await;
+        assert!(matches!(res, Err(StoreError::InvalidHash)));
+        assert_eq!(store.find_blob(&target, hash).await.unwrap(), None);
+        // Nothing is left half-written.
+        assert_eq!(
+            std::fs::read_dir(root.join(".incoming")).unwrap().count(),
+            0
+        );
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
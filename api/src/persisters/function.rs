@@ -0,0 +1,191 @@
+use crate::middlewares::auth::Auth;
+use crate::models::cache_policy::CachePolicy;
+use crate::models::function::{
+    next_since, Function, FunctionError, FunctionVersion, FunctionsChanged, MAX_SOURCE_LEN,
+    MAX_SYNC_FUNCTIONS,
+};
+use crate::persisters::{Persist, Query};
+use crate::policy::{self, Action, Request};
+use crate::state::State;
+use sqlx::types::chrono::{self, Utc};
+
+/// Registers a version of a function. A version which is already registered is left as it is,
+/// since its hash identifies its source.
+#[derive(Deserialize, Debug)]
+pub struct FunctionInsert {
+    pub fn_key: String,
+    pub fn_hash: String,
+    pub source: String,
+    pub docstring: Option<String>,
+    pub signature: Option<String>,
+}
+
+/// Looks up the registered versions of a function, most recent first, or a single version when
+/// `fn_hash` is given.
+#[derive(Deserialize, Debug)]
+pub struct FunctionsGet {
+    pub fn_key: String,
+    pub fn_hash: Option<String>,
+}
+
+/// Looks up what has changed in the user's functions registry since `since`, or everything in it if
+/// `since` isn't given.
+#[derive(Deserialize, Debug)]
+pub struct FunctionsChangedGet {
+    pub since: Option<chrono::DateTime<Utc>>,
+}
+
+#[async_trait]
+impl Persist for FunctionInsert {
+    type Ret = ();
+    type Error = FunctionError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(FunctionError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::EvalWrite), state).await?;
+
+        if self.source.len() > MAX_SOURCE_LEN {
+            return Err(FunctionError::TooLarge);
+        }
+
+        query!(
+            r#"
+            INSERT INTO functions (user_id, fn_key, fn_hash, source, docstring, signature)
+            VALUES (get_user_id($1, $2), $3, $4, $5, $6, $7)
+            ON CONFLICT (user_id, fn_key, fn_hash) DO NOTHING
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.fn_key,
+            self.fn_hash,
+            self.source,
+            self.docstring,
+            self.signature,
+        )
+        .execute(&state.db_conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Query for FunctionsGet {
+    type Resolve = Vec<Function>;
+    type Error = FunctionError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(FunctionError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::EvalRead), state).await?;
+
+        let res = query_as!(
+            Function,
+            r#"
+            SELECT fn_key, fn_hash, source, docstring, signature, create_dt
+            FROM functions
+            WHERE user_id = get_user_id($1, $2)
+                AND fn_key = $3
+                AND (fn_hash = $4 OR $4 IS NULL)
+            ORDER BY create_dt DESC
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.fn_key,
+            self.fn_hash,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        if res.is_empty() {
+            return Err(FunctionError::NotFound);
+        }
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for FunctionsChangedGet {
+    type Resolve = FunctionsChanged;
+    type Error = FunctionError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(FunctionError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::EvalRead), state).await?;
+
+        // Read from one snapshot, so that the three lists agree.
+        let mut tx = state.db_conn.begin().await?;
+        query!("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")
+            .execute(&mut tx)
+            .await?;
+        let now = query_scalar!(r#"SELECT current_timestamp AS "now!""#)
+            .fetch_one(&mut tx)
+            .await?;
+
+        let mut functions = query_as!(
+            FunctionVersion,
+            r#"
+            SELECT fn_key, fn_hash, docstring, signature, create_dt, update_dt
+            FROM functions
+            WHERE user_id = get_user_id($1, $2)
+                AND (update_dt >= $3 OR $3 IS NULL)
+            ORDER BY update_dt, fn_key, fn_hash
+            LIMIT $4
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.since,
+            MAX_SYNC_FUNCTIONS + 1,
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        let truncated = functions.len() as i64 > MAX_SYNC_FUNCTIONS;
+        functions.truncate(MAX_SYNC_FUNCTIONS as usize);
+
+        let policies = query_as!(
+            CachePolicy,
+            r#"
+            SELECT fn_key, mode, ttl_secs, max_result_bytes, update_dt
+            FROM cache_policies
+            WHERE user_id = get_user_id($1, $2)
+                AND (update_dt >= $3 OR $3 IS NULL)
+            ORDER BY fn_key
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.since,
+        )
+        .fetch_all(&mut tx)
+        .await?;
+
+        // A client syncing everything has no policies to remove.
+        let removed_policies = match self.since {
+            Some(since) => {
+                query_scalar!(
+                    r#"
+                    SELECT fn_key
+                    FROM cache_policy_removals
+                    WHERE user_id = get_user_id($1, $2)
+                        AND remove_dt >= $3
+                    ORDER BY fn_key
+                    "#,
+                    auth.jwt().map(|c| c.sub),
+                    auth.api_key(),
+                    since,
+                )
+                .fetch_all(&mut tx)
+                .await?
+            }
+            None => vec![],
+        };
+        tx.commit().await?;
+
+        Ok(FunctionsChanged {
+            next_since: next_since(now, &functions, truncated),
+            functions,
+            policies,
+            removed_policies,
+            truncated,
+        })
+    }
+}
@@ -0,0 +1,333 @@
+use crate::middlewares::auth::Auth;
+use crate::models::hold::{HoldAction, HoldError, HoldEvent, LegalHold};
+use crate::persisters::{Persist, Query};
+use crate::state::State;
+use sqlx::{types::Uuid, Postgres, Transaction};
+
+/// The runs and evals to put on hold, and why.
+#[derive(Deserialize, Debug)]
+pub struct HoldSpec {
+    pub reason: String,
+    #[serde(default)]
+    pub runs: Vec<Uuid>,
+    #[serde(default)]
+    pub evals: Vec<Uuid>,
+}
+
+/// Places a legal hold on runs and evals of an org's owner.
+pub struct HoldInsert {
+    pub org: String,
+    pub spec: HoldSpec,
+}
+
+/// Lists an org's legal holds, newest first, including released ones.
+pub struct HoldsGet {
+    pub org: String,
+}
+
+/// Releases a legal hold. The hold itself is kept, for the record.
+pub struct HoldRelease {
+    pub org: String,
+    pub id: i64,
+}
+
+/// Lists the audit log of an org's legal holds, newest first.
+pub struct HoldEventsGet {
+    pub org: String,
+}
+
+struct OrgResult {
+    id: Uuid,
+    owner_id: Uuid,
+}
+
+/// Looks up an org of the authenticated user by its external id, locking it against deletion for
+/// the rest of the transaction.
+async fn org_for_share(
+    tx: &mut Transaction<'_, Postgres>,
+    auth: &Auth,
+    org: &str,
+) -> Result<OrgResult, HoldError> {
+    let org = query_as!(
+        OrgResult,
+        r#"
+        SELECT id, owner_id
+        FROM orgs
+        WHERE owner_id = get_user_id($1, $2)
+            AND external_id = $3
+        FOR SHARE
+        "#,
+        auth.jwt().map(|c| c.sub),
+        auth.api_key(),
+        org,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    Ok(org)
+}
+
+/// Records a change to a hold in the audit log, along with what the hold covers.
+async fn record_event(
+    tx: &mut Transaction<'_, Postgres>,
+    auth: &Auth,
+    hold_id: i64,
+    action: HoldAction,
+) -> Result<(), HoldError> {
+    query!(
+        r#"
+        INSERT INTO legal_hold_events (hold_id, action, user_id, api_key_label, runs, evals)
+        SELECT $1, $2, get_user_id($3, $4),
+            (SELECT label FROM api_keys WHERE key = $4),
+            ARRAY(SELECT run_id FROM legal_hold_runs WHERE hold_id = $1 ORDER BY run_id),
+            ARRAY(SELECT eval_id FROM legal_hold_evals WHERE hold_id = $1 ORDER BY eval_id)
+        "#,
+        hold_id,
+        action.as_str(),
+        auth.jwt().map(|c| c.sub),
+        auth.api_key(),
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    log::info!("legal hold {} {}", hold_id, action.as_str());
+
+    Ok(())
+}
+
+/// Whether any of the evals is under an unreleased legal hold, and so can't be deleted. The evals
+/// should be locked `FOR UPDATE`, so that a hold can't be placed on them before they're deleted.
+pub async fn evals_held(
+    tx: &mut Transaction<'_, Postgres>,
+    eval_ids: &[Uuid],
+) -> Result<bool, sqlx::Error> {
+    query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM unnest($1::uuid[]) AS e(id)
+            WHERE eval_held(e.id)
+        ) AS "held!"
+        "#,
+        eval_ids,
+    )
+    .fetch_one(&mut *tx)
+    .await
+}
+
+#[async_trait]
+impl Persist for HoldInsert {
+    type Ret = LegalHold;
+    type Error = HoldError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(HoldError::Unauthorized)?;
+
+        if self.spec.reason.trim().is_empty()
+            || (self.spec.runs.is_empty() && self.spec.evals.is_empty())
+        {
+            return Err(HoldError::InvalidHold);
+        }
+
+        let mut tx = state.db_conn.begin().await?;
+
+        let org = org_for_share(&mut tx, auth, &self.org).await?;
+
+        let hold_id = query_scalar!(
+            "INSERT INTO legal_holds (org_id, reason) VALUES ($1, $2) RETURNING id",
+            org.id,
+            self.spec.reason,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        // The held evals, and those recorded by held runs, are locked so that a delete which is
+        // already under way finishes first, and later ones see the hold.
+        let runs = query_scalar!(
+            r#"
+            INSERT INTO legal_hold_runs (hold_id, run_id)
+            SELECT $1, id
+            FROM runs
+            WHERE id = ANY($2)
+                AND user_id = $3
+            ON CONFLICT DO NOTHING
+            RETURNING run_id
+            "#,
+            hold_id,
+            &self.spec.runs,
+            org.owner_id,
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        let evals = query_scalar!(
+            r#"
+            INSERT INTO legal_hold_evals (hold_id, eval_id)
+            SELECT $1, id
+            FROM evals
+            WHERE id = ANY($2)
+                AND user_id = $3
+            ON CONFLICT DO NOTHING
+            RETURNING eval_id
+            "#,
+            hold_id,
+            &self.spec.evals,
+            org.owner_id,
+        )
+        .fetch_all(&mut tx)
+        .await?;
+        query!(
+            r#"
+            SELECT e.id
+            FROM evals e
+            JOIN runs r ON r.eval_id = e.id
+            WHERE r.id = ANY($1)
+            FOR SHARE OF e
+            "#,
+            &runs,
+        )
+        .fetch_all(&mut tx)
+        .await?;
+
+        // Duplicates were dropped on insert, so the counts only match if every one was found.
+        let mut wanted_runs = self.spec.runs.clone();
+        wanted_runs.sort();
+        wanted_runs.dedup();
+        let mut wanted_evals = self.spec.evals.clone();
+        wanted_evals.sort();
+        wanted_evals.dedup();
+        if runs.len() != wanted_runs.len() || evals.len() != wanted_evals.len() {
+            return Err(HoldError::NotFound);
+        }
+
+        record_event(&mut tx, auth, hold_id, HoldAction::Placed).await?;
+
+        let hold = query_as!(
+            LegalHold,
+            r#"
+            SELECT id, reason, create_dt, release_dt,
+                ARRAY(SELECT run_id FROM legal_hold_runs WHERE hold_id = $1 ORDER BY run_id)
+                    AS "runs!",
+                ARRAY(SELECT eval_id FROM legal_hold_evals WHERE hold_id = $1 ORDER BY eval_id)
+                    AS "evals!"
+            FROM legal_holds
+            WHERE id = $1
+            "#,
+            hold_id,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(hold)
+    }
+}
+
+#[async_trait]
+impl Query for HoldsGet {
+    type Resolve = Vec<LegalHold>;
+    type Error = HoldError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(HoldError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+        let org = org_for_share(&mut tx, auth, &self.org).await?;
+
+        let res = query_as!(
+            LegalHold,
+            r#"
+            SELECT h.id, h.reason, h.create_dt, h.release_dt,
+                ARRAY(SELECT run_id FROM legal_hold_runs r WHERE r.hold_id = h.id ORDER BY run_id)
+                    AS "runs!",
+                ARRAY(SELECT eval_id FROM legal_hold_evals e WHERE e.hold_id = h.id
+                    ORDER BY eval_id) AS "evals!"
+            FROM legal_holds h
+            WHERE h.org_id = $1
+            ORDER BY h.create_dt DESC
+            "#,
+            org.id,
+        )
+        .fetch_all(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Persist for HoldRelease {
+    type Ret = ();
+    type Error = HoldError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(HoldError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        let org = org_for_share(&mut tx, auth, &self.org).await?;
+
+        let released = query_scalar!(
+            r#"
+            SELECT release_dt IS NOT NULL AS "released!"
+            FROM legal_holds
+            WHERE id = $1
+                AND org_id = $2
+            FOR UPDATE
+            "#,
+            self.id,
+            org.id,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        if released {
+            return Err(HoldError::AlreadyReleased);
+        }
+
+        query!(
+            "UPDATE legal_holds SET release_dt = current_timestamp WHERE id = $1",
+            self.id,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        record_event(&mut tx, auth, self.id, HoldAction::Released).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Query for HoldEventsGet {
+    type Resolve = Vec<HoldEvent>;
+    type Error = HoldError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(HoldError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+        let org = org_for_share(&mut tx, auth, &self.org).await?;
+
+        let res = query_as!(
+            HoldEvent,
+            r#"
+            SELECT ev.id, ev.hold_id, ev.action, ev.user_id, ev.api_key_label, ev.runs,
+                ev.evals, ev.create_dt
+            FROM legal_hold_events ev
+            JOIN legal_holds h ON h.id = ev.hold_id
+            WHERE h.org_id = $1
+            ORDER BY ev.id DESC
+            "#,
+            org.id,
+        )
+        .fetch_all(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(res)
+    }
+}
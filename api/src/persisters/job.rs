@@ -0,0 +1,163 @@
+use crate::middlewares::auth::Auth;
+use crate::models::job::{Job, JobError};
+use crate::persisters::export::OpenLineageExport;
+use crate::persisters::{Persist, Query};
+use crate::state::State;
+use sqlx::types::Uuid;
+
+/// What a job produces, with everything needed to produce it. It's stored with the job, so that a
+/// job interrupted by a restart can be run again.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobSpec {
+    /// The user's runs and evals as OpenLineage events, as from `GET /export/openlineage`.
+    OpenlineageExport(OpenLineageExport),
+}
+
+impl JobSpec {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            JobSpec::OpenlineageExport(_) => "openlineage_export",
+        }
+    }
+}
+
+/// Submits a job to run in the background.
+pub struct JobSubmit {
+    pub spec: JobSpec,
+}
+
+/// Gets one of the authenticated user's jobs.
+pub struct JobGet {
+    pub id: Uuid,
+}
+
+fn default_limit() -> i64 {
+    100
+}
+
+/// Lists the authenticated user's jobs, most recent first.
+#[derive(Deserialize, Debug)]
+pub struct JobsGet {
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+#[async_trait]
+impl Persist for JobSubmit {
+    type Ret = Job;
+    type Error = JobError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(JobError::Unauthorized)?;
+
+        let spec = serde_json::to_value(&self.spec).unwrap_or_default();
+        let res = query_as!(
+            Job,
+            r#"
+            WITH job AS (
+                INSERT INTO jobs (user_id, kind, spec)
+                VALUES (get_user_id($1, $2), $3, $4)
+                RETURNING id, kind, status, progress_done, progress_total, error, create_dt,
+                    update_dt, finish_dt
+            )
+            SELECT id, kind, status, progress_done, progress_total,
+                NULL::text AS "content_hash?", error, create_dt, update_dt, finish_dt
+            FROM job
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.spec.as_str(),
+            spec,
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for JobGet {
+    type Resolve = Job;
+    type Error = JobError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(JobError::Unauthorized)?;
+
+        let res = query_as!(
+            Job,
+            r#"
+            SELECT j.id, j.kind, j.status, j.progress_done, j.progress_total,
+                b.content_hash AS "content_hash?", j.error, j.create_dt, j.update_dt, j.finish_dt
+            FROM jobs j
+            LEFT JOIN blobs b
+                ON b.id = j.blob_id
+            WHERE j.id = $1
+                AND j.user_id = get_user_id($2, $3)
+            "#,
+            self.id,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for JobsGet {
+    type Resolve = Vec<Job>;
+    type Error = JobError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(JobError::Unauthorized)?;
+
+        let res = query_as!(
+            Job,
+            r#"
+            SELECT j.id, j.kind, j.status, j.progress_done, j.progress_total,
+                b.content_hash AS "content_hash?", j.error, j.create_dt, j.update_dt, j.finish_dt
+            FROM jobs j
+            LEFT JOIN blobs b
+                ON b.id = j.blob_id
+            WHERE j.user_id = get_user_id($1, $2)
+            ORDER BY j.create_dt DESC
+            LIMIT $3
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.limit,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn specs_are_tagged_with_their_kind() {
+        let spec: JobSpec =
+            serde_json::from_str(r#"{"kind": "openlineage_export", "project": "churn"}"#).unwrap();
+        assert_eq!(spec.as_str(), "openlineage_export");
+        match &spec {
+            JobSpec::OpenlineageExport(export) => {
+                assert_eq!(export.project.as_deref(), Some("churn"));
+                assert_eq!(export.since, None);
+            }
+        }
+
+        // What's stored with the job reads back as the same spec.
+        let stored = serde_json::to_value(&spec).unwrap();
+        assert_eq!(stored["kind"], "openlineage_export");
+        let spec: JobSpec = serde_json::from_value(stored).unwrap();
+        assert_eq!(spec.as_str(), "openlineage_export");
+    }
+}
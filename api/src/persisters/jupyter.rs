@@ -0,0 +1,148 @@
+use crate::middlewares::auth::Auth;
+use crate::models::jupyter::{CompactEval, JupyterError, MAX_PREVIEW_BYTES, RESULT_PREVIEW_LEN};
+use crate::persisters::{
+    s3store::{StoreError, Target},
+    Persist, Query,
+};
+use crate::state::State;
+
+use blake3::Hash;
+
+/// Lists the most recent evals computed in a kernel session.
+pub struct SessionEvalsGet {
+    pub session_id: String,
+    pub limit: i64,
+}
+
+/// Pins or unpins an eval from a notebook.
+#[derive(Deserialize, Debug)]
+pub struct EvalPin {
+    pub fn_key: String,
+    pub fn_hash: String,
+    pub args_hash: String,
+    #[serde(default = "default_pinned")]
+    pub pinned: bool,
+}
+
+fn default_pinned() -> bool {
+    true
+}
+
+/// Fetches the first `len` bytes of a BLOB, for previewing it inline.
+pub struct BlobPreview {
+    pub content_hash: String,
+    pub len: u64,
+}
+
+#[async_trait]
+impl Query for SessionEvalsGet {
+    type Resolve = Vec<CompactEval>;
+    type Error = JupyterError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(JupyterError::Unauthorized)?;
+
+        let res = query_as!(
+            CompactEval,
+            r#"
+            SELECT fn_key, fn_hash, args_hash, content_hash, start_time, elapsed_process_time,
+                pinned, left(result_json::text, $2) AS result_preview
+            FROM evals e
+            JOIN blobs b
+                ON b.id = e.blob_id
+            WHERE e.session_id = $1
+                AND e.user_id = get_user_id($3, $4)
+            ORDER BY start_time DESC
+            LIMIT $5
+            "#,
+            self.session_id,
+            RESULT_PREVIEW_LEN,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.limit,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Persist for EvalPin {
+    type Ret = ();
+    type Error = JupyterError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(JupyterError::Unauthorized)?;
+
+        let res = query!(
+            r#"
+            UPDATE evals
+            SET pinned = $4
+            WHERE fn_key = $1
+                AND fn_hash = $2
+                AND args_hash = $3
+                AND user_id = get_user_id($5, $6)
+            "#,
+            self.fn_key,
+            self.fn_hash,
+            self.args_hash,
+            self.pinned,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .execute(&state.db_conn)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(JupyterError::NotFound);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Query for BlobPreview {
+    type Resolve = bytes::Bytes;
+    type Error = JupyterError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(JupyterError::Unauthorized)?;
+
+        let hash = Hash::from_hex(&self.content_hash).map_err(|_| JupyterError::InvalidHash)?;
+
+        let blob = query!(
+            r#"
+            SELECT storage_region, storage_bucket, storage_prefix
+            FROM blobs
+            WHERE content_hash = $1
+                AND storage_class = 'STANDARD'
+                AND user_id = get_user_id($2, $3)
+            "#,
+            self.content_hash,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+        let target = Target {
+            region: blob.storage_region,
+            bucket: blob.storage_bucket,
+            prefix: blob.storage_prefix,
+        };
+
+        let len = self.len.clamp(1, MAX_PREVIEW_BYTES);
+        let bytes = state
+            .blob_store
+            .retrieve_blob_range(&target, hash, 0, len - 1)
+            .await?
+            .collect()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?
+            .into_bytes();
+
+        Ok(bytes)
+    }
+}
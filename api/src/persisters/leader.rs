@@ -0,0 +1,52 @@
+use crate::leader::{JobLeader, LeaderError, LEADER_LOCK, SINGLETONS};
+use crate::middlewares::auth::Auth;
+use crate::persisters::user::is_admin;
+use crate::persisters::Query;
+use crate::state::State;
+
+/// Lists who leads each job which runs on one replica at a time. See [`crate::leader`].
+pub struct JobLeadersGet {}
+
+#[async_trait]
+impl Query for JobLeadersGet {
+    type Resolve = Vec<JobLeader>;
+    type Error = LeaderError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(LeaderError::Unauthorized)?;
+        if !is_admin(auth, state).await? {
+            return Err(LeaderError::Forbidden);
+        }
+
+        // A two-part advisory lock key is `classid` and `objid`, with `objsubid` 2.
+        let holders = query!(
+            r#"
+            SELECT l.objid::bigint AS "lock_id!", l.pid AS "pid!", host(a.client_addr) AS addr
+            FROM pg_locks l
+            LEFT JOIN pg_stat_activity a ON a.pid = l.pid
+            WHERE l.locktype = 'advisory'
+                AND l.objsubid = 2
+                AND l.classid::bigint = $1
+                AND l.granted
+            "#,
+            LEADER_LOCK as i64,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(SINGLETONS
+            .into_iter()
+            .map(|job| {
+                let holder = holders.iter().find(|h| h.lock_id == job.lock_id() as i64);
+                let since = state.leadership.since(job);
+                JobLeader {
+                    job,
+                    leader: since.is_some(),
+                    since,
+                    leader_pid: holder.map(|h| h.pid),
+                    leader_addr: holder.and_then(|h| h.addr.clone()),
+                }
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,241 @@
+use crate::cache::CacheKey;
+use crate::middlewares::auth::Auth;
+use crate::models::metric::{Metric, MetricError, MetricRule};
+use crate::persisters::user::user_id;
+use crate::persisters::{Persist, Query};
+use crate::state::State;
+
+use sqlx::{types::Uuid, Postgres, Transaction};
+
+/// Creates or replaces the metric rule `name` in the given project.
+#[derive(Debug)]
+pub struct MetricRuleUpsert {
+    pub project: String,
+    pub rule: MetricRule,
+}
+
+/// Deletes the metric rule `name` from the given project. Metrics already derived by the rule are
+/// kept.
+#[derive(Debug)]
+pub struct MetricRuleDelete {
+    pub project: String,
+    pub name: String,
+}
+
+/// Lists the metric rules of the given project.
+#[derive(Debug)]
+pub struct MetricRulesGet {
+    pub project: String,
+}
+
+/// Parameters for listing recorded metric values.
+#[derive(Deserialize, Debug)]
+pub struct MetricsGet {
+    pub project: Option<String>,
+    pub name: Option<String>,
+    pub fn_key: Option<String>,
+}
+
+/// Applies the metric rules of the eval's project to its `result_json`, recording a metric for each
+/// rule whose path resolves to a number. When the eval's BLOB has already been summarised as a
+/// tensor artifact, its NaN and infinity counts are recorded too.
+///
+/// This is called from within the transaction which inserts the eval, so that an eval is never
+/// visible without its derived metrics.
+pub async fn derive_metrics(
+    tx: &mut Transaction<'_, Postgres>,
+    eval_id: Uuid,
+) -> Result<(), sqlx::Error> {
+    derive_metrics_many(tx, &[eval_id]).await
+}
+
+/// As [`derive_metrics`], for many evals at once.
+pub async fn derive_metrics_many(
+    tx: &mut Transaction<'_, Postgres>,
+    eval_ids: &[Uuid],
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"
+        INSERT INTO metrics (eval_id, name, value)
+        SELECT e.id, r.name, (v #>> '{}')::float8
+        FROM evals e
+        JOIN metric_rules r
+            ON r.project_id = e.project_id
+        CROSS JOIN LATERAL jsonb_path_query_first(e.result_json, r.path::jsonpath) AS v
+        WHERE e.id = ANY($1)
+            AND jsonb_typeof(v) = 'number'
+        ON CONFLICT (eval_id, name) DO NOTHING
+        "#,
+        eval_ids,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    query!(
+        r#"
+        INSERT INTO metrics (eval_id, name, value)
+        SELECT e.id, m.name, m.value
+        FROM evals e
+        JOIN blobs b
+            ON b.id = e.blob_id
+        JOIN tensor_summaries s
+            ON s.content_hash = b.content_hash
+        CROSS JOIN LATERAL (
+            SELECT 'tensor.nan_count' AS name, sum((t ->> 'nan_count')::float8) AS value
+            FROM jsonb_array_elements(s.tensors) AS t
+            UNION ALL
+            SELECT 'tensor.inf_count', sum((t ->> 'inf_count')::float8)
+            FROM jsonb_array_elements(s.tensors) AS t
+        ) AS m
+        WHERE e.id = ANY($1)
+            AND s.status = 'done'
+            AND m.value IS NOT NULL
+        ON CONFLICT (eval_id, name) DO NOTHING
+        "#,
+        eval_ids,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl Persist for MetricRuleUpsert {
+    type Ret = ();
+    type Error = MetricError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(MetricError::Unauthorized)?;
+
+        // The cast to `jsonpath` rejects invalid paths up front, rather than at insert time.
+        query!(
+            r#"
+            INSERT INTO metric_rules (project_id, name, path)
+            SELECT p.id, $2, $3::text::jsonpath::text
+            FROM projects p
+            WHERE p.name = $1
+                AND p.user_id = get_user_id($4, $5)
+            ON CONFLICT (project_id, name) DO UPDATE
+                SET path = EXCLUDED.path
+            RETURNING id
+            "#,
+            self.project,
+            self.rule.name,
+            self.rule.path,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Persist for MetricRuleDelete {
+    type Ret = ();
+    type Error = MetricError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(MetricError::Unauthorized)?;
+
+        query!(
+            r#"
+            DELETE FROM metric_rules r
+            USING projects p
+            WHERE p.id = r.project_id
+                AND p.name = $1
+                AND r.name = $2
+                AND p.user_id = get_user_id($3, $4)
+            RETURNING r.id
+            "#,
+            self.project,
+            self.name,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Query for MetricRulesGet {
+    type Resolve = Vec<MetricRule>;
+    type Error = MetricError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(MetricError::Unauthorized)?;
+
+        let res = query_as!(
+            MetricRule,
+            r#"
+            SELECT r.name, r.path
+            FROM metric_rules r
+            JOIN projects p
+                ON p.id = r.project_id
+            WHERE p.name = $1
+                AND p.user_id = get_user_id($2, $3)
+            ORDER BY r.name
+            "#,
+            self.project,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for MetricsGet {
+    type Resolve = Vec<Metric>;
+    type Error = MetricError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(MetricError::Unauthorized)?;
+
+        let key = CacheKey {
+            user_id: user_id(auth, state).await?,
+            project: self.project.clone(),
+            listing: "metrics",
+            params: format!("{:?}", (&self.name, &self.fn_key)),
+        };
+
+        let query = query_as!(
+            Metric,
+            r#"
+            SELECT m.name, m.value, m.step, COALESCE(e.fn_key, r.fn_key) AS "fn_key!",
+                COALESCE(e.args_hash, r.args_hash) AS "args_hash!",
+                COALESCE(e.start_time, m.create_dt) AS "start_time!"
+            FROM metrics m
+            LEFT JOIN evals e
+                ON e.id = m.eval_id
+            LEFT JOIN runs r
+                ON r.id = m.run_id
+            LEFT JOIN projects p
+                ON p.id = COALESCE(e.project_id, r.project_id)
+            WHERE   (p.name = $1 OR $1 IS NULL)
+                AND (m.name = $2 OR $2 IS NULL)
+                AND (COALESCE(e.fn_key, r.fn_key) = $3 OR $3 IS NULL)
+                AND COALESCE(e.user_id, r.user_id) = get_user_id($4, $5)
+            ORDER BY 6, m.step
+            "#,
+            self.project,
+            self.name,
+            self.fn_key,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_all(&state.db_conn);
+        let res = state.listing_cache.get_or_fetch(key, query).await?;
+
+        Ok(res)
+    }
+}
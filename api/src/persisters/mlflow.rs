@@ -0,0 +1,616 @@
+use crate::middlewares::auth::Auth;
+use crate::models::dead_letter::DeadLetterOp;
+use crate::models::mlflow::{
+    artifact_uri, state_from_status, status_from_state, Experiment, KeyValue, MetricValue,
+    MlflowError, MlflowRun, RunData, RunInfo, DEFAULT_EXPERIMENT_ID, DEFAULT_EXPERIMENT_NAME,
+};
+use crate::models::run::RunState;
+use crate::persisters::dead_letter::{self, DeadLetterInsert};
+use crate::persisters::s3store::{store_shared, upload_target, Stored, Target};
+use crate::persisters::{blob::upsert_blob, Persist, Query};
+use crate::state::State;
+
+use aws_sdk_s3::types::ByteStream;
+use blake3::Hash;
+use sqlx::types::{
+    chrono::{DateTime, Utc},
+    Uuid,
+};
+
+#[derive(Deserialize, Debug)]
+pub struct CreateExperiment {
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetExperiment {
+    pub experiment_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetExperimentByName {
+    pub experiment_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateRun {
+    pub experiment_id: Option<String>,
+    pub run_name: Option<String>,
+    /// Milliseconds since the UNIX epoch.
+    pub start_time: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GetRun {
+    pub run_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UpdateRun {
+    pub run_id: String,
+    pub status: Option<String>,
+    pub run_name: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LogMetric {
+    pub run_id: String,
+    #[serde(flatten)]
+    pub metric: MetricValue,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LogParam {
+    pub run_id: String,
+    #[serde(flatten)]
+    pub param: KeyValue,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LogBatch {
+    pub run_id: String,
+    #[serde(default)]
+    pub metrics: Vec<MetricValue>,
+    #[serde(default)]
+    pub params: Vec<KeyValue>,
+}
+
+/// An artifact file uploaded through the `mlflow-artifacts` proxy.
+pub struct ArtifactUpload {
+    pub run_id: String,
+    pub path: String,
+    pub bytes: bytes::Bytes,
+}
+
+pub struct ArtifactGet {
+    pub run_id: String,
+    pub path: String,
+}
+
+struct ProjectResult {
+    id: Uuid,
+    name: String,
+}
+
+struct RunRow {
+    id: Uuid,
+    name: Option<String>,
+    project_id: Option<Uuid>,
+    state: String,
+    create_dt: DateTime<Utc>,
+    update_dt: DateTime<Utc>,
+}
+
+struct IdResult {
+    id: Uuid,
+}
+
+struct ContentHashResult {
+    content_hash: String,
+    storage_region: Option<String>,
+    storage_bucket: Option<String>,
+    storage_prefix: Option<String>,
+}
+
+fn parse_uuid(s: &str, field: &str) -> Result<Uuid, MlflowError> {
+    Uuid::parse_str(s).map_err(|_| MlflowError::InvalidParameter(format!("invalid {}", field)))
+}
+
+/// Maps an MLflow experiment ID to a project ID; the default experiment has no project.
+fn parse_experiment_id(s: &str) -> Result<Option<Uuid>, MlflowError> {
+    if s == DEFAULT_EXPERIMENT_ID {
+        Ok(None)
+    } else {
+        parse_uuid(s, "experiment_id").map(Some)
+    }
+}
+
+fn millis_to_datetime(ms: i64) -> Option<DateTime<Utc>> {
+    use sqlx::types::chrono::TimeZone;
+    Utc.timestamp_millis_opt(ms).single()
+}
+
+fn experiment(id: Option<Uuid>, name: String) -> Experiment {
+    let experiment_id = id.map_or(DEFAULT_EXPERIMENT_ID.to_string(), |id| id.to_string());
+    Experiment {
+        artifact_location: format!("mlflow-artifacts:/{}", experiment_id),
+        experiment_id,
+        name,
+        lifecycle_stage: "active",
+    }
+}
+
+impl RunRow {
+    fn into_info(self) -> RunInfo {
+        let state = self.state.parse::<RunState>().unwrap_or(RunState::Queued);
+        let experiment_id = self
+            .project_id
+            .map_or(DEFAULT_EXPERIMENT_ID.to_string(), |id| id.to_string());
+        let run_id = self.id.to_string();
+        RunInfo {
+            artifact_uri: artifact_uri(&experiment_id, &run_id),
+            run_uuid: run_id.clone(),
+            run_id,
+            run_name: self.name,
+            experiment_id,
+            status: status_from_state(state),
+            start_time: self.create_dt.timestamp_millis(),
+            end_time: state
+                .is_terminal()
+                .then(|| self.update_dt.timestamp_millis()),
+            lifecycle_stage: "active",
+        }
+    }
+}
+
+/// Fetches a run, checking that it belongs to the authenticated user.
+async fn fetch_run(run_id: Uuid, auth: &Auth, state: &State) -> Result<RunRow, MlflowError> {
+    let row = query_as!(
+        RunRow,
+        r#"
+        SELECT id, name, project_id, state, create_dt, update_dt
+        FROM runs
+        WHERE id = $1
+            AND user_id = get_user_id($2, $3)
+        "#,
+        run_id,
+        auth.jwt().map(|c| c.sub),
+        auth.api_key(),
+    )
+    .fetch_one(&state.db_conn)
+    .await?;
+
+    Ok(row)
+}
+
+#[async_trait]
+impl Persist for CreateExperiment {
+    type Ret = String;
+    type Error = MlflowError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(MlflowError::Unauthorized)?;
+
+        let res = query_as!(
+            IdResult,
+            r#"
+            INSERT INTO projects (user_id, name)
+            VALUES (get_user_id($1, $2), $3)
+            ON CONFLICT (user_id, name) DO UPDATE
+                SET name = EXCLUDED.name
+            RETURNING id
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.name,
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(res.id.to_string())
+    }
+}
+
+#[async_trait]
+impl Query for GetExperiment {
+    type Resolve = Experiment;
+    type Error = MlflowError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(MlflowError::Unauthorized)?;
+
+        let id = match parse_experiment_id(&self.experiment_id)? {
+            Some(id) => id,
+            None => return Ok(experiment(None, DEFAULT_EXPERIMENT_NAME.to_string())),
+        };
+
+        let res = query_as!(
+            ProjectResult,
+            r#"
+            SELECT id, name
+            FROM projects
+            WHERE id = $1
+                AND user_id = get_user_id($2, $3)
+            "#,
+            id,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(experiment(Some(res.id), res.name))
+    }
+}
+
+#[async_trait]
+impl Query for GetExperimentByName {
+    type Resolve = Experiment;
+    type Error = MlflowError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(MlflowError::Unauthorized)?;
+
+        if self.experiment_name == DEFAULT_EXPERIMENT_NAME {
+            return Ok(experiment(None, self.experiment_name));
+        }
+
+        let res = query_as!(
+            ProjectResult,
+            r#"
+            SELECT id, name
+            FROM projects
+            WHERE name = $1
+                AND user_id = get_user_id($2, $3)
+            "#,
+            self.experiment_name,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(experiment(Some(res.id), res.name))
+    }
+}
+
+#[async_trait]
+impl Persist for CreateRun {
+    type Ret = MlflowRun;
+    type Error = MlflowError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(MlflowError::Unauthorized)?;
+
+        let project_id = match &self.experiment_id {
+            Some(id) => parse_experiment_id(id)?,
+            None => None,
+        };
+        let start_time = self.start_time.and_then(millis_to_datetime);
+
+        // MLflow runs aren't evals of a function, so they have no function or argument hashes.
+        // The run name stands in for the function key.
+        let res = query_as!(
+            IdResult,
+            r#"
+            INSERT INTO runs (user_id, project_id, name, fn_key, fn_hash, args_hash, state, create_dt)
+            SELECT u.id, $3, $4, COALESCE($4, 'mlflow'), '', '', 'running',
+                COALESCE($5, current_timestamp)
+            FROM (SELECT get_user_id($1, $2) AS id) u
+            WHERE $3::uuid IS NULL
+                OR EXISTS (SELECT 1 FROM projects p WHERE p.id = $3 AND p.user_id = u.id)
+            RETURNING id
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            project_id,
+            self.run_name,
+            start_time,
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        let info = fetch_run(res.id, auth, state).await?.into_info();
+
+        Ok(MlflowRun {
+            info,
+            data: RunData::default(),
+        })
+    }
+}
+
+#[async_trait]
+impl Query for GetRun {
+    type Resolve = MlflowRun;
+    type Error = MlflowError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(MlflowError::Unauthorized)?;
+        let run_id = parse_uuid(&self.run_id, "run_id")?;
+
+        let info = fetch_run(run_id, auth, state).await?.into_info();
+
+        // Like MLflow, report the latest value of each metric.
+        let metrics = query_as!(
+            MetricValue,
+            r#"
+            SELECT DISTINCT ON (name) name AS key, value,
+                (extract(epoch FROM create_dt) * 1000)::int8 AS "timestamp!", step
+            FROM metrics
+            WHERE run_id = $1
+            ORDER BY name, step DESC, create_dt DESC
+            "#,
+            run_id,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        let params = query_as!(
+            KeyValue,
+            "SELECT key, value FROM run_params WHERE run_id = $1 ORDER BY key",
+            run_id,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(MlflowRun {
+            info,
+            data: RunData {
+                metrics,
+                params,
+                tags: vec![],
+            },
+        })
+    }
+}
+
+#[async_trait]
+impl Persist for UpdateRun {
+    type Ret = RunInfo;
+    type Error = MlflowError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(MlflowError::Unauthorized)?;
+        let run_id = parse_uuid(&self.run_id, "run_id")?;
+
+        let current = fetch_run(run_id, auth, state).await?;
+        let from = current
+            .state
+            .parse::<RunState>()
+            .unwrap_or(RunState::Queued);
+
+        let next = match &self.status {
+            Some(status) => state_from_status(status)
+                .ok_or_else(|| MlflowError::InvalidParameter("invalid status".to_string()))?,
+            None => from,
+        };
+        if next != from && !from.can_transition_to(next) {
+            return Err(MlflowError::InvalidParameter(format!(
+                "cannot move run from {} to {}",
+                status_from_state(from),
+                status_from_state(next)
+            )));
+        }
+
+        query!(
+            r#"
+            UPDATE runs
+            SET state = $2,
+                name = COALESCE($3, name),
+                update_dt = current_timestamp
+            WHERE id = $1
+            "#,
+            run_id,
+            next.as_str(),
+            self.run_name,
+        )
+        .execute(&state.db_conn)
+        .await?;
+
+        Ok(fetch_run(run_id, auth, state).await?.into_info())
+    }
+}
+
+#[async_trait]
+impl Persist for LogMetric {
+    type Ret = ();
+    type Error = MlflowError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        LogBatch {
+            run_id: self.run_id,
+            metrics: vec![self.metric],
+            params: vec![],
+        }
+        .persist(auth, state)
+        .await
+    }
+}
+
+#[async_trait]
+impl Persist for LogParam {
+    type Ret = ();
+    type Error = MlflowError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        LogBatch {
+            run_id: self.run_id,
+            metrics: vec![],
+            params: vec![self.param],
+        }
+        .persist(auth, state)
+        .await
+    }
+}
+
+#[async_trait]
+impl Persist for LogBatch {
+    type Ret = ();
+    type Error = MlflowError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(MlflowError::Unauthorized)?;
+        let run_id = parse_uuid(&self.run_id, "run_id")?;
+
+        // Checks that the run belongs to the user.
+        fetch_run(run_id, auth, state).await?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        for metric in self.metrics {
+            query!(
+                r#"
+                INSERT INTO metrics (run_id, name, value, step, create_dt)
+                VALUES ($1, $2, $3, $4, COALESCE($5, current_timestamp))
+                "#,
+                run_id,
+                metric.key,
+                metric.value,
+                metric.step,
+                millis_to_datetime(metric.timestamp),
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        // Params are immutable in MLflow, so logging the same key twice keeps the first value.
+        for param in self.params {
+            query!(
+                r#"
+                INSERT INTO run_params (run_id, key, value)
+                VALUES ($1, $2, $3)
+                ON CONFLICT (run_id, key) DO NOTHING
+                "#,
+                run_id,
+                param.key,
+                param.value,
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Persist for ArtifactUpload {
+    type Ret = ();
+    type Error = MlflowError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(MlflowError::Unauthorized)?;
+        let run_id = parse_uuid(&self.run_id, "run_id")?;
+
+        fetch_run(run_id, auth, state).await?;
+
+        let hash = blake3::hash(&self.bytes);
+        let target = upload_target(auth, state).await?;
+        let (target, stored) = store_shared(state, target, hash, self.bytes).await?;
+
+        let content_hash = hash.to_hex().to_string();
+        let recorded = record_artifact(
+            auth,
+            run_id,
+            &self.path,
+            &content_hash,
+            &target,
+            stored,
+            state,
+        )
+        .await;
+        if let Err(e) = recorded {
+            let letter = DeadLetterInsert {
+                content_hash,
+                target,
+                op: DeadLetterOp::RunArtifact {
+                    run_id,
+                    path: self.path,
+                },
+                error: e.to_string(),
+            };
+            dead_letter::record(state, auth, letter).await;
+            return Err(e.into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Records an artifact's BLOB, and the path it was logged at, once the BLOB is stored.
+async fn record_artifact(
+    auth: &Auth,
+    run_id: Uuid,
+    path: &str,
+    content_hash: &str,
+    target: &Target,
+    stored: Option<Stored>,
+    state: &State,
+) -> Result<(), sqlx::Error> {
+    let mut tx = state.db_conn.begin().await?;
+
+    let blob_id = upsert_blob(&mut tx, auth, content_hash, target, stored).await?;
+
+    query!(
+        r#"
+        INSERT INTO run_artifacts (run_id, path, blob_id)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (run_id, path) DO UPDATE
+            SET blob_id = EXCLUDED.blob_id,
+                create_dt = current_timestamp
+        "#,
+        run_id,
+        path,
+        blob_id,
+    )
+    .execute(&mut tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl Query for ArtifactGet {
+    type Resolve = ByteStream;
+    type Error = MlflowError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(MlflowError::Unauthorized)?;
+        let run_id = parse_uuid(&self.run_id, "run_id")?;
+
+        let res = query_as!(
+            ContentHashResult,
+            r#"
+            SELECT b.content_hash, b.storage_region, b.storage_bucket, b.storage_prefix
+            FROM run_artifacts a
+            JOIN runs r
+                ON r.id = a.run_id
+            JOIN blobs b
+                ON b.id = a.blob_id
+            WHERE a.run_id = $1
+                AND a.path = $2
+                AND r.user_id = get_user_id($3, $4)
+            "#,
+            run_id,
+            self.path,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        let hash = Hash::from_hex(&res.content_hash)
+            .map_err(|_| MlflowError::InvalidParameter("corrupt content hash".to_string()))?;
+
+        let target = Target {
+            region: res.storage_region,
+            bucket: res.storage_bucket,
+            prefix: res.storage_prefix,
+        };
+        Ok(state.blob_store.retrieve_blob(&target, hash).await?)
+    }
+}
@@ -0,0 +1,83 @@
+use crate::middlewares::auth::Auth;
+use crate::persisters::{Persist, Query};
+use crate::policy::{PolicyError, Rule};
+use crate::state::State;
+use sqlx::types::{JsonValue, Uuid};
+
+/// Sets the rules of an org's policy. Only the org's owner can set them.
+pub struct PolicyPut {
+    pub org_id: Uuid,
+    pub rules: Vec<Rule>,
+}
+
+pub struct PolicyGet {
+    pub org_id: Uuid,
+}
+
+struct PolicyResult {
+    rules: JsonValue,
+}
+
+#[async_trait]
+impl Persist for PolicyPut {
+    type Ret = Vec<Rule>;
+    type Error = PolicyError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(PolicyError::Unauthorized)?;
+
+        let rules = serde_json::to_value(&self.rules).map_err(|_| PolicyError::InvalidRules)?;
+
+        query!(
+            r#"
+            INSERT INTO org_policies (org_id, rules)
+            SELECT id, $4
+            FROM orgs
+            WHERE id = $1
+                AND owner_id = get_user_id($2, $3)
+            ON CONFLICT (org_id) DO UPDATE
+                SET rules = EXCLUDED.rules,
+                    update_dt = current_timestamp
+            RETURNING org_id
+            "#,
+            self.org_id,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            rules,
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(self.rules)
+    }
+}
+
+#[async_trait]
+impl Query for PolicyGet {
+    type Resolve = Vec<Rule>;
+    type Error = PolicyError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(PolicyError::Unauthorized)?;
+
+        // An org which has never set a policy has no rules of its own.
+        let res = query_as!(
+            PolicyResult,
+            r#"
+            SELECT coalesce(p.rules, '[]') AS "rules!"
+            FROM orgs o
+            LEFT JOIN org_policies p
+                ON p.org_id = o.id
+            WHERE o.id = $1
+                AND o.owner_id = get_user_id($2, $3)
+            "#,
+            self.org_id,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        serde_json::from_value(res.rules).map_err(|_| PolicyError::InvalidRules)
+    }
+}
@@ -0,0 +1,275 @@
+//! Uploads of BLOBs straight to S3, with presigned URLs, so that very large BLOBs needn't pass
+//! through the server at all.
+//!
+//! Since the server doesn't see the bytes as they're uploaded, the user's storage quota is checked
+//! before a URL is given out, and the BLOB is checked against its claimed hash once the upload is
+//! confirmed. Until then it's staged under a key of its own (see [`Target::staged_key`]), so that
+//! an upload which isn't the BLOB claimed never replaces one which is.
+use crate::middlewares::auth::Auth;
+use crate::models::anomaly::Activity;
+use crate::models::dead_letter::DeadLetterOp;
+use crate::models::presigned_upload::{
+    PresignedUpload, PresignedUploadError, MAX_PRESIGNED_LENGTH, PRESIGNED_UPLOAD_TTL_HOURS,
+    PRESIGNED_URL_TTL_SECS,
+};
+use crate::persisters::anomaly::record_activity;
+use crate::persisters::blob::upsert_blob;
+use crate::persisters::dead_letter::{self, DeadLetterInsert};
+use crate::persisters::s3store::{shared_object, upload_target, StoreError, Stored, Target};
+use crate::persisters::usage::within_storage_quota;
+use crate::persisters::user::user_id;
+use crate::persisters::Persist;
+use crate::state::State;
+use blake3::{Hash, Hasher};
+use futures::StreamExt;
+use sqlx::types::Uuid;
+use std::time::Duration;
+
+/// Starts an upload of a BLOB straight to S3.
+#[derive(Deserialize, Debug)]
+pub struct PresignedUploadStart {
+    pub content_hash: String,
+    pub content_length: i64,
+}
+
+/// Confirms that a BLOB has been uploaded straight to S3, returning the BLOB's id once it's been
+/// checked and stored.
+pub struct PresignedUploadConfirm {
+    pub id: Uuid,
+}
+
+struct UploadRow {
+    id: Uuid,
+    content_hash: String,
+    content_length: i64,
+    storage_region: Option<String>,
+    storage_bucket: Option<String>,
+    storage_prefix: Option<String>,
+}
+
+impl UploadRow {
+    fn target(&self) -> Target {
+        Target {
+            region: self.storage_region.clone(),
+            bucket: self.storage_bucket.clone(),
+            prefix: self.storage_prefix.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Persist for PresignedUploadStart {
+    type Ret = PresignedUpload;
+    type Error = PresignedUploadError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(PresignedUploadError::Unauthorized)?;
+        let hash = Hash::from_hex(&self.content_hash)?;
+        let hex = hash.to_hex();
+        if !(0..=MAX_PRESIGNED_LENGTH).contains(&self.content_length) {
+            return Err(PresignedUploadError::InvalidLength);
+        }
+
+        let user_id = user_id(auth, state).await?;
+        if !within_storage_quota(state, user_id, self.content_length).await? {
+            return Err(PresignedUploadError::QuotaExceeded);
+        }
+
+        // A BLOB which is already stored where it would be uploaded to needn't be uploaded again.
+        let id = Uuid::new_v4();
+        let target = upload_target(auth, state).await?;
+        let put = match shared_object(&target, hex.as_str(), state).await? {
+            Some(_) => None,
+            None => {
+                let expires_in = Duration::from_secs(PRESIGNED_URL_TTL_SECS);
+                let put = state
+                    .blob_store
+                    .presign_staged_put(&target, id, self.content_length, expires_in)
+                    .await?
+                    .ok_or(PresignedUploadError::Unsupported)?;
+                Some(put)
+            }
+        };
+
+        let expire_dt = query_scalar!(
+            r#"
+            INSERT INTO presigned_uploads (id, user_id, content_hash, content_length,
+                storage_region, storage_bucket, storage_prefix, expire_dt)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, now() + make_interval(hours => $8))
+            RETURNING expire_dt
+            "#,
+            id,
+            user_id,
+            hex.as_str(),
+            self.content_length,
+            target.region,
+            target.bucket,
+            target.prefix,
+            PRESIGNED_UPLOAD_TTL_HOURS,
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(PresignedUpload {
+            id,
+            content_hash: hex.to_string(),
+            content_length: self.content_length,
+            put,
+            expire_dt,
+        })
+    }
+}
+
+/// Hashes what was uploaded to the staging key of an upload, returning its hash and length.
+async fn hash_staged(
+    state: &State,
+    upload: &UploadRow,
+) -> Result<(Hash, i64), PresignedUploadError> {
+    let mut stream = state
+        .blob_store
+        .retrieve_staged(&upload.target(), upload.id)
+        .await
+        .map_err(|e| match e {
+            StoreError::NotFound => PresignedUploadError::NotUploaded,
+            e => e.into(),
+        })?;
+    let mut hasher = Hasher::new();
+    let mut len = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| StoreError::S3Other(Box::new(e)))?;
+        hasher.update(&chunk);
+        len += chunk.len() as i64;
+    }
+
+    Ok((hasher.finalize(), len))
+}
+
+/// Abandons an upload, deleting whatever was staged.
+async fn abandon(state: &State, upload: &UploadRow) -> Result<(), PresignedUploadError> {
+    state
+        .blob_store
+        .delete_staged(&upload.target(), upload.id)
+        .await?;
+    query!("DELETE FROM presigned_uploads WHERE id = $1", upload.id)
+        .execute(&state.db_conn)
+        .await?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl Persist for PresignedUploadConfirm {
+    type Ret = i64;
+    type Error = PresignedUploadError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(PresignedUploadError::Unauthorized)?;
+        let user_id = user_id(auth, state).await?;
+        let upload = query_as!(
+            UploadRow,
+            r#"
+            SELECT id, content_hash, content_length, storage_region, storage_bucket,
+                storage_prefix
+            FROM presigned_uploads
+            WHERE id = $1 AND user_id = $2 AND expire_dt > now()
+            "#,
+            self.id,
+            user_id,
+        )
+        .fetch_optional(&state.db_conn)
+        .await?
+        .ok_or(PresignedUploadError::NotFound)?;
+
+        // If the BLOB has been stored by someone else meanwhile, their object is shared and
+        // whatever was staged is thrown away. Otherwise what was staged has to be the BLOB claimed
+        // before it's moved into place.
+        let hash = Hash::from_hex(&upload.content_hash)?;
+        let (target, stored) =
+            match shared_object(&upload.target(), &upload.content_hash, state).await? {
+                Some(shared) => {
+                    let delete = state
+                        .blob_store
+                        .delete_staged(&upload.target(), upload.id)
+                        .await;
+                    if let Err(e) = delete {
+                        log::warn!("could not delete staged upload {}: {:?}", upload.id, e);
+                    }
+                    (shared, None)
+                }
+                None => {
+                    let (staged_hash, staged_len) = hash_staged(state, &upload).await?;
+                    if staged_hash != hash || staged_len != upload.content_length {
+                        record_activity(state, auth, Activity::InvalidHash, None).await;
+                        // If it can't be deleted now, it's deleted once it expires.
+                        if let Err(e) = abandon(state, &upload).await {
+                            log::warn!("error abandoning upload {}: {:?}", upload.id, e);
+                        }
+                        return Err(PresignedUploadError::InvalidHash);
+                    }
+                    let target = upload.target();
+                    state
+                        .blob_store
+                        .promote_staged(&target, upload.id, hash)
+                        .await?;
+                    (target, Some(Stored::raw(upload.content_length)))
+                }
+            };
+
+        match record_blob(auth, &upload, &target, stored, state).await {
+            Ok(id) => Ok(id),
+            Err(e) => {
+                let letter = DeadLetterInsert {
+                    content_hash: upload.content_hash.clone(),
+                    target,
+                    op: DeadLetterOp::Blob,
+                    error: e.to_string(),
+                };
+                dead_letter::record(state, auth, letter).await;
+                Err(e.into())
+            }
+        }
+    }
+}
+
+/// Records a confirmed upload's BLOB, once it's stored, and closes the upload.
+async fn record_blob(
+    auth: &Auth,
+    upload: &UploadRow,
+    target: &Target,
+    stored: Option<Stored>,
+    state: &State,
+) -> Result<i64, sqlx::Error> {
+    let mut tx = state.db_conn.begin().await?;
+    let id = upsert_blob(&mut tx, auth, &upload.content_hash, target, stored).await?;
+    query!("DELETE FROM presigned_uploads WHERE id = $1", upload.id)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+
+    Ok(id)
+}
+
+/// Abandons uploads which have expired unconfirmed, returning how many were. Uploads whose staged
+/// objects can't be deleted are left to be tried again.
+pub async fn prune_expired(state: &State) -> Result<u64, PresignedUploadError> {
+    let expired = query_as!(
+        UploadRow,
+        r#"
+        SELECT id, content_hash, content_length, storage_region, storage_bucket, storage_prefix
+        FROM presigned_uploads
+        WHERE expire_dt <= now()
+        "#,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    let mut pruned = 0;
+    for upload in expired {
+        match abandon(state, &upload).await {
+            Ok(()) => pruned += 1,
+            Err(e) => log::warn!("error abandoning expired upload {}: {:?}", upload.id, e),
+        }
+    }
+
+    Ok(pruned)
+}
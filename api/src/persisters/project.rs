@@ -0,0 +1,201 @@
+use crate::integrity::{IntegrityManifest, ManifestEntry, SignedManifest};
+use crate::middlewares::auth::Auth;
+use crate::models::project::{Project, ProjectError};
+use crate::persisters::{Persist, Query};
+use crate::state::State;
+use sqlx::types::{chrono::Utc, Uuid};
+
+/// Creates a project, or updates the settings of an existing project with the same name.
+#[derive(Deserialize, Debug)]
+pub struct ProjectUpsert {
+    pub name: String,
+    #[serde(default)]
+    pub index_results: bool,
+    #[serde(default)]
+    pub archive_after_days: Option<i32>,
+    #[serde(default)]
+    pub miss_storm_webhook_url: Option<String>,
+    #[serde(default = "default_miss_storm_threshold")]
+    pub miss_storm_threshold: f64,
+    #[serde(default = "default_miss_storm_window_hours")]
+    pub miss_storm_window_hours: i32,
+    #[serde(default)]
+    pub validation_webhook_url: Option<String>,
+    #[serde(default = "default_validation_timeout_ms")]
+    pub validation_timeout_ms: i32,
+    #[serde(default = "default_validation_fail_open")]
+    pub validation_fail_open: bool,
+}
+
+fn default_miss_storm_threshold() -> f64 {
+    0.5
+}
+
+fn default_miss_storm_window_hours() -> i32 {
+    1
+}
+
+fn default_validation_timeout_ms() -> i32 {
+    2000
+}
+
+fn default_validation_fail_open() -> bool {
+    true
+}
+
+/// Lists all of the projects belonging to the authenticated user.
+pub struct ProjectsGet {}
+
+/// The signed integrity manifest of one of the authenticated user's projects, listing the BLOBs
+/// of its evals and run artifacts as they are now.
+pub struct ProjectIntegrityGet {
+    pub id: Uuid,
+}
+
+#[async_trait]
+impl Persist for ProjectUpsert {
+    type Ret = Project;
+    type Error = ProjectError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(ProjectError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        let project = query_as!(
+            Project,
+            r#"
+            INSERT INTO projects (user_id, name, index_results, archive_after_days,
+                miss_storm_webhook_url, miss_storm_threshold, miss_storm_window_hours,
+                validation_webhook_url, validation_timeout_ms, validation_fail_open)
+            VALUES (get_user_id($1, $2), $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            ON CONFLICT (user_id, name) DO UPDATE
+                SET index_results = EXCLUDED.index_results,
+                    archive_after_days = EXCLUDED.archive_after_days,
+                    miss_storm_webhook_url = EXCLUDED.miss_storm_webhook_url,
+                    miss_storm_threshold = EXCLUDED.miss_storm_threshold,
+                    miss_storm_window_hours = EXCLUDED.miss_storm_window_hours,
+                    validation_webhook_url = EXCLUDED.validation_webhook_url,
+                    validation_timeout_ms = EXCLUDED.validation_timeout_ms,
+                    validation_fail_open = EXCLUDED.validation_fail_open
+            RETURNING id, name, index_results, archive_after_days, miss_storm_webhook_url,
+                miss_storm_threshold, miss_storm_window_hours, miss_storm_fired_dt,
+                validation_webhook_url, validation_timeout_ms, validation_fail_open, create_dt
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.name,
+            self.index_results,
+            self.archive_after_days,
+            self.miss_storm_webhook_url,
+            self.miss_storm_threshold,
+            self.miss_storm_window_hours,
+            self.validation_webhook_url,
+            self.validation_timeout_ms,
+            self.validation_fail_open,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        // Bring evals which are already stored in line with the (possibly changed) setting, so
+        // that they are added to, or dropped from, the partial index on `result_json`.
+        query!(
+            r#"
+            UPDATE evals
+            SET result_indexed = $2
+            WHERE project_id = $1
+                AND result_indexed <> $2
+            "#,
+            project.id,
+            project.index_results,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(project)
+    }
+}
+
+#[async_trait]
+impl Query for ProjectsGet {
+    type Resolve = Vec<Project>;
+    type Error = ProjectError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(ProjectError::Unauthorized)?;
+
+        let res = query_as!(
+            Project,
+            r#"
+            SELECT id, name, index_results, archive_after_days, miss_storm_webhook_url,
+                miss_storm_threshold, miss_storm_window_hours, miss_storm_fired_dt,
+                validation_webhook_url, validation_timeout_ms, validation_fail_open, create_dt
+            FROM projects
+            WHERE user_id = get_user_id($1, $2)
+            ORDER BY name
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for ProjectIntegrityGet {
+    type Resolve = SignedManifest;
+    type Error = ProjectError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(ProjectError::Unauthorized)?;
+        let key = state
+            .integrity_key
+            .as_ref()
+            .ok_or(ProjectError::IntegrityDisabled)?;
+
+        query!(
+            "SELECT id FROM projects WHERE id = $1 AND user_id = get_user_id($2, $3)",
+            self.id,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_optional(&state.db_conn)
+        .await?
+        .ok_or(ProjectError::NotFound)?;
+
+        // One statement, so that the listing is of a single point in time.
+        let generated_dt = Utc::now();
+        let blobs = query_as!(
+            ManifestEntry,
+            r#"
+            SELECT b.content_hash, b.content_length
+            FROM blobs b
+            WHERE b.id IN (
+                SELECT e.blob_id FROM evals e WHERE e.project_id = $1
+                UNION
+                SELECT a.blob_id
+                FROM run_artifacts a
+                JOIN runs r ON r.id = a.run_id
+                WHERE r.project_id = $1
+            )
+            ORDER BY b.content_hash
+            "#,
+            self.id,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        let manifest = IntegrityManifest {
+            project_id: self.id,
+            generated_dt,
+            blobs,
+        };
+
+        Ok(key.sign(manifest))
+    }
+}
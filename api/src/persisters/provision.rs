@@ -0,0 +1,831 @@
+use crate::keys::StoredKey;
+use crate::middlewares::auth::Auth;
+use crate::models::api_key::ApiKey;
+use crate::models::provision::{
+    Org, OrgProject, Precondition, ProvisionError, Provisioned, ServiceAccount, ServiceAccountKey,
+};
+use crate::persisters::s3store::Target;
+use crate::persisters::{Persist, Query};
+use crate::state::State;
+use sqlx::{types::Uuid, Postgres, Transaction};
+
+/// The declared state of an org.
+#[derive(Deserialize, Debug)]
+pub struct OrgSpec {
+    pub name: String,
+    /// The deployment region to pin the org's BLOBs to, for data residency.
+    #[serde(default)]
+    pub residency_region: Option<String>,
+}
+
+/// The declared state of a project in an org.
+#[derive(Deserialize, Debug)]
+pub struct ProjectSpec {
+    pub name: String,
+    #[serde(default)]
+    pub index_results: bool,
+    #[serde(default)]
+    pub archive_after_days: Option<i32>,
+}
+
+/// The declared state of a service account.
+#[derive(Deserialize, Debug)]
+pub struct ServiceAccountSpec {
+    pub name: String,
+}
+
+/// The declared state of a service account's API key.
+#[derive(Deserialize, Debug)]
+pub struct KeySpec {
+    pub label: String,
+}
+
+/// Identifies a provisioned resource by the external ids of it and its ancestors. `org` is the
+/// external id of the org, and `service_account` that of the service account, where relevant.
+#[derive(Debug)]
+pub struct ResourcePath {
+    pub org: String,
+    pub service_account: Option<String>,
+    pub external_id: String,
+}
+
+/// Creates or updates a resource so that it matches `spec`. Putting a resource which already
+/// matches its spec leaves it, and its version, unchanged.
+pub struct ResourcePut<S> {
+    pub path: ResourcePath,
+    pub spec: S,
+    pub precondition: Precondition,
+}
+
+pub struct OrgGet {
+    pub path: ResourcePath,
+}
+
+pub struct OrgDelete {
+    pub path: ResourcePath,
+    pub precondition: Precondition,
+}
+
+pub struct ProjectGet {
+    pub path: ResourcePath,
+}
+
+pub struct ProjectDelete {
+    pub path: ResourcePath,
+    pub precondition: Precondition,
+}
+
+pub struct ServiceAccountGet {
+    pub path: ResourcePath,
+}
+
+pub struct ServiceAccountDelete {
+    pub path: ResourcePath,
+    pub precondition: Precondition,
+}
+
+pub struct KeyGet {
+    pub path: ResourcePath,
+}
+
+pub struct KeyDelete {
+    pub path: ResourcePath,
+    pub precondition: Precondition,
+}
+
+/// Looks up an org of the authenticated user by its external id, locking it against deletion for
+/// the rest of the transaction.
+async fn org_for_share(
+    tx: &mut Transaction<'_, Postgres>,
+    auth: &Auth,
+    org: &str,
+) -> Result<Org, ProvisionError> {
+    let org = query_as!(
+        Org,
+        r#"
+        SELECT id, external_id, name, residency_region, version, create_dt, update_dt
+        FROM orgs
+        WHERE owner_id = get_user_id($1, $2)
+            AND external_id = $3
+        FOR SHARE
+        "#,
+        auth.jwt().map(|c| c.sub),
+        auth.api_key(),
+        org,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    Ok(org)
+}
+
+/// Looks up a service account of an org by its external id, locking it against deletion for the
+/// rest of the transaction.
+async fn service_account_for_share(
+    tx: &mut Transaction<'_, Postgres>,
+    org_id: Uuid,
+    service_account: &str,
+) -> Result<ServiceAccount, ProvisionError> {
+    let service_account = query_as!(
+        ServiceAccount,
+        r#"
+        SELECT id, external_id, name, version, create_dt, update_dt
+        FROM service_accounts
+        WHERE org_id = $1
+            AND external_id = $2
+        FOR SHARE
+        "#,
+        org_id,
+        service_account,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    Ok(service_account)
+}
+
+/// Checks that an org's BLOBs can be pinned to `region`: the region exists and its bucket can be
+/// reached. BLOBs belong to the owner of the org rather than to the org itself, so no other org of
+/// the owner may pin them elsewhere.
+async fn check_residency(
+    tx: &mut Transaction<'_, Postgres>,
+    auth: &Auth,
+    org_id: Option<Uuid>,
+    region: &str,
+    state: &State,
+) -> Result<(), ProvisionError> {
+    let bucket = query_scalar!(
+        "SELECT bucket FROM deployment_regions WHERE name = $1 AND enabled",
+        region,
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(ProvisionError::InvalidResidency("no such region"))?;
+
+    let pinned_elsewhere = query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM orgs
+            WHERE owner_id = get_user_id($1, $2)
+                AND id IS DISTINCT FROM $3
+                AND residency_region <> $4
+        ) AS "exists!"
+        "#,
+        auth.jwt().map(|c| c.sub),
+        auth.api_key(),
+        org_id,
+        region,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if pinned_elsewhere {
+        return Err(ProvisionError::InvalidResidency(
+            "another org of the owner pins its data to a different region",
+        ));
+    }
+
+    let target = Target {
+        region: Some(region.to_string()),
+        bucket: Some(bucket),
+        prefix: None,
+    };
+    if let Err(e) = state.blob_store.check_target(&target).await {
+        log::warn!("could not reach the bucket of region {}: {:?}", region, e);
+        return Err(ProvisionError::InvalidResidency(
+            "the region's bucket can't be reached",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Returns the external id of the service account in `path`.
+fn service_account_id(path: &ResourcePath) -> Result<&str, ProvisionError> {
+    path.service_account
+        .as_deref()
+        .ok_or(ProvisionError::NotFound)
+}
+
+#[async_trait]
+impl Persist for ResourcePut<OrgSpec> {
+    type Ret = Provisioned<Org>;
+    type Error = ProvisionError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(ProvisionError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        let current = query_as!(
+            Org,
+            r#"
+            SELECT id, external_id, name, residency_region, version, create_dt, update_dt
+            FROM orgs
+            WHERE owner_id = get_user_id($1, $2)
+                AND external_id = $3
+            FOR UPDATE
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.path.external_id,
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+
+        self.precondition
+            .check(current.as_ref().map(|o| o.version))?;
+
+        if let Some(region) = &self.spec.residency_region {
+            let unchanged = current
+                .as_ref()
+                .map_or(false, |o| o.residency_region.as_ref() == Some(region));
+            if !unchanged {
+                check_residency(&mut tx, auth, current.as_ref().map(|o| o.id), region, state)
+                    .await?;
+            }
+        }
+
+        let res = match current {
+            None => Provisioned {
+                resource: query_as!(
+                    Org,
+                    r#"
+                    INSERT INTO orgs (owner_id, external_id, name, residency_region)
+                    VALUES (get_user_id($1, $2), $3, $4, $5)
+                    RETURNING id, external_id, name, residency_region, version, create_dt,
+                        update_dt
+                    "#,
+                    auth.jwt().map(|c| c.sub),
+                    auth.api_key(),
+                    self.path.external_id,
+                    self.spec.name,
+                    self.spec.residency_region,
+                )
+                .fetch_one(&mut tx)
+                .await?,
+                created: true,
+            },
+            Some(org)
+                if org.name == self.spec.name
+                    && org.residency_region == self.spec.residency_region =>
+            {
+                Provisioned {
+                    resource: org,
+                    created: false,
+                }
+            }
+            Some(org) => Provisioned {
+                resource: query_as!(
+                    Org,
+                    r#"
+                    UPDATE orgs
+                    SET name = $2,
+                        residency_region = $3,
+                        version = version + 1,
+                        update_dt = current_timestamp
+                    WHERE id = $1
+                    RETURNING id, external_id, name, residency_region, version, create_dt,
+                        update_dt
+                    "#,
+                    org.id,
+                    self.spec.name,
+                    self.spec.residency_region,
+                )
+                .fetch_one(&mut tx)
+                .await?,
+                created: false,
+            },
+        };
+
+        tx.commit().await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for OrgGet {
+    type Resolve = Org;
+    type Error = ProvisionError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(ProvisionError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+        let org = org_for_share(&mut tx, auth, &self.path.external_id).await?;
+        tx.commit().await?;
+
+        Ok(org)
+    }
+}
+
+#[async_trait]
+impl Persist for OrgDelete {
+    type Ret = ();
+    type Error = ProvisionError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(ProvisionError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        let org = org_for_share(&mut tx, auth, &self.path.external_id).await?;
+        self.precondition.check(Some(org.version))?;
+
+        // Fails with a foreign key violation, and so a conflict, while the org still has projects
+        // or service accounts, or if it has ever placed a legal hold.
+        query!("DELETE FROM orgs WHERE id = $1", org.id)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Persist for ResourcePut<ProjectSpec> {
+    type Ret = Provisioned<OrgProject>;
+    type Error = ProvisionError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(ProvisionError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        let org = org_for_share(&mut tx, auth, &self.path.org).await?;
+
+        let current = query_as!(
+            OrgProject,
+            r#"
+            SELECT id, external_id AS "external_id!", name, index_results, archive_after_days,
+                version, create_dt
+            FROM projects
+            WHERE org_id = $1
+                AND external_id = $2
+            FOR UPDATE
+            "#,
+            org.id,
+            self.path.external_id,
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+
+        self.precondition
+            .check(current.as_ref().map(|p| p.version))?;
+
+        let res = match current {
+            None => Provisioned {
+                resource: query_as!(
+                    OrgProject,
+                    r#"
+                    INSERT INTO projects (user_id, org_id, external_id, name, index_results,
+                        archive_after_days)
+                    SELECT owner_id, id, $2, $3, $4, $5
+                    FROM orgs
+                    WHERE id = $1
+                    RETURNING id, external_id AS "external_id!", name, index_results,
+                        archive_after_days, version, create_dt
+                    "#,
+                    org.id,
+                    self.path.external_id,
+                    self.spec.name,
+                    self.spec.index_results,
+                    self.spec.archive_after_days,
+                )
+                .fetch_one(&mut tx)
+                .await?,
+                created: true,
+            },
+            Some(project)
+                if project.name == self.spec.name
+                    && project.index_results == self.spec.index_results
+                    && project.archive_after_days == self.spec.archive_after_days =>
+            {
+                Provisioned {
+                    resource: project,
+                    created: false,
+                }
+            }
+            Some(project) => Provisioned {
+                resource: query_as!(
+                    OrgProject,
+                    r#"
+                    UPDATE projects
+                    SET name = $2,
+                        index_results = $3,
+                        archive_after_days = $4,
+                        version = version + 1
+                    WHERE id = $1
+                    RETURNING id, external_id AS "external_id!", name, index_results,
+                        archive_after_days, version, create_dt
+                    "#,
+                    project.id,
+                    self.spec.name,
+                    self.spec.index_results,
+                    self.spec.archive_after_days,
+                )
+                .fetch_one(&mut tx)
+                .await?,
+                created: false,
+            },
+        };
+
+        // As for `ProjectUpsert`, keep the evals already stored in line with the setting.
+        query!(
+            r#"
+            UPDATE evals
+            SET result_indexed = $2
+            WHERE project_id = $1
+                AND result_indexed <> $2
+            "#,
+            res.resource.id,
+            res.resource.index_results,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for ProjectGet {
+    type Resolve = OrgProject;
+    type Error = ProvisionError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(ProvisionError::Unauthorized)?;
+        let path = self.path;
+
+        let project = query_as!(
+            OrgProject,
+            r#"
+            SELECT p.id, p.external_id AS "external_id!", p.name, p.index_results,
+                p.archive_after_days, p.version, p.create_dt
+            FROM projects p
+            JOIN orgs o
+                ON o.id = p.org_id
+            WHERE o.owner_id = get_user_id($1, $2)
+                AND o.external_id = $3
+                AND p.external_id = $4
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            path.org,
+            path.external_id,
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(project)
+    }
+}
+
+#[async_trait]
+impl Persist for ProjectDelete {
+    type Ret = ();
+    type Error = ProvisionError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(ProvisionError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        let org = org_for_share(&mut tx, auth, &self.path.org).await?;
+        let version = query_scalar!(
+            r#"
+            SELECT version
+            FROM projects
+            WHERE org_id = $1
+                AND external_id = $2
+            FOR UPDATE
+            "#,
+            org.id,
+            self.path.external_id,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        self.precondition.check(Some(version))?;
+
+        // Fails with a foreign key violation, and so a conflict, while evals or runs still
+        // belong to the project.
+        query!(
+            "DELETE FROM projects WHERE org_id = $1 AND external_id = $2",
+            org.id,
+            self.path.external_id,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Persist for ResourcePut<ServiceAccountSpec> {
+    type Ret = Provisioned<ServiceAccount>;
+    type Error = ProvisionError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(ProvisionError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        let org = org_for_share(&mut tx, auth, &self.path.org).await?;
+
+        let current = query_as!(
+            ServiceAccount,
+            r#"
+            SELECT id, external_id, name, version, create_dt, update_dt
+            FROM service_accounts
+            WHERE org_id = $1
+                AND external_id = $2
+            FOR UPDATE
+            "#,
+            org.id,
+            self.path.external_id,
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+
+        self.precondition
+            .check(current.as_ref().map(|s| s.version))?;
+
+        let res = match current {
+            None => Provisioned {
+                resource: query_as!(
+                    ServiceAccount,
+                    r#"
+                    INSERT INTO service_accounts (org_id, external_id, name)
+                    VALUES ($1, $2, $3)
+                    RETURNING id, external_id, name, version, create_dt, update_dt
+                    "#,
+                    org.id,
+                    self.path.external_id,
+                    self.spec.name,
+                )
+                .fetch_one(&mut tx)
+                .await?,
+                created: true,
+            },
+            Some(service_account) if service_account.name == self.spec.name => Provisioned {
+                resource: service_account,
+                created: false,
+            },
+            Some(service_account) => Provisioned {
+                resource: query_as!(
+                    ServiceAccount,
+                    r#"
+                    UPDATE service_accounts
+                    SET name = $2,
+                        version = version + 1,
+                        update_dt = current_timestamp
+                    WHERE id = $1
+                    RETURNING id, external_id, name, version, create_dt, update_dt
+                    "#,
+                    service_account.id,
+                    self.spec.name,
+                )
+                .fetch_one(&mut tx)
+                .await?,
+                created: false,
+            },
+        };
+
+        tx.commit().await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for ServiceAccountGet {
+    type Resolve = ServiceAccount;
+    type Error = ProvisionError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(ProvisionError::Unauthorized)?;
+        let path = self.path;
+
+        let mut tx = state.db_conn.begin().await?;
+        let org = org_for_share(&mut tx, auth, &path.org).await?;
+        let service_account = service_account_for_share(&mut tx, org.id, &path.external_id).await?;
+        tx.commit().await?;
+
+        Ok(service_account)
+    }
+}
+
+#[async_trait]
+impl Persist for ServiceAccountDelete {
+    type Ret = ();
+    type Error = ProvisionError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(ProvisionError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        let org = org_for_share(&mut tx, auth, &self.path.org).await?;
+        let service_account =
+            service_account_for_share(&mut tx, org.id, &self.path.external_id).await?;
+        self.precondition.check(Some(service_account.version))?;
+
+        // The service account's keys are deleted along with it.
+        query!(
+            "DELETE FROM service_accounts WHERE id = $1",
+            service_account.id
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Persist for ResourcePut<KeySpec> {
+    type Ret = Provisioned<ServiceAccountKey>;
+    type Error = ProvisionError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(ProvisionError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        let org = org_for_share(&mut tx, auth, &self.path.org).await?;
+        let service_account =
+            service_account_for_share(&mut tx, org.id, service_account_id(&self.path)?).await?;
+
+        let current = query_as!(
+            ServiceAccountKey,
+            r#"
+            SELECT external_id AS "external_id!", label, version, create_dt, NULL::text AS key
+            FROM api_keys
+            WHERE service_account_id = $1
+                AND external_id = $2
+            FOR UPDATE
+            "#,
+            service_account.id,
+            self.path.external_id,
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+
+        self.precondition
+            .check(current.as_ref().map(|k| k.version))?;
+
+        let res = match current {
+            None => {
+                let api_key = ApiKey::random();
+                let stored =
+                    StoredKey::new_blocking(api_key.key.clone(), state.gateway_key.clone())
+                        .await
+                        .ok_or(ProvisionError::Hash)?;
+                let mut key = query_as!(
+                    ServiceAccountKey,
+                    r#"
+                    INSERT INTO api_keys (user_id, label, key, key_hash, sealed_key,
+                        service_account_id, external_id)
+                    SELECT o.owner_id, $3, $4, $5, $6, s.id, $2
+                    FROM service_accounts s
+                    JOIN orgs o
+                        ON o.id = s.org_id
+                    WHERE s.id = $1
+                    RETURNING external_id AS "external_id!", label, version, create_dt,
+                        NULL::text AS key
+                    "#,
+                    service_account.id,
+                    self.path.external_id,
+                    self.spec.label,
+                    stored.id,
+                    stored.hash,
+                    stored.sealed,
+                )
+                .fetch_one(&mut tx)
+                .await?;
+                key.key = Some(api_key.key);
+
+                Provisioned {
+                    resource: key,
+                    created: true,
+                }
+            }
+            Some(key) if key.label == self.spec.label => Provisioned {
+                resource: key,
+                created: false,
+            },
+            Some(_) => Provisioned {
+                resource: query_as!(
+                    ServiceAccountKey,
+                    r#"
+                    UPDATE api_keys
+                    SET label = $3,
+                        version = version + 1
+                    WHERE service_account_id = $1
+                        AND external_id = $2
+                    RETURNING external_id AS "external_id!", label, version, create_dt,
+                        NULL::text AS key
+                    "#,
+                    service_account.id,
+                    self.path.external_id,
+                    self.spec.label,
+                )
+                .fetch_one(&mut tx)
+                .await?,
+                created: false,
+            },
+        };
+
+        tx.commit().await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for KeyGet {
+    type Resolve = ServiceAccountKey;
+    type Error = ProvisionError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(ProvisionError::Unauthorized)?;
+        let path = self.path;
+
+        let key = query_as!(
+            ServiceAccountKey,
+            r#"
+            SELECT k.external_id AS "external_id!", k.label, k.version, k.create_dt,
+                NULL::text AS key
+            FROM api_keys k
+            JOIN service_accounts s
+                ON s.id = k.service_account_id
+            JOIN orgs o
+                ON o.id = s.org_id
+            WHERE o.owner_id = get_user_id($1, $2)
+                AND o.external_id = $3
+                AND s.external_id = $4
+                AND k.external_id = $5
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            path.org,
+            service_account_id(&path)?,
+            path.external_id,
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(key)
+    }
+}
+
+#[async_trait]
+impl Persist for KeyDelete {
+    type Ret = ();
+    type Error = ProvisionError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(ProvisionError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        let org = org_for_share(&mut tx, auth, &self.path.org).await?;
+        let service_account =
+            service_account_for_share(&mut tx, org.id, service_account_id(&self.path)?).await?;
+        let version = query_scalar!(
+            r#"
+            SELECT version
+            FROM api_keys
+            WHERE service_account_id = $1
+                AND external_id = $2
+            FOR UPDATE
+            "#,
+            service_account.id,
+            self.path.external_id,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        self.precondition.check(Some(version))?;
+
+        query!(
+            "DELETE FROM api_keys WHERE service_account_id = $1 AND external_id = $2",
+            service_account.id,
+            self.path.external_id,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,360 @@
+use crate::middlewares::auth::Auth;
+use crate::models::run::{Run, RunError, RunState};
+use crate::persisters::{
+    s3store::{StoreError, Target},
+    Persist, Query,
+};
+use crate::policy::{self, Action, Request};
+use crate::state::State;
+
+use blake3::Hash;
+use sqlx::types::Uuid;
+
+/// Registers a new run, in the `queued` state.
+#[derive(Deserialize, Debug)]
+pub struct RunInsert {
+    pub project: Option<String>,
+    pub fn_key: String,
+    pub fn_hash: String,
+    pub args_hash: String,
+}
+
+/// Fetches a single run. Clients poll this to find out whether cancellation has been requested.
+pub struct RunGet {
+    pub id: Uuid,
+}
+
+/// Lists runs, optionally restricted to a project and/or state.
+#[derive(Deserialize, Debug)]
+pub struct RunsGet {
+    pub project: Option<String>,
+    pub state: Option<RunState>,
+}
+
+/// A request to move a run into a new state.
+#[derive(Deserialize, Debug)]
+pub struct StateChange {
+    pub state: RunState,
+    /// The eval recorded by the run. Only meaningful when the run has succeeded.
+    pub eval_id: Option<Uuid>,
+}
+
+pub struct RunStateUpdate {
+    pub id: Uuid,
+    pub change: StateChange,
+}
+
+/// Requests cancellation of a run which hasn't finished yet.
+pub struct RunCancel {
+    pub id: Uuid,
+}
+
+/// Requests that the artifacts of an archived run are brought back from cold storage.
+pub struct RunUnarchive {
+    pub id: Uuid,
+}
+
+struct RunInsertResult {
+    id: Uuid,
+}
+
+struct RunStateResult {
+    state: String,
+}
+
+struct ArchivedBlobResult {
+    content_hash: String,
+    storage_region: Option<String>,
+    storage_bucket: Option<String>,
+    storage_prefix: Option<String>,
+}
+
+#[async_trait]
+impl Persist for RunInsert {
+    type Ret = Uuid;
+    type Error = RunError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(RunError::Unauthorized)?;
+        policy::authorize(
+            auth,
+            Request::in_project(Action::RunWrite, self.project.as_deref()),
+            state,
+        )
+        .await?;
+        let api_key = auth.api_key().ok_or(RunError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        let project_id = match &self.project {
+            Some(name) => Some(
+                query!(
+                    r#"
+                    INSERT INTO projects (user_id, name)
+                    VALUES (user_from_key($1), $2)
+                    ON CONFLICT (user_id, name) DO UPDATE
+                        SET name = EXCLUDED.name
+                    RETURNING id
+                    "#,
+                    api_key,
+                    name,
+                )
+                .fetch_one(&mut tx)
+                .await?
+                .id,
+            ),
+            None => None,
+        };
+
+        let res = query_as!(
+            RunInsertResult,
+            r#"
+            INSERT INTO runs (user_id, project_id, fn_key, fn_hash, args_hash)
+            VALUES (user_from_key($1), $2, $3, $4, $5)
+            RETURNING id
+            "#,
+            api_key,
+            project_id,
+            self.fn_key,
+            self.fn_hash,
+            self.args_hash,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(res.id)
+    }
+}
+
+#[async_trait]
+impl Query for RunGet {
+    type Resolve = Run;
+    type Error = RunError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(RunError::Unauthorized)?;
+
+        let res = query_as!(
+            Run,
+            r#"
+            SELECT r.id, p.name AS "project?", r.fn_key, r.fn_hash, r.args_hash, r.state,
+                r.cancel_requested, r.eval_id, r.create_dt, r.update_dt, r.archived_dt,
+                r.unarchive_requested_dt
+            FROM runs r
+            LEFT JOIN projects p
+                ON p.id = r.project_id
+            WHERE r.id = $1
+                AND r.user_id = get_user_id($2, $3)
+            "#,
+            self.id,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for RunsGet {
+    type Resolve = Vec<Run>;
+    type Error = RunError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(RunError::Unauthorized)?;
+        policy::authorize(
+            auth,
+            Request::in_project(Action::RunRead, self.project.as_deref()),
+            state,
+        )
+        .await?;
+
+        let res = query_as!(
+            Run,
+            r#"
+            SELECT r.id, p.name AS "project?", r.fn_key, r.fn_hash, r.args_hash, r.state,
+                r.cancel_requested, r.eval_id, r.create_dt, r.update_dt, r.archived_dt,
+                r.unarchive_requested_dt
+            FROM runs r
+            LEFT JOIN projects p
+                ON p.id = r.project_id
+            WHERE   (p.name = $1 OR $1 IS NULL)
+                AND (r.state = $2 OR $2 IS NULL)
+                AND r.user_id = get_user_id($3, $4)
+            ORDER BY r.create_dt DESC
+            "#,
+            self.project,
+            self.state.map(|s| s.as_str()),
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Persist for RunStateUpdate {
+    type Ret = ();
+    type Error = RunError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(RunError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        // Lock the run, so that concurrent state changes are applied one after the other and each
+        // is checked against the state left by the previous one.
+        let current = query_as!(
+            RunStateResult,
+            r#"
+            SELECT state
+            FROM runs
+            WHERE id = $1
+                AND user_id = get_user_id($2, $3)
+            FOR UPDATE
+            "#,
+            self.id,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        let next = self.change.state;
+        let allowed = current
+            .state
+            .parse::<RunState>()
+            .map_or(false, |from| from.can_transition_to(next));
+        if !allowed {
+            return Err(RunError::InvalidTransition {
+                from: current.state,
+                to: next,
+            });
+        }
+
+        // Only the caller's own evals can be linked to their runs.
+        let updated = query!(
+            r#"
+            UPDATE runs
+            SET state = $2,
+                eval_id = COALESCE($3, eval_id),
+                update_dt = current_timestamp
+            WHERE id = $1
+                AND ($3::uuid IS NULL OR EXISTS (
+                    SELECT 1 FROM evals e
+                    WHERE e.id = $3
+                        AND e.user_id = get_user_id($4, $5)
+                ))
+            "#,
+            self.id,
+            next.as_str(),
+            self.change.eval_id,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .execute(&mut tx)
+        .await?;
+        if updated.rows_affected() == 0 {
+            return Err(RunError::EvalNotFound);
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Persist for RunCancel {
+    type Ret = ();
+    type Error = RunError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(RunError::Unauthorized)?;
+
+        // Cancelling a run which has already finished is a no-op.
+        query!(
+            r#"
+            UPDATE runs
+            SET cancel_requested = state IN ('queued', 'running'),
+                update_dt = current_timestamp
+            WHERE id = $1
+                AND user_id = get_user_id($2, $3)
+            RETURNING id
+            "#,
+            self.id,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Persist for RunUnarchive {
+    type Ret = ();
+    type Error = RunError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(RunError::Unauthorized)?;
+
+        let mut tx = state.db_conn.begin().await?;
+
+        let blob = query_as!(
+            ArchivedBlobResult,
+            r#"
+            SELECT b.content_hash, b.storage_region, b.storage_bucket, b.storage_prefix
+            FROM runs r
+            JOIN evals e
+                ON e.id = r.eval_id
+            JOIN blobs b
+                ON b.id = e.blob_id
+            WHERE r.id = $1
+                AND r.user_id = get_user_id($2, $3)
+                AND r.archived_dt IS NOT NULL
+            FOR UPDATE OF r
+            "#,
+            self.id,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(RunError::NotArchived)?;
+
+        // The restored copy only needs to live long enough for the archive job to move the object
+        // back to standard storage.
+        let hash = Hash::from_hex(&blob.content_hash).map_err(StoreError::from)?;
+        let target = Target {
+            region: blob.storage_region,
+            bucket: blob.storage_bucket,
+            prefix: blob.storage_prefix,
+        };
+        state.blob_store.request_restore(&target, hash, 1).await?;
+
+        query!(
+            r#"
+            UPDATE runs
+            SET unarchive_requested_dt = COALESCE(unarchive_requested_dt, current_timestamp)
+            WHERE id = $1
+            "#,
+            self.id,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,160 @@
+use crate::middlewares::auth::Auth;
+use crate::models::s3gateway::{S3GatewayError, ACCESS_KEY_ID_LEN, MAX_CLOCK_SKEW_MINS};
+use crate::persisters::s3store::Target;
+use crate::persisters::Query;
+use crate::sigv4::{self, Authorization, CanonicalRequest};
+use crate::state::State;
+
+use aws_sdk_s3::types::ByteStream;
+use blake3::Hash;
+use sqlx::types::chrono::{NaiveDateTime, Utc};
+
+/// An owned copy of the parts of a SigV4 signed request needed to verify it.
+pub struct SignedRequest {
+    pub authorization: Authorization,
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    pub header_values: Vec<String>,
+    pub payload_hash: String,
+    pub amz_date: String,
+}
+
+/// A GetObject or HeadObject request. Object keys are BLOB content hashes; the bucket name is
+/// ignored, since each user sees only their own BLOBs.
+pub struct S3ObjectGet {
+    pub request: SignedRequest,
+    pub key: String,
+    /// Whether this is a HeadObject request, in which case no body is fetched.
+    pub head: bool,
+}
+
+pub struct S3Object {
+    pub content_length: i64,
+    pub body: Option<ByteStream>,
+}
+
+struct ApiKeyResult {
+    key: String,
+    key_hash: Option<String>,
+    sealed_key: Option<Vec<u8>>,
+}
+
+struct StorageClassResult {
+    storage_class: String,
+    storage_region: Option<String>,
+    storage_bucket: Option<String>,
+    storage_prefix: Option<String>,
+}
+
+impl SignedRequest {
+    /// Resolves the request's access key ID to the API key which signed it, and checks the
+    /// signature.
+    async fn authenticate(&self, state: &State) -> Result<Auth, S3GatewayError> {
+        let access_key_id = &self.authorization.access_key_id;
+        if access_key_id.len() != ACCESS_KEY_ID_LEN
+            || !access_key_id.chars().all(|c| c.is_ascii_alphanumeric())
+        {
+            return Err(S3GatewayError::AccessDenied);
+        }
+
+        let signed_at = NaiveDateTime::parse_from_str(&self.amz_date, "%Y%m%dT%H%M%SZ")
+            .map_err(|_| S3GatewayError::SignatureDoesNotMatch)?;
+        let skew = Utc::now().naive_utc() - signed_at;
+        if skew.num_minutes().abs() > MAX_CLOCK_SKEW_MINS {
+            return Err(S3GatewayError::SignatureDoesNotMatch);
+        }
+
+        // The access key ID is the id the key is stored under, or, for keys which haven't been
+        // hashed yet, the start of the key.
+        let api_key = query_as!(
+            ApiKeyResult,
+            r#"
+            SELECT key, key_hash, sealed_key
+            FROM api_keys
+            WHERE key = $2 OR (left(key, $1) = $2 AND key_hash IS NULL)
+            "#,
+            ACCESS_KEY_ID_LEN as i32,
+            access_key_id,
+        )
+        .fetch_optional(&state.db_conn)
+        .await?
+        .ok_or(S3GatewayError::AccessDenied)?;
+        // Signatures are checked with the whole key, which is only kept sealed with the gateway
+        // key. See `crate::keys`.
+        let secret = match (&api_key.key_hash, &api_key.sealed_key, &state.gateway_key) {
+            (None, _, _) => api_key.key.clone(),
+            (Some(_), Some(sealed), Some(gateway_key)) => gateway_key
+                .open(sealed)
+                .and_then(|k| String::from_utf8(k).ok())
+                .ok_or(S3GatewayError::AccessDenied)?,
+            _ => return Err(S3GatewayError::AccessDenied),
+        };
+
+        let request = CanonicalRequest {
+            method: &self.method,
+            path: &self.path,
+            query: &self.query,
+            header_values: self.header_values.iter().map(String::as_str).collect(),
+            payload_hash: &self.payload_hash,
+        };
+        if !sigv4::verify(&self.authorization, &request, &self.amz_date, &secret) {
+            return Err(S3GatewayError::SignatureDoesNotMatch);
+        }
+
+        Ok(Auth::ApiKey(api_key.key))
+    }
+}
+
+#[async_trait]
+impl Query for S3ObjectGet {
+    type Resolve = S3Object;
+    type Error = S3GatewayError;
+
+    async fn fetch(
+        self,
+        _auth: Option<&Auth>,
+        state: &State,
+    ) -> Result<Self::Resolve, Self::Error> {
+        let auth = self.request.authenticate(state).await?;
+
+        let hash = Hash::from_hex(&self.key).map_err(|_| S3GatewayError::NoSuchKey)?;
+
+        let blob = query_as!(
+            StorageClassResult,
+            r#"
+            SELECT storage_class, storage_region, storage_bucket, storage_prefix
+            FROM blobs
+            WHERE content_hash = $1
+                AND user_id = get_user_id($2, $3)
+            "#,
+            self.key,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_optional(&state.db_conn)
+        .await?
+        .ok_or(S3GatewayError::NoSuchKey)?;
+
+        if blob.storage_class != "STANDARD" {
+            return Err(S3GatewayError::InvalidObjectState);
+        }
+
+        let target = Target {
+            region: blob.storage_region,
+            bucket: blob.storage_bucket,
+            prefix: blob.storage_prefix,
+        };
+        let content_length = state.blob_store.blob_length(&target, hash).await?;
+        let body = if self.head {
+            None
+        } else {
+            Some(state.blob_store.retrieve_blob(&target, hash).await?)
+        };
+
+        Ok(S3Object {
+            content_length,
+            body,
+        })
+    }
+}
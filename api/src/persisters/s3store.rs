@@ -1,37 +1,404 @@
-use crate::extractors::with_blob::{BlobPayload, WithBlob, WithBlobError};
+use crate::chaos::{Chaos, Layer};
+use crate::error_code::{coded, ErrorCode};
+use crate::extractors::with_blob::{WithBlob, WithBlobError};
 use crate::middlewares::auth::Auth;
+use crate::models::anomaly::Activity;
+use crate::models::dead_letter::DeadLetterOp;
 use crate::models::eval::EvalError;
+use crate::persisters::anomaly::record_activity;
+use crate::persisters::bandwidth::bucket_for;
+use crate::persisters::blob_store::{BlobStore, Payload, PresignedPut};
+use crate::persisters::dead_letter::{self, DeadLetterInsert};
+use crate::persisters::usage::within_storage_quota;
+use crate::persisters::user::user_id;
 use crate::persisters::Persist;
+use crate::retry::{retry, Backoff};
 use crate::state::State;
+use crate::throttle::{throttled, Direction};
 use crate::CONFIG;
 
+use actix_web::{http::Uri, web};
 use aws_config::profile::{
     profile_file, ProfileFileCredentialsProvider, ProfileFileRegionProvider,
 };
 use aws_sdk_s3::{
     error::PutObjectError,
-    output::PutObjectOutput,
+    model::{
+        CompletedMultipartUpload, CompletedPart, MetadataDirective, RestoreRequest,
+        ServerSideEncryption, StorageClass,
+    },
+    presigning::config::PresigningConfig,
     types::{ByteStream, SdkError},
-    Client,
+    Client, Endpoint, Region,
 };
+use aws_types::SdkConfig;
 use blake3::{Hash, Hasher};
-use futures::stream::StreamExt;
+use futures::stream::{Stream, StreamExt};
+use sqlx::types::Uuid;
 
+use std::collections::HashMap;
 use std::marker::{Send, Sync};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-/// This gets stored in application state and when we want to store something, we call `store`.
+/// BLOB storage in S3, or an S3-compatible service. This gets stored in application state, as its
+/// [`BlobStore`], unless BLOBs are stored in a directory instead.
 #[derive(Clone)]
 pub struct S3Store {
     client: Client,
+    /// The loaded AWS configuration, from which clients for other regions are built.
+    sdk_config: SdkConfig,
+    /// Clients for the regions of buckets BLOBs are pinned to, built as they're first needed.
+    regional: Arc<Mutex<HashMap<String, Client>>>,
+    chaos: Chaos,
+}
+
+/// Where a BLOB is stored. BLOBs are stored in the server's bucket, unless the org owning them
+/// pins its data to a region, in which case they're stored in that region's bucket. The target of
+/// each stored BLOB is recorded on its `blobs` row.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Target {
+    /// The AWS region the bucket is in, or `None` for the server's own region.
+    pub region: Option<String>,
+    /// The bucket, or `None` for the server's own bucket.
+    pub bucket: Option<String>,
+    /// The prefix of the BLOB's key, or `None` for none. This is the server's `S3_KEY_PREFIX` at
+    /// the time the BLOB was stored, so that a BLOB can still be found after the prefix changes.
+    pub prefix: Option<String>,
+}
+
+impl Target {
+    /// The server's own bucket, under the server's key prefix.
+    pub fn server() -> Self {
+        Self {
+            region: None,
+            bucket: None,
+            prefix: key_prefix(),
+        }
+    }
+
+    pub fn bucket(&self) -> &str {
+        self.bucket.as_deref().unwrap_or(&CONFIG.aws_s3_blob_bucket)
+    }
+
+    /// The S3 key the BLOB with the content hash is stored under.
+    pub fn key(&self, content_hash: Hash) -> String {
+        blob_key(self.prefix.as_deref(), content_hash)
+    }
+
+    /// The S3 key an upload straight to storage is staged under, until it's checked. See
+    /// [`crate::persisters::presigned_upload`].
+    pub fn staged_key(&self, upload_id: Uuid) -> String {
+        format!(
+            "{}staged/{}",
+            self.prefix.as_deref().unwrap_or_default(),
+            upload_id
+        )
+    }
+}
+
+fn blob_key(prefix: Option<&str>, content_hash: Hash) -> String {
+    format!("{}{}", prefix.unwrap_or_default(), content_hash.to_hex())
+}
+
+/// The prefix new BLOBs' keys are given, if one is configured.
+fn key_prefix() -> Option<String> {
+    Some(CONFIG.s3_key_prefix.clone()).filter(|p| !p.is_empty())
+}
+
+/// The compression of BLOBs at rest, as set on their objects' `Content-Encoding`.
+pub const ZSTD: &str = "zstd";
+
+/// BLOBs longer than this are stored as they are, since they'd have to be held in memory to be
+/// compressed.
+const MAX_COMPRESSED_LEN: i64 = 64 * 1024 * 1024;
+
+/// BLOBs up to this long are received in full before they're stored, so that their PUTs can be
+/// retried. Longer ones are streamed to S3 as they're received.
+const MAX_RETRIED_LEN: i64 = 16 * 1024 * 1024;
+
+/// The metadata of a compressed object which says how long the BLOB is.
+const RAW_LENGTH_KEY: &str = "raw-length";
+
+/// The server-side encryption objects are written with, if it's configured rather than left to
+/// the bucket. It's given to copies as well as PUTs, since S3 encrypts a copy as the bucket says
+/// otherwise, whatever the object it's a copy of.
+fn encryption() -> Option<ServerSideEncryption> {
+    CONFIG.s3_sse.as_deref().map(ServerSideEncryption::from)
+}
+
+/// How a BLOB has just been stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stored {
+    /// [`ZSTD`], if the object is compressed.
+    pub compression: Option<&'static str>,
+    /// The object's length, which is less than the BLOB's if it's compressed.
+    pub length: i64,
+}
+
+impl Stored {
+    /// A BLOB stored as it is.
+    pub fn raw(length: i64) -> Self {
+        Self {
+            compression: None,
+            length,
+        }
+    }
+}
+
+/// Compresses a BLOB, if it gets any smaller.
+async fn compress(bytes: bytes::Bytes, level: i32) -> Result<Option<bytes::Bytes>, StoreError> {
+    let len = bytes.len();
+    let compressed = web::block(move || zstd::bulk::compress(&bytes, level))
+        .await
+        .map_err(|_| StoreError::S3Other("compression was cancelled".into()))?
+        .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+    Ok((compressed.len() < len).then(|| compressed.into()))
+}
+
+async fn decompress(bytes: bytes::Bytes) -> Result<bytes::Bytes, StoreError> {
+    let raw = web::block(move || zstd::stream::decode_all(&bytes[..]))
+        .await
+        .map_err(|_| StoreError::S3Other("decompression was cancelled".into()))?
+        .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+    Ok(raw.into())
+}
+
+/// The bytes `first..=last` of a BLOB, or as many of them as there are.
+fn byte_range(bytes: bytes::Bytes, first: u64, last: u64) -> bytes::Bytes {
+    let end = (last as usize).saturating_add(1).min(bytes.len());
+    let start = (first as usize).min(end);
+    bytes.slice(start..end)
+}
+
+/// Where new BLOBs of the authenticated user are stored: the bucket of the region an org they own
+/// pins its data to, if there is one. Either way, they're stored under the server's key prefix.
+pub async fn upload_target(auth: &Auth, state: &State) -> Result<Target, sqlx::Error> {
+    pinned_target(auth.jwt().map(|c| c.sub), auth.api_key(), state).await
+}
+
+/// Where new BLOBs the server produces for a user, e.g. exports, are stored; as for
+/// [`upload_target`].
+pub async fn user_upload_target(user_id: Uuid, state: &State) -> Result<Target, sqlx::Error> {
+    pinned_target(Some(user_id), None, state).await
+}
+
+async fn pinned_target(
+    user_id: Option<Uuid>,
+    api_key: Option<&str>,
+    state: &State,
+) -> Result<Target, sqlx::Error> {
+    let pinned = query!(
+        r#"
+        SELECT r.name AS region, r.bucket
+        FROM orgs o
+        JOIN deployment_regions r
+            ON r.name = o.residency_region
+        WHERE o.owner_id = get_user_id($1, $2)
+        ORDER BY o.create_dt
+        LIMIT 1
+        "#,
+        user_id,
+        api_key,
+    )
+    .fetch_optional(&state.db_conn)
+    .await?;
+
+    Ok(match pinned {
+        Some(pinned) => Target {
+            region: Some(pinned.region),
+            bucket: Some(pinned.bucket),
+            prefix: key_prefix(),
+        },
+        None => Target::server(),
+    })
+}
+
+/// Where the BLOB is already stored in the target's bucket, whoever stored it, if it is. Storage is
+/// deduplicated by content: a BLOB which is already stored is shared rather than stored again,
+/// under whatever prefix it was first stored. The object is marked as used, so that the garbage
+/// collector leaves it while its new owner is recorded.
+///
+/// Objects of archived BLOBs aren't shared, since they can't be read until they're restored.
+pub async fn shared_object(
+    target: &Target,
+    content_hash: &str,
+    state: &State,
+) -> Result<Option<Target>, sqlx::Error> {
+    let prefix = query_scalar!(
+        r#"
+        UPDATE blob_objects o
+        SET used_dt = now()
+        WHERE o.content_hash = $1
+            AND coalesce(o.storage_region, '') = coalesce($2, '')
+            AND coalesce(o.storage_bucket, '') = coalesce($3, '')
+            AND NOT EXISTS (
+                SELECT 1 FROM blobs b
+                WHERE b.object_id = o.id
+                    AND b.storage_class <> 'STANDARD'
+            )
+        RETURNING o.storage_prefix
+        "#,
+        content_hash,
+        target.region,
+        target.bucket,
+    )
+    .fetch_optional(&state.db_conn)
+    .await?;
+
+    Ok(prefix.map(|prefix| Target {
+        region: target.region.clone(),
+        bucket: target.bucket.clone(),
+        prefix,
+    }))
+}
+
+/// Stores a BLOB which has already been received in full in the target's bucket, unless it's
+/// already stored there, returning where it's stored. The caller is responsible for computing
+/// `content_hash` from `bytes`.
+pub async fn store_shared(
+    state: &State,
+    target: Target,
+    content_hash: Hash,
+    bytes: bytes::Bytes,
+) -> Result<(Target, Option<Stored>), StoreError> {
+    if let Some(shared) = shared_object(&target, content_hash.to_hex().as_str(), state).await? {
+        return Ok((shared, None));
+    }
+    let stored = state
+        .blob_store
+        .store_bytes(&target, content_hash, bytes)
+        .await?;
+
+    Ok((target, Some(stored)))
+}
+
+/// Receives a BLOB in full without storing it, checking that it's the BLOB claimed. A BLOB which
+/// is already stored is still received, so that nobody can come to own a BLOB just by knowing its
+/// hash.
+pub async fn receive_blob<S>(
+    payload: S,
+    hash_claim: Hash,
+    content_length: i64,
+) -> Result<(), StoreError>
+where
+    S: Stream<Item = Result<bytes::Bytes, WithBlobError>>,
+{
+    receive(payload, hash_claim, content_length, false).await?;
+    Ok(())
+}
+
+/// What the stream of a BLOB being uploaded saw of it, which the upload's errors don't tell.
+#[derive(Default)]
+struct UploadCheck {
+    /// The number of bytes received so far.
+    received: AtomicI64,
+    /// Whether the bytes turned out not to be the BLOB they were claimed to be.
+    mismatch: AtomicBool,
+    /// Whether the upload stalled.
+    stalled: AtomicBool,
+}
+
+/// Receives a BLOB in full, as [`receive_blob`] does, returning its bytes if `keep`.
+async fn receive<S>(
+    payload: S,
+    hash_claim: Hash,
+    content_length: i64,
+    keep: bool,
+) -> Result<bytes::Bytes, StoreError>
+where
+    S: Stream<Item = Result<bytes::Bytes, WithBlobError>>,
+{
+    let mut payload = Box::pin(payload);
+    let mut hasher = Hasher::new();
+    let mut kept = bytes::BytesMut::new();
+    let mut len = 0;
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(StoreError::WithBlob)?;
+        hasher.update(&chunk);
+        len += chunk.len() as i64;
+        if len > content_length {
+            return Err(StoreError::InvalidHash);
+        }
+        if keep {
+            kept.extend_from_slice(&chunk);
+        }
+    }
+
+    if len != content_length || hasher.finalize() != hash_claim {
+        return Err(StoreError::InvalidHash);
+    }
+
+    Ok(kept.freeze())
+}
+
+/// Records where the user's BLOB was stored, once it has been, and that it's available. The
+/// object it's stored as is recorded too, for later uploads of the same BLOB to share, along with
+/// how it was stored if it has just been (rather than shared).
+async fn record_target(
+    auth: &Auth,
+    content_hash: &str,
+    target: &Target,
+    stored: Option<Stored>,
+    state: &State,
+) -> Result<(), sqlx::Error> {
+    query!(
+        r#"
+        WITH object AS (
+            INSERT INTO blob_objects (content_hash, storage_region, storage_bucket, storage_prefix,
+                compression, stored_length)
+            VALUES ($3, $4, $5, $6, $7, $8)
+            ON CONFLICT (content_hash, coalesce(storage_region, ''), coalesce(storage_bucket, ''))
+                DO UPDATE SET used_dt = now(),
+                    compression = CASE WHEN EXCLUDED.stored_length IS NULL
+                        THEN blob_objects.compression ELSE EXCLUDED.compression END,
+                    stored_length = coalesce(EXCLUDED.stored_length, blob_objects.stored_length)
+            RETURNING id, compression, stored_length
+        )
+        UPDATE blobs
+        SET storage_region = $4, storage_bucket = $5, storage_prefix = $6,
+            object_id = object.id, compression = object.compression,
+            stored_length = object.stored_length, object_status = 'available', checked_dt = now()
+        FROM object
+        WHERE user_id = get_user_id($1, $2)
+            AND content_hash = $3
+        "#,
+        auth.jwt().map(|c| c.sub),
+        auth.api_key(),
+        content_hash,
+        target.region,
+        target.bucket,
+        target.prefix,
+        stored.and_then(|s| s.compression),
+        stored.map(|s| s.length),
+    )
+    .execute(&state.db_conn)
+    .await?;
+
+    Ok(())
 }
 
 #[derive(Debug)]
 pub enum StoreError {
     InvalidHash,
+    InvalidQuery,
     MissingPayload,
     Unauthorized,
+    Forbidden,
     NotFound,
+    /// Storing the BLOB would take the user over the storage quota.
+    QuotaExceeded,
     S3(SdkError<PutObjectError>),
+    /// Errors from S3 operations other than storing a BLOB.
+    S3Other(Box<dyn std::error::Error + Send + Sync>),
+    /// Errors from S3 which may not happen again, e.g. when S3 throttles requests or drops a
+    /// connection. Operations which fail with them are retried.
+    S3Transient(Box<dyn std::error::Error + Send + Sync>),
+    /// Errors from BLOB storage in a directory, rather than S3.
+    Io(std::io::Error),
     WithBlob(WithBlobError),
     Sqlx(sqlx::error::Error),
 }
@@ -42,6 +409,18 @@ impl From<EvalError> for StoreError {
             EvalError::NotFound(e) => StoreError::Sqlx(e),
             EvalError::Sqlx(e) => StoreError::Sqlx(e),
             EvalError::Unauthorized => StoreError::Unauthorized,
+            EvalError::Forbidden | EvalError::UnresolvedPrincipal => StoreError::Forbidden,
+            EvalError::Miss { .. } => StoreError::NotFound,
+            EvalError::InvalidQuery
+            | EvalError::InvalidReport
+            | EvalError::InvalidImport
+            | EvalError::InvalidMulti
+            | EvalError::Rejected(_) => StoreError::InvalidQuery,
+            EvalError::ImportClosed
+            | EvalError::ValidationFailed
+            | EvalError::SimilarityDisabled
+            | EvalError::Embedding(_)
+            | EvalError::Held => StoreError::S3Other(format!("{:?}", e).into()),
         }
     }
 }
@@ -50,10 +429,16 @@ impl std::fmt::Display for StoreError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             StoreError::InvalidHash => writeln!(f, "Invalid hash"),
+            StoreError::InvalidQuery => writeln!(f, "Invalid query"),
             StoreError::MissingPayload => writeln!(f, "Missing payload"),
             StoreError::Unauthorized => writeln!(f, "Unauthorized"),
+            StoreError::Forbidden => writeln!(f, "Forbidden"),
             StoreError::NotFound => writeln!(f, "Not found"),
+            StoreError::QuotaExceeded => writeln!(f, "Storage quota exceeded"),
             StoreError::S3(_) => writeln!(f, "Error storing BLOB"),
+            StoreError::S3Other(_) => writeln!(f, "Error accessing BLOB storage"),
+            StoreError::S3Transient(_) => writeln!(f, "BLOB storage is unavailable"),
+            StoreError::Io(_) => writeln!(f, "Error accessing BLOB storage"),
             StoreError::WithBlob(_) => writeln!(f, "Error decoding BLOB transfer protocol"),
             StoreError::Sqlx(_) => writeln!(f, "Error storing BLOB metadata"),
         }
@@ -62,31 +447,95 @@ impl std::fmt::Display for StoreError {
 
 impl std::error::Error for StoreError {}
 
+impl StoreError {
+    /// Whether the operation which failed with the error may succeed if it's tried again.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            StoreError::S3Transient(_) => true,
+            StoreError::S3(e) => is_transient(e),
+            _ => false,
+        }
+    }
+}
+
+/// Whether an S3 operation failed in a way which may not happen again: it timed out, the
+/// connection failed, or S3 answered that it's throttling requests or has failed itself.
+fn is_transient<E>(e: &SdkError<E>) -> bool {
+    match e {
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) => true,
+        SdkError::ResponseError { .. } => true,
+        SdkError::ServiceError { raw, .. } => {
+            let status = raw.http().status();
+            status.is_server_error() || status.as_u16() == 429
+        }
+        _ => false,
+    }
+}
+
+/// The error an S3 operation other than storing a BLOB failed with.
+fn s3_error<E>(e: SdkError<E>) -> StoreError
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    if is_transient(&e) {
+        StoreError::S3Transient(Box::new(e))
+    } else {
+        StoreError::S3Other(Box::new(e))
+    }
+}
+
 impl From<sqlx::error::Error> for StoreError {
     fn from(e: sqlx::error::Error) -> Self {
         Self::Sqlx(e)
     }
 }
 
+impl From<std::io::Error> for StoreError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
 impl From<StoreError> for actix_web::Error {
     fn from(e: StoreError) -> Self {
         use actix_web::error;
         match e {
             StoreError::S3(e) => {
                 log::error!("error storing data in S3: {:?}", e);
-                error::ErrorInternalServerError("could not store data in S3")
+                coded(ErrorCode::BlobStoreError, "could not store data in S3")
+            }
+            StoreError::S3Other(e) => {
+                log::error!("error accessing S3: {:?}", e);
+                coded(ErrorCode::BlobStoreError, "could not access data in S3")
+            }
+            StoreError::S3Transient(e) => {
+                log::error!("error accessing S3, after retries: {:?}", e);
+                coded(
+                    ErrorCode::BlobStoreUnavailable,
+                    "S3 is unavailable, try again later",
+                )
+            }
+            StoreError::Io(e) => {
+                log::error!("error accessing BLOB storage: {:?}", e);
+                coded(ErrorCode::BlobStoreError, "could not access data")
             }
             StoreError::Sqlx(e) => {
                 log::error!("error storing byte metadata in Postgres: {:?}", e);
-                error::ErrorInternalServerError("could not store data")
+                coded(ErrorCode::BlobStoreError, "could not store data")
             }
-            StoreError::InvalidHash => error::ErrorBadRequest("invalid hash"),
-            StoreError::MissingPayload => error::ErrorBadRequest("missing payload"),
+            StoreError::InvalidHash => coded(ErrorCode::InvalidHash, "invalid hash"),
+            StoreError::InvalidQuery => coded(ErrorCode::InvalidBlobQuery, "invalid query"),
+            StoreError::MissingPayload => coded(ErrorCode::MissingPayload, "missing payload"),
             StoreError::Unauthorized => error::ErrorUnauthorized("unauthorized"),
+            StoreError::Forbidden => error::ErrorForbidden("forbidden by policy"),
             StoreError::NotFound => error::ErrorNotFound("resource not found"),
+            StoreError::QuotaExceeded => {
+                coded(ErrorCode::QuotaExceeded, "blob would exceed storage quota")
+            }
+            StoreError::WithBlob(WithBlobError::Stalled) => WithBlobError::Stalled.into(),
             StoreError::WithBlob(e) => {
                 log::error!("error extracting BLOB from request: {:?}", e);
-                error::ErrorBadRequest("invalid encoding")
+                coded(ErrorCode::InvalidBlobEncoding, "invalid encoding")
             }
         }
     }
@@ -100,9 +549,9 @@ impl From<blake3::HexError> for StoreError {
 
 #[async_trait]
 /// A trait implemented on types which allow storage of BLOBs in S3.
-// TODO: We want to eventually implement different storage strategies based on the size of the
-// bytes payload. Small payloads can be a single PUT with retries, large payloads can be
-// split up with the multi part upload API, and probably with no retries.
+// TODO: Small payloads are a single PUT with retries, and large payloads a single streamed PUT
+// without. Large payloads could be split up with the multi part upload API, so that their parts
+// can be retried.
 pub trait BlobMetadata {
     /// The content hash to be used for addressing the underlying BLOB storage.
     fn content_hash(&self) -> &str;
@@ -116,7 +565,7 @@ pub trait BlobMetadata {
 }
 
 impl S3Store {
-    pub async fn new() -> S3Store {
+    pub async fn new(chaos: Chaos) -> S3Store {
         let profile_files = profile_file::Builder::new()
             .with_file(
                 profile_file::ProfileFileKind::Credentials,
@@ -132,75 +581,724 @@ impl S3Store {
             .profile_files(profile_files)
             .build();
 
-        let config = aws_config::from_env()
-            .credentials_provider(credentials_provider)
-            .region(region_provider)
-            .load()
-            .await;
+        let loader = aws_config::from_env().credentials_provider(credentials_provider);
+        let loader = match &CONFIG.aws_s3_region {
+            Some(region) => loader.region(Region::new(region.clone())),
+            None => loader.region(region_provider),
+        };
+        // Clients for other regions are built from this config, so they use the endpoint too.
+        let loader = match &CONFIG.aws_s3_endpoint_url {
+            Some(url) => {
+                let uri = url.parse::<Uri>().expect("invalid AWS_S3_ENDPOINT_URL");
+                loader.endpoint_resolver(Endpoint::immutable(uri))
+            }
+            None => loader,
+        };
+        let config = loader.load().await;
 
         let client = Client::new(&config);
 
-        Self { client }
+        Self {
+            client,
+            sdk_config: config,
+            regional: Default::default(),
+            chaos,
+        }
+    }
+
+    /// The client for the region of the target's bucket.
+    fn client(&self, target: &Target) -> Client {
+        let region = match &target.region {
+            Some(region) => region,
+            None => return self.client.clone(),
+        };
+
+        let mut regional = self.regional.lock().unwrap();
+        regional
+            .entry(region.clone())
+            .or_insert_with(|| {
+                let config = aws_sdk_s3::config::Builder::from(&self.sdk_config)
+                    .region(Region::new(region.clone()))
+                    .build();
+                Client::from_conf(config)
+            })
+            .clone()
+    }
+
+    /// Injects any faults set on BLOB storage into an S3 operation. They stand in for S3 failing
+    /// transiently, so the operation is retried.
+    async fn inject_faults(&self) -> Result<(), StoreError> {
+        self.chaos
+            .inject(Layer::Blob)
+            .await
+            .map_err(|e| StoreError::S3Transient(Box::new(e)))
+    }
+
+    /// Returns the length, in bytes, of the stored BLOB, and whether its object is compressed.
+    async fn head_blob(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<(i64, bool), StoreError> {
+        self.inject_faults().await?;
+        let head = self
+            .client(target)
+            .head_object()
+            .bucket(target.bucket())
+            .key(target.key(content_hash))
+            .send()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+        if head.content_encoding() != Some(ZSTD) {
+            return Ok((head.content_length(), false));
+        }
+        let length = head
+            .metadata()
+            .and_then(|m| m.get(RAW_LENGTH_KEY))
+            .and_then(|l| l.parse().ok())
+            .ok_or_else(|| StoreError::S3Other("compressed BLOB has no length".into()))?;
+
+        Ok((length, true))
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3Store {
+    /// Checks that the target's bucket exists and can be reached, before BLOBs are pinned to it.
+    async fn check_target(&self, target: &Target) -> Result<(), StoreError> {
+        self.client(target)
+            .head_bucket()
+            .bucket(target.bucket())
+            .send()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+        Ok(())
     }
 
     /// Attempts to transmit the BLOB to S3.
-    pub async fn store_blob(
+    async fn store_blob(
         &self,
-        payload: BlobPayload,
+        target: &Target,
+        payload: Payload,
         hash_claim: Hash,
         content_length: i64,
-    ) -> Result<PutObjectOutput, StoreError> {
+    ) -> Result<Stored, StoreError> {
+        // A BLOB has to be held in memory to be compressed, or for its PUT to be retried, so large
+        // ones are streamed as they are, and not retried once the stream has started.
+        if content_length <= MAX_RETRIED_LEN
+            || (CONFIG.blob_zstd_level.is_some() && content_length <= MAX_COMPRESSED_LEN)
+        {
+            let bytes = receive(payload, hash_claim, content_length, true).await?;
+            return self.store_bytes(target, hash_claim, bytes).await;
+        }
+
+        self.inject_faults().await?;
+        // Whatever fails the stream fails the PUT, and S3 discards what it had received, but the
+        // error which failed it can't be got back out of the SDK's. So the stream notes on the
+        // side what it saw, and that's checked once the PUT is done, however it ended.
+        let check = Arc::new(UploadCheck::default());
+        let check_in_stream = check.clone();
         let stream = payload.scan((Hasher::new(), 0), move |(h, len), item| match item {
             Ok(ref b) => {
                 h.update(&b);
-                *len += b.len();
+                *len += b.len() as i64;
+                check_in_stream.received.store(*len, Ordering::Relaxed);
 
-                if *len == content_length as usize {
-                    let hash = h.finalize();
-                    if hash != hash_claim {
-                        return futures::future::ready(Some(Err(StoreError::InvalidHash)));
-                    }
+                if *len > content_length || (*len == content_length && h.finalize() != hash_claim) {
+                    check_in_stream.mismatch.store(true, Ordering::Relaxed);
+                    return futures::future::ready(Some(Err(StoreError::InvalidHash)));
                 }
 
                 futures::future::ready(Some(Ok(b.clone())))
             }
-            Err(e) => futures::future::ready(Some(Err(StoreError::WithBlob(e)))),
+            Err(e) => {
+                if matches!(e, WithBlobError::Stalled) {
+                    check_in_stream.stalled.store(true, Ordering::Relaxed);
+                }
+                futures::future::ready(Some(Err(StoreError::WithBlob(e))))
+            }
         });
 
         let body = hyper::Body::wrap_stream(stream);
         let byte_stream = ByteStream::new(body.into());
 
-        // TODO: in the case that the hash doesn't match, the error returned from the final stream
-        // item gets wrapped up in the AWS error types and it's difficult for us to get at it. For
-        // now, we are correctly erroring but not returning a useful message to the user. It would
-        // be better if we could inspect the AWS error and determine if it's the result of an
-        // invalid hash. If so, this function should be returning `StoreError::InvalidHash` rather
-        // than `StoreError::S3(err)`.
-        let aws_res = self
-            .client
+        let res = self
+            .client(target)
             .put_object()
-            .bucket(&CONFIG.aws_s3_blob_bucket)
-            .key(hash_claim.to_hex().to_string())
+            .set_server_side_encryption(encryption())
+            .set_ssekms_key_id(CONFIG.s3_sse_kms_key_id.clone())
+            .bucket(target.bucket())
+            .key(target.key(hash_claim))
             .body(byte_stream)
             .content_length(content_length)
             .send()
+            .await;
+
+        if check.mismatch.load(Ordering::Relaxed) {
+            // Should the PUT have gone through regardless, the object holds bytes which aren't
+            // the BLOB's, so it's deleted rather than left to be served under the claimed hash.
+            if res.is_ok() {
+                log::warn!("deleting object stored with the wrong hash: {}", hash_claim);
+                self.delete_blob(target, hash_claim).await?;
+            }
+            return Err(StoreError::InvalidHash);
+        }
+        res.map_err(|e| {
+            if check.stalled.load(Ordering::Relaxed) {
+                StoreError::WithBlob(WithBlobError::Stalled)
+            } else if check.received.load(Ordering::Relaxed) < content_length {
+                // The payload ended before the length it claimed, so it can't be the BLOB.
+                StoreError::InvalidHash
+            } else {
+                StoreError::S3(e)
+            }
+        })?;
+
+        Ok(Stored::raw(content_length))
+    }
+
+    /// Stores a BLOB which has already been received in full, compressed if compression is
+    /// configured and it gets smaller. The caller is responsible for computing `content_hash` from
+    /// `bytes`.
+    async fn store_bytes(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        bytes: bytes::Bytes,
+    ) -> Result<Stored, StoreError> {
+        let content_length = bytes.len() as i64;
+        let compressed = match CONFIG.blob_zstd_level {
+            Some(level) if content_length <= MAX_COMPRESSED_LEN => {
+                compress(bytes.clone(), level).await?
+            }
+            _ => None,
+        };
+        let stored = match &compressed {
+            Some(compressed) => Stored {
+                compression: Some(ZSTD),
+                length: compressed.len() as i64,
+            },
+            None => Stored::raw(content_length),
+        };
+
+        let (compressed, bytes) = (&compressed, &bytes);
+        retry(Backoff::from_config(), move || async move {
+            self.inject_faults().await?;
+            let put = self
+                .client(target)
+                .put_object()
+                .set_server_side_encryption(encryption())
+                .set_ssekms_key_id(CONFIG.s3_sse_kms_key_id.clone())
+                .bucket(target.bucket())
+                .key(target.key(content_hash));
+            let put = match compressed {
+                Some(compressed) => put
+                    .body(ByteStream::from(compressed.clone()))
+                    .content_encoding(ZSTD)
+                    .metadata(RAW_LENGTH_KEY, content_length.to_string()),
+                None => put.body(ByteStream::from(bytes.clone())),
+            };
+            put.content_length(stored.length)
+                .send()
+                .await
+                .map_err(StoreError::S3)
+        })
+        .await?;
+
+        Ok(stored)
+    }
+
+    /// Starts a multipart upload of a BLOB, returning the upload's id. The BLOB isn't stored until
+    /// the upload is completed, and the parts uploaded so far are kept until it's aborted.
+    async fn create_multipart_upload(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<String, StoreError> {
+        self.inject_faults().await?;
+        let res = self
+            .client(target)
+            .create_multipart_upload()
+            .set_server_side_encryption(encryption())
+            .set_ssekms_key_id(CONFIG.s3_sse_kms_key_id.clone())
+            .bucket(target.bucket())
+            .key(target.key(content_hash))
+            .send()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+        res.upload_id()
+            .map(String::from)
+            .ok_or_else(|| StoreError::S3Other("multipart upload has no id".into()))
+    }
+
+    /// Uploads a part of a multipart upload, returning the part's ETag, which is needed to
+    /// complete the upload. Uploading a part again replaces it.
+    async fn upload_part(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        upload_id: &str,
+        part_number: i32,
+        bytes: bytes::Bytes,
+    ) -> Result<String, StoreError> {
+        self.inject_faults().await?;
+        let content_length = bytes.len() as i64;
+        let res = self
+            .client(target)
+            .upload_part()
+            .bucket(target.bucket())
+            .key(target.key(content_hash))
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(bytes))
+            .content_length(content_length)
+            .send()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+        res.e_tag()
+            .map(String::from)
+            .ok_or_else(|| StoreError::S3Other("uploaded part has no ETag".into()))
+    }
+
+    /// Completes a multipart upload, storing the BLOB from its parts. `e_tags` are those of parts
+    /// `1..=e_tags.len()`, in order.
+    async fn complete_multipart_upload(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        upload_id: &str,
+        e_tags: &[String],
+    ) -> Result<(), StoreError> {
+        self.inject_faults().await?;
+        let parts = e_tags
+            .iter()
+            .zip(1..)
+            .map(|(e_tag, part_number)| {
+                CompletedPart::builder()
+                    .e_tag(e_tag)
+                    .part_number(part_number)
+                    .build()
+            })
+            .collect();
+        self.client(target)
+            .complete_multipart_upload()
+            .bucket(target.bucket())
+            .key(target.key(content_hash))
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+        Ok(())
+    }
+
+    /// Aborts a multipart upload, deleting the parts uploaded so far.
+    async fn abort_multipart_upload(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        upload_id: &str,
+    ) -> Result<(), StoreError> {
+        self.inject_faults().await?;
+        self.client(target)
+            .abort_multipart_upload()
+            .bucket(target.bucket())
+            .key(target.key(content_hash))
+            .upload_id(upload_id)
+            .send()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+        Ok(())
+    }
+
+    /// Attempts to retrieve the BLOB from S3, decompressing it if it was compressed.
+    async fn retrieve_blob(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<ByteStream, StoreError> {
+        let output = retry(Backoff::from_config(), move || async move {
+            self.inject_faults().await?;
+            self.client(target)
+                .get_object()
+                .bucket(target.bucket())
+                .key(target.key(content_hash))
+                .send()
+                .await
+                .map_err(s3_error)
+        })
+        .await?;
+
+        if output.content_encoding() == Some(ZSTD) {
+            let compressed = output
+                .body
+                .collect()
+                .await
+                .map_err(|e| StoreError::S3Other(Box::new(e)))?
+                .into_bytes();
+            return Ok(ByteStream::from(decompress(compressed).await?));
+        }
+
+        Ok(output.body)
+    }
+
+    /// Attempts to retrieve the bytes `first..=last` of the BLOB from S3. A compressed BLOB can't
+    /// be read in ranges, so it's decompressed in full and the range taken from that; compressed
+    /// BLOBs are never large.
+    async fn retrieve_blob_range(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        first: u64,
+        last: u64,
+    ) -> Result<ByteStream, StoreError> {
+        // A range which starts partway through the BLOB may start past the end of a compressed
+        // object, so whether it's compressed is looked up first.
+        if first > 0 && self.head_blob(target, content_hash).await?.1 {
+            let bytes = self.retrieve_blob_bytes(target, content_hash).await?;
+            return Ok(ByteStream::from(byte_range(bytes, first, last)));
+        }
+
+        let output = retry(Backoff::from_config(), move || async move {
+            self.inject_faults().await?;
+            self.client(target)
+                .get_object()
+                .bucket(target.bucket())
+                .key(target.key(content_hash))
+                .range(format!("bytes={}-{}", first, last))
+                .send()
+                .await
+                .map_err(s3_error)
+        })
+        .await?;
+
+        if output.content_encoding() == Some(ZSTD) {
+            let bytes = self.retrieve_blob_bytes(target, content_hash).await?;
+            return Ok(ByteStream::from(byte_range(bytes, first, last)));
+        }
+
+        Ok(output.body)
+    }
+
+    /// Returns the length, in bytes, of the stored BLOB.
+    async fn blob_length(&self, target: &Target, content_hash: Hash) -> Result<i64, StoreError> {
+        let (length, _) = self.head_blob(target, content_hash).await?;
+        Ok(length)
+    }
+
+    /// Moves the BLOB to a different S3 storage class, by copying the object onto itself.
+    async fn set_storage_class(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        storage_class: StorageClass,
+    ) -> Result<(), StoreError> {
+        self.inject_faults().await?;
+        let key = target.key(content_hash);
+        self.client(target)
+            .copy_object()
+            .set_server_side_encryption(encryption())
+            .set_ssekms_key_id(CONFIG.s3_sse_kms_key_id.clone())
+            .bucket(target.bucket())
+            .copy_source(format!("{}/{}", target.bucket(), key))
+            .key(key)
+            .storage_class(storage_class)
+            .metadata_directive(MetadataDirective::Copy)
+            .send()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+        Ok(())
+    }
+
+    /// Asks S3 to restore a temporary copy of an archived BLOB, which remains readable for `days`
+    /// days. Restoration happens asynchronously; poll `restore_complete` to find out when it's done.
+    async fn request_restore(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        days: i32,
+    ) -> Result<(), StoreError> {
+        self.inject_faults().await?;
+        self.client(target)
+            .restore_object()
+            .bucket(target.bucket())
+            .key(target.key(content_hash))
+            .restore_request(RestoreRequest::builder().days(days).build())
+            .send()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+        Ok(())
+    }
+
+    /// Whether a restore requested by `request_restore` has finished.
+    async fn restore_complete(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<bool, StoreError> {
+        self.inject_faults().await?;
+        let head = self
+            .client(target)
+            .head_object()
+            .bucket(target.bucket())
+            .key(target.key(content_hash))
+            .send()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+        // While the restore is in progress, S3 reports `ongoing-request="true"`.
+        Ok(head
+            .restore()
+            .map_or(false, |r| r.contains("ongoing-request=\"false\"")))
+    }
+
+    /// Stores an object which isn't a BLOB, such as a manifest, under `key`, after the server's key
+    /// prefix. Keys of other objects must not look like content hashes.
+    async fn store_object(&self, key: &str, bytes: bytes::Bytes) -> Result<(), StoreError> {
+        self.inject_faults().await?;
+        let content_length = bytes.len() as i64;
+        self.client
+            .put_object()
+            .set_server_side_encryption(encryption())
+            .set_ssekms_key_id(CONFIG.s3_sse_kms_key_id.clone())
+            .bucket(&CONFIG.aws_s3_blob_bucket)
+            .key(format!("{}{}", CONFIG.s3_key_prefix, key))
+            .body(ByteStream::from(bytes))
+            .content_length(content_length)
+            .send()
             .await
-            .map_err(|e| StoreError::S3(e));
+            .map_err(StoreError::S3)?;
 
-        aws_res
+        Ok(())
     }
 
-    /// Attempts to retrieve the BLOB from S3.
-    pub async fn retrieve_blob(&self, content_hash: Hash) -> Result<ByteStream, StoreError> {
-        Ok(self
+    /// Retrieves an object stored by `store_object` into memory.
+    async fn retrieve_object(&self, key: &str) -> Result<bytes::Bytes, StoreError> {
+        self.inject_faults().await?;
+        let bytes = self
             .client
             .get_object()
             .bucket(&CONFIG.aws_s3_blob_bucket)
-            .key(content_hash.to_hex().to_string())
+            .key(format!("{}{}", CONFIG.s3_key_prefix, key))
+            .send()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?
+            .body
+            .collect()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?
+            .into_bytes();
+
+        Ok(bytes)
+    }
+
+    /// Returns the length, in bytes, of the stored BLOB and when it was last written, in seconds
+    /// since the Unix epoch, if S3 says, or `None` when nothing is stored under the hash.
+    async fn find_blob_written(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+    ) -> Result<Option<(i64, Option<i64>)>, StoreError> {
+        self.inject_faults().await?;
+        let key = target.key(content_hash);
+        let res = self
+            .client(target)
+            .list_objects_v2()
+            .bucket(target.bucket())
+            .prefix(&key)
+            .max_keys(1)
+            .send()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+        Ok(res
+            .contents()
+            .unwrap_or_default()
+            .iter()
+            .find(|o| o.key() == Some(key.as_str()))
+            .map(|o| (o.size(), o.last_modified().map(|t| t.secs()))))
+    }
+
+    /// Lists a page of the objects stored under the target's prefix, as their keys and lengths,
+    /// along with where the next page starts, if there's one.
+    async fn list_objects(
+        &self,
+        target: &Target,
+        continuation: Option<String>,
+    ) -> Result<(Vec<(String, i64)>, Option<String>), StoreError> {
+        self.inject_faults().await?;
+        let res = self
+            .client(target)
+            .list_objects_v2()
+            .bucket(target.bucket())
+            .prefix(target.prefix.as_deref().unwrap_or_default())
+            .set_continuation_token(continuation)
+            .send()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+        let objects = res
+            .contents()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|o| Some((o.key()?.to_string(), o.size())))
+            .collect();
+        let next = if res.is_truncated() {
+            res.next_continuation_token().map(str::to_string)
+        } else {
+            None
+        };
+
+        Ok((objects, next))
+    }
+
+    /// Deletes the stored BLOB. Deleting a BLOB which isn't stored isn't an error.
+    async fn delete_blob(&self, target: &Target, content_hash: Hash) -> Result<(), StoreError> {
+        self.inject_faults().await?;
+        self.client(target)
+            .delete_object()
+            .bucket(target.bucket())
+            .key(target.key(content_hash))
+            .send()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+        Ok(())
+    }
+
+    /// Presigns a GET of the BLOB's object, valid for `expires_in`.
+    async fn presign_get(
+        &self,
+        target: &Target,
+        content_hash: Hash,
+        expires_in: Duration,
+    ) -> Result<Option<String>, StoreError> {
+        self.inject_faults().await?;
+        let config = PresigningConfig::expires_in(expires_in)
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+        let req = self
+            .client(target)
+            .get_object()
+            .bucket(target.bucket())
+            .key(target.key(content_hash))
+            .presigned(config)
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+        Ok(Some(req.uri().to_string()))
+    }
+
+    /// Presigns a PUT of `content_length` bytes to the staging key of an upload, valid for
+    /// `expires_in`. The length is signed, so nothing longer can be uploaded with it.
+    async fn presign_staged_put(
+        &self,
+        target: &Target,
+        upload_id: Uuid,
+        content_length: i64,
+        expires_in: Duration,
+    ) -> Result<Option<PresignedPut>, StoreError> {
+        self.inject_faults().await?;
+        let config = PresigningConfig::expires_in(expires_in)
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+        let req = self
+            .client(target)
+            .put_object()
+            .set_server_side_encryption(encryption())
+            .set_ssekms_key_id(CONFIG.s3_sse_kms_key_id.clone())
+            .bucket(target.bucket())
+            .key(target.staged_key(upload_id))
+            .content_length(content_length)
+            .presigned(config)
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+        let headers = req
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        Ok(Some(PresignedPut {
+            url: req.uri().to_string(),
+            headers,
+        }))
+    }
+
+    /// Retrieves what was uploaded to the staging key of an upload.
+    async fn retrieve_staged(
+        &self,
+        target: &Target,
+        upload_id: Uuid,
+    ) -> Result<ByteStream, StoreError> {
+        self.inject_faults().await?;
+        let output = self
+            .client(target)
+            .get_object()
+            .bucket(target.bucket())
+            .key(target.staged_key(upload_id))
             .send()
             .await
-            .unwrap()
-            .body)
+            .map_err(|e| match e {
+                SdkError::ServiceError { ref err, .. } if err.is_no_such_key() => {
+                    StoreError::NotFound
+                }
+                e => StoreError::S3Other(Box::new(e)),
+            })?;
+
+        Ok(output.body)
+    }
+
+    /// Copies what was uploaded to the staging key of an upload to the key of the BLOB, and
+    /// deletes it from the staging key. A single PUT can't be more than 5 GiB, so neither can what
+    /// was uploaded, and it can be copied in one go.
+    async fn promote_staged(
+        &self,
+        target: &Target,
+        upload_id: Uuid,
+        content_hash: Hash,
+    ) -> Result<(), StoreError> {
+        self.inject_faults().await?;
+        self.client(target)
+            .copy_object()
+            .set_server_side_encryption(encryption())
+            .set_ssekms_key_id(CONFIG.s3_sse_kms_key_id.clone())
+            .bucket(target.bucket())
+            .copy_source(format!(
+                "{}/{}",
+                target.bucket(),
+                target.staged_key(upload_id)
+            ))
+            .key(target.key(content_hash))
+            .send()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+        self.delete_staged(target, upload_id).await
+    }
+
+    /// Deletes what was uploaded to the staging key of an upload.
+    async fn delete_staged(&self, target: &Target, upload_id: Uuid) -> Result<(), StoreError> {
+        self.inject_faults().await?;
+        self.client(target)
+            .delete_object()
+            .bucket(target.bucket())
+            .key(target.staged_key(upload_id))
+            .send()
+            .await
+            .map_err(|e| StoreError::S3Other(Box::new(e)))?;
+
+        Ok(())
     }
 }
 
@@ -208,6 +1306,7 @@ impl S3Store {
 impl<P> Persist for WithBlob<P>
 where
     P: Persist + BlobMetadata + Send + Sync + std::marker::Unpin,
+    P::Ret: Send,
     P::Error: Into<StoreError>,
 {
     type Ret = <P as Persist>::Ret;
@@ -221,18 +1320,101 @@ where
         let payload = self.blob.take().ok_or(StoreError::MissingPayload)?;
         let meta = self.meta;
 
-        let hash_hex = meta.content_hash();
-        let hash = Hash::from_hex(hash_hex)?;
+        let bucket = match auth {
+            Some(auth) => bucket_for(auth, state, Direction::Upload, self.priority).await,
+            None => None,
+        };
+        let payload = throttled(payload, bucket);
 
+        let target = match auth {
+            Some(auth) => upload_target(auth, state).await?,
+            None => Target::server(),
+        };
+
+        let hash_hex = meta.content_hash();
         let content_length = meta.content_length();
 
-        // Attempt to store the byte stream in S3.
-        let _s3_result = state
-            .s3_store
-            .store_blob(payload, hash, content_length)
-            .await?;
+        // Attempt to store the byte stream in S3, unless the BLOB is stored there already.
+        let shared = shared_object(&target, hash_hex, state).await?;
+
+        // A BLOB which is stored already adds nothing to what's stored, so only others are checked
+        // against the quota, before any of their bytes are read.
+        if let (Some(auth), None, Some(_)) = (auth, &shared, state.config.storage_quota_bytes) {
+            let user_id = user_id(auth, state).await?;
+            if !within_storage_quota(state, user_id, content_length).await? {
+                return Err(StoreError::QuotaExceeded);
+            }
+        }
+        let res = match (Hash::from_hex(hash_hex), &shared) {
+            (Ok(hash), Some(_)) => receive_blob(payload, hash, content_length)
+                .await
+                .map(|_| None),
+            (Ok(hash), None) => state
+                .blob_store
+                .store_blob(&target, payload.boxed(), hash, content_length)
+                .await
+                .map(Some),
+            (Err(e), _) => Err(e.into()),
+        };
+        let target = shared.unwrap_or(target);
+
+        // Repeated invalid hashes are watched for by the anomaly job.
+        if let (Err(StoreError::InvalidHash), Some(auth)) = (&res, auth) {
+            record_activity(state, auth, Activity::InvalidHash, None).await;
+        }
+        // So are stalled uploads, which were aborted to free what they held on to.
+        if let (Err(StoreError::WithBlob(WithBlobError::Stalled)), Some(auth)) = (&res, auth) {
+            log::warn!("aborted stalled upload of {}", hash_hex);
+            record_activity(state, auth, Activity::StalledUpload, None).await;
+        }
+        let stored = res?;
+
+        // If successful, move on to inserting the row in Postgres, and recording where the BLOB
+        // went.
+        let hash_hex = hash_hex.to_string();
+        let res = async {
+            let ret = meta.persist(auth, state).await.map_err(Into::into)?;
+            if let Some(auth) = auth {
+                record_target(auth, &hash_hex, &target, stored, state).await?;
+            }
+            Ok::<_, StoreError>(ret)
+        }
+        .await;
+
+        // The BLOB is stored but its row isn't, which a client may not retry.
+        if let (Err(StoreError::Sqlx(e)), Some(auth)) = (&res, auth) {
+            let letter = DeadLetterInsert {
+                content_hash: hash_hex,
+                target,
+                op: DeadLetterOp::Blob,
+                error: e.to_string(),
+            };
+            dead_letter::record(state, auth, letter).await;
+        }
+
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefixes_keys() {
+        let hash = blake3::hash(b"hello");
+        assert_eq!(blob_key(None, hash), hash.to_hex().to_string());
+        assert_eq!(
+            blob_key(Some("staging/"), hash),
+            format!("staging/{}", hash.to_hex())
+        );
+    }
 
-        // If successful, move on to inserting the row in Postgres.
-        meta.persist(auth, state).await.map_err(Into::into)
+    #[test]
+    fn takes_ranges_of_decompressed_blobs() {
+        let bytes = bytes::Bytes::from_static(b"0123456789");
+        assert_eq!(&byte_range(bytes.clone(), 2, 4)[..], b"234");
+        assert_eq!(&byte_range(bytes.clone(), 8, 20)[..], b"89");
+        assert_eq!(&byte_range(bytes, 12, 20)[..], b"");
     }
 }
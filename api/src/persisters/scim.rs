@@ -0,0 +1,697 @@
+use crate::middlewares::auth::Auth;
+use crate::models::scim::{
+    Filter, Group, GroupAttributes, GroupRow, ListResponse, MemberRef, PatchOperation, ScimError,
+    User, UserAttributes, UserRow, MAX_RESULTS,
+};
+use crate::models::user::normalize_email;
+use crate::persisters::{Persist, Query};
+use crate::state::State;
+use sqlx::{types::Uuid, Postgres, Transaction};
+
+/// SCIM list parameters. `startIndex` is 1-based.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ListParams {
+    pub filter: Option<String>,
+    pub start_index: Option<i64>,
+    pub count: Option<i64>,
+}
+
+impl ListParams {
+    fn filter(&self) -> Result<Option<Filter>, ScimError> {
+        self.filter.as_deref().map(Filter::parse).transpose()
+    }
+
+    fn start_index(&self) -> i64 {
+        self.start_index.unwrap_or(1).max(1)
+    }
+
+    fn count(&self) -> i64 {
+        self.count.unwrap_or(MAX_RESULTS).clamp(0, MAX_RESULTS)
+    }
+}
+
+pub struct UsersGet {
+    pub params: ListParams,
+}
+
+pub struct UserGet {
+    pub id: Uuid,
+}
+
+/// Provisions a user as a member of the org, creating the user if they are new to HitSave.
+pub struct UserCreate {
+    pub attributes: UserAttributes,
+}
+
+pub struct UserReplace {
+    pub id: Uuid,
+    pub attributes: UserAttributes,
+}
+
+pub struct UserPatch {
+    pub id: Uuid,
+    pub operations: Vec<PatchOperation>,
+}
+
+/// Deprovisions a user by removing them from the org, and so from all of the org's groups.
+pub struct UserDelete {
+    pub id: Uuid,
+}
+
+pub struct GroupsGet {
+    pub params: ListParams,
+}
+
+pub struct GroupGet {
+    pub id: Uuid,
+}
+
+pub struct GroupCreate {
+    pub attributes: GroupAttributes,
+}
+
+pub struct GroupReplace {
+    pub id: Uuid,
+    pub attributes: GroupAttributes,
+}
+
+pub struct GroupPatch {
+    pub id: Uuid,
+    pub operations: Vec<PatchOperation>,
+}
+
+pub struct GroupDelete {
+    pub id: Uuid,
+}
+
+/// Returns the org whose identity provider made the request, which authenticates with a key of
+/// one of the org's service accounts.
+async fn scim_org(
+    tx: &mut Transaction<'_, Postgres>,
+    auth: Option<&Auth>,
+) -> Result<Uuid, ScimError> {
+    let key = auth
+        .and_then(|a| a.api_key())
+        .ok_or(ScimError::Unauthorized)?;
+
+    query_scalar!(
+        r#"
+        SELECT s.org_id
+        FROM api_keys k
+        JOIN service_accounts s
+            ON s.id = k.service_account_id
+        WHERE k.key = $1
+        "#,
+        key,
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(ScimError::Unauthorized)
+}
+
+async fn user_rows(
+    tx: &mut Transaction<'_, Postgres>,
+    org_id: Uuid,
+    id: Option<Uuid>,
+    filter: Option<&Filter>,
+    offset: i64,
+    limit: i64,
+) -> Result<(Vec<UserRow>, i64), ScimError> {
+    let user_name = filter
+        .and_then(|f| f.value_of("userName"))
+        .map(normalize_email);
+    let external_id = filter.and_then(|f| f.value_of("externalId"));
+    if filter.is_some() && user_name.is_none() && external_id.is_none() {
+        return Err(ScimError::InvalidFilter);
+    }
+
+    let total = query_scalar!(
+        r#"
+        SELECT count(*) AS "count!"
+        FROM org_members m
+        JOIN users u
+            ON u.id = m.user_id
+        WHERE m.org_id = $1
+            AND (m.user_id = $2 OR $2 IS NULL)
+            AND (u.gh_email = $3 OR $3 IS NULL)
+            AND (m.external_id = $4 OR $4 IS NULL)
+        "#,
+        org_id,
+        id,
+        user_name,
+        external_id,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let rows = query_as!(
+        UserRow,
+        r#"
+        SELECT u.id, u.gh_email AS "email!", m.external_id, m.given_name, m.family_name, m.active,
+            m.create_dt, m.update_dt
+        FROM org_members m
+        JOIN users u
+            ON u.id = m.user_id
+        WHERE m.org_id = $1
+            AND (m.user_id = $2 OR $2 IS NULL)
+            AND (u.gh_email = $3 OR $3 IS NULL)
+            AND (m.external_id = $4 OR $4 IS NULL)
+        ORDER BY m.create_dt, u.id
+        OFFSET $5
+        LIMIT $6
+        "#,
+        org_id,
+        id,
+        user_name,
+        external_id,
+        offset,
+        limit,
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    Ok((rows, total))
+}
+
+async fn user_row(
+    tx: &mut Transaction<'_, Postgres>,
+    org_id: Uuid,
+    id: Uuid,
+) -> Result<UserRow, ScimError> {
+    user_rows(tx, org_id, Some(id), None, 0, 1)
+        .await?
+        .0
+        .pop()
+        .ok_or(ScimError::NotFound)
+}
+
+/// Updates a membership to match `attributes`. A user's `userName` is their email address, which
+/// identifies them across orgs, so it can't be changed.
+async fn replace_user(
+    tx: &mut Transaction<'_, Postgres>,
+    org_id: Uuid,
+    current: &UserRow,
+    attributes: &UserAttributes,
+) -> Result<(), ScimError> {
+    if normalize_email(&attributes.user_name) != current.email {
+        return Err(ScimError::Mutability);
+    }
+
+    query!(
+        r#"
+        UPDATE org_members
+        SET external_id = $3,
+            given_name = $4,
+            family_name = $5,
+            active = $6,
+            update_dt = current_timestamp
+        WHERE org_id = $1
+            AND user_id = $2
+        "#,
+        org_id,
+        current.id,
+        attributes.external_id,
+        attributes.name.given_name,
+        attributes.name.family_name,
+        attributes.active,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+async fn group_rows(
+    tx: &mut Transaction<'_, Postgres>,
+    org_id: Uuid,
+    id: Option<Uuid>,
+    filter: Option<&Filter>,
+    offset: i64,
+    limit: i64,
+) -> Result<(Vec<GroupRow>, i64), ScimError> {
+    let display_name = filter.and_then(|f| f.value_of("displayName"));
+    let external_id = filter.and_then(|f| f.value_of("externalId"));
+    if filter.is_some() && display_name.is_none() && external_id.is_none() {
+        return Err(ScimError::InvalidFilter);
+    }
+
+    let total = query_scalar!(
+        r#"
+        SELECT count(*) AS "count!"
+        FROM org_groups
+        WHERE org_id = $1
+            AND (id = $2 OR $2 IS NULL)
+            AND (display_name = $3 OR $3 IS NULL)
+            AND (external_id = $4 OR $4 IS NULL)
+        "#,
+        org_id,
+        id,
+        display_name,
+        external_id,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let rows = query_as!(
+        GroupRow,
+        r#"
+        SELECT g.id, g.display_name, g.external_id,
+            array_remove(array_agg(u.id ORDER BY u.gh_email), NULL) AS "member_ids!",
+            array_remove(array_agg(u.gh_email ORDER BY u.gh_email), NULL) AS "member_emails!",
+            g.create_dt, g.update_dt
+        FROM org_groups g
+        LEFT JOIN org_group_members gm
+            ON gm.group_id = g.id
+        LEFT JOIN users u
+            ON u.id = gm.user_id
+        WHERE g.org_id = $1
+            AND (g.id = $2 OR $2 IS NULL)
+            AND (g.display_name = $3 OR $3 IS NULL)
+            AND (g.external_id = $4 OR $4 IS NULL)
+        GROUP BY g.id
+        ORDER BY g.create_dt, g.id
+        OFFSET $5
+        LIMIT $6
+        "#,
+        org_id,
+        id,
+        display_name,
+        external_id,
+        offset,
+        limit,
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    Ok((rows, total))
+}
+
+async fn group_row(
+    tx: &mut Transaction<'_, Postgres>,
+    org_id: Uuid,
+    id: Uuid,
+) -> Result<GroupRow, ScimError> {
+    group_rows(tx, org_id, Some(id), None, 0, 1)
+        .await?
+        .0
+        .pop()
+        .ok_or(ScimError::NotFound)
+}
+
+/// Sets the members of a group. Members must already be members of the org.
+async fn set_members(
+    tx: &mut Transaction<'_, Postgres>,
+    org_id: Uuid,
+    group_id: Uuid,
+    members: &[MemberRef],
+) -> Result<(), ScimError> {
+    let user_ids = members
+        .iter()
+        .map(|m| Uuid::parse_str(&m.value).map_err(|_| ScimError::InvalidValue))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    query!(
+        "DELETE FROM org_group_members WHERE group_id = $1",
+        group_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    query!(
+        r#"
+        INSERT INTO org_group_members (group_id, org_id, user_id)
+        SELECT $1, $2, user_id
+        FROM unnest($3::uuid[]) AS user_id
+        ON CONFLICT DO NOTHING
+        "#,
+        group_id,
+        org_id,
+        &user_ids,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Updates a group to match `attributes`.
+async fn replace_group(
+    tx: &mut Transaction<'_, Postgres>,
+    org_id: Uuid,
+    id: Uuid,
+    attributes: &GroupAttributes,
+) -> Result<(), ScimError> {
+    query_scalar!(
+        r#"
+        UPDATE org_groups
+        SET display_name = $3,
+            external_id = $4,
+            update_dt = current_timestamp
+        WHERE org_id = $1
+            AND id = $2
+        RETURNING id
+        "#,
+        org_id,
+        id,
+        attributes.display_name,
+        attributes.external_id,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    set_members(tx, org_id, id, &attributes.members).await
+}
+
+#[async_trait]
+impl Query for UsersGet {
+    type Resolve = ListResponse<User>;
+    type Error = ScimError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let mut tx = state.db_conn.begin().await?;
+        let org_id = scim_org(&mut tx, auth).await?;
+
+        let filter = self.params.filter()?;
+        let start_index = self.params.start_index();
+        let (rows, total) = user_rows(
+            &mut tx,
+            org_id,
+            None,
+            filter.as_ref(),
+            start_index - 1,
+            self.params.count(),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(ListResponse::new(
+            rows.into_iter().map(User::from).collect(),
+            total,
+            start_index,
+        ))
+    }
+}
+
+#[async_trait]
+impl Query for UserGet {
+    type Resolve = User;
+    type Error = ScimError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let mut tx = state.db_conn.begin().await?;
+        let org_id = scim_org(&mut tx, auth).await?;
+        let row = user_row(&mut tx, org_id, self.id).await?;
+        tx.commit().await?;
+
+        Ok(row.into())
+    }
+}
+
+#[async_trait]
+impl Persist for UserCreate {
+    type Ret = User;
+    type Error = ScimError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let mut tx = state.db_conn.begin().await?;
+        let org_id = scim_org(&mut tx, auth).await?;
+        let attributes = self.attributes;
+
+        let email = normalize_email(&attributes.user_name);
+
+        // The org's identity provider asserting an email address isn't enough to take over the
+        // account which has it, so the membership of an existing account waits for its user to
+        // accept it.
+        let existing = query_scalar!("SELECT id FROM users WHERE gh_email = $1 FOR UPDATE", email)
+            .fetch_optional(&mut tx)
+            .await?;
+        let accepted = existing.is_none();
+
+        // Users who have never signed in with GitHub have no GitHub login, so their email address
+        // stands in for it. GitHub logins can't contain an `@`, so the two can't collide.
+        let user_id = match existing {
+            Some(id) => id,
+            None => {
+                query_scalar!(
+                    r#"
+                    INSERT INTO users (gh_email, gh_login)
+                    VALUES ($1, $1)
+                    RETURNING id
+                    "#,
+                    email,
+                )
+                .fetch_one(&mut tx)
+                .await?
+            }
+        };
+
+        // Fails with a unique violation if the user is already a member of the org.
+        query!(
+            r#"
+            INSERT INTO org_members
+                (org_id, user_id, external_id, given_name, family_name, active, accepted)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+            org_id,
+            user_id,
+            attributes.external_id,
+            attributes.name.given_name,
+            attributes.name.family_name,
+            attributes.active,
+            accepted,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        let row = user_row(&mut tx, org_id, user_id).await?;
+        tx.commit().await?;
+
+        Ok(row.into())
+    }
+}
+
+#[async_trait]
+impl Persist for UserReplace {
+    type Ret = User;
+    type Error = ScimError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let mut tx = state.db_conn.begin().await?;
+        let org_id = scim_org(&mut tx, auth).await?;
+
+        let current = user_row(&mut tx, org_id, self.id).await?;
+        replace_user(&mut tx, org_id, &current, &self.attributes).await?;
+
+        let row = user_row(&mut tx, org_id, self.id).await?;
+        tx.commit().await?;
+
+        Ok(row.into())
+    }
+}
+
+#[async_trait]
+impl Persist for UserPatch {
+    type Ret = User;
+    type Error = ScimError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let mut tx = state.db_conn.begin().await?;
+        let org_id = scim_org(&mut tx, auth).await?;
+
+        query!(
+            "SELECT user_id FROM org_members WHERE org_id = $1 AND user_id = $2 FOR UPDATE",
+            org_id,
+            self.id,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        let current = user_row(&mut tx, org_id, self.id).await?;
+        let mut attributes = current.attributes();
+        attributes.apply(&self.operations)?;
+        replace_user(&mut tx, org_id, &current, &attributes).await?;
+
+        let row = user_row(&mut tx, org_id, self.id).await?;
+        tx.commit().await?;
+
+        Ok(row.into())
+    }
+}
+
+#[async_trait]
+impl Persist for UserDelete {
+    type Ret = ();
+    type Error = ScimError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let mut tx = state.db_conn.begin().await?;
+        let org_id = scim_org(&mut tx, auth).await?;
+
+        let res = query!(
+            "DELETE FROM org_members WHERE org_id = $1 AND user_id = $2",
+            org_id,
+            self.id,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(ScimError::NotFound);
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Query for GroupsGet {
+    type Resolve = ListResponse<Group>;
+    type Error = ScimError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let mut tx = state.db_conn.begin().await?;
+        let org_id = scim_org(&mut tx, auth).await?;
+
+        let filter = self.params.filter()?;
+        let start_index = self.params.start_index();
+        let (rows, total) = group_rows(
+            &mut tx,
+            org_id,
+            None,
+            filter.as_ref(),
+            start_index - 1,
+            self.params.count(),
+        )
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(ListResponse::new(
+            rows.into_iter().map(Group::from).collect(),
+            total,
+            start_index,
+        ))
+    }
+}
+
+#[async_trait]
+impl Query for GroupGet {
+    type Resolve = Group;
+    type Error = ScimError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let mut tx = state.db_conn.begin().await?;
+        let org_id = scim_org(&mut tx, auth).await?;
+        let row = group_row(&mut tx, org_id, self.id).await?;
+        tx.commit().await?;
+
+        Ok(row.into())
+    }
+}
+
+#[async_trait]
+impl Persist for GroupCreate {
+    type Ret = Group;
+    type Error = ScimError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let mut tx = state.db_conn.begin().await?;
+        let org_id = scim_org(&mut tx, auth).await?;
+
+        let id = query_scalar!(
+            r#"
+            INSERT INTO org_groups (org_id, display_name, external_id)
+            VALUES ($1, $2, $3)
+            RETURNING id
+            "#,
+            org_id,
+            self.attributes.display_name,
+            self.attributes.external_id,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        set_members(&mut tx, org_id, id, &self.attributes.members).await?;
+
+        let row = group_row(&mut tx, org_id, id).await?;
+        tx.commit().await?;
+
+        Ok(row.into())
+    }
+}
+
+#[async_trait]
+impl Persist for GroupReplace {
+    type Ret = Group;
+    type Error = ScimError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let mut tx = state.db_conn.begin().await?;
+        let org_id = scim_org(&mut tx, auth).await?;
+
+        replace_group(&mut tx, org_id, self.id, &self.attributes).await?;
+
+        let row = group_row(&mut tx, org_id, self.id).await?;
+        tx.commit().await?;
+
+        Ok(row.into())
+    }
+}
+
+#[async_trait]
+impl Persist for GroupPatch {
+    type Ret = Group;
+    type Error = ScimError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let mut tx = state.db_conn.begin().await?;
+        let org_id = scim_org(&mut tx, auth).await?;
+
+        query!(
+            "SELECT id FROM org_groups WHERE org_id = $1 AND id = $2 FOR UPDATE",
+            org_id,
+            self.id,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        let mut attributes = group_row(&mut tx, org_id, self.id).await?.attributes();
+        attributes.apply(&self.operations)?;
+        replace_group(&mut tx, org_id, self.id, &attributes).await?;
+
+        let row = group_row(&mut tx, org_id, self.id).await?;
+        tx.commit().await?;
+
+        Ok(row.into())
+    }
+}
+
+#[async_trait]
+impl Persist for GroupDelete {
+    type Ret = ();
+    type Error = ScimError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let mut tx = state.db_conn.begin().await?;
+        let org_id = scim_org(&mut tx, auth).await?;
+
+        let res = query!(
+            "DELETE FROM org_groups WHERE org_id = $1 AND id = $2",
+            org_id,
+            self.id,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(ScimError::NotFound);
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use crate::middlewares::auth::Auth;
+use crate::models::search::{
+    contains_pattern, ArtifactHit, FunctionHit, ProjectHit, RunHit, SearchError, SearchResults,
+    MAX_SEARCH_HITS,
+};
+use crate::persisters::user::user_id;
+use crate::persisters::Query;
+use crate::policy::{self, Action, PolicyError, Request};
+use crate::state::State;
+
+fn default_limit() -> i64 {
+    10
+}
+
+/// Searches the names of the authenticated user's functions, runs, run artifacts and projects for
+/// `q`. Runs have no tags to search, since MLflow tags aren't kept.
+#[derive(Deserialize, Debug)]
+pub struct SearchGet {
+    pub q: String,
+    /// The most hits of each kind to return.
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+/// Whether the policy lets the user take the action. Kinds of things they can't read are left out
+/// of searches, rather than failing them.
+async fn allowed(auth: &Auth, action: Action, state: &State) -> Result<bool, SearchError> {
+    match policy::authorize(auth, Request::new(action), state).await {
+        Ok(()) => Ok(true),
+        Err(PolicyError::Sqlx(e)) => Err(e.into()),
+        Err(_) => Ok(false),
+    }
+}
+
+#[async_trait]
+impl Query for SearchGet {
+    type Resolve = SearchResults;
+    type Error = SearchError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(SearchError::Unauthorized)?;
+        let q = self.q.trim();
+        if q.is_empty() {
+            return Err(SearchError::InvalidQuery);
+        }
+        let pattern = contains_pattern(q);
+        let limit = self.limit.clamp(1, MAX_SEARCH_HITS);
+        let user_id = user_id(auth, state).await?;
+        let mut res = SearchResults::default();
+
+        if allowed(auth, Action::EvalRead, state).await? {
+            res.functions = query_as!(
+                FunctionHit,
+                r#"
+                SELECT fn_key, count(*) AS "evals!",
+                    word_similarity($2, fn_key)::float8 AS "score!"
+                FROM evals
+                WHERE user_id = $1
+                    AND (fn_key ILIKE $3 OR $2 <% fn_key)
+                GROUP BY fn_key
+                ORDER BY 3 DESC, fn_key
+                LIMIT $4
+                "#,
+                user_id,
+                q,
+                pattern,
+                limit,
+            )
+            .fetch_all(&state.db_conn)
+            .await?;
+
+            res.projects = query_as!(
+                ProjectHit,
+                r#"
+                SELECT id, name, word_similarity($2, name)::float8 AS "score!"
+                FROM projects
+                WHERE user_id = $1
+                    AND (name ILIKE $3 OR $2 <% name)
+                ORDER BY 3 DESC, name
+                LIMIT $4
+                "#,
+                user_id,
+                q,
+                pattern,
+                limit,
+            )
+            .fetch_all(&state.db_conn)
+            .await?;
+        }
+
+        if allowed(auth, Action::RunRead, state).await? {
+            res.runs = query_as!(
+                RunHit,
+                r#"
+                SELECT id, name, fn_key, state, create_dt,
+                    greatest(word_similarity($2, coalesce(name, '')),
+                        word_similarity($2, fn_key))::float8 AS "score!"
+                FROM runs
+                WHERE user_id = $1
+                    AND (name ILIKE $3 OR $2 <% name OR fn_key ILIKE $3 OR $2 <% fn_key)
+                ORDER BY 6 DESC, create_dt DESC
+                LIMIT $4
+                "#,
+                user_id,
+                q,
+                pattern,
+                limit,
+            )
+            .fetch_all(&state.db_conn)
+            .await?;
+
+            res.artifacts = query_as!(
+                ArtifactHit,
+                r#"
+                SELECT a.run_id, a.path, b.content_hash,
+                    word_similarity($2, a.path)::float8 AS "score!"
+                FROM run_artifacts a
+                JOIN runs r
+                    ON r.id = a.run_id
+                JOIN blobs b
+                    ON b.id = a.blob_id
+                WHERE r.user_id = $1
+                    AND (a.path ILIKE $3 OR $2 <% a.path)
+                ORDER BY 4 DESC, a.create_dt DESC
+                LIMIT $4
+                "#,
+                user_id,
+                q,
+                pattern,
+                limit,
+            )
+            .fetch_all(&state.db_conn)
+            .await?;
+        }
+
+        Ok(res)
+    }
+}
@@ -0,0 +1,358 @@
+use crate::middlewares::auth::Auth;
+use crate::models::sso::{ProviderConfig, Role, SsoConfig, SsoError, LOGIN_TTL_MINS};
+use crate::models::user::normalize_email;
+use crate::persisters::{Persist, Query};
+use crate::state::State;
+use sqlx::types::Uuid;
+use std::collections::HashMap;
+
+fn default_groups_claim() -> String {
+    "groups".to_string()
+}
+
+fn default_role() -> Role {
+    Role::Member
+}
+
+/// Configures the OpenID Connect identity provider of an org, replacing any existing
+/// configuration. Only the org's owner can configure it.
+#[derive(Deserialize, Debug)]
+pub struct SsoConfigUpsert {
+    #[serde(skip)]
+    pub org_id: Uuid,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    #[serde(default = "default_groups_claim")]
+    pub groups_claim: String,
+    #[serde(default)]
+    pub role_mappings: HashMap<String, Role>,
+    #[serde(default = "default_role")]
+    pub default_role: Role,
+}
+
+pub struct SsoConfigGet {
+    pub org_id: Uuid,
+}
+
+pub struct SsoConfigDelete {
+    pub org_id: Uuid,
+}
+
+/// Looks up the identity provider of an org, for signing in. No authentication is needed.
+pub struct ProviderConfigGet {
+    pub org_id: Uuid,
+}
+
+/// Records a sign-in which has been started, so that it can be completed by its `state`.
+pub struct LoginStart {
+    pub org_id: Uuid,
+    pub state: String,
+    pub nonce: String,
+    pub redirect_uri: String,
+}
+
+/// Completes a sign-in by its `state`. A sign-in can only be completed once.
+pub struct LoginTake {
+    pub state: String,
+}
+
+pub struct LoginTakeResult {
+    pub org_id: Uuid,
+    pub nonce: String,
+    pub redirect_uri: String,
+}
+
+/// Makes a user who signed in through an org's identity provider a member of the org, creating
+/// the user on their first sign-in, and updating their role on later ones.
+pub struct MemberUpsert {
+    pub org_id: Uuid,
+    pub email: String,
+    pub given_name: Option<String>,
+    pub family_name: Option<String>,
+    pub role: Role,
+}
+
+struct ExistingUser {
+    id: Uuid,
+    active: Option<bool>,
+    accepted: Option<bool>,
+}
+
+#[async_trait]
+impl Persist for SsoConfigUpsert {
+    type Ret = SsoConfig;
+    type Error = SsoError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(SsoError::Unauthorized)?;
+
+        let role_mappings =
+            serde_json::to_value(&self.role_mappings).map_err(|_| SsoError::InvalidConfig)?;
+
+        let res = query_as!(
+            SsoConfig,
+            r#"
+            INSERT INTO org_sso_configs (org_id, issuer, client_id, client_secret, groups_claim,
+                role_mappings, default_role)
+            SELECT id, $4, $5, $6, $7, $8, $9
+            FROM orgs
+            WHERE id = $1
+                AND owner_id = get_user_id($2, $3)
+            ON CONFLICT (org_id) DO UPDATE
+                SET issuer = EXCLUDED.issuer,
+                    client_id = EXCLUDED.client_id,
+                    client_secret = EXCLUDED.client_secret,
+                    groups_claim = EXCLUDED.groups_claim,
+                    role_mappings = EXCLUDED.role_mappings,
+                    default_role = EXCLUDED.default_role,
+                    update_dt = current_timestamp
+            RETURNING org_id, protocol, issuer, client_id, groups_claim, role_mappings,
+                default_role, create_dt, update_dt
+            "#,
+            self.org_id,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.issuer.trim_end_matches('/'),
+            self.client_id,
+            self.client_secret,
+            self.groups_claim,
+            role_mappings,
+            self.default_role.as_str(),
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for SsoConfigGet {
+    type Resolve = SsoConfig;
+    type Error = SsoError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(SsoError::Unauthorized)?;
+
+        let res = query_as!(
+            SsoConfig,
+            r#"
+            SELECT c.org_id, c.protocol, c.issuer, c.client_id, c.groups_claim, c.role_mappings,
+                c.default_role, c.create_dt, c.update_dt
+            FROM org_sso_configs c
+            JOIN orgs o
+                ON o.id = c.org_id
+            WHERE c.org_id = $1
+                AND o.owner_id = get_user_id($2, $3)
+            "#,
+            self.org_id,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Persist for SsoConfigDelete {
+    type Ret = ();
+    type Error = SsoError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(SsoError::Unauthorized)?;
+
+        let res = query!(
+            r#"
+            DELETE FROM org_sso_configs c
+            USING orgs o
+            WHERE o.id = c.org_id
+                AND c.org_id = $1
+                AND o.owner_id = get_user_id($2, $3)
+            "#,
+            self.org_id,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+        )
+        .execute(&state.db_conn)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(SsoError::NotFound);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Query for ProviderConfigGet {
+    type Resolve = ProviderConfig;
+    type Error = SsoError;
+
+    async fn fetch(
+        self,
+        _auth: Option<&Auth>,
+        state: &State,
+    ) -> Result<Self::Resolve, Self::Error> {
+        let res = query_as!(
+            ProviderConfig,
+            r#"
+            SELECT org_id, issuer, client_id, client_secret, groups_claim, role_mappings,
+                default_role
+            FROM org_sso_configs
+            WHERE org_id = $1
+            "#,
+            self.org_id,
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Persist for LoginStart {
+    type Ret = ();
+    type Error = SsoError;
+
+    async fn persist(self, _auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let mut tx = state.db_conn.begin().await?;
+
+        // Sign-ins which were never completed are cleared out as new ones start.
+        query!(
+            "DELETE FROM sso_logins WHERE create_dt < current_timestamp - make_interval(mins => $1)",
+            LOGIN_TTL_MINS,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        query!(
+            r#"
+            INSERT INTO sso_logins (state, org_id, nonce, redirect_uri)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            self.state,
+            self.org_id,
+            self.nonce,
+            self.redirect_uri,
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Persist for LoginTake {
+    type Ret = LoginTakeResult;
+    type Error = SsoError;
+
+    async fn persist(self, _auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let res = query_as!(
+            LoginTakeResult,
+            r#"
+            DELETE FROM sso_logins
+            WHERE state = $1
+                AND create_dt >= current_timestamp - make_interval(mins => $2)
+            RETURNING org_id, nonce, redirect_uri
+            "#,
+            self.state,
+            LOGIN_TTL_MINS,
+        )
+        .fetch_optional(&state.db_conn)
+        .await?
+        .ok_or(SsoError::LoginExpired)?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Persist for MemberUpsert {
+    type Ret = Uuid;
+    type Error = SsoError;
+
+    async fn persist(self, _auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let email = normalize_email(&self.email);
+        let mut tx = state.db_conn.begin().await?;
+
+        let existing = query_as!(
+            ExistingUser,
+            r#"
+            SELECT u.id, m.active AS "active?", m.accepted AS "accepted?"
+            FROM users u
+            LEFT JOIN org_members m
+                ON m.user_id = u.id
+                AND m.org_id = $1
+            WHERE u.gh_email = $2
+            FOR UPDATE OF u
+            "#,
+            self.org_id,
+            email,
+        )
+        .fetch_optional(&mut tx)
+        .await?;
+
+        let user_id = match existing {
+            Some(ExistingUser {
+                active: Some(false),
+                ..
+            }) => return Err(SsoError::Deprovisioned),
+            Some(ExistingUser {
+                id,
+                active: Some(true),
+                accepted: Some(true),
+            }) => id,
+            // An account which the org never provisioned, e.g. one created by signing in with
+            // GitHub, or which it provisioned over SCIM but whose user hasn't accepted the
+            // membership. The org's identity provider asserting the same email address isn't
+            // enough to take it over.
+            Some(ExistingUser { .. }) => return Err(SsoError::AccountExists),
+            // As for users provisioned over SCIM, the email address stands in for the GitHub
+            // login of users who have never signed in with GitHub.
+            None => {
+                query_scalar!(
+                    r#"
+                    INSERT INTO users (gh_email, gh_login, email_verified)
+                    VALUES ($1, $1, true)
+                    RETURNING id
+                    "#,
+                    email,
+                )
+                .fetch_one(&mut tx)
+                .await?
+            }
+        };
+
+        query!(
+            r#"
+            INSERT INTO org_members (org_id, user_id, given_name, family_name, role)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (org_id, user_id) DO UPDATE
+                SET given_name = coalesce(EXCLUDED.given_name, org_members.given_name),
+                    family_name = coalesce(EXCLUDED.family_name, org_members.family_name),
+                    role = EXCLUDED.role,
+                    update_dt = current_timestamp
+            "#,
+            self.org_id,
+            user_id,
+            self.given_name,
+            self.family_name,
+            self.role.as_str(),
+        )
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(user_id)
+    }
+}
@@ -0,0 +1,317 @@
+//! The public status feed, and the incidents admins post to it. See [`crate::status`].
+use crate::middlewares::auth::Auth;
+use crate::models::status::{
+    Incident, IncidentInsert, IncidentNote, IncidentNoteInsert, StatusError, StatusFeed,
+    INCIDENT_HISTORY_DAYS, INCIDENT_STATUSES,
+};
+use crate::models::SqlDateTime;
+use crate::persisters::user::{is_admin, user_id};
+use crate::persisters::{Persist, Query};
+use crate::state::State;
+use crate::status::Health;
+use sqlx::types::Uuid;
+
+/// The admin's user id.
+async fn require_admin(auth: Option<&Auth>, state: &State) -> Result<Uuid, StatusError> {
+    let auth = auth.ok_or(StatusError::Unauthorized)?;
+
+    if !is_admin(auth, state).await? {
+        return Err(StatusError::Forbidden);
+    }
+
+    Ok(user_id(auth, state).await?)
+}
+
+fn check_status(status: &str) -> Result<(), StatusError> {
+    match INCIDENT_STATUSES.contains(&status) {
+        true => Ok(()),
+        false => Err(StatusError::InvalidIncident),
+    }
+}
+
+fn check_impact(impact: Health) -> Result<(), StatusError> {
+    match impact {
+        Health::Operational => Err(StatusError::InvalidIncident),
+        _ => Ok(()),
+    }
+}
+
+fn default_limit() -> i64 {
+    100
+}
+
+/// The status feed. Anyone can read it, without authenticating.
+pub struct StatusGet {}
+
+/// Lists incidents, most recent first, for admins.
+#[derive(Deserialize, Debug)]
+pub struct IncidentsGet {
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+/// Posts a note on an incident.
+pub struct IncidentNotePost {
+    pub id: i64,
+    pub note: IncidentNoteInsert,
+}
+
+/// Deletes an incident, e.g. one posted by mistake, along with its notes.
+pub struct IncidentDelete {
+    pub id: i64,
+}
+
+struct IncidentRow {
+    id: i64,
+    title: String,
+    components: Vec<String>,
+    impact: String,
+    status: String,
+    create_dt: SqlDateTime,
+    update_dt: SqlDateTime,
+    resolve_dt: Option<SqlDateTime>,
+}
+
+/// The incidents with their notes.
+async fn with_notes(state: &State, rows: Vec<IncidentRow>) -> Result<Vec<Incident>, sqlx::Error> {
+    let ids: Vec<i64> = rows.iter().map(|r| r.id).collect();
+    let notes = query_as!(
+        IncidentNote,
+        r#"
+        SELECT incident_id, status, message, create_dt
+        FROM status_incident_notes
+        WHERE incident_id = ANY($1)
+        ORDER BY create_dt DESC, id DESC
+        "#,
+        &ids,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    let incidents = rows
+        .into_iter()
+        .map(|row| Incident {
+            notes: notes
+                .iter()
+                .filter(|n| n.incident_id == row.id)
+                .cloned()
+                .collect(),
+            id: row.id,
+            title: row.title,
+            components: row.components,
+            impact: row.impact,
+            status: row.status,
+            create_dt: row.create_dt,
+            update_dt: row.update_dt,
+            resolve_dt: row.resolve_dt,
+        })
+        .collect();
+
+    Ok(incidents)
+}
+
+async fn fetch(state: &State, id: i64) -> Result<Incident, StatusError> {
+    let row = query_as!(
+        IncidentRow,
+        r#"
+        SELECT id, title, components, impact, status, create_dt, update_dt, resolve_dt
+        FROM status_incidents
+        WHERE id = $1
+        "#,
+        id,
+    )
+    .fetch_one(&state.db_conn)
+    .await?;
+
+    let mut incidents = with_notes(state, vec![row]).await?;
+    Ok(incidents.remove(0))
+}
+
+/// Unresolved incidents, and those resolved within the feed's history.
+async fn recent(state: &State) -> Result<Vec<Incident>, sqlx::Error> {
+    let rows = query_as!(
+        IncidentRow,
+        r#"
+        SELECT id, title, components, impact, status, create_dt, update_dt, resolve_dt
+        FROM status_incidents
+        WHERE resolve_dt IS NULL
+            OR resolve_dt > now() - make_interval(days => $1)
+        ORDER BY create_dt DESC
+        "#,
+        INCIDENT_HISTORY_DAYS,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    with_notes(state, rows).await
+}
+
+#[async_trait]
+impl Query for StatusGet {
+    type Resolve = StatusFeed;
+    type Error = StatusError;
+
+    /// The feed is served even when the database is down, without its incidents, since that's
+    /// when it's needed most.
+    async fn fetch(
+        self,
+        _auth: Option<&Auth>,
+        state: &State,
+    ) -> Result<Self::Resolve, Self::Error> {
+        let incidents = match recent(state).await {
+            Ok(incidents) => incidents,
+            Err(e) => {
+                log::warn!("error listing incidents for the status feed: {:?}", e);
+                vec![]
+            }
+        };
+
+        Ok(StatusFeed::new(state.status.components(), incidents))
+    }
+}
+
+#[async_trait]
+impl Query for IncidentsGet {
+    type Resolve = Vec<Incident>;
+    type Error = StatusError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        require_admin(auth, state).await?;
+
+        let rows = query_as!(
+            IncidentRow,
+            r#"
+            SELECT id, title, components, impact, status, create_dt, update_dt, resolve_dt
+            FROM status_incidents
+            ORDER BY create_dt DESC
+            LIMIT $1
+            "#,
+            self.limit,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(with_notes(state, rows).await?)
+    }
+}
+
+#[async_trait]
+impl Persist for IncidentInsert {
+    type Ret = Incident;
+    type Error = StatusError;
+
+    /// Posts the incident, with its message as its first note.
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let admin_id = require_admin(auth, state).await?;
+        check_status(&self.status)?;
+        check_impact(self.impact)?;
+        if self.components.is_empty() {
+            return Err(StatusError::InvalidIncident);
+        }
+        let components: Vec<String> = self
+            .components
+            .iter()
+            .map(|c| c.as_str().to_string())
+            .collect();
+
+        let mut tx = state.db_conn.begin().await?;
+        let id = query_scalar!(
+            r#"
+            INSERT INTO status_incidents (title, components, impact, status, user_id, resolve_dt)
+            VALUES ($1, $2, $3, $4, $5, CASE WHEN $4::varchar = 'resolved' THEN now() END)
+            RETURNING id
+            "#,
+            self.title,
+            &components,
+            self.impact.as_str(),
+            self.status,
+            admin_id,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        query!(
+            r#"
+            INSERT INTO status_incident_notes (incident_id, status, message, user_id)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            id,
+            self.status,
+            self.message,
+            admin_id,
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        fetch(state, id).await
+    }
+}
+
+#[async_trait]
+impl Persist for IncidentNotePost {
+    type Ret = Incident;
+    type Error = StatusError;
+
+    /// Moves the incident to the note's status and impact. A resolved incident which is given
+    /// another status is reopened.
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let admin_id = require_admin(auth, state).await?;
+        check_status(&self.note.status)?;
+        if let Some(impact) = self.note.impact {
+            check_impact(impact)?;
+        }
+
+        let mut tx = state.db_conn.begin().await?;
+        query_scalar!(
+            r#"
+            UPDATE status_incidents
+            SET status = $2::varchar,
+                impact = coalesce($3, impact),
+                update_dt = now(),
+                resolve_dt = CASE WHEN $2::varchar = 'resolved' THEN coalesce(resolve_dt, now()) END
+            WHERE id = $1
+            RETURNING id
+            "#,
+            self.id,
+            self.note.status,
+            self.note.impact.map(|i| i.as_str()),
+        )
+        .fetch_one(&mut tx)
+        .await?;
+        query!(
+            r#"
+            INSERT INTO status_incident_notes (incident_id, status, message, user_id)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            self.id,
+            self.note.status,
+            self.note.message,
+            admin_id,
+        )
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+
+        fetch(state, self.id).await
+    }
+}
+
+#[async_trait]
+impl Persist for IncidentDelete {
+    type Ret = ();
+    type Error = StatusError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        require_admin(auth, state).await?;
+
+        let deleted = query!("DELETE FROM status_incidents WHERE id = $1", self.id)
+            .execute(&state.db_conn)
+            .await?
+            .rows_affected();
+        if deleted == 0 {
+            return Err(StatusError::NotFound);
+        }
+
+        Ok(())
+    }
+}
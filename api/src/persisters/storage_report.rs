@@ -0,0 +1,188 @@
+use crate::middlewares::auth::Auth;
+use crate::models::storage_report::{
+    OrgStorageReport, StorageInventory, StorageReport, StorageReportError, INVENTORY_LOCK,
+};
+use crate::persisters::user::is_admin;
+use crate::persisters::{Persist, Query};
+use crate::state::State;
+
+async fn require_admin(auth: Option<&Auth>, state: &State) -> Result<(), StorageReportError> {
+    let auth = auth.ok_or(StorageReportError::Unauthorized)?;
+
+    if !is_admin(auth, state).await? {
+        return Err(StorageReportError::Forbidden);
+    }
+
+    Ok(())
+}
+
+/// Whether an inventory is being taken, periodically or by an admin.
+async fn is_running(state: &State) -> Result<bool, sqlx::Error> {
+    query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM pg_locks
+            WHERE locktype = 'advisory'
+                AND objsubid = 1
+                AND (classid::bigint << 32) | objid::bigint = $1
+        ) AS "running!"
+        "#,
+        INVENTORY_LOCK,
+    )
+    .fetch_one(&state.db_conn)
+    .await
+}
+
+fn default_limit() -> i64 {
+    100
+}
+
+/// Checks that an inventory can be taken. The inventory itself is taken by the caller, in the
+/// background.
+pub struct InventoryStart {}
+
+/// Lists inventories, most recent first.
+#[derive(Deserialize, Debug)]
+pub struct InventoriesGet {
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ReportParams {
+    /// Only list reports where what was found differs from what's recorded.
+    #[serde(default)]
+    pub drifted: bool,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+/// Lists an inventory's report for each user, those which have drifted furthest first.
+pub struct StorageReportsGet {
+    pub inventory_id: i64,
+    pub params: ReportParams,
+}
+
+/// Lists an inventory's report for each org, as [`StorageReportsGet`] does for users.
+pub struct OrgStorageReportsGet {
+    pub inventory_id: i64,
+    pub params: ReportParams,
+}
+
+/// Checks that the inventory exists, so that one without reports isn't mistaken for one which
+/// found no drift.
+async fn require_inventory(state: &State, id: i64) -> Result<(), StorageReportError> {
+    query_scalar!("SELECT id FROM storage_inventories WHERE id = $1", id)
+        .fetch_one(&state.db_conn)
+        .await?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl Persist for InventoryStart {
+    type Ret = ();
+    type Error = StorageReportError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        require_admin(auth, state).await?;
+
+        if is_running(state).await? {
+            return Err(StorageReportError::AlreadyRunning);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Query for InventoriesGet {
+    type Resolve = Vec<StorageInventory>;
+    type Error = StorageReportError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        require_admin(auth, state).await?;
+
+        let res = query_as!(
+            StorageInventory,
+            r#"
+            SELECT id, status, objects, bytes, unowned_objects, unowned_bytes, error, start_dt,
+                finish_dt
+            FROM storage_inventories
+            ORDER BY start_dt DESC
+            LIMIT $1
+            "#,
+            self.limit,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for StorageReportsGet {
+    type Resolve = Vec<StorageReport>;
+    type Error = StorageReportError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        require_admin(auth, state).await?;
+        require_inventory(state, self.inventory_id).await?;
+
+        let res = query_as!(
+            StorageReport,
+            r#"
+            SELECT user_id, expected_objects, expected_bytes, actual_bytes, missing_objects,
+                mismatched_objects
+            FROM storage_reports
+            WHERE inventory_id = $1
+                AND (NOT $2 OR expected_bytes <> actual_bytes OR missing_objects > 0
+                    OR mismatched_objects > 0)
+            ORDER BY abs(expected_bytes - actual_bytes) DESC, user_id
+            LIMIT $3
+            "#,
+            self.inventory_id,
+            self.params.drifted,
+            self.params.limit,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Query for OrgStorageReportsGet {
+    type Resolve = Vec<OrgStorageReport>;
+    type Error = StorageReportError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        require_admin(auth, state).await?;
+        require_inventory(state, self.inventory_id).await?;
+
+        let res = query_as!(
+            OrgStorageReport,
+            r#"
+            SELECT o.id AS org_id, o.name, o.owner_id, r.expected_objects, r.expected_bytes,
+                r.actual_bytes, r.missing_objects, r.mismatched_objects
+            FROM orgs o
+            JOIN storage_reports r
+                ON r.user_id = o.owner_id
+            WHERE r.inventory_id = $1
+                AND (NOT $2 OR r.expected_bytes <> r.actual_bytes OR r.missing_objects > 0
+                    OR r.mismatched_objects > 0)
+            ORDER BY abs(r.expected_bytes - r.actual_bytes) DESC, o.id
+            LIMIT $3
+            "#,
+            self.inventory_id,
+            self.params.drifted,
+            self.params.limit,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
@@ -0,0 +1,167 @@
+use crate::middlewares::auth::Auth;
+use crate::models::topology::{DeploymentRegion, Route, TopologyError};
+use crate::persisters::user::is_admin;
+use crate::persisters::{Persist, Query};
+use crate::state::State;
+
+async fn require_admin(auth: Option<&Auth>, state: &State) -> Result<(), TopologyError> {
+    let auth = auth.ok_or(TopologyError::Unauthorized)?;
+
+    if !is_admin(auth, state).await? {
+        return Err(TopologyError::Forbidden);
+    }
+
+    Ok(())
+}
+
+/// Routes a client to the region its BLOB requests should go to. `region` is the client's own
+/// region, e.g. `eu-west-2`, if it knows it.
+#[derive(Deserialize, Debug)]
+pub struct RouteGet {
+    pub region: Option<String>,
+}
+
+/// Lists the regions of the deployment.
+pub struct RegionsGet;
+
+/// Adds a region to the deployment, or updates it. Making a region the default stops any other
+/// from being the default.
+#[derive(Deserialize, Debug)]
+pub struct RegionUpsert {
+    #[serde(skip)]
+    pub name: String,
+    pub blob_endpoint: String,
+    pub bucket: String,
+    #[serde(default)]
+    pub serves: Vec<String>,
+    #[serde(default)]
+    pub is_default: bool,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Removes a region from the deployment.
+pub struct RegionDelete {
+    pub name: String,
+}
+
+async fn regions(state: &State) -> Result<Vec<DeploymentRegion>, sqlx::Error> {
+    query_as!(
+        DeploymentRegion,
+        r#"
+        SELECT name, blob_endpoint, bucket, serves, is_default, enabled, update_dt
+        FROM deployment_regions
+        ORDER BY name
+        "#,
+    )
+    .fetch_all(&state.db_conn)
+    .await
+}
+
+#[async_trait]
+impl Query for RouteGet {
+    type Resolve = Route;
+    type Error = TopologyError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        auth.ok_or(TopologyError::Unauthorized)?;
+
+        let regions = regions(state).await?;
+        Route::choose(&regions, self.region.as_deref()).ok_or(TopologyError::NotConfigured)
+    }
+}
+
+#[async_trait]
+impl Query for RegionsGet {
+    type Resolve = Vec<DeploymentRegion>;
+    type Error = TopologyError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        require_admin(auth, state).await?;
+        Ok(regions(state).await?)
+    }
+}
+
+#[async_trait]
+impl Persist for RegionUpsert {
+    type Ret = DeploymentRegion;
+    type Error = TopologyError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        require_admin(auth, state).await?;
+
+        if self.name.is_empty()
+            || self.bucket.is_empty()
+            || url::Url::parse(&self.blob_endpoint).is_err()
+        {
+            return Err(TopologyError::InvalidRegion);
+        }
+
+        let mut tx = state.db_conn.begin().await?;
+
+        if self.is_default {
+            query!(
+                r#"
+                UPDATE deployment_regions
+                SET is_default = false, update_dt = current_timestamp
+                WHERE is_default AND name <> $1
+                "#,
+                self.name,
+            )
+            .execute(&mut tx)
+            .await?;
+        }
+
+        let res = query_as!(
+            DeploymentRegion,
+            r#"
+            INSERT INTO deployment_regions (name, blob_endpoint, bucket, serves, is_default, enabled)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (name) DO UPDATE
+                SET blob_endpoint = EXCLUDED.blob_endpoint,
+                    bucket = EXCLUDED.bucket,
+                    serves = EXCLUDED.serves,
+                    is_default = EXCLUDED.is_default,
+                    enabled = EXCLUDED.enabled,
+                    update_dt = current_timestamp
+            RETURNING name, blob_endpoint, bucket, serves, is_default, enabled, update_dt
+            "#,
+            self.name,
+            self.blob_endpoint,
+            self.bucket,
+            &self.serves,
+            self.is_default,
+            self.enabled,
+        )
+        .fetch_one(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Persist for RegionDelete {
+    type Ret = ();
+    type Error = TopologyError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        require_admin(auth, state).await?;
+
+        let res = query!("DELETE FROM deployment_regions WHERE name = $1", self.name)
+            .execute(&state.db_conn)
+            .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(TopologyError::NotFound);
+        }
+
+        Ok(())
+    }
+}
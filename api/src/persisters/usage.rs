@@ -0,0 +1,207 @@
+use crate::metering::{within_quota, TierBytes, TierWeights};
+use crate::middlewares::auth::Auth;
+use crate::models::usage::{
+    DedupReport, DuplicatedFn, StorageUsage, UsageError, MAX_DEDUP_FUNCTIONS,
+};
+use crate::models::SqlDateTime;
+use crate::persisters::user::user_id;
+use crate::persisters::Query;
+use crate::state::State;
+use sqlx::types::Uuid;
+
+/// The authenticated user's stored bytes, by storage tier, as of the latest count.
+pub struct StorageUsageGet {}
+
+fn default_dedup_limit() -> i64 {
+    20
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DedupParams {
+    /// Only count computations started at or after this.
+    #[serde(default)]
+    pub since: Option<SqlDateTime>,
+    #[serde(default = "default_dedup_limit")]
+    pub limit: i64,
+}
+
+/// Reports computation duplicated across the members of one of the authenticated user's orgs,
+/// found by its external id. Its members are its owner, whose evals include those of its service
+/// accounts, and its active members.
+pub struct DedupReportGet {
+    pub org: String,
+    pub params: DedupParams,
+}
+
+/// The user's stored bytes, by storage tier, as of the latest count, and the start of the hour
+/// they were counted in, if they have been.
+pub async fn latest_usage(
+    state: &State,
+    user_id: Uuid,
+) -> Result<(Option<SqlDateTime>, TierBytes), sqlx::Error> {
+    let rows = query!(
+        r#"
+        SELECT period_start, tier, bytes
+        FROM storage_usage
+        WHERE user_id = $1
+            AND period_start = (
+                SELECT max(period_start) FROM storage_usage
+                WHERE user_id = $1
+            )
+        "#,
+        user_id,
+    )
+    .fetch_all(&state.db_conn)
+    .await?;
+
+    let period_start = rows.first().map(|r| r.period_start);
+    let mut tiers = TierBytes::default();
+    for row in rows {
+        if let Ok(tier) = row.tier.parse() {
+            tiers.0.insert(tier, row.bytes);
+        }
+    }
+
+    Ok((period_start, tiers))
+}
+
+/// Whether the user can store `content_length` more bytes and stay within the storage quota, if
+/// there is one.
+///
+/// Stored bytes are only counted hourly, so BLOBs recorded since the start of the hour of the
+/// latest count are added to it, as hot bytes, along with unconfirmed uploads straight to S3, so
+/// that a user can't start several uploads which are each within the quota. BLOBs recorded in the
+/// hour before the count was taken are counted twice until the next one, erring towards the quota.
+pub async fn within_storage_quota(
+    state: &State,
+    user_id: Uuid,
+    content_length: i64,
+) -> Result<bool, sqlx::Error> {
+    let quota = match state.config.storage_quota_bytes {
+        Some(quota) => quota,
+        None => return Ok(true),
+    };
+
+    let (period_start, tiers) = latest_usage(state, user_id).await?;
+    let uncounted = query_scalar!(
+        r#"
+        SELECT (
+            SELECT coalesce(sum(content_length), 0)::bigint
+            FROM blobs
+            WHERE user_id = $1
+                AND (create_dt >= $2 OR $2::timestamptz IS NULL)
+        ) + (
+            SELECT coalesce(sum(content_length), 0)::bigint
+            FROM presigned_uploads
+            WHERE user_id = $1
+                AND expire_dt > now()
+        ) AS "bytes!"
+        "#,
+        user_id,
+        period_start,
+    )
+    .fetch_one(&state.db_conn)
+    .await?;
+
+    let weights = TierWeights::from_config(&state.config);
+    Ok(within_quota(
+        tiers.billable(&weights),
+        uncounted.saturating_add(content_length),
+        &weights,
+        quota,
+    ))
+}
+
+#[async_trait]
+impl Query for StorageUsageGet {
+    type Resolve = StorageUsage;
+    type Error = UsageError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(UsageError::Unauthorized)?;
+        let user_id = user_id(auth, state).await?;
+
+        let (period_start, tiers) = latest_usage(state, user_id).await?;
+        let weights = TierWeights::from_config(&state.config);
+
+        Ok(StorageUsage {
+            period_start,
+            total_bytes: tiers.total(),
+            billable_bytes: tiers.billable(&weights),
+            tiers,
+            weights,
+        })
+    }
+}
+
+#[async_trait]
+impl Query for DedupReportGet {
+    type Resolve = DedupReport;
+    type Error = UsageError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(UsageError::Unauthorized)?;
+
+        let org_id = query_scalar!(
+            r#"
+            SELECT id
+            FROM orgs
+            WHERE owner_id = get_user_id($1, $2)
+                AND external_id = $3
+            "#,
+            auth.jwt().map(|c| c.sub),
+            auth.api_key(),
+            self.org,
+        )
+        .fetch_optional(&state.db_conn)
+        .await?
+        .ok_or(UsageError::NotFound)?;
+
+        // Each member's first computation of a call is counted, since their own cache serves them
+        // after that. Of the members who computed the same call, whoever computed it first would
+        // have served the others, had they shared a cache.
+        let functions = query_as!(
+            DuplicatedFn,
+            r#"
+            WITH team AS (
+                SELECT owner_id AS user_id FROM orgs WHERE id = $1
+                UNION
+                SELECT user_id FROM org_members WHERE org_id = $1 AND active AND accepted
+            ), computed AS (
+                SELECT DISTINCT ON (e.fn_key, e.fn_hash, e.args_hash, e.user_id)
+                    e.fn_key, e.fn_hash, e.args_hash, e.start_time, e.elapsed_process_time
+                FROM evals e
+                JOIN team t ON t.user_id = e.user_id
+                WHERE e.start_time >= $2 OR $2::timestamptz IS NULL
+                ORDER BY e.fn_key, e.fn_hash, e.args_hash, e.user_id, e.start_time
+            ), duplicated AS (
+                SELECT fn_key, count(*) - 1 AS duplicates,
+                    sum(elapsed_process_time)
+                        - (array_agg(elapsed_process_time ORDER BY start_time))[1] AS saved
+                FROM computed
+                GROUP BY fn_key, fn_hash, args_hash
+                HAVING count(*) > 1
+            )
+            SELECT fn_key AS "fn_key!",
+                count(*) AS "calls!",
+                sum(duplicates)::bigint AS "duplicate_evals!",
+                sum(saved)::bigint AS "saved_process_time!"
+            FROM duplicated
+            GROUP BY fn_key
+            ORDER BY sum(saved) DESC, fn_key
+            "#,
+            org_id,
+            self.params.since,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        let limit = self.params.limit.clamp(1, MAX_DEDUP_FUNCTIONS) as usize;
+        Ok(DedupReport::new(
+            org_id,
+            self.params.since,
+            functions,
+            limit,
+        ))
+    }
+}
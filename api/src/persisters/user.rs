@@ -1,13 +1,43 @@
 use crate::middlewares::auth::Auth;
-use crate::models::user::User;
+use crate::models::user::{normalize_email, OrgInvite, Preferences, User};
 use crate::persisters::{Persist, Query};
+use crate::policy::{self, Action, PolicyError, Request};
 use crate::state::State;
 
 use sqlx::{types::Uuid, Error};
 
+/// Whether the authenticated user is an admin of the HitSave deployment.
+pub async fn is_admin(auth: &Auth, state: &State) -> Result<bool, sqlx::Error> {
+    let is_admin = query_scalar!(
+        "SELECT is_admin FROM users WHERE id = get_user_id($1, $2)",
+        auth.jwt().map(|c| c.sub),
+        auth.api_key(),
+    )
+    .fetch_optional(&state.db_conn)
+    .await?;
+
+    Ok(is_admin == Some(true))
+}
+
+/// The id of the authenticated user.
+pub async fn user_id(auth: &Auth, state: &State) -> Result<Uuid, sqlx::Error> {
+    let user_id = query_scalar!(
+        r#"SELECT get_user_id($1, $2) AS "user_id!""#,
+        auth.jwt().map(|c| c.sub),
+        auth.api_key(),
+    )
+    .fetch_one(&state.db_conn)
+    .await?;
+
+    Ok(user_id)
+}
+
 #[derive(Debug)]
 pub enum UserUpsertError {
     AlreadyExists,
+    /// The email address belongs to another account, e.g. one an org created for its SSO or
+    /// SCIM, which signing in with a GitHub account with the same address can't take over.
+    EmailInUse,
     /// This is used when the upsert query returns no rows. If the query is written correctly, this
     /// should never happen, because we either return the row that got inserted, or the one which
     /// is already there. In theory, this error is unreachable, but we want to handle it just in
@@ -28,8 +58,27 @@ pub struct UserUpsert {
 
 pub struct UserGet {}
 
+/// The authenticated user's preferences.
+pub struct PreferencesGet {}
+
+/// The memberships orgs have provisioned for the authenticated user's account which wait for them
+/// to accept them.
+pub struct OrgInvitesGet {}
+
+/// Accepts an org's membership of the authenticated user's account.
+pub struct OrgInviteAccept {
+    pub org_id: Uuid,
+}
+
+/// Declines an org's membership of the authenticated user's account, removing it.
+pub struct OrgInviteDecline {
+    pub org_id: Uuid,
+}
+
 pub enum UserGetError {
     Unauthorized,
+    Forbidden,
+    NotFound,
     Sqlx(sqlx::Error),
 }
 
@@ -39,16 +88,25 @@ impl From<sqlx::Error> for UserGetError {
     }
 }
 
+impl From<PolicyError> for UserGetError {
+    fn from(e: PolicyError) -> Self {
+        match e {
+            PolicyError::Sqlx(e) => Self::Sqlx(e),
+            _ => Self::Forbidden,
+        }
+    }
+}
+
 #[async_trait]
 impl Query for UserGet {
     type Resolve = User;
     type Error = UserGetError;
 
     async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
-        let jwt = auth
-            .ok_or(UserGetError::Unauthorized)?
-            .allow_only_jwt()
-            .map_err(|_| UserGetError::Unauthorized)?;
+        let auth = auth.ok_or(UserGetError::Unauthorized)?;
+        policy::authorize(auth, Request::new(Action::UserRead), state).await?;
+        // The built-in policy only lets signed in users read their account.
+        let jwt = auth.jwt().ok_or(UserGetError::Unauthorized)?;
 
         let res = query_as!(
             User,
@@ -66,32 +124,202 @@ impl Query for UserGet {
     }
 }
 
+#[async_trait]
+impl Query for PreferencesGet {
+    type Resolve = Preferences;
+    type Error = UserGetError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(UserGetError::Unauthorized)?;
+        let user_id = user_id(auth, state).await?;
+
+        let res = query_as!(
+            Preferences,
+            r#"
+            SELECT coalesce(
+                (SELECT digest_emails FROM user_preferences WHERE user_id = $1),
+                true
+            ) AS "digest_emails!"
+            "#,
+            user_id,
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+/// Replaces the authenticated user's preferences.
+#[async_trait]
+impl Persist for Preferences {
+    type Ret = Preferences;
+    type Error = UserGetError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(UserGetError::Unauthorized)?;
+        let user_id = user_id(auth, state).await?;
+
+        query!(
+            r#"
+            INSERT INTO user_preferences (user_id, digest_emails)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE
+                SET digest_emails = EXCLUDED.digest_emails,
+                    update_dt = current_timestamp
+            "#,
+            user_id,
+            self.digest_emails,
+        )
+        .execute(&state.db_conn)
+        .await?;
+
+        Ok(self)
+    }
+}
+
+#[async_trait]
+impl Query for OrgInvitesGet {
+    type Resolve = Vec<OrgInvite>;
+    type Error = UserGetError;
+
+    async fn fetch(self, auth: Option<&Auth>, state: &State) -> Result<Self::Resolve, Self::Error> {
+        let auth = auth.ok_or(UserGetError::Unauthorized)?;
+        let user_id = user_id(auth, state).await?;
+
+        let res = query_as!(
+            OrgInvite,
+            r#"
+            SELECT m.org_id, o.name AS org_name, m.create_dt
+            FROM org_members m
+            JOIN orgs o
+                ON o.id = m.org_id
+            WHERE m.user_id = $1
+                AND NOT m.accepted
+            ORDER BY m.create_dt, m.org_id
+            "#,
+            user_id,
+        )
+        .fetch_all(&state.db_conn)
+        .await?;
+
+        Ok(res)
+    }
+}
+
+#[async_trait]
+impl Persist for OrgInviteAccept {
+    type Ret = ();
+    type Error = UserGetError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(UserGetError::Unauthorized)?;
+        // Only the user themselves, signed in, can bring their account into an org, not any of
+        // their keys.
+        let jwt = auth.jwt().ok_or(UserGetError::Unauthorized)?;
+
+        let res = query!(
+            r#"
+            UPDATE org_members
+            SET accepted = true, update_dt = current_timestamp
+            WHERE org_id = $1
+                AND user_id = $2
+                AND NOT accepted
+            "#,
+            self.org_id,
+            &jwt.sub,
+        )
+        .execute(&state.db_conn)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(UserGetError::NotFound);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Persist for OrgInviteDecline {
+    type Ret = ();
+    type Error = UserGetError;
+
+    async fn persist(self, auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let auth = auth.ok_or(UserGetError::Unauthorized)?;
+        let user_id = user_id(auth, state).await?;
+
+        // The org's groups lose the user with the membership.
+        let res = query!(
+            r#"
+            DELETE FROM org_members
+            WHERE org_id = $1
+                AND user_id = $2
+                AND NOT accepted
+            "#,
+            self.org_id,
+            user_id,
+        )
+        .execute(&state.db_conn)
+        .await?;
+
+        if res.rows_affected() == 0 {
+            return Err(UserGetError::NotFound);
+        }
+
+        Ok(())
+    }
+}
+
 #[async_trait]
 impl Persist for UserUpsert {
     type Ret = Uuid;
     type Error = UserUpsertError;
 
     async fn persist(self, _auth: Option<&Auth>, state: &State) -> Result<Self::Ret, Self::Error> {
+        let email = normalize_email(&self.gh_email);
+
+        // Accounts are never linked by their email addresses, so a GitHub account which has no
+        // account yet can't have one with an address which is already in use. Addresses are
+        // compared as their unique index compares them. Users who lost theirs to another user's
+        // differing only by case are found by their GitHub account, so still sign in.
+        let email_in_use = query_scalar!(
+            r#"
+            SELECT EXISTS (SELECT 1 FROM users WHERE lower(gh_email) = lower($2))
+                AND NOT EXISTS (SELECT 1 FROM users WHERE gh_id = $1) AS "exists!"
+            "#,
+            &self.gh_id,
+            &email,
+        )
+        .fetch_one(&state.db_conn)
+        .await?;
+        if email_in_use {
+            return Err(UserUpsertError::EmailInUse);
+        }
+
         let res = query_as!(
             UpsertResult,
             r#"WITH e AS(
                   INSERT INTO users (gh_id, gh_email, gh_login, gh_token, gh_avatar_url, email_verified) 
                          VALUES ($1, $2, $3, $4, $5, $6)
-                  ON CONFLICT(gh_id) DO NOTHING
+                  ON CONFLICT DO NOTHING
                   RETURNING id
                )
                SELECT id FROM e UNION
                SELECT id FROM users WHERE gh_id = $1;"#,
             &self.gh_id,
-            &self.gh_email,
+            email,
             &self.gh_login,
             &self.gh_token,
             &self.gh_avatar_url,
             &self.email_verified,
         )
-        .fetch_one(&state.db_conn)
+        .fetch_optional(&state.db_conn)
         .await
-        .inspect_err(|e| error!("error inserting user: {:?}", e))?;
+        .inspect_err(|e| error!("error inserting user: {:?}", e))?
+        // Nothing conflicted on the GitHub account, so the address was taken since it was
+        // checked.
+        .ok_or(UserUpsertError::EmailInUse)?;
 
         let uuid = res.id.ok_or(UserUpsertError::Unreachable)?;
 
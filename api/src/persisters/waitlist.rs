@@ -1,5 +1,7 @@
+use crate::error_code::{coded, ErrorCode};
 use crate::handlers::waitlist::WaitlistInsert;
 use crate::middlewares::auth::Auth;
+use crate::models::user::normalize_email;
 use crate::persisters::Persist;
 use crate::state::State;
 
@@ -15,7 +17,7 @@ impl From<WaitlistInsertError> for actix_web::Error {
     fn from(e: WaitlistInsertError) -> Self {
         match e {
             WaitlistInsertError::AlreadyExists => {
-                actix_web::error::ErrorConflict("Already on waitlist.")
+                coded(ErrorCode::AlreadyOnWaitlist, "Already on waitlist.")
             }
             WaitlistInsertError::Sqlx(e) => {
                 log::error!("error inserting to waitlist: {:?}", e);
@@ -37,7 +39,7 @@ impl Persist for WaitlistInsert {
               VALUES ($1)
               RETURNING id
             "#,
-            &self.email
+            normalize_email(&self.email),
         )
         .fetch_one(&state.db_conn)
         .await?;
@@ -0,0 +1,382 @@
+//! Authorization policies, consulted by persisters before they act on behalf of a principal.
+//!
+//! A policy is a list of rules, each of which allows or forbids a set of actions to a set of
+//! principals, optionally restricted to some projects. A request is authorized when at least one
+//! rule allows it and none forbids it. The built-in rules apply to everyone; an org can add its
+//! own rules, which apply to its members and service accounts.
+use crate::middlewares::auth::Auth;
+use crate::models::sso::Role;
+use crate::state::State;
+use sqlx::types::{JsonValue, Uuid};
+
+/// Something a principal can do.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    #[serde(rename = "user:read")]
+    UserRead,
+    #[serde(rename = "api_key:create")]
+    ApiKeyCreate,
+    #[serde(rename = "api_key:revoke")]
+    ApiKeyRevoke,
+    #[serde(rename = "eval:read")]
+    EvalRead,
+    #[serde(rename = "eval:write")]
+    EvalWrite,
+    #[serde(rename = "run:read")]
+    RunRead,
+    #[serde(rename = "run:write")]
+    RunWrite,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::UserRead => "user:read",
+            Action::ApiKeyCreate => "api_key:create",
+            Action::ApiKeyRevoke => "api_key:revoke",
+            Action::EvalRead => "eval:read",
+            Action::EvalWrite => "eval:write",
+            Action::RunRead => "run:read",
+            Action::RunWrite => "run:write",
+        }
+    }
+}
+
+/// How a principal authenticated.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PrincipalKind {
+    /// A signed in user, authenticated with a JWT.
+    Session,
+    /// A user's own API key.
+    ApiKey,
+    /// A key of an org's service account.
+    ServiceAccount,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Effect {
+    Allow,
+    Forbid,
+}
+
+/// A policy rule. Empty lists match anything.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub effect: Effect,
+    #[serde(default)]
+    pub principals: Vec<PrincipalKind>,
+    /// The roles, within the org, of the principals the rule applies to. Service accounts have
+    /// no role, so rules listing roles never apply to them.
+    #[serde(default)]
+    pub roles: Vec<Role>,
+    /// Action patterns: an action (`eval:write`), all actions on a resource (`eval:*`), or `*`.
+    pub actions: Vec<String>,
+    /// The names of the projects the rule applies to.
+    #[serde(default)]
+    pub projects: Vec<String>,
+}
+
+/// The principal making a request, in the context of one org.
+#[derive(Debug, Clone, Copy)]
+pub struct Principal {
+    pub kind: PrincipalKind,
+    pub role: Option<Role>,
+}
+
+/// What a principal asks to do.
+#[derive(Debug, Clone, Copy)]
+pub struct Request<'a> {
+    pub action: Action,
+    /// The project acted on, when there is one.
+    pub project: Option<&'a str>,
+}
+
+impl<'a> Request<'a> {
+    pub fn new(action: Action) -> Self {
+        Self {
+            action,
+            project: None,
+        }
+    }
+
+    pub fn in_project(action: Action, project: Option<&'a str>) -> Self {
+        Self { action, project }
+    }
+}
+
+fn action_matches(pattern: &str, action: Action) -> bool {
+    let action = action.as_str();
+    match pattern.strip_suffix('*') {
+        Some(prefix) => action.starts_with(prefix),
+        None => pattern == action,
+    }
+}
+
+impl Rule {
+    fn matches(&self, principal: &Principal, request: &Request) -> bool {
+        (self.principals.is_empty() || self.principals.contains(&principal.kind))
+            && (self.roles.is_empty() || principal.role.map_or(false, |r| self.roles.contains(&r)))
+            && self
+                .actions
+                .iter()
+                .any(|a| action_matches(a, request.action))
+            && (self.projects.is_empty()
+                || request
+                    .project
+                    .map_or(false, |p| self.projects.iter().any(|q| q == p)))
+    }
+
+    fn new(effect: Effect, principals: &[PrincipalKind], actions: &[&str]) -> Self {
+        Self {
+            effect,
+            principals: principals.to_vec(),
+            roles: vec![],
+            actions: actions.iter().map(|a| a.to_string()).collect(),
+            projects: vec![],
+        }
+    }
+}
+
+lazy_static! {
+    /// The rules which apply to everyone. Accounts and keys are managed from a signed in session,
+    /// and evals and runs are recorded by clients using keys.
+    pub static ref DEFAULT_RULES: Vec<Rule> = vec![
+        Rule::new(Effect::Allow, &[], &["*"]),
+        Rule::new(
+            Effect::Forbid,
+            &[PrincipalKind::ApiKey, PrincipalKind::ServiceAccount],
+            &["user:read", "api_key:create", "api_key:revoke"],
+        ),
+        Rule::new(
+            Effect::Forbid,
+            &[PrincipalKind::Session],
+            &["eval:write", "run:write"],
+        ),
+    ];
+}
+
+/// Whether a request is within the scope of a key exchanged for one project: a scoped key can't
+/// act on any other project. Requests which don't name a project aren't restricted.
+pub fn within_scope(scope: Option<&str>, request: &Request) -> bool {
+    match (scope, request.project) {
+        (Some(scope), Some(project)) => scope == project,
+        _ => true,
+    }
+}
+
+/// Evaluates `rules` for a request: at least one rule must allow it, and none may forbid it.
+pub fn evaluate(rules: &[Rule], principal: &Principal, request: &Request) -> bool {
+    let mut allowed = false;
+    for rule in rules.iter().filter(|r| r.matches(principal, request)) {
+        match rule.effect {
+            Effect::Forbid => return false,
+            Effect::Allow => allowed = true,
+        }
+    }
+    allowed
+}
+
+#[derive(Debug)]
+pub enum PolicyError {
+    Unauthorized,
+    /// The policy doesn't allow the request.
+    Forbidden,
+    /// The org doesn't exist, or isn't owned by the principal.
+    NotFound,
+    /// The rules of an org's policy don't parse.
+    InvalidRules,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for PolicyError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound,
+            _ => Self::Sqlx(e),
+        }
+    }
+}
+
+struct KeyResult {
+    org_id: Option<Uuid>,
+    project: Option<String>,
+}
+
+/// An org whose rules apply to the principal, along with the principal's role in it.
+struct OrgRules {
+    role: Option<String>,
+    rules: JsonValue,
+}
+
+/// Checks that the principal authenticated by `auth` may make `request`, under the built-in rules
+/// and the rules of each org it belongs to.
+pub async fn authorize(
+    auth: &Auth,
+    request: Request<'_>,
+    state: &State,
+) -> Result<(), PolicyError> {
+    let key = match auth.api_key() {
+        Some(key) => {
+            query_as!(
+                KeyResult,
+                r#"
+            SELECT s.org_id AS "org_id?", p.name AS "project?"
+            FROM api_keys k
+            LEFT JOIN service_accounts s
+                ON s.id = k.service_account_id
+            LEFT JOIN projects p
+                ON p.id = k.project_id
+            WHERE k.key = $1
+            "#,
+                key,
+            )
+            .fetch_optional(&state.db_conn)
+            .await?
+        }
+        None => None,
+    };
+    if !within_scope(key.as_ref().and_then(|k| k.project.as_deref()), &request) {
+        return Err(PolicyError::Forbidden);
+    }
+    let service_account_org = key.and_then(|k| k.org_id);
+
+    let (kind, orgs) = match (auth, service_account_org) {
+        (_, Some(org_id)) => (
+            PrincipalKind::ServiceAccount,
+            query_as!(
+                OrgRules,
+                r#"
+                SELECT NULL::text AS role, rules
+                FROM org_policies
+                WHERE org_id = $1
+                "#,
+                org_id,
+            )
+            .fetch_all(&state.db_conn)
+            .await?,
+        ),
+        (auth, None) => (
+            if auth.is_jwt() {
+                PrincipalKind::Session
+            } else {
+                PrincipalKind::ApiKey
+            },
+            query_as!(
+                OrgRules,
+                r#"
+                SELECT m.role AS "role?", p.rules
+                FROM org_members m
+                JOIN org_policies p
+                    ON p.org_id = m.org_id
+                WHERE m.user_id = get_user_id($1, $2)
+                    AND m.active
+                    AND m.accepted
+                "#,
+                auth.jwt().map(|c| c.sub),
+                auth.api_key(),
+            )
+            .fetch_all(&state.db_conn)
+            .await?,
+        ),
+    };
+
+    let principal = Principal { kind, role: None };
+    if !evaluate(&DEFAULT_RULES, &principal, &request) {
+        return Err(PolicyError::Forbidden);
+    }
+
+    for org in orgs {
+        let principal = Principal {
+            kind,
+            role: org.role.and_then(|r| r.parse().ok()),
+        };
+        // Rules are validated when they are stored, so any which don't parse are skipped.
+        let rules = serde_json::from_value::<Vec<Rule>>(org.rules).unwrap_or_default();
+        let rules = DEFAULT_RULES
+            .iter()
+            .cloned()
+            .chain(rules)
+            .collect::<Vec<_>>();
+        if !evaluate(&rules, &principal, &request) {
+            return Err(PolicyError::Forbidden);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(kind: PrincipalKind, role: Option<Role>) -> Principal {
+        Principal { kind, role }
+    }
+
+    #[test]
+    fn default_rules() {
+        let session = principal(PrincipalKind::Session, None);
+        let key = principal(PrincipalKind::ApiKey, None);
+
+        assert!(evaluate(
+            &DEFAULT_RULES,
+            &session,
+            &Request::new(Action::ApiKeyCreate)
+        ));
+        assert!(evaluate(
+            &DEFAULT_RULES,
+            &session,
+            &Request::new(Action::EvalRead)
+        ));
+        assert!(!evaluate(
+            &DEFAULT_RULES,
+            &session,
+            &Request::new(Action::EvalWrite)
+        ));
+        assert!(evaluate(
+            &DEFAULT_RULES,
+            &key,
+            &Request::new(Action::EvalWrite)
+        ));
+        assert!(!evaluate(
+            &DEFAULT_RULES,
+            &key,
+            &Request::new(Action::UserRead)
+        ));
+    }
+
+    #[test]
+    fn org_rules_restrict_by_role_and_project() {
+        let rules: Vec<Rule> = DEFAULT_RULES
+            .iter()
+            .cloned()
+            .chain(
+                serde_json::from_value::<Vec<Rule>>(serde_json::json!([
+                    { "effect": "forbid", "roles": ["viewer"], "actions": ["eval:write", "run:*"] },
+                    { "effect": "forbid", "actions": ["eval:*"], "projects": ["secret"] },
+                ]))
+                .unwrap(),
+            )
+            .collect();
+        let viewer = principal(PrincipalKind::ApiKey, Some(Role::Viewer));
+        let member = principal(PrincipalKind::ApiKey, Some(Role::Member));
+        let write = |project| Request::in_project(Action::EvalWrite, project);
+
+        assert!(!evaluate(&rules, &viewer, &write(None)));
+        assert!(!evaluate(&rules, &viewer, &Request::new(Action::RunRead)));
+        assert!(evaluate(&rules, &member, &write(Some("public"))));
+        assert!(!evaluate(&rules, &member, &write(Some("secret"))));
+    }
+
+    #[test]
+    fn scoped_keys_stay_in_their_project() {
+        let write = |project| Request::in_project(Action::EvalWrite, project);
+
+        assert!(within_scope(None, &write(Some("other"))));
+        assert!(within_scope(Some("mine"), &write(Some("mine"))));
+        assert!(!within_scope(Some("mine"), &write(Some("other"))));
+        assert!(within_scope(Some("mine"), &Request::new(Action::EvalRead)));
+    }
+}
@@ -0,0 +1,94 @@
+//! Priority classes for requests, so that interactive lookups (e.g. from a notebook) aren't
+//! starved by batch work (e.g. a CI job uploading thousands of artifacts).
+//!
+//! Clients hint at the priority of a request with the [`PRIORITY_HEADER`] header. Requests
+//! without the hint are interactive. Once the server is busy, batch requests are turned away so
+//! that the rest of its capacity is left to interactive ones, and an API key's batch transfers
+//! are throttled separately from its interactive ones, so they can't use up its bandwidth.
+use crate::error_code::{coded_retry_after, ErrorCode};
+use actix_web::{dev::Payload, FromRequest, HttpRequest};
+use futures::future::{ok, Ready};
+
+/// The header a client hints at the priority of a request with.
+pub const PRIORITY_HEADER: &str = "x-hitsave-priority";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Priority {
+    /// A request a user is waiting on.
+    #[default]
+    Interactive,
+    /// A request which can be retried later.
+    Batch,
+}
+
+impl Priority {
+    /// The priority hinted at by a request. Hints which aren't understood are ignored.
+    pub fn of(req: &HttpRequest) -> Self {
+        match req
+            .headers()
+            .get(PRIORITY_HEADER)
+            .and_then(|v| v.to_str().ok())
+        {
+            Some(v) if v.eq_ignore_ascii_case("batch") => Priority::Batch,
+            _ => Priority::Interactive,
+        }
+    }
+}
+
+impl FromRequest for Priority {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ok(Priority::of(req))
+    }
+}
+
+/// Whether a request of `priority` is admitted with `in_flight` requests already being served.
+/// Interactive requests are always admitted; batch requests only while fewer than
+/// `batch_capacity` requests are in flight.
+pub fn admits(priority: Priority, in_flight: usize, batch_capacity: usize) -> bool {
+    match priority {
+        Priority::Interactive => true,
+        Priority::Batch => in_flight < batch_capacity,
+    }
+}
+
+/// The response to a batch request which wasn't admitted, asking the client to retry after
+/// `retry_after_ms`.
+pub fn overloaded(retry_after_ms: u64) -> actix_web::Error {
+    coded_retry_after(
+        ErrorCode::BatchRejected,
+        "server is busy; retry batch requests later",
+        retry_after_ms,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn reads_hint() {
+        let req = TestRequest::default()
+            .insert_header((PRIORITY_HEADER, "Batch"))
+            .to_http_request();
+        assert_eq!(Priority::of(&req), Priority::Batch);
+
+        let req = TestRequest::default()
+            .insert_header((PRIORITY_HEADER, "urgent"))
+            .to_http_request();
+        assert_eq!(Priority::of(&req), Priority::Interactive);
+
+        let req = TestRequest::default().to_http_request();
+        assert_eq!(Priority::of(&req), Priority::Interactive);
+    }
+
+    #[test]
+    fn turns_batch_away_when_busy() {
+        assert!(admits(Priority::Batch, 10, 48));
+        assert!(!admits(Priority::Batch, 48, 48));
+        assert!(admits(Priority::Interactive, 100, 48));
+    }
+}
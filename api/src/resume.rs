@@ -0,0 +1,250 @@
+//! Resumable verification of BLOB downloads.
+//!
+//! A verified download hashes the BLOB as it's streamed, and aborts the response if the hash
+//! doesn't match, so that a client never silently receives corrupt data. So that a dropped
+//! download of a large BLOB needn't be verified from the start again, the state of the hash is
+//! checkpointed every [`CHECKPOINT_INTERVAL`] bytes against a resume token. A client resuming with
+//! the token gets the rest of the BLOB, and hashing picks up from the last checkpoint before it.
+//!
+//! BLAKE3 hashes 1 KiB chunks into a Merkle tree, so at a chunk boundary its whole state is the
+//! chaining values of the complete subtrees to the left, at most one per level of the tree.
+use blake3::guts::{parent_cv, ChunkState, CHUNK_LEN};
+use blake3::Hash;
+
+/// How far apart checkpoints are, in bytes. A resumed download re-hashes at most this much of
+/// what the client already has. This must be a multiple of BLAKE3's chunk length.
+pub const CHECKPOINT_INTERVAL: u64 = 256 * 1024 * 1024;
+
+/// How long a resume token can be used for.
+pub const RESUME_TOKEN_TTL_HOURS: i32 = 24;
+
+/// The header a verified download's resume token is sent in, and presented in to resume it.
+pub const RESUME_TOKEN_HEADER: &str = "x-hitsave-resume-token";
+
+/// The state of a hash at a chunk boundary, after hashing `offset` bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub offset: u64,
+    /// The chaining values of the complete subtrees to the left of `offset`, from the root down.
+    pub cv_stack: Vec<[u8; 32]>,
+}
+
+impl Checkpoint {
+    /// The checkpoint of a hash yet to start.
+    pub fn start() -> Self {
+        Self {
+            offset: 0,
+            cv_stack: vec![],
+        }
+    }
+
+    /// The chaining values, concatenated, for storing.
+    pub fn cv_bytes(&self) -> Vec<u8> {
+        self.cv_stack.concat()
+    }
+
+    /// Reads a checkpoint stored with [`Checkpoint::cv_bytes`]. Returns `None` if the bytes don't
+    /// match the number of complete subtrees to the left of `offset`.
+    pub fn from_cv_bytes(offset: u64, bytes: &[u8]) -> Option<Self> {
+        if offset % CHUNK_LEN as u64 != 0
+            || bytes.len() != 32 * (offset / CHUNK_LEN as u64).count_ones() as usize
+        {
+            return None;
+        }
+        let cv_stack = bytes
+            .chunks_exact(32)
+            .map(|cv| cv.try_into().unwrap())
+            .collect();
+        Some(Self { offset, cv_stack })
+    }
+}
+
+/// A BLAKE3 hasher whose state can be checkpointed at chunk boundaries.
+pub struct ResumableHasher {
+    chunk: ChunkState,
+    chunk_counter: u64,
+    cv_stack: Vec<Hash>,
+}
+
+impl ResumableHasher {
+    /// Resumes hashing from `checkpoint`. Only resume a checkpoint when there's input left after
+    /// it, as its last chunk is assumed not to be the root of the tree.
+    pub fn resume(checkpoint: &Checkpoint) -> Self {
+        let chunk_counter = checkpoint.offset / CHUNK_LEN as u64;
+        Self {
+            chunk: ChunkState::new(chunk_counter),
+            chunk_counter,
+            cv_stack: checkpoint.cv_stack.iter().map(|&cv| cv.into()).collect(),
+        }
+    }
+
+    /// Pushes the chaining value of a complete chunk, merging the subtrees it completes. The
+    /// number of subtrees on the stack is the number of 1 bits in the number of chunks.
+    fn push_chunk_cv(cv_stack: &mut Vec<Hash>, mut cv: Hash, mut total_chunks: u64) {
+        while total_chunks & 1 == 0 {
+            cv = parent_cv(&cv_stack.pop().unwrap(), &cv, false);
+            total_chunks >>= 1;
+        }
+        cv_stack.push(cv);
+    }
+
+    pub fn update(&mut self, mut input: &[u8]) {
+        while !input.is_empty() {
+            // A full chunk is only hashed once more input arrives, as it may be the root.
+            if self.chunk.len() == CHUNK_LEN {
+                let cv = self.chunk.finalize(false);
+                self.chunk_counter += 1;
+                Self::push_chunk_cv(&mut self.cv_stack, cv, self.chunk_counter);
+                self.chunk = ChunkState::new(self.chunk_counter);
+            }
+            let take = (CHUNK_LEN - self.chunk.len()).min(input.len());
+            self.chunk.update(&input[..take]);
+            input = &input[take..];
+        }
+    }
+
+    /// The number of bytes hashed.
+    pub fn offset(&self) -> u64 {
+        self.chunk_counter * CHUNK_LEN as u64 + self.chunk.len() as u64
+    }
+
+    /// Checkpoints the hash, if it's at a chunk boundary. The checkpoint can only be resumed if
+    /// there's more input to come.
+    pub fn checkpoint(&self) -> Option<Checkpoint> {
+        let mut cv_stack = self.cv_stack.clone();
+        match self.chunk.len() {
+            0 => {}
+            CHUNK_LEN => Self::push_chunk_cv(
+                &mut cv_stack,
+                self.chunk.finalize(false),
+                self.chunk_counter + 1,
+            ),
+            _ => return None,
+        }
+        Some(Checkpoint {
+            offset: self.offset(),
+            cv_stack: cv_stack.iter().map(|cv| *cv.as_bytes()).collect(),
+        })
+    }
+
+    pub fn finalize(&self) -> Hash {
+        if self.cv_stack.is_empty() {
+            return self.chunk.finalize(true);
+        }
+        let mut cv = self.chunk.finalize(false);
+        for (i, left) in self.cv_stack.iter().enumerate().rev() {
+            cv = parent_cv(left, &cv, i == 0);
+        }
+        cv
+    }
+}
+
+/// The first byte requested by a `Range` header of the form `bytes=N-`, which is all a resumed
+/// download asks for.
+pub fn range_start(range: &str) -> Option<u64> {
+    range
+        .strip_prefix("bytes=")?
+        .strip_suffix('-')?
+        .parse()
+        .ok()
+}
+
+/// The bytes `first..=last` requested by a `Range` header of a BLOB which is `length` bytes long,
+/// if it can be satisfied. Ranges of the form `bytes=N-M`, `bytes=N-` and `bytes=-N` (the last `N`
+/// bytes) are understood; a range running past the end of the BLOB is cut short. Only a single
+/// range can be requested.
+pub fn requested_range(range: &str, length: u64) -> Option<(u64, u64)> {
+    let (first, last) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let end = length.checked_sub(1)?;
+    let (first, last) = if first.is_empty() {
+        let suffix: u64 = last.parse().ok()?;
+        if suffix == 0 {
+            return None;
+        }
+        (length.saturating_sub(suffix), end)
+    } else if last.is_empty() {
+        (first.parse().ok()?, end)
+    } else {
+        (first.parse().ok()?, last.parse::<u64>().ok()?.min(end))
+    };
+    if first > last {
+        return None;
+    }
+
+    Some((first, last))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn hashes_like_blake3() {
+        for len in [
+            0,
+            1,
+            1023,
+            1024,
+            1025,
+            2048,
+            3 * 1024 + 7,
+            8 * 1024,
+            9 * 1024 + 1,
+        ] {
+            let data = input(len);
+            let mut hasher = ResumableHasher::resume(&Checkpoint::start());
+            for piece in data.chunks(700) {
+                hasher.update(piece);
+            }
+            assert_eq!(hasher.finalize(), blake3::hash(&data), "len {}", len);
+        }
+    }
+
+    #[test]
+    fn resumes_from_checkpoints() {
+        let data = input(13 * 1024 + 5);
+        for split in [1024, 4 * 1024, 5 * 1024, 12 * 1024] {
+            let mut hasher = ResumableHasher::resume(&Checkpoint::start());
+            hasher.update(&data[..split]);
+            let checkpoint = hasher.checkpoint().unwrap();
+            assert_eq!(checkpoint.offset, split as u64);
+
+            let stored = Checkpoint::from_cv_bytes(checkpoint.offset, &checkpoint.cv_bytes());
+            assert_eq!(stored.as_ref(), Some(&checkpoint));
+
+            let mut resumed = ResumableHasher::resume(&checkpoint);
+            resumed.update(&data[split..]);
+            assert_eq!(resumed.finalize(), blake3::hash(&data), "split {}", split);
+        }
+
+        let mut hasher = ResumableHasher::resume(&Checkpoint::start());
+        hasher.update(&data[..100]);
+        assert_eq!(hasher.checkpoint(), None);
+    }
+
+    #[test]
+    fn parses_open_ranges() {
+        assert_eq!(range_start("bytes=1024-"), Some(1024));
+        assert_eq!(range_start("bytes=0-99"), None);
+        assert_eq!(range_start("items=5-"), None);
+    }
+
+    #[test]
+    fn parses_requested_ranges() {
+        assert_eq!(requested_range("bytes=0-99", 1000), Some((0, 99)));
+        assert_eq!(requested_range("bytes=900-", 1000), Some((900, 999)));
+        assert_eq!(requested_range("bytes=-100", 1000), Some((900, 999)));
+        assert_eq!(requested_range("bytes=-2000", 1000), Some((0, 999)));
+        assert_eq!(requested_range("bytes=990-2000", 1000), Some((990, 999)));
+        assert_eq!(requested_range("bytes=1000-", 1000), None);
+        assert_eq!(requested_range("bytes=5-3", 1000), None);
+        assert_eq!(requested_range("bytes=-0", 1000), None);
+        assert_eq!(requested_range("bytes=0-", 0), None);
+        assert_eq!(requested_range("bytes=0-1,5-6", 1000), None);
+        assert_eq!(requested_range("items=0-1", 1000), None);
+    }
+}
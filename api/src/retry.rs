@@ -0,0 +1,96 @@
+//! Retries of operations on BLOB storage which fail transiently, e.g. when S3 throttles requests or
+//! drops a connection. The waits between attempts back off exponentially, with full jitter so that
+//! operations which failed together don't retry together, and operations are given up on after a
+//! number of attempts, or once another attempt would start after their deadline.
+use crate::persisters::s3store::StoreError;
+use crate::CONFIG;
+use rand::Rng;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The longest wait between attempts, however many there have been.
+const MAX_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    /// The most attempts made, including the first.
+    pub attempts: u32,
+    /// The longest wait before the first retry. The longest wait doubles with each retry after it.
+    pub base: Duration,
+    /// How long after the first attempt starts no more are started.
+    pub deadline: Duration,
+}
+
+impl Backoff {
+    pub fn from_config() -> Self {
+        Self {
+            attempts: CONFIG.s3_retry_attempts.max(1),
+            base: Duration::from_millis(CONFIG.s3_retry_base_ms),
+            deadline: Duration::from_millis(CONFIG.s3_retry_deadline_ms),
+        }
+    }
+
+    /// The longest wait before the `retry`th retry.
+    fn ceiling(&self, retry: u32) -> Duration {
+        let factor = 1u32 << retry.saturating_sub(1).min(16);
+        self.base
+            .checked_mul(factor)
+            .map_or(MAX_DELAY, |d| d.min(MAX_DELAY))
+    }
+
+    /// The wait before the `retry`th retry, given `jitter` between 0 and 1.
+    fn delay(&self, retry: u32, jitter: f64) -> Duration {
+        self.ceiling(retry).mul_f64(jitter.clamp(0.0, 1.0))
+    }
+}
+
+/// Runs `op` until it succeeds, fails with an error which isn't transient, or runs out of attempts
+/// or time, returning what it last returned.
+pub async fn retry<T, F, Fut>(backoff: Backoff, mut op: F) -> Result<T, StoreError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, StoreError>>,
+{
+    let deadline = Instant::now() + backoff.deadline;
+    let mut attempt = 1;
+    loop {
+        let e = match op().await {
+            Ok(res) => return Ok(res),
+            Err(e) => e,
+        };
+        if attempt >= backoff.attempts || !e.is_transient() {
+            return Err(e);
+        }
+        let delay = backoff.delay(attempt, rand::thread_rng().gen());
+        if Instant::now() + delay >= deadline {
+            return Err(e);
+        }
+        log::warn!(
+            "retrying BLOB storage operation after attempt {}: {:?}",
+            attempt,
+            e
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_exponentially_up_to_a_limit() {
+        let backoff = Backoff {
+            attempts: 10,
+            base: Duration::from_millis(100),
+            deadline: Duration::from_secs(60),
+        };
+        assert_eq!(backoff.ceiling(1), Duration::from_millis(100));
+        assert_eq!(backoff.ceiling(3), Duration::from_millis(400));
+        assert_eq!(backoff.ceiling(40), MAX_DELAY);
+        assert_eq!(backoff.delay(2, 0.5), Duration::from_millis(100));
+        assert_eq!(backoff.delay(2, 2.0), Duration::from_millis(200));
+    }
+}
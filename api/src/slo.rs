@@ -0,0 +1,268 @@
+//! Service level objectives for the API, tracked per route.
+//!
+//! A request is good if it succeeds (doesn't fail with a server error) within the latency
+//! threshold. The outcomes of each route's requests are counted in one minute buckets over a
+//! rolling window, from which its availability and remaining error budget are reported. How fast a
+//! route is burning its budget is measured over a short and a long window, and a route is only
+//! alerted on when both burn too fast, so that a brief spike doesn't page anyone but a sustained
+//! one is caught quickly.
+use crate::middlewares::auth::Auth;
+use crate::persisters::user::is_admin;
+use crate::state::State;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The windows, in minutes, burn rates are measured over.
+pub const SHORT_WINDOW_MINS: u64 = 5;
+pub const LONG_WINDOW_MINS: u64 = 60;
+
+/// Counts of a route's requests, by outcome.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counts {
+    pub total: u64,
+    /// Requests which failed with a server error.
+    pub errors: u64,
+    /// Requests which succeeded, but took longer than the latency threshold.
+    pub slow: u64,
+}
+
+impl Counts {
+    pub fn bad(&self) -> u64 {
+        self.errors + self.slow
+    }
+
+    fn add(&mut self, other: &Counts) {
+        self.total += other.total;
+        self.errors += other.errors;
+        self.slow += other.slow;
+    }
+
+    /// How many times faster than the objective allows the budget is being spent. A burn rate of
+    /// 1 spends exactly the whole budget over the window.
+    pub fn burn_rate(&self, target: f64) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        self.bad() as f64 / self.total as f64 / (1.0 - target)
+    }
+}
+
+#[derive(Default)]
+struct RouteWindow {
+    /// Counts per minute, oldest first.
+    buckets: VecDeque<(u64, Counts)>,
+    /// Counts since the server started.
+    lifetime: Counts,
+}
+
+impl RouteWindow {
+    /// The counts of the buckets within the last `mins` minutes before `now`.
+    fn since(&self, now: u64, mins: u64) -> Counts {
+        let mut counts = Counts::default();
+        for (_, c) in self.buckets.iter().filter(|(m, _)| *m + mins > now) {
+            counts.add(c);
+        }
+        counts
+    }
+}
+
+/// How a route is doing against its objective.
+#[derive(Serialize, Debug, Clone)]
+pub struct RouteSlo {
+    /// The route template, e.g. `/blob/{content_hash}`.
+    pub route: String,
+    pub window_mins: u64,
+    pub total: u64,
+    pub errors: u64,
+    pub slow: u64,
+    /// The fraction of requests in the window which were good.
+    pub availability: f64,
+    /// The fraction of the window's error budget left. This is negative once it's overspent.
+    pub error_budget_remaining: f64,
+    pub burn_rate_short: f64,
+    pub burn_rate_long: f64,
+}
+
+/// The outcomes of recent requests, per route.
+#[derive(Clone)]
+pub struct SloTracker {
+    routes: Arc<Mutex<HashMap<String, RouteWindow>>>,
+    window_mins: u64,
+    latency_threshold: Duration,
+}
+
+fn current_minute() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 60
+}
+
+impl SloTracker {
+    pub fn new(window_mins: u64, latency_threshold: Duration) -> Self {
+        Self {
+            routes: Default::default(),
+            window_mins: window_mins.max(LONG_WINDOW_MINS),
+            latency_threshold,
+        }
+    }
+
+    /// Records the outcome of a request to `route`.
+    pub fn record(&self, route: &str, status: u16, latency: Duration) {
+        self.record_at(current_minute(), route, status, latency)
+    }
+
+    fn record_at(&self, minute: u64, route: &str, status: u16, latency: Duration) {
+        let outcome = Counts {
+            total: 1,
+            errors: (status >= 500) as u64,
+            slow: (status < 500 && latency > self.latency_threshold) as u64,
+        };
+
+        let mut routes = self.routes.lock().unwrap();
+        let window = routes.entry(route.to_string()).or_default();
+        window.lifetime.add(&outcome);
+        match window.buckets.back_mut() {
+            Some((m, counts)) if *m == minute => counts.add(&outcome),
+            _ => window.buckets.push_back((minute, outcome)),
+        }
+        while let Some((m, _)) = window.buckets.front() {
+            if *m + self.window_mins > minute {
+                break;
+            }
+            window.buckets.pop_front();
+        }
+    }
+
+    /// How each route with requests in the window is doing against `target`, the fraction of
+    /// requests which should be good. Routes burning their budget fastest come first.
+    pub fn report(&self, target: f64) -> Vec<RouteSlo> {
+        self.report_at(current_minute(), target)
+    }
+
+    fn report_at(&self, now: u64, target: f64) -> Vec<RouteSlo> {
+        let routes = self.routes.lock().unwrap();
+        let mut report = routes
+            .iter()
+            .filter_map(|(route, window)| {
+                let counts = window.since(now, self.window_mins);
+                if counts.total == 0 {
+                    return None;
+                }
+                let availability = 1.0 - counts.bad() as f64 / counts.total as f64;
+                Some(RouteSlo {
+                    route: route.clone(),
+                    window_mins: self.window_mins,
+                    total: counts.total,
+                    errors: counts.errors,
+                    slow: counts.slow,
+                    availability,
+                    error_budget_remaining: 1.0 - counts.burn_rate(target),
+                    burn_rate_short: window.since(now, SHORT_WINDOW_MINS).burn_rate(target),
+                    burn_rate_long: window.since(now, LONG_WINDOW_MINS).burn_rate(target),
+                })
+            })
+            .collect::<Vec<_>>();
+        report.sort_by(|a, b| b.burn_rate_long.total_cmp(&a.burn_rate_long));
+        report
+    }
+
+    /// The counts of each route's requests since the server started.
+    pub fn lifetime(&self) -> Vec<(String, Counts)> {
+        let routes = self.routes.lock().unwrap();
+        let mut lifetime = routes
+            .iter()
+            .map(|(route, window)| (route.clone(), window.lifetime))
+            .collect::<Vec<_>>();
+        lifetime.sort_by(|a, b| a.0.cmp(&b.0));
+        lifetime
+    }
+}
+
+/// Whether a route is burning its budget fast enough, over both windows, to alert on.
+pub fn should_alert(slo: &RouteSlo, burn_rate_threshold: f64) -> bool {
+    slo.burn_rate_short >= burn_rate_threshold && slo.burn_rate_long >= burn_rate_threshold
+}
+
+#[derive(Debug)]
+pub enum SloError {
+    Unauthorized,
+    /// Only admins can see how the API is doing against its objectives.
+    Forbidden,
+    Sqlx(sqlx::Error),
+}
+
+impl From<sqlx::Error> for SloError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+/// The report of how each route is doing against the configured objective, for an admin.
+pub async fn report(auth: Option<&Auth>, state: &State) -> Result<Vec<RouteSlo>, SloError> {
+    let auth = auth.ok_or(SloError::Unauthorized)?;
+
+    if !is_admin(auth, state).await? {
+        return Err(SloError::Forbidden);
+    }
+
+    Ok(state.slo.report(state.config.slo_target))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET: f64 = 0.99;
+
+    fn tracker() -> SloTracker {
+        SloTracker::new(LONG_WINDOW_MINS, Duration::from_millis(500))
+    }
+
+    #[test]
+    fn counts_errors_and_slow_requests() {
+        let slo = tracker();
+        let fast = Duration::from_millis(10);
+        for _ in 0..96 {
+            slo.record_at(100, "/eval", 200, fast);
+        }
+        slo.record_at(100, "/eval", 500, fast);
+        slo.record_at(100, "/eval", 503, Duration::from_secs(2));
+        slo.record_at(100, "/eval", 200, Duration::from_secs(2));
+        slo.record_at(100, "/eval", 404, fast);
+
+        let report = slo.report_at(100, TARGET);
+        assert_eq!(report.len(), 1);
+        assert_eq!(
+            (report[0].total, report[0].errors, report[0].slow),
+            (100, 2, 1)
+        );
+        assert!((report[0].availability - 0.97).abs() < 1e-9);
+        assert!((report[0].burn_rate_long - 3.0).abs() < 1e-9);
+        assert!((report[0].error_budget_remaining + 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn burn_rates_use_their_windows() {
+        let slo = tracker();
+        let fast = Duration::from_millis(10);
+        // An hour ago, errors; recently, all good.
+        for _ in 0..10 {
+            slo.record_at(100, "/blob", 500, fast);
+        }
+        for _ in 0..90 {
+            slo.record_at(150, "/blob", 200, fast);
+        }
+
+        let report = slo.report_at(150, TARGET);
+        assert_eq!(report[0].burn_rate_short, 0.0);
+        assert!((report[0].burn_rate_long - 10.0).abs() < 1e-9);
+        assert!(!should_alert(&report[0], 5.0));
+
+        // Once the errors leave the window, the route is forgotten until it's requested again.
+        assert!(slo.report_at(150 + LONG_WINDOW_MINS, TARGET).is_empty());
+        assert_eq!(slo.lifetime()[0].1.total, 100);
+    }
+}
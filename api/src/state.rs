@@ -1,8 +1,25 @@
 pub type SqlPool = sqlx::PgPool;
 pub type PoolOptions = sqlx::postgres::PgPoolOptions;
 
+use crate::cache::ListingCache;
+use crate::capture::CaptureKey;
+use crate::cdn::Cdn;
+use crate::chaos::Chaos;
 use crate::config::Config;
-use crate::persisters::s3store::S3Store;
+use crate::downloads::Downloads;
+use crate::embed::Embedder;
+use crate::fanout::Fanout;
+use crate::gc::GcStats;
+use crate::integrity::IntegrityKey;
+use crate::keys::VerifiedKeys;
+use crate::leader::Leadership;
+use crate::load::Load;
+use crate::notify::Notifier;
+use crate::persisters::blob_store::BlobStore;
+use crate::persisters::cached_store::BlobCacheStats;
+use crate::slo::SloTracker;
+use crate::status::StatusBoard;
+use crate::throttle::Throttle;
 
 #[derive(Clone)]
 pub struct State {
@@ -10,7 +27,49 @@ pub struct State {
     // the `State` struct passed into the web server
     pub config: Config,
     pub db_conn: SqlPool,
-    pub s3_store: S3Store,
+    /// Where BLOBs are stored: S3, unless `BLOB_STORE_DIR` is set.
+    pub blob_store: std::sync::Arc<dyn BlobStore>,
+    pub notifier: Notifier,
+    pub embedder: Embedder,
+    pub load: Load,
+    /// The BLOB downloads being served.
+    pub downloads: Downloads,
+    pub listing_cache: ListingCache,
+    /// Postgres notifications, for whatever is waiting on them.
+    pub fanout: Fanout,
+    pub throttle: Throttle,
+    pub slo: SloTracker,
+    pub chaos: Chaos,
+    /// The key captured requests are sealed with, if capture is enabled.
+    pub capture_key: Option<CaptureKey>,
+    /// The key projects' integrity manifests are signed with, if they're enabled.
+    pub integrity_key: Option<IntegrityKey>,
+    /// The key API keys are sealed with for the S3 gateway, if it's configured. Sealing is the
+    /// same as for captured requests.
+    pub gateway_key: Option<CaptureKey>,
+    /// Signs the URLs BLOB downloads are redirected to, if they're served from a CDN.
+    pub cdn: Option<Cdn>,
+    /// The API keys whose hashes have been checked.
+    pub verified_keys: VerifiedKeys,
+    /// What the BLOB garbage collector has found and reclaimed.
+    pub gc: GcStats,
+    /// How the cache of BLOBs on local disk has fared, if BLOBs are cached.
+    pub blob_cache: BlobCacheStats,
+    /// The latest checks of the components in the status feed.
+    pub status: StatusBoard,
+    /// The jobs which run on one replica at a time that this replica leads.
+    pub leadership: Leadership,
+}
+
+impl State {
+    /// How long a polling client should wait before polling again, given the current load.
+    pub fn poll_after_ms(&self) -> u64 {
+        let requests = self.load.in_flight() as f64 / self.config.poll_capacity.max(1) as f64;
+        let max_connections = self.config.db_max_connections.max(1);
+        let busy_connections = self.db_conn.size() as usize - self.db_conn.num_idle();
+        let db = busy_connections as f64 / max_connections as f64;
+        crate::load::poll_after_ms(self.config.poll_base_ms, requests, db)
+    }
 }
 
 pub type AppStateRaw = std::sync::Arc<State>;
@@ -0,0 +1,205 @@
+//! The health of each of the service's components, as published in the status feed at
+//! `/status.json`. See [`crate::persisters::status`].
+//!
+//! Components are checked in the background by [`crate::jobs::status_checks`], and the latest
+//! checks are kept in memory, so the feed can be served however often it's polled, and while the
+//! database is down. Incidents posted by admins raise the health of the components they affect.
+use sqlx::types::chrono;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long waiting jobs have to have waited for the job queue to be degraded, or down.
+pub const JOB_QUEUE_DEGRADED_SECS: i64 = 5 * 60;
+pub const JOB_QUEUE_OUTAGE_SECS: i64 = 30 * 60;
+
+/// How slow a check of the database or BLOB storage has to be for it to be degraded.
+pub const SLOW_CHECK: Duration = Duration::from_secs(1);
+
+/// How a component is doing, from best to worst.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Health {
+    Operational,
+    Degraded,
+    Outage,
+}
+
+impl Health {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Health::Operational => "operational",
+            Health::Degraded => "degraded",
+            Health::Outage => "outage",
+        }
+    }
+}
+
+impl FromStr for Health {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "operational" => Ok(Health::Operational),
+            "degraded" => Ok(Health::Degraded),
+            "outage" => Ok(Health::Outage),
+            _ => Err(()),
+        }
+    }
+}
+
+/// A part of the service whose health is published.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Component {
+    Api,
+    Database,
+    BlobStore,
+    JobQueue,
+}
+
+/// Every component, in the order they're listed.
+pub const COMPONENTS: [Component; 4] = [
+    Component::Api,
+    Component::Database,
+    Component::BlobStore,
+    Component::JobQueue,
+];
+
+impl Component {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Component::Api => "api",
+            Component::Database => "database",
+            Component::BlobStore => "blob_store",
+            Component::JobQueue => "job_queue",
+        }
+    }
+}
+
+/// The latest check of a component.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ComponentStatus {
+    pub component: Component,
+    pub health: Health,
+    /// Why the component isn't operational, if it isn't.
+    pub detail: Option<String>,
+    /// When the component was last checked, or `None` if it hasn't been since the server started.
+    pub checked_dt: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl ComponentStatus {
+    pub fn new(component: Component, health: Health, detail: Option<String>) -> Self {
+        Self {
+            component,
+            health,
+            detail,
+            checked_dt: Some(chrono::Utc::now()),
+        }
+    }
+}
+
+/// The health of a check which took `elapsed`, or failed with `error`.
+pub fn check_health(elapsed: Duration, error: Option<String>) -> (Health, Option<String>) {
+    match error {
+        Some(e) => (Health::Outage, Some(e)),
+        None if elapsed > SLOW_CHECK => (
+            Health::Degraded,
+            Some(format!("responding slowly ({}ms)", elapsed.as_millis())),
+        ),
+        None => (Health::Operational, None),
+    }
+}
+
+/// The health of the job queue, given how long the longest waiting job has waited, in seconds.
+pub fn job_queue_health(longest_wait_secs: Option<i64>) -> (Health, Option<String>) {
+    match longest_wait_secs {
+        Some(secs) if secs >= JOB_QUEUE_DEGRADED_SECS => {
+            let health = match secs >= JOB_QUEUE_OUTAGE_SECS {
+                true => Health::Outage,
+                false => Health::Degraded,
+            };
+            let detail = format!("jobs have waited up to {} minutes to start", secs / 60);
+            (health, Some(detail))
+        }
+        _ => (Health::Operational, None),
+    }
+}
+
+/// The latest check of each component.
+#[derive(Clone)]
+pub struct StatusBoard(Arc<Mutex<Vec<ComponentStatus>>>);
+
+impl Default for StatusBoard {
+    /// Components haven't been checked yet, so they're taken to be operational.
+    fn default() -> Self {
+        let unchecked = COMPONENTS
+            .into_iter()
+            .map(|component| ComponentStatus {
+                component,
+                health: Health::Operational,
+                detail: None,
+                checked_dt: None,
+            })
+            .collect();
+        Self(Arc::new(Mutex::new(unchecked)))
+    }
+}
+
+impl StatusBoard {
+    pub fn record(&self, status: ComponentStatus) {
+        let mut board = self.0.lock().unwrap();
+        if let Some(s) = board.iter_mut().find(|s| s.component == status.component) {
+            *s = status;
+        }
+    }
+
+    pub fn components(&self) -> Vec<ComponentStatus> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slow_and_failed_checks() {
+        assert_eq!(
+            check_health(Duration::from_millis(20), None),
+            (Health::Operational, None)
+        );
+        assert_eq!(
+            check_health(Duration::from_millis(1500), None).0,
+            Health::Degraded
+        );
+        assert_eq!(
+            check_health(Duration::from_millis(20), Some("refused".to_string())),
+            (Health::Outage, Some("refused".to_string()))
+        );
+    }
+
+    #[test]
+    fn job_queue_waits() {
+        assert_eq!(job_queue_health(None).0, Health::Operational);
+        assert_eq!(job_queue_health(Some(60)).0, Health::Operational);
+        assert_eq!(job_queue_health(Some(10 * 60)).0, Health::Degraded);
+        assert_eq!(job_queue_health(Some(60 * 60)).0, Health::Outage);
+    }
+
+    #[test]
+    fn board_keeps_the_latest_check() {
+        let board = StatusBoard::default();
+        board.record(ComponentStatus::new(
+            Component::Database,
+            Health::Outage,
+            None,
+        ));
+        let components = board.components();
+        assert_eq!(components.len(), COMPONENTS.len());
+        assert_eq!(components[1].component, Component::Database);
+        assert_eq!(components[1].health, Health::Outage);
+        assert!(components[0].checked_dt.is_none());
+        assert_eq!(Health::Outage.max(Health::Degraded), Health::Outage);
+    }
+}
@@ -0,0 +1,216 @@
+//! Statistics of tabular artifacts.
+//!
+//! Parquet and Arrow IPC files are recognised by their magic bytes. CSV has none, so a BLOB is
+//! treated as CSV when its first lines are text with the same number of commas (or tabs) on each.
+use crate::models::blob_stats::{ColumnStats, Format};
+
+use arrow::array::{as_primitive_array, Array, ArrayRef};
+use arrow::compute;
+use arrow::datatypes::{DataType, Float64Type};
+use arrow::error::ArrowError;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::errors::ParquetError;
+use std::io::Cursor;
+
+/// How many bytes from the start of a BLOB are needed to recognise its format.
+pub const SNIFF_LEN: usize = 4096;
+
+/// How many CSV rows are read to infer the types of the columns.
+const CSV_INFER_ROWS: usize = 1000;
+
+#[derive(Debug)]
+pub enum TabularError {
+    Arrow(ArrowError),
+    Parquet(ParquetError),
+}
+
+impl From<ArrowError> for TabularError {
+    fn from(e: ArrowError) -> Self {
+        Self::Arrow(e)
+    }
+}
+
+impl From<ParquetError> for TabularError {
+    fn from(e: ParquetError) -> Self {
+        Self::Parquet(e)
+    }
+}
+
+impl std::fmt::Display for TabularError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TabularError::Arrow(e) => write!(f, "{}", e),
+            TabularError::Parquet(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// The delimiter of `sample` if it looks like the start of a CSV file with a header row.
+fn csv_delimiter(sample: &[u8]) -> Option<u8> {
+    // The sample may end part way through a line (or a character), so only whole lines count.
+    let end = sample.iter().rposition(|&b| b == b'\n')?;
+    let text = std::str::from_utf8(&sample[..end]).ok()?;
+    let lines = text.lines().collect::<Vec<_>>();
+    if lines.len() < 2 {
+        return None;
+    }
+
+    [b',', b'\t'].into_iter().find(|&d| {
+        let count = |line: &str| line.bytes().filter(|&b| b == d).count();
+        let header = count(lines[0]);
+        header > 0 && lines.iter().all(|l| count(l) == header)
+    })
+}
+
+/// Recognises the format of a BLOB from its first `SNIFF_LEN` bytes (or all of them, if it's
+/// shorter).
+pub fn sniff(sample: &[u8]) -> Option<Format> {
+    if sample.starts_with(b"PAR1") {
+        Some(Format::Parquet)
+    } else if sample.starts_with(b"ARROW1") {
+        Some(Format::Arrow)
+    } else if csv_delimiter(sample).is_some() {
+        Some(Format::Csv)
+    } else {
+        None
+    }
+}
+
+/// Running statistics of a column, over the batches read so far.
+struct Accumulator {
+    name: String,
+    data_type: DataType,
+    null_count: i64,
+    count: i64,
+    min: Option<f64>,
+    max: Option<f64>,
+    sum: f64,
+}
+
+impl Accumulator {
+    fn update(&mut self, array: &ArrayRef) -> Result<(), ArrowError> {
+        self.null_count += array.null_count() as i64;
+        if !DataType::is_numeric(&self.data_type) {
+            return Ok(());
+        }
+
+        let floats = compute::cast(array, &DataType::Float64)?;
+        let floats = as_primitive_array::<Float64Type>(&floats);
+        self.count += (floats.len() - floats.null_count()) as i64;
+        if let Some(min) = compute::min(floats) {
+            self.min = Some(self.min.map_or(min, |m| m.min(min)));
+        }
+        if let Some(max) = compute::max(floats) {
+            self.max = Some(self.max.map_or(max, |m| m.max(max)));
+        }
+        self.sum += compute::sum(floats).unwrap_or_default();
+
+        Ok(())
+    }
+
+    fn finish(self) -> ColumnStats {
+        ColumnStats {
+            name: self.name,
+            data_type: self.data_type.to_string(),
+            null_count: self.null_count,
+            min: self.min,
+            max: self.max,
+            mean: (self.count > 0).then(|| self.sum / self.count as f64),
+        }
+    }
+}
+
+/// Computes the row count and column statistics of a table from its batches.
+fn stats_of<I>(batches: I) -> Result<(i64, Vec<ColumnStats>), TabularError>
+where
+    I: Iterator<Item = Result<RecordBatch, ArrowError>>,
+{
+    let mut rows = 0;
+    let mut columns: Option<Vec<Accumulator>> = None;
+
+    for batch in batches {
+        let batch = batch?;
+        let columns = columns.get_or_insert_with(|| {
+            batch
+                .schema()
+                .fields()
+                .iter()
+                .map(|f| Accumulator {
+                    name: f.name().clone(),
+                    data_type: f.data_type().clone(),
+                    null_count: 0,
+                    count: 0,
+                    min: None,
+                    max: None,
+                    sum: 0.0,
+                })
+                .collect()
+        });
+
+        rows += batch.num_rows() as i64;
+        for (acc, array) in columns.iter_mut().zip(batch.columns()) {
+            acc.update(array)?;
+        }
+    }
+
+    let columns = columns
+        .unwrap_or_default()
+        .into_iter()
+        .map(Accumulator::finish)
+        .collect();
+    Ok((rows, columns))
+}
+
+/// Reads a whole table, returning its row count and column statistics. This is CPU bound, so
+/// should be run off the async workers.
+pub fn compute(
+    format: Format,
+    bytes: bytes::Bytes,
+) -> Result<(i64, Vec<ColumnStats>), TabularError> {
+    match format {
+        Format::Parquet => stats_of(ParquetRecordBatchReaderBuilder::try_new(bytes)?.build()?),
+        Format::Arrow => stats_of(arrow::ipc::reader::FileReader::try_new(
+            Cursor::new(bytes),
+            None,
+        )?),
+        Format::Csv => {
+            let delimiter = csv_delimiter(&bytes[..bytes.len().min(SNIFF_LEN)]).unwrap_or(b',');
+            let reader = arrow::csv::ReaderBuilder::new()
+                .has_header(true)
+                .with_delimiter(delimiter)
+                .infer_schema(Some(CSV_INFER_ROWS))
+                .build(Cursor::new(bytes))?;
+            stats_of(reader)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffs_formats() {
+        assert_eq!(sniff(b"PAR1\x15\x04"), Some(Format::Parquet));
+        assert_eq!(sniff(b"ARROW1\0\0"), Some(Format::Arrow));
+        assert_eq!(sniff(b"a,b\n1,2\n3,4\n"), Some(Format::Csv));
+        assert_eq!(sniff(b"a\tb\n1\t2\n"), Some(Format::Csv));
+        assert_eq!(sniff(b"a,b\n1,2,3\n"), None);
+        assert_eq!(sniff(b"\x80\x04\x95pickle"), None);
+    }
+
+    #[test]
+    fn computes_csv_stats() {
+        let csv = bytes::Bytes::from_static(b"x,label\n1,a\n3,\n5,c\n");
+        let (rows, columns) = compute(Format::Csv, csv).unwrap();
+
+        assert_eq!(rows, 3);
+        assert_eq!(columns[0].name, "x");
+        assert_eq!(columns[0].min, Some(1.0));
+        assert_eq!(columns[0].max, Some(5.0));
+        assert_eq!(columns[0].mean, Some(3.0));
+        assert_eq!(columns[1].name, "label");
+        assert_eq!(columns[1].mean, None);
+    }
+}
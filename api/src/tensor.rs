@@ -0,0 +1,345 @@
+//! Summaries of tensor artifacts.
+//!
+//! Numpy arrays and safetensors files are parsed directly: both are a small header describing the
+//! dtype and shape of each tensor, followed by the raw elements. PyTorch archives are zip files
+//! holding pickled tensors, so they are recognised but not summarised.
+use crate::models::tensor::{TensorFormat, TensorSummary};
+
+use std::collections::HashMap;
+
+/// How many bytes from the start of a BLOB are needed to recognise its format.
+pub const SNIFF_LEN: usize = 4096;
+
+#[derive(Debug)]
+pub enum TensorError {
+    /// The format is recognised, but can't be summarised.
+    Unsupported(&'static str),
+    /// The header is malformed, or the data is shorter than the header says.
+    Malformed(&'static str),
+}
+
+impl std::fmt::Display for TensorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TensorError::Unsupported(s) => write!(f, "unsupported: {}", s),
+            TensorError::Malformed(s) => write!(f, "malformed: {}", s),
+        }
+    }
+}
+
+/// An element type which can be decoded. All are little-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dtype {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    F16,
+    BF16,
+    F32,
+    F64,
+}
+
+impl Dtype {
+    fn size(&self) -> usize {
+        match self {
+            Dtype::Bool | Dtype::U8 | Dtype::I8 => 1,
+            Dtype::U16 | Dtype::I16 | Dtype::F16 | Dtype::BF16 => 2,
+            Dtype::U32 | Dtype::I32 | Dtype::F32 => 4,
+            Dtype::U64 | Dtype::I64 | Dtype::F64 => 8,
+        }
+    }
+
+    fn decode(&self, b: &[u8]) -> f64 {
+        match self {
+            Dtype::Bool | Dtype::U8 => b[0] as f64,
+            Dtype::I8 => b[0] as i8 as f64,
+            Dtype::U16 => u16::from_le_bytes([b[0], b[1]]) as f64,
+            Dtype::I16 => i16::from_le_bytes([b[0], b[1]]) as f64,
+            Dtype::F16 => half::f16::from_le_bytes([b[0], b[1]]).to_f64(),
+            Dtype::BF16 => half::bf16::from_le_bytes([b[0], b[1]]).to_f64(),
+            Dtype::U32 => u32::from_le_bytes(b.try_into().unwrap()) as f64,
+            Dtype::I32 => i32::from_le_bytes(b.try_into().unwrap()) as f64,
+            Dtype::F32 => f32::from_le_bytes(b.try_into().unwrap()) as f64,
+            Dtype::U64 => u64::from_le_bytes(b.try_into().unwrap()) as f64,
+            Dtype::I64 => i64::from_le_bytes(b.try_into().unwrap()) as f64,
+            Dtype::F64 => f64::from_le_bytes(b.try_into().unwrap()),
+        }
+    }
+
+    /// Parses a numpy dtype descriptor, e.g. `<f4`. Big-endian and structured dtypes aren't
+    /// decoded.
+    fn from_numpy(descr: &str) -> Option<Self> {
+        let (order, kind) = descr.split_at(descr.len().min(1));
+        if !matches!(order, "<" | "|" | "=") {
+            return None;
+        }
+        Some(match kind {
+            "b1" => Dtype::Bool,
+            "u1" => Dtype::U8,
+            "u2" => Dtype::U16,
+            "u4" => Dtype::U32,
+            "u8" => Dtype::U64,
+            "i1" => Dtype::I8,
+            "i2" => Dtype::I16,
+            "i4" => Dtype::I32,
+            "i8" => Dtype::I64,
+            "f2" => Dtype::F16,
+            "f4" => Dtype::F32,
+            "f8" => Dtype::F64,
+            _ => return None,
+        })
+    }
+
+    fn from_safetensors(dtype: &str) -> Option<Self> {
+        Some(match dtype {
+            "BOOL" => Dtype::Bool,
+            "U8" => Dtype::U8,
+            "U16" => Dtype::U16,
+            "U32" => Dtype::U32,
+            "U64" => Dtype::U64,
+            "I8" => Dtype::I8,
+            "I16" => Dtype::I16,
+            "I32" => Dtype::I32,
+            "I64" => Dtype::I64,
+            "F16" => Dtype::F16,
+            "BF16" => Dtype::BF16,
+            "F32" => Dtype::F32,
+            "F64" => Dtype::F64,
+            _ => return None,
+        })
+    }
+}
+
+/// Recognises the format of a BLOB from its first `SNIFF_LEN` bytes (or all of them, if it's
+/// shorter).
+pub fn sniff(sample: &[u8]) -> Option<TensorFormat> {
+    if sample.starts_with(b"\x93NUMPY") {
+        return Some(TensorFormat::Npy);
+    }
+    // PyTorch archives are zip files whose first entry is the pickle, `<name>/data.pkl`.
+    if sample.starts_with(b"PK\x03\x04") && sample.windows(8).take(128).any(|w| w == b"data.pkl") {
+        return Some(TensorFormat::Torch);
+    }
+    // Safetensors files start with the length of their JSON header.
+    if sample.len() > 9 && sample[8] == b'{' {
+        let header_len = u64::from_le_bytes(sample[..8].try_into().unwrap());
+        if header_len < 100 * 1024 * 1024 {
+            return Some(TensorFormat::Safetensors);
+        }
+    }
+    None
+}
+
+/// Summarises `data`, the raw elements of a tensor.
+fn summarise(
+    name: Option<String>,
+    dtype_name: String,
+    dtype: Option<Dtype>,
+    shape: Vec<u64>,
+    data: &[u8],
+) -> Result<TensorSummary, TensorError> {
+    let mut summary = TensorSummary {
+        name,
+        dtype: dtype_name,
+        shape,
+        min: None,
+        max: None,
+        mean: None,
+        nan_count: 0,
+        inf_count: 0,
+    };
+    let dtype = match dtype {
+        Some(dtype) => dtype,
+        None => return Ok(summary),
+    };
+
+    let len = summary.shape.iter().product::<u64>() as usize;
+    if data.len() < len * dtype.size() {
+        return Err(TensorError::Malformed("data is shorter than the shape"));
+    }
+
+    let (mut count, mut sum) = (0u64, 0.0);
+    for b in data[..len * dtype.size()].chunks_exact(dtype.size()) {
+        let v = dtype.decode(b);
+        if v.is_nan() {
+            summary.nan_count += 1;
+        } else if v.is_infinite() {
+            summary.inf_count += 1;
+        } else {
+            summary.min = Some(summary.min.map_or(v, |m| m.min(v)));
+            summary.max = Some(summary.max.map_or(v, |m| m.max(v)));
+            count += 1;
+            sum += v;
+        }
+    }
+    summary.mean = (count > 0).then(|| sum / count as f64);
+
+    Ok(summary)
+}
+
+/// The value of `key` in a numpy header, which is a Python dict literal, e.g.
+/// `{'descr': '<f4', 'fortran_order': False, 'shape': (3, 4), }`.
+fn numpy_header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{}':", key))? + key.len() + 3;
+    let value = header[start..].trim_start();
+    let end = match value.chars().next()? {
+        '\'' => value[1..].find('\'')? + 2,
+        '(' => value.find(')')? + 1,
+        _ => value.find(',')?,
+    };
+    Some(&value[..end])
+}
+
+fn summarise_npy(bytes: &[u8]) -> Result<Vec<TensorSummary>, TensorError> {
+    // Version 1 headers have a 2 byte length, and later versions a 4 byte one.
+    let (header_start, header_len) = match bytes.get(6).copied() {
+        Some(1) if bytes.len() >= 10 => (10, u16::from_le_bytes([bytes[8], bytes[9]]) as usize),
+        Some(2 | 3) if bytes.len() >= 12 => (
+            12,
+            u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize,
+        ),
+        _ => return Err(TensorError::Malformed("invalid numpy header")),
+    };
+    let header = bytes
+        .get(header_start..header_start + header_len)
+        .and_then(|h| std::str::from_utf8(h).ok())
+        .ok_or(TensorError::Malformed("invalid numpy header"))?;
+
+    let descr = numpy_header_value(header, "descr")
+        .and_then(|d| d.strip_prefix('\''))
+        .and_then(|d| d.strip_suffix('\''))
+        .ok_or(TensorError::Unsupported("structured numpy dtype"))?;
+    let shape = numpy_header_value(header, "shape")
+        .and_then(|s| s.strip_prefix('('))
+        .and_then(|s| s.strip_suffix(')'))
+        .ok_or(TensorError::Malformed("invalid numpy shape"))?
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(|d| d.parse::<u64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| TensorError::Malformed("invalid numpy shape"))?;
+
+    let summary = summarise(
+        None,
+        descr.to_string(),
+        Dtype::from_numpy(descr),
+        shape,
+        &bytes[header_start + header_len..],
+    )?;
+    Ok(vec![summary])
+}
+
+#[derive(Deserialize)]
+struct SafetensorsEntry {
+    dtype: String,
+    shape: Vec<u64>,
+    data_offsets: (usize, usize),
+}
+
+fn summarise_safetensors(bytes: &[u8]) -> Result<Vec<TensorSummary>, TensorError> {
+    let malformed = || TensorError::Malformed("invalid safetensors header");
+
+    let header_len = bytes
+        .get(..8)
+        .map(|b| u64::from_le_bytes(b.try_into().unwrap()) as usize)
+        .ok_or_else(malformed)?;
+    let header = bytes.get(8..8 + header_len).ok_or_else(malformed)?;
+    let mut entries = serde_json::from_slice::<HashMap<String, serde_json::Value>>(header)
+        .map_err(|_| malformed())?;
+    entries.remove("__metadata__");
+    let data = &bytes[8 + header_len..];
+
+    let mut summaries = entries
+        .into_iter()
+        .map(|(name, entry)| {
+            let entry =
+                serde_json::from_value::<SafetensorsEntry>(entry).map_err(|_| malformed())?;
+            let (begin, end) = entry.data_offsets;
+            let tensor = data.get(begin..end).ok_or_else(malformed)?;
+            summarise(
+                Some(name),
+                entry.dtype.clone(),
+                Dtype::from_safetensors(&entry.dtype),
+                entry.shape,
+                tensor,
+            )
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    summaries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(summaries)
+}
+
+/// Summarises each tensor in a BLOB. This is CPU bound, so should be run off the async workers.
+pub fn summarise_all(
+    format: TensorFormat,
+    bytes: &[u8],
+) -> Result<Vec<TensorSummary>, TensorError> {
+    match format {
+        TensorFormat::Npy => summarise_npy(bytes),
+        TensorFormat::Safetensors => summarise_safetensors(bytes),
+        TensorFormat::Torch => Err(TensorError::Unsupported("pickled PyTorch tensors")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn npy(descr: &str, shape: &str, data: &[u8]) -> Vec<u8> {
+        let header = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}\n",
+            descr, shape
+        );
+        let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+        bytes.extend((header.len() as u16).to_le_bytes());
+        bytes.extend(header.as_bytes());
+        bytes.extend(data);
+        bytes
+    }
+
+    #[test]
+    fn summarises_npy() {
+        let data = [1.0f32, f32::NAN, 3.0, f32::INFINITY]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect::<Vec<_>>();
+        let bytes = npy("<f4", "(2, 2)", &data);
+
+        assert_eq!(sniff(&bytes), Some(TensorFormat::Npy));
+        let summaries = summarise_all(TensorFormat::Npy, &bytes).unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].shape, vec![2, 2]);
+        assert_eq!(summaries[0].min, Some(1.0));
+        assert_eq!(summaries[0].max, Some(3.0));
+        assert_eq!(summaries[0].mean, Some(2.0));
+        assert_eq!(summaries[0].nan_count, 1);
+        assert_eq!(summaries[0].inf_count, 1);
+
+        // Big-endian data is described but not decoded.
+        let summaries = summarise_all(TensorFormat::Npy, &npy(">i4", "(3,)", &[0; 12])).unwrap();
+        assert_eq!(summaries[0].shape, vec![3]);
+        assert_eq!(summaries[0].mean, None);
+    }
+
+    #[test]
+    fn summarises_safetensors() {
+        let header = br#"{"__metadata__":{},"w":{"dtype":"I16","shape":[2],"data_offsets":[0,4]}}"#;
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend(header);
+        bytes.extend([0xff, 0xff, 0x03, 0x00]);
+
+        assert_eq!(sniff(&bytes), Some(TensorFormat::Safetensors));
+        let summaries = summarise_all(TensorFormat::Safetensors, &bytes).unwrap();
+        assert_eq!(summaries[0].name.as_deref(), Some("w"));
+        assert_eq!(summaries[0].min, Some(-1.0));
+        assert_eq!(summaries[0].max, Some(3.0));
+    }
+}
@@ -0,0 +1,154 @@
+//! Bandwidth caps on BLOB transfers, so that one client's bulk transfer can't saturate the
+//! server's network for everyone else.
+//!
+//! Each API key (or signed in user) has a token bucket per direction, shared by all of its
+//! transfers of the same priority. A transfer waits before passing on each chunk until the bucket
+//! can pay for it. The buckets live in memory, so caps apply per server.
+use crate::priority::Priority;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+/// A token bucket holding up to a second's worth of bytes.
+#[derive(Debug)]
+pub struct TokenBucket {
+    bytes_per_sec: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(bytes_per_sec: u64, now: Instant) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec as f64,
+            tokens: bytes_per_sec as f64,
+            updated: now,
+        }
+    }
+
+    pub fn set_rate(&mut self, bytes_per_sec: u64) {
+        self.bytes_per_sec = bytes_per_sec as f64;
+        self.tokens = self.tokens.min(self.bytes_per_sec);
+    }
+
+    /// Takes `bytes` worth of tokens, returning how long to wait before sending them. A chunk
+    /// larger than the bucket puts it into debt, which later chunks wait out.
+    pub fn take(&mut self, bytes: usize, now: Instant) -> Duration {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.bytes_per_sec).min(self.bytes_per_sec);
+        self.updated = now;
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / self.bytes_per_sec)
+        }
+    }
+}
+
+/// A bucket shared by the transfers of one key, of one priority, in one direction.
+#[derive(Clone)]
+pub struct Bucket(Arc<Mutex<TokenBucket>>);
+
+impl Bucket {
+    /// Waits until `bytes` can be sent.
+    pub async fn wait(&self, bytes: usize) {
+        let wait = self.0.lock().unwrap().take(bytes, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// The buckets of every key which has transferred a BLOB.
+#[derive(Clone, Default)]
+pub struct Throttle {
+    buckets: Arc<Mutex<HashMap<(String, Direction, Priority), Bucket>>>,
+}
+
+impl Throttle {
+    /// The bucket of `principal` (an API key, or a user) for transfers of `priority` in
+    /// `direction`, capped at `bytes_per_sec`. The cap of an existing bucket is updated, as it may
+    /// have been changed. Batch transfers have their own buckets, so that they can't use up the
+    /// bandwidth of interactive ones.
+    pub fn bucket(
+        &self,
+        principal: &str,
+        direction: Direction,
+        priority: Priority,
+        bytes_per_sec: u64,
+    ) -> Bucket {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets
+            .entry((principal.to_string(), direction, priority))
+            .or_insert_with(|| {
+                Bucket(Arc::new(Mutex::new(TokenBucket::new(
+                    bytes_per_sec,
+                    Instant::now(),
+                ))))
+            });
+        bucket.0.lock().unwrap().set_rate(bytes_per_sec);
+        bucket.clone()
+    }
+}
+
+/// Passes on the chunks of `stream` no faster than `bucket` allows, or as they come if there's no
+/// bucket.
+pub fn throttled<S, E>(stream: S, bucket: Option<Bucket>) -> impl Stream<Item = Result<Bytes, E>>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+{
+    stream.then(move |item| {
+        let bucket = bucket.clone();
+        let len = item.as_ref().map_or(0, |bytes| bytes.len());
+        async move {
+            if let Some(bucket) = bucket {
+                bucket.wait(len).await;
+            }
+            item
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paces_to_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1000, start);
+
+        // The first second's worth is sent straight away.
+        assert_eq!(bucket.take(1000, start), Duration::ZERO);
+        // Then sending must wait for the bucket to refill.
+        assert_eq!(bucket.take(500, start), Duration::from_millis(500));
+        // A chunk larger than the bucket is sent, and its debt waited out.
+        let later = start + Duration::from_secs(10);
+        assert_eq!(bucket.take(3000, later), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn shares_buckets_per_key_direction_and_priority() {
+        let throttle = Throttle::default();
+        let interactive = Priority::Interactive;
+        let a = throttle.bucket("a", Direction::Upload, interactive, 1000);
+        let b = throttle.bucket("a", Direction::Upload, interactive, 1000);
+        let now = Instant::now();
+        assert_eq!(a.0.lock().unwrap().take(1000, now), Duration::ZERO);
+        assert!(!b.0.lock().unwrap().take(1000, now).is_zero());
+
+        let c = throttle.bucket("a", Direction::Download, interactive, 1000);
+        assert_eq!(c.0.lock().unwrap().take(1000, now), Duration::ZERO);
+        let d = throttle.bucket("a", Direction::Upload, Priority::Batch, 1000);
+        assert_eq!(d.0.lock().unwrap().take(1000, now), Duration::ZERO);
+    }
+}
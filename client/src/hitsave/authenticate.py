@@ -16,12 +16,12 @@ Todo:
 """
 
 
-async def loopback_login(*, autoopen=True) -> str:
+async def loopback_login(*, autoopen=True, save=True) -> str:
     """Interactive workflow to perform the github authentication loop.
 
     ① present a sign-in-with-github link to the user in the terminal.
     ② ping api.hitsave.io/user/login for a new JWT.
-    ③ return the JWT and store it locally the JWT in a local file.
+    ③ return the JWT and, if `save` is set, store it locally in a local file.
 
     A holder of this JWT, for the period that it is valid, is authenticated in hitsave as the person
     who logged in.
@@ -79,7 +79,8 @@ async def loopback_login(*, autoopen=True) -> str:
     await server.shutdown()
     assert "jwt" in result
     jwt = result["jwt"]
-    save_jwt(jwt)
+    if save:
+        save_jwt(jwt)
     console.print("Successfully logged in.")
     return jwt
 
@@ -113,3 +114,29 @@ async def generate_api_key(label: str):
                 raise Exception(f"Unknown content_type {resp.content_type}")
     logger.debug(f"Successfully recieved new API key")
     return api_key
+
+
+async def exchange_api_key(
+    jwt: str, device_name: str, project: Optional[str] = None
+) -> str:
+    """Exchanges the JWT of a login session for a short-lived api key for this device,
+    so that the JWT itself doesn't need to be kept.
+    """
+    cloud_url = Config.current().cloud_url
+    body: Dict[str, str] = {"device_name": device_name}
+    if project is not None:
+        body["project"] = project
+
+    logger.debug(f"Asking {cloud_url} for an API key for device {device_name}.")
+    async with aiohttp.ClientSession(
+        cloud_url, headers={"Authorization": f"Bearer {jwt}"}
+    ) as session:
+        async with session.post("/user/exchange", json=body) as resp:
+            if resp.status == 401:
+                msg = await resp.text()
+                logger.debug(msg)
+                raise AuthenticationError(f"Authentication session has expired.")
+            resp.raise_for_status()
+            exchanged = await resp.json()
+    logger.debug(f"Successfully recieved API key expiring at {exchanged['expire_dt']}")
+    return exchanged["key"]
@@ -16,12 +16,26 @@ from hitsave.util import (
 from blake3 import blake3
 from stat import S_IREAD, S_IRGRP
 import tempfile
+import time
 import io
 from hitsave.console import logger, internal_error
-from hitsave.cloudutils import request, read_header, create_header, encode_hitsavemsg
+from hitsave.cloudutils import (
+    request,
+    read_header,
+    create_header,
+    encode_hitsavemsg,
+    BLOB_PROTO_HEADER,
+    BLOB_PROTO_VERSION,
+)
 
 """ This file contains everything to do with storing and retrieving blobs locally and on the cloud. """
 
+UPLOAD_IN_PARTS_THRESHOLD = 64 * 2**20
+""" Blobs larger than this are uploaded in parts, so that an interrupted upload can be resumed. """
+
+MAX_PART_RETRIES = 5
+""" How many times in a row sending a part of an upload is retried after losing the connection. """
+
 
 @dataclass
 class BlobInfo:
@@ -197,13 +211,69 @@ class CloudBlobStore:
             message=f"Uploading {pp_label} ({human_size(content_length)}) {digest}.",
             description="Uploading",
         ) as tape:
+            if content_length > UPLOAD_IN_PARTS_THRESHOLD:
+                self._add_blob_in_parts(tape, digest, content_length)
+                return BlobInfo(digest, content_length)
             msg = encode_hitsavemsg(mdata, tape)
-            r = request("PUT", "/blob", data=msg)
+            r = request(
+                "PUT",
+                "/blob",
+                data=msg,
+                headers={BLOB_PROTO_HEADER: str(BLOB_PROTO_VERSION)},
+            )
         r.raise_for_status()
         if label is not None:
             logger.debug(f"Uploaded {pp_label} {digest}.")
         return BlobInfo(digest, content_length)
 
+    def _add_blob_in_parts(self, tape: IO[bytes], digest: str, content_length: int):
+        """Uploads the blob in parts, picking up an earlier upload of it which didn't finish.
+
+        A part whose request loses the connection is sent again, a few times, before giving up.
+
+        Raises:
+            ConnectionError: We lost the connection to the cloud and couldn't get it back.
+        """
+        r = request(
+            "POST",
+            "/blob/upload/start",
+            json={"content_hash": digest, "content_length": content_length},
+        )
+        r.raise_for_status()
+        upload = r.json()
+        upload_id = upload["id"]
+        part_size = upload["part_size"]
+        if upload["parts_received"] > 0:
+            logger.debug(
+                f"Resuming upload of {digest} from part {upload['parts_received'] + 1}."
+            )
+        part_number = upload["parts_received"] + 1
+        retries = 0
+        while part_number <= upload["part_count"]:
+            tape.seek((part_number - 1) * part_size)
+            part = tape.read(part_size)
+            try:
+                r = request(
+                    "PUT", f"/blob/upload/{upload_id}/part/{part_number}", data=part
+                )
+            except ConnectionError:
+                retries += 1
+                if retries > MAX_PART_RETRIES:
+                    raise
+                time.sleep(2**retries)
+                continue
+            retries = 0
+            if r.status_code == 409:
+                # The server says which part it's expecting.
+                part_number = r.json()["next_part"]
+                continue
+            r.raise_for_status()
+            upload = r.json()
+            part_number = upload["parts_received"] + 1
+        r = request("POST", f"/blob/upload/{upload_id}/complete")
+        r.raise_for_status()
+        logger.debug(f"Uploaded {digest} in {upload['part_count']} parts.")
+
     def open_blob(self, digest: str) -> IO[bytes]:
         """Downloads the given blob to a temporary file.
 
@@ -7,6 +7,7 @@ import platform
 from enum import Enum
 from hitsave.authenticate import (
     AuthenticationError,
+    exchange_api_key,
     generate_api_key,
     get_jwt,
     loopback_login,
@@ -56,9 +57,19 @@ def login(
 ):
     """Log in or sign up to the HitSave cloud service.
 
-    This will present a link to you which can be used to register hitsave using your github account."""
+    This will present a link to you which can be used to register hitsave using your github account.
+    The login session is exchanged for a short-lived API key for this machine, which is saved in
+    place of the session."""
     autoopen = not no_autoopen
-    asyncio.run(loopback_login(autoopen=autoopen))
+
+    async def login_async():
+        jwt = await loopback_login(autoopen=autoopen, save=False)
+        return await exchange_api_key(jwt, platform.node())
+
+    api_key = asyncio.run(login_async())
+    cfg = Config.current()
+    console.print(f"Saving key to {cfg.api_key_file_path}.")
+    cfg.set_api_key(api_key)
 
 
 async def keygen_async():
@@ -15,18 +15,22 @@ logger = logging.getLogger("hitsave")
 """
 The HitSave wire format is a utf-8 encoded JSON object followed by raw bytes stream.
 
+- 1 byte; the version of the wire format, which must match the BLOB_PROTO_HEADER header,
 - 4 byte unsigned integer; the content length of the JSON part,
 - n bytes; the utf-8 encoded JSON object.
 - bytestream
 
  """
 
+BLOB_PROTO_VERSION = 1
+BLOB_PROTO_HEADER = "X-HitSave-Blob-Proto"
+
 
 def create_header(meta: dict) -> bytes:
     """Creates a header for the HitSave wire format."""
     meta_json = json.dumps(meta).encode("utf-8")
     json_len = len(meta_json).to_bytes(4, byteorder="big", signed=False)
-    return json_len + meta_json
+    return bytes([BLOB_PROTO_VERSION]) + json_len + meta_json
 
 
 def encode_hitsavemsg(meta: dict, payload: IO[bytes]) -> Iterator[bytes]:
@@ -253,10 +253,13 @@ class CloudEvalStore:
         try:
             r = self.request_eval(key)
             if r.status_code == 404:
-                return StoreMiss("Not found.")
+                # the reason is one of unknown_function, unknown_version, not_computed or filtered.
+                reason = r.json().get("reason", "unknown")
+                return StoreMiss(f"Not found ({reason}).")
             if r.status_code == 403:
-                # [todo] unauthorized
-                pass
+                msg = r.json().get("message", "forbidden")
+                logger.error(f"Cloud eval store refused lookup: {msg}")
+                return StoreMiss(f"Forbidden: {msg}")
             r.raise_for_status()
         except ConnectionError as err:
             # request will already tell the user they are offline. We just fail here.
@@ -269,6 +272,13 @@ class CloudEvalStore:
             return StoreMiss(msg)
         results: list = r.json()
         for result in results:
+            # the result blob may not be downloadable: pending, missing or archived.
+            blob_status = result.get("blob_status", "available")
+            if blob_status != "available":
+                logger.debug(
+                    f"Skipping cloud eval for {key.fn_key}; its result is {blob_status}."
+                )
+                continue
             logger.debug(f"Found cloud eval for {key.fn_key}.")
             digest = result["content_hash"]  # [todo] will be renamed
             # [todo]; for now, blobs are always streamed, but in the future we will probably put small blobs inline.
@@ -279,6 +289,8 @@ class CloudEvalStore:
                     return PollEvalResult(value, origin="cloud")
                 except pickle.UnpicklingError:
                     return StoreMiss(f"Corrupted result for {key.fn_key}.")
+        if results:
+            return StoreMiss("No results with an available blob.")
         return StoreMiss("No results.")
 
     def start_eval(
@@ -321,6 +333,10 @@ class CloudEvalStore:
         try:
             r = request("PUT", f"/eval/", json=metadata)
             r.raise_for_status()
+            if r.json().get("replayed", False):
+                # the eval was already put with this result, and its blob is already uploaded.
+                logger.debug(f"Eval {key} is already uploaded.")
+                return
         except ConnectionError:
             # we are offline. we have already told the user this.
             return False